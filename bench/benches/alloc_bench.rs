@@ -23,11 +23,12 @@ use tikv_jemallocator::Jemalloc;
 mod rtmalloc_ffi {
     use std::alloc::{GlobalAlloc, Layout};
 
+    // The unchecked entries: a `Layout` is valid by construction.
     unsafe extern "C" {
         // Nightly variant (#[thread_local] thread cache)
-        fn rtmalloc_nightly_alloc(size: usize, align: usize) -> *mut u8;
-        fn rtmalloc_nightly_dealloc(ptr: *mut u8, size: usize, align: usize);
-        fn rtmalloc_nightly_realloc(
+        fn rtmalloc_nightly_alloc_unchecked(size: usize, align: usize) -> *mut u8;
+        fn rtmalloc_nightly_dealloc_unchecked(ptr: *mut u8, size: usize, align: usize);
+        fn rtmalloc_nightly_realloc_unchecked(
             ptr: *mut u8,
             size: usize,
             align: usize,
@@ -35,9 +36,9 @@ mod rtmalloc_ffi {
         ) -> *mut u8;
 
         // Std variant (std::thread_local! thread cache)
-        fn rtmalloc_std_alloc(size: usize, align: usize) -> *mut u8;
-        fn rtmalloc_std_dealloc(ptr: *mut u8, size: usize, align: usize);
-        fn rtmalloc_std_realloc(
+        fn rtmalloc_std_alloc_unchecked(size: usize, align: usize) -> *mut u8;
+        fn rtmalloc_std_dealloc_unchecked(ptr: *mut u8, size: usize, align: usize);
+        fn rtmalloc_std_realloc_unchecked(
             ptr: *mut u8,
             size: usize,
             align: usize,
@@ -45,9 +46,9 @@ mod rtmalloc_ffi {
         ) -> *mut u8;
 
         // Nostd variant (central cache only, no thread cache)
-        fn rtmalloc_nostd_alloc(size: usize, align: usize) -> *mut u8;
-        fn rtmalloc_nostd_dealloc(ptr: *mut u8, size: usize, align: usize);
-        fn rtmalloc_nostd_realloc(
+        fn rtmalloc_nostd_alloc_unchecked(size: usize, align: usize) -> *mut u8;
+        fn rtmalloc_nostd_dealloc_unchecked(ptr: *mut u8, size: usize, align: usize);
+        fn rtmalloc_nostd_realloc_unchecked(
             ptr: *mut u8,
             size: usize,
             align: usize,
@@ -58,9 +59,9 @@ mod rtmalloc_ffi {
     // Per-CPU variant (rseq, Linux x86_64 only)
     #[cfg(has_rtmalloc_percpu)]
     unsafe extern "C" {
-        fn rtmalloc_percpu_alloc(size: usize, align: usize) -> *mut u8;
-        fn rtmalloc_percpu_dealloc(ptr: *mut u8, size: usize, align: usize);
-        fn rtmalloc_percpu_realloc(
+        fn rtmalloc_percpu_alloc_unchecked(size: usize, align: usize) -> *mut u8;
+        fn rtmalloc_percpu_dealloc_unchecked(ptr: *mut u8, size: usize, align: usize);
+        fn rtmalloc_percpu_realloc_unchecked(
             ptr: *mut u8,
             size: usize,
             align: usize,
@@ -91,28 +92,28 @@ mod rtmalloc_ffi {
 
     impl_ffi_alloc!(
         RtmallocNightly,
-        rtmalloc_nightly_alloc,
-        rtmalloc_nightly_dealloc,
-        rtmalloc_nightly_realloc
+        rtmalloc_nightly_alloc_unchecked,
+        rtmalloc_nightly_dealloc_unchecked,
+        rtmalloc_nightly_realloc_unchecked
     );
     impl_ffi_alloc!(
         RtmallocStd,
-        rtmalloc_std_alloc,
-        rtmalloc_std_dealloc,
-        rtmalloc_std_realloc
+        rtmalloc_std_alloc_unchecked,
+        rtmalloc_std_dealloc_unchecked,
+        rtmalloc_std_realloc_unchecked
     );
     impl_ffi_alloc!(
         RtmallocNostd,
-        rtmalloc_nostd_alloc,
-        rtmalloc_nostd_dealloc,
-        rtmalloc_nostd_realloc
+        rtmalloc_nostd_alloc_unchecked,
+        rtmalloc_nostd_dealloc_unchecked,
+        rtmalloc_nostd_realloc_unchecked
     );
     #[cfg(has_rtmalloc_percpu)]
     impl_ffi_alloc!(
         RtmallocPercpu,
-        rtmalloc_percpu_alloc,
-        rtmalloc_percpu_dealloc,
-        rtmalloc_percpu_realloc
+        rtmalloc_percpu_alloc_unchecked,
+        rtmalloc_percpu_dealloc_unchecked,
+        rtmalloc_percpu_realloc_unchecked
     );
 }
 
@@ -208,6 +209,19 @@ unsafe fn alloc_n_then_free(allocator: &dyn GlobalAlloc, layout: Layout, n: usiz
     }
 }
 
+/// Allocate and free `depth` objects in LIFO order: the thread cache hot loop.
+unsafe fn hot_lifo(allocator: &dyn GlobalAlloc, layout: Layout, depth: usize) {
+    let mut ptrs = [core::ptr::null_mut(); 4];
+    for slot in ptrs.iter_mut().take(depth) {
+        *slot = unsafe { allocator.alloc(layout) };
+        assert!(!slot.is_null());
+    }
+    black_box(&ptrs);
+    for &ptr in ptrs.iter().take(depth).rev() {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+}
+
 unsafe fn churn(allocator: &dyn GlobalAlloc, layout: Layout, rounds: usize) {
     let mut live: Vec<*mut u8> = Vec::new();
     for _ in 0..rounds {
@@ -324,6 +338,39 @@ fn bench_batch_alloc_free(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_hot_small(c: &mut Criterion) {
+    let sizes: &[usize] = &[8, 16, 32, 64];
+    let depth = 4;
+    let mut group = c.benchmark_group("hot_small");
+    group.sample_size(50);
+
+    for &size in sizes {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        group.throughput(Throughput::Elements(depth as u64));
+
+        group.bench_with_input(BenchmarkId::new("system", size), &size, |b, _| {
+            b.iter(|| unsafe { hot_lifo(&System, layout, depth) })
+        });
+        group.bench_with_input(BenchmarkId::new("rt_nightly", size), &size, |b, _| {
+            b.iter(|| unsafe { hot_lifo(&RTMALLOC_NIGHTLY, layout, depth) })
+        });
+        #[cfg(has_rtmalloc_percpu)]
+        group.bench_with_input(BenchmarkId::new("rt_percpu", size), &size, |b, _| {
+            b.iter(|| unsafe { hot_lifo(&RTMALLOC_PERCPU, layout, depth) })
+        });
+        group.bench_with_input(BenchmarkId::new("rt_std", size), &size, |b, _| {
+            b.iter(|| unsafe { hot_lifo(&RTMALLOC_STD, layout, depth) })
+        });
+        group.bench_with_input(BenchmarkId::new("mimalloc", size), &size, |b, _| {
+            b.iter(|| unsafe { hot_lifo(&MIMALLOC, layout, depth) })
+        });
+        group.bench_with_input(BenchmarkId::new("snmalloc", size), &size, |b, _| {
+            b.iter(|| unsafe { hot_lifo(&SNMALLOC, layout, depth) })
+        });
+    }
+    group.finish();
+}
+
 fn bench_churn(c: &mut Criterion) {
     let sizes: &[usize] = &[32, 256, 2048];
     let rounds = 200;
@@ -700,6 +747,84 @@ fn bench_thread_scalability(c: &mut Criterion) {
     group.finish();
 }
 
+// ---------------------------------------------------------------------------
+// Adjacent classes: each thread churns its own neighbouring size class in
+// bursts bigger than its thread cache, so every thread lives in the transfer
+// and central caches and only contends on lock cache lines it shares.
+// ---------------------------------------------------------------------------
+
+fn bench_adjacent_classes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("adjacent_classes");
+    let burst = 16_384usize;
+    group.sample_size(15);
+
+    fn adjacent_workload<A: GlobalAlloc + Sync>(
+        allocator: &'static A,
+        nthreads: usize,
+        burst: usize,
+    ) {
+        let handles: Vec<_> = (0..nthreads)
+            .map(|t| {
+                std::thread::spawn(move || {
+                    // 8, 16, 24, ...: one size class per thread, side by side.
+                    let layout = Layout::from_size_align(8 * (t + 1), 8).unwrap();
+                    let mut ptrs: Vec<*mut u8> = Vec::with_capacity(burst);
+                    for _ in 0..4 {
+                        for _ in 0..burst {
+                            let ptr = unsafe { allocator.alloc(layout) };
+                            assert!(!ptr.is_null());
+                            ptrs.push(ptr);
+                        }
+                        for p in ptrs.drain(..) {
+                            unsafe { allocator.dealloc(p, layout) };
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+    }
+
+    static SYS4: System = System;
+
+    for &nthreads in &[1usize, 2, 4, 8] {
+        group.throughput(Throughput::Elements((4 * burst * nthreads) as u64));
+
+        group.bench_with_input(BenchmarkId::new("system", nthreads), &nthreads, |b, &nt| {
+            b.iter(|| adjacent_workload(&SYS4, nt, burst))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("rt_nightly", nthreads),
+            &nthreads,
+            |b, &nt| b.iter(|| adjacent_workload(&RTMALLOC_NIGHTLY, nt, burst)),
+        );
+        #[cfg(has_rtmalloc_percpu)]
+        group.bench_with_input(
+            BenchmarkId::new("rt_percpu", nthreads),
+            &nthreads,
+            |b, &nt| b.iter(|| adjacent_workload(&RTMALLOC_PERCPU, nt, burst)),
+        );
+        group.bench_with_input(BenchmarkId::new("rt_std", nthreads), &nthreads, |b, &nt| {
+            b.iter(|| adjacent_workload(&RTMALLOC_STD, nt, burst))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("rt_nostd", nthreads),
+            &nthreads,
+            |b, &nt| b.iter(|| adjacent_workload(&RTMALLOC_NOSTD, nt, burst)),
+        );
+        #[cfg(has_google_tcmalloc)]
+        group.bench_with_input(
+            BenchmarkId::new("google_tc", nthreads),
+            &nthreads,
+            |b, &nt| b.iter(|| adjacent_workload(&GOOGLE_TC, nt, burst)),
+        );
+    }
+
+    group.finish();
+}
+
 // ---------------------------------------------------------------------------
 // Mixed sizes: realistic size distribution (many small, few large)
 // ---------------------------------------------------------------------------
@@ -887,11 +1012,13 @@ criterion_group!(
     benches,
     bench_single_alloc_dealloc,
     bench_batch_alloc_free,
+    bench_hot_small,
     bench_churn,
     bench_vec_push,
     bench_multithreaded,
     bench_cross_thread_free,
     bench_thread_scalability,
+    bench_adjacent_classes,
     bench_mixed_sizes,
     bench_producer_consumer,
 );
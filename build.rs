@@ -45,8 +45,31 @@ struct ConfigSection {
     steal_amount: Option<usize>,
     max_free_list_length: Option<u32>,
     max_overages: Option<u32>,
+    thread_cache_decay_ms: Option<u64>,
     max_transfer_slots: Option<usize>,
+    max_transfer_bytes: Option<usize>,
+    max_objects_per_lock: Option<usize>,
     max_pages: Option<usize>,
+    prefault: Option<bool>,
+    array_cache_slots: Option<usize>,
+    max_retained_spans: Option<usize>,
+    mid_max_size: Option<usize>,
+    mid_cache_spans: Option<usize>,
+    large_trim_pages: Option<usize>,
+    large_reserve_pages: Option<usize>,
+    address_ordered_spans: Option<bool>,
+    max_heap: Option<usize>,
+    heap_base: Option<usize>,
+    zero_decommit_min: Option<usize>,
+    free_decommit_min: Option<usize>,
+    span_cooldown: Option<usize>,
+    span_cooldown_ms: Option<u64>,
+    numa_interleave_min: Option<usize>,
+    span_pages_scale: Option<usize>,
+    meta_guard_pages: Option<usize>,
+    num_arenas: Option<usize>,
+    class_map: Option<String>,
+    cache_line_size: Option<usize>,
 }
 
 #[derive(Deserialize, Default)]
@@ -74,8 +97,31 @@ struct ResolvedConfig {
     steal_amount: usize,
     max_free_list_length: u32,
     max_overages: u32,
+    thread_cache_decay_ms: u64,
     max_transfer_slots: usize,
+    max_transfer_bytes: usize,
+    max_objects_per_lock: usize,
     max_pages: usize,
+    prefault: bool,
+    array_cache_slots: usize,
+    max_retained_spans: usize,
+    mid_max_size: usize,
+    mid_cache_spans: usize,
+    large_trim_pages: usize,
+    large_reserve_pages: usize,
+    address_ordered_spans: bool,
+    max_heap: usize,
+    heap_base: usize,
+    zero_decommit_min: usize,
+    free_decommit_min: usize,
+    span_cooldown: usize,
+    span_cooldown_ms: u64,
+    numa_interleave_min: usize,
+    span_pages_scale: usize,
+    meta_guard_pages: usize,
+    num_arenas: usize,
+    class_map: String,
+    cache_line_size: usize,
 }
 
 fn resolve_config(cfg: &ConfigSection) -> ResolvedConfig {
@@ -96,8 +142,33 @@ fn resolve_config(cfg: &ConfigSection) -> ResolvedConfig {
     let steal_amount = cfg.steal_amount.unwrap_or(64 * 1024);
     let max_free_list_length = cfg.max_free_list_length.unwrap_or(8192);
     let max_overages = cfg.max_overages.unwrap_or(3);
+    let thread_cache_decay_ms = cfg.thread_cache_decay_ms.unwrap_or(0);
     let max_transfer_slots = cfg.max_transfer_slots.unwrap_or(64);
+    let max_transfer_bytes = cfg.max_transfer_bytes.unwrap_or(0);
+    let max_objects_per_lock = cfg.max_objects_per_lock.unwrap_or(256);
     let max_pages = cfg.max_pages.unwrap_or(128);
+    let prefault = cfg.prefault.unwrap_or(false);
+    let array_cache_slots = cfg.array_cache_slots.unwrap_or(0);
+    let max_retained_spans = cfg.max_retained_spans.unwrap_or(4);
+    let mid_max_size = cfg.mid_max_size.unwrap_or(2 * 1024 * 1024);
+    let mid_cache_spans = cfg.mid_cache_spans.unwrap_or(4);
+    let large_trim_pages = cfg.large_trim_pages.unwrap_or(0);
+    let large_reserve_pages = cfg.large_reserve_pages.unwrap_or(0);
+    let address_ordered_spans = cfg.address_ordered_spans.unwrap_or(false);
+    let max_heap = cfg.max_heap.unwrap_or(0);
+    let heap_base = cfg.heap_base.unwrap_or(0);
+    let zero_decommit_min = cfg.zero_decommit_min.unwrap_or(16 * 1024 * 1024);
+    let free_decommit_min = cfg.free_decommit_min.unwrap_or(0);
+    let span_cooldown = cfg.span_cooldown.unwrap_or(0);
+    let span_cooldown_ms = cfg.span_cooldown_ms.unwrap_or(0);
+    let numa_interleave_min = cfg.numa_interleave_min.unwrap_or(0);
+    let span_pages_scale = cfg.span_pages_scale.unwrap_or(1);
+    let meta_guard_pages = cfg.meta_guard_pages.unwrap_or(1);
+    let num_arenas = cfg.num_arenas.unwrap_or(1);
+    let class_map = cfg.class_map.clone().unwrap_or_else(|| "lookup".into());
+    let cache_line_size = cfg
+        .cache_line_size
+        .unwrap_or_else(|| default_cache_line_size(&env::var("CARGO_CFG_TARGET_ARCH").unwrap()));
 
     assert!(thread_cache_size > 0, "thread_cache_size must be > 0");
     assert!(min_per_thread_cache > 0, "min_per_thread_cache must be > 0");
@@ -111,7 +182,59 @@ fn resolve_config(cfg: &ConfigSection) -> ResolvedConfig {
     assert!(max_free_list_length > 0, "max_free_list_length must be > 0");
     assert!(max_overages > 0, "max_overages must be > 0");
     assert!(max_transfer_slots > 0, "max_transfer_slots must be > 0");
+    assert!(max_objects_per_lock > 0, "max_objects_per_lock must be > 0");
     assert!(max_pages > 0, "max_pages must be > 0");
+    assert!(
+        array_cache_slots <= 16,
+        "array_cache_slots ({}) must be <= 16",
+        array_cache_slots
+    );
+    assert!(max_retained_spans > 0, "max_retained_spans must be > 0");
+    assert!(span_pages_scale > 0, "span_pages_scale must be > 0");
+    assert!(
+        meta_guard_pages <= 16,
+        "meta_guard_pages ({}) must be <= 16",
+        meta_guard_pages
+    );
+    assert!(
+        span_cooldown <= 65536,
+        "span_cooldown ({}) must be <= 65536",
+        span_cooldown
+    );
+    assert!(
+        span_cooldown_ms == 0 || span_cooldown > 0,
+        "span_cooldown_ms needs span_cooldown > 0"
+    );
+    assert!(
+        mid_max_size <= 1 << 30,
+        "mid_max_size ({}) must be <= 1 GiB",
+        mid_max_size
+    );
+    assert!(
+        max_heap == 0 || (max_heap.is_power_of_two() && max_heap >= 2048 * page_size),
+        "max_heap ({}) must be 0 or a power of 2 of at least 2048 pages",
+        max_heap
+    );
+    assert!(
+        heap_base == 0 || (max_heap != 0 && heap_base.is_multiple_of(max_heap)),
+        "heap_base ({:#x}) needs max_heap set and must be a multiple of it",
+        heap_base
+    );
+    assert!(
+        (1..=4).contains(&num_arenas),
+        "num_arenas ({}) must be between 1 and 4",
+        num_arenas
+    );
+    assert!(
+        class_map_type(&class_map).is_some(),
+        "class_map ({:?}) must be \"lookup\" or \"power_of_two\"",
+        class_map
+    );
+    assert!(
+        cache_line_size == 64 || cache_line_size == 128,
+        "cache_line_size ({}) must be 64 or 128",
+        cache_line_size
+    );
 
     ResolvedConfig {
         page_size,
@@ -121,11 +244,64 @@ fn resolve_config(cfg: &ConfigSection) -> ResolvedConfig {
         steal_amount,
         max_free_list_length,
         max_overages,
+        thread_cache_decay_ms,
         max_transfer_slots,
+        max_transfer_bytes,
+        max_objects_per_lock,
         max_pages,
+        prefault,
+        array_cache_slots,
+        max_retained_spans,
+        mid_max_size,
+        mid_cache_spans,
+        large_trim_pages,
+        large_reserve_pages,
+        address_ordered_spans,
+        max_heap,
+        heap_base,
+        zero_decommit_min,
+        free_decommit_min,
+        span_cooldown,
+        span_cooldown_ms,
+        numa_interleave_min,
+        span_pages_scale,
+        meta_guard_pages,
+        num_arenas,
+        class_map,
+        cache_line_size,
+    }
+}
+
+/// Cache line size to pad per-class locks to when the config leaves it
+/// out: 128 bytes on aarch64, whose big cores (Apple M-series, Neoverse)
+/// pull lines in pairs, 64 everywhere else.
+fn default_cache_line_size(target_arch: &str) -> usize {
+    match target_arch {
+        "aarch64" => 128,
+        _ => 64,
     }
 }
 
+/// `ClassMap` implementation in `size_class` named by the `class_map` option.
+fn class_map_type(name: &str) -> Option<&'static str> {
+    match name {
+        "lookup" => Some("LookupClassMap"),
+        "power_of_two" => Some("PowerOfTwoClassMap"),
+        _ => None,
+    }
+}
+
+/// Replace `defs` with auto-tuned powers of two from 8 bytes up to the
+/// largest configured class, the table `PowerOfTwoClassMap` expects.
+fn power_of_two_classes(defs: &[ClassDef], page_size: usize) -> Vec<ClassDef> {
+    let max = defs.last().map_or(8, |d| d.size);
+    (3..usize::BITS)
+        .map(|shift| 1usize << shift)
+        .take_while(|&size| size <= max)
+        .map(|size| auto_class(size, page_size))
+        .collect()
+}
+
 fn parse_classes(config: &Config, page_size: usize) -> Vec<ClassDef> {
     if !config.classes.is_empty() && !config.class_full.is_empty() {
         panic!("RTMALLOC_CLASSES: use either `classes = [...]` or `[[class]]`, not both");
@@ -191,7 +367,11 @@ fn validate_classes(defs: &[ClassDef]) {
 
 fn default_config_path() -> String {
     let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
-    format!("{}/default_classes.toml", manifest_dir)
+    if env::var_os("CARGO_FEATURE_MINIMAL").is_some() {
+        format!("{}/minimal_classes.toml", manifest_dir)
+    } else {
+        format!("{}/default_classes.toml", manifest_dir)
+    }
 }
 
 fn generate_config(cfg: &ResolvedConfig, out_path: &Path) {
@@ -204,8 +384,30 @@ fn generate_config(cfg: &ResolvedConfig, out_path: &Path) {
          pub const STEAL_AMOUNT: usize = {};\n\
          pub const MAX_DYNAMIC_FREE_LIST_LENGTH: u32 = {};\n\
          pub const MAX_OVERAGES: u32 = {};\n\
+         pub const THREAD_CACHE_DECAY_MS: u64 = {};\n\
          pub const MAX_TRANSFER_SLOTS: usize = {};\n\
-         pub const MAX_PAGES: usize = {};\n",
+         pub const MAX_TRANSFER_BYTES: usize = {};\n\
+         pub const MAX_OBJECTS_PER_LOCK: usize = {};\n\
+         pub const MAX_PAGES: usize = {};\n\
+         pub const PREFAULT: bool = {};\n\
+         pub const ARRAY_CACHE_SLOTS: usize = {};\n\
+         pub const MAX_RETAINED_SPANS: usize = {};\n\
+         pub const MID_MAX_SIZE: usize = {};\n\
+         pub const MID_CACHE_SPANS: usize = {};\n\
+         pub const LARGE_TRIM_PAGES: usize = {};\n\
+         pub const LARGE_RESERVE_PAGES: usize = {};\n\
+         pub const ADDRESS_ORDERED_SPANS: bool = {};\n\
+         pub const MAX_HEAP: usize = {};\n\
+         pub const HEAP_BASE: usize = {:#x};\n\
+         pub const ZERO_DECOMMIT_MIN: usize = {};\n\
+         pub const FREE_DECOMMIT_MIN: usize = {};\n\
+         pub const SPAN_COOLDOWN: usize = {};\n\
+         pub const SPAN_COOLDOWN_MS: u64 = {};\n\
+         pub const NUMA_INTERLEAVE_MIN: usize = {};\n\
+         pub const SPAN_PAGES_SCALE: usize = {};\n\
+         pub const META_GUARD_PAGES: usize = {};\n\
+         pub const NUM_ARENAS: usize = {};\n\
+         pub const CACHE_LINE_SIZE: usize = {};\n",
         cfg.page_shift,
         cfg.page_size,
         cfg.thread_cache_size,
@@ -213,16 +415,42 @@ fn generate_config(cfg: &ResolvedConfig, out_path: &Path) {
         cfg.steal_amount,
         cfg.max_free_list_length,
         cfg.max_overages,
+        cfg.thread_cache_decay_ms,
         cfg.max_transfer_slots,
+        cfg.max_transfer_bytes,
+        cfg.max_objects_per_lock,
         cfg.max_pages,
+        cfg.prefault,
+        cfg.array_cache_slots,
+        cfg.max_retained_spans,
+        cfg.mid_max_size,
+        cfg.mid_cache_spans,
+        cfg.large_trim_pages,
+        cfg.large_reserve_pages,
+        cfg.address_ordered_spans,
+        cfg.max_heap,
+        cfg.heap_base,
+        cfg.zero_decommit_min,
+        cfg.free_decommit_min,
+        cfg.span_cooldown,
+        cfg.span_cooldown_ms,
+        cfg.numa_interleave_min,
+        cfg.span_pages_scale,
+        cfg.meta_guard_pages,
+        cfg.num_arenas,
+        cfg.cache_line_size,
     );
     fs::write(out_path, code).expect("failed to write config_gen.rs");
 }
 
-fn generate_size_classes(defs: &[ClassDef], out_path: &Path) {
+fn generate_size_classes(cfg: &ResolvedConfig, defs: &[ClassDef], out_path: &Path) {
     let num_size_classes = defs.len() + 1;
 
     let mut code = String::from("// Auto-generated by build.rs. Do not edit.\n\n");
+    code.push_str(&format!(
+        "pub type Classes = {};\n\n",
+        class_map_type(&cfg.class_map).unwrap()
+    ));
 
     code.push_str(&format!(
         "pub static SIZE_CLASSES: [SizeClassInfo; {num_size_classes}] = [\n\
@@ -239,6 +467,72 @@ fn generate_size_classes(defs: &[ClassDef], out_path: &Path) {
     fs::write(out_path, code).expect("failed to write size_class_gen.rs");
 }
 
+/// Enabled cargo features, sorted and comma separated (e.g. `nightly,stats`).
+fn enabled_features() -> String {
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|f| f.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    features.join(",")
+}
+
+/// FNV-1a hash over everything that shapes the allocator's metadata layout:
+/// resolved config, the size class table, and the enabled features.
+fn config_fingerprint(cfg: &ResolvedConfig, defs: &[ClassDef], features: &str) -> u64 {
+    let mut desc = format!(
+        "{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}",
+        env::var("CARGO_PKG_VERSION").unwrap_or_default(),
+        cfg.class_map,
+        cfg.num_arenas,
+        cfg.cache_line_size,
+        cfg.page_shift,
+        cfg.max_heap,
+        cfg.max_pages,
+        cfg.max_transfer_slots,
+        cfg.max_free_list_length,
+        defs.len() + 1,
+        features,
+    );
+    for d in defs {
+        desc.push_str(&format!(":{}/{}/{}", d.size, d.pages, d.batch_size));
+    }
+
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in desc.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+fn generate_build_info(cfg: &ResolvedConfig, defs: &[ClassDef], out_path: &Path) {
+    let features = enabled_features();
+    let version = format!(
+        "{} (features: {}; page_size: {}; classes: {})",
+        env::var("CARGO_PKG_VERSION").unwrap_or_default(),
+        if features.is_empty() {
+            "none"
+        } else {
+            &features
+        },
+        cfg.page_size,
+        defs.len() + 1,
+    );
+    let code = format!(
+        "// Auto-generated by build.rs. Do not edit.\n\n\
+         pub const FEATURES: &str = \"{}\";\n\
+         pub const VERSION_STRING: &core::ffi::CStr = c\"{}\";\n\
+         pub const CONFIG_FINGERPRINT: u64 = {:#018x};\n",
+        features,
+        version,
+        config_fingerprint(cfg, defs, &features),
+    );
+    fs::write(out_path, code).expect("failed to write build_info_gen.rs");
+}
+
 fn main() {
     println!("cargo:rerun-if-env-changed=RTMALLOC_CLASSES");
 
@@ -252,8 +546,27 @@ fn main() {
     let config: Config = toml::from_str(&content).expect("failed to parse TOML config");
 
     let resolved = resolve_config(&config.config);
-    let defs = parse_classes(&config, resolved.page_size);
+    // `repr(align)` takes a literal, so `CachePadded` picks its alignment
+    // by cfg rather than from `CACHE_LINE_SIZE`.
+    println!("cargo:rustc-check-cfg=cfg(rtmalloc_cache_line_128)");
+    if resolved.cache_line_size == 128 {
+        println!("cargo:rustc-cfg=rtmalloc_cache_line_128");
+    }
+    let mut defs = parse_classes(&config, resolved.page_size);
+    if resolved.class_map == "power_of_two" {
+        defs = power_of_two_classes(&defs, resolved.page_size);
+        validate_classes(&defs);
+    }
 
     generate_config(&resolved, &Path::new(&out_dir).join("config_gen.rs"));
-    generate_size_classes(&defs, &Path::new(&out_dir).join("size_class_gen.rs"));
+    generate_size_classes(
+        &resolved,
+        &defs,
+        &Path::new(&out_dir).join("size_class_gen.rs"),
+    );
+    generate_build_info(
+        &resolved,
+        &defs,
+        &Path::new(&out_dir).join("build_info_gen.rs"),
+    );
 }
@@ -0,0 +1,53 @@
+//! Replay a trace recorded with `rtmalloc::trace` against the system
+//! allocator and rtmalloc.
+//!
+//! Run with: cargo run --release --features trace --example replay -- app.trace [runs]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::trace::{self, Replay, ReplayStats};
+use std::alloc::{GlobalAlloc, System};
+use std::process::ExitCode;
+
+fn best<A: GlobalAlloc>(replay: &Replay, alloc: &A, runs: usize) -> ReplayStats {
+    (0..runs)
+        .map(|_| replay.run(alloc))
+        .min_by_key(|s| s.elapsed)
+        .unwrap()
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(path) = args.next() else {
+        eprintln!("usage: replay <trace> [runs]");
+        return ExitCode::FAILURE;
+    };
+    let runs = args.next().and_then(|r| r.parse().ok()).unwrap_or(5).max(1);
+    let records = match trace::read(&path) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let replay = Replay::new(&records);
+    println!(
+        "{path}: {} records, {} calls replayed, {} skipped (best of {runs})",
+        records.len(),
+        replay.len(),
+        replay.skipped()
+    );
+
+    for (name, stats) in [
+        ("system", best(&replay, &System, runs)),
+        ("rtmalloc", best(&replay, &RtMalloc, runs)),
+    ] {
+        let ns_per_op = stats.elapsed.as_nanos() as f64 / stats.ops.max(1) as f64;
+        println!(
+            "  {name:<10} {ns_per_op:>8.1} ns/op  {:>10.3} ms  peak {} KiB  failed {}",
+            stats.elapsed.as_secs_f64() * 1e3,
+            stats.peak_live_bytes / 1024,
+            stats.failed
+        );
+    }
+    ExitCode::SUCCESS
+}
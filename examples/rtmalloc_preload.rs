@@ -0,0 +1,19 @@
+//! Shared library that replaces the system allocator with `LD_PRELOAD`.
+//!
+//! Build with: cargo build --release --features preload --example rtmalloc_preload
+//!
+//! ```text
+//! LD_PRELOAD=target/release/examples/librtmalloc_preload.so ./app
+//! ```
+//!
+//! This crate only links rtmalloc in; the exports come from `c-abi`. For a
+//! cdylib, rustc hands the linker a version script that makes the
+//! `#[no_mangle]` functions global and everything else local, so the
+//! library exports the `malloc` family and `rtmalloc_*` and nothing from
+//! Rust: `std` is linked in statically, with its symbols hidden.
+//! `tests/preload.rs` checks both.
+
+use rtmalloc::RtMalloc;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
@@ -0,0 +1,271 @@
+/*
+ * rtmalloc C interface.
+ *
+ * Declarations for the functions exported by the rtmalloc staticlib or
+ * cdylib. `tests/c_header.rs` checks this file against `src/ffi.rs`, so a
+ * Rust signature change fails the build instead of drifting silently.
+ *
+ * The `ffi` feature exports everything up to `rtmalloc_features`. The
+ * `c-abi` feature adds the `rtmalloc_set_*` / `rtmalloc_foreign_stats`
+ * controls below, plus the standard `malloc` family (`malloc`, `free`,
+ * `realloc`, `calloc`, `posix_memalign`, `aligned_alloc`, `memalign`,
+ * `valloc`, `pvalloc`, `malloc_usable_size`, `mallinfo`, `mallinfo2`), which
+ * are declared by the system headers and not repeated here.
+ *
+ * Builds with the `testing` feature prefix every export by variant
+ * (`rtmalloc_nightly_alloc`, ...); this header covers the plain names only.
+ */
+
+#ifndef RTMALLOC_H
+#define RTMALLOC_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#if defined(__clang__)
+#define RTMALLOC_NULLABLE _Nullable
+#define RTMALLOC_NONNULL _Nonnull
+#else
+#define RTMALLOC_NULLABLE
+#define RTMALLOC_NONNULL
+#endif
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* ---- ffi ---------------------------------------------------------------- */
+
+/*
+ * Allocate `size` bytes aligned to `align`. Returns NULL on failure: out of
+ * memory, or (with errno set to EINVAL) `align` not a power of two or `size`
+ * rounded up to `align` larger than PTRDIFF_MAX.
+ */
+void *RTMALLOC_NULLABLE rtmalloc_alloc(size_t size, size_t align);
+
+/*
+ * Free `ptr`, which must come from `rtmalloc_alloc` or `rtmalloc_realloc`
+ * with the same `size` and `align`. NULL, or a `size`/`align` pair
+ * `rtmalloc_alloc` rejects, is ignored.
+ */
+void rtmalloc_dealloc(void *RTMALLOC_NULLABLE ptr, size_t size, size_t align);
+
+/*
+ * Resize `ptr` (allocated with `size` and `align`) to `new_size` bytes.
+ * Returns NULL on failure, in which case `ptr` is left untouched; errno is
+ * EINVAL if either size is invalid with `align` as for `rtmalloc_alloc`.
+ */
+void *RTMALLOC_NULLABLE rtmalloc_realloc(void *RTMALLOC_NONNULL ptr, size_t size,
+                                         size_t align, size_t new_size);
+
+/*
+ * The three calls above without the argument checks, for trusted callers
+ * whose sizes and alignments are known to be valid. Invalid ones are
+ * undefined behaviour.
+ */
+void *RTMALLOC_NULLABLE rtmalloc_alloc_unchecked(size_t size, size_t align);
+void rtmalloc_dealloc_unchecked(void *RTMALLOC_NONNULL ptr, size_t size, size_t align);
+void *RTMALLOC_NULLABLE rtmalloc_realloc_unchecked(void *RTMALLOC_NONNULL ptr, size_t size,
+                                                   size_t align, size_t new_size);
+
+/* Flags for `rtmalloc_alloc_hint`. */
+#define RTMALLOC_HINT_SHORT_LIVED (1u << 0) /* freed soon, on the same thread */
+#define RTMALLOC_HINT_COLD (1u << 1)        /* long-lived, rarely touched */
+#define RTMALLOC_HINT_INTERLEAVE (1u << 2)  /* large, shared across NUMA nodes */
+
+/*
+ * `rtmalloc_alloc` with RTMALLOC_HINT_* flags. Cold small objects come from
+ * spans of their own instead of fragmenting the spans hot objects churn
+ * through; interleaved large ones have their pages spread over all NUMA
+ * nodes. Unknown flags are ignored.
+ */
+void *RTMALLOC_NULLABLE rtmalloc_alloc_hint(size_t size, size_t align, uint32_t hints);
+
+/*
+ * Free `ptr`, allocated with `size` bytes and an `align` of at most 8 and
+ * never passed to `rtmalloc_realloc`, using `size` instead of a page map
+ * lookup to find its size class. NULL is ignored; a wrong `size` is
+ * undefined behaviour.
+ */
+void rtmalloc_dealloc_sized(void *RTMALLOC_NULLABLE ptr, size_t size);
+
+/*
+ * Version and build configuration as a static NUL-terminated string, e.g.
+ * "0.1.0 (features: ffi,nightly; page_size: 8192; classes: 46)".
+ * Never NULL; must not be freed.
+ */
+const char *RTMALLOC_NONNULL rtmalloc_version(void);
+
+/* Fingerprint of the build configuration. Equal values mean equal
+ * allocator metadata layouts. */
+uint64_t rtmalloc_config_fingerprint(void);
+
+/* Identifier unique to this process's heap. Never zero. */
+uint64_t rtmalloc_heap_id(void);
+
+/* Give everything the calling thread has cached back for other threads. */
+void rtmalloc_thread_flush(void);
+
+/* Run the self-test. Returns 0 if every check passed, otherwise a mask with
+ * one bit set per failed check. */
+uint32_t rtmalloc_selftest(void);
+
+/* glibc-compatible `struct mallinfo2`. See `Mallinfo2` for the mapping. */
+struct rtmalloc_mallinfo2 {
+  size_t arena;
+  size_t ordblks;
+  size_t smblks;
+  size_t hblks;
+  size_t hblkhd;
+  size_t usmblks;
+  size_t fsmblks;
+  size_t uordblks;
+  size_t fordblks;
+  size_t keepcost;
+};
+
+/* Best-effort heap summary. All zero if called from inside the allocator. */
+struct rtmalloc_mallinfo2 rtmalloc_mallinfo2(void);
+
+/* Which variant this binary embeds and what it found on the machine. */
+struct rtmalloc_features {
+  /* Compile-time features. */
+  bool percpu;
+  bool nightly_tls;
+  bool std;
+  bool stats;
+  /* The kernel implements rseq. */
+  bool rseq;
+  /* Transparent huge pages are enabled (always or madvise). */
+  bool hugepages;
+  /* NUMA nodes online, 0 if unknown. */
+  uint32_t numa_nodes;
+};
+
+/* Probed on every call; keep it out of hot paths. */
+struct rtmalloc_features rtmalloc_features(void);
+
+/* One size class of the table this build was made with. */
+struct rtmalloc_size_class {
+  size_t index;
+  /* Allocation size in bytes. */
+  size_t size;
+  /* Pages per span. */
+  size_t pages;
+  /* Objects moved per transfer to and from the central lists. */
+  size_t batch;
+};
+
+/* Number of size classes; they are numbered 1 to this, smallest first. */
+size_t rtmalloc_num_classes(void);
+
+/* Fill `out` with class `index` and return true, or return false if there
+ * is no such class. */
+bool rtmalloc_class_info(size_t index, struct rtmalloc_size_class *RTMALLOC_NULLABLE out);
+
+/*
+ * Receives a report in order, in pieces of at most 256 bytes that are not
+ * NUL-terminated; a piece may end inside a UTF-8 sequence. `ctx` is the
+ * pointer passed to the `rtmalloc_write_*` call.
+ */
+typedef void (*rtmalloc_write_callback)(void *RTMALLOC_NULLABLE ctx,
+                                        const char *RTMALLOC_NONNULL text, size_t len);
+
+/* stats builds: every counter, page heap occupancy and count_site label,
+ * one `name value` line each. */
+void rtmalloc_write_stats(rtmalloc_write_callback RTMALLOC_NONNULL callback,
+                          void *RTMALLOC_NULLABLE ctx);
+
+/* stats builds: a table of the size classes and their cached objects. */
+void rtmalloc_write_class_report(rtmalloc_write_callback RTMALLOC_NONNULL callback,
+                                 void *RTMALLOC_NULLABLE ctx);
+
+/* alloc-histogram builds: the allocation size histogram and a suggested
+ * class table. */
+void rtmalloc_write_histogram(rtmalloc_write_callback RTMALLOC_NONNULL callback,
+                              void *RTMALLOC_NULLABLE ctx);
+
+/* ---- c-abi -------------------------------------------------------------- */
+
+/* An internal invariant that failed, as passed to the failure handler. */
+enum rtmalloc_failure {
+  RTMALLOC_FAILURE_ZERO_PAGE_SPAN = 0,
+  RTMALLOC_FAILURE_SPAN_TOO_SMALL = 1,
+  RTMALLOC_FAILURE_PAGE_OUT_OF_RANGE = 2,
+  RTMALLOC_FAILURE_PAGE_MAP_NODE = 3,
+  RTMALLOC_FAILURE_CORRUPTED_FREE_LIST = 4,
+  /* lock-debug builds: an allocator lock taken twice by one thread. */
+  RTMALLOC_FAILURE_LOCK_REENTERED = 5,
+  /* lock-debug builds: an allocator lock released by a panic. */
+  RTMALLOC_FAILURE_LOCK_POISONED = 6,
+  /* layout-check builds: a free whose size maps to another class. */
+  RTMALLOC_FAILURE_LAYOUT_MISMATCH = 7,
+  /* heap_base builds: the heap region's address is already mapped. */
+  RTMALLOC_FAILURE_HEAP_PLACEMENT = 8,
+  /* double-free-check builds: an object freed twice into a thread cache. */
+  RTMALLOC_FAILURE_DOUBLE_FREE = 9,
+};
+
+/*
+ * Called with the failure and a related address or page id (0 if none).
+ * Runs inside the allocator, possibly with locks held: it must not allocate
+ * or free through rtmalloc.
+ */
+typedef void (*rtmalloc_failure_handler)(enum rtmalloc_failure failure,
+                                         size_t addr);
+
+/* One change in the memory the page heap holds from the OS. */
+struct rtmalloc_growth_event {
+  /* Start of the region mapped or released. */
+  uintptr_t addr;
+  /* Bytes added (positive) or given back (negative). */
+  ptrdiff_t delta;
+  /* Bytes held from the OS for spans after the change. */
+  size_t system_bytes;
+};
+
+/*
+ * Called with every growth event. Runs with the page heap lock held: it must
+ * not allocate or free through rtmalloc and should return quickly.
+ */
+typedef void (*rtmalloc_growth_hook)(struct rtmalloc_growth_event event);
+
+/* Foreign pointer counters since process start. */
+struct rtmalloc_foreign_stats {
+  uint64_t frees;
+  uint64_t reallocs;
+  uint64_t migrated;
+  uint64_t migrated_bytes;
+  uint64_t leaked;
+};
+
+/* 0 = forward foreign pointers, 1 = migrate them on realloc. Returns 0, or
+ * EINVAL for an unknown policy. */
+int rtmalloc_set_foreign_policy(int policy);
+
+/* 0 = abort, 1 = return NULL, 2 = call the handler. Returns 0, or EINVAL
+ * for an unknown policy. */
+int rtmalloc_set_failure_policy(int policy);
+
+/* Register `handler` and switch to the handler policy. NULL goes back to
+ * aborting. */
+void rtmalloc_set_failure_handler(rtmalloc_failure_handler RTMALLOC_NULLABLE handler);
+
+/* Error of the latest request for memory the OS refused (errno on Unix,
+ * usually ENOMEM; GetLastError() on Windows), or 0 if none was. A nonzero
+ * `clear` resets it to 0 after reading. */
+int rtmalloc_last_error(int clear);
+
+/* Register `hook` for page heap growth events. NULL removes it. */
+void rtmalloc_set_growth_hook(rtmalloc_growth_hook RTMALLOC_NULLABLE hook);
+
+/* Copy the foreign pointer counters to `*out`. Does nothing if `out` is
+ * NULL. */
+void rtmalloc_foreign_stats(struct rtmalloc_foreign_stats *RTMALLOC_NULLABLE out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RTMALLOC_H */
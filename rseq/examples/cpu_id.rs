@@ -66,11 +66,11 @@ fn main() {
     let mut region = vec![0u8; region_size];
 
     // Capacities per class: class 0 unused, classes 1-3 get 16 slots each.
-    let capacities: [u16; NUM_CLASSES] = [0, 16, 16, 16];
+    let capacities: [u32; NUM_CLASSES] = [0, 16, 16, 16];
 
     let mut slab = PerCpuSlab::<NUM_CLASSES>::empty();
-    let ok = unsafe { slab.init(region.as_mut_ptr(), num_cpus, SHIFT, &capacities) };
-    assert!(ok, "slab layout doesn't fit in 2^{SHIFT} bytes");
+    unsafe { slab.init(region.as_mut_ptr(), num_cpus, SHIFT, &capacities) }
+        .unwrap_or_else(|e| panic!("slab layout rejected: {e}"));
 
     RSEQ.with(|r| {
         let rseq_ptr = r.rseq_ptr().expect("rseq available");
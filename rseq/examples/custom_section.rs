@@ -0,0 +1,112 @@
+//! Example: a custom per-CPU data structure built with `critical_section!`.
+//!
+//! A cacheline-padded per-CPU counter. Each increment is a restartable
+//! sequence: read `cpu_id`, load the CPU's counter, add one, store it back.
+//! The store is the commit; if the thread is preempted or migrated before
+//! it, the kernel jumps to the abort handler and we simply retry.
+//!
+//! Run with:
+//!   cargo run -p rseq --features nightly --example custom_section
+
+use rseq::{Outcome, RseqLocal};
+
+thread_local! {
+    static RSEQ: RseqLocal = const { RseqLocal::new() };
+}
+
+/// One counter per cacheline to avoid false sharing.
+#[repr(C, align(64))]
+struct Slot {
+    count: u64,
+}
+
+struct PerCpuCounter {
+    slots: Box<[Slot]>,
+}
+
+impl PerCpuCounter {
+    fn new(num_cpus: usize) -> Self {
+        Self {
+            slots: (0..num_cpus).map(|_| Slot { count: 0 }).collect(),
+        }
+    }
+
+    /// Increment the current CPU's counter. Returns the CPU it landed on.
+    fn increment(&self) -> Option<u32> {
+        let rseq = RSEQ.with(|r| r.rseq_ptr())?;
+        let base = self.slots.as_ptr();
+        loop {
+            let cpu: u64;
+            let outcome = unsafe {
+                rseq::critical_section!(
+                    rseq = rseq,
+                    body = [
+                        "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                        // Bail out if the CPU is beyond the array.
+                        "cmp {cpu}, {len}",
+                        "jae 7f",
+                        // off = cpu * 64
+                        "mov {off}, {cpu}",
+                        "shl {off}, 6",
+                        "mov {val}, qword ptr [{base} + {off}]",
+                        "inc {val}",
+                        // Commit.
+                        "mov qword ptr [{base} + {off}], {val}",
+                    ],
+                    base = in(reg) base,
+                    len = in(reg) self.slots.len(),
+                    cpu = out(reg) cpu,
+                    off = out(reg) _,
+                    val = out(reg) _,
+                    options(nostack),
+                )
+            };
+            match outcome {
+                Outcome::Committed => return Some(cpu as u32),
+                Outcome::Bailed => return None,
+                Outcome::Aborted => continue,
+            }
+        }
+    }
+
+    fn sum(&self) -> u64 {
+        self.slots.iter().map(|s| s.count).sum()
+    }
+}
+
+// Writers only touch their own CPU's slot inside a critical section.
+unsafe impl Sync for PerCpuCounter {}
+
+fn main() {
+    if RSEQ.with(|r| r.cpu_id()).is_none() {
+        println!("rseq unavailable (kernel too old or not Linux x86_64).");
+        return;
+    }
+
+    // Sized by configured CPUs; cpu_id can exceed the online count.
+    let num_cpus = 1024;
+    let counter = PerCpuCounter::new(num_cpus);
+
+    const THREADS: u64 = 4;
+    const PER_THREAD: u64 = 100_000;
+    std::thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                for _ in 0..PER_THREAD {
+                    counter
+                        .increment()
+                        .expect("cpu id within the counter array");
+                }
+            });
+        }
+    });
+
+    let total = counter.sum();
+    println!("total = {total} (expected {})", THREADS * PER_THREAD);
+    assert_eq!(total, THREADS * PER_THREAD);
+    for (cpu, slot) in counter.slots.iter().enumerate() {
+        if slot.count != 0 {
+            println!("  cpu {cpu}: {}", slot.count);
+        }
+    }
+}
@@ -44,7 +44,7 @@ const NUM_CLASSES: usize = 4;
 const CLASS_SIZES: [usize; NUM_CLASSES] = [0, 64, 128, 256];
 
 /// How many pointers each CPU can cache per class.
-const SLAB_CAPACITY: u16 = 16;
+const SLAB_CAPACITY: u32 = 16;
 
 /// When the slab is empty, fetch this many blocks from central at once.
 const BATCH_SIZE: usize = 8;
@@ -230,11 +230,11 @@ fn main() {
     // Set up the per-CPU slab.
     let region_size = (num_cpus as usize) << SHIFT;
     let mut region = vec![0u8; region_size];
-    let capacities: [u16; NUM_CLASSES] = [0, SLAB_CAPACITY, SLAB_CAPACITY, SLAB_CAPACITY];
+    let capacities: [u32; NUM_CLASSES] = [0, SLAB_CAPACITY, SLAB_CAPACITY, SLAB_CAPACITY];
 
     let mut slab = PerCpuSlab::<NUM_CLASSES>::empty();
-    let ok = unsafe { slab.init(region.as_mut_ptr(), num_cpus, SHIFT, &capacities) };
-    assert!(ok, "slab layout doesn't fit");
+    unsafe { slab.init(region.as_mut_ptr(), num_cpus, SHIFT, &capacities) }
+        .unwrap_or_else(|e| panic!("slab layout rejected: {e}"));
 
     let allocator = PerCpuAllocator {
         slab,
@@ -6,6 +6,20 @@
 /// rseq syscall number on x86_64.
 pub const SYS_RSEQ: u64 = 334;
 
+/// membarrier syscall number on x86_64.
+pub const SYS_MEMBARRIER: u64 = 324;
+
+/// membarrier command: restart the rseq critical sections of this
+/// process's threads running on the targeted CPUs (kernel >= 5.10).
+pub const MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ: i32 = 1 << 7;
+
+/// membarrier command: register the process for
+/// [`MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ`].
+pub const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_RSEQ: i32 = 1 << 8;
+
+/// membarrier flag: target only the CPU given in `cpu_id`.
+pub const MEMBARRIER_CMD_FLAG_CPU: u32 = 1 << 0;
+
 /// Unregister the current thread's rseq area.
 pub const RSEQ_FLAG_UNREGISTER: i32 = 1 << 0;
 
@@ -0,0 +1,163 @@
+//! Building block for custom rseq critical sections (x86_64).
+//!
+//! Every critical section needs the same scaffolding around a few
+//! instructions of real work: an `rseq_cs` descriptor in the `__rseq_cs`
+//! section, the store that arms it in `rseq->rseq_cs`, the `RSEQ_SIG`
+//! signature in front of the abort handler, and the paths that disarm it
+//! again. [`critical_section!`] emits all of that; the caller supplies only
+//! the body and its operands.
+//!
+//! # Body contract
+//!
+//! The body is a list of assembly template strings that runs between
+//! `start_ip` and `post_commit_ip`:
+//!
+//! - The **last instruction must be the single commit store**. Everything
+//!   before it must be side-effect free (loads, arithmetic, stores to
+//!   memory that is not yet published), because the kernel may abort and
+//!   the caller will re-run the whole sequence.
+//! - Jump to `7f` to bail out without committing (e.g. a stack is empty or
+//!   a compare failed). The macro returns [`Outcome::Bailed`].
+//! - Labels `3`, `4`, `5`, `6`, `7` and `77` are used by the scaffolding;
+//!   use other numbers (e.g. `10`–`19`) for local labels in the body.
+//! - `{rseq}` names the rseq area and `{cpu_id_off}` the byte offset of
+//!   `cpu_id` in it, so `"mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]"`
+//!   reads the current CPU. Operand names starting with `rseq_` are
+//!   reserved.
+//!
+//! The body must not call functions or touch the stack. Pass
+//! `options(nostack)` at the end of the operand list when it doesn't use
+//! the red zone either, as every section in this crate does.
+//!
+//! # Example
+//!
+//! ```ignore
+//! // Increment a u32 counter in a cacheline-padded per-CPU array.
+//! let cpu: u64;
+//! let outcome = unsafe {
+//!     rseq::critical_section!(
+//!         rseq = rseq_ptr,
+//!         body = [
+//!             "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+//!             "mov {off}, {cpu}",
+//!             "shl {off}, 6",
+//!             "mov {val:e}, dword ptr [{base} + {off}]",
+//!             "inc {val:e}",
+//!             "mov dword ptr [{base} + {off}], {val:e}", // commit
+//!         ],
+//!         base = in(reg) counters,
+//!         cpu = out(reg) cpu,
+//!         off = out(reg) _,
+//!         val = out(reg) _,
+//!         options(nostack),
+//!     )
+//! };
+//! if outcome.is_committed() { /* ran on `cpu` */ }
+//! ```
+
+/// Result of one attempt at a critical section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The body ran to completion, including the commit store.
+    Committed,
+    /// The body jumped to `7f`; nothing was committed.
+    Bailed,
+    /// The kernel preempted, migrated or signalled the thread inside the
+    /// section; nothing was committed. Retry.
+    Aborted,
+}
+
+impl Outcome {
+    /// Decode the status register written by [`critical_section!`].
+    #[doc(hidden)]
+    #[inline(always)]
+    pub const fn from_status(status: u64) -> Self {
+        match status {
+            0 => Self::Committed,
+            1 => Self::Bailed,
+            _ => Self::Aborted,
+        }
+    }
+
+    /// Whether the commit store executed.
+    #[inline(always)]
+    pub const fn is_committed(self) -> bool {
+        matches!(self, Self::Committed)
+    }
+}
+
+/// Run an rseq critical section and return its [`Outcome`].
+///
+/// ```ignore
+/// critical_section!(
+///     rseq = <*mut Rseq expression>,
+///     body = ["insn", "insn", ..., "commit insn"],
+///     <asm! operands and options>
+/// )
+/// ```
+///
+/// See the [module documentation](crate::cs) for the body contract.
+///
+/// # Safety
+///
+/// Must be invoked inside `unsafe`. `rseq` must be the registered rseq
+/// area of the current thread, and the body must follow the contract
+/// above; the macro cannot check the assembly.
+#[macro_export]
+macro_rules! critical_section {
+    (rseq = $rseq:expr, body = [$($body:expr),+ $(,)?], $($operands:tt)*) => {{
+        let rseq_status: u64;
+        ::core::arch::asm!(
+            // rseq_cs descriptor in a relocatable data section.
+            ".pushsection __rseq_cs, \"aw\"",
+            ".balign 32",
+            "77:",
+            ".long 0",                     // version
+            ".long 0",                     // flags
+            ".quad 3f",                    // start_ip
+            ".quad (4f - 3f)",             // post_commit_offset
+            ".quad 6f",                    // abort_ip
+            ".popsection",
+
+            // Arm: rseq->rseq_cs = &descriptor
+            "lea {rseq_tmp}, [rip + 77b]",
+            "mov qword ptr [{rseq} + {rseq_cs_off}], {rseq_tmp}",
+
+            // -- start_ip --
+            // (`{cpu_id_off}` is for the body; mentioned here so bodies
+            // that don't read the CPU still compile.)
+            "/* cpu_id at +{cpu_id_off} */",
+            "3:",
+            $($body,)+
+            // -- post_commit_ip --
+            "4:",
+
+            "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
+            "xor {rseq_status:e}, {rseq_status:e}",
+            "jmp 5f",
+
+            // Bail-out path.
+            "7:",
+            "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
+            "mov {rseq_status:e}, 1",
+            "jmp 5f",
+
+            // Abort handler, preceded by the signature.
+            ".long {rseq_sig}",
+            "6:",
+            "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
+            "mov {rseq_status:e}, 2",
+
+            "5:",
+
+            rseq = in(reg) $rseq,
+            rseq_tmp = out(reg) _,
+            rseq_status = out(reg) rseq_status,
+            rseq_cs_off = const $crate::abi::RSEQ_OFF_RSEQ_CS,
+            cpu_id_off = const $crate::abi::RSEQ_OFF_CPU_ID,
+            rseq_sig = const $crate::abi::RSEQ_SIG,
+            $($operands)*
+        );
+        $crate::cs::Outcome::from_status(rseq_status)
+    }};
+}
@@ -4,6 +4,28 @@
 //! Provides per-CPU atomic operations without hardware atomics on the
 //! fast path — the kernel handles preemption detection.
 //!
+//! # Custom critical sections
+//!
+//! [`critical_section!`] emits the descriptor, signature and abort glue
+//! around a caller-supplied body, so downstream crates can build their own
+//! per-CPU structures; see [`cs`] and `examples/custom_section.rs`.
+//!
+//! # Per-CPU slabs
+//!
+//! [`PerCpuSlab`] keeps a LIFO stack of pointers per CPU and size class,
+//! pushed and popped by critical sections. Its capacities can be moved
+//! per CPU, and [`PerCpuSlab::stop_cpu`] gives any thread a [`StoppedCpu`]
+//! to drain, refill, resize or walk another CPU's stacks; see [`percpu`].
+//! Under Miri the slab falls back to a lock, so code built on it can be
+//! checked there too.
+//!
+//! # Per-CPU lists
+//!
+//! [`percpu_list_push`], [`percpu_list_push_chain`] and [`percpu_list_pop`]
+//! push and pop nodes of an intrusive linked list per CPU, for layers that
+//! keep their objects linked through their first word rather than in
+//! pointer arrays.
+//!
 //! # Features
 //!
 //! - `nightly` — enables `#[thread_local]` for the self-managed rseq area
@@ -17,7 +39,11 @@
 #![no_std]
 #![cfg_attr(feature = "nightly", feature(thread_local, linkage))]
 
+#[cfg(test)]
+extern crate std;
+
 pub mod abi;
+pub mod cs;
 pub mod ops;
 pub mod percpu;
 pub mod syscall;
@@ -25,6 +51,11 @@ pub mod thread;
 
 // Re-export key types at crate root.
 pub use abi::{RSEQ_SIG, Rseq, RseqCs};
-pub use ops::{percpu_add, percpu_cmpxchg, percpu_load, percpu_store};
-pub use percpu::{PerCpuSlab, SlabHeader};
+pub use cs::Outcome;
+pub use ops::{
+    PerCpuInt, PerCpuWord, percpu_add, percpu_add_strided, percpu_cmpxchg, percpu_cmpxchg_strided,
+    percpu_list_pop, percpu_list_push, percpu_list_push_chain, percpu_load, percpu_load_strided,
+    percpu_store, percpu_store_strided,
+};
+pub use percpu::{PerCpuSlab, SlabHeader, SlabInitError, StoppedCpu, WideSlabHeader};
 pub use thread::{RseqLocal, current_cpu, current_rseq, rseq_available};
@@ -7,21 +7,24 @@
 //! The fast path executes with zero atomic instructions — all
 //! synchronisation is handled by the kernel's preemption detection.
 //!
-//! # Abort handler contract
+//! `percpu_{load,store,add,cmpxchg}` address a dense `[u64; num_cpus]`.
+//! The `*_strided` variants take the element for CPU 0 and the byte stride
+//! between CPUs, and work on any [`PerCpuWord`] (`u32`, `u64`, pointers,
+//! ...), so a field inside a cacheline-padded per-CPU struct can be used
+//! directly.
 //!
-//! Every abort label must be preceded by the 4-byte `RSEQ_SIG` signature
-//! (`0x53053053`). On x86_64 this encodes as `ud1 %edi, %eax`, a
-//! guaranteed-illegal instruction that provides control-flow integrity.
-
-use core::arch::asm;
+//! `percpu_list_{push,push_chain,pop}` keep an intrusive singly linked
+//! list per CPU, addressed the same strided way: the head for the current
+//! CPU is swapped inside the critical section, so lists of free objects can
+//! live in the per-CPU tier as they are, without copying their pointers
+//! into a slab.
+//!
+//! The descriptor, signature and abort handler glue come from
+//! [`critical_section!`](crate::critical_section); see [`crate::cs`] for
+//! writing new primitives.
 
 use crate::abi::Rseq;
-
-/// Byte offset of `rseq_cs` within `struct Rseq`.
-const RSEQ_CS_OFFSET: u32 = 8;
-
-/// Byte offset of `cpu_id` within `struct Rseq`.
-const CPU_ID_OFFSET: u32 = 4;
+use crate::cs::Outcome;
 
 /// Load a `u64` value from `array[cpu_id]`.
 ///
@@ -37,67 +40,24 @@ const CPU_ID_OFFSET: u32 = 4;
 pub unsafe fn percpu_load(rseq: *mut Rseq, array: *const u64) -> Option<(u32, u64)> {
     let cpu: u64;
     let value: u64;
-    let success: u64;
 
-    unsafe {
-        asm!(
-            // rseq_cs descriptor in a relocatable data section.
-            ".pushsection __rseq_cs, \"aw\"",
-            ".balign 32",
-            "77:",
-            ".long 0",                         // version
-            ".long 0",                         // flags
-            ".quad 3f",                        // start_ip
-            ".quad (4f - 3f)",                 // post_commit_offset
-            ".quad 6f",                        // abort_ip
-            ".popsection",
-
-            // Store rseq_cs pointer into rseq->rseq_cs
-            "lea {tmp}, [rip + 77b]",
-            "mov qword ptr [{rseq} + {rseq_cs_off}], {tmp}",
-
-            // -- start_ip --
-            "3:",
-            // Read cpu_id
-            "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
-
-            // Load value from array[cpu_id]
-            "mov {val}, qword ptr [{array} + {cpu} * 8]",
-
-            // -- commit (the load itself is the commit point) --
-            "4:",
-
-            // Clear rseq_cs
-            "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-
-            // Signal success
-            "mov {succ}, 1",
-            "jmp 5f",
-
-            ".long 0x53053053",                // RSEQ_SIG before abort_ip
-            "6:",
-            "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-            "xor {succ:e}, {succ:e}",          // success = 0
-
-            "5:",
-
-            rseq = in(reg) rseq,
+    let outcome = unsafe {
+        crate::critical_section!(
+            rseq = rseq,
+            body = [
+                // Read cpu_id
+                "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                // Load value from array[cpu_id] (the load is the commit point)
+                "mov {val}, qword ptr [{array} + {cpu} * 8]",
+            ],
             array = in(reg) array,
             cpu = out(reg) cpu,
             val = out(reg) value,
-            succ = out(reg) success,
-            tmp = out(reg) _,
-            rseq_cs_off = const RSEQ_CS_OFFSET,
-            cpu_id_off = const CPU_ID_OFFSET,
             options(nostack),
-        );
-    }
+        )
+    };
 
-    if success != 0 {
-        Some((cpu as u32, value))
-    } else {
-        None
-    }
+    outcome.is_committed().then_some((cpu as u32, value))
 }
 
 /// Store a `u64` value to `array[cpu_id]`.
@@ -112,54 +72,23 @@ pub unsafe fn percpu_load(rseq: *mut Rseq, array: *const u64) -> Option<(u32, u6
 #[inline(never)]
 pub unsafe fn percpu_store(rseq: *mut Rseq, array: *mut u64, value: u64) -> Option<u32> {
     let cpu: u64;
-    let success: u64;
 
-    unsafe {
-        asm!(
-            ".pushsection __rseq_cs, \"aw\"",
-            ".balign 32",
-            "77:",
-            ".long 0",
-            ".long 0",
-            ".quad 3f",
-            ".quad (4f - 3f)",
-            ".quad 6f",
-            ".popsection",
-
-            "lea {tmp}, [rip + 77b]",
-            "mov qword ptr [{rseq} + {rseq_cs_off}], {tmp}",
-
-            "3:",
-            "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
-
-            // Commit: store value into array[cpu_id]
-            "mov qword ptr [{array} + {cpu} * 8], {val}",
-            "4:",
-
-            "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-            "mov {succ}, 1",
-            "jmp 5f",
-
-            ".long 0x53053053",
-            "6:",
-            "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-            "xor {succ:e}, {succ:e}",
-
-            "5:",
-
-            rseq = in(reg) rseq,
+    let outcome = unsafe {
+        crate::critical_section!(
+            rseq = rseq,
+            body = [
+                "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                // Commit: store value into array[cpu_id]
+                "mov qword ptr [{array} + {cpu} * 8], {val}",
+            ],
             array = in(reg) array,
             val = in(reg) value,
             cpu = out(reg) cpu,
-            succ = out(reg) success,
-            tmp = out(reg) _,
-            rseq_cs_off = const RSEQ_CS_OFFSET,
-            cpu_id_off = const CPU_ID_OFFSET,
             options(nostack),
-        );
-    }
+        )
+    };
 
-    if success != 0 { Some(cpu as u32) } else { None }
+    outcome.is_committed().then_some(cpu as u32)
 }
 
 /// Add `delta` to `array[cpu_id]` (u64 element).
@@ -172,64 +101,34 @@ pub unsafe fn percpu_store(rseq: *mut Rseq, array: *mut u64, value: u64) -> Opti
 #[inline(never)]
 pub unsafe fn percpu_add(rseq: *mut Rseq, array: *mut u64, delta: u64) -> Option<u32> {
     let cpu: u64;
-    let success: u64;
 
-    unsafe {
-        asm!(
-            ".pushsection __rseq_cs, \"aw\"",
-            ".balign 32",
-            "77:",
-            ".long 0",
-            ".long 0",
-            ".quad 3f",
-            ".quad (4f - 3f)",
-            ".quad 6f",
-            ".popsection",
-
-            "lea {tmp}, [rip + 77b]",
-            "mov qword ptr [{rseq} + {rseq_cs_off}], {tmp}",
-
-            "3:",
-            "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
-
-            // Load current value, add delta, store back (commit).
-            "mov {scratch}, qword ptr [{array} + {cpu} * 8]",
-            "add {scratch}, {delta}",
-            "mov qword ptr [{array} + {cpu} * 8], {scratch}",
-            "4:",
-
-            "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-            "mov {succ}, 1",
-            "jmp 5f",
-
-            ".long 0x53053053",
-            "6:",
-            "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-            "xor {succ:e}, {succ:e}",
-
-            "5:",
-
-            rseq = in(reg) rseq,
+    let outcome = unsafe {
+        crate::critical_section!(
+            rseq = rseq,
+            body = [
+                "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                // Load current value, add delta, store back (commit).
+                "mov {scratch}, qword ptr [{array} + {cpu} * 8]",
+                "add {scratch}, {delta}",
+                "mov qword ptr [{array} + {cpu} * 8], {scratch}",
+            ],
             array = in(reg) array,
             delta = in(reg) delta,
             cpu = out(reg) cpu,
-            succ = out(reg) success,
-            tmp = out(reg) _,
             scratch = out(reg) _,
-            rseq_cs_off = const RSEQ_CS_OFFSET,
-            cpu_id_off = const CPU_ID_OFFSET,
             options(nostack),
-        );
-    }
+        )
+    };
 
-    if success != 0 { Some(cpu as u32) } else { None }
+    outcome.is_committed().then_some(cpu as u32)
 }
 
 /// Compare-and-exchange on `array[cpu_id]`.
 ///
 /// If `array[cpu_id] == expected`, stores `new` and returns
 /// `Ok((cpu, expected))`. Otherwise returns `Err(actual)` with the
-/// value that was found. Also returns `Err` on abort (CPU migration).
+/// value that was found. On abort (CPU migration) returns
+/// `Err(expected)`, since the real value is unknown.
 ///
 /// # Safety
 ///
@@ -243,73 +142,723 @@ pub unsafe fn percpu_cmpxchg(
 ) -> Result<(u32, u64), u64> {
     let cpu: u64;
     let old_val: u64;
-    let success: u64;
 
-    unsafe {
-        asm!(
-            ".pushsection __rseq_cs, \"aw\"",
-            ".balign 32",
-            "77:",
-            ".long 0",
-            ".long 0",
-            ".quad 3f",
-            ".quad (4f - 3f)",
-            ".quad 6f",
-            ".popsection",
-
-            "lea {tmp}, [rip + 77b]",
-            "mov qword ptr [{rseq} + {rseq_cs_off}], {tmp}",
-
-            "3:",
-            "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
-
-            // Load current value
-            "mov {old}, qword ptr [{array} + {cpu} * 8]",
-
-            // Compare with expected
-            "cmp {old}, {exp}",
-            "jne 7f",                          // mismatch → fail path
-
-            // Commit: store new value
-            "mov qword ptr [{array} + {cpu} * 8], {new}",
-            "4:",
-
-            "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-            "mov {succ}, 1",
-            "jmp 5f",
-
-            "7:",
-            "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-            "xor {succ:e}, {succ:e}",
-            "jmp 5f",
-
-            ".long 0x53053053",
-            "6:",
-            "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-            "xor {succ:e}, {succ:e}",
-            // Set old to expected so caller knows it was an abort,
-            // not a value mismatch (real value unknown after abort).
-            "mov {old}, {exp}",
-
-            "5:",
-
-            rseq = in(reg) rseq,
+    let outcome = unsafe {
+        crate::critical_section!(
+            rseq = rseq,
+            body = [
+                "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                // Load current value
+                "mov {old}, qword ptr [{array} + {cpu} * 8]",
+                // Compare with expected; mismatch → bail
+                "cmp {old}, {exp}",
+                "jne 7f",
+                // Commit: store new value
+                "mov qword ptr [{array} + {cpu} * 8], {new}",
+            ],
             array = in(reg) array,
             exp = in(reg) expected,
             new = in(reg) new,
             cpu = out(reg) cpu,
             old = out(reg) old_val,
-            succ = out(reg) success,
-            tmp = out(reg) _,
-            rseq_cs_off = const RSEQ_CS_OFFSET,
-            cpu_id_off = const CPU_ID_OFFSET,
             options(nostack),
-        );
+        )
+    };
+
+    match outcome {
+        Outcome::Committed => Ok((cpu as u32, old_val)),
+        Outcome::Bailed => Err(old_val),
+        Outcome::Aborted => Err(expected),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Strided variants
+// ---------------------------------------------------------------------------
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A value the strided per-CPU operations can access: 4 or 8 bytes wide.
+///
+/// Implemented for `u32`, `i32`, `u64`, `i64`, `usize`, `isize` and raw
+/// pointers.
+pub trait PerCpuWord: Copy + sealed::Sealed {
+    /// `true` for 8-byte values, `false` for 4-byte values.
+    #[doc(hidden)]
+    const WIDE: bool;
+    #[doc(hidden)]
+    fn to_bits(self) -> u64;
+    #[doc(hidden)]
+    fn from_bits(bits: u64) -> Self;
+}
+
+/// Integer [`PerCpuWord`]s, which also support [`percpu_add_strided`].
+pub trait PerCpuInt: PerCpuWord {}
+
+macro_rules! impl_percpu_int {
+    ($($ty:ty => $wide:literal),+ $(,)?) => {$(
+        impl sealed::Sealed for $ty {}
+        impl PerCpuWord for $ty {
+            const WIDE: bool = $wide;
+            #[inline(always)]
+            fn to_bits(self) -> u64 {
+                self as u64
+            }
+            #[inline(always)]
+            fn from_bits(bits: u64) -> Self {
+                bits as $ty
+            }
+        }
+        impl PerCpuInt for $ty {}
+    )+};
+}
+
+impl_percpu_int!(u32 => false, i32 => false, u64 => true, i64 => true, usize => true, isize => true);
+
+impl<T> sealed::Sealed for *mut T {}
+impl<T> PerCpuWord for *mut T {
+    const WIDE: bool = true;
+    #[inline(always)]
+    fn to_bits(self) -> u64 {
+        self as u64
+    }
+    #[inline(always)]
+    fn from_bits(bits: u64) -> Self {
+        bits as *mut T
+    }
+}
+
+impl<T> sealed::Sealed for *const T {}
+impl<T> PerCpuWord for *const T {
+    const WIDE: bool = true;
+    #[inline(always)]
+    fn to_bits(self) -> u64 {
+        self as u64
+    }
+    #[inline(always)]
+    fn from_bits(bits: u64) -> Self {
+        bits as *const T
+    }
+}
+
+const _: () = assert!(size_of::<usize>() == 8);
+
+/// Load the element for the current CPU: `*(base + cpu_id * stride)`.
+///
+/// `base` points at the field in CPU 0's element and `stride` is the byte
+/// distance between consecutive CPUs' elements, so a cacheline-padded
+/// `[Padded; N]` array is addressed as
+/// `percpu_load_strided(rseq, &raw const arr[0].field, size_of::<Padded>())`.
+///
+/// Returns `Some((cpu, value))` on success, or `None` if rseq aborted
+/// (caller should retry).
+///
+/// # Safety
+///
+/// - `rseq` must be a valid, registered rseq pointer for the current thread.
+/// - For every possible CPU, `base + cpu * stride` must be a valid, aligned
+///   `T`.
+#[inline]
+pub unsafe fn percpu_load_strided<T: PerCpuWord>(
+    rseq: *mut Rseq,
+    base: *const T,
+    stride: usize,
+) -> Option<(u32, T)> {
+    let base = base as *const u8;
+    let res = unsafe {
+        if T::WIDE {
+            load_strided::<true>(rseq, base, stride)
+        } else {
+            load_strided::<false>(rseq, base, stride)
+        }
+    };
+    res.map(|(cpu, bits)| (cpu, T::from_bits(bits)))
+}
+
+/// Store `value` to the current CPU's element.
+///
+/// Returns `Some(cpu)` on success, or `None` if aborted (retry).
+///
+/// # Safety
+///
+/// Same requirements as [`percpu_load_strided`], and the elements must be
+/// writable.
+#[inline]
+pub unsafe fn percpu_store_strided<T: PerCpuWord>(
+    rseq: *mut Rseq,
+    base: *mut T,
+    stride: usize,
+    value: T,
+) -> Option<u32> {
+    let (base, value) = (base as *mut u8, value.to_bits());
+    unsafe {
+        if T::WIDE {
+            store_strided::<true>(rseq, base, stride, value)
+        } else {
+            store_strided::<false>(rseq, base, stride, value)
+        }
+    }
+}
+
+/// Add `delta` to the current CPU's element (wrapping).
+///
+/// Returns `Some(cpu)` on success, or `None` if aborted (retry).
+///
+/// # Safety
+///
+/// Same requirements as [`percpu_store_strided`].
+#[inline]
+pub unsafe fn percpu_add_strided<T: PerCpuInt>(
+    rseq: *mut Rseq,
+    base: *mut T,
+    stride: usize,
+    delta: T,
+) -> Option<u32> {
+    let (base, delta) = (base as *mut u8, delta.to_bits());
+    unsafe {
+        if T::WIDE {
+            add_strided::<true>(rseq, base, stride, delta)
+        } else {
+            add_strided::<false>(rseq, base, stride, delta)
+        }
+    }
+}
+
+/// Compare-and-exchange on the current CPU's element.
+///
+/// Same results as [`percpu_cmpxchg`]: `Ok((cpu, expected))` on success,
+/// `Err(actual)` on mismatch, `Err(expected)` on abort.
+///
+/// # Safety
+///
+/// Same requirements as [`percpu_store_strided`].
+#[inline]
+pub unsafe fn percpu_cmpxchg_strided<T: PerCpuWord>(
+    rseq: *mut Rseq,
+    base: *mut T,
+    stride: usize,
+    expected: T,
+    new: T,
+) -> Result<(u32, T), T> {
+    let base = base as *mut u8;
+    let (expected, new) = (expected.to_bits(), new.to_bits());
+    let res = unsafe {
+        if T::WIDE {
+            cmpxchg_strided::<true>(rseq, base, stride, expected, new)
+        } else {
+            cmpxchg_strided::<false>(rseq, base, stride, expected, new)
+        }
+    };
+    res.map(|(cpu, bits)| (cpu, T::from_bits(bits)))
+        .map_err(T::from_bits)
+}
+
+#[inline(never)]
+unsafe fn load_strided<const WIDE: bool>(
+    rseq: *mut Rseq,
+    base: *const u8,
+    stride: usize,
+) -> Option<(u32, u64)> {
+    let cpu: u64;
+    let value: u64;
+
+    let outcome = unsafe {
+        if WIDE {
+            crate::critical_section!(
+                rseq = rseq,
+                body = [
+                    "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                    "mov {off}, {cpu}",
+                    "imul {off}, {stride}",
+                    "mov {val}, qword ptr [{base} + {off}]",
+                ],
+                base = in(reg) base,
+                stride = in(reg) stride,
+                cpu = out(reg) cpu,
+                off = out(reg) _,
+                val = out(reg) value,
+                options(nostack),
+            )
+        } else {
+            crate::critical_section!(
+                rseq = rseq,
+                body = [
+                    "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                    "mov {off}, {cpu}",
+                    "imul {off}, {stride}",
+                    "mov {val:e}, dword ptr [{base} + {off}]",
+                ],
+                base = in(reg) base,
+                stride = in(reg) stride,
+                cpu = out(reg) cpu,
+                off = out(reg) _,
+                val = out(reg) value,
+                options(nostack),
+            )
+        }
+    };
+
+    outcome.is_committed().then_some((cpu as u32, value))
+}
+
+#[inline(never)]
+unsafe fn store_strided<const WIDE: bool>(
+    rseq: *mut Rseq,
+    base: *mut u8,
+    stride: usize,
+    value: u64,
+) -> Option<u32> {
+    let cpu: u64;
+
+    let outcome = unsafe {
+        if WIDE {
+            crate::critical_section!(
+                rseq = rseq,
+                body = [
+                    "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                    "mov {off}, {cpu}",
+                    "imul {off}, {stride}",
+                    // Commit
+                    "mov qword ptr [{base} + {off}], {val}",
+                ],
+                base = in(reg) base,
+                stride = in(reg) stride,
+                val = in(reg) value,
+                cpu = out(reg) cpu,
+                off = out(reg) _,
+                options(nostack),
+            )
+        } else {
+            crate::critical_section!(
+                rseq = rseq,
+                body = [
+                    "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                    "mov {off}, {cpu}",
+                    "imul {off}, {stride}",
+                    // Commit
+                    "mov dword ptr [{base} + {off}], {val:e}",
+                ],
+                base = in(reg) base,
+                stride = in(reg) stride,
+                val = in(reg) value,
+                cpu = out(reg) cpu,
+                off = out(reg) _,
+                options(nostack),
+            )
+        }
+    };
+
+    outcome.is_committed().then_some(cpu as u32)
+}
+
+#[inline(never)]
+unsafe fn add_strided<const WIDE: bool>(
+    rseq: *mut Rseq,
+    base: *mut u8,
+    stride: usize,
+    delta: u64,
+) -> Option<u32> {
+    let cpu: u64;
+
+    let outcome = unsafe {
+        if WIDE {
+            crate::critical_section!(
+                rseq = rseq,
+                body = [
+                    "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                    "mov {off}, {cpu}",
+                    "imul {off}, {stride}",
+                    "mov {scratch}, qword ptr [{base} + {off}]",
+                    "add {scratch}, {delta}",
+                    // Commit
+                    "mov qword ptr [{base} + {off}], {scratch}",
+                ],
+                base = in(reg) base,
+                stride = in(reg) stride,
+                delta = in(reg) delta,
+                cpu = out(reg) cpu,
+                off = out(reg) _,
+                scratch = out(reg) _,
+                options(nostack),
+            )
+        } else {
+            crate::critical_section!(
+                rseq = rseq,
+                body = [
+                    "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                    "mov {off}, {cpu}",
+                    "imul {off}, {stride}",
+                    "mov {scratch:e}, dword ptr [{base} + {off}]",
+                    "add {scratch:e}, {delta:e}",
+                    // Commit
+                    "mov dword ptr [{base} + {off}], {scratch:e}",
+                ],
+                base = in(reg) base,
+                stride = in(reg) stride,
+                delta = in(reg) delta,
+                cpu = out(reg) cpu,
+                off = out(reg) _,
+                scratch = out(reg) _,
+                options(nostack),
+            )
+        }
+    };
+
+    outcome.is_committed().then_some(cpu as u32)
+}
+
+#[inline(never)]
+unsafe fn cmpxchg_strided<const WIDE: bool>(
+    rseq: *mut Rseq,
+    base: *mut u8,
+    stride: usize,
+    expected: u64,
+    new: u64,
+) -> Result<(u32, u64), u64> {
+    let cpu: u64;
+    let old_val: u64;
+
+    let outcome = unsafe {
+        if WIDE {
+            crate::critical_section!(
+                rseq = rseq,
+                body = [
+                    "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                    "mov {off}, {cpu}",
+                    "imul {off}, {stride}",
+                    "mov {old}, qword ptr [{base} + {off}]",
+                    "cmp {old}, {exp}",
+                    "jne 7f",
+                    // Commit
+                    "mov qword ptr [{base} + {off}], {new}",
+                ],
+                base = in(reg) base,
+                stride = in(reg) stride,
+                exp = in(reg) expected,
+                new = in(reg) new,
+                cpu = out(reg) cpu,
+                off = out(reg) _,
+                old = out(reg) old_val,
+                options(nostack),
+            )
+        } else {
+            crate::critical_section!(
+                rseq = rseq,
+                body = [
+                    "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                    "mov {off}, {cpu}",
+                    "imul {off}, {stride}",
+                    "mov {old:e}, dword ptr [{base} + {off}]",
+                    "cmp {old:e}, {exp:e}",
+                    "jne 7f",
+                    // Commit
+                    "mov dword ptr [{base} + {off}], {new:e}",
+                ],
+                base = in(reg) base,
+                stride = in(reg) stride,
+                exp = in(reg) expected,
+                new = in(reg) new,
+                cpu = out(reg) cpu,
+                off = out(reg) _,
+                old = out(reg) old_val,
+                options(nostack),
+            )
+        }
+    };
+
+    match outcome {
+        Outcome::Committed => Ok((cpu as u32, old_val)),
+        Outcome::Bailed => Err(old_val),
+        Outcome::Aborted => Err(expected),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Intrusive lists
+// ---------------------------------------------------------------------------
+
+/// Push `node` onto the current CPU's intrusive list.
+///
+/// `heads` points at CPU 0's list head and `stride` is the byte distance
+/// between consecutive CPUs' heads, as for [`percpu_load_strided`]. The
+/// first word of every node holds the plain pointer to the next node (null
+/// at the tail); `node`'s is overwritten.
+///
+/// Returns `Some(cpu)` with the CPU whose list took the node, or `None` if
+/// aborted (retry).
+///
+/// # Safety
+///
+/// - `rseq` must be a valid, registered rseq pointer for the current thread.
+/// - For every possible CPU, `heads + cpu * stride` must be a valid, aligned
+///   `*mut T` heading a list of valid nodes.
+/// - Each list head may only be changed by these functions on its own CPU,
+///   or while no thread can run them on that CPU.
+/// - `node` must be valid for a pointer-sized write and on no list.
+#[inline]
+pub unsafe fn percpu_list_push<T>(
+    rseq: *mut Rseq,
+    heads: *mut *mut T,
+    stride: usize,
+    node: *mut T,
+) -> Option<u32> {
+    unsafe { percpu_list_push_chain(rseq, heads, stride, node, node) }
+}
+
+/// Push the chain `first` → ... → `last` onto the current CPU's intrusive
+/// list in one step, so it keeps its order at the front of the list.
+///
+/// Returns `Some(cpu)` on success, or `None` if aborted (retry).
+///
+/// # Safety
+///
+/// Same requirements as [`percpu_list_push`], and `first` must reach `last`
+/// through the nodes' links; `last`'s link is overwritten.
+#[inline(never)]
+pub unsafe fn percpu_list_push_chain<T>(
+    rseq: *mut Rseq,
+    heads: *mut *mut T,
+    stride: usize,
+    first: *mut T,
+    last: *mut T,
+) -> Option<u32> {
+    let cpu: u64;
+
+    let outcome = unsafe {
+        crate::critical_section!(
+            rseq = rseq,
+            body = [
+                "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                "mov {off}, {cpu}",
+                "imul {off}, {stride}",
+                "mov {head}, qword ptr [{heads} + {off}]",
+                // The chain is not published yet; a rerun rewrites the link.
+                "mov qword ptr [{last}], {head}",
+                // Commit
+                "mov qword ptr [{heads} + {off}], {first}",
+            ],
+            heads = in(reg) heads,
+            stride = in(reg) stride,
+            first = in(reg) first,
+            last = in(reg) last,
+            cpu = out(reg) cpu,
+            off = out(reg) _,
+            head = out(reg) _,
+            options(nostack),
+        )
+    };
+
+    outcome.is_committed().then_some(cpu as u32)
+}
+
+/// Pop the first node of the current CPU's intrusive list: the head and its
+/// link are read and the head replaced inside one critical section, so no
+/// other thread can take the node in between.
+///
+/// Returns `Some((cpu, node))`, with a null `node` if that CPU's list is
+/// empty, or `None` if aborted (retry). The popped node's link is left as
+/// it was.
+///
+/// # Safety
+///
+/// Same requirements as [`percpu_list_push`], without `node`.
+#[inline(never)]
+pub unsafe fn percpu_list_pop<T>(
+    rseq: *mut Rseq,
+    heads: *mut *mut T,
+    stride: usize,
+) -> Option<(u32, *mut T)> {
+    let cpu: u64;
+    let node: *mut T;
+
+    let outcome = unsafe {
+        crate::critical_section!(
+            rseq = rseq,
+            body = [
+                "mov {cpu:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                "mov {off}, {cpu}",
+                "imul {off}, {stride}",
+                "mov {node}, qword ptr [{heads} + {off}]",
+                "test {node}, {node}",
+                "jz 7f",
+                "mov {next}, qword ptr [{node}]",
+                // Commit
+                "mov qword ptr [{heads} + {off}], {next}",
+            ],
+            heads = in(reg) heads,
+            stride = in(reg) stride,
+            cpu = out(reg) cpu,
+            off = out(reg) _,
+            node = out(reg) node,
+            next = out(reg) _,
+            options(nostack),
+        )
+    };
+
+    match outcome {
+        Outcome::Committed | Outcome::Bailed => Some((cpu as u32, node)),
+        Outcome::Aborted => None,
+    }
+}
+
+#[cfg(all(test, feature = "nightly", not(miri)))]
+mod tests {
+    use super::*;
+    use core::ptr;
+    use std::vec::Vec;
+
+    #[repr(C)]
+    struct Node {
+        next: *mut Node,
+        id: usize,
+    }
+
+    /// One list head per cacheline.
+    #[repr(C, align(64))]
+    struct Head(*mut Node);
+
+    fn heads() -> Vec<Head> {
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        (0..cpus).map(|_| Head(ptr::null_mut())).collect()
+    }
+
+    fn nodes(n: usize) -> Vec<Node> {
+        (0..n)
+            .map(|id| Node {
+                next: ptr::null_mut(),
+                id,
+            })
+            .collect()
+    }
+
+    /// Ids on every CPU's list, in list order.
+    fn walk(heads: &[Head]) -> Vec<usize> {
+        let mut ids = Vec::new();
+        for head in heads {
+            let mut node = head.0;
+            while !node.is_null() {
+                ids.push(unsafe { (*node).id });
+                node = unsafe { (*node).next };
+            }
+        }
+        ids
+    }
+
+    fn base(heads: &mut [Head]) -> *mut *mut Node {
+        heads.as_mut_ptr().cast()
+    }
+
+    #[test]
+    fn list_push_chain_and_pop() {
+        let Some(rseq) = (unsafe { crate::current_rseq() }) else {
+            return;
+        };
+        let mut heads = heads();
+        let mut nodes = nodes(16);
+        let stride = size_of::<Head>();
+        let n = nodes.as_mut_ptr();
+
+        // Singles, then a chain of the rest that lands in front in order.
+        for i in 0..8 {
+            while unsafe { percpu_list_push(rseq, base(&mut heads), stride, n.add(i)) }.is_none() {}
+        }
+        for i in 8..15 {
+            unsafe { (*n.add(i)).next = n.add(i + 1) };
+        }
+        let cpu = loop {
+            if let Some(cpu) = unsafe {
+                percpu_list_push_chain(rseq, base(&mut heads), stride, n.add(8), n.add(15))
+            } {
+                break cpu as usize;
+            }
+        };
+        let front: Vec<usize> = walk(&heads[cpu..=cpu]).into_iter().take(8).collect();
+        assert_eq!(front, (8..16).collect::<Vec<_>>());
+
+        // Each pop takes its CPU's head and leaves its link as the new one.
+        let mut popped = Vec::new();
+        for _ in 0..16 {
+            let before: Vec<*mut Node> = heads.iter().map(|h| h.0).collect();
+            let (cpu, node) = loop {
+                if let Some(res) = unsafe { percpu_list_pop(rseq, base(&mut heads), stride) } {
+                    break res;
+                }
+            };
+            let cpu = cpu as usize;
+            assert_eq!(node, before[cpu]);
+            if node.is_null() {
+                continue;
+            }
+            assert_eq!(heads[cpu].0, unsafe { (*node).next });
+            popped.push(unsafe { (*node).id });
+        }
+        popped.extend(walk(&heads));
+        popped.sort();
+        assert_eq!(popped, (0..16).collect::<Vec<_>>());
     }
 
-    if success != 0 {
-        Ok((cpu as u32, old_val))
-    } else {
-        Err(old_val)
+    /// Threads move nodes between their hands and their CPUs' lists; not
+    /// one node may be lost or linked twice.
+    #[test]
+    fn list_races_push_and_pop() {
+        use std::thread;
+
+        const THREADS: usize = 4;
+        const NODES: usize = 64;
+        const ROUNDS: usize = 20_000;
+
+        if unsafe { crate::current_rseq() }.is_none() {
+            return;
+        }
+        let mut heads = heads();
+        let mut nodes = nodes(THREADS * NODES);
+        let stride = size_of::<Head>();
+        let base = base(&mut heads).expose_provenance();
+        let first = nodes.as_mut_ptr().expose_provenance();
+
+        let held: Vec<usize> = thread::scope(|s| {
+            let workers: Vec<_> = (0..THREADS)
+                .map(|t| {
+                    s.spawn(move || {
+                        let rseq = unsafe { crate::current_rseq() }.unwrap();
+                        let base = ptr::with_exposed_provenance_mut::<*mut Node>(base);
+                        let mut held: Vec<*mut Node> = (0..NODES)
+                            .map(|i| {
+                                ptr::with_exposed_provenance_mut::<Node>(first)
+                                    .wrapping_add(t * NODES + i)
+                            })
+                            .collect();
+                        for round in 0..ROUNDS {
+                            if round % 3 != 0
+                                && let Some(&node) = held.last()
+                            {
+                                if unsafe { percpu_list_push(rseq, base, stride, node) }.is_some() {
+                                    held.pop();
+                                }
+                            } else if let Some((_, node)) =
+                                unsafe { percpu_list_pop(rseq, base, stride) }
+                                && !node.is_null()
+                            {
+                                held.push(node);
+                            }
+                        }
+                        held.into_iter()
+                            .map(|n| unsafe { (*n).id })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().unwrap())
+                .collect()
+        });
+
+        let mut ids = walk(&heads);
+        ids.extend(held);
+        ids.sort();
+        assert_eq!(ids, (0..THREADS * NODES).collect::<Vec<_>>());
     }
 }
@@ -5,7 +5,7 @@
 //!
 //! ```text
 //! ┌─────────────────────────────────────────────────────┐
-//! │ Header[0]  (4 bytes: current u16 | end u16)         │
+//! │ Header[0]  (current | end, u16 each or u32 each)    │
 //! │ Header[1]                                           │
 //! │ ...                                                 │
 //! │ Header[NUM_CLASSES-1]                               │
@@ -16,22 +16,58 @@
 //! └─────────────────────────────────────────────────────┘
 //! ```
 //!
+//! `current` and `end` are slot indices from the start of the CPU region,
+//! so the header width bounds the region size. The default 4-byte
+//! [`SlabHeader`] addresses 64K slots (`shift <= 19`); selecting
+//! `WIDE = true` uses the 8-byte [`WideSlabHeader`] and allows regions up
+//! to `2^35` bytes.
+//!
 //! Push and pop are lock-free via rseq critical sections. The only
-//! commit operation is a single 16-bit store to `current`.
+//! commit operation is a single store to `current` (16 or 32 bits).
+//!
+//! # Capacity
+//!
+//! The capacities given to [`PerCpuSlab::init`] are the most each class
+//! can ever hold: they fix where its slot array sits. What a class may
+//! hold right now is its `end`, which starts at that maximum and can be
+//! moved anywhere up to it per CPU with [`StoppedCpu::set_capacity`].
+//!
+//! # Remote access
+//!
+//! Only the thread running on a CPU may push or pop there, so other
+//! threads reach a CPU's region through [`PerCpuSlab::stop_cpu`]: it locks
+//! every header by storing `current = end = 0`, which makes both
+//! critical sections bail, then fences the CPU with
+//! `membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ)` so that no section
+//! that read a header before the lock can still commit. The returned
+//! [`StoppedCpu`] drains, refills, resizes and iterates the region, and
+//! puts the headers back when dropped. Pushes and pops on that CPU fail
+//! meanwhile, as if the class were full or empty.
+//!
+//! ```ignore
+//! let mut cpu = unsafe { slab.stop_cpu(3)? };
+//! for class in 1..NUM_CLASSES {
+//!     cpu.drain(class, |ptr| central.give_back(class, ptr));
+//! }
+//! drop(cpu); // CPU 3 caches again
+//! ```
+//!
+//! # Miri
+//!
+//! Miri cannot run the critical sections or membarrier. Under `cfg(miri)`
+//! every thread counts as running on CPU 0, [`pop`](PerCpuSlab::pop) and
+//! [`push`](PerCpuSlab::push) ignore `rseq` and take a process-wide lock
+//! instead, and [`stop_cpu`](PerCpuSlab::stop_cpu) fences by taking that
+//! lock once. Same results, none of the speed.
 //!
 //! Modelled after Google tcmalloc's `TcmallocSlab` in `percpu_tcmalloc.h`.
 
-use core::arch::asm;
+use core::fmt;
 use core::ptr;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 
 use crate::abi::Rseq;
 
-/// Byte offset of `cpu_id` within `struct Rseq`.
-const RSEQ_CPU_ID_OFF: u32 = 4;
-
-/// Byte offset of `rseq_cs` within `struct Rseq`.
-const RSEQ_CS_OFF: u32 = 8;
-
 /// Per-size-class header within a CPU region.
 ///
 /// Stored as two adjacent `u16` values at `base + class * 4`:
@@ -40,21 +76,73 @@ const RSEQ_CS_OFF: u32 = 8;
 ///   `current == begin` means empty, `current == end` means full.
 /// - offset 2: `end` — one past the last slot (capacity limit).
 ///
-/// The rseq commit is a single 16-bit store to `current`.
+/// The rseq commit is a single 16-bit store to `current`. `end == 0`
+/// marks a header locked by [`PerCpuSlab::stop_cpu`]; `begin` is never 0,
+/// so no unlocked header looks like that.
 #[repr(C)]
 pub struct SlabHeader {
     pub current: u16,
     pub end: u16,
 }
 
+/// Header used by `PerCpuSlab<_, true>`.
+///
+/// Same meaning as [`SlabHeader`] with `u32` fields at `base + class * 8`.
+/// The rseq commit is a single 32-bit store to `current`.
+#[repr(C)]
+pub struct WideSlabHeader {
+    pub current: u32,
+    pub end: u32,
+}
+
+/// Why [`PerCpuSlab::init`] rejected a layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlabInitError {
+    /// `shift` exceeds [`PerCpuSlab::MAX_SHIFT`] for the header width.
+    ShiftTooLarge { shift: u32, max: u32 },
+    /// The slots of `class` end past the last index the header can store.
+    SlotIndexOverflow {
+        class: usize,
+        end: usize,
+        max: usize,
+    },
+    /// Headers and slot arrays need more than `2^shift` bytes per CPU.
+    RegionTooSmall { required: usize, available: usize },
+}
+
+impl fmt::Display for SlabInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::ShiftTooLarge { shift, max } => {
+                write!(f, "shift {shift} exceeds the header limit of {max}")
+            }
+            Self::SlotIndexOverflow { class, end, max } => write!(
+                f,
+                "class {class} ends at slot {end}, past the header limit of {max}; \
+                 use the wide header"
+            ),
+            Self::RegionTooSmall {
+                required,
+                available,
+            } => write!(
+                f,
+                "per-CPU layout needs {required} bytes but the region has {available}"
+            ),
+        }
+    }
+}
+
 /// Per-CPU slab allocator with LIFO stacks per size class.
 ///
 /// `NUM_CLASSES` is the total number of size classes (including class 0
 /// which is unused). Must match the allocator's size class table.
 ///
+/// `WIDE` selects the header width: `false` (default) for the compact
+/// 4-byte [`SlabHeader`], `true` for the 8-byte [`WideSlabHeader`].
+///
 /// The slab does **not** own the backing memory — the caller is
 /// responsible for allocating (e.g., via `mmap`) and freeing it.
-pub struct PerCpuSlab<const NUM_CLASSES: usize> {
+pub struct PerCpuSlab<const NUM_CLASSES: usize, const WIDE: bool = false> {
     /// Base pointer to the mmap'd region.
     slabs: *mut u8,
     /// Log2 of per-CPU region size in bytes.
@@ -63,36 +151,161 @@ pub struct PerCpuSlab<const NUM_CLASSES: usize> {
     num_cpus: u32,
     /// Per-size-class begin offsets in pointer-sized units (8 bytes).
     /// Shared layout across all CPUs.
-    begins: [u16; NUM_CLASSES],
+    begins: [u32; NUM_CLASSES],
+    /// Per-size-class slot array lengths: the most `end` can grow to.
+    limits: [u32; NUM_CLASSES],
 }
 
 // Safety: the slab is a shared data structure accessed by multiple threads,
-// each touching only their current CPU's region (enforced by rseq).
-unsafe impl<const N: usize> Sync for PerCpuSlab<N> {}
-unsafe impl<const N: usize> Send for PerCpuSlab<N> {}
+// each touching only their current CPU's region (enforced by rseq), or a
+// region stopped for them by `stop_cpu`.
+unsafe impl<const N: usize, const WIDE: bool> Sync for PerCpuSlab<N, WIDE> {}
+unsafe impl<const N: usize, const WIDE: bool> Send for PerCpuSlab<N, WIDE> {}
+
+/// The rseq pop sequence. `load` leaves `current` in `{cur}` and `end` in
+/// `{end_}` (both zero-extended); `store` commits `{cur}` back.
+///
+/// `current > end` only holds for a header locked by `stop_cpu` that a
+/// racing push or pop committed to, so it bails like an empty class.
+macro_rules! slab_pop {
+    ($rseq:expr, $slabs:expr, $shift:expr, $class_off:expr, $begin:expr,
+     $result:ident, load = [$($load:literal),+], store = $store:literal) => {
+        crate::critical_section!(
+            rseq = $rseq,
+            body = [
+                // Read cpu_id, compute region base = slabs + (cpu << shift)
+                "mov {base:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                "shl {base}, cl",
+                "add {base}, {slabs}",
+                // Load current and end from header
+                $($load,)+
+                // Empty or locked check: current <= begin
+                "cmp {cur}, {begin}",
+                "jbe 7f",
+                // Locked after a racing commit: current > end
+                "cmp {cur:e}, {end_:e}",
+                "ja 7f",
+                // new_current = current - 1
+                "dec {cur:e}",
+                // Load pointer from slot[new_current]
+                "mov {result}, qword ptr [{base} + {cur} * 8]",
+                // COMMIT: store new current
+                $store,
+            ],
+            slabs = in(reg) $slabs,
+            class_off = in(reg) $class_off,
+            begin = in(reg) $begin,
+            base = out(reg) _,
+            cur = out(reg) _,
+            end_ = out(reg) _,
+            result = out(reg) $result,
+            in("rcx") $shift as u64,
+            options(nostack),
+        )
+    };
+}
+
+/// The rseq push sequence. `load` leaves `current` in `{hdr}` and `end`
+/// in `{end_}` (both zero-extended); `store` commits `{hdr}`.
+///
+/// A locked header has `end == 0`, so the full check rejects it.
+macro_rules! slab_push {
+    ($rseq:expr, $slabs:expr, $shift:expr, $class_off:expr, $ptr:expr,
+     load = [$($load:literal),+], store = $store:literal) => {
+        crate::critical_section!(
+            rseq = $rseq,
+            body = [
+                // Read cpu_id, compute region base
+                "mov {base:e}, dword ptr [{rseq} + {cpu_id_off}]",
+                "shl {base}, cl",
+                "add {base}, {slabs}",
+                // Load current and end from header
+                $($load,)+
+                // Full or locked check: current >= end
+                "cmp {hdr:e}, {end_:e}",
+                "jae 7f",
+                // Store pointer at slot[current]
+                "mov qword ptr [{base} + {hdr} * 8], {ptr}",
+                // COMMIT: store current + 1
+                "inc {hdr:e}",
+                $store,
+            ],
+            slabs = in(reg) $slabs,
+            class_off = in(reg) $class_off,
+            ptr = in(reg) $ptr,
+            base = out(reg) _,
+            hdr = out(reg) _,
+            end_ = out(reg) _,
+            in("rcx") $shift as u64,
+            options(nostack),
+        )
+    };
+}
+
+impl<const NUM_CLASSES: usize, const WIDE: bool> PerCpuSlab<NUM_CLASSES, WIDE> {
+    /// Size of one per-class header in bytes.
+    pub const HEADER_SIZE: usize = if WIDE {
+        size_of::<WideSlabHeader>()
+    } else {
+        size_of::<SlabHeader>()
+    };
+
+    /// Largest slot index a header can store.
+    pub const MAX_SLOT_INDEX: usize = if WIDE {
+        u32::MAX as usize
+    } else {
+        u16::MAX as usize
+    };
+
+    /// Largest usable `shift`: a bigger region would hold slots the
+    /// header cannot index.
+    pub const MAX_SHIFT: u32 = if WIDE { 35 } else { 19 };
 
-impl<const NUM_CLASSES: usize> PerCpuSlab<NUM_CLASSES> {
     /// Create an uninitialized slab. Must call [`init`] before use.
     pub const fn empty() -> Self {
         Self {
             slabs: ptr::null_mut(),
             shift: 0,
             num_cpus: 0,
-            begins: [0u16; NUM_CLASSES],
+            begins: [0u32; NUM_CLASSES],
+            limits: [0u32; NUM_CLASSES],
         }
     }
 
+    /// Bytes of one CPU region used by headers and slot arrays for the
+    /// given capacities. Usable in const context to check a layout at
+    /// compile time.
+    pub const fn layout_bytes(capacities: &[u32; NUM_CLASSES]) -> usize {
+        let mut slots = Self::data_start() / 8;
+        let mut class = 1;
+        while class < NUM_CLASSES {
+            slots += capacities[class] as usize;
+            class += 1;
+        }
+        slots * 8
+    }
+
+    /// Byte offset of the first slot array: headers, aligned to 8 bytes.
+    const fn data_start() -> usize {
+        (NUM_CLASSES * Self::HEADER_SIZE + 7) & !7
+    }
+
     /// Initialize the slab over a caller-provided memory region.
     ///
     /// - `region`: base pointer, must be at least `num_cpus << shift` bytes.
     ///   Should be page-aligned (e.g., from `mmap`).
     /// - `num_cpus`: number of CPUs to provision.
     /// - `shift`: log2 of per-CPU region size. Each CPU gets `2^shift` bytes.
-    ///   Typical values: 12 (4 KiB) to 18 (256 KiB).
+    ///   Typical values: 12 (4 KiB) to 18 (256 KiB); at most
+    ///   [`MAX_SHIFT`](Self::MAX_SHIFT).
     /// - `capacities`: max number of cached pointers per size class.
-    ///   `capacities[0]` is ignored (class 0 is unused).
+    ///   `capacities[0]` is ignored (class 0 is unused). Every class
+    ///   starts empty at this capacity; [`StoppedCpu::set_capacity`] can
+    ///   lower it and raise it back, never past it.
     ///
-    /// Returns `false` if the per-CPU layout exceeds `2^shift` bytes.
+    /// Fails without touching `region` if `shift` is too large, a class's
+    /// slots cannot be indexed by the header, or the per-CPU layout
+    /// exceeds `2^shift` bytes.
     ///
     /// # Safety
     ///
@@ -105,42 +318,58 @@ impl<const NUM_CLASSES: usize> PerCpuSlab<NUM_CLASSES> {
         region: *mut u8,
         num_cpus: u32,
         shift: u32,
-        capacities: &[u16; NUM_CLASSES],
-    ) -> bool {
-        // Compute begin offsets.
-        // Headers occupy the first NUM_CLASSES * 4 bytes, then align to 8.
-        let header_bytes = NUM_CLASSES * 4;
-        let data_start = (header_bytes + 7) & !7; // align to 8 bytes
-        let mut offset = data_start / 8; // convert to pointer-sized units
-
-        self.begins[0] = 0;
+        capacities: &[u32; NUM_CLASSES],
+    ) -> Result<(), SlabInitError> {
+        if shift > Self::MAX_SHIFT {
+            return Err(SlabInitError::ShiftTooLarge {
+                shift,
+                max: Self::MAX_SHIFT,
+            });
+        }
+
+        // Compute begin offsets in pointer-sized units, after the headers.
+        let mut begins = [0u32; NUM_CLASSES];
+        let mut offset = Self::data_start() / 8;
         for class in 1..NUM_CLASSES {
-            self.begins[class] = offset as u16;
+            begins[class] = offset as u32;
             offset += capacities[class] as usize;
+            if offset > Self::MAX_SLOT_INDEX {
+                return Err(SlabInitError::SlotIndexOverflow {
+                    class,
+                    end: offset,
+                    max: Self::MAX_SLOT_INDEX,
+                });
+            }
         }
 
         // Check that the per-CPU layout fits.
         let per_cpu_bytes = offset * 8;
         if per_cpu_bytes > (1usize << shift) {
-            return false;
+            return Err(SlabInitError::RegionTooSmall {
+                required: per_cpu_bytes,
+                available: 1usize << shift,
+            });
         }
 
+        let mut limits = *capacities;
+        limits[0] = 0;
+
+        self.begins = begins;
+        self.limits = limits;
+        self.slabs = region;
+        self.shift = shift;
+        self.num_cpus = num_cpus;
+
         // Write initial headers for each CPU: all classes empty.
         unsafe {
             for cpu in 0..num_cpus {
-                let base = region.add((cpu as usize) << shift);
                 for class in 0..NUM_CLASSES {
-                    let hdr = base.add(class * 4) as *mut SlabHeader;
-                    (*hdr).current = self.begins[class];
-                    (*hdr).end = self.begins[class] + capacities[class];
+                    let begin = begins[class];
+                    self.write_header(cpu, class, begin, begin + limits[class]);
                 }
             }
         }
-
-        self.slabs = region;
-        self.shift = shift;
-        self.num_cpus = num_cpus;
-        true
+        Ok(())
     }
 
     /// Whether the slab has been initialized.
@@ -151,7 +380,7 @@ impl<const NUM_CLASSES: usize> PerCpuSlab<NUM_CLASSES> {
 
     /// Begin offset for a size class (in pointer-sized units).
     #[inline(always)]
-    pub fn begin(&self, class: usize) -> u16 {
+    pub fn begin(&self, class: usize) -> u32 {
         self.begins[class]
     }
 
@@ -167,24 +396,116 @@ impl<const NUM_CLASSES: usize> PerCpuSlab<NUM_CLASSES> {
         self.shift
     }
 
-    /// Number of cached objects for `class` on `cpu`.
-    pub fn length(&self, cpu: u32, class: usize) -> u16 {
+    /// Number of CPUs the slab was initialized for.
+    #[inline(always)]
+    pub fn num_cpus(&self) -> u32 {
+        self.num_cpus
+    }
+
+    /// Number of cached objects for `class` on `cpu`, 0 while the CPU is
+    /// stopped.
+    pub fn length(&self, cpu: u32, class: usize) -> u32 {
+        let (current, end) = unsafe { self.read_header(cpu, class) };
+        if end == 0 {
+            return 0;
+        }
+        current - self.begins[class]
+    }
+
+    /// Capacity (max objects) for `class`, 0 while the CPU is stopped.
+    pub fn capacity(&self, cpu: u32, class: usize) -> u32 {
+        let (_, end) = unsafe { self.read_header(cpu, class) };
+        end.saturating_sub(self.begins[class])
+    }
+
+    /// The most `class` can hold on any CPU: its capacity at
+    /// [`init`](Self::init).
+    #[inline(always)]
+    pub fn max_capacity(&self, class: usize) -> u32 {
+        self.limits[class]
+    }
+
+    /// Base of `cpu`'s region.
+    #[inline(always)]
+    unsafe fn cpu_base(&self, cpu: u32) -> *mut u8 {
+        unsafe { self.slabs.add((cpu as usize) << self.shift) }
+    }
+
+    /// `class`'s header on `cpu` as one word: `current` in the low half,
+    /// `end` in the high half. Every access from Rust goes through this so
+    /// that it never tears against [`stop_cpu`](Self::stop_cpu)'s CAS.
+    #[inline(always)]
+    unsafe fn header_ptr(&self, cpu: u32, class: usize) -> *mut u8 {
+        unsafe { self.cpu_base(cpu).add(class * Self::HEADER_SIZE) }
+    }
+
+    /// `(current, end)` for `class` on `cpu`.
+    #[inline(always)]
+    unsafe fn read_header(&self, cpu: u32, class: usize) -> (u32, u32) {
+        unsafe {
+            let hdr = self.header_ptr(cpu, class);
+            if WIDE {
+                let word = AtomicU64::from_ptr(hdr.cast()).load(Ordering::Relaxed);
+                (word as u32, (word >> 32) as u32)
+            } else {
+                let word = AtomicU32::from_ptr(hdr.cast()).load(Ordering::Relaxed);
+                (word & 0xffff, word >> 16)
+            }
+        }
+    }
+
+    /// Store both header fields for `class` on `cpu`. `init` has checked
+    /// that they fit the header width.
+    #[inline(always)]
+    unsafe fn write_header(&self, cpu: u32, class: usize, current: u32, end: u32) {
         unsafe {
-            let base = self.slabs.add((cpu as usize) << self.shift);
-            let hdr = &*(base.add(class * 4) as *const SlabHeader);
-            hdr.current - self.begins[class]
+            let hdr = self.header_ptr(cpu, class);
+            if WIDE {
+                let word = current as u64 | (end as u64) << 32;
+                AtomicU64::from_ptr(hdr.cast()).store(word, Ordering::Release);
+            } else {
+                let word = current | end << 16;
+                AtomicU32::from_ptr(hdr.cast()).store(word, Ordering::Release);
+            }
         }
     }
 
-    /// Capacity (max objects) for `class`.
-    pub fn capacity(&self, cpu: u32, class: usize) -> u16 {
+    /// Replace `old` with `new` as `class`'s header on `cpu`, if it still
+    /// holds `old`.
+    #[inline(always)]
+    unsafe fn cas_header(&self, cpu: u32, class: usize, old: (u32, u32), new: (u32, u32)) -> bool {
         unsafe {
-            let base = self.slabs.add((cpu as usize) << self.shift);
-            let hdr = &*(base.add(class * 4) as *const SlabHeader);
-            hdr.end - self.begins[class]
+            let hdr = self.header_ptr(cpu, class);
+            if WIDE {
+                let pack = |(current, end): (u32, u32)| current as u64 | (end as u64) << 32;
+                AtomicU64::from_ptr(hdr.cast())
+                    .compare_exchange(pack(old), pack(new), Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+            } else {
+                let pack = |(current, end): (u32, u32)| current | end << 16;
+                AtomicU32::from_ptr(hdr.cast())
+                    .compare_exchange(pack(old), pack(new), Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+            }
         }
     }
 
+    /// Store `current` for `class` on `cpu`, keeping `end`. Only for
+    /// callers with exclusive access to the region.
+    #[inline(always)]
+    unsafe fn write_current(&self, cpu: u32, class: usize, current: u32) {
+        unsafe {
+            let (_, end) = self.read_header(cpu, class);
+            self.write_header(cpu, class, current, end);
+        }
+    }
+
+    /// Slot `index` (from the start of the region) of `cpu`.
+    #[inline(always)]
+    unsafe fn slot(&self, cpu: u32, index: u32) -> *mut *mut u8 {
+        unsafe { self.cpu_base(cpu).add(index as usize * 8).cast() }
+    }
+
     /// Pop a pointer from `class` on the current CPU.
     ///
     /// Returns `Some(ptr)` on success, `None` if the class is empty or
@@ -196,91 +517,52 @@ impl<const NUM_CLASSES: usize> PerCpuSlab<NUM_CLASSES> {
     /// - `class` must be `< NUM_CLASSES` and have been initialized.
     #[inline(always)]
     pub unsafe fn pop(&self, rseq: *mut Rseq, class: usize) -> Option<*mut u8> {
-        let class_off = (class * 4) as u64;
+        if cfg!(miri) {
+            return unsafe { self.serialized_pop(class) };
+        }
+
+        let class_off = (class * Self::HEADER_SIZE) as u64;
         let begin = self.begins[class] as u64;
         let slabs = self.slabs as u64;
         let shift = self.shift;
 
         let result: u64;
-        let success: u64;
-
-        unsafe {
-            asm!(
-                // rseq_cs descriptor in a relocatable data section.
-                ".pushsection __rseq_cs, \"aw\"",
-                ".balign 32",
-                "77:",
-                ".long 0",                     // version
-                ".long 0",                     // flags
-                ".quad 3f",                    // start_ip
-                ".quad (4f - 3f)",             // post_commit_offset
-                ".quad 6f",                    // abort_ip
-                ".popsection",
-
-                "lea {tmp}, [rip + 77b]",
-                "mov qword ptr [{rseq} + {rseq_cs_off}], {tmp}",
-
-                "3:",
-
-                // Read cpu_id, compute region base = slabs + (cpu << shift)
-                "mov {base:e}, dword ptr [{rseq} + {cpu_id_off}]",
-                "shl {base}, cl",
-                "add {base}, {slabs}",
-
-                // Load current (16-bit) from header
-                "movzx {cur:e}, word ptr [{base} + {class_off}]",
-
-                // Empty check: current == begin
-                "cmp {cur}, {begin}",
-                "je 7f",
-
-                // new_current = current - 1
-                "dec {cur:e}",
 
-                // Load pointer from slot[new_current]
-                "mov {result}, qword ptr [{base} + {cur} * 8]",
+        let outcome = unsafe {
+            if WIDE {
+                slab_pop!(
+                    rseq,
+                    slabs,
+                    shift,
+                    class_off,
+                    begin,
+                    result,
+                    load = [
+                        "mov {cur:e}, dword ptr [{base} + {class_off}]",
+                        "mov {end_:e}, dword ptr [{base} + {class_off} + 4]"
+                    ],
+                    store = "mov dword ptr [{base} + {class_off}], {cur:e}"
+                )
+            } else {
+                slab_pop!(
+                    rseq,
+                    slabs,
+                    shift,
+                    class_off,
+                    begin,
+                    result,
+                    load = [
+                        "mov {cur:e}, dword ptr [{base} + {class_off}]",
+                        "mov {end_:e}, {cur:e}",
+                        "shr {end_:e}, 16",
+                        "movzx {cur:e}, {cur:x}"
+                    ],
+                    store = "mov word ptr [{base} + {class_off}], {cur:x}"
+                )
+            }
+        };
 
-                // COMMIT: store new current (16-bit write)
-                "mov word ptr [{base} + {class_off}], {cur:x}",
-                "4:",
-
-                "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-                "mov {succ}, 1",
-                "jmp 5f",
-
-                "7:",
-                "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-                "xor {succ:e}, {succ:e}",
-                "jmp 5f",
-
-                ".long 0x53053053",
-                "6:",
-                "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-                "xor {succ:e}, {succ:e}",
-
-                "5:",
-
-                rseq = in(reg) rseq,
-                slabs = in(reg) slabs,
-                in("rcx") shift as u64,
-                class_off = in(reg) class_off,
-                begin = in(reg) begin,
-                base = out(reg) _,
-                cur = out(reg) _,
-                result = out(reg) result,
-                succ = out(reg) success,
-                tmp = out(reg) _,
-                rseq_cs_off = const RSEQ_CS_OFF,
-                cpu_id_off = const RSEQ_CPU_ID_OFF,
-                options(nostack),
-            );
-        }
-
-        if success != 0 {
-            Some(result as *mut u8)
-        } else {
-            None
-        }
+        outcome.is_committed().then_some(result as *mut u8)
     }
 
     /// Push a pointer to `class` on the current CPU.
@@ -295,90 +577,81 @@ impl<const NUM_CLASSES: usize> PerCpuSlab<NUM_CLASSES> {
     /// - `ptr` must be a valid pointer that was previously allocated.
     #[inline(always)]
     pub unsafe fn push(&self, rseq: *mut Rseq, class: usize, ptr: *mut u8) -> Option<()> {
-        let class_off = (class * 4) as u64;
+        if cfg!(miri) {
+            return unsafe { self.serialized_push(class, ptr) };
+        }
+
+        let class_off = (class * Self::HEADER_SIZE) as u64;
         let slabs = self.slabs as u64;
         let shift = self.shift;
 
-        let success: u64;
-
-        unsafe {
-            asm!(
-                // rseq_cs descriptor in a relocatable data section.
-                ".pushsection __rseq_cs, \"aw\"",
-                ".balign 32",
-                "77:",
-                ".long 0",
-                ".long 0",
-                ".quad 3f",
-                ".quad (4f - 3f)",
-                ".quad 6f",
-                ".popsection",
-
-                "lea {tmp}, [rip + 77b]",
-                "mov qword ptr [{rseq} + {rseq_cs_off}], {tmp}",
-
-                "3:",
-
-                // Read cpu_id, compute region base
-                "mov {base:e}, dword ptr [{rseq} + {cpu_id_off}]",
-                "shl {base}, cl",
-                "add {base}, {slabs}",
-
-                // Load full header (current | end << 16)
-                "mov {hdr:e}, dword ptr [{base} + {class_off}]",
-
-                // Extract end (high 16 bits) into tmp
-                "mov {end_:e}, {hdr:e}",
-                "shr {end_:e}, 16",
-
-                // Extract current (low 16 bits)
-                "movzx {hdr:e}, {hdr:x}",
-
-                // Full check: current == end
-                "cmp {hdr:e}, {end_:e}",
-                "je 7f",
-
-                // Store pointer at slot[current]
-                "mov qword ptr [{base} + {hdr} * 8], {ptr}",
-
-                // COMMIT: store current + 1 (16-bit write)
-                "inc {hdr:e}",
-                "mov word ptr [{base} + {class_off}], {hdr:x}",
-                "4:",
-
-                "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-                "mov {succ}, 1",
-                "jmp 5f",
-
-                "7:",
-                "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-                "xor {succ:e}, {succ:e}",
-                "jmp 5f",
-
-                ".long 0x53053053",
-                "6:",
-                "mov qword ptr [{rseq} + {rseq_cs_off}], 0",
-                "xor {succ:e}, {succ:e}",
+        let outcome = unsafe {
+            if WIDE {
+                slab_push!(
+                    rseq,
+                    slabs,
+                    shift,
+                    class_off,
+                    ptr,
+                    load = [
+                        "mov {hdr:e}, dword ptr [{base} + {class_off}]",
+                        "mov {end_:e}, dword ptr [{base} + {class_off} + 4]"
+                    ],
+                    store = "mov dword ptr [{base} + {class_off}], {hdr:e}"
+                )
+            } else {
+                slab_push!(
+                    rseq,
+                    slabs,
+                    shift,
+                    class_off,
+                    ptr,
+                    load = [
+                        // Load full header (current | end << 16)
+                        "mov {hdr:e}, dword ptr [{base} + {class_off}]",
+                        // Extract end (high 16 bits)
+                        "mov {end_:e}, {hdr:e}",
+                        "shr {end_:e}, 16",
+                        // Extract current (low 16 bits)
+                        "movzx {hdr:e}, {hdr:x}"
+                    ],
+                    store = "mov word ptr [{base} + {class_off}], {hdr:x}"
+                )
+            }
+        };
 
-                "5:",
+        outcome.is_committed().then_some(())
+    }
 
-                rseq = in(reg) rseq,
-                slabs = in(reg) slabs,
-                in("rcx") shift as u64,
-                class_off = in(reg) class_off,
-                ptr = in(reg) ptr,
-                base = out(reg) _,
-                hdr = out(reg) _,
-                end_ = out(reg) _,
-                succ = out(reg) success,
-                tmp = out(reg) _,
-                rseq_cs_off = const RSEQ_CS_OFF,
-                cpu_id_off = const RSEQ_CPU_ID_OFF,
-                options(nostack),
-            );
+    /// [`pop`](Self::pop) under Miri: CPU 0, under the serial lock. The
+    /// CAS fails, like a bail, if `stop_cpu` locked the header meanwhile.
+    #[cold]
+    unsafe fn serialized_pop(&self, class: usize) -> Option<*mut u8> {
+        let _serial = serial::lock();
+        unsafe {
+            let (current, end) = self.read_header(0, class);
+            if current <= self.begins[class] || current > end {
+                return None;
+            }
+            let ptr = self.slot(0, current - 1).read();
+            self.cas_header(0, class, (current, end), (current - 1, end))
+                .then_some(ptr)
         }
+    }
 
-        if success != 0 { Some(()) } else { None }
+    /// [`push`](Self::push) under Miri.
+    #[cold]
+    unsafe fn serialized_push(&self, class: usize, ptr: *mut u8) -> Option<()> {
+        let _serial = serial::lock();
+        unsafe {
+            let (current, end) = self.read_header(0, class);
+            if current >= end {
+                return None;
+            }
+            self.slot(0, current).write(ptr);
+            self.cas_header(0, class, (current, end), (current + 1, end))
+                .then_some(())
+        }
     }
 
     /// Pop up to `count` pointers from `class` on a specific `cpu`.
@@ -397,18 +670,19 @@ impl<const NUM_CLASSES: usize> PerCpuSlab<NUM_CLASSES> {
         count: usize,
     ) -> usize {
         unsafe {
-            let base = self.slabs.add((cpu as usize) << self.shift);
-            let hdr = &mut *(base.add(class * 4) as *mut SlabHeader);
+            let base = self.cpu_base(cpu);
+            let (mut current, _) = self.read_header(cpu, class);
             let begin = self.begins[class];
 
-            let avail = (hdr.current - begin) as usize;
+            let avail = (current - begin) as usize;
             let n = count.min(avail);
 
             for i in 0..n {
-                hdr.current -= 1;
-                let slot = base.add(hdr.current as usize * 8) as *const *mut u8;
+                current -= 1;
+                let slot = base.add(current as usize * 8) as *const *mut u8;
                 out.add(i).write(slot.read());
             }
+            self.write_current(cpu, class, current);
 
             n
         }
@@ -429,19 +703,413 @@ impl<const NUM_CLASSES: usize> PerCpuSlab<NUM_CLASSES> {
         count: usize,
     ) -> usize {
         unsafe {
-            let base = self.slabs.add((cpu as usize) << self.shift);
-            let hdr = &mut *(base.add(class * 4) as *mut SlabHeader);
+            let base = self.cpu_base(cpu);
+            let (mut current, end) = self.read_header(cpu, class);
 
-            let room = (hdr.end - hdr.current) as usize;
+            let room = (end - current) as usize;
             let n = count.min(room);
 
             for i in 0..n {
-                let slot = base.add(hdr.current as usize * 8) as *mut *mut u8;
+                let slot = base.add(current as usize * 8) as *mut *mut u8;
                 slot.write(ptrs.add(i).read());
-                hdr.current += 1;
+                current += 1;
             }
+            self.write_current(cpu, class, current);
 
             n
         }
     }
 }
+
+impl<const NUM_CLASSES: usize, const WIDE: bool> PerCpuSlab<NUM_CLASSES, WIDE> {
+    /// Take exclusive access to `cpu`'s region from any thread.
+    ///
+    /// Locks the header of every class, then fences `cpu` so that no push
+    /// or pop that read a header before the lock can still commit. Until
+    /// the returned [`StoppedCpu`] is dropped, pushes and pops on `cpu`
+    /// fail as if the class were full or empty, and another `stop_cpu` of
+    /// the same CPU waits.
+    ///
+    /// Fails with the negative errno of membarrier if the kernel cannot
+    /// fence (before 5.10, or without `CONFIG_RSEQ`), or `EINVAL` if `cpu`
+    /// is not below [`num_cpus`](Self::num_cpus). Nothing is left locked.
+    ///
+    /// # Safety
+    ///
+    /// The slab must be initialized, and everything that pushes to or pops
+    /// from it outside the critical sections, such as
+    /// [`pop_batch`](Self::pop_batch), must not run on `cpu` meanwhile.
+    pub unsafe fn stop_cpu(&self, cpu: u32) -> Result<StoppedCpu<'_, NUM_CLASSES, WIDE>, i32> {
+        if cpu >= self.num_cpus {
+            return Err(crate::syscall::EINVAL);
+        }
+        if !cfg!(miri) {
+            crate::syscall::rseq_fence_register()?;
+        }
+
+        let mut headers = [(0, 0); NUM_CLASSES];
+        for (class, header) in headers.iter_mut().enumerate().skip(1) {
+            *header = unsafe { self.lock_header(cpu, class) };
+        }
+
+        let fenced = if cfg!(miri) {
+            // Any serialized push or pop that saw a header unlocked is done
+            // once the lock is free.
+            drop(serial::lock());
+            Ok(())
+        } else {
+            crate::syscall::rseq_fence(cpu)
+        };
+
+        // A push or pop that read its header before the lock may have
+        // committed after it: then `current` is no longer 0, and is the
+        // real one.
+        for (class, header) in headers.iter_mut().enumerate().skip(1) {
+            let (current, _) = unsafe { self.read_header(cpu, class) };
+            if current != 0 {
+                header.0 = current;
+            }
+        }
+
+        let stopped = StoppedCpu {
+            slab: self,
+            cpu,
+            headers,
+        };
+        fenced.map(|()| stopped)
+    }
+
+    /// Lock `class`'s header on `cpu` and return what it held. Waits while
+    /// another `stop_cpu` holds it; classes are always locked in order, so
+    /// two of them cannot deadlock.
+    unsafe fn lock_header(&self, cpu: u32, class: usize) -> (u32, u32) {
+        loop {
+            let header = unsafe { self.read_header(cpu, class) };
+            if header.1 == 0 {
+                core::hint::spin_loop();
+            } else if unsafe { self.cas_header(cpu, class, header, (0, 0)) } {
+                return header;
+            }
+        }
+    }
+}
+
+/// Exclusive access to one CPU's region of a [`PerCpuSlab`], from
+/// [`PerCpuSlab::stop_cpu`]. Dropping it unlocks the CPU with the lengths
+/// and capacities it was left with.
+pub struct StoppedCpu<'a, const NUM_CLASSES: usize, const WIDE: bool = false> {
+    slab: &'a PerCpuSlab<NUM_CLASSES, WIDE>,
+    cpu: u32,
+    /// Real `(current, end)` of each class while its header reads locked.
+    headers: [(u32, u32); NUM_CLASSES],
+}
+
+impl<const NUM_CLASSES: usize, const WIDE: bool> StoppedCpu<'_, NUM_CLASSES, WIDE> {
+    /// The stopped CPU.
+    pub fn cpu(&self) -> u32 {
+        self.cpu
+    }
+
+    /// Number of cached objects for `class`.
+    pub fn length(&self, class: usize) -> u32 {
+        self.headers[class].0 - self.slab.begins[class]
+    }
+
+    /// Capacity for `class`.
+    pub fn capacity(&self, class: usize) -> u32 {
+        self.headers[class].1 - self.slab.begins[class]
+    }
+
+    /// The objects cached for `class`, oldest first.
+    pub fn slots(&self, class: usize) -> &[*mut u8] {
+        let begin = self.slab.begins[class];
+        unsafe {
+            core::slice::from_raw_parts(
+                self.slab.slot(self.cpu, begin),
+                self.length(class) as usize,
+            )
+        }
+    }
+
+    /// Every cached object with its class, class by class, oldest first.
+    pub fn occupied(&self) -> impl Iterator<Item = (usize, *mut u8)> + '_ {
+        (1..NUM_CLASSES).flat_map(move |class| self.slots(class).iter().map(move |&p| (class, p)))
+    }
+
+    /// Pop the newest object of `class`, or `None` if it is empty.
+    pub fn pop(&mut self, class: usize) -> Option<*mut u8> {
+        let current = &mut self.headers[class].0;
+        if *current == self.slab.begins[class] {
+            return None;
+        }
+        *current -= 1;
+        Some(unsafe { self.slab.slot(self.cpu, *current).read() })
+    }
+
+    /// Push `ptr` to `class`. Returns false if it is full.
+    pub fn push(&mut self, class: usize, ptr: *mut u8) -> bool {
+        let (current, end) = &mut self.headers[class];
+        if *current == *end {
+            return false;
+        }
+        unsafe { *self.slab.slot(self.cpu, *current) = ptr };
+        *current += 1;
+        true
+    }
+
+    /// Pop every object of `class`, newest first, passing each to `f`.
+    /// Returns how many there were.
+    pub fn drain(&mut self, class: usize, mut f: impl FnMut(*mut u8)) -> u32 {
+        let n = self.length(class);
+        while let Some(ptr) = self.pop(class) {
+            f(ptr);
+        }
+        n
+    }
+
+    /// Set the capacity of `class` to `capacity`, clamped to its
+    /// [`max_capacity`](PerCpuSlab::max_capacity). Objects past the new
+    /// capacity are popped, newest first, and passed to `f`. Returns the
+    /// capacity set.
+    pub fn set_capacity(&mut self, class: usize, capacity: u32, mut f: impl FnMut(*mut u8)) -> u32 {
+        let capacity = capacity.min(self.slab.limits[class]);
+        while self.length(class) > capacity {
+            f(self.pop(class).unwrap());
+        }
+        self.headers[class].1 = self.slab.begins[class] + capacity;
+        capacity
+    }
+}
+
+impl<const NUM_CLASSES: usize, const WIDE: bool> Drop for StoppedCpu<'_, NUM_CLASSES, WIDE> {
+    fn drop(&mut self) {
+        for (class, &(current, end)) in self.headers.iter().enumerate().skip(1) {
+            unsafe { self.slab.write_header(self.cpu, class, current, end) };
+        }
+    }
+}
+
+/// The process-wide lock that stands in for critical sections under Miri.
+mod serial {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    static LOCKED: AtomicBool = AtomicBool::new(false);
+
+    pub(super) struct Guard;
+
+    pub(super) fn lock() -> Guard {
+        while LOCKED
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        Guard
+    }
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            LOCKED.store(false, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{Layout, alloc_zeroed, dealloc};
+    use std::vec::Vec;
+
+    const CLASSES: usize = 3;
+    const SHIFT: u32 = 10;
+    const CPUS: u32 = 4;
+    const CAPACITIES: [u32; CLASSES] = [0, 8, 16];
+
+    /// Page-aligned backing memory for a slab.
+    struct Region(*mut u8, Layout);
+
+    impl Region {
+        fn new(cpus: u32, shift: u32) -> Self {
+            let layout = Layout::from_size_align((cpus as usize) << shift, 4096).unwrap();
+            Self(unsafe { alloc_zeroed(layout) }, layout)
+        }
+    }
+
+    impl Drop for Region {
+        fn drop(&mut self) {
+            unsafe { dealloc(self.0, self.1) };
+        }
+    }
+
+    fn token(i: usize) -> *mut u8 {
+        ptr::without_provenance_mut(i * 16)
+    }
+
+    fn slab(region: &Region) -> PerCpuSlab<CLASSES> {
+        let mut slab = PerCpuSlab::empty();
+        unsafe { slab.init(region.0, CPUS, SHIFT, &CAPACITIES) }.unwrap();
+        slab
+    }
+
+    /// Whether the kernel can fence CPUs; tests that stop one skip if not.
+    fn can_stop() -> bool {
+        cfg!(miri) || crate::syscall::rseq_fence_register().is_ok()
+    }
+
+    #[test]
+    fn init_rejects_bad_layouts() {
+        let region = Region::new(1, SHIFT);
+        let mut slab = PerCpuSlab::<CLASSES>::empty();
+        assert_eq!(
+            unsafe { slab.init(region.0, 1, 20, &CAPACITIES) },
+            Err(SlabInitError::ShiftTooLarge { shift: 20, max: 19 })
+        );
+        assert!(matches!(
+            unsafe { slab.init(region.0, 1, SHIFT, &[0, 100, 100]) },
+            Err(SlabInitError::RegionTooSmall { .. })
+        ));
+        assert!(!slab.is_initialized());
+    }
+
+    #[test]
+    fn batches_respect_capacity() {
+        let region = Region::new(CPUS, SHIFT);
+        let slab = slab(&region);
+        let ptrs: Vec<_> = (1..=20).map(token).collect();
+        assert_eq!(slab.max_capacity(1), 8);
+        assert_eq!(unsafe { slab.push_batch(2, 1, ptrs.as_ptr(), 20) }, 8);
+        assert_eq!(slab.length(2, 1), 8);
+        assert_eq!(slab.length(1, 1), 0);
+
+        let mut out = [ptr::null_mut(); 20];
+        assert_eq!(unsafe { slab.pop_batch(2, 1, out.as_mut_ptr(), 3) }, 3);
+        assert_eq!(out[..3], [token(8), token(7), token(6)]);
+        assert_eq!(slab.length(2, 1), 5);
+    }
+
+    #[test]
+    fn stopped_cpu_drains_and_resizes() {
+        if !can_stop() {
+            return;
+        }
+        let region = Region::new(CPUS, SHIFT);
+        let slab = slab(&region);
+        let ptrs: Vec<_> = (1..=12).map(token).collect();
+        unsafe {
+            slab.push_batch(1, 1, ptrs.as_ptr(), 6);
+            slab.push_batch(1, 2, ptrs[6..].as_ptr(), 6);
+        }
+
+        let mut cpu = unsafe { slab.stop_cpu(1) }.unwrap();
+        assert_eq!(slab.length(1, 1), 0, "headers read locked");
+        assert_eq!(cpu.slots(1), &ptrs[..6]);
+        assert!(cpu.occupied().map(|(_, p)| p).eq(ptrs.iter().copied()));
+
+        let mut spilled = Vec::new();
+        assert_eq!(cpu.set_capacity(1, 4, |p| spilled.push(p)), 4);
+        assert_eq!(spilled, [token(6), token(5)]);
+        assert!(!cpu.push(1, token(99)));
+        assert_eq!(cpu.set_capacity(2, 1000, |_| unreachable!()), 16);
+
+        let mut drained = Vec::new();
+        assert_eq!(cpu.drain(2, |p| drained.push(p)), 6);
+        assert_eq!(drained.len(), 6);
+        drop(cpu);
+
+        assert_eq!(slab.length(1, 1), 4);
+        assert_eq!(slab.capacity(1, 1), 4);
+        assert_eq!(slab.length(1, 2), 0);
+        assert_eq!(slab.capacity(1, 2), 16);
+        assert_eq!(unsafe { slab.push_batch(1, 1, ptrs.as_ptr(), 6) }, 0);
+        assert_eq!(
+            unsafe { slab.stop_cpu(CPUS) }.err(),
+            Some(crate::syscall::EINVAL)
+        );
+    }
+
+    /// Threads move tokens between their hands and their CPUs' slabs while
+    /// another drains, refills and resizes every CPU; not one token may be
+    /// lost or duplicated.
+    #[cfg(any(miri, feature = "nightly"))]
+    #[test]
+    fn stop_cpu_races_push_and_pop() {
+        use std::sync::atomic::AtomicBool;
+        use std::thread;
+
+        const THREADS: usize = 4;
+        const TOKENS: usize = 64;
+        const SWEEPS: usize = if cfg!(miri) { 3 } else { 2000 };
+
+        if !can_stop() || (!cfg!(miri) && unsafe { crate::current_rseq() }.is_none()) {
+            return;
+        }
+        let cpus = thread::available_parallelism().map_or(1, |n| n.get() as u32);
+        let region = Region::new(cpus, SHIFT);
+        let mut slab = PerCpuSlab::<CLASSES>::empty();
+        unsafe { slab.init(region.0, cpus, SHIFT, &CAPACITIES) }.unwrap();
+        let slab = &slab;
+        let done = &AtomicBool::new(false);
+
+        let mut tokens: Vec<usize> = thread::scope(|s| {
+            let workers: Vec<_> = (0..THREADS)
+                .map(|t| {
+                    s.spawn(move || {
+                        let rseq = unsafe { crate::current_rseq() }.unwrap_or(ptr::null_mut());
+                        let mut held: Vec<_> = (0..TOKENS / THREADS)
+                            .map(|i| token(1 + t * TOKENS + i))
+                            .collect();
+                        let mut round = 0;
+                        while !done.load(Ordering::Relaxed) {
+                            let class = 1 + round % 2;
+                            if let Some(p) = unsafe { slab.pop(rseq, class) } {
+                                held.push(p);
+                            }
+                            for _ in 0..2 {
+                                if let Some(&p) = held.last()
+                                    && unsafe { slab.push(rseq, class, p) }.is_some()
+                                {
+                                    held.pop();
+                                }
+                            }
+                            round += 1;
+                            if cfg!(miri) {
+                                thread::yield_now();
+                            }
+                        }
+                        held.into_iter().map(|p| p.addr()).collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            let mut held = Vec::new();
+            for sweep in 0..SWEEPS {
+                for c in 0..cpus {
+                    let mut cpu = unsafe { slab.stop_cpu(c) }.unwrap();
+                    for class in 1..CLASSES {
+                        cpu.drain(class, |p| held.push(p.addr()));
+                        let cap = cpu.set_capacity(class, sweep as u32 % 9, |_| unreachable!());
+                        while cpu.length(class) < cap / 2 {
+                            let Some(p) = held.pop() else { break };
+                            cpu.push(class, ptr::without_provenance_mut(p));
+                        }
+                    }
+                }
+            }
+            done.store(true, Ordering::Relaxed);
+            held.extend(workers.into_iter().flat_map(|w| w.join().unwrap()));
+            held
+        });
+        for c in 0..cpus {
+            let mut cpu = unsafe { slab.stop_cpu(c) }.unwrap();
+            for class in 1..CLASSES {
+                cpu.drain(class, |p| tokens.push(p.addr()));
+            }
+        }
+
+        tokens.sort();
+        let expected: Vec<_> = (0..THREADS)
+            .flat_map(|t| (0..TOKENS / THREADS).map(move |i| token(1 + t * TOKENS + i).addr()))
+            .collect();
+        assert_eq!(tokens, expected);
+    }
+}
@@ -1,10 +1,17 @@
 //! Raw rseq syscall via inline assembly.
 //!
-//! Invokes syscall #334 directly — no libc wrapper.
+//! Invokes syscall #334 directly — no libc wrapper. membarrier (#324),
+//! which reaches into other CPUs' critical sections, is wrapped the same
+//! way.
 
 use core::arch::asm;
+use core::sync::atomic::{AtomicI32, Ordering};
 
-use crate::abi::{RSEQ_FLAG_UNREGISTER, RSEQ_MIN_SIZE, RSEQ_SIG, Rseq, SYS_RSEQ};
+use crate::abi::{
+    MEMBARRIER_CMD_FLAG_CPU, MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ,
+    MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_RSEQ, RSEQ_FLAG_UNREGISTER, RSEQ_MIN_SIZE, RSEQ_SIG,
+    Rseq, SYS_MEMBARRIER, SYS_RSEQ,
+};
 
 /// Issue the raw rseq syscall.
 ///
@@ -61,6 +68,68 @@ pub unsafe fn rseq_unregister(rseq: *mut Rseq) -> Result<(), i32> {
     if ret == 0 { Ok(()) } else { Err(ret as i32) }
 }
 
+/// Issue the raw membarrier syscall.
+///
+/// # Safety
+///
+/// Must only be called on Linux x86_64.
+#[inline(always)]
+pub unsafe fn raw_membarrier(cmd: i32, flags: u32, cpu_id: i32) -> i64 {
+    let ret: i64;
+    unsafe {
+        asm!(
+            "syscall",
+            in("rax") SYS_MEMBARRIER,
+            in("rdi") cmd as u64,
+            in("rsi") flags as u64,
+            in("rdx") cpu_id as u64,
+            lateout("rax") ret,
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack, preserves_flags)
+        );
+    }
+    ret
+}
+
+/// Result of registering for rseq fences: 0 not tried yet, 1 registered,
+/// otherwise the negative errno.
+static FENCE_REGISTRATION: AtomicI32 = AtomicI32::new(0);
+
+/// Register the process for [`rseq_fence`]. Done once; later calls return
+/// the first result.
+pub fn rseq_fence_register() -> Result<(), i32> {
+    let mut state = FENCE_REGISTRATION.load(Ordering::Acquire);
+    if state == 0 {
+        let ret = unsafe { raw_membarrier(MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_RSEQ, 0, 0) };
+        state = if ret == 0 { 1 } else { ret as i32 };
+        FENCE_REGISTRATION.store(state, Ordering::Release);
+    }
+    if state == 1 { Ok(()) } else { Err(state) }
+}
+
+/// Restart any rseq critical section that a thread of this process is
+/// running on `cpu`, and wait until it has been. A section that read
+/// memory before the call either committed before it returns or will
+/// restart and read it again.
+///
+/// A `cpu` the kernel does not know (`EINVAL`) runs nothing, so there is
+/// nothing to fence and it succeeds.
+pub fn rseq_fence(cpu: u32) -> Result<(), i32> {
+    rseq_fence_register()?;
+    let ret = unsafe {
+        raw_membarrier(
+            MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ,
+            MEMBARRIER_CMD_FLAG_CPU,
+            cpu as i32,
+        )
+    };
+    match ret as i32 {
+        0 | EINVAL => Ok(()),
+        err => Err(err),
+    }
+}
+
 /// Linux syscall returns negative errno on failure.
 pub const ENOSYS: i32 = -38;
 pub const EBUSY: i32 = -16;
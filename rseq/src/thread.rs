@@ -120,8 +120,11 @@ unsafe fn init_thread_rseq() -> RseqOwner {
             }
         }
 
-        // Check global "give up" flag.
+        // Check global "give up" flag. Mark the local area failed too, so
+        // the fast path above keeps reporting it unavailable.
         if RSEQ_UNAVAILABLE.load(Ordering::Relaxed) {
+            let ptr = &raw mut LOCAL_RSEQ;
+            (*ptr).cpu_id = RSEQ_CPU_ID_REGISTRATION_FAILED;
             THREAD_INITIALIZED = true;
             return RseqOwner::Unavailable;
         }
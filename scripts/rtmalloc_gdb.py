@@ -0,0 +1,308 @@
+"""gdb helpers for inspecting an rtmalloc heap in a live process or core file.
+
+Requires a binary built with the `coredump` feature, which exports the
+`rtmalloc_heap_layout` descriptor (see src/coredump.rs). Debug info is not
+needed; only the symbol.
+
+Usage:
+    (gdb) source scripts/rtmalloc_gdb.py
+    (gdb) rtmalloc info            # descriptor summary
+    (gdb) rtmalloc spans           # every span reachable from the page map
+    (gdb) rtmalloc classes         # objects and live bytes per size class
+    (gdb) rtmalloc find ADDR       # span that owns ADDR
+    (gdb) rtmalloc stats           # counters (`stats` feature)
+    (gdb) rtmalloc symbol NAME     # use another descriptor symbol, e.g.
+                                   # rtmalloc_nightly_heap_layout
+
+"Live" counts objects handed out of spans, which includes objects parked in
+thread, per-CPU and transfer caches.
+"""
+
+import struct
+
+import gdb
+
+# --- BEGIN GENERATED (src/coredump.rs) ---
+MAGIC = 0x00504145484d5452
+LAYOUT_VERSION = 1
+FIELDS = [
+    "magic",
+    "layout_version",
+    "config_fingerprint",
+    "pointer_size",
+    "page_shift",
+    "pagemap_root",
+    "pagemap_root_bits",
+    "pagemap_mid_bits",
+    "pagemap_leaf_bits",
+    "span_size",
+    "span_start_page",
+    "span_num_pages",
+    "span_size_class",
+    "span_state",
+    "span_allocated_count",
+    "span_total_count",
+    "span_state_in_use",
+    "size_classes",
+    "size_class_stride",
+    "size_class_size",
+    "num_size_classes",
+    "stats",
+    "num_stats",
+]
+STAT_NAMES = [
+    "alloc_count",
+    "dealloc_count",
+    "realloc_count",
+    "alloc_bytes",
+    "thread_cache_hits",
+    "thread_cache_misses",
+    "transfer_cache_hits",
+    "central_cache_hits",
+    "page_heap_allocs",
+    "mid_cache_hits",
+    "mid_cache_reclaims",
+    "central_populate_waits",
+    "class_cap_hits",
+    "zeroed_fresh_bytes",
+    "zeroed_decommit_bytes",
+    "free_decommit_bytes",
+    "cooldown_spans",
+    "cooldown_delayed_reuses",
+    "transfer_cache_evictions",
+    "thread_cache_decay_bytes",
+    "realloc_trim_bytes",
+    "realloc_in_place",
+    "realloc_moved",
+    "realloc_copy_bytes",
+    "os_alloc_count",
+    "os_alloc_bytes",
+    "os_alloc_nanos",
+    "os_alloc_max_nanos",
+    "os_alloc_failures",
+    "span_splits",
+    "span_coalesces",
+]
+# --- END GENERATED ---
+
+_symbol = "rtmalloc_heap_layout"
+
+
+def _read(addr, size):
+    return bytes(gdb.selected_inferior().read_memory(addr, size))
+
+
+def _u64s(addr, count):
+    return struct.unpack("<%dQ" % count, _read(addr, 8 * count))
+
+
+def _layout():
+    try:
+        addr = int(gdb.parse_and_eval("(unsigned long)&%s" % _symbol))
+    except gdb.error:
+        raise gdb.GdbError("symbol %s not found; build with the `coredump` feature" % _symbol)
+    layout = dict(zip(FIELDS, _u64s(addr, len(FIELDS))))
+    if layout["magic"] != MAGIC:
+        raise gdb.GdbError("%s: bad magic %#x" % (_symbol, layout["magic"]))
+    if layout["layout_version"] != LAYOUT_VERSION:
+        raise gdb.GdbError(
+            "%s: layout version %d, script understands %d"
+            % (_symbol, layout["layout_version"], LAYOUT_VERSION)
+        )
+    return layout
+
+
+class Span:
+    def __init__(self, layout, addr):
+        raw = _read(addr, layout["span_size"])
+
+        def usize(off):
+            return struct.unpack_from("<Q", raw, off)[0]
+
+        def u32(off):
+            return struct.unpack_from("<I", raw, off)[0]
+
+        self.addr = addr
+        self.start_page = usize(layout["span_start_page"])
+        self.num_pages = usize(layout["span_num_pages"])
+        self.size_class = usize(layout["span_size_class"])
+        self.in_use = raw[layout["span_state"]] == layout["span_state_in_use"]
+        self.allocated = u32(layout["span_allocated_count"])
+        self.total = u32(layout["span_total_count"])
+        shift = layout["page_shift"]
+        self.start = self.start_page << shift
+        self.end = (self.start_page + self.num_pages) << shift
+
+
+def _class_sizes(layout):
+    stride = layout["size_class_stride"]
+    base = layout["size_classes"] + layout["size_class_size"]
+    return [
+        _u64s(base + i * stride, 1)[0] for i in range(layout["num_size_classes"])
+    ]
+
+
+def _region(layout):
+    """High page ID bits shared by every mapped page; the word after the root.
+
+    A map covering all 35 page ID bits has no such word: the bits are 0.
+    """
+    bits = layout["pagemap_root_bits"] + layout["pagemap_mid_bits"] + layout["pagemap_leaf_bits"]
+    if bits == 35:
+        return 0
+    root_len = 1 << layout["pagemap_root_bits"]
+    return _u64s(layout["pagemap_root"] + 8 * root_len, 1)[0]
+
+
+def _lookup(layout, page_id):
+    root_bits, mid_bits, leaf_bits = (
+        layout["pagemap_root_bits"],
+        layout["pagemap_mid_bits"],
+        layout["pagemap_leaf_bits"],
+    )
+    if page_id >> (root_bits + mid_bits + leaf_bits) != _region(layout):
+        return 0
+    root_idx = (page_id >> (mid_bits + leaf_bits)) & ((1 << root_bits) - 1)
+    mid = _u64s(layout["pagemap_root"] + 8 * root_idx, 1)[0]
+    if mid == 0:
+        return 0
+    mid_idx = (page_id >> leaf_bits) & ((1 << mid_bits) - 1)
+    leaf = _u64s(mid + 8 * mid_idx, 1)[0]
+    if leaf == 0:
+        return 0
+    return _u64s(leaf + 8 * (page_id & ((1 << leaf_bits) - 1)), 1)[0]
+
+
+def _spans(layout):
+    """Yield every span once, at the page map entry for its first page.
+
+    Entries for interior pages of freed spans may be stale; requiring the
+    entry's page to equal the span's start page filters them out.
+    """
+    root_bits, mid_bits, leaf_bits = (
+        layout["pagemap_root_bits"],
+        layout["pagemap_mid_bits"],
+        layout["pagemap_leaf_bits"],
+    )
+    region = _region(layout) << (root_bits + mid_bits + leaf_bits)
+    roots = _u64s(layout["pagemap_root"], 1 << root_bits)
+    for ri, mid in enumerate(roots):
+        if mid == 0:
+            continue
+        for mi, leaf in enumerate(_u64s(mid, 1 << mid_bits)):
+            if leaf == 0:
+                continue
+            base = region | (ri << (mid_bits + leaf_bits)) | (mi << leaf_bits)
+            for li, span in enumerate(_u64s(leaf, 1 << leaf_bits)):
+                if span == 0:
+                    continue
+                s = Span(layout, span)
+                if s.start_page == base | li:
+                    yield s
+
+
+def _describe(s, sizes):
+    state = "in-use" if s.in_use else "free"
+    if s.size_class:
+        kind = "class %d (%d B) %d/%d objects" % (
+            s.size_class,
+            sizes[s.size_class],
+            s.allocated,
+            s.total,
+        )
+    else:
+        kind = "large"
+    return "span %#x [%#x, %#x) %d pages %s %s" % (
+        s.addr,
+        s.start,
+        s.end,
+        s.num_pages,
+        state,
+        kind,
+    )
+
+
+class RtmallocCommand(gdb.Command):
+    """Inspect the rtmalloc heap. Subcommands: info, spans, classes, find ADDR, stats, symbol NAME."""
+
+    def __init__(self):
+        super().__init__("rtmalloc", gdb.COMMAND_DATA)
+
+    def invoke(self, arg, from_tty):
+        global _symbol
+        argv = gdb.string_to_argv(arg)
+        if not argv:
+            raise gdb.GdbError("usage: rtmalloc info|spans|classes|find ADDR|stats|symbol NAME")
+        cmd, rest = argv[0], argv[1:]
+
+        if cmd == "symbol":
+            if len(rest) != 1:
+                raise gdb.GdbError("usage: rtmalloc symbol NAME")
+            _symbol = rest[0]
+            return
+
+        layout = _layout()
+        if cmd == "info":
+            for name in FIELDS:
+                print("%-22s %#x" % (name, layout[name]))
+        elif cmd == "spans":
+            sizes = _class_sizes(layout)
+            count = 0
+            for s in _spans(layout):
+                print(_describe(s, sizes))
+                count += 1
+            print("%d spans" % count)
+        elif cmd == "classes":
+            sizes = _class_sizes(layout)
+            objects = [0] * len(sizes)
+            spans = [0] * len(sizes)
+            large_bytes = 0
+            free_bytes = 0
+            shift = layout["page_shift"]
+            for s in _spans(layout):
+                if not s.in_use:
+                    free_bytes += s.num_pages << shift
+                elif s.size_class == 0:
+                    large_bytes += s.num_pages << shift
+                else:
+                    objects[s.size_class] += s.allocated
+                    spans[s.size_class] += 1
+            print("%5s %10s %8s %12s %14s" % ("class", "size", "spans", "objects", "live bytes"))
+            total = 0
+            for cls in range(1, len(sizes)):
+                if spans[cls] == 0:
+                    continue
+                live = objects[cls] * sizes[cls]
+                total += live
+                print("%5d %10d %8d %12d %14d" % (cls, sizes[cls], spans[cls], objects[cls], live))
+            print("small live bytes: %d" % total)
+            print("large live bytes: %d" % large_bytes)
+            print("free span bytes:  %d" % free_bytes)
+        elif cmd == "find":
+            if len(rest) != 1:
+                raise gdb.GdbError("usage: rtmalloc find ADDR")
+            addr = int(gdb.parse_and_eval(rest[0]))
+            span = _lookup(layout, addr >> layout["page_shift"])
+            if span == 0:
+                print("%#x is not in the rtmalloc heap" % addr)
+                return
+            s = Span(layout, span)
+            if not s.start <= addr < s.end:
+                print("%#x: stale page map entry" % addr)
+                return
+            print(_describe(s, _class_sizes(layout)))
+            if s.size_class and s.in_use:
+                size = _class_sizes(layout)[s.size_class]
+                index = (addr - s.start) // size
+                print("object %d at %#x (+%d)" % (index, s.start + index * size, (addr - s.start) % size))
+        elif cmd == "stats":
+            if layout["stats"] == 0:
+                raise gdb.GdbError("built without the `stats` feature")
+            values = _u64s(layout["stats"], layout["num_stats"])
+            for name, value in zip(STAT_NAMES, values):
+                print("%-22s %d" % (name, value))
+        else:
+            raise gdb.GdbError("unknown subcommand %r" % cmd)
+
+
+RtmallocCommand()
@@ -7,17 +7,22 @@
 //! - `percpu` feature: per-CPU slab via rseq (Linux x86_64, fastest)
 //! - `nightly` feature: `#[thread_local]` with const-init (single TLS read, no branches)
 //! - `std` feature: `std::thread_local!` with const-init (no lazy init overhead)
-//! - neither: central free list only (locked, slowest)
+//! - neither: lock-free object stacks in front of the central free lists for
+//!   small classes, the locked central free list for the rest (slowest)
 
-use crate::central_free_list::CentralCache;
-use crate::config::{PAGE_SHIFT, PAGE_SIZE};
+use crate::bootstrap::{self, ReentrancyGuard};
+use crate::central_free_list::{Age, CentralCache};
+use crate::config::{NUMA_INTERLEAVE_MIN, PAGE_SHIFT, PAGE_SIZE, ZERO_DECOMMIT_MIN};
+use crate::mid_heap::{self, MidHeap};
 use crate::page_heap::PageHeap;
 use crate::pagemap::PageMap;
+use crate::platform;
 use crate::size_class;
 use crate::sync::SpinMutex;
-use crate::{hist_record, stat_add, stat_inc};
+use crate::{hist_record, lifetime_alloc, lifetime_free, stat_add, stat_inc};
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr;
+use core::sync::atomic::{AtomicU8, Ordering};
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "percpu")] {
@@ -26,25 +31,80 @@ cfg_if::cfg_if! {
     } else if #[cfg(all(not(feature = "percpu"), any(feature = "nightly", feature = "std")))] {
         use crate::thread_cache::ThreadCache;
         use crate::transfer_cache::TransferCacheArray;
+    } else {
+        use crate::object_stack::{self, ObjectStackArray};
     }
 }
 
-use crate::span;
-
-cfg_if::cfg_if! {
-    if #[cfg(not(feature = "percpu"))] {
-        use crate::span::FreeObject;
-    }
-}
+use crate::span::{self, FreeObject, SpanState};
 
 pub(crate) static PAGE_MAP: PageMap = PageMap::new();
-pub(crate) static PAGE_HEAP: SpinMutex<PageHeap> = SpinMutex::new(PageHeap::new(&PAGE_MAP));
+pub(crate) static PAGE_HEAP: SpinMutex<PageHeap> =
+    SpinMutex::new(PageHeap::new(&PAGE_MAP).with_mid_heap(&MID_HEAP));
 pub(crate) static CENTRAL_CACHE: CentralCache = CentralCache::new();
+pub(crate) static MID_HEAP: MidHeap = MidHeap::new();
 
 cfg_if::cfg_if! {
     if #[cfg(any(feature = "percpu", feature = "nightly", feature = "std"))] {
         pub(crate) static TRANSFER_CACHE: TransferCacheArray = TransferCacheArray::new();
+    } else {
+        pub(crate) static OBJECT_STACKS: ObjectStackArray = ObjectStackArray::new();
+    }
+}
+
+const BYPASS_UNREAD: u8 = 0;
+const BYPASS_OFF: u8 = 1;
+const BYPASS_ON: u8 = 2;
+
+/// Cache bypass, see [`debug::bypass_caches`](crate::debug::bypass_caches).
+static BYPASS: AtomicU8 = AtomicU8::new(BYPASS_UNREAD);
+
+/// Whether small objects skip the front-end and transfer caches and large
+/// spans skip the mid-heap.
+#[inline(always)]
+pub(crate) fn caches_bypassed() -> bool {
+    match BYPASS.load(Ordering::Relaxed) {
+        BYPASS_OFF => false,
+        BYPASS_ON => true,
+        _ => bypass_from_env(),
+    }
+}
+
+/// First check: take the setting from `RTMALLOC_BYPASS_CACHES` (set and not
+/// `0`), unless [`set_caches_bypassed`] got there first.
+#[cold]
+fn bypass_from_env() -> bool {
+    let on =
+        crate::platform::env(c"RTMALLOC_BYPASS_CACHES").is_some_and(|v| !v.is_empty() && v != c"0");
+    let state = if on { BYPASS_ON } else { BYPASS_OFF };
+    let _ = BYPASS.compare_exchange(BYPASS_UNREAD, state, Ordering::Relaxed, Ordering::Relaxed);
+    BYPASS.load(Ordering::Relaxed) == BYPASS_ON
+}
+
+pub(crate) fn set_caches_bypassed(on: bool) {
+    BYPASS.store(if on { BYPASS_ON } else { BYPASS_OFF }, Ordering::Relaxed);
+}
+
+/// Move transfer cache batches to the central free lists and parked
+/// mid-heap spans to the page heap. Returns the bytes moved.
+#[cfg(any(
+    all(feature = "control", unix),
+    all(feature = "pressure", target_os = "linux")
+))]
+pub(crate) fn flush_shared_caches() -> usize {
+    let mut bytes = TRANSFER_CACHE.drain(&CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP);
+    let mut heap = PAGE_HEAP.lock();
+    loop {
+        let span = MID_HEAP.take_largest();
+        if span.is_null() {
+            break;
+        }
+        unsafe {
+            bytes += (*span).byte_size();
+            heap.deallocate_span(span);
+        }
     }
+    bytes
 }
 
 // --- Shared types and functions for nightly + std paths ---
@@ -56,15 +116,23 @@ enum TlsState {
     Uninitialized = 0,
     Active = 1,
     Destroyed = 2,
+    /// The cache is being built and its exit hook registered; nested
+    /// allocations bypass it.
+    Initializing = 3,
 }
 
 /// Thread-local slot holding the state machine and cache. ThreadCache has no
-/// Drop impl, so std::thread_local! won't call __cxa_thread_atexit_impl —
-/// no LD_PRELOAD recursion. Cleanup is explicit via `destroy()` from Guard::drop.
+/// Drop impl, so std::thread_local! won't call __cxa_thread_atexit_impl for
+/// the slot itself. Cleanup is explicit via `destroy()` from Guard::drop,
+/// whose registration may allocate (see [`TcSlot::init`]).
+///
+/// `cache` comes first and is cache-line aligned, so `state` lands on its own
+/// line after the cache and never shares one with the hot free list heads.
 #[cfg(all(not(feature = "percpu"), any(feature = "nightly", feature = "std")))]
+#[repr(C)]
 struct TcSlot {
-    state: TlsState,
     cache: ThreadCache,
+    state: TlsState,
 }
 
 #[cfg(all(not(feature = "percpu"), any(feature = "nightly", feature = "std")))]
@@ -74,14 +142,21 @@ impl TcSlot {
         &mut self.cache
     }
 
+    /// Build the cache and register its exit hook. Registering can call
+    /// `malloc` (glibc's `__cxa_thread_atexit_impl` does, through `calloc`);
+    /// those nested calls see `Initializing` and go to the central lists
+    /// without touching the cache. Takes the slot's own raw pointer, and
+    /// callers take a fresh reference after, so no `&mut` to the slot is
+    /// live while they read its state.
     #[cold]
     #[inline(never)]
-    unsafe fn init(&mut self) {
-        self.cache.init();
-        // Active BEFORE register — reentrant mallocs from Guard registration
-        // see Active state and use the thread cache normally.
-        self.state = TlsState::Active;
-        tc_cleanup::register();
+    unsafe fn init(slot: *mut Self) {
+        unsafe {
+            (*slot).state = TlsState::Initializing;
+            (*slot).cache.init();
+            tc_cleanup::register();
+            (*slot).state = TlsState::Active;
+        }
     }
 
     #[cold]
@@ -109,8 +184,8 @@ cfg_if::cfg_if! {
     } else if #[cfg(feature = "nightly")] {
         #[thread_local]
         static mut TC: TcSlot = TcSlot {
-            state: TlsState::Uninitialized,
             cache: ThreadCache::new_const(),
+            state: TlsState::Uninitialized,
         };
 
         #[inline(always)]
@@ -121,14 +196,109 @@ cfg_if::cfg_if! {
         std::thread_local! {
             static TC_CELL: core::cell::UnsafeCell<TcSlot> = const {
                 core::cell::UnsafeCell::new(TcSlot {
-                    state: TlsState::Uninitialized,
                     cache: ThreadCache::new_const(),
+                    state: TlsState::Uninitialized,
                 })
             };
         }
     }
 }
 
+// --- Thread cache donation, flushing and arenas (see `hint`, `thread`) ---
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "percpu")] {
+        // Per-CPU caches stay with the CPU, whichever task runs on it.
+        pub(crate) fn donate_thread_cache() {}
+        pub(crate) fn flush_thread_cache() {}
+        pub(crate) fn thread_cache_info() -> Option<crate::thread_cache::ThreadCacheInfo> {
+            None
+        }
+        pub(crate) fn set_thread_arena(_arena: usize) -> bool {
+            false
+        }
+        pub(crate) fn thread_arena() -> usize {
+            0
+        }
+    } else if #[cfg(any(feature = "nightly", feature = "std"))] {
+        pub(crate) fn donate_thread_cache() {
+            with_active_cache(|tc| unsafe {
+                tc.donate(&TRANSFER_CACHE, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP)
+            });
+        }
+
+        pub(crate) fn flush_thread_cache() {
+            with_active_cache(|tc| unsafe {
+                tc.flush(&TRANSFER_CACHE, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP)
+            });
+        }
+
+        pub(crate) fn thread_cache_info() -> Option<crate::thread_cache::ThreadCacheInfo> {
+            with_active_cache(|tc| tc.info())
+        }
+
+        /// Creates the calling thread's cache if it has none yet, so the
+        /// arena holds from the first allocation.
+        pub(crate) fn set_thread_arena(arena: usize) -> bool {
+            if arena >= crate::config::NUM_ARENAS {
+                return false;
+            }
+            let set = |slot: *mut TcSlot| unsafe {
+                match (*slot).state {
+                    TlsState::Destroyed | TlsState::Initializing => return false,
+                    TlsState::Uninitialized => TcSlot::init(slot),
+                    TlsState::Active => {}
+                }
+                (*slot).tc().set_arena(arena, &TRANSFER_CACHE, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP);
+                true
+            };
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "nightly")] {
+                    set(core::ptr::addr_of_mut!(TC))
+                } else {
+                    TC_CELL.try_with(|cell| set(cell.get())).unwrap_or(false)
+                }
+            }
+        }
+
+        pub(crate) fn thread_arena() -> usize {
+            with_active_cache(|tc| tc.arena()).unwrap_or(0)
+        }
+
+        /// Run `f` on the calling thread's cache, if it has an active one.
+        fn with_active_cache<R>(f: impl FnOnce(&mut ThreadCache) -> R) -> Option<R> {
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "nightly")] {
+                    let slot = unsafe { tc_slot() };
+                    (slot.state == TlsState::Active).then(|| f(slot.tc()))
+                } else {
+                    TC_CELL
+                        .try_with(|cell| {
+                            let slot = unsafe { &mut *cell.get() };
+                            (slot.state == TlsState::Active).then(|| f(slot.tc()))
+                        })
+                        .ok()
+                        .flatten()
+                }
+            }
+        }
+    } else {
+        // No thread caches: objects go straight to the shared object stacks and
+        // central lists.
+        pub(crate) fn donate_thread_cache() {}
+        pub(crate) fn flush_thread_cache() {}
+        pub(crate) fn thread_cache_info() -> Option<crate::thread_cache::ThreadCacheInfo> {
+            None
+        }
+        pub(crate) fn set_thread_arena(_arena: usize) -> bool {
+            false
+        }
+        pub(crate) fn thread_arena() -> usize {
+            0
+        }
+    }
+}
+
 // --- Thread cache cleanup ---
 
 #[cfg(all(not(feature = "percpu"), any(feature = "nightly", feature = "std")))]
@@ -173,6 +343,15 @@ mod tc_cleanup {
 /// #[global_allocator]
 /// static GLOBAL: rtmalloc::RtMalloc = rtmalloc::RtMalloc;
 /// ```
+///
+/// Every `RtMalloc` value is a handle to the same process-wide heap, so it
+/// need not be the global allocator: with `nightly` it also implements
+/// `Allocator` for `Vec::new_in(RtMalloc)` and friends while another
+/// allocator stays global. Memory must be freed through the allocator that
+/// returned it. Converting a `Vec<T, RtMalloc>` into a plain `Vec<T>` hands
+/// rtmalloc memory to the global allocator, and a pointer rtmalloc does not
+/// own is ignored by its frees (the memory leaks), not returned anywhere.
+#[derive(Clone, Copy, Debug, Default)]
 pub struct RtMalloc;
 
 unsafe impl GlobalAlloc for RtMalloc {
@@ -180,33 +359,30 @@ unsafe impl GlobalAlloc for RtMalloc {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let size = layout.size();
         if size == 0 {
-            return layout.align() as *mut u8;
+            return ptr::without_provenance_mut(layout.align());
         }
 
+        // Nested allocation from instrumentation running inside the allocator:
+        // serve it without touching any lock the outer frame may hold.
+        let Some(_guard) = ReentrancyGuard::enter() else {
+            return unsafe { bootstrap::alloc(layout) };
+        };
+
         stat_inc!(alloc_count);
         stat_add!(alloc_bytes, size as u64);
         hist_record!(size);
 
-        let align = layout.align();
-
-        if align <= 8 {
-            let class = size_class::size_to_class(size);
-            if class != 0 {
-                return unsafe { self.alloc_small(class) };
-            }
+        let class = size_class::layout_to_class(size, layout.align());
+        let ptr = if class == 0 {
+            unsafe { self.alloc_large(layout, false) }
+        } else if caches_bypassed() {
+            unsafe { self.alloc_uncached(class) }
         } else {
-            let effective_size = size.max(align);
-            let class = size_class::size_to_class(effective_size);
-            if class != 0 {
-                let class_size = size_class::class_to_size(class);
-                if align > PAGE_SIZE || !class_size.is_multiple_of(align) {
-                    return unsafe { self.alloc_large(layout) };
-                }
-                return unsafe { self.alloc_small(class) };
-            }
-        }
-
-        unsafe { self.alloc_large(layout) }
+            unsafe { self.alloc_small(class) }
+        };
+        debug_assert!((ptr as usize).is_multiple_of(layout.align()));
+        lifetime_alloc!(ptr, class, size);
+        ptr
     }
 
     #[inline]
@@ -214,79 +390,46 @@ unsafe impl GlobalAlloc for RtMalloc {
         if layout.size() == 0 {
             return;
         }
-
-        stat_inc!(dealloc_count);
-
-        // Look up the actual size class from the span metadata, like tcmalloc.
-        // We cannot trust layout.size() because realloc may return the same
-        // pointer for a shrink (staying in-place when new_size fits in the
-        // existing size class), so the caller's layout may not match the
-        // span's real size class.
-        let page_id = (ptr as usize) >> PAGE_SHIFT;
-        let span = PAGE_MAP.get(page_id);
-        if span.is_null() {
-            return;
-        }
-
-        let sc = unsafe { (*span).size_class };
-        if sc != 0 {
-            unsafe { self.dealloc_small(ptr, sc) };
-        } else {
-            unsafe { PAGE_HEAP.lock().deallocate_span(span) };
-        }
+        #[cfg(feature = "layout-check")]
+        crate::layout_check::check(ptr, layout);
+        unsafe { self.free(ptr) }
     }
 
+    #[allow(clippy::absurd_extreme_comparisons)]
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
         let ptr = unsafe { self.alloc(layout) };
-        if !ptr.is_null() && layout.size() > 0 {
-            unsafe { ptr::write_bytes(ptr, 0, layout.size()) };
-        }
-        ptr
-    }
-
-    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
         if ptr.is_null() || layout.size() == 0 {
-            let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
-            return unsafe { self.alloc(new_layout) };
-        }
-
-        if new_size == 0 {
-            unsafe { self.dealloc(ptr, layout) };
-            return layout.align() as *mut u8;
+            return ptr;
         }
-
-        stat_inc!(realloc_count);
-
-        // Look up the REAL allocation size from span metadata, like tcmalloc.
-        // We cannot trust layout.size() because prior reallocs may have returned
-        // the same pointer for an in-place shrink, so the caller's layout may
-        // carry a smaller size than the span's actual size class.
-        let page_id = (ptr as usize) >> PAGE_SHIFT;
-        let span = PAGE_MAP.get(page_id);
-        let old_usable = if !span.is_null() {
-            let sc = unsafe { (*span).size_class };
-            if sc != 0 {
-                size_class::class_to_size(sc)
-            } else {
-                (unsafe { (*span).num_pages }) * PAGE_SIZE
+        // A large span straight from the OS is already zero. Nobody else
+        // can have touched it yet: it was handed out just now, to us.
+        if size_class::layout_to_class(layout.size(), layout.align()) == 0 {
+            let span = PAGE_MAP.get(ptr.addr() >> PAGE_SHIFT);
+            if !span.is_null() && unsafe { (*span).fresh_from_os } {
+                debug_assert!(unsafe { ptr.read() == 0 && ptr.add(layout.size() - 1).read() == 0 });
+                stat_add!(zeroed_fresh_bytes, layout.size() as u64);
+                return ptr;
+            }
+            // A big reused span is cheaper to have the OS drop and fault back
+            // in as zero pages than to clear byte by byte.
+            if ZERO_DECOMMIT_MIN != 0
+                && layout.size() >= ZERO_DECOMMIT_MIN
+                && unsafe { platform::page_zero(ptr, layout.size()) }
+            {
+                stat_add!(zeroed_decommit_bytes, layout.size() as u64);
+                return ptr;
             }
-        } else {
-            layout.size() // Defensive fallback
-        };
-
-        // Fits in current allocation — return same pointer
-        if new_size <= old_usable {
-            return ptr;
         }
+        unsafe { ptr::write_bytes(ptr, 0, layout.size()) };
+        ptr
+    }
 
-        // Must grow — allocate, copy, free
-        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
-        let new_ptr = unsafe { self.alloc(new_layout) };
-        if !new_ptr.is_null() {
-            unsafe { ptr::copy_nonoverlapping(ptr, new_ptr, old_usable.min(new_size)) };
-            unsafe { self.dealloc(ptr, layout) };
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        #[cfg(feature = "layout-check")]
+        if !ptr.is_null() && layout.size() != 0 {
+            crate::layout_check::check(ptr, layout);
         }
-        new_ptr
+        unsafe { self.resize(ptr, layout, new_size) }
     }
 }
 
@@ -300,8 +443,10 @@ impl RtMalloc {
                 }
             }
 
+            /// Only threads with a cache pick an arena, so every object here
+            /// is from the shared one.
             #[inline(always)]
-            unsafe fn dealloc_small(&self, ptr: *mut u8, class: usize) {
+            unsafe fn dealloc_small(&self, ptr: *mut u8, class: usize, _arena: usize) {
                 unsafe {
                     cpu_cache::dealloc(ptr, class, &TRANSFER_CACHE, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP)
                 };
@@ -315,21 +460,23 @@ impl RtMalloc {
                         slot.tc().allocate(class, &TRANSFER_CACHE, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP)
                     },
                     TlsState::Uninitialized => unsafe {
-                        slot.init();
-                        slot.tc().allocate(class, &TRANSFER_CACHE, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP)
+                        TcSlot::init(ptr::addr_of_mut!(TC));
+                        tc_slot().tc().allocate(class, &TRANSFER_CACHE, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP)
                     },
-                    TlsState::Destroyed => unsafe { self.alloc_from_central(class) },
+                    TlsState::Initializing | TlsState::Destroyed => unsafe { self.alloc_uncached(class) },
                 }
             }
 
             #[inline(always)]
-            unsafe fn dealloc_small(&self, ptr: *mut u8, class: usize) {
+            unsafe fn dealloc_small(&self, ptr: *mut u8, class: usize, arena: usize) {
                 let slot = unsafe { tc_slot() };
                 match slot.state {
-                    TlsState::Active => unsafe {
+                    TlsState::Active if slot.cache.arena() == arena => unsafe {
                         slot.tc().deallocate(ptr, class, &TRANSFER_CACHE, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP);
                     },
-                    _ => unsafe { self.dealloc_to_central(ptr, class) },
+                    // A thread that only frees never builds a thread cache, and
+                    // objects of another arena never enter it.
+                    _ => unsafe { self.dealloc_to_arena(ptr, class, arena) },
                 }
             }
         } else if #[cfg(feature = "std")] {
@@ -342,23 +489,23 @@ impl RtMalloc {
                             slot.tc().allocate(class, &TRANSFER_CACHE, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP)
                         }
                         TlsState::Uninitialized => {
-                            slot.init();
-                            slot.tc().allocate(class, &TRANSFER_CACHE, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP)
+                            TcSlot::init(cell.get());
+                            (*cell.get()).tc().allocate(class, &TRANSFER_CACHE, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP)
                         }
-                        TlsState::Destroyed => ptr::null_mut(),
+                        TlsState::Initializing | TlsState::Destroyed => ptr::null_mut(),
                     }
                 }) {
                     Ok(ptr) if !ptr.is_null() => ptr,
-                    _ => unsafe { self.alloc_from_central(class) },
+                    _ => unsafe { self.alloc_uncached(class) },
                 }
             }
 
             #[inline(always)]
-            unsafe fn dealloc_small(&self, ptr: *mut u8, class: usize) {
+            unsafe fn dealloc_small(&self, ptr: *mut u8, class: usize, arena: usize) {
                 let used_tc = TC_CELL.try_with(|cell| unsafe {
                     let slot = &mut *cell.get();
                     match slot.state {
-                        TlsState::Active => {
+                        TlsState::Active if slot.cache.arena() == arena => {
                             slot.tc().deallocate(ptr, class, &TRANSFER_CACHE, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP);
                             true
                         }
@@ -366,59 +513,410 @@ impl RtMalloc {
                     }
                 });
                 if !matches!(used_tc, Ok(true)) {
-                    unsafe { self.dealloc_to_central(ptr, class) };
+                    // A thread that only frees never builds a thread cache, and
+                    // objects of another arena never enter it.
+                    unsafe { self.dealloc_to_arena(ptr, class, arena) };
                 }
             }
         } else {
             #[inline(always)]
             unsafe fn alloc_small(&self, class: usize) -> *mut u8 {
-                unsafe { self.alloc_from_central(class) }
+                if object_stack::is_stacked(class) {
+                    return unsafe {
+                        OBJECT_STACKS.allocate(class, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP)
+                    } as *mut u8;
+                }
+                unsafe { self.alloc_uncached(class) }
             }
 
+            /// Only threads with a cache pick an arena, so every object here
+            /// is from the shared one.
             #[inline(always)]
-            unsafe fn dealloc_small(&self, ptr: *mut u8, class: usize) {
-                unsafe { self.dealloc_to_central(ptr, class) };
+            unsafe fn dealloc_small(&self, ptr: *mut u8, class: usize, _arena: usize) {
+                if object_stack::is_stacked(class) {
+                    unsafe {
+                        OBJECT_STACKS.deallocate(class, ptr as *mut FreeObject, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP)
+                    };
+                    return;
+                }
+                unsafe { self.dealloc_uncached(ptr, class, 0) };
             }
         }
     }
 
+    /// Whether `ptr` points into memory this heap manages: pages it handed
+    /// out (or holds free for reuse) and the bootstrap arena. Code that mixes
+    /// rtmalloc with another allocator can use it to route a free; memory
+    /// never handed out by either is a bug it cannot catch.
+    pub fn owns(&self, ptr: *const u8) -> bool {
+        let page_id = ptr.addr() >> PAGE_SHIFT;
+        PAGE_MAP.size_class(page_id) != 0
+            || !PAGE_MAP.get(page_id).is_null()
+            || bootstrap::owns(ptr)
+    }
+
+    /// Free `ptr` by its page map entry alone: its size class, or the large
+    /// span it lies in. `dealloc` without a layout, for `free(3)`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live allocation from this allocator.
+    #[inline]
+    pub(crate) unsafe fn free(&self, ptr: *mut u8) {
+        // A nested free cannot safely take allocator locks. Leak it instead;
+        // bootstrap arena pointers are never in the page map anyway.
+        let Some(_guard) = ReentrancyGuard::enter() else {
+            return;
+        };
+
+        stat_inc!(dealloc_count);
+        lifetime_free!(ptr);
+
+        // Look up the actual size class from the page map, like tcmalloc.
+        // We cannot trust layout.size() because realloc may return the same
+        // pointer for a shrink (staying in-place when new_size fits in the
+        // existing size class), so the caller's layout may not match the
+        // span's real size class. Small objects never touch the span here.
+        let page_id = (ptr as usize) >> PAGE_SHIFT;
+        let (sc, arena) = PAGE_MAP.class_and_arena(page_id);
+        if sc != 0 {
+            if caches_bypassed() {
+                unsafe { self.dealloc_uncached(ptr, sc, arena) };
+            } else {
+                unsafe { self.dealloc_small(ptr, sc, arena) };
+            }
+            return;
+        }
+
+        let span = PAGE_MAP.get(page_id);
+        if span.is_null() {
+            return;
+        }
+        // Every registered page of an in-use span points at a span covering
+        // it. Anything else would return someone else's pages to the heap.
+        let live = unsafe { (*span).state == SpanState::InUse && (*span).contains(ptr) };
+        debug_assert!(live, "large free of {ptr:p} does not match its span");
+        #[cfg(feature = "span-quarantine")]
+        if live && unsafe { crate::quarantine::hold(span) } {
+            return;
+        }
+        if live && (caches_bypassed() || !unsafe { MID_HEAP.park(span) }) {
+            unsafe { PAGE_HEAP.lock().deallocate_span(span) };
+        }
+    }
+
+    /// `realloc` without checking `layout`, which only supplies the
+    /// alignment and, for a pointer not in the page map, the old size. For
+    /// `realloc(3)`, which has no layout.
+    ///
+    /// # Safety
+    ///
+    /// As for [`GlobalAlloc::realloc`], except that `layout` need not be the
+    /// one `ptr` was allocated with.
+    pub(crate) unsafe fn resize(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if ptr.is_null() || layout.size() == 0 {
+            let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+            return unsafe { self.alloc(new_layout) };
+        }
+
+        if new_size == 0 {
+            unsafe { self.free(ptr) };
+            return ptr::without_provenance_mut(layout.align());
+        }
+
+        stat_inc!(realloc_count);
+
+        // Look up the REAL allocation size from span metadata, like tcmalloc.
+        // We cannot trust layout.size() because prior reallocs may have returned
+        // the same pointer for an in-place shrink, so the caller's layout may
+        // carry a smaller size than the span's actual size class.
+        let page_id = (ptr as usize) >> PAGE_SHIFT;
+        let sc = PAGE_MAP.size_class(page_id);
+        let old_usable = if sc != 0 {
+            size_class::class_to_size(sc)
+        } else {
+            let span = PAGE_MAP.get(page_id);
+            if !span.is_null() {
+                // Measured from `ptr`, which need not be the span's first page.
+                unsafe { (*span).bytes_from(ptr) }
+            } else if bootstrap::owns(ptr) {
+                unsafe { bootstrap::allocation_size(ptr) }
+            } else {
+                layout.size() // Defensive fallback
+            }
+        };
+
+        // Fits in current allocation — return same pointer. Checked layouts
+        // must name the class the object is in, so only stay within it.
+        let fits = new_size <= old_usable;
+        #[cfg(feature = "layout-check")]
+        let fits = fits && size_class::layout_to_class(new_size, layout.align()) == sc;
+        if fits {
+            if sc == 0 {
+                unsafe { self.shrink_large(ptr, layout.align(), new_size) };
+            }
+            stat_inc!(realloc_in_place);
+            return ptr;
+        }
+
+        // Must grow — allocate, copy, free
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            let copied = old_usable.min(new_size);
+            unsafe { ptr::copy_nonoverlapping(ptr, new_ptr, copied) };
+            unsafe { self.free(ptr) };
+            stat_inc!(realloc_moved);
+            stat_add!(realloc_copy_bytes, copied);
+        }
+        new_ptr
+    }
+
+    /// Give the pages a large allocation shrunk in place to `new_size` no
+    /// longer reaches back to the page heap. A mid-size allocation keeps the
+    /// pages of its new mid class, as a fresh one of `new_size` would get,
+    /// so the span can still be parked when freed.
+    unsafe fn shrink_large(&self, ptr: *mut u8, align: usize, new_size: usize) {
+        let Some(_guard) = ReentrancyGuard::enter() else {
+            return;
+        };
+        let span = PAGE_MAP.get(ptr.addr() >> PAGE_SHIFT);
+        if span.is_null() {
+            return;
+        }
+        let offset = ptr.addr() - unsafe { (*span).start_addr() }.addr();
+        let keep = match mid_heap::size_to_class(new_size) {
+            Some(cls) if offset == 0 && align <= PAGE_SIZE => mid_heap::carve_pages(new_size, cls),
+            _ => (offset + new_size).div_ceil(PAGE_SIZE),
+        };
+        let pages = unsafe { (*span).num_pages };
+        if keep < pages && unsafe { PAGE_HEAP.lock().trim_span(span, keep) } {
+            stat_add!(realloc_trim_bytes, (pages - keep) * PAGE_SIZE);
+        }
+    }
+
+    /// Take one object straight from the central free list.
+    unsafe fn alloc_uncached(&self, size_class: usize) -> *mut u8 {
+        unsafe { self.alloc_central(size_class, Age::Nursery) }
+    }
+
+    /// Take one object straight from the `age` spans of the central free
+    /// list.
+    unsafe fn alloc_central(&self, size_class: usize, age: Age) -> *mut u8 {
+        stat_inc!(thread_cache_misses);
+        stat_inc!(central_cache_hits);
+        let (count, head, _) = unsafe {
+            CENTRAL_CACHE
+                .get(size_class)
+                .lock()
+                .remove_range_aged(1, age, &PAGE_HEAP, &PAGE_MAP)
+        };
+        if count == 0 || head.is_null() {
+            ptr::null_mut()
+        } else {
+            head as *mut u8
+        }
+    }
+
+    /// Return one object straight to its arena's central free list.
+    unsafe fn dealloc_uncached(&self, ptr: *mut u8, size_class: usize, arena: usize) {
+        let obj = ptr as *mut FreeObject;
+        unsafe { FreeObject::set_next(obj, ptr::null_mut()) };
+        unsafe {
+            CENTRAL_CACHE
+                .arena(arena, size_class)
+                .lock()
+                .insert_range(obj, 1, &PAGE_HEAP, &PAGE_MAP)
+        };
+    }
+
     cfg_if::cfg_if! {
         if #[cfg(not(feature = "percpu"))] {
-            unsafe fn alloc_from_central(&self, size_class: usize) -> *mut u8 {
-                stat_inc!(thread_cache_misses);
-                stat_inc!(central_cache_hits);
-                let (count, head) = unsafe {
-                    CENTRAL_CACHE
-                        .get(size_class)
-                        .lock()
-                        .remove_range(1, &PAGE_HEAP, &PAGE_MAP)
+            /// Free without a thread cache: batch through the transfer cache
+            /// rather than locking the central free list per object.
+            #[cfg(any(feature = "nightly", feature = "std"))]
+            unsafe fn dealloc_to_transfer(&self, ptr: *mut u8, size_class: usize) {
+                unsafe {
+                    TRANSFER_CACHE.insert_one(
+                        size_class,
+                        ptr as *mut FreeObject,
+                        &CENTRAL_CACHE,
+                        &PAGE_HEAP,
+                        &PAGE_MAP,
+                    )
                 };
-                if count == 0 || head.is_null() {
-                    ptr::null_mut()
+            }
+
+            /// Free an object the calling thread's cache must not keep: the
+            /// thread has none, or the object is from another arena.
+            #[cfg(any(feature = "nightly", feature = "std"))]
+            unsafe fn dealloc_to_arena(&self, ptr: *mut u8, size_class: usize, arena: usize) {
+                if arena == 0 {
+                    unsafe { self.dealloc_to_transfer(ptr, size_class) };
                 } else {
-                    head as *mut u8
+                    unsafe { self.dealloc_uncached(ptr, size_class, arena) };
                 }
             }
+        }
+    }
 
-            unsafe fn dealloc_to_central(&self, ptr: *mut u8, size_class: usize) {
-                let obj = ptr as *mut FreeObject;
-                unsafe { (*obj).next = ptr::null_mut() };
-                unsafe {
-                    CENTRAL_CACHE
-                        .get(size_class)
-                        .lock()
-                        .insert_range(obj, 1, &PAGE_HEAP, &PAGE_MAP)
+    /// Free many allocations made with `layout` at once, e.g. when tearing
+    /// down a collection of boxes.
+    ///
+    /// Small objects are linked into one list per size class and handed on a
+    /// batch at a time, through the transfer cache where there is one,
+    /// instead of taking the full free path each. They bypass the calling
+    /// thread's cache. Large allocations are freed one by one as by
+    /// [`GlobalAlloc::dealloc`].
+    ///
+    /// ```ignore
+    /// let layout = Layout::new::<Node>();
+    /// unsafe { GLOBAL.dealloc_iter(layout, nodes.into_iter().map(|n| Box::into_raw(n).cast())) };
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// Every pointer must be a live allocation from this allocator, made with
+    /// `layout` as for [`GlobalAlloc::dealloc`], and appear only once.
+    pub unsafe fn dealloc_iter(&self, layout: Layout, ptrs: impl IntoIterator<Item = *mut u8>) {
+        if layout.size() == 0 {
+            return;
+        }
+
+        let mut lists =
+            [(ptr::null_mut::<FreeObject>(), ptr::null_mut(), 0); size_class::NUM_SIZE_CLASSES];
+        for ptr in ptrs {
+            #[cfg(feature = "layout-check")]
+            crate::layout_check::check(ptr, layout);
+            // As in `dealloc`, the page map knows the real class. The
+            // batches go to the shared arena; objects of others go alone.
+            let (sc, arena) = PAGE_MAP.class_and_arena((ptr as usize) >> PAGE_SHIFT);
+            if sc == 0 || arena != 0 {
+                unsafe { self.free(ptr) };
+                continue;
+            }
+            let (head, tail, count) = &mut lists[sc];
+            let obj = ptr as *mut FreeObject;
+            unsafe { FreeObject::set_next(obj, *head) };
+            if head.is_null() {
+                *tail = obj;
+            }
+            *head = obj;
+            *count += 1;
+            if *count >= size_class::batch_size(sc) {
+                unsafe { free_list(sc, *head, *tail, *count) };
+                lists[sc] = (ptr::null_mut(), ptr::null_mut(), 0);
+            }
+        }
+        for (sc, &(head, tail, count)) in lists.iter().enumerate() {
+            if count > 0 {
+                unsafe { free_list(sc, head, tail, count) };
+            }
+        }
+    }
+
+    /// Allocate as [`GlobalAlloc::alloc`], with [`hint`](crate::hint) flags
+    /// saying how the memory will be used.
+    ///
+    /// [`hint::COLD`](crate::hint::COLD) small objects come straight from
+    /// the tenured spans of their central list (see [`Age`]), so long-lived
+    /// objects fill spans of their own instead of pinning spans that hot
+    /// objects churn through. [`hint::INTERLEAVE`](crate::hint::INTERLEAVE)
+    /// large allocations have their pages spread over all NUMA nodes, as
+    /// those of `numa_interleave_min` or more bytes always do. Every other
+    /// flag, and the sizes they don't apply to, get a plain allocation.
+    ///
+    /// # Safety
+    ///
+    /// As for [`GlobalAlloc::alloc`].
+    pub unsafe fn alloc_hinted(&self, layout: Layout, hints: u32) -> *mut u8 {
+        use crate::hint::{COLD, INTERLEAVE};
+        if hints & (COLD | INTERLEAVE) != 0 && layout.size() != 0 {
+            let class = size_class::layout_to_class(layout.size(), layout.align());
+            if (class != 0 && hints & COLD != 0) || (class == 0 && hints & INTERLEAVE != 0) {
+                let Some(_guard) = ReentrancyGuard::enter() else {
+                    return unsafe { bootstrap::alloc(layout) };
+                };
+                stat_inc!(alloc_count);
+                stat_add!(alloc_bytes, layout.size() as u64);
+                hist_record!(layout.size());
+                let ptr = if class != 0 {
+                    unsafe { self.alloc_central(class, Age::Tenured) }
+                } else {
+                    unsafe { self.alloc_large(layout, true) }
                 };
+                lifetime_alloc!(ptr, class, layout.size());
+                return ptr;
             }
         }
+        unsafe { self.alloc(layout) }
     }
 
-    unsafe fn alloc_large(&self, layout: Layout) -> *mut u8 {
-        stat_inc!(page_heap_allocs);
+    /// Free `ptr` as [`GlobalAlloc::dealloc`], but trust `layout` for its
+    /// size class instead of looking it up in the page map.
+    ///
+    /// Saves a page map load on every small free for callers that know the
+    /// exact size, such as language runtimes that track object sizes. Large
+    /// objects, builds with more than one arena or `layout-check`, and
+    /// bypassed caches take the normal path.
+    ///
+    /// # Safety
+    ///
+    /// As for [`GlobalAlloc::dealloc`], and `ptr` must not have been resized
+    /// in place by `realloc`: `layout` must map to the size class `ptr` was
+    /// allocated from.
+    pub unsafe fn dealloc_sized(&self, ptr: *mut u8, layout: Layout) {
+        let class = size_class::layout_to_class(layout.size(), layout.align());
+        if layout.size() == 0
+            || class == 0
+            || crate::config::NUM_ARENAS > 1
+            || cfg!(feature = "layout-check")
+            || caches_bypassed()
+        {
+            return unsafe { self.dealloc(ptr, layout) };
+        }
+        let Some(_guard) = ReentrancyGuard::enter() else {
+            return;
+        };
+        debug_assert_eq!(
+            PAGE_MAP.size_class(ptr.addr() >> PAGE_SHIFT),
+            class,
+            "sized free of {ptr:p} does not match its size class"
+        );
+
+        stat_inc!(dealloc_count);
+        lifetime_free!(ptr);
+        unsafe { self.dealloc_small(ptr, class, 0) };
+    }
 
+    /// Allocate a span for `layout`, with its pages interleaved over the
+    /// NUMA nodes if `interleave` or `numa_interleave_min` asks for it.
+    #[allow(clippy::absurd_extreme_comparisons)]
+    unsafe fn alloc_large(&self, layout: Layout, interleave: bool) -> *mut u8 {
         let size = layout.size();
         let align = layout.align();
-        let size_pages = size.div_ceil(PAGE_SIZE);
+        let mut size_pages = size.div_ceil(PAGE_SIZE);
+        let interleave = interleave || (NUMA_INTERLEAVE_MIN != 0 && size >= NUMA_INTERLEAVE_MIN);
+
+        // Medium sizes round up to a mid-heap class so freed spans can be
+        // reused whole without touching the page heap.
+        if align <= PAGE_SIZE
+            && let Some(cls) = mid_heap::size_to_class(size)
+        {
+            let span = if caches_bypassed() {
+                ptr::null_mut()
+            } else {
+                MID_HEAP.take(mid_heap::class_to_pages(cls))
+            };
+            if !span.is_null() {
+                stat_inc!(mid_cache_hits);
+                unsafe { set_interleave(span, interleave) };
+                return unsafe { (*span).start_addr() };
+            }
+            size_pages = mid_heap::carve_pages(size, cls);
+        }
+
+        stat_inc!(page_heap_allocs);
 
         if align <= PAGE_SIZE {
             // Page alignment is sufficient — simple allocation
@@ -428,7 +926,11 @@ impl RtMalloc {
             }
             unsafe {
                 (*span).size_class = 0;
-                PAGE_MAP.register_span(span);
+                if !PAGE_MAP.register_span(span) {
+                    PAGE_HEAP.lock().deallocate_span(span);
+                    return ptr::null_mut();
+                }
+                set_interleave(span, interleave);
             }
             return unsafe { (*span).start_addr() };
         }
@@ -445,43 +947,100 @@ impl RtMalloc {
             return ptr::null_mut();
         }
 
-        let start_addr = unsafe { (*span).start_addr() } as usize;
-        let aligned_addr = (start_addr + align - 1) & !(align - 1);
-        let prefix_pages = (aligned_addr - start_addr) / PAGE_SIZE;
+        let start = unsafe { (*span).start_addr() };
+        let aligned = start.map_addr(|a| (a + align - 1) & !(align - 1));
+        let prefix_pages = (aligned.addr() - start.addr()) / PAGE_SIZE;
         let suffix_pages = total_pages - prefix_pages - size_pages;
 
         unsafe {
             // Clear pagemap entries for the original span
             PAGE_MAP.unregister_span(span);
 
-            // Return prefix pages to page heap
+            // Return prefix pages to page heap. Without a span struct for
+            // them they stay in the main span and the aligned pointer is
+            // interior to it, which dealloc and realloc both handle.
             if prefix_pages > 0 {
                 let prefix = span::alloc_span();
                 if !prefix.is_null() {
                     (*prefix).start_page = (*span).start_page;
                     (*prefix).num_pages = prefix_pages;
+                    (*prefix).chunk_id = (*span).chunk_id;
                     heap.deallocate_span(prefix);
+                    (*span).start_page += prefix_pages;
+                    (*span).num_pages -= prefix_pages;
                 }
             }
 
-            // Resize main span to the aligned region
-            (*span).start_page += prefix_pages;
-            (*span).num_pages = size_pages;
-            (*span).size_class = 0;
-            PAGE_MAP.register_span(span);
-
-            // Return suffix pages to page heap
+            // Return suffix pages to page heap, with the same fallback.
             if suffix_pages > 0 {
-                let suffix = span::alloc_span();
-                if !suffix.is_null() {
-                    (*suffix).start_page = (*span).start_page + size_pages;
-                    (*suffix).num_pages = suffix_pages;
-                    heap.deallocate_span(suffix);
-                }
+                heap.trim_span(span, (*span).num_pages - suffix_pages);
+            }
+
+            // Register every page of the trimmed span, so any pointer into
+            // the aligned region resolves to it.
+            (*span).size_class = 0;
+            if !PAGE_MAP.register_span(span) {
+                heap.deallocate_span(span);
+                return ptr::null_mut();
             }
+            debug_assert!((*span).contains(aligned));
         }
+        drop(heap);
+        unsafe { set_interleave(span, interleave) };
+
+        aligned
+    }
+}
 
-        aligned_addr as *mut u8
+/// Give the pages of a large span the NUMA policy its allocation asks for.
+/// Spans from the page heap come with the default policy; a parked mid-heap
+/// span keeps the one its last allocation had.
+unsafe fn set_interleave(span: *mut span::Span, interleave: bool) {
+    unsafe {
+        if (*span).interleaved == interleave {
+            return;
+        }
+        let set = platform::page_interleave((*span).start_addr(), (*span).byte_size(), interleave);
+        (*span).interleaved = interleave && set;
+    }
+}
+
+/// Free a list of `count` objects of `size_class` linked `head..=tail`.
+///
+/// The reentrancy guard is taken here rather than around `dealloc_iter`'s
+/// loop, so the caller's iterator can still allocate and free normally.
+unsafe fn free_list(size_class: usize, head: *mut FreeObject, tail: *mut FreeObject, count: usize) {
+    // A nested free cannot safely take allocator locks; leak like `dealloc`.
+    let Some(_guard) = ReentrancyGuard::enter() else {
+        return;
+    };
+    stat_add!(dealloc_count, count);
+    if caches_bypassed() {
+        unsafe {
+            CENTRAL_CACHE
+                .get(size_class)
+                .lock()
+                .insert_range(head, count, &PAGE_HEAP, &PAGE_MAP)
+        };
+        return;
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(any(feature = "percpu", feature = "nightly", feature = "std"))] {
+            unsafe {
+                TRANSFER_CACHE.insert_range(size_class, head, tail, count, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP)
+            };
+        } else {
+            let _ = tail;
+            unsafe {
+                crate::central_free_list::insert_range_dropping_lock(
+                    CENTRAL_CACHE.get(size_class),
+                    head,
+                    count,
+                    &PAGE_HEAP,
+                    &PAGE_MAP,
+                )
+            };
+        }
     }
 }
 
@@ -492,15 +1051,85 @@ unsafe impl core::alloc::Allocator for RtMalloc {
         layout: Layout,
     ) -> Result<core::ptr::NonNull<[u8]>, core::alloc::AllocError> {
         let ptr = unsafe { GlobalAlloc::alloc(self, layout) };
-        if ptr.is_null() {
-            Err(core::alloc::AllocError)
-        } else {
-            let slice = core::ptr::slice_from_raw_parts_mut(ptr, layout.size());
-            Ok(unsafe { core::ptr::NonNull::new_unchecked(slice) })
-        }
+        into_slice(ptr, layout.size())
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<core::ptr::NonNull<[u8]>, core::alloc::AllocError> {
+        let ptr = unsafe { GlobalAlloc::alloc_zeroed(self, layout) };
+        into_slice(ptr, layout.size())
     }
 
     unsafe fn deallocate(&self, ptr: core::ptr::NonNull<u8>, layout: Layout) {
         unsafe { GlobalAlloc::dealloc(self, ptr.as_ptr(), layout) }
     }
+
+    unsafe fn grow(
+        &self,
+        ptr: core::ptr::NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<core::ptr::NonNull<[u8]>, core::alloc::AllocError> {
+        unsafe { self.reallocate(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: core::ptr::NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<core::ptr::NonNull<[u8]>, core::alloc::AllocError> {
+        let new = unsafe { self.reallocate(ptr, old_layout, new_layout)? };
+        let tail = new_layout.size() - old_layout.size();
+        unsafe { ptr::write_bytes(new.cast::<u8>().as_ptr().add(old_layout.size()), 0, tail) };
+        Ok(new)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: core::ptr::NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<core::ptr::NonNull<[u8]>, core::alloc::AllocError> {
+        unsafe { self.reallocate(ptr, old_layout, new_layout) }
+    }
+}
+
+#[cfg(feature = "nightly")]
+fn into_slice(
+    ptr: *mut u8,
+    size: usize,
+) -> Result<core::ptr::NonNull<[u8]>, core::alloc::AllocError> {
+    let slice = core::ptr::slice_from_raw_parts_mut(ptr, size);
+    core::ptr::NonNull::new(slice).ok_or(core::alloc::AllocError)
+}
+
+#[cfg(feature = "nightly")]
+impl RtMalloc {
+    /// `Allocator` grow and shrink: in place through `realloc` where it can,
+    /// by copying to a fresh allocation when the alignment changes, which
+    /// `realloc` cannot express.
+    unsafe fn reallocate(
+        &self,
+        ptr: core::ptr::NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<core::ptr::NonNull<[u8]>, core::alloc::AllocError> {
+        use core::alloc::Allocator;
+
+        if old_layout.align() == new_layout.align() {
+            let new =
+                unsafe { GlobalAlloc::realloc(self, ptr.as_ptr(), old_layout, new_layout.size()) };
+            return into_slice(new, new_layout.size());
+        }
+        let new = self.allocate(new_layout)?;
+        let len = old_layout.size().min(new_layout.size());
+        unsafe {
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new.cast::<u8>().as_ptr(), len);
+            self.deallocate(ptr, old_layout);
+        }
+        Ok(new)
+    }
 }
@@ -0,0 +1,476 @@
+//! Re-entrancy guard and internal bootstrap arena.
+//!
+//! Instrumentation (histogram reports, debug logging, profiling tables) may
+//! call back into the allocator while the allocator is already running on the
+//! same thread — possibly with a size-class or page heap lock held. Taking the
+//! same lock again would deadlock, and recording the nested allocation would
+//! recurse forever.
+//!
+//! While an allocator entry point is active, a thread-local flag is set. Any
+//! allocation that observes the flag is served from a dedicated bump arena that
+//! sits on its own OS pages, never touches the page heap or the page map, and
+//! takes only its own lock. Allocator-internal structures can also use the
+//! arena directly through [`InternalAlloc`].
+//!
+//! Arena memory is never reused. Pointers handed out by the arena are not
+//! registered in the page map, so freeing them through [`RtMalloc`] is a
+//! harmless no-op. Each object is preceded by a word holding its size, so
+//! `realloc` can copy all of it, and each OS chunk starts with a header
+//! linking it into a list that [`owns`] walks without taking the arena
+//! lock. The first [`STATIC_CHUNK_SIZE`] bytes come from a static buffer in
+//! `.bss` rather than the OS, so the arena serves its first requests with
+//! no system call at all.
+//!
+//! # Startup
+//!
+//! The first allocation of a process, possibly from an `LD_PRELOAD`ed
+//! `malloc` called by the dynamic loader or a C++ static constructor before
+//! `main` and before Rust's runtime, finds everything it reaches already in
+//! place: the page heap, page map, central free lists, transfer cache and
+//! mid-heap are `const`-initialized statics, the thread cache slot is a
+//! `const` thread local without a destructor, and the only calls made are
+//! to the OS (`mmap`, `getenv`). Nothing is lazily built through `std`.
+//!
+//! The one step that can call back into the allocator is registering the
+//! thread cache's exit hook, which with `std` is a thread local with a
+//! destructor: glibc's `__cxa_thread_atexit_impl` calls `calloc`, and std's
+//! fallback on other targets grows a `Vec`. The slot is marked as being
+//! initialized while that runs, and allocations nested in it go straight to
+//! the central free lists, never to the half-registered thread cache.
+//!
+//! The guard only exists when an instrumentation feature that can allocate is
+//! enabled and a thread-local mechanism is available (`nightly` or `std`).
+//! Otherwise [`ReentrancyGuard::enter`] is a constant `Some` and optimizes away.
+//!
+//! [`RtMalloc`]: crate::RtMalloc
+
+use crate::config::PAGE_SIZE;
+use crate::platform;
+use crate::sync::SpinMutex;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// Size of each arena chunk requested from the OS.
+const CHUNK_SIZE: usize = 16 * PAGE_SIZE;
+
+/// Size of the static first chunk.
+pub const STATIC_CHUNK_SIZE: usize = 16 << 10;
+
+/// The first chunk, served before any is requested from the OS.
+#[repr(C, align(4096))]
+struct StaticChunk(core::cell::UnsafeCell<[u8; STATIC_CHUNK_SIZE]>);
+
+// SAFETY: only handed out piecewise by the arena, under its lock.
+unsafe impl Sync for StaticChunk {}
+
+static STATIC_CHUNK: StaticChunk = StaticChunk(core::cell::UnsafeCell::new([0; STATIC_CHUNK_SIZE]));
+
+/// Start of every OS chunk: its end and the chunk taken before it.
+#[repr(C)]
+struct ChunkHeader {
+    next: *const ChunkHeader,
+    end: usize,
+}
+
+const HEADER_SIZE: usize = core::mem::size_of::<ChunkHeader>();
+
+/// Word before every arena object, holding its size.
+const SIZE_WORD: usize = core::mem::size_of::<usize>();
+
+/// OS chunks of the arena, newest first. Pushed under the arena lock and
+/// never popped or unmapped, so readers walk it without the lock.
+static CHUNKS: AtomicPtr<ChunkHeader> = AtomicPtr::new(ptr::null_mut());
+
+cfg_if::cfg_if! {
+    if #[cfg(all(
+        any(
+            feature = "alloc-histogram",
+            feature = "debug",
+            feature = "stats",
+            feature = "tracing"
+        ),
+        any(feature = "nightly", feature = "std")
+    ))] {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "nightly")] {
+                #[thread_local]
+                static mut IN_ALLOCATOR: bool = false;
+
+                #[inline(always)]
+                fn get_flag() -> bool {
+                    unsafe { *ptr::addr_of!(IN_ALLOCATOR) }
+                }
+
+                #[inline(always)]
+                fn set_flag(val: bool) {
+                    unsafe { *ptr::addr_of_mut!(IN_ALLOCATOR) = val };
+                }
+            } else {
+                std::thread_local! {
+                    static IN_ALLOCATOR: core::cell::Cell<bool> = const { core::cell::Cell::new(false) };
+                }
+
+                // The slot has no destructor, so it stays accessible for the
+                // whole thread lifetime. Treat access failure as "not inside".
+                #[inline(always)]
+                fn get_flag() -> bool {
+                    IN_ALLOCATOR.try_with(|c| c.get()).unwrap_or(false)
+                }
+
+                #[inline(always)]
+                fn set_flag(val: bool) {
+                    let _ = IN_ALLOCATOR.try_with(|c| c.set(val));
+                }
+            }
+        }
+
+        /// Marks the current thread as running inside the allocator.
+        ///
+        /// Obtained with [`ReentrancyGuard::enter`]; clears the flag on drop.
+        pub struct ReentrancyGuard(());
+
+        impl ReentrancyGuard {
+            /// Enter the allocator on this thread.
+            ///
+            /// Returns `None` if the thread is already inside the allocator, in
+            /// which case the caller must not take any allocator lock and should
+            /// use the bootstrap arena instead.
+            #[inline(always)]
+            pub fn enter() -> Option<Self> {
+                if get_flag() {
+                    None
+                } else {
+                    set_flag(true);
+                    Some(Self(()))
+                }
+            }
+        }
+
+        impl Drop for ReentrancyGuard {
+            #[inline(always)]
+            fn drop(&mut self) {
+                set_flag(false);
+                // Out of the allocator: queued events can be emitted now.
+                #[cfg(feature = "tracing")]
+                crate::tracing_events::flush();
+            }
+        }
+
+        /// Whether the current thread is inside an allocator entry point.
+        #[inline]
+        pub fn in_allocator() -> bool {
+            get_flag()
+        }
+    } else {
+        /// No-op guard: no instrumentation can allocate in this configuration.
+        pub struct ReentrancyGuard(());
+
+        impl ReentrancyGuard {
+            #[inline(always)]
+            pub fn enter() -> Option<Self> {
+                Some(Self(()))
+            }
+        }
+
+        #[inline(always)]
+        pub fn in_allocator() -> bool {
+            false
+        }
+    }
+}
+
+/// Bump allocator over dedicated OS chunks.
+struct Arena {
+    /// Current bump pointer within the active chunk.
+    bump_ptr: *mut u8,
+    /// End of the active chunk.
+    bump_end: usize,
+    /// Total bytes obtained from the OS for the arena.
+    reserved: usize,
+    /// Whether the static chunk has been taken as the active chunk.
+    static_used: bool,
+}
+
+// SAFETY: Arena is only accessed through a SpinMutex, and its chunks are
+// never returned to the OS.
+unsafe impl Send for Arena {}
+
+impl Arena {
+    const fn new() -> Self {
+        Self {
+            bump_ptr: ptr::null_mut(),
+            bump_end: 0,
+            reserved: 0,
+            static_used: false,
+        }
+    }
+
+    /// Map a chunk of `size` bytes and link it into [`CHUNKS`]. Returns the
+    /// first byte past its header, or null on OOM.
+    unsafe fn new_chunk(&mut self, size: usize) -> *mut u8 {
+        let chunk = unsafe { platform::page_alloc(size) };
+        if chunk.is_null() {
+            return ptr::null_mut();
+        }
+        let header = chunk.cast::<ChunkHeader>();
+        unsafe {
+            header.write(ChunkHeader {
+                next: CHUNKS.load(Ordering::Relaxed),
+                end: chunk.addr() + size,
+            })
+        };
+        CHUNKS.store(header, Ordering::Release);
+        self.reserved += size;
+        chunk.wrapping_add(HEADER_SIZE)
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let size = layout.size().max(1);
+        let align = layout.align().max(SIZE_WORD);
+
+        let aligned = place(self.bump_ptr, align);
+        if !self.bump_ptr.is_null() && aligned.addr() + size <= self.bump_end {
+            self.bump_ptr = aligned.wrapping_add(size);
+            return unsafe { with_size(aligned, size) };
+        }
+
+        // Oversized or over-aligned requests get a dedicated chunk so the
+        // active chunk's tail is not wasted.
+        let padded = HEADER_SIZE + SIZE_WORD + size + align - 1;
+        if padded > CHUNK_SIZE / 2 {
+            let start = unsafe { self.new_chunk(padded.div_ceil(PAGE_SIZE) * PAGE_SIZE) };
+            if start.is_null() {
+                return ptr::null_mut();
+            }
+            return unsafe { with_size(place(start, align), size) };
+        }
+
+        if !self.static_used {
+            self.static_used = true;
+            self.bump_ptr = STATIC_CHUNK.0.get().cast();
+            self.bump_end = self.bump_ptr.addr() + STATIC_CHUNK_SIZE;
+            return unsafe { self.alloc(layout) };
+        }
+
+        let start = unsafe { self.new_chunk(CHUNK_SIZE) };
+        if start.is_null() {
+            return ptr::null_mut();
+        }
+        self.bump_ptr = start;
+        self.bump_end = start.addr() - HEADER_SIZE + CHUNK_SIZE;
+
+        // Page-aligned fresh chunk: this cannot fail for padded <= CHUNK_SIZE / 2.
+        unsafe { self.alloc(layout) }
+    }
+}
+
+/// First address at or after `free` aligned to `align` with room for the
+/// size word before it.
+fn place(free: *mut u8, align: usize) -> *mut u8 {
+    free.map_addr(|a| (a + SIZE_WORD + align - 1) & !(align - 1))
+}
+
+/// Record `size` in the word before `obj` and return `obj`.
+unsafe fn with_size(obj: *mut u8, size: usize) -> *mut u8 {
+    unsafe { obj.sub(SIZE_WORD).cast::<usize>().write(size) };
+    obj
+}
+
+static ARENA: SpinMutex<Arena> = SpinMutex::new(Arena::new());
+
+/// Allocate from the bootstrap arena. Returns zeroed memory or null on OOM.
+///
+/// # Safety
+///
+/// `layout.align()` must be a power of two (guaranteed by `Layout`).
+pub unsafe fn alloc(layout: Layout) -> *mut u8 {
+    unsafe { ARENA.lock().alloc(layout) }
+}
+
+/// Bytes reserved from the OS by the bootstrap arena so far.
+pub fn reserved_bytes() -> usize {
+    ARENA.lock().reserved
+}
+
+/// Whether `ptr` points into the static chunk or one of the arena's OS
+/// chunks. Lock-free, so it is safe to call with allocator locks held.
+pub fn owns(ptr: *const u8) -> bool {
+    let first = STATIC_CHUNK.0.get().addr();
+    if (first..first + STATIC_CHUNK_SIZE).contains(&ptr.addr()) {
+        return true;
+    }
+    let mut chunk = CHUNKS.load(Ordering::Acquire);
+    while !chunk.is_null() {
+        // SAFETY: headers are written before they are published and chunks
+        // are never unmapped.
+        let header = unsafe { &*chunk };
+        if (chunk.addr()..header.end).contains(&ptr.addr()) {
+            return true;
+        }
+        chunk = header.next.cast_mut();
+    }
+    false
+}
+
+/// Size `ptr` was allocated with.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by the arena.
+pub unsafe fn allocation_size(ptr: *const u8) -> usize {
+    unsafe { ptr.sub(SIZE_WORD).cast::<usize>().read() }
+}
+
+/// Allocator handle for allocator-internal structures.
+///
+/// Serves every request from the bootstrap arena, so it is safe to use while
+/// allocator locks are held. Deallocation is a no-op; use it only for
+/// structures that are long-lived or bounded in size.
+pub struct InternalAlloc;
+
+unsafe impl GlobalAlloc for InternalAlloc {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { alloc(layout) }
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+}
+
+#[cfg(feature = "nightly")]
+unsafe impl core::alloc::Allocator for InternalAlloc {
+    fn allocate(
+        &self,
+        layout: Layout,
+    ) -> Result<core::ptr::NonNull<[u8]>, core::alloc::AllocError> {
+        let ptr = unsafe { alloc(layout) };
+        if ptr.is_null() {
+            Err(core::alloc::AllocError)
+        } else {
+            let slice = core::ptr::slice_from_raw_parts_mut(ptr, layout.size());
+            Ok(unsafe { core::ptr::NonNull::new_unchecked(slice) })
+        }
+    }
+
+    unsafe fn deallocate(&self, _ptr: core::ptr::NonNull<u8>, _layout: Layout) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arena_alignment() {
+        for align in [1, 8, 16, 64, 4096, 2 * PAGE_SIZE] {
+            let layout = Layout::from_size_align(24, align).unwrap();
+            let p = unsafe { alloc(layout) };
+            assert!(!p.is_null());
+            assert_eq!(p as usize % align, 0, "align {align}");
+            unsafe { p.write_bytes(0xAB, 24) };
+        }
+    }
+
+    #[test]
+    fn test_arena_large_request() {
+        let layout = Layout::from_size_align(CHUNK_SIZE * 2, 8).unwrap();
+        let p = unsafe { alloc(layout) };
+        assert!(!p.is_null());
+        unsafe {
+            *p = 1;
+            *p.add(CHUNK_SIZE * 2 - 1) = 2;
+        }
+    }
+
+    #[test]
+    fn test_arena_not_in_pagemap() {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let p = unsafe { alloc(layout) };
+        assert!(!p.is_null());
+        let page_id = (p as usize) >> crate::config::PAGE_SHIFT;
+        assert!(crate::allocator::PAGE_MAP.get(page_id).is_null());
+    }
+
+    #[test]
+    fn test_arena_owns() {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let p = unsafe { alloc(layout) };
+        assert!(owns(p));
+        assert!(owns(unsafe { p.add(63) }));
+        let big = Layout::from_size_align(CHUNK_SIZE * 2, 8).unwrap();
+        let q = unsafe { alloc(big) };
+        assert!(owns(q));
+        assert!(!owns(core::ptr::null()));
+        assert!(!owns(ptr::without_provenance(usize::MAX)));
+    }
+
+    #[test]
+    fn test_owns_only_chunks() {
+        // A mapping taken between two arena chunks is not the arena's.
+        let big = Layout::from_size_align(CHUNK_SIZE, 8).unwrap();
+        let a = unsafe { alloc(big) };
+        let other = unsafe { platform::page_alloc(PAGE_SIZE) };
+        let b = unsafe { alloc(big) };
+        assert!(owns(a) && owns(b));
+        assert!(!other.is_null());
+        assert!(!owns(other));
+        unsafe { platform::page_dealloc(other, PAGE_SIZE) };
+    }
+
+    #[test]
+    fn test_allocation_size() {
+        for (size, align) in [(1, 1), (24, 8), (100, 64), (CHUNK_SIZE * 2, 8)] {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let p = unsafe { alloc(layout) };
+            assert_eq!(unsafe { allocation_size(p) }, size);
+        }
+    }
+
+    #[test]
+    fn test_resize_copies_whole_object() {
+        let layout = Layout::from_size_align(1000, 8).unwrap();
+        let p = unsafe { alloc(layout) };
+        for i in 0..1000 {
+            unsafe { p.add(i).write(i as u8) };
+        }
+        let min = Layout::from_size_align(16, 16).unwrap();
+        let q = unsafe { crate::RtMalloc.resize(p, min, 5000) };
+        assert!(!q.is_null());
+        for i in 0..1000 {
+            assert_eq!(unsafe { q.add(i).read() }, i as u8, "byte {i}");
+        }
+        unsafe { crate::RtMalloc.dealloc(q, Layout::from_size_align(5000, 16).unwrap()) };
+    }
+
+    #[test]
+    fn test_static_chunk_first() {
+        // A second arena over the same static chunk as the global one, so
+        // only addresses are compared, nothing is written.
+        let mut arena = Arena::new();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let p = unsafe { arena.alloc(layout) };
+        let first = STATIC_CHUNK.0.get().addr();
+        assert!((first..first + STATIC_CHUNK_SIZE).contains(&p.addr()));
+        assert_eq!(arena.reserved, 0);
+        // Past the static chunk, the OS takes over.
+        let mut q = p;
+        for _ in 0..STATIC_CHUNK_SIZE / 64 {
+            q = unsafe { arena.alloc(layout) };
+        }
+        assert!(!(first..first + STATIC_CHUNK_SIZE).contains(&q.addr()));
+        assert_eq!(arena.reserved, CHUNK_SIZE);
+    }
+
+    #[test]
+    fn test_guard_nesting() {
+        {
+            let outer = ReentrancyGuard::enter();
+            assert!(outer.is_some());
+            let inner = ReentrancyGuard::enter();
+            assert_eq!(inner.is_none(), in_allocator());
+        }
+        assert!(!in_allocator());
+        assert!(ReentrancyGuard::enter().is_some());
+    }
+}
@@ -0,0 +1,246 @@
+//! Host calibration (`std` only).
+//!
+//! [`calibrate`] times a few short workloads on the running machine and
+//! tunes the knobs that can change at runtime:
+//!
+//! - Batch sizes. A batch round trip through a central free list costs a
+//!   fixed amount (locks, span lookups) plus a little per object. Batches
+//!   are sized so the fixed part, spread over the batch, stays under a
+//!   twentieth of a fast-path alloc/free pair. They can only shrink from the
+//!   built table.
+//! - The overall thread cache budget. When a hand-off between threads costs
+//!   much more than a local alloc/free, caches are given twice the configured
+//!   budget so fewer objects bounce through the central lists; when it is
+//!   nearly as cheap, half.
+//!
+//! Per-CPU slab capacities are fixed when the slabs are laid out, so with
+//! `percpu` calibration changes only how many objects each refill and drain
+//! moves.
+//!
+//! It takes a few milliseconds. Call it once at startup; later calls return
+//! the stored result without measuring again. [`calibration`] (also
+//! `stats::calibration` with `stats`) shows what was measured and chosen, and
+//! [`apply`] reuses numbers saved from an earlier run instead of measuring.
+//!
+//! ```ignore
+//! let c = rtmalloc::calibrate();
+//! println!("fast path {:.1} ns, budget {} bytes", c.local_ns, c.thread_cache_budget);
+//! ```
+
+use crate::allocator::{CENTRAL_CACHE, PAGE_HEAP, PAGE_MAP, RtMalloc};
+use crate::bootstrap::ReentrancyGuard;
+use crate::central_free_list;
+use crate::config::{MIN_PER_THREAD_CACHE_SIZE, OVERALL_THREAD_CACHE_SIZE};
+use crate::size_class::{self, MAX_SMALL_SIZE, NUM_SIZE_CLASSES};
+use crate::thread_cache;
+use core::alloc::{GlobalAlloc, Layout};
+use core::hint::black_box;
+use std::sync::Mutex;
+use std::time::Instant;
+use std::vec::Vec;
+
+/// Sizes whose classes are timed. Other classes use the nearest one below.
+const KEY_SIZES: [usize; 5] = [16, 64, 256, 1024, 4096];
+/// Alloc/free pairs timed on the fast path, per key class.
+const LOCAL_ROUNDS: usize = 20_000;
+/// Central free list round trips timed per key class and batch size.
+const CENTRAL_ROUNDS: usize = 200;
+/// Objects handed from one thread to another.
+const HANDOFF_OBJECTS: usize = 4096;
+/// Fixed batch cost, spread over the batch, allowed per fast-path pair.
+const OVERHEAD_SHARE: f64 = 0.05;
+/// Timings are the best of this many trials, to shed page faults and
+/// preemption.
+const TRIALS: usize = 3;
+
+/// What [`calibrate`] measured and chose.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    /// Nanoseconds per alloc/free pair served by the front-end cache.
+    pub local_ns: f64,
+    /// Fixed nanoseconds per batch round trip through a central free list.
+    pub central_batch_ns: f64,
+    /// Nanoseconds per object allocated on one thread and freed on another.
+    pub handoff_ns: f64,
+    /// Batch size chosen for each size class (index 0 unused).
+    pub batch_sizes: [u32; NUM_SIZE_CLASSES],
+    /// Overall thread cache budget chosen, in bytes.
+    pub thread_cache_budget: usize,
+}
+
+static CALIBRATION: Mutex<Option<Calibration>> = Mutex::new(None);
+
+/// Measure this host and tune batch sizes and the thread cache budget.
+///
+/// Returns the stored result if calibration already ran or [`apply`] was
+/// called.
+pub fn calibrate() -> Calibration {
+    let mut stored = CALIBRATION.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(c) = *stored {
+        return c;
+    }
+    let c = tune(measure());
+    *stored = Some(c);
+    c
+}
+
+/// The stored calibration, if [`calibrate`] or [`apply`] ran.
+pub fn calibration() -> Option<Calibration> {
+    *CALIBRATION.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Apply a calibration saved from an earlier run and store it, as clamped
+/// to what this build allows.
+pub fn apply(c: &Calibration) -> Calibration {
+    let mut applied = *c;
+    for cls in 1..NUM_SIZE_CLASSES {
+        applied.batch_sizes[cls] =
+            size_class::set_batch_size(cls, c.batch_sizes[cls] as usize) as u32;
+    }
+    applied.thread_cache_budget = thread_cache::set_overall_cache_size(c.thread_cache_budget);
+    *CALIBRATION.lock().unwrap_or_else(|e| e.into_inner()) = Some(applied);
+    applied
+}
+
+/// Per key class timings, plus the hand-off time.
+struct Measurements {
+    /// (size class, ns per fast-path pair, fixed ns per central round trip)
+    classes: Vec<(usize, f64, f64)>,
+    handoff_ns: f64,
+}
+
+fn measure() -> Measurements {
+    let classes = KEY_SIZES
+        .iter()
+        .filter(|&&size| size <= MAX_SMALL_SIZE)
+        .map(|&size| {
+            let cls = size_class::size_to_class(size);
+            (cls, measure_local(size), measure_central_fixed(cls))
+        })
+        .collect();
+    Measurements {
+        classes,
+        handoff_ns: measure_handoff(64),
+    }
+}
+
+fn tune(m: Measurements) -> Calibration {
+    let mut batch_sizes = [0u32; NUM_SIZE_CLASSES];
+    for (cls, batch) in batch_sizes.iter_mut().enumerate().skip(1) {
+        let size = size_class::class_to_size(cls);
+        let &(_, local, fixed) = m
+            .classes
+            .iter()
+            .rev()
+            .find(|&&(key, _, _)| size_class::class_to_size(key) <= size)
+            .unwrap_or(&m.classes[0]);
+        let wanted = (fixed / (local * OVERHEAD_SHARE).max(f64::MIN_POSITIVE)).ceil() as usize;
+        *batch = size_class::set_batch_size(cls, wanted) as u32;
+    }
+
+    let n = m.classes.len() as f64;
+    let local_ns = m.classes.iter().map(|c| c.1).sum::<f64>() / n;
+    let central_batch_ns = m.classes.iter().map(|c| c.2).sum::<f64>() / n;
+
+    let ratio = m.handoff_ns / local_ns.max(f64::MIN_POSITIVE);
+    let budget = if ratio > 4.0 {
+        OVERALL_THREAD_CACHE_SIZE * 2
+    } else if ratio < 1.5 {
+        OVERALL_THREAD_CACHE_SIZE / 2
+    } else {
+        OVERALL_THREAD_CACHE_SIZE
+    };
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let budget = budget.max(MIN_PER_THREAD_CACHE_SIZE * threads);
+
+    Calibration {
+        local_ns,
+        central_batch_ns,
+        handoff_ns: m.handoff_ns,
+        batch_sizes,
+        thread_cache_budget: thread_cache::set_overall_cache_size(budget),
+    }
+}
+
+/// Nanoseconds per alloc/free pair of `size` bytes on a warm cache.
+fn measure_local(size: usize) -> f64 {
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    let round = || unsafe {
+        let p = RtMalloc.alloc(layout);
+        if !p.is_null() {
+            RtMalloc.dealloc(black_box(p), layout);
+        }
+    };
+    for _ in 0..LOCAL_ROUNDS / 10 {
+        round();
+    }
+    best_of(|| {
+        let start = Instant::now();
+        for _ in 0..LOCAL_ROUNDS {
+            round();
+        }
+        start.elapsed().as_nanos() as f64 / LOCAL_ROUNDS as f64
+    })
+}
+
+/// Fixed cost of a batch round trip through the central free list of `cls`:
+/// the time at one object less the per-object part seen at a full batch.
+fn measure_central_fixed(cls: usize) -> f64 {
+    let full = size_class::class_info(cls).batch_size;
+    let one = time_central(cls, 1);
+    if full <= 1 {
+        return one;
+    }
+    let per_object = ((time_central(cls, full) - one) / (full - 1) as f64).max(0.0);
+    (one - per_object).max(0.0)
+}
+
+/// Nanoseconds per remove/insert round trip of `count` objects.
+fn time_central(cls: usize, count: usize) -> f64 {
+    let Some(_guard) = ReentrancyGuard::enter() else {
+        return 0.0;
+    };
+    let list = CENTRAL_CACHE.get(cls);
+    let round = || unsafe {
+        let (n, head, _) =
+            central_free_list::remove_range_dropping_lock(list, cls, count, &PAGE_HEAP, &PAGE_MAP);
+        if n > 0 {
+            central_free_list::insert_range_dropping_lock(list, head, n, &PAGE_HEAP, &PAGE_MAP);
+        }
+    };
+    round();
+    best_of(|| {
+        let start = Instant::now();
+        for _ in 0..CENTRAL_ROUNDS {
+            round();
+        }
+        start.elapsed().as_nanos() as f64 / CENTRAL_ROUNDS as f64
+    })
+}
+
+fn best_of(mut trial: impl FnMut() -> f64) -> f64 {
+    (0..TRIALS).map(|_| trial()).fold(f64::INFINITY, f64::min)
+}
+
+/// Nanoseconds per object of `size` bytes allocated on a fresh thread and
+/// freed on this one.
+fn measure_handoff(size: usize) -> f64 {
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    let Ok((ptrs, alloc_ns)) = std::thread::spawn(move || {
+        let mut ptrs = Vec::with_capacity(HANDOFF_OBJECTS);
+        let start = Instant::now();
+        for _ in 0..HANDOFF_OBJECTS {
+            ptrs.push(unsafe { RtMalloc.alloc(layout) }.expose_provenance());
+        }
+        (ptrs, start.elapsed().as_nanos())
+    })
+    .join() else {
+        return 0.0;
+    };
+
+    let start = Instant::now();
+    for &p in ptrs.iter().filter(|&&p| p != 0) {
+        unsafe { RtMalloc.dealloc(std::ptr::with_exposed_provenance_mut(p), layout) };
+    }
+    (alloc_ns + start.elapsed().as_nanos()) as f64 / HANDOFF_OBJECTS as f64
+}
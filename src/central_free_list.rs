@@ -5,24 +5,122 @@
 //! When the central free list is empty, it requests a new span from the page heap
 //! and carves it into objects.
 
-use crate::config::{PAGE_SHIFT, PAGE_SIZE};
+use crate::class_cap;
+use crate::config::{
+    MAX_OBJECTS_PER_LOCK, MAX_RETAINED_SPANS, NUM_ARENAS, PAGE_SHIFT, PAGE_SIZE, SPAN_PAGES_SCALE,
+};
 use crate::page_heap::PageHeap;
 use crate::pagemap::PageMap;
-use crate::size_class::{self, NUM_SIZE_CLASSES};
+use crate::platform;
+use crate::size_class::{self, NUM_SIZE_CLASSES, ReuseOrder};
 use crate::span::{FreeObject, Span, SpanList, SpanState};
-use crate::sync::SpinMutex;
+use crate::sync::{CachePadded, SpinMutex};
 use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
 #[cfg(feature = "debug")]
 use std::println;
 
+/// Number of fullness buckets for spans with free objects.
+const NUM_FULLNESS_LISTS: usize = 8;
+
+/// Spins between looks at a list another thread is populating.
+const POPULATE_WAIT_SPINS: usize = 64;
+
+/// Span pool of a central free list, by how long its objects are expected
+/// to live.
+///
+/// Long-lived objects scattered over the spans short-lived ones churn
+/// through keep those spans from ever draining. Each list therefore keeps
+/// its tenured spans apart: they are carved only for allocations hinted
+/// [`COLD`](crate::hint::COLD), so nursery spans hold short-lived objects
+/// alone and go back to the page heap as soon as a burst of them is freed.
+/// A span keeps its pool until it leaves the list; objects freed back to
+/// it re-file it in that pool, whichever path they took. `minimal` builds
+/// keep a single pool, so tenured allocations share the nursery's spans.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Age {
+    /// The normal allocation path.
+    Nursery = 0,
+    /// Allocations expected to live long.
+    Tenured = 1,
+}
+
+/// Pools each list keeps: one per [`Age`], or one for both under
+/// `minimal`, whose `CentralCache` has a footprint budget.
+const NUM_POOLS: usize = if cfg!(feature = "minimal") { 1 } else { 2 };
+
+/// Pool serving `age`.
+#[inline]
+fn pool(age: Age) -> usize {
+    if NUM_POOLS == 1 { 0 } else { age as usize }
+}
+
+/// Pool `span` was carved for.
+///
+/// # Safety
+///
+/// `span` must be valid.
+#[inline]
+unsafe fn span_age(span: *mut Span) -> usize {
+    unsafe { (*span).tenured as usize }
+}
+
 /// Central free list for a single size class.
+///
+/// Spans with free objects are bucketed by how full they are, and allocation
+/// always takes from the fullest bucket. Sparsely used spans are then left
+/// alone, drain to zero, and go back to the page heap instead of every span
+/// staying a little bit used.
+///
+/// Up to `retain_target` completely free spans stay linked instead of going
+/// back to the page heap. The target adapts: it grows by one each time the
+/// list has to populate after having released a span (the class is
+/// oscillating across a span boundary), and shrinks by one for every release
+/// beyond the target without an intervening populate (the class is really
+/// shrinking). It stays within `1..=MAX_RETAINED_SPANS`.
+///
+/// Within a span, freed objects go to the front of its free list, or to the
+/// back when the class's [`ReuseOrder`] is FIFO.
 pub struct CentralFreeList {
     /// Size class index this list manages.
     size_class: usize,
-    /// Spans that have free objects available.
-    nonempty_spans: SpanList,
+    /// Arena this list carves spans for, recorded in each span.
+    arena: u8,
+    /// Spans that have free objects available, per [`Age`] and indexed by
+    /// [`fullness_bucket`]; higher index means fuller.
+    nonempty_spans: [[SpanList; NUM_FULLNESS_LISTS]; NUM_POOLS],
+    /// Number of spans across all `nonempty_spans` buckets of each pool.
+    num_nonempty: [usize; NUM_POOLS],
     /// Total number of free objects across all spans.
     num_free: usize,
+    /// Linked spans with no allocated objects.
+    num_empty: usize,
+    /// Empty spans to keep before returning them to the page heap.
+    retain_target: usize,
+    /// Spans returned to the page heap since the last populate.
+    released_since_populate: usize,
+    /// A thread has dropped the lock to fetch a span for this list. Others
+    /// that find it empty wait for that span instead of fetching their own.
+    populating: bool,
+}
+
+/// Fullness bucket of a span that has at least one free object.
+#[inline]
+fn fullness_bucket(allocated: u32, total: u32) -> usize {
+    if total == 0 {
+        return 0;
+    }
+    ((allocated as usize * NUM_FULLNESS_LISTS) / total as usize).min(NUM_FULLNESS_LISTS - 1)
+}
+
+/// Fullness bucket of `span` from its current counts.
+///
+/// # Safety
+///
+/// `span` must be valid.
+#[inline]
+unsafe fn span_bucket(span: *mut Span) -> usize {
+    unsafe { fullness_bucket((*span).allocated_count, (*span).total_count) }
 }
 
 // SAFETY: Only accessed through external SpinMutex synchronization.
@@ -30,16 +128,222 @@ unsafe impl Send for CentralFreeList {}
 
 impl CentralFreeList {
     pub const fn new(size_class: usize) -> Self {
+        Self::in_arena(size_class, 0)
+    }
+
+    /// A list for `size_class` whose spans belong to `arena`.
+    pub const fn in_arena(size_class: usize, arena: u8) -> Self {
         Self {
             size_class,
-            nonempty_spans: SpanList::new(),
+            arena,
+            nonempty_spans: [const { [const { SpanList::new() }; NUM_FULLNESS_LISTS] }; NUM_POOLS],
+            num_nonempty: [0; NUM_POOLS],
             num_free: 0,
+            num_empty: 0,
+            retain_target: 1,
+            released_since_populate: 0,
+            populating: false,
+        }
+    }
+
+    /// Free objects across all of this list's spans.
+    pub fn free_objects(&self) -> usize {
+        self.num_free
+    }
+
+    /// Spans with free objects, nursery then tenured, emptiest first. Full
+    /// spans are not linked anywhere and are not included.
+    ///
+    /// # Safety
+    ///
+    /// The spans must be valid; holding the list's lock keeps them so.
+    pub unsafe fn spans(&self) -> impl Iterator<Item = *mut Span> + '_ {
+        self.nonempty_spans
+            .iter()
+            .flatten()
+            .flat_map(|list| unsafe { list.spans() })
+    }
+
+    /// Number of spans with free objects in the `age` pool.
+    pub fn nonempty_spans(&self, age: Age) -> usize {
+        self.num_nonempty[pool(age)]
+    }
+
+    /// Number of completely free spans currently kept by this list.
+    pub fn retained_spans(&self) -> usize {
+        self.num_empty
+    }
+
+    /// Number of completely free spans this list currently keeps before
+    /// returning more to the page heap.
+    pub fn retain_target(&self) -> usize {
+        self.retain_target
+    }
+
+    /// A span was taken from the page heap. Needing one right after giving
+    /// one back means the target is too low.
+    fn note_populate(&mut self) {
+        if self.released_since_populate > 0 && self.retain_target < MAX_RETAINED_SPANS {
+            self.retain_target += 1;
+        }
+        self.released_since_populate = 0;
+        #[cfg(feature = "stats")]
+        crate::stats::record_span_populate(self.size_class);
+    }
+
+    /// A span went back to the page heap. A run of releases longer than the
+    /// target without a populate means the class is shrinking.
+    fn note_release(&mut self) {
+        self.released_since_populate += 1;
+        if self.released_since_populate > self.retain_target && self.retain_target > 1 {
+            self.retain_target -= 1;
+        }
+        #[cfg(feature = "stats")]
+        crate::stats::record_span_release(self.size_class);
+    }
+
+    /// Whether no span of the `age` pool has a free object.
+    #[inline]
+    fn is_empty(&self, age: Age) -> bool {
+        self.num_nonempty[pool(age)] == 0
+    }
+
+    /// The fullest span of the `age` pool that still has free objects, or
+    /// null.
+    #[inline]
+    fn fullest_span(&self, age: Age) -> *mut Span {
+        for list in self.nonempty_spans[pool(age)].iter().rev() {
+            if !list.is_empty() {
+                return list.head;
+            }
+        }
+        ptr::null_mut()
+    }
+
+    /// Add a span with free objects to the bucket of its pool matching its
+    /// fullness.
+    unsafe fn link_span(&mut self, span: *mut Span) {
+        let age = unsafe { span_age(span) };
+        unsafe { self.nonempty_spans[age][span_bucket(span)].push(span) };
+        self.num_nonempty[age] += 1;
+    }
+
+    /// Remove a span from `bucket` of its pool.
+    unsafe fn unlink_span(&mut self, span: *mut Span, bucket: usize) {
+        let age = unsafe { span_age(span) };
+        unsafe { self.nonempty_spans[age][bucket].remove(span) };
+        self.num_nonempty[age] -= 1;
+    }
+
+    /// Pop up to `want` objects from `span` onto `head`, then re-file the span
+    /// under its new fullness (or drop it from the lists if it is now full).
+    /// Returns the number of objects taken.
+    unsafe fn take_from_span(
+        &mut self,
+        span: *mut Span,
+        want: usize,
+        head: &mut *mut FreeObject,
+        tail: &mut *mut FreeObject,
+    ) -> usize {
+        let mut taken = 0;
+        unsafe {
+            let bucket = span_bucket(span);
+            if (*span).allocated_count == 0 {
+                self.num_empty -= 1;
+            }
+            while taken < want && !(*span).freelist.is_null() {
+                let obj = (*span).freelist;
+                (*span).freelist = FreeObject::next(obj);
+                FreeObject::set_next(obj, *head);
+                if tail.is_null() {
+                    *tail = obj; // First object taken ends the list
+                }
+                *head = obj;
+                (*span).allocated_count += 1;
+                taken += 1;
+            }
+            self.num_free -= taken;
+
+            if (*span).freelist.is_null() {
+                (*span).freelist_tail = ptr::null_mut();
+                self.unlink_span(span, bucket);
+            } else if span_bucket(span) != bucket {
+                self.unlink_span(span, bucket);
+                self.link_span(span);
+            }
+        }
+        taken
+    }
+
+    /// Return `obj` to its span's free list, at the end `order` picks, and
+    /// re-file the span.
+    ///
+    /// Returns true if the span became completely free and was unlinked; the
+    /// caller must hand it back to the page heap. Up to `retain_target` empty
+    /// spans are kept to avoid populate/return churn.
+    unsafe fn return_object(
+        &mut self,
+        span: *mut Span,
+        obj: *mut FreeObject,
+        order: ReuseOrder,
+    ) -> bool {
+        unsafe {
+            let was_full = (*span).freelist.is_null();
+            let bucket = span_bucket(span);
+
+            if was_full {
+                FreeObject::set_next(obj, ptr::null_mut());
+                (*span).freelist = obj;
+                (*span).freelist_tail = obj;
+            } else if order == ReuseOrder::Fifo {
+                FreeObject::set_next(obj, ptr::null_mut());
+                FreeObject::set_next((*span).freelist_tail, obj);
+                (*span).freelist_tail = obj;
+            } else {
+                FreeObject::set_next(obj, (*span).freelist);
+                (*span).freelist = obj;
+            }
+            (*span).allocated_count -= 1;
+            self.num_free += 1;
+
+            if was_full {
+                self.link_span(span);
+            } else if span_bucket(span) != bucket {
+                self.unlink_span(span, bucket);
+                self.link_span(span);
+            }
+
+            if (*span).allocated_count == 0 {
+                self.num_empty += 1;
+                if self.num_empty > self.retain_target {
+                    self.num_empty -= 1;
+                    self.unlink_span(span, span_bucket(span));
+                    self.num_free -= (*span).total_count as usize;
+                    (*span).freelist = ptr::null_mut();
+                    (*span).freelist_tail = ptr::null_mut();
+                    self.note_release();
+                    class_cap::uncharge(self.size_class, (*span).num_pages * PAGE_SIZE);
+                    #[cfg(feature = "usdt")]
+                    crate::usdt::span_release(
+                        self.size_class,
+                        (*span).start_addr().addr(),
+                        (*span).num_pages * PAGE_SIZE,
+                    );
+                    #[cfg(feature = "tracing")]
+                    crate::tracing_events::record(crate::tracing_events::Event::SpanRelease {
+                        size_class: self.size_class,
+                        bytes: (*span).num_pages * PAGE_SIZE,
+                    });
+                    return true;
+                }
+            }
         }
+        false
     }
 
     /// Remove up to `batch_size` objects from this central free list.
-    /// Returns (count, head_of_linked_list).
-    /// If the list is empty, fetches a new span from the page heap.
+    /// Returns (count, head, tail) of a linked list; both ends are null when
+    /// count is 0. If the list is empty, fetches a new span from the page heap.
     ///
     /// # Safety
     ///
@@ -50,37 +354,39 @@ impl CentralFreeList {
         batch_size: usize,
         page_heap: &SpinMutex<PageHeap>,
         pagemap: &PageMap,
-    ) -> (usize, *mut FreeObject) {
+    ) -> (usize, *mut FreeObject, *mut FreeObject) {
+        unsafe { self.remove_range_aged(batch_size, Age::Nursery, page_heap, pagemap) }
+    }
+
+    /// [`remove_range`](Self::remove_range) from the `age` pool's spans.
+    ///
+    /// # Safety
+    ///
+    /// As for [`remove_range`](Self::remove_range).
+    pub unsafe fn remove_range_aged(
+        &mut self,
+        batch_size: usize,
+        age: Age,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
+    ) -> (usize, *mut FreeObject, *mut FreeObject) {
         let mut head: *mut FreeObject = ptr::null_mut();
+        let mut tail: *mut FreeObject = ptr::null_mut();
         let mut count = 0;
 
         while count < batch_size {
-            if self.nonempty_spans.is_empty() {
-                unsafe { self.populate(page_heap, pagemap) };
-                if self.nonempty_spans.is_empty() {
+            if self.is_empty(age) {
+                unsafe { self.populate(age, page_heap, pagemap) };
+                if self.is_empty(age) {
                     break; // OOM or can't grow
                 }
             }
 
-            let span = self.nonempty_spans.head;
-            unsafe {
-                while count < batch_size && !(*span).freelist.is_null() {
-                    let obj = (*span).freelist;
-                    (*span).freelist = (*obj).next;
-                    (*obj).next = head;
-                    head = obj;
-                    (*span).allocated_count += 1;
-                    count += 1;
-                    self.num_free -= 1;
-                }
-
-                if (*span).freelist.is_null() {
-                    self.nonempty_spans.remove(span);
-                }
-            }
+            let span = self.fullest_span(age);
+            count += unsafe { self.take_from_span(span, batch_size - count, &mut head, &mut tail) };
         }
 
-        (count, head)
+        (count, head, tail)
     }
 
     /// Insert a batch of objects back into the central free list.
@@ -97,11 +403,12 @@ impl CentralFreeList {
         page_heap: &SpinMutex<PageHeap>,
         pagemap: &PageMap,
     ) {
+        let order = size_class::reuse_order(self.size_class);
         let mut remaining = count;
 
         while !head.is_null() && remaining > 0 {
             let obj = head;
-            unsafe { head = (*obj).next };
+            unsafe { head = FreeObject::next(obj) };
             remaining -= 1;
 
             let page_id = (obj as usize) >> PAGE_SHIFT;
@@ -110,57 +417,46 @@ impl CentralFreeList {
                 continue; // Shouldn't happen, but be defensive
             }
 
-            unsafe {
-                let was_full = (*span).freelist.is_null();
-
-                // Add object back to span's free list
-                (*obj).next = (*span).freelist;
-                (*span).freelist = obj;
-                (*span).allocated_count -= 1;
-                self.num_free += 1;
-
-                // If span was previously full (not in nonempty list), add it back
-                if was_full {
-                    self.nonempty_spans.push(span);
-                }
-
-                // If span is completely free, return it to page heap.
-                // Keep at least one span cached to avoid populate/return churn
-                // on single alloc+dealloc cycles.
-                if (*span).allocated_count == 0 && self.nonempty_spans.count > 1 {
-                    self.nonempty_spans.remove(span);
-                    self.num_free -= (*span).total_count as usize;
-                    (*span).freelist = ptr::null_mut();
-                    page_heap.lock().deallocate_span(span);
-                }
+            // If span is completely free, return it to page heap.
+            if unsafe { self.return_object(span, obj, order) } {
+                unsafe { page_heap.lock().deallocate_span(span) };
             }
         }
     }
 
-    /// Fetch a new span from the page heap and carve it into objects.
-    unsafe fn populate(&mut self, page_heap: &SpinMutex<PageHeap>, pagemap: &PageMap) {
-        let info = size_class::class_info(self.size_class);
-        let span = unsafe { page_heap.lock().allocate_span(info.pages) };
+    /// Fetch a new span from the page heap and carve it into objects for
+    /// the `age` pool.
+    unsafe fn populate(&mut self, age: Age, page_heap: &SpinMutex<PageHeap>, pagemap: &PageMap) {
+        let span = unsafe { fetch_span(self.size_class, page_heap) };
         if span.is_null() {
             return;
         }
-        unsafe { self.inject_span(span, pagemap) };
+        if !unsafe { self.inject_span(span, age, pagemap) } {
+            unsafe { give_back(self.size_class, span, page_heap) };
+        }
     }
 
-    /// Carve a pre-allocated span into objects and add to the nonempty list.
-    /// Called while holding the central lock.
-    unsafe fn inject_span(&mut self, span: *mut Span, pagemap: &PageMap) {
+    /// Carve a pre-allocated span into objects and add it to the `age`
+    /// pool. Called while holding the central lock.
+    ///
+    /// Returns false, leaving the span untouched for the caller to give
+    /// back, if the page map could not record it.
+    unsafe fn inject_span(&mut self, span: *mut Span, age: Age, pagemap: &PageMap) -> bool {
         let info = size_class::class_info(self.size_class);
         let obj_size = info.size;
 
         unsafe {
             (*span).size_class = self.size_class;
+            (*span).arena = self.arena;
+            (*span).tenured = pool(age) == Age::Tenured as usize;
             (*span).state = SpanState::InUse;
 
             #[cfg(feature = "debug")]
             println!("[inject] register_span");
 
-            pagemap.register_span(span);
+            if !pagemap.register_span(span) {
+                return false;
+            }
 
             let base = (*span).start_addr();
             let span_bytes = (*span).num_pages * PAGE_SIZE;
@@ -173,9 +469,10 @@ impl CentralFreeList {
             (*span).allocated_count = 0;
 
             let mut freelist: *mut FreeObject = ptr::null_mut();
+            (*span).freelist_tail = base.add((num_objects - 1) * obj_size) as *mut FreeObject;
             for i in (0..num_objects).rev() {
                 let obj = base.add(i * obj_size) as *mut FreeObject;
-                (*obj).next = freelist;
+                FreeObject::set_next(obj, freelist);
                 freelist = obj;
             }
 
@@ -184,16 +481,91 @@ impl CentralFreeList {
 
             (*span).freelist = freelist;
             self.num_free += num_objects;
-            self.nonempty_spans.push(span);
+            self.num_empty += 1;
+            self.link_span(span);
         }
+        self.note_populate();
+        true
+    }
+}
+
+/// Fetch a span for `size_class` from the page heap, charged to the class.
+/// Null if the heap is out of memory or the class is at its cap.
+unsafe fn fetch_span(size_class: usize, page_heap: &SpinMutex<PageHeap>) -> *mut Span {
+    let pages = next_span_pages(size_class);
+    if !class_cap::charge(size_class, pages * PAGE_SIZE) {
+        return ptr::null_mut();
+    }
+    let span = unsafe { page_heap.lock().allocate_small_span(pages) };
+    if span.is_null() {
+        class_cap::uncharge(size_class, pages * PAGE_SIZE);
+    }
+    span
+}
+
+/// Populates of a class closer together than this grow its spans.
+const FAST_POPULATE_MS: u64 = 10;
+
+/// Populates of a class further apart than this shrink its spans.
+const SLOW_POPULATE_MS: u64 = 1000;
+
+/// Time of each class's last populate, 0 before the first.
+static LAST_POPULATE_MS: [AtomicU64; NUM_SIZE_CLASSES] =
+    [const { AtomicU64::new(0) }; NUM_SIZE_CLASSES];
+
+/// Pages for the next span of `size_class`. With `span_pages_scale` above
+/// 1, a class whose populates come fast gets spans twice the size, fewer
+/// trips to the page heap for the same objects, and one that populates
+/// rarely gets them half the size, so it does not hold a big span for a
+/// few objects; both within [`size_class::span_pages_range`]. The clock
+/// makes layouts differ between runs, so `deterministic` keeps the built
+/// sizes.
+fn next_span_pages(size_class: usize) -> usize {
+    let pages = size_class::span_pages(size_class);
+    if SPAN_PAGES_SCALE == 1 || cfg!(feature = "deterministic") {
+        return pages;
+    }
+    let now = platform::now_ms();
+    let last = LAST_POPULATE_MS[size_class].swap(now, Ordering::Relaxed);
+    if last == 0 {
+        return pages;
+    }
+    match scaled_span_pages(pages, now.saturating_sub(last)) {
+        next if next != pages => size_class::set_span_pages(size_class, next),
+        _ => pages,
+    }
+}
+
+/// `pages` adjusted for a populate `gap_ms` after the previous one.
+fn scaled_span_pages(pages: usize, gap_ms: u64) -> usize {
+    if gap_ms < FAST_POPULATE_MS {
+        pages * 2
+    } else if gap_ms > SLOW_POPULATE_MS {
+        pages / 2
+    } else {
+        pages
+    }
+}
+
+/// Return a span from [`fetch_span`] that could not be used.
+unsafe fn give_back(size_class: usize, span: *mut Span, page_heap: &SpinMutex<PageHeap>) {
+    unsafe {
+        class_cap::uncharge(size_class, (*span).num_pages * PAGE_SIZE);
+        page_heap.lock().deallocate_span(span);
     }
 }
 
 /// Remove up to `batch_size` objects, dropping the central lock during page heap calls.
+/// Returns (count, head, tail) like [`CentralFreeList::remove_range`].
 ///
 /// This prevents threads wanting the same size class from blocking while another
 /// thread waits for OS memory in VirtualAlloc/mmap.
 ///
+/// Only one thread fetches a span for a class at a time. Others that find the
+/// list empty meanwhile return the partial batch they already have, or with
+/// nothing yet spin until the span arrives, instead of all fetching a span
+/// each under a miss storm.
+///
 /// # Safety
 ///
 /// `page_heap` and `pagemap` must be the global instances.
@@ -203,9 +575,9 @@ pub unsafe fn remove_range_dropping_lock(
     batch_size: usize,
     page_heap: &SpinMutex<PageHeap>,
     pagemap: &PageMap,
-) -> (usize, *mut FreeObject) {
-    let info = size_class::class_info(size_class);
+) -> (usize, *mut FreeObject, *mut FreeObject) {
     let mut head: *mut FreeObject = ptr::null_mut();
+    let mut tail: *mut FreeObject = ptr::null_mut();
     let mut count = 0;
 
     loop {
@@ -213,48 +585,61 @@ pub unsafe fn remove_range_dropping_lock(
         {
             let mut cfl = cfl_lock.lock();
 
-            while count < batch_size && !cfl.nonempty_spans.is_empty() {
-                let span = cfl.nonempty_spans.head;
-                unsafe {
-                    while count < batch_size && !(*span).freelist.is_null() {
-                        let obj = (*span).freelist;
-                        (*span).freelist = (*obj).next;
-                        (*obj).next = head;
-                        head = obj;
-                        (*span).allocated_count += 1;
-                        count += 1;
-                        cfl.num_free -= 1;
-                    }
-                    if (*span).freelist.is_null() {
-                        cfl.nonempty_spans.remove(span);
-                    }
-                }
+            let limit = batch_size.min(count + MAX_OBJECTS_PER_LOCK);
+            while count < limit && !cfl.is_empty(Age::Nursery) {
+                let span = cfl.fullest_span(Age::Nursery);
+                count += unsafe { cfl.take_from_span(span, limit - count, &mut head, &mut tail) };
             }
 
             if count >= batch_size {
-                return (count, head);
+                return (count, head, tail);
+            }
+            if count == limit {
+                continue; // Let others at the lock before the next chunk.
+            }
+
+            if cfl.populating {
+                if count > 0 {
+                    return (count, head, tail);
+                }
+                // Wait for the span another thread is fetching.
+                drop(cfl);
+                crate::stat_inc!(central_populate_waits);
+                for _ in 0..POPULATE_WAIT_SPINS {
+                    crate::sync::spin_relax();
+                }
+                continue;
             }
 
             // nonempty_spans empty -- need to populate
+            cfl.populating = true;
             // Central lock drops here
         }
 
         // Phase 2: Allocate span from page heap (NO central lock held)
-        let span = unsafe { page_heap.lock().allocate_span(info.pages) };
-        if span.is_null() {
-            return (count, head); // OOM, return what we have
-        }
+        let span = unsafe { fetch_span(size_class, page_heap) };
 
         // Phase 3: Inject span under central lock
-        {
-            let mut cfl = cfl_lock.lock();
-            unsafe { cfl.inject_span(span, pagemap) };
+        let mut cfl = cfl_lock.lock();
+        cfl.populating = false;
+        if span.is_null() {
+            return (count, head, tail); // OOM, return what we have
+        }
+        if !unsafe { cfl.inject_span(span, Age::Nursery, pagemap) } {
+            drop(cfl);
+            unsafe { give_back(size_class, span, page_heap) };
+            return (count, head, tail);
         }
     }
 }
 
 /// Insert objects back, dropping the central lock for page heap span deallocation.
 ///
+/// The batch is inserted in chunks of at most `max_objects_per_lock` objects
+/// per lock acquisition, and spans that become free are handed to the page
+/// heap between chunks, so a huge batch (a thread cache emptied at thread
+/// exit) never holds the central lock for long.
+///
 /// # Safety
 ///
 /// `head` must point to a valid linked list of `count` `FreeObject`s.
@@ -266,65 +651,54 @@ pub unsafe fn insert_range_dropping_lock(
     pagemap: &PageMap,
 ) {
     const MAX_FREED: usize = 8;
-    let mut freed_spans: [*mut Span; MAX_FREED] = [ptr::null_mut(); MAX_FREED];
-    let mut num_freed = 0;
-
-    // Phase 1: Insert all objects (central lock held)
-    {
-        let mut cfl = cfl_lock.lock();
-        let mut remaining = count;
-
-        while !head.is_null() && remaining > 0 {
-            let obj = head;
-            unsafe { head = (*obj).next };
-            remaining -= 1;
+    let mut remaining = count;
 
-            let page_id = (obj as usize) >> PAGE_SHIFT;
-            let span = pagemap.get(page_id);
-            if span.is_null() {
-                continue;
-            }
+    while !head.is_null() && remaining > 0 {
+        let mut freed_spans: [*mut Span; MAX_FREED] = [ptr::null_mut(); MAX_FREED];
+        let mut num_freed = 0;
 
-            unsafe {
-                let was_full = (*span).freelist.is_null();
+        // Phase 1: Insert one chunk (central lock held)
+        crate::time_slow_path!(CentralRelease, {
+            let mut cfl = cfl_lock.lock();
+            let order = size_class::reuse_order(cfl.size_class);
+            let mut chunk = remaining.min(MAX_OBJECTS_PER_LOCK);
 
-                (*obj).next = (*span).freelist;
-                (*span).freelist = obj;
-                (*span).allocated_count -= 1;
-                cfl.num_free += 1;
+            while !head.is_null() && chunk > 0 && num_freed < MAX_FREED {
+                let obj = head;
+                unsafe { head = FreeObject::next(obj) };
+                remaining -= 1;
+                chunk -= 1;
 
-                if was_full {
-                    cfl.nonempty_spans.push(span);
+                let page_id = (obj as usize) >> PAGE_SHIFT;
+                let span = pagemap.get(page_id);
+                if span.is_null() {
+                    continue;
                 }
 
-                // Keep at least one span cached to avoid populate/return churn
-                if (*span).allocated_count == 0 && cfl.nonempty_spans.count > 1 {
-                    cfl.nonempty_spans.remove(span);
-                    cfl.num_free -= (*span).total_count as usize;
-                    (*span).freelist = ptr::null_mut();
-
-                    if num_freed < MAX_FREED {
-                        freed_spans[num_freed] = span;
-                        num_freed += 1;
-                    } else {
-                        page_heap.lock().deallocate_span(span);
-                    }
+                if unsafe { cfl.return_object(span, obj, order) } {
+                    freed_spans[num_freed] = span;
+                    num_freed += 1;
                 }
             }
-        }
-    }
-    // Central lock dropped
+        });
+        // Central lock dropped
 
-    // Phase 2: Return freed spans to page heap (NO central lock held)
-    for span in freed_spans.iter().take(num_freed) {
-        unsafe { page_heap.lock().deallocate_span(*span) };
+        // Phase 2: Return freed spans to page heap (NO central lock held)
+        for span in freed_spans.iter().take(num_freed) {
+            unsafe { page_heap.lock().deallocate_span(*span) };
+        }
     }
 }
 
-/// Array of central free lists, one per size class.
+/// Array of central free lists, one per size class and arena.
 /// Each is individually locked for fine-grained concurrency.
+///
+/// Arena 0 is the shared one behind the transfer cache. The others (with
+/// `num_arenas` above 1) carve their own spans for the threads that picked
+/// them, and are only reached from those threads' caches and from frees of
+/// their objects. Each list sits on its own cache line.
 pub struct CentralCache {
-    lists: [SpinMutex<CentralFreeList>; NUM_SIZE_CLASSES],
+    lists: [[CachePadded<SpinMutex<CentralFreeList>>; NUM_SIZE_CLASSES]; NUM_ARENAS],
 }
 
 impl Default for CentralCache {
@@ -335,25 +709,48 @@ impl Default for CentralCache {
 
 impl CentralCache {
     pub const fn new() -> Self {
-        let mut lists = [const { SpinMutex::new(CentralFreeList::new(0)) }; NUM_SIZE_CLASSES];
-        let mut i = 0;
-        while i < NUM_SIZE_CLASSES {
-            lists[i] = SpinMutex::new(CentralFreeList::new(i));
-            i += 1;
+        let mut lists = [const {
+            [const { CachePadded::new(SpinMutex::new(CentralFreeList::new(0))) }; NUM_SIZE_CLASSES]
+        }; NUM_ARENAS];
+        let mut a = 0;
+        while a < NUM_ARENAS {
+            let mut i = 0;
+            while i < NUM_SIZE_CLASSES {
+                lists[a][i] =
+                    CachePadded::new(SpinMutex::new(CentralFreeList::in_arena(i, a as u8)));
+                i += 1;
+            }
+            a += 1;
         }
         Self { lists }
     }
 
-    /// Get a reference to the central free list for a size class.
+    /// Get a reference to the shared central free list for a size class.
     #[inline]
     pub fn get(&self, size_class: usize) -> &SpinMutex<CentralFreeList> {
-        &self.lists[size_class]
+        &self.lists[0][size_class]
+    }
+
+    /// The central free list for a size class in `arena`.
+    #[inline]
+    pub fn arena(&self, arena: usize, size_class: usize) -> &SpinMutex<CentralFreeList> {
+        &self.lists[arena][size_class]
+    }
+
+    /// Free objects of a size class across every arena. Takes each list's
+    /// lock in turn.
+    pub fn free_objects(&self, size_class: usize) -> usize {
+        self.lists
+            .iter()
+            .map(|lists| lists[size_class].lock().free_objects())
+            .sum()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::boxed::Box;
+    use std::vec::Vec;
 
     use super::*;
     use crate::pagemap::PageMap;
@@ -365,13 +762,21 @@ mod tests {
         (pm, heap, cache)
     }
 
+    #[test]
+    fn test_scaled_span_pages() {
+        assert_eq!(scaled_span_pages(4, 0), 8);
+        assert_eq!(scaled_span_pages(4, FAST_POPULATE_MS), 4);
+        assert_eq!(scaled_span_pages(4, SLOW_POPULATE_MS), 4);
+        assert_eq!(scaled_span_pages(4, SLOW_POPULATE_MS + 1), 2);
+    }
+
     #[test]
     fn test_remove_range_populates() {
         let (pm, heap, cache) = make_test_env();
         // Size class 1 = 8 bytes
         let mut cfl = cache.get(1).lock();
         unsafe {
-            let (count, head) = cfl.remove_range(32, &heap, pm);
+            let (count, head, _) = cfl.remove_range(32, &heap, pm);
             assert!(count > 0);
             assert!(!head.is_null());
 
@@ -380,7 +785,7 @@ mod tests {
             let mut actual = 0;
             while !node.is_null() {
                 actual += 1;
-                node = (*node).next;
+                node = FreeObject::next(node);
             }
             assert_eq!(actual, count);
         }
@@ -393,7 +798,7 @@ mod tests {
         let mut cfl = cache.get(2).lock();
         unsafe {
             // First get some objects
-            let (count, head) = cfl.remove_range(16, &heap, pm);
+            let (count, head, _) = cfl.remove_range(16, &heap, pm);
             assert!(count > 0);
 
             // Return them
@@ -408,10 +813,240 @@ mod tests {
         let mut cfl = cache.get(8).lock();
         unsafe {
             for _ in 0..10 {
-                let (count, head) = cfl.remove_range(4, &heap, pm);
+                let (count, head, _) = cfl.remove_range(4, &heap, pm);
                 assert!(count > 0);
                 cfl.insert_range(head, count, &heap, pm);
             }
         }
     }
+
+    #[test]
+    fn test_fullness_bucket() {
+        assert_eq!(fullness_bucket(0, 64), 0);
+        assert_eq!(fullness_bucket(8, 64), 1);
+        assert_eq!(fullness_bucket(63, 64), NUM_FULLNESS_LISTS - 1);
+        assert_eq!(fullness_bucket(1, 2), NUM_FULLNESS_LISTS / 2);
+    }
+
+    #[test]
+    fn test_allocates_from_fullest_span() {
+        let (pm, heap, cache) = make_test_env();
+        let cls = 8;
+        let mut cfl = cache.get(cls).lock();
+        let span_of = |p: *mut FreeObject| pm.get(p as usize >> PAGE_SHIFT);
+        unsafe {
+            // Fill span A completely, then take a few objects from span B.
+            let mut objs = Vec::new();
+            let (_, first, _) = cfl.remove_range(1, &heap, pm);
+            let a = span_of(first);
+            objs.push(first);
+            let total = (*a).total_count as usize;
+            for _ in 0..total + 2 {
+                let (n, obj, _) = cfl.remove_range(1, &heap, pm);
+                assert_eq!(n, 1);
+                objs.push(obj);
+            }
+            let b = span_of(objs[objs.len() - 1]);
+            assert_ne!(a, b);
+
+            // Free half of A: it is now sparser than full, but much fuller
+            // than B.
+            for &obj in objs.iter().filter(|&&o| span_of(o) == a).take(total / 2) {
+                FreeObject::set_next(obj, ptr::null_mut());
+                cfl.insert_range(obj, 1, &heap, pm);
+            }
+            assert!(span_bucket(a) > span_bucket(b));
+
+            let (_, next, _) = cfl.remove_range(1, &heap, pm);
+            assert_eq!(span_of(next), a, "should allocate from the fuller span");
+        }
+    }
+
+    #[cfg(not(feature = "minimal"))]
+    #[test]
+    fn test_tenured_spans_kept_apart() {
+        let (pm, heap, cache) = make_test_env();
+        let mut cfl = cache.get(8).lock();
+        let span_of = |p: *mut FreeObject| pm.get(p as usize >> PAGE_SHIFT);
+        unsafe {
+            let (_, young, _) = cfl.remove_range(1, &heap, pm);
+            let (_, old, _) = cfl.remove_range_aged(1, Age::Tenured, &heap, pm);
+            let (n, s) = (span_of(young), span_of(old));
+            assert_ne!(n, s);
+            assert!(!(*n).tenured && (*s).tenured);
+            assert_eq!(cfl.nonempty_spans(Age::Nursery), 1);
+            assert_eq!(cfl.nonempty_spans(Age::Tenured), 1);
+
+            // The tenured span's free objects are not handed to the nursery.
+            let total = (*n).total_count as usize;
+            for _ in 0..total - 1 {
+                let (_, obj, _) = cfl.remove_range(1, &heap, pm);
+                assert_eq!(span_of(obj), n);
+            }
+            let (_, obj, _) = cfl.remove_range(1, &heap, pm);
+            assert!(span_of(obj) != n && span_of(obj) != s);
+
+            // A freed object re-files its span in its own pool.
+            FreeObject::set_next(old, ptr::null_mut());
+            cfl.insert_range(old, 1, &heap, pm);
+            assert_eq!(cfl.nonempty_spans(Age::Tenured), 1);
+            let (_, again, _) = cfl.remove_range_aged(1, Age::Tenured, &heap, pm);
+            assert_eq!(span_of(again), s);
+        }
+    }
+
+    #[test]
+    fn test_concurrent_misses_populate_once() {
+        let (pm, heap, cache) = make_test_env();
+        let cls = 1;
+        let per_span =
+            size_class::class_info(cls).pages * PAGE_SIZE / size_class::class_to_size(cls);
+        let threads = 8;
+        let batch = per_span / threads / 2;
+        let barrier = std::sync::Barrier::new(threads);
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    barrier.wait();
+                    let (count, _, _) = unsafe {
+                        remove_range_dropping_lock(cache.get(cls), cls, batch, &heap, pm)
+                    };
+                    assert!(count > 0);
+                });
+            }
+        });
+        // Every miss was served from one span, however the threads raced.
+        let cfl = cache.get(cls).lock();
+        assert!(cfl.free_objects() < per_span);
+        assert!(!cfl.populating);
+    }
+
+    #[test]
+    fn test_huge_batches_move_in_chunks() {
+        let (pm, heap, cache) = make_test_env();
+        let cls = 1;
+        let n = 5 * MAX_OBJECTS_PER_LOCK + 3;
+        #[cfg(feature = "latency-histogram")]
+        let before = crate::stats::latency_snapshot();
+        unsafe {
+            let (count, head, _) = remove_range_dropping_lock(cache.get(cls), cls, n, &heap, pm);
+            assert_eq!(count, n);
+            insert_range_dropping_lock(cache.get(cls), head, count, &heap, pm);
+        }
+        let cfl = cache.get(cls).lock();
+        // Everything came back: only whole free spans are left.
+        let per_span =
+            size_class::class_info(cls).pages * PAGE_SIZE / size_class::class_to_size(cls);
+        assert_eq!(cfl.free_objects(), cfl.retained_spans() * per_span);
+        #[cfg(feature = "latency-histogram")]
+        {
+            use crate::stats::SlowPath;
+            let chunks = crate::stats::latency_snapshot()
+                .get(SlowPath::CentralRelease)
+                .count()
+                - before.get(SlowPath::CentralRelease).count();
+            assert!(chunks as usize >= n.div_ceil(MAX_OBJECTS_PER_LOCK));
+        }
+    }
+
+    #[test]
+    fn test_retain_target_adapts() {
+        let (pm, heap, cache) = make_test_env();
+        let cls = 8;
+        let mut cfl = cache.get(cls).lock();
+        let per_span = {
+            let info = size_class::class_info(cls);
+            info.pages * PAGE_SIZE / info.size
+        };
+        let take = |cfl: &mut CentralFreeList, n: usize| -> Vec<*mut FreeObject> {
+            (0..n)
+                .map(|_| unsafe { cfl.remove_range(1, &heap, pm).1 })
+                .collect()
+        };
+        let give = |cfl: &mut CentralFreeList, objs: Vec<*mut FreeObject>| {
+            for obj in objs {
+                unsafe {
+                    FreeObject::set_next(obj, ptr::null_mut());
+                    cfl.insert_range(obj, 1, &heap, pm);
+                }
+            }
+        };
+
+        // Two spans in use, then freed: one is kept, one goes back.
+        let objs = take(&mut cfl, 2 * per_span);
+        give(&mut cfl, objs);
+        assert_eq!((cfl.retain_target(), cfl.retained_spans()), (1, 1));
+
+        // Needing the released span again raises the target.
+        let objs = take(&mut cfl, 2 * per_span);
+        assert_eq!(cfl.retain_target(), 2.min(MAX_RETAINED_SPANS));
+        give(&mut cfl, objs);
+        assert_eq!(cfl.retained_spans(), 2.min(MAX_RETAINED_SPANS));
+
+        // A burst of releases past the target without populating lowers it.
+        let target = cfl.retain_target();
+        let objs = take(&mut cfl, (2 * target + 1) * per_span);
+        give(&mut cfl, objs);
+        assert!(cfl.retain_target() < target || target == 1);
+        assert!(cfl.retain_target() >= 1);
+
+        // Other tests share the global counters, so only lower bounds hold.
+        #[cfg(feature = "stats")]
+        {
+            let churn = crate::stats::span_churn(cls);
+            assert!(churn.populates >= 6, "{churn:?}");
+            assert!(churn.releases >= 4, "{churn:?}");
+        }
+    }
+    #[test]
+    fn test_fifo_reuses_oldest_free() {
+        let (pm, heap, cache) = make_test_env();
+        let cls = 8;
+        let mut cfl = cache.get(cls).lock();
+        let span_of = |p: *mut FreeObject| pm.get(p as usize >> PAGE_SHIFT);
+        unsafe {
+            // Take a whole span, then free three of its objects in order.
+            let (_, first, _) = cfl.remove_range(1, &heap, pm);
+            let span = span_of(first);
+            let mut objs = Vec::from([first]);
+            for _ in 1..(*span).total_count {
+                objs.push(cfl.remove_range(1, &heap, pm).1);
+            }
+            assert!((*span).freelist.is_null());
+            let freed = [objs[3], objs[1], objs[2]];
+            for &obj in &freed {
+                assert!(!cfl.return_object(span, obj, ReuseOrder::Fifo));
+            }
+            assert_eq!((*span).freelist_tail, objs[2]);
+
+            // Handed out again oldest first.
+            for &obj in &freed {
+                assert_eq!(cfl.remove_range(1, &heap, pm).1, obj);
+            }
+
+            // LIFO hands the last one freed back first.
+            for &obj in &freed {
+                cfl.return_object(span, obj, ReuseOrder::Lifo);
+            }
+            assert_eq!(cfl.remove_range(1, &heap, pm).1, objs[2]);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "minimal"))]
+    fn test_lists_on_separate_cache_lines() {
+        use crate::config::CACHE_LINE_SIZE;
+        // First and last cache line a list's lock and state touch.
+        fn lines(list: &SpinMutex<CentralFreeList>) -> (usize, usize) {
+            let start = list as *const _ as usize;
+            let end = start + size_of_val(list) - 1;
+            (start / CACHE_LINE_SIZE, end / CACHE_LINE_SIZE)
+        }
+        let cache = CentralCache::new();
+        for a in 0..NUM_ARENAS {
+            for cls in 1..NUM_SIZE_CLASSES {
+                assert!(lines(cache.arena(a, cls - 1)).1 < lines(cache.arena(a, cls)).0);
+            }
+        }
+    }
 }
@@ -0,0 +1,117 @@
+//! Per-size-class byte caps.
+//!
+//! Every span a central free list takes from the page heap is charged to
+//! its size class, across all arenas, until the list gives it back. That is
+//! the memory the class holds in every tier: live objects plus the ones
+//! sitting free in thread caches, per-CPU slabs, the transfer cache and the
+//! central lists. A leak in one object type shows up as its class growing
+//! without bound.
+//!
+//! With a cap set, a populate that would take the class past it is refused
+//! and the allocation that needed it fails (null from `alloc`), while other
+//! classes carry on. A registered [`CapHandler`] sees each refusal first and
+//! can let it through, e.g. to log or raise an alert before failing hard.
+//!
+//! ```ignore
+//! use rtmalloc::class_cap;
+//!
+//! extern "C" fn on_cap(size_class: usize, held: usize, cap: usize) -> bool {
+//!     flag_backpressure(size_class, held, cap); // must not allocate
+//!     false
+//! }
+//! class_cap::set_size_cap(0..=64, 256 << 20);
+//! class_cap::set_handler(Some(on_cap));
+//! ```
+//!
+//! Caps are checked per span, so a class stops at the last whole span that
+//! fits. Lowering a cap below what a class already holds frees nothing; the
+//! class only stops growing until it has shrunk below the cap.
+
+use crate::size_class::{self, NUM_SIZE_CLASSES};
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Called when a populate would take `size_class` past its cap, with the
+/// bytes it holds and the cap. Return true to let the populate through.
+///
+/// It runs inside the allocator, possibly with the class's central lock
+/// held, so it must not allocate or free through rtmalloc.
+pub type CapHandler = extern "C" fn(size_class: usize, held: usize, cap: usize) -> bool;
+
+/// Byte cap of each class, 0 for none.
+static CAPS: [AtomicUsize; NUM_SIZE_CLASSES] = [const { AtomicUsize::new(0) }; NUM_SIZE_CLASSES];
+/// Bytes in spans each class holds.
+static HELD: [AtomicUsize; NUM_SIZE_CLASSES] = [const { AtomicUsize::new(0) }; NUM_SIZE_CLASSES];
+/// Registered [`CapHandler`] as an address, 0 when none.
+static HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Cap the bytes `size_class` may hold at `bytes`, 0 for no cap.
+pub fn set_cap(size_class: usize, bytes: usize) {
+    CAPS[size_class].store(bytes, Ordering::Relaxed);
+}
+
+/// The cap of `size_class`, 0 if none.
+pub fn cap(size_class: usize) -> usize {
+    CAPS[size_class].load(Ordering::Relaxed)
+}
+
+/// Give every class whose object size lies in `sizes` the cap `bytes`. Each
+/// class is capped on its own. Returns the number of classes changed.
+pub fn set_size_cap(sizes: RangeInclusive<usize>, bytes: usize) -> usize {
+    let mut changed = 0;
+    for cls in 1..NUM_SIZE_CLASSES {
+        if sizes.contains(&size_class::class_to_size(cls)) {
+            set_cap(cls, bytes);
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// Bytes in spans `size_class` holds right now.
+pub fn held(size_class: usize) -> usize {
+    HELD[size_class].load(Ordering::Relaxed)
+}
+
+/// Register `handler` to decide on populates past a cap. `None` removes
+/// it, and such populates fail again.
+pub fn set_handler(handler: Option<CapHandler>) {
+    HANDLER.store(handler.map_or(0, |h| h as usize), Ordering::Release);
+}
+
+fn handler() -> Option<CapHandler> {
+    match HANDLER.load(Ordering::Acquire) {
+        0 => None,
+        addr => Some(unsafe { core::mem::transmute::<usize, CapHandler>(addr) }),
+    }
+}
+
+/// Charge a span of `bytes` to `size_class` before fetching it. Returns
+/// false, charging nothing, if the cap refuses it.
+#[inline]
+pub(crate) fn charge(size_class: usize, bytes: usize) -> bool {
+    let held = HELD[size_class].fetch_add(bytes, Ordering::Relaxed);
+    let cap = cap(size_class);
+    if cap == 0 || held + bytes <= cap {
+        return true;
+    }
+    refuse(size_class, held, bytes, cap)
+}
+
+#[cold]
+#[inline(never)]
+fn refuse(size_class: usize, held: usize, bytes: usize, cap: usize) -> bool {
+    crate::stat_inc!(class_cap_hits);
+    if handler().is_some_and(|h| h(size_class, held, cap)) {
+        return true;
+    }
+    uncharge(size_class, bytes);
+    false
+}
+
+/// Give back a charge: the span went back to the page heap or was never
+/// fetched.
+#[inline]
+pub(crate) fn uncharge(size_class: usize, bytes: usize) {
+    HELD[size_class].fetch_sub(bytes, Ordering::Relaxed);
+}
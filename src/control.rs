@@ -0,0 +1,254 @@
+//! Stats and control over a Unix domain socket (`control` feature).
+//!
+//! Lets an operator inspect and poke the allocator of a running process
+//! without code changes or signals. The process opts in once at startup:
+//!
+//! ```ignore
+//! let _control = rtmalloc::control::start_from_env()?;
+//! ```
+//!
+//! With [`SOCKET_ENV`] set to a path, that binds a socket there (mode 0600,
+//! set before the socket appears at the path) and serves it from a background thread, one connection at a time, so
+//! the application's threads never wait on a client. The protocol is line
+//! based: each command line gets zero or more reply lines, then `ok` or
+//! `error: <reason>`.
+//!
+//! | Command | Reply |
+//! |---------|-------|
+//! | `stats` | `name value` lines: page heap, cache sizes and, with `stats`, every counter |
+//! | `flush` | `flushed <bytes>`: transfer caches and parked mid-heap spans moved back |
+//! | `release` | `released <bytes>`: after a `flush`, free page heap memory dropped from RSS |
+//! | `thread-cache-size [bytes]` | `thread_cache_size <bytes>`: read or set the overall thread cache budget |
+//! | `help` | the command list |
+//!
+//! ```text
+//! $ echo stats | socat - UNIX-CONNECT:/run/app/rtmalloc.sock
+//! ```
+//!
+//! Thread caches and per-CPU slabs belong to their threads and CPUs, so
+//! `flush` cannot empty them; they shrink as usual when their budget does.
+
+use crate::allocator::PAGE_HEAP;
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::string::String;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// Environment variable [`start_from_env`] reads the socket path from.
+pub const SOCKET_ENV: &str = "RTMALLOC_CONTROL_SOCKET";
+
+/// How long a connection may sit idle before the server drops it.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest command line the server reads; a longer one ends the connection.
+const MAX_LINE: usize = 256;
+
+const HELP: &str = "stats\nflush\nrelease\nthread-cache-size [bytes]\nhelp\n";
+
+/// Move transfer cache batches to the central free lists and parked
+/// mid-heap spans to the page heap. Returns the bytes moved.
+pub fn flush() -> usize {
+    crate::allocator::flush_shared_caches()
+}
+
+/// [`flush`], then have the OS drop every free page heap page. Returns the
+/// bytes dropped; always 0 outside Linux and Android.
+pub fn release() -> usize {
+    flush();
+    unsafe { PAGE_HEAP.lock().release_free() }
+}
+
+/// Run one command line and return its reply, as the socket server does.
+pub fn execute(line: &str) -> String {
+    let mut reply = String::new();
+    let mut words = line.split_whitespace();
+    let result = match (words.next(), words.next(), words.next()) {
+        (Some("stats"), None, _) => {
+            write_stats(&mut reply);
+            Ok(())
+        }
+        (Some("flush"), None, _) => {
+            let _ = writeln!(reply, "flushed {}", flush());
+            Ok(())
+        }
+        (Some("release"), None, _) => {
+            let _ = writeln!(reply, "released {}", release());
+            Ok(())
+        }
+        (Some("thread-cache-size"), arg, None) => thread_cache_size(arg, &mut reply),
+        (Some("help"), None, _) => {
+            reply.push_str(HELP);
+            Ok(())
+        }
+        (None, ..) => Err("empty command"),
+        _ => Err("unknown command, try `help`"),
+    };
+    match result {
+        Ok(()) => reply.push_str("ok\n"),
+        Err(reason) => {
+            reply.clear();
+            let _ = writeln!(reply, "error: {reason}");
+        }
+    }
+    reply
+}
+
+fn write_stats(reply: &mut String) {
+    #[cfg(feature = "stats")]
+    let _ = crate::stats::write_report(reply);
+    #[cfg(not(feature = "stats"))]
+    {
+        use crate::allocator::{MID_HEAP, TRANSFER_CACHE};
+
+        let heap = PAGE_HEAP.lock().usage();
+        let values = [
+            ("system_bytes", heap.system_bytes),
+            ("free_spans", heap.free_spans),
+            ("free_bytes", heap.free_bytes),
+            ("mid_cached_bytes", MID_HEAP.cached_bytes()),
+            ("transfer_cache_bytes", TRANSFER_CACHE.total_bytes()),
+        ];
+        for (name, value) in values {
+            let _ = writeln!(reply, "{name} {value}");
+        }
+    }
+    #[cfg(not(feature = "percpu"))]
+    let _ = writeln!(
+        reply,
+        "thread_cache_size {}",
+        crate::thread_cache::overall_cache_size()
+    );
+}
+
+fn thread_cache_size(arg: Option<&str>, reply: &mut String) -> Result<(), &'static str> {
+    #[cfg(not(feature = "percpu"))]
+    {
+        use crate::thread_cache::{overall_cache_size, set_overall_cache_size};
+
+        let bytes = match arg {
+            None => overall_cache_size(),
+            Some(arg) => set_overall_cache_size(arg.parse().map_err(|_| "bad byte count")?),
+        };
+        let _ = writeln!(reply, "thread_cache_size {bytes}");
+        Ok(())
+    }
+    #[cfg(feature = "percpu")]
+    {
+        let _ = (arg, reply);
+        Err("no thread caches in percpu builds")
+    }
+}
+
+/// A running control socket server. Dropping it leaves the server running
+/// for the rest of the process.
+pub struct Server {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Server {
+    /// Path of the socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stop serving once the current client is done, and remove the socket.
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Release);
+        // Wake the accept loop; it checks the flag before serving.
+        drop(UnixStream::connect(&self.path));
+        self.thread
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e));
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Bind a control socket at `path` and serve it from a background thread.
+/// A stale socket left at `path` by an earlier process is replaced; any
+/// other file there is an error.
+pub fn start(path: impl AsRef<Path>) -> io::Result<Server> {
+    let path = path.as_ref().to_path_buf();
+    if let Ok(meta) = fs::symlink_metadata(&path)
+        && meta.file_type().is_socket()
+        && UnixStream::connect(&path).is_err()
+    {
+        fs::remove_file(&path)?;
+    }
+    // Bind in a fresh 0700 directory so nobody can connect before the
+    // socket is 0600, then link it into place. Linking fails if anything
+    // appeared at `path` meanwhile.
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let mut dir_name = std::ffi::OsString::from(".");
+    dir_name.push(name);
+    dir_name.push(std::format!(".{}", std::process::id()));
+    let dir = path.with_file_name(dir_name);
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let tmp = dir.join("s");
+    let bound = UnixListener::bind(&tmp).and_then(|listener| {
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+        fs::hard_link(&tmp, &path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&tmp);
+    let _ = fs::remove_dir(&dir);
+    let listener = bound?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::clone(&stop);
+    let thread = std::thread::Builder::new()
+        .name("rtmalloc-control".into())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::Acquire) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let _ = serve(stream);
+                }
+            }
+        })?;
+    Ok(Server { path, stop, thread })
+}
+
+/// [`start`] at the path in [`SOCKET_ENV`], or `None` if it is unset or
+/// empty.
+pub fn start_from_env() -> io::Result<Option<Server>> {
+    match std::env::var_os(SOCKET_ENV) {
+        Some(path) if !path.is_empty() => start(path).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Answer commands from one client until it hangs up, goes quiet or sends
+/// a line longer than [`MAX_LINE`].
+fn serve(stream: UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut out = &stream;
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let n = (&mut reader)
+            .take(MAX_LINE as u64 + 1)
+            .read_line(&mut line)?;
+        if n == 0 {
+            return Ok(());
+        }
+        if n > MAX_LINE && !line.ends_with('\n') {
+            return out.write_all(b"error: command too long\n");
+        }
+        out.write_all(execute(&line).as_bytes())?;
+    }
+}
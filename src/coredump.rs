@@ -0,0 +1,496 @@
+//! Stable heap layout descriptor for post-mortem (core dump) analysis.
+//!
+//! Gated behind `features = ["coredump"]`. Exports a single `#[repr(C)]`
+//! static, [`HeapLayout`], that records where the allocator's global
+//! structures live and the offsets of the fields a debugger needs to walk
+//! them. Every field is a `u64`, so the descriptor itself reads the same from
+//! any tool regardless of how the rest of the crate is laid out.
+//!
+//! `scripts/rtmalloc_gdb.py` consumes the descriptor from a live process or a
+//! core file:
+//!
+//! ```text
+//! (gdb) source scripts/rtmalloc_gdb.py
+//! (gdb) rtmalloc spans          # every span in the page map
+//! (gdb) rtmalloc classes        # objects / live bytes per size class
+//! (gdb) rtmalloc find 0x7f...   # span owning an address
+//! (gdb) rtmalloc stats          # counters (with the `stats` feature)
+//! ```
+//!
+//! The script's constants block is generated from this module; the
+//! `test_gdb_script_in_sync` test fails when they drift (rerun it with
+//! `RTMALLOC_BLESS=1` to rewrite the block). Bump [`LAYOUT_VERSION`] whenever
+//! the descriptor or the field widths the script assumes change.
+//!
+//! Tools attached to a live process (uprobes, eBPF, debuggers without
+//! DWARF) find the allocator's global structures through a second export,
+//! `RTMALLOC_INTROSPECTION`, an [`IntrospectionTable`] of their addresses
+//! and sizes: the page map, page heap, central free lists, transfer cache,
+//! mid-heap and stats counters, with the offsets that get from a lock to
+//! the value it guards. It points at the [`HeapLayout`] for the span and
+//! size class offsets. [`INTROSPECTION_VERSION`] changes whenever a field
+//! changes meaning; new fields are only appended, and `table_size` tells a
+//! reader which are there.
+
+use crate::allocator::{CENTRAL_CACHE, MID_HEAP, PAGE_HEAP, PAGE_MAP};
+use crate::central_free_list::{CentralCache, CentralFreeList};
+use crate::config::{NUM_ARENAS, PAGE_SHIFT};
+use crate::mid_heap::MidHeap;
+use crate::page_heap::PageHeap;
+use crate::pagemap::{self, PageMap};
+use crate::size_class::{ClassMap, Classes, NUM_SIZE_CLASSES, SizeClassInfo};
+use crate::span::{Span, SpanState};
+use crate::sync::{CachePadded, SpinMutex};
+use core::mem::{offset_of, size_of};
+
+/// `"RTMHEAP\0"` as a little-endian `u64`.
+pub const MAGIC: u64 = u64::from_le_bytes(*b"RTMHEAP\0");
+
+/// Version of the [`HeapLayout`] contract.
+pub const LAYOUT_VERSION: u64 = 1;
+
+/// Describes the allocator's global metadata to out-of-process tools.
+///
+/// Addresses are absolute in the target process. Offsets are in bytes.
+#[repr(C)]
+pub struct HeapLayout {
+    pub magic: u64,
+    pub layout_version: u64,
+    /// [`crate::version::CONFIG_FINGERPRINT`] of the build.
+    pub config_fingerprint: u64,
+    pub pointer_size: u64,
+    pub page_shift: u64,
+
+    /// Address of the page map root (an array of `1 << pagemap_root_bits`
+    /// pointers to mid nodes, each an array of `1 << pagemap_mid_bits`
+    /// pointers to leaves of `1 << pagemap_leaf_bits` span pointers).
+    pub pagemap_root: u64,
+    pub pagemap_root_bits: u64,
+    pub pagemap_mid_bits: u64,
+    pub pagemap_leaf_bits: u64,
+
+    pub span_size: u64,
+    /// `usize`
+    pub span_start_page: u64,
+    /// `usize`
+    pub span_num_pages: u64,
+    /// `usize`
+    pub span_size_class: u64,
+    /// `u8`
+    pub span_state: u64,
+    /// `u32`
+    pub span_allocated_count: u64,
+    /// `u32`
+    pub span_total_count: u64,
+    /// Value of `span_state` for spans handed out by the page heap.
+    pub span_state_in_use: u64,
+
+    /// Address of the size class table (class 0 is the sentinel).
+    pub size_classes: u64,
+    pub size_class_stride: u64,
+    /// `usize`
+    pub size_class_size: u64,
+    pub num_size_classes: u64,
+
+    /// Address of the stats counters (an array of `u64`), or 0 without the
+    /// `stats` feature.
+    pub stats: u64,
+    pub num_stats: u64,
+}
+
+/// Addresses cannot be cast to integers in a const context, so the pointer
+/// fields are stored as pointers and reinterpreted as `u64` by readers.
+#[repr(C)]
+struct RawLayout {
+    head: [u64; 5],
+    pagemap_root: *const u8,
+    pagemap: [u64; 3],
+    span: [u64; 8],
+    size_classes: *const u8,
+    size_class: [u64; 3],
+    stats: *const u8,
+    num_stats: u64,
+}
+
+unsafe impl Sync for RawLayout {}
+
+const _: () = assert!(size_of::<usize>() == size_of::<u64>());
+const _: () = assert!(size_of::<RawLayout>() == size_of::<HeapLayout>());
+
+#[used]
+#[cfg_attr(not(feature = "testing"), unsafe(export_name = "rtmalloc_heap_layout"))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_heap_layout")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_heap_layout")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_heap_layout")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_heap_layout")
+)]
+static HEAP_LAYOUT: RawLayout = RawLayout {
+    head: [
+        MAGIC,
+        LAYOUT_VERSION,
+        crate::version::CONFIG_FINGERPRINT,
+        size_of::<usize>() as u64,
+        PAGE_SHIFT as u64,
+    ],
+    pagemap_root: &PAGE_MAP as *const _ as *const u8,
+    pagemap: [
+        pagemap::ROOT_BITS as u64,
+        pagemap::MID_BITS as u64,
+        pagemap::LEAF_BITS as u64,
+    ],
+    span: [
+        size_of::<Span>() as u64,
+        offset_of!(Span, start_page) as u64,
+        offset_of!(Span, num_pages) as u64,
+        offset_of!(Span, size_class) as u64,
+        offset_of!(Span, state) as u64,
+        offset_of!(Span, allocated_count) as u64,
+        offset_of!(Span, total_count) as u64,
+        SpanState::InUse as u64,
+    ],
+    size_classes: Classes::CLASSES.as_ptr() as *const u8,
+    size_class: [
+        size_of::<SizeClassInfo>() as u64,
+        offset_of!(SizeClassInfo, size) as u64,
+        NUM_SIZE_CLASSES as u64,
+    ],
+    #[cfg(feature = "stats")]
+    stats: &crate::stats::STATS as *const _ as *const u8,
+    #[cfg(feature = "stats")]
+    num_stats: crate::stats::COUNTER_NAMES.len() as u64,
+    #[cfg(not(feature = "stats"))]
+    stats: core::ptr::null(),
+    #[cfg(not(feature = "stats"))]
+    num_stats: 0,
+};
+
+/// The descriptor exported to debuggers.
+pub fn heap_layout() -> &'static HeapLayout {
+    // SAFETY: same size, both `repr(C)`, every field 8 bytes wide; pointers
+    // are valid `u64` bit patterns on 64-bit targets.
+    unsafe { &*(&HEAP_LAYOUT as *const RawLayout as *const HeapLayout) }
+}
+
+/// Field names of [`HeapLayout`], in order. The gdb script decodes the
+/// descriptor with these.
+pub const FIELD_NAMES: [&str; size_of::<HeapLayout>() / 8] = [
+    "magic",
+    "layout_version",
+    "config_fingerprint",
+    "pointer_size",
+    "page_shift",
+    "pagemap_root",
+    "pagemap_root_bits",
+    "pagemap_mid_bits",
+    "pagemap_leaf_bits",
+    "span_size",
+    "span_start_page",
+    "span_num_pages",
+    "span_size_class",
+    "span_state",
+    "span_allocated_count",
+    "span_total_count",
+    "span_state_in_use",
+    "size_classes",
+    "size_class_stride",
+    "size_class_size",
+    "num_size_classes",
+    "stats",
+    "num_stats",
+];
+
+/// `"RTMINTRO"` as a little-endian `u64`.
+pub const INTROSPECTION_MAGIC: u64 = u64::from_le_bytes(*b"RTMINTRO");
+
+/// Version of the [`IntrospectionTable`] contract.
+pub const INTROSPECTION_VERSION: u64 = 1;
+
+/// Where the allocator's global structures live, for tools that walk a
+/// running process without debug info. Exported as
+/// `RTMALLOC_INTROSPECTION`.
+///
+/// Addresses are absolute in the target process, 0 for a structure the
+/// build does not have; sizes and offsets are in bytes. The structures
+/// themselves are not `repr(C)`: beyond what [`HeapLayout`] describes, a
+/// reader must take their layout from a build with the same
+/// `config_fingerprint`.
+#[repr(C)]
+pub struct IntrospectionTable {
+    pub magic: u64,
+    pub version: u64,
+    /// Size of this table; fields past it are not there.
+    pub table_size: u64,
+    /// [`crate::version::CONFIG_FINGERPRINT`] of the build.
+    pub config_fingerprint: u64,
+    /// Address of the [`HeapLayout`] descriptor.
+    pub heap_layout: u64,
+
+    pub page_map: u64,
+    pub page_map_size: u64,
+
+    /// Address of the page heap's lock; the page heap itself sits
+    /// `page_heap_data` bytes into it.
+    pub page_heap: u64,
+    pub page_heap_size: u64,
+    pub page_heap_data: u64,
+
+    /// Address of the central free lists: `num_arenas` rows of
+    /// `num_size_classes` locks `central_list_stride` bytes apart, each
+    /// with its list `central_list_data` bytes in.
+    pub central_cache: u64,
+    pub central_cache_size: u64,
+    pub central_list_stride: u64,
+    pub central_list_data: u64,
+    pub num_arenas: u64,
+
+    /// Address of the transfer cache, or 0 without one (`minimal` or no
+    /// `percpu`, `nightly` or `std`).
+    pub transfer_cache: u64,
+    pub transfer_cache_size: u64,
+
+    pub mid_heap: u64,
+    pub mid_heap_size: u64,
+
+    /// Address of the stats counters (as in [`HeapLayout::stats`]), or 0
+    /// without the `stats` feature.
+    pub stats: u64,
+    pub stats_size: u64,
+}
+
+/// [`IntrospectionTable`] with its addresses as pointers, as [`RawLayout`]
+/// is for [`HeapLayout`].
+#[repr(C)]
+struct RawIntrospection {
+    head: [u64; 4],
+    heap_layout: *const u8,
+    page_map: *const u8,
+    page_map_size: u64,
+    page_heap: *const u8,
+    page_heap_sizes: [u64; 2],
+    central_cache: *const u8,
+    central_sizes: [u64; 4],
+    transfer_cache: *const u8,
+    transfer_cache_size: u64,
+    mid_heap: *const u8,
+    mid_heap_size: u64,
+    stats: *const u8,
+    stats_size: u64,
+}
+
+unsafe impl Sync for RawIntrospection {}
+
+const _: () = assert!(size_of::<RawIntrospection>() == size_of::<IntrospectionTable>());
+
+cfg_if::cfg_if! {
+    if #[cfg(any(feature = "percpu", feature = "nightly", feature = "std"))] {
+        const TRANSFER_CACHE: (*const u8, u64) = (
+            &crate::allocator::TRANSFER_CACHE as *const _ as *const u8,
+            size_of::<crate::transfer_cache::TransferCacheArray>() as u64,
+        );
+    } else {
+        const TRANSFER_CACHE: (*const u8, u64) = (core::ptr::null(), 0);
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "stats")] {
+        const STATS: (*const u8, u64) = (
+            &crate::stats::STATS as *const _ as *const u8,
+            size_of::<crate::stats::Stats>() as u64,
+        );
+    } else {
+        const STATS: (*const u8, u64) = (core::ptr::null(), 0);
+    }
+}
+
+#[used]
+#[cfg_attr(
+    not(feature = "testing"),
+    unsafe(export_name = "RTMALLOC_INTROSPECTION")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "RTMALLOC_PERCPU_INTROSPECTION")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "RTMALLOC_NIGHTLY_INTROSPECTION")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "RTMALLOC_STD_INTROSPECTION")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "RTMALLOC_NOSTD_INTROSPECTION")
+)]
+static INTROSPECTION: RawIntrospection = RawIntrospection {
+    head: [
+        INTROSPECTION_MAGIC,
+        INTROSPECTION_VERSION,
+        size_of::<IntrospectionTable>() as u64,
+        crate::version::CONFIG_FINGERPRINT,
+    ],
+    heap_layout: &HEAP_LAYOUT as *const _ as *const u8,
+    page_map: &PAGE_MAP as *const _ as *const u8,
+    page_map_size: size_of::<PageMap>() as u64,
+    page_heap: &PAGE_HEAP as *const _ as *const u8,
+    page_heap_sizes: [
+        size_of::<SpinMutex<PageHeap>>() as u64,
+        SpinMutex::<PageHeap>::DATA_OFFSET as u64,
+    ],
+    central_cache: &CENTRAL_CACHE as *const _ as *const u8,
+    central_sizes: [
+        size_of::<CentralCache>() as u64,
+        size_of::<CachePadded<SpinMutex<CentralFreeList>>>() as u64,
+        (CachePadded::<SpinMutex<CentralFreeList>>::DATA_OFFSET
+            + SpinMutex::<CentralFreeList>::DATA_OFFSET) as u64,
+        NUM_ARENAS as u64,
+    ],
+    transfer_cache: TRANSFER_CACHE.0,
+    transfer_cache_size: TRANSFER_CACHE.1,
+    mid_heap: &MID_HEAP as *const _ as *const u8,
+    mid_heap_size: size_of::<MidHeap>() as u64,
+    stats: STATS.0,
+    stats_size: STATS.1,
+};
+
+/// The table exported to introspection tools.
+pub fn introspection() -> &'static IntrospectionTable {
+    // SAFETY: as for `heap_layout`.
+    unsafe { &*(&INTROSPECTION as *const RawIntrospection as *const IntrospectionTable) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::string::String;
+
+    const SCRIPT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/scripts/rtmalloc_gdb.py");
+    const BEGIN: &str = "# --- BEGIN GENERATED (src/coredump.rs) ---\n";
+    const END: &str = "# --- END GENERATED ---\n";
+
+    fn generated_block() -> String {
+        let mut out = String::from(BEGIN);
+        out.push_str(&format!("MAGIC = {:#018x}\n", MAGIC));
+        out.push_str(&format!("LAYOUT_VERSION = {}\n", LAYOUT_VERSION));
+        out.push_str("FIELDS = [\n");
+        for name in FIELD_NAMES {
+            out.push_str(&format!("    \"{}\",\n", name));
+        }
+        out.push_str("]\n");
+        out.push_str("STAT_NAMES = [\n");
+        #[cfg(feature = "stats")]
+        for name in crate::stats::COUNTER_NAMES {
+            out.push_str(&format!("    \"{}\",\n", name));
+        }
+        out.push_str("]\n");
+        out.push_str(END);
+        out
+    }
+
+    #[test]
+    fn test_layout_matches_consts() {
+        let l = heap_layout();
+        assert_eq!(l.magic, MAGIC);
+        assert_eq!(l.layout_version, LAYOUT_VERSION);
+        assert_eq!(l.page_shift, PAGE_SHIFT as u64);
+        assert_eq!(l.pagemap_root, &PAGE_MAP as *const _ as u64);
+        assert_eq!(l.num_size_classes, NUM_SIZE_CLASSES as u64);
+        assert_eq!(l.span_state_in_use, SpanState::InUse as u64);
+        assert_eq!(l.stats == 0, !cfg!(feature = "stats"));
+    }
+
+    #[test]
+    fn test_introspection_points_at_statics() {
+        let t = introspection();
+        assert_eq!(t.magic, INTROSPECTION_MAGIC);
+        assert_eq!(t.version, INTROSPECTION_VERSION);
+        assert_eq!(t.table_size, size_of::<IntrospectionTable>() as u64);
+        assert_eq!(t.heap_layout, heap_layout() as *const _ as u64);
+        assert_eq!(t.page_map, &PAGE_MAP as *const _ as u64);
+        assert_eq!(t.central_cache, &CENTRAL_CACHE as *const _ as u64);
+        assert_eq!(t.mid_heap, &MID_HEAP as *const _ as u64);
+        assert_eq!(t.stats == 0, !cfg!(feature = "stats"));
+        assert_eq!(
+            t.transfer_cache == 0,
+            !cfg!(any(
+                feature = "percpu",
+                feature = "nightly",
+                feature = "std"
+            ))
+        );
+
+        // The offsets reach the guarded values.
+        let heap = &*PAGE_HEAP.lock() as *const PageHeap as u64;
+        assert_eq!(t.page_heap + t.page_heap_data, heap);
+        let (arena, cls) = (NUM_ARENAS as u64 - 1, 3);
+        let list = &*CENTRAL_CACHE.arena(arena as usize, cls).lock() as *const CentralFreeList;
+        let row = arena * NUM_SIZE_CLASSES as u64 + cls as u64;
+        assert_eq!(
+            t.central_cache + row * t.central_list_stride + t.central_list_data,
+            list as u64
+        );
+    }
+
+    #[test]
+    fn test_size_class_table_readable() {
+        let l = heap_layout();
+        for (cls, info) in Classes::CLASSES.iter().enumerate().skip(1) {
+            let addr = l.size_classes + cls as u64 * l.size_class_stride + l.size_class_size;
+            let size = unsafe { *core::ptr::with_exposed_provenance::<usize>(addr as usize) };
+            assert_eq!(size, info.size);
+        }
+    }
+
+    /// The script's constants block must match this module. Run with
+    /// `RTMALLOC_BLESS=1` to regenerate it.
+    #[test]
+    fn test_gdb_script_in_sync() {
+        // The stat names depend on the `stats` feature; only check the
+        // block in the configuration it was generated from.
+        if !cfg!(feature = "stats") {
+            return;
+        }
+        let script = std::fs::read_to_string(SCRIPT_PATH).unwrap();
+        let start = script.find(BEGIN).expect("missing BEGIN marker");
+        let end = script.find(END).expect("missing END marker") + END.len();
+        let expected = generated_block();
+        if std::env::var_os("RTMALLOC_BLESS").is_some() {
+            let updated = format!("{}{}{}", &script[..start], expected, &script[end..]);
+            std::fs::write(SCRIPT_PATH, updated).unwrap();
+            return;
+        }
+        assert_eq!(
+            &script[start..end],
+            expected,
+            "scripts/rtmalloc_gdb.py is stale; rerun with RTMALLOC_BLESS=1"
+        );
+    }
+}
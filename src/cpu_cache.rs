@@ -4,11 +4,21 @@
 //! When the slab is empty (alloc) or full (free), batches transfer through the
 //! existing TransferCache → CentralFreeList → PageHeap hierarchy.
 //!
+//! Slab slots store raw object pointers in allocator-owned memory, never in
+//! freed objects, so they are not subject to `safe-linking`. Chains built when
+//! refilling or draining the slab are linked with [`FreeObject::set_next`] and
+//! are protected like every other free list.
+//!
+//! With `stats`, every CPU also counts the operations its slab served and the
+//! critical sections that had to be retried (see [`cpu_stats`]).
+//!
 //! This module is only compiled when `feature = "percpu"` is active.
 
 use core::cell::UnsafeCell;
 use core::ptr;
 use core::sync::atomic::{AtomicPtr, Ordering};
+#[cfg(feature = "stats")]
+use core::sync::atomic::{AtomicU32, AtomicU64};
 
 use rseq::PerCpuSlab;
 
@@ -20,8 +30,11 @@ use crate::span::FreeObject;
 use crate::sync::SpinMutex;
 use crate::transfer_cache::TransferCacheArray;
 
+/// Slab with the compact 4-byte header; [`SHIFT`] keeps it within range.
+type Slab = PerCpuSlab<NUM_SIZE_CLASSES>;
+
 /// Wrapper so we can put PerCpuSlab in a static (it's Sync by rseq design).
-struct SlabCell(UnsafeCell<PerCpuSlab<NUM_SIZE_CLASSES>>);
+struct SlabCell(UnsafeCell<Slab>);
 unsafe impl Sync for SlabCell {}
 
 impl SlabCell {
@@ -31,14 +44,14 @@ impl SlabCell {
 
     /// Get a shared reference. Safe after initialization.
     #[inline(always)]
-    fn get(&self) -> &PerCpuSlab<NUM_SIZE_CLASSES> {
+    fn get(&self) -> &Slab {
         unsafe { &*self.0.get() }
     }
 
     /// Get a mutable reference. Only call during init (under lock).
     #[inline(always)]
     #[allow(clippy::mut_from_ref)]
-    unsafe fn get_mut(&self) -> &mut PerCpuSlab<NUM_SIZE_CLASSES> {
+    unsafe fn get_mut(&self) -> &mut Slab {
         unsafe { &mut *self.0.get() }
     }
 }
@@ -47,6 +60,33 @@ impl SlabCell {
 /// 46 classes × 32 slots × 8 bytes = ~11 KiB, well within 256 KiB.
 const SHIFT: u32 = 18;
 
+/// Per-class slab capacities, one batch each.
+const CAPACITIES: [u32; NUM_SIZE_CLASSES] = {
+    let mut caps = [0u32; NUM_SIZE_CLASSES];
+    let mut class = 1;
+    while class < NUM_SIZE_CLASSES {
+        caps[class] = size_class::class_info(class).batch_size as u32;
+        class += 1;
+    }
+    caps
+};
+
+const _: () = assert!(
+    SHIFT <= Slab::MAX_SHIFT,
+    "cpu_cache SHIFT too large for the slab header"
+);
+/// Smallest per-CPU region that still holds every class at full capacity.
+/// When the OS refuses `SHIFT` for all CPUs (no overcommit), the slab is set
+/// up with smaller regions, down to this one, rather than not at all.
+const MIN_SHIFT: u32 = Slab::layout_bytes(&CAPACITIES)
+    .next_power_of_two()
+    .trailing_zeros();
+
+const _: () = assert!(
+    MIN_SHIFT <= SHIFT,
+    "size class batch sizes do not fit in a per-CPU slab region"
+);
+
 /// `_SC_NPROCESSORS_CONF` on Linux x86_64.
 const _SC_NPROCESSORS_CONF: i32 = 83;
 
@@ -94,35 +134,89 @@ fn init_slow() {
     let num_cpus = unsafe { sysconf(_SC_NPROCESSORS_CONF) };
     let num_cpus = if num_cpus <= 0 { 1 } else { num_cpus as u32 };
 
-    // Allocate backing memory.
-    let region_size = (num_cpus as usize) << SHIFT;
-    let region = unsafe { crate::platform::page_alloc(region_size) };
-    if region.is_null() {
-        // Can't allocate — fall through to transfer cache on every call.
-        return;
-    }
-
-    // Build per-class capacities from batch_size.
-    let mut capacities = [0u16; NUM_SIZE_CLASSES];
-    for (class, cap) in capacities.iter_mut().enumerate().skip(1) {
-        *cap = size_class::class_info(class).batch_size as u16;
-    }
+    // Allocate backing memory, halving the regions while the OS refuses.
+    let mut shift = SHIFT;
+    let (region, region_size) = loop {
+        let region_size = (num_cpus as usize) << shift;
+        let region = unsafe { crate::platform::page_alloc(region_size) };
+        if !region.is_null() {
+            break (region, region_size);
+        }
+        if shift == MIN_SHIFT {
+            // Can't allocate — fall through to transfer cache on every call.
+            return;
+        }
+        shift -= 1;
+    };
 
-    let ok = unsafe {
+    let res = unsafe {
         CPU_SLAB
             .get_mut()
-            .init(region, num_cpus, SHIFT, &capacities)
+            .init(region, num_cpus, shift, &CAPACITIES)
     };
-    if !ok {
-        // Layout doesn't fit — shouldn't happen with shift=18.
+    if res.is_err() {
+        // Unreachable: the layout is checked at compile time.
         unsafe { crate::platform::page_dealloc(region, region_size) };
         return;
     }
 
+    #[cfg(feature = "stats")]
+    {
+        let bytes = num_cpus as usize * core::mem::size_of::<CpuCounters>();
+        let counters = unsafe { crate::platform::page_alloc(bytes) };
+        if !counters.is_null() {
+            // Zeroed pages are zeroed counters.
+            CPU_COUNTERS.store(counters.cast(), Ordering::Relaxed);
+            NUM_CPUS.store(num_cpus, Ordering::Relaxed);
+        }
+    }
+
     // Publish: all subsequent ensure_init() calls see non-null and skip.
     SLAB_REGION.store(region, Ordering::Release);
 }
 
+/// Pop from this CPU's slab, retrying once in case the first critical
+/// section was aborted rather than the slab being empty.
+#[inline(always)]
+unsafe fn slab_pop(rseq_ptr: *mut rseq::Rseq, class: usize) -> Option<*mut u8> {
+    #[cfg(feature = "stats")]
+    let cpu = current_cpu_id(rseq_ptr);
+    if let Some(ptr) = unsafe { CPU_SLAB.get().pop(rseq_ptr, class) } {
+        #[cfg(feature = "stats")]
+        count_on_cpu(rseq_ptr, |c| &c.allocs);
+        return Some(ptr);
+    }
+    let ptr = unsafe { CPU_SLAB.get().pop(rseq_ptr, class) }?;
+    #[cfg(feature = "stats")]
+    {
+        count_retry(rseq_ptr, cpu);
+        count_on_cpu(rseq_ptr, |c| &c.allocs);
+    }
+    Some(ptr)
+}
+
+/// Push onto this CPU's slab, retrying once like [`slab_pop`]. False if the
+/// slab is full.
+#[inline(always)]
+unsafe fn slab_push(rseq_ptr: *mut rseq::Rseq, class: usize, ptr: *mut u8) -> bool {
+    #[cfg(feature = "stats")]
+    let cpu = current_cpu_id(rseq_ptr);
+    if unsafe { CPU_SLAB.get().push(rseq_ptr, class, ptr) }.is_some() {
+        #[cfg(feature = "stats")]
+        count_on_cpu(rseq_ptr, |c| &c.frees);
+        return true;
+    }
+    if unsafe { CPU_SLAB.get().push(rseq_ptr, class, ptr) }.is_none() {
+        return false;
+    }
+    #[cfg(feature = "stats")]
+    {
+        count_retry(rseq_ptr, cpu);
+        count_on_cpu(rseq_ptr, |c| &c.frees);
+    }
+    true
+}
+
 /// Allocate an object of the given size class via the per-CPU cache.
 ///
 /// Fast path: single TLS load + inlined rseq pop (no locks, no atomics).
@@ -143,14 +237,9 @@ pub unsafe fn alloc(
     let rseq_ptr = unsafe { CACHED_RSEQ };
     if !rseq_ptr.is_null() {
         // Fast path: try popping from the slab.
-        unsafe {
-            if let Some(ptr) = CPU_SLAB.get().pop(rseq_ptr, class) {
-                return ptr;
-            }
-            // Could be rseq abort — retry once.
-            if let Some(ptr) = CPU_SLAB.get().pop(rseq_ptr, class) {
-                return ptr;
-            }
+        if let Some(ptr) = unsafe { slab_pop(rseq_ptr, class) } {
+            crate::stat_inc!(thread_cache_hits);
+            return ptr;
         }
         // Slab empty — refill and retry.
         return unsafe {
@@ -172,6 +261,7 @@ unsafe fn alloc_init(
     page_heap: &SpinMutex<PageHeap>,
     pagemap: &PageMap,
 ) -> *mut u8 {
+    crate::stat_inc!(thread_cache_misses);
     ensure_init();
 
     if !CPU_SLAB.get().is_initialized() {
@@ -191,10 +281,7 @@ unsafe fn alloc_init(
     unsafe { CACHED_RSEQ = rseq_ptr };
 
     unsafe {
-        if let Some(ptr) = CPU_SLAB.get().pop(rseq_ptr, class) {
-            return ptr;
-        }
-        if let Some(ptr) = CPU_SLAB.get().pop(rseq_ptr, class) {
+        if let Some(ptr) = slab_pop(rseq_ptr, class) {
             return ptr;
         }
         alloc_refill(class, rseq_ptr, transfer_cache, central, page_heap, pagemap)
@@ -212,13 +299,11 @@ unsafe fn alloc_refill(
     page_heap: &SpinMutex<PageHeap>,
     pagemap: &PageMap,
 ) -> *mut u8 {
+    crate::stat_inc!(thread_cache_misses);
     unsafe {
         refill(class, rseq_ptr, transfer_cache, central, page_heap, pagemap);
 
-        if let Some(ptr) = CPU_SLAB.get().pop(rseq_ptr, class) {
-            return ptr;
-        }
-        if let Some(ptr) = CPU_SLAB.get().pop(rseq_ptr, class) {
+        if let Some(ptr) = slab_pop(rseq_ptr, class) {
             return ptr;
         }
         alloc_from_central(class, transfer_cache, central, page_heap, pagemap)
@@ -246,14 +331,8 @@ pub unsafe fn dealloc(
     let rseq_ptr = unsafe { CACHED_RSEQ };
     if !rseq_ptr.is_null() {
         // Fast path: push onto the slab.
-        unsafe {
-            if CPU_SLAB.get().push(rseq_ptr, class, ptr).is_some() {
-                return;
-            }
-            // Could be rseq abort — retry once.
-            if CPU_SLAB.get().push(rseq_ptr, class, ptr).is_some() {
-                return;
-            }
+        if unsafe { slab_push(rseq_ptr, class, ptr) } {
+            return;
         }
         // Slab full — drain and retry.
         unsafe {
@@ -304,10 +383,7 @@ unsafe fn dealloc_init(
     unsafe { CACHED_RSEQ = rseq_ptr };
 
     unsafe {
-        if CPU_SLAB.get().push(rseq_ptr, class, ptr).is_some() {
-            return;
-        }
-        if CPU_SLAB.get().push(rseq_ptr, class, ptr).is_some() {
+        if slab_push(rseq_ptr, class, ptr) {
             return;
         }
         dealloc_drain(
@@ -337,10 +413,7 @@ unsafe fn dealloc_drain(
     unsafe {
         drain(class, rseq_ptr, transfer_cache, central, page_heap, pagemap);
 
-        if CPU_SLAB.get().push(rseq_ptr, class, ptr).is_some() {
-            return;
-        }
-        if CPU_SLAB.get().push(rseq_ptr, class, ptr).is_some() {
+        if slab_push(rseq_ptr, class, ptr) {
             return;
         }
         dealloc_to_central(ptr, class, transfer_cache, central, page_heap, pagemap)
@@ -359,9 +432,11 @@ unsafe fn refill(
     page_heap: &SpinMutex<PageHeap>,
     pagemap: &PageMap,
 ) {
-    let batch_size = size_class::class_info(class).batch_size;
+    let batch_size = size_class::batch_size(class);
+    #[cfg(feature = "stats")]
+    count_on_cpu(rseq_ptr, |c| &c.refills);
 
-    let (count, head) =
+    let (count, head, tail) =
         unsafe { transfer_cache.remove_range(class, batch_size, central, page_heap, pagemap) };
 
     if count == 0 || head.is_null() {
@@ -374,7 +449,7 @@ unsafe fn refill(
         if node.is_null() {
             break;
         }
-        let next = unsafe { (*node).next };
+        let next = unsafe { FreeObject::next(node) };
         // Push into slab. Retry a few times for rseq aborts, then stop.
         let mut ok = false;
         for _ in 0..4 {
@@ -384,17 +459,9 @@ unsafe fn refill(
             }
         }
         if !ok {
-            // Slab full or persistent aborts — return remaining objects
-            // to the transfer cache. Re-link the unpushed tail.
-            unsafe { (*node).next = next };
+            // Slab full or persistent aborts — return the unpushed rest of
+            // the batch, which still ends at `tail`, to the transfer cache.
             let remaining = count - pushed;
-            // Find the tail of the remaining chain.
-            let mut tail = node;
-            let mut walk = next;
-            while !walk.is_null() {
-                tail = walk;
-                walk = unsafe { (*walk).next };
-            }
             unsafe {
                 transfer_cache
                     .insert_range(class, node, tail, remaining, central, page_heap, pagemap)
@@ -417,7 +484,11 @@ unsafe fn drain(
     page_heap: &SpinMutex<PageHeap>,
     pagemap: &PageMap,
 ) {
-    let batch_size = size_class::class_info(class).batch_size;
+    let batch_size = size_class::batch_size(class);
+    #[cfg(feature = "tracing")]
+    let start = std::time::Instant::now();
+    #[cfg(feature = "stats")]
+    count_on_cpu(rseq_ptr, |c| &c.drains);
 
     // Pop pointers from the slab into a linked list.
     let mut head: *mut FreeObject = ptr::null_mut();
@@ -434,7 +505,7 @@ unsafe fn drain(
         match ptr {
             Some(p) => {
                 let obj = p as *mut FreeObject;
-                unsafe { (*obj).next = head };
+                unsafe { FreeObject::set_next(obj, head) };
                 if tail.is_null() {
                     tail = obj;
                 }
@@ -447,13 +518,192 @@ unsafe fn drain(
 
     if count > 0 && !head.is_null() {
         // Null-terminate the tail.
-        unsafe { (*tail).next = ptr::null_mut() };
+        unsafe { FreeObject::set_next(tail, ptr::null_mut()) };
         unsafe {
             transfer_cache.insert_range(class, head, tail, count, central, page_heap, pagemap)
         };
+        #[cfg(feature = "tracing")]
+        crate::tracing_events::record(crate::tracing_events::Event::CpuDrain {
+            size_class: class,
+            objects: count,
+            bytes: count * size_class::class_to_size(class),
+            nanos: start.elapsed().as_nanos() as u64,
+        });
     }
 }
 
+/// Move every object cached in every CPU's slab to the transfer cache and
+/// return the bytes moved.
+///
+/// Each CPU is stopped in turn (see [`PerCpuSlab::stop_cpu`]), so threads
+/// running there meanwhile go to the transfer cache as if their slab were
+/// empty or full. Stops early, keeping what is left, if the kernel cannot
+/// fence other CPUs.
+#[cfg(feature = "pressure")]
+pub(crate) fn drain_all(
+    transfer_cache: &TransferCacheArray,
+    central: &CentralCache,
+    page_heap: &SpinMutex<PageHeap>,
+    pagemap: &PageMap,
+) -> usize {
+    if SLAB_REGION.load(Ordering::Acquire).is_null() {
+        return 0;
+    }
+    let slab = CPU_SLAB.get();
+    let mut bytes = 0;
+    for cpu in 0..slab.num_cpus() {
+        // (head, tail, count) of what each class held.
+        let mut chains: [(*mut FreeObject, *mut FreeObject, usize); NUM_SIZE_CLASSES] =
+            [(ptr::null_mut(), ptr::null_mut(), 0); NUM_SIZE_CLASSES];
+        {
+            // Safety: the slab is initialized, and only critical sections
+            // touch it outside this function.
+            let Ok(mut stopped) = (unsafe { slab.stop_cpu(cpu) }) else {
+                break;
+            };
+            for (class, chain) in chains.iter_mut().enumerate().skip(1) {
+                stopped.drain(class, |p| {
+                    let obj = p as *mut FreeObject;
+                    unsafe { FreeObject::set_next(obj, chain.0) };
+                    if chain.1.is_null() {
+                        chain.1 = obj;
+                    }
+                    chain.0 = obj;
+                    chain.2 += 1;
+                });
+            }
+        }
+        // Hand the objects over once the CPU runs again, so its threads
+        // don't wait on the transfer cache locks taken here.
+        for (class, &(head, tail, count)) in chains.iter().enumerate() {
+            if count > 0 {
+                bytes += count * size_class::class_to_size(class);
+                unsafe {
+                    transfer_cache
+                        .insert_range(class, head, tail, count, central, page_heap, pagemap)
+                };
+            }
+        }
+    }
+    bytes
+}
+
+/// One CPU's counters, on a cache line of its own.
+#[cfg(feature = "stats")]
+#[repr(align(64))]
+struct CpuCounters {
+    allocs: AtomicU64,
+    frees: AtomicU64,
+    refills: AtomicU64,
+    drains: AtomicU64,
+    migrations: AtomicU64,
+    preemptions: AtomicU64,
+}
+
+/// `NUM_CPUS` counter blocks, allocated with the slab. Null until then, or
+/// if that allocation failed.
+#[cfg(feature = "stats")]
+static CPU_COUNTERS: AtomicPtr<CpuCounters> = AtomicPtr::new(ptr::null_mut());
+#[cfg(feature = "stats")]
+static NUM_CPUS: AtomicU32 = AtomicU32::new(0);
+
+/// The CPU this thread is on now, per its rseq area.
+#[cfg(feature = "stats")]
+#[inline(always)]
+fn current_cpu_id(rseq_ptr: *mut rseq::Rseq) -> u32 {
+    unsafe { ptr::read_volatile(ptr::addr_of!((*rseq_ptr).cpu_id)) }
+}
+
+/// Bump one of the current CPU's counters. The thread may move CPUs right
+/// after; the count then lands on the CPU it just left, which is fine for
+/// statistics.
+#[cfg(feature = "stats")]
+#[inline(always)]
+fn count_on_cpu(rseq_ptr: *mut rseq::Rseq, field: impl FnOnce(&CpuCounters) -> &AtomicU64) {
+    let cpu = current_cpu_id(rseq_ptr);
+    let counters = CPU_COUNTERS.load(Ordering::Relaxed);
+    if !counters.is_null() && cpu < NUM_CPUS.load(Ordering::Relaxed) {
+        field(unsafe { &*counters.add(cpu as usize) }).fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A slab operation only went through on its second try. If the thread is
+/// now on another CPU than `before`, the first try was aborted by a
+/// migration; otherwise by preemption or a signal (or the slab changed in
+/// between, which also takes a preemption). Charged to the CPU it ran on
+/// first.
+#[cfg(feature = "stats")]
+#[cold]
+fn count_retry(rseq_ptr: *mut rseq::Rseq, before: u32) {
+    let counters = CPU_COUNTERS.load(Ordering::Relaxed);
+    if counters.is_null() || before >= NUM_CPUS.load(Ordering::Relaxed) {
+        return;
+    }
+    let c = unsafe { &*counters.add(before as usize) };
+    if current_cpu_id(rseq_ptr) != before {
+        c.migrations.fetch_add(1, Ordering::Relaxed);
+    } else {
+        c.preemptions.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// What one CPU's slab has done since the slab was set up.
+#[cfg(feature = "stats")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CpuStats {
+    pub cpu: u32,
+    /// Allocations served from this CPU's slab.
+    pub allocs: u64,
+    /// Frees that went into this CPU's slab.
+    pub frees: u64,
+    /// Times the slab ran empty and took a batch from the transfer cache.
+    pub refills: u64,
+    /// Times the slab ran full and gave a batch to the transfer cache.
+    pub drains: u64,
+    /// Slab operations retried because the thread moved to another CPU
+    /// mid-operation.
+    pub migrations: u64,
+    /// Slab operations retried after preemption or a signal on this CPU.
+    pub preemptions: u64,
+}
+
+/// Counters of `cpu`, or None if it is out of range or the slab has not
+/// been set up yet (no thread has allocated through it).
+#[cfg(feature = "stats")]
+pub fn cpu_stats(cpu: u32) -> Option<CpuStats> {
+    let counters = CPU_COUNTERS.load(Ordering::Relaxed);
+    if counters.is_null() || cpu >= NUM_CPUS.load(Ordering::Relaxed) {
+        return None;
+    }
+    let c = unsafe { &*counters.add(cpu as usize) };
+    Some(CpuStats {
+        cpu,
+        allocs: c.allocs.load(Ordering::Relaxed),
+        frees: c.frees.load(Ordering::Relaxed),
+        refills: c.refills.load(Ordering::Relaxed),
+        drains: c.drains.load(Ordering::Relaxed),
+        migrations: c.migrations.load(Ordering::Relaxed),
+        preemptions: c.preemptions.load(Ordering::Relaxed),
+    })
+}
+
+/// CPUs with counters: those configured when the slab was set up, or 0
+/// before.
+#[cfg(feature = "stats")]
+pub fn num_cpus() -> u32 {
+    NUM_CPUS.load(Ordering::Relaxed)
+}
+
+/// Log2 of the bytes of each CPU's slab region: 18 (256 KiB), or less
+/// if the OS refused that much when the slab was set up. None before that,
+/// or if no region could be mapped at all.
+pub fn slab_shift() -> Option<u32> {
+    if SLAB_REGION.load(Ordering::Acquire).is_null() {
+        return None;
+    }
+    Some(CPU_SLAB.get().shift())
+}
+
 /// Allocate directly from the transfer/central cache (rseq not available).
 #[cold]
 unsafe fn alloc_from_central(
@@ -463,13 +713,12 @@ unsafe fn alloc_from_central(
     page_heap: &SpinMutex<PageHeap>,
     pagemap: &PageMap,
 ) -> *mut u8 {
-    let (count, head) =
+    let (count, head, _) =
         unsafe { transfer_cache.remove_range(class, 1, central, page_heap, pagemap) };
     if count == 0 || head.is_null() {
-        ptr::null_mut()
-    } else {
-        head as *mut u8
+        return ptr::null_mut();
     }
+    head as *mut u8
 }
 
 /// Free directly to the transfer/central cache (rseq not available).
@@ -483,6 +732,6 @@ unsafe fn dealloc_to_central(
     pagemap: &PageMap,
 ) {
     let obj = ptr as *mut FreeObject;
-    unsafe { (*obj).next = ptr::null_mut() };
+    unsafe { FreeObject::set_next(obj, ptr::null_mut()) };
     unsafe { transfer_cache.insert_range(class, obj, obj, 1, central, page_heap, pagemap) };
 }
@@ -0,0 +1,135 @@
+//! Inspecting where cached memory sits.
+//!
+//! When a process holds more memory than its live objects need, the rest is
+//! spread over the thread caches, the transfer cache, the central free lists
+//! and the page heap. These calls show the calling thread's share, and
+//! [`bypass_caches`] takes the caching layers out of the picture entirely.
+//!
+//! ```ignore
+//! // On a thread suspected of hoarding:
+//! rtmalloc::debug::print_thread_cache();
+//! ```
+
+use crate::size_class;
+use crate::thread_cache::ThreadCacheInfo;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::println;
+
+/// Contents of the calling thread's cache, per size class.
+///
+/// None if the thread has no active cache: with `percpu` or without a thread
+/// cache (neither `nightly` nor `std`), before the thread's first allocation,
+/// after it exited, or when called from inside the allocator.
+///
+/// Copies the cache's counters without allocating, so it leaves the cache as
+/// it found it.
+pub fn dump_thread_cache() -> Option<ThreadCacheInfo> {
+    let _guard = crate::bootstrap::ReentrancyGuard::enter()?;
+    crate::allocator::thread_cache_info()
+}
+
+/// Print [`dump_thread_cache`] to stdout.
+#[cfg(feature = "std")]
+pub fn print_thread_cache() {
+    match dump_thread_cache() {
+        Some(info) => println!("{info}"),
+        None => println!("no thread cache on this thread"),
+    }
+}
+
+/// Per-CPU slab counters, one entry per CPU (`percpu` with `stats`).
+///
+/// Shows which CPUs serve the process's allocations and how often a slab
+/// operation was cut short by a migration or by preemption, to line
+/// allocator behaviour up with scheduler affinity settings. Empty until the
+/// first allocation sets the slab up.
+#[cfg(all(feature = "percpu", feature = "stats"))]
+pub fn cpu_stats() -> impl Iterator<Item = crate::cpu_cache::CpuStats> {
+    (0..crate::cpu_cache::num_cpus()).filter_map(crate::cpu_cache::cpu_stats)
+}
+
+/// Print [`cpu_stats`] to stdout, skipping CPUs the slab was never used on.
+#[cfg(all(feature = "percpu", feature = "stats", feature = "std"))]
+pub fn print_cpu_stats() {
+    println!(
+        "  {:>4} {:>12} {:>12} {:>9} {:>9} {:>10} {:>11}",
+        "cpu", "allocs", "frees", "refills", "drains", "migrations", "preemptions"
+    );
+    for c in cpu_stats().filter(|c| c.allocs + c.frees > 0) {
+        println!("{c}");
+    }
+}
+
+#[cfg(all(feature = "percpu", feature = "stats"))]
+impl fmt::Display for crate::cpu_cache::CpuStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "  {:>4} {:>12} {:>12} {:>9} {:>9} {:>10} {:>11}",
+            self.cpu,
+            self.allocs,
+            self.frees,
+            self.refills,
+            self.drains,
+            self.migrations,
+            self.preemptions
+        )
+    }
+}
+
+/// Send every allocation past the caches (`true`), or back through them.
+///
+/// While on, small objects are taken from and returned straight to the
+/// central free lists, skipping the thread or per-CPU caches and the transfer
+/// cache, and large allocations skip the mid-heap. A bug or an RSS problem
+/// that goes away with it on lies in the caching layers; one that stays lies
+/// in the central lists or the page heap. It also shows memory checkers such
+/// as Valgrind each free when it happens. Every allocation takes a lock, so
+/// expect it to be several times slower.
+///
+/// Objects the caches already hold stay there until the caches are used
+/// again. Setting `RTMALLOC_BYPASS_CACHES=1` in the environment turns it on
+/// from the first allocation (Unix only).
+pub fn bypass_caches(enabled: bool) {
+    crate::allocator::set_caches_bypassed(enabled);
+}
+
+/// Whether [`bypass_caches`] or `RTMALLOC_BYPASS_CACHES` has the caches
+/// bypassed.
+pub fn caches_bypassed() -> bool {
+    crate::allocator::caches_bypassed()
+}
+
+/// One line per size class holding objects or with a grown limit.
+impl fmt::Display for ThreadCacheInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "thread cache: {} of {} bytes",
+            self.total_bytes, self.max_bytes
+        )?;
+        writeln!(
+            f,
+            "  {:>5} {:>8} {:>8} {:>6} {:>8} {:>9} {:>10}",
+            "class", "size", "length", "array", "max_len", "low_water", "bytes"
+        )?;
+        for (cls, c) in self.classes.iter().enumerate().skip(1) {
+            if c.length == 0 && c.array_length == 0 && c.max_length <= 1 {
+                continue;
+            }
+            writeln!(
+                f,
+                "  {:>5} {:>8} {:>8} {:>6} {:>8} {:>9} {:>10}",
+                cls,
+                size_class::class_to_size(cls),
+                c.length,
+                c.array_length,
+                c.max_length,
+                c.low_water_mark,
+                c.bytes
+            )?;
+        }
+        Ok(())
+    }
+}
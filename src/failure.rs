@@ -0,0 +1,448 @@
+//! What to do when an internal invariant fails.
+//!
+//! A few checks inside the allocator guard against states that should never
+//! happen, or that only happen when memory for the allocator's own metadata
+//! runs out: a zero-page span request, a span carved past its end, a page
+//! outside the range the page map covers, a page map node that could not be
+//! mapped, a heap region that could not be placed at its configured
+//! `heap_base`. By default each of these aborts the process with a message.
+//!
+//! Embedders that would rather see the allocation fail can pick
+//! [`Policy::ReturnNull`]: the check returns null up the stack instead, and
+//! the allocator stays usable. [`set_handler`] registers a function that is
+//! called with the [`Failure`] first; if it returns, the allocation fails as
+//! with `ReturnNull`.
+//!
+//! Free list corruption is always fatal, since continuing would hand out an
+//! attacker-chosen address. A registered handler still sees it before the
+//! process aborts. So is lock misuse caught by the `lock-debug` feature (see
+//! [`crate::sync`]), whose message also names where the lock was taken, and
+//! a double free caught by the `double-free-check` feature.
+//!
+//! A free whose layout does not match the object, caught by the
+//! `layout-check` feature (see [`crate::layout_check`]), is the opposite: the
+//! allocator frees the object by its real size class and carries on, after
+//! printing the mismatch and passing it to a registered handler.
+//!
+//! ```ignore
+//! extern "C" fn on_failure(failure: rtmalloc::failure::Failure, detail: usize) {
+//!     log_to_flash(failure.message(), detail);
+//! }
+//! rtmalloc::failure::set_handler(Some(on_failure));
+//! ```
+//!
+//! Running out of memory is not an invariant failure: the allocation just
+//! returns null. [`last_error`] tells why, with the error of the last
+//! request for memory the OS refused.
+
+use core::sync::atomic::{AtomicI32, AtomicU8, AtomicUsize, Ordering};
+
+/// How recoverable invariant failures are handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Policy {
+    /// Print a message to stderr and abort. The default.
+    Abort = 0,
+    /// Fail the allocation that hit the check and carry on.
+    ReturnNull = 1,
+    /// Call the registered handler, then fail the allocation. Aborts if no
+    /// handler is registered.
+    Handler = 2,
+}
+
+/// An internal invariant that failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub enum Failure {
+    /// The page heap was asked for a span of zero pages.
+    ZeroPageSpan = 0,
+    /// A span was carved into more pages than it holds.
+    SpanTooSmall = 1,
+    /// A page lies outside the address range the page map covers.
+    PageOutOfRange = 2,
+    /// The page map could not map a node to record a span.
+    PageMapNode = 3,
+    /// A free list link failed validation. Always fatal.
+    CorruptedFreeList = 4,
+    /// A thread took an allocator lock it already holds, e.g. from a signal
+    /// handler or hook that allocates (`lock-debug`). Always fatal.
+    LockReentered = 5,
+    /// An allocator lock was released by a panic (`lock-debug` with `std`).
+    /// Always fatal.
+    LockPoisoned = 6,
+    /// A free's layout maps to another size class than the object's
+    /// (`layout-check`). Never fatal.
+    LayoutMismatch = 7,
+    /// The `max_heap` region could not be reserved at `heap_base`, which
+    /// another mapping already covers. The detail is `heap_base`.
+    HeapPlacement = 8,
+    /// An object was freed into the thread cache that already holds it
+    /// (`double-free-check`). Always fatal.
+    DoubleFree = 9,
+}
+
+impl Failure {
+    /// Short description, as printed when aborting.
+    pub const fn message(self) -> &'static str {
+        match self {
+            Failure::ZeroPageSpan => "zero-page span requested",
+            Failure::SpanTooSmall => "span carved past its end",
+            Failure::PageOutOfRange => "page_id out of range for page map",
+            Failure::PageMapNode => "failed to allocate page map node",
+            Failure::CorruptedFreeList => "corrupted free list link",
+            Failure::LockReentered => "lock re-entered by the thread holding it",
+            Failure::LockPoisoned => "lock poisoned by a panic",
+            Failure::LayoutMismatch => "dealloc layout does not match the allocation",
+            Failure::HeapPlacement => "heap region could not be reserved at heap_base",
+            Failure::DoubleFree => "double free of an object in the thread cache",
+        }
+    }
+}
+
+/// Called with the failure and a related address or page id (0 if none).
+///
+/// It runs inside the allocator, possibly with allocator locks held, so it
+/// must not allocate or free through rtmalloc.
+pub type Handler = extern "C" fn(Failure, usize);
+
+static POLICY: AtomicU8 = AtomicU8::new(Policy::Abort as u8);
+/// Registered [`Handler`] as an address, 0 when none.
+static HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Choose how recoverable invariant failures are handled.
+pub fn set_policy(policy: Policy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// The current failure policy.
+pub fn policy() -> Policy {
+    match POLICY.load(Ordering::Relaxed) {
+        1 => Policy::ReturnNull,
+        2 => Policy::Handler,
+        _ => Policy::Abort,
+    }
+}
+
+/// Register `handler` and switch to [`Policy::Handler`]. `None` removes the
+/// handler and goes back to [`Policy::Abort`].
+pub fn set_handler(handler: Option<Handler>) {
+    HANDLER.store(handler.map_or(0, |h| h as usize), Ordering::Release);
+    set_policy(if handler.is_some() {
+        Policy::Handler
+    } else {
+        Policy::Abort
+    });
+}
+
+fn handler() -> Option<Handler> {
+    match HANDLER.load(Ordering::Acquire) {
+        0 => None,
+        addr => Some(unsafe { core::mem::transmute::<usize, Handler>(addr) }),
+    }
+}
+
+/// `ENOMEM`: no memory to be had.
+pub const ENOMEM: i32 = 12;
+
+static LAST_ERROR: AtomicI32 = AtomicI32::new(0);
+
+/// Error code of the latest request for memory the OS refused, or 0 if none
+/// was refused since the process started or [`clear_last_error`] ran.
+///
+/// On Unix it is the `errno` of the failed `mmap`, usually [`ENOMEM`]. With
+/// overcommit disabled (`vm.overcommit_memory = 2` on Linux) that happens
+/// as soon as the commit limit is reached, however much memory is free; the
+/// allocator then asks for less before it gives up. On Windows it is
+/// `GetLastError()`. A used-up `max_heap` region records `ENOMEM` as well.
+/// The code is process-wide, not per thread.
+pub fn last_error() -> i32 {
+    LAST_ERROR.load(Ordering::Relaxed)
+}
+
+/// Reset [`last_error`] to 0.
+pub fn clear_last_error() {
+    LAST_ERROR.store(0, Ordering::Relaxed);
+}
+
+pub(crate) fn set_last_error(code: i32) {
+    LAST_ERROR.store(code, Ordering::Relaxed);
+}
+
+/// Report a failure the caller can recover from by failing its allocation.
+/// Returns only if the policy allows that.
+#[cold]
+#[inline(never)]
+pub(crate) fn report(failure: Failure, detail: usize) {
+    match policy() {
+        Policy::ReturnNull => {}
+        Policy::Handler => match handler() {
+            Some(h) => h(failure, detail),
+            None => die(failure, detail),
+        },
+        Policy::Abort => die(failure, detail),
+    }
+}
+
+/// Report a failure the allocator cannot continue from.
+#[cold]
+#[inline(never)]
+pub(crate) fn fatal(failure: Failure, detail: usize) -> ! {
+    if policy() == Policy::Handler
+        && let Some(h) = handler()
+    {
+        h(failure, detail);
+    }
+    die(failure, detail)
+}
+
+fn die(failure: Failure, detail: usize) -> ! {
+    cfg_if::cfg_if! {
+        if #[cfg(test)] {
+            panic!("rtmalloc: {} ({:#x})", failure.message(), detail);
+        } else {
+            let mut line = Line::new(failure, detail);
+            line.push(b"\n");
+            line.print();
+            crate::platform::abort()
+        }
+    }
+}
+
+/// Report misuse of the lock at `lock`: the holder took it at `held`, and it
+/// is taken again at `at`. Always fatal.
+#[cfg(feature = "lock-debug")]
+#[cold]
+#[inline(never)]
+pub(crate) fn lock_fatal(
+    failure: Failure,
+    lock: usize,
+    held: &core::panic::Location<'_>,
+    at: &core::panic::Location<'_>,
+) -> ! {
+    if policy() == Policy::Handler
+        && let Some(h) = handler()
+    {
+        h(failure, lock);
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(test)] {
+            panic!("rtmalloc: {} ({lock:#x}), held since {held}, taken at {at}", failure.message());
+        } else {
+            let mut line = Line::new(failure, lock);
+            line.push(b", held since ");
+            line.push_location(held);
+            line.push(b", taken at ");
+            line.push_location(at);
+            line.push(b"\n");
+            line.print();
+            crate::platform::abort()
+        }
+    }
+}
+
+/// Report a free of `ptr` whose layout (`size` at `align`) maps to
+/// `layout_class`, while the object is of `class`, `usable` bytes from
+/// `ptr` on. Class 0 is a large allocation. `callers` are return addresses
+/// on the freeing thread's stack, innermost first.
+#[cfg(feature = "layout-check")]
+#[cold]
+#[inline(never)]
+pub(crate) fn layout_mismatch(
+    ptr: usize,
+    size: usize,
+    align: usize,
+    layout_class: usize,
+    class: usize,
+    usable: usize,
+    callers: &[usize],
+) {
+    let mut line = Line::new(Failure::LayoutMismatch, ptr);
+    line.push(b": size ");
+    line.push_decimal(size);
+    line.push(b" align ");
+    line.push_decimal(align);
+    line.push(b" is ");
+    line.push_class(layout_class, None);
+    line.push(b", the object is ");
+    line.push_class(class, Some(usable));
+    if !callers.is_empty() {
+        line.push(b", freed from");
+    }
+    for &pc in callers {
+        line.push(b" ");
+        line.push_code_address(pc);
+    }
+    line.push(b"\n");
+    line.print();
+    if policy() == Policy::Handler
+        && let Some(h) = handler()
+    {
+        h(Failure::LayoutMismatch, ptr);
+    }
+}
+
+/// One line of stderr output built in a stack buffer: neither formatting
+/// machinery nor `std`'s stderr, since the failure may come from inside
+/// `std`'s own allocations, or from a constructor before `main`. Too long a
+/// line is cut short.
+#[cfg_attr(test, allow(dead_code))]
+struct Line {
+    buf: [u8; 512],
+    len: usize,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl Line {
+    /// `rtmalloc: <message> (0x<detail>)`.
+    fn new(failure: Failure, detail: usize) -> Self {
+        let mut line = Line {
+            buf: [0; 512],
+            len: 0,
+        };
+        line.push(b"rtmalloc: ");
+        line.push(failure.message().as_bytes());
+        line.push(b" (");
+        line.push_hex(detail);
+        line.push(b")");
+        line
+    }
+
+    /// `0x<value>`, without leading zeros.
+    fn push_hex(&mut self, value: usize) {
+        self.push(b"0x");
+        let digits = (usize::BITS - (value | 1).leading_zeros()).div_ceil(4);
+        for i in (0..digits).rev() {
+            self.push(&[b"0123456789abcdef"[(value >> (i * 4)) & 0xf]]);
+        }
+    }
+
+    /// `<file name>+0x<offset>` for `addr2line -e <file>`, or the bare
+    /// address if the module containing it is unknown.
+    #[cfg(feature = "layout-check")]
+    fn push_code_address(&mut self, addr: usize) {
+        match crate::platform::module_offset(addr) {
+            Some((path, offset)) => {
+                let path = path.to_bytes();
+                let name = match path.iter().rposition(|&b| b == b'/') {
+                    Some(slash) => &path[slash + 1..],
+                    None => path,
+                };
+                self.push(name);
+                self.push(b"+");
+                self.push_hex(offset);
+            }
+            None => self.push_hex(addr),
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let n = bytes.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+    }
+
+    #[cfg(any(feature = "lock-debug", feature = "layout-check"))]
+    fn push_decimal(&mut self, mut value: usize) {
+        let mut digits = [0u8; 20];
+        let mut start = digits.len();
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (value % 10) as u8;
+            value /= 10;
+            if value == 0 {
+                break;
+            }
+        }
+        self.push(&digits[start..]);
+    }
+
+    /// `file:line:column`.
+    #[cfg(feature = "lock-debug")]
+    fn push_location(&mut self, location: &core::panic::Location<'_>) {
+        self.push(location.file().as_bytes());
+        self.push(b":");
+        self.push_decimal(location.line() as usize);
+        self.push(b":");
+        self.push_decimal(location.column() as usize);
+    }
+
+    /// `class 5 (64 bytes)`, or `a large allocation` for class 0, with
+    /// `(N bytes)` if `bytes` is given.
+    #[cfg(feature = "layout-check")]
+    fn push_class(&mut self, class: usize, bytes: Option<usize>) {
+        let bytes = match class {
+            0 => {
+                self.push(b"a large allocation");
+                bytes
+            }
+            _ => {
+                self.push(b"class ");
+                self.push_decimal(class);
+                Some(crate::size_class::class_to_size(class))
+            }
+        };
+        if let Some(bytes) = bytes {
+            self.push(b" (");
+            self.push_decimal(bytes);
+            self.push(b" bytes)");
+        }
+    }
+
+    fn print(&self) {
+        crate::platform::write_stderr(&self.buf[..self.len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page_heap::PageHeap;
+    use crate::pagemap::PageMap;
+    use alloc::boxed::Box;
+    use core::sync::atomic::AtomicUsize;
+
+    static SEEN: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn count_failure(failure: Failure, _detail: usize) {
+        assert_eq!(failure, Failure::ZeroPageSpan);
+        SEEN.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // One test: the policy is process-wide.
+    #[test]
+    fn test_policies() {
+        let pm = Box::leak(Box::new(PageMap::new()));
+        let mut heap = PageHeap::new(pm);
+
+        set_policy(Policy::ReturnNull);
+        assert!(unsafe { heap.allocate_span(0) }.is_null());
+
+        // A span the page map cannot cover fails to register, and nothing
+        // of it is left behind.
+        let far = crate::span::alloc_span();
+        unsafe {
+            (*far).start_page = usize::MAX >> 12;
+            (*far).num_pages = 2;
+            assert!(!pm.register_span(far));
+            assert!(pm.get((*far).start_page).is_null());
+            crate::span::dealloc_span(far);
+        }
+
+        set_handler(Some(count_failure));
+        assert_eq!(policy(), Policy::Handler);
+        assert!(unsafe { heap.allocate_span(0) }.is_null());
+        assert_eq!(SEEN.load(Ordering::Relaxed), 1);
+
+        // The heap still works after a failed request.
+        let span = unsafe { heap.allocate_span(1) };
+        assert!(!span.is_null());
+        unsafe { heap.deallocate_span(span) };
+
+        set_handler(None);
+        assert_eq!(policy(), Policy::Abort);
+        let aborted = std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| unsafe {
+            heap.allocate_span(0)
+        }));
+        assert!(aborted.is_err());
+    }
+}
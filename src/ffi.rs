@@ -10,20 +10,557 @@
 //! Without `testing`, exports plain `rtmalloc_*` names.
 
 use crate::allocator::RtMalloc;
+use crate::platform;
+use crate::version;
 use core::alloc::{GlobalAlloc, Layout};
+use core::ffi::{c_char, c_void};
+use core::fmt;
+use core::ptr;
 
 static ALLOC: RtMalloc = RtMalloc;
 
+const EINVAL: i32 = 22;
+
+/// `size` and `align` from a C caller as a `Layout`, or None with `errno`
+/// set to `EINVAL`.
+fn checked_layout(size: usize, align: usize) -> Option<Layout> {
+    let layout = Layout::from_size_align(size, align).ok();
+    if layout.is_none() {
+        platform::set_errno(EINVAL);
+    }
+    layout
+}
+
 // Note: percpu implies nightly, so the percpu check must come first.
 
 #[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
 #[cfg_attr(
     all(feature = "testing", feature = "percpu"),
-    unsafe(export_name = "rtmalloc_percpu_alloc")
+    unsafe(export_name = "rtmalloc_percpu_alloc")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_alloc")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_alloc")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_alloc")
+)]
+/// Allocate `size` bytes aligned to `align`. Returns null, with `errno` set
+/// to `EINVAL`, if `align` is not a power of two or `size` rounded up to it
+/// overflows `isize`; null on out of memory.
+pub extern "C" fn rtmalloc_alloc(size: usize, align: usize) -> *mut u8 {
+    match checked_layout(size, align) {
+        Some(layout) => unsafe { ALLOC.alloc(layout) },
+        None => ptr::null_mut(),
+    }
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_dealloc")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_dealloc")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_dealloc")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_dealloc")
+)]
+/// Free `ptr`. Null, or a `size`/`align` pair `rtmalloc_alloc` would have
+/// rejected, is ignored (with `errno` set to `EINVAL` for the latter).
+///
+/// # Safety
+///
+/// A non-null `ptr` must have been returned by `rtmalloc_alloc` with the same `size`/`align`.
+pub unsafe extern "C" fn rtmalloc_dealloc(ptr: *mut u8, size: usize, align: usize) {
+    if ptr.is_null() {
+        return;
+    }
+    if let Some(layout) = checked_layout(size, align) {
+        unsafe { ALLOC.dealloc(ptr, layout) }
+    }
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_realloc")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_realloc")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_realloc")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_realloc")
+)]
+/// Resize `ptr` to `new_size` bytes. Returns null, leaving `ptr` as it was,
+/// on out of memory, or with `errno` set to `EINVAL` if either size is
+/// invalid with `align` as for `rtmalloc_alloc`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `rtmalloc_alloc` with the same `size`/`align`.
+pub unsafe extern "C" fn rtmalloc_realloc(
+    ptr: *mut u8,
+    size: usize,
+    align: usize,
+    new_size: usize,
+) -> *mut u8 {
+    match (checked_layout(size, align), checked_layout(new_size, align)) {
+        (Some(layout), Some(_)) => unsafe { ALLOC.realloc(ptr, layout, new_size) },
+        _ => ptr::null_mut(),
+    }
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_alloc_unchecked")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_alloc_unchecked")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_alloc_unchecked")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_alloc_unchecked")
+)]
+/// [`rtmalloc_alloc`] without the layout checks, for callers that already
+/// hold a valid Rust `Layout`.
+///
+/// # Safety
+///
+/// `align` must be a power of two, and `size` rounded up to `align` must
+/// not overflow `isize`.
+pub unsafe extern "C" fn rtmalloc_alloc_unchecked(size: usize, align: usize) -> *mut u8 {
+    let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
+    unsafe { ALLOC.alloc(layout) }
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_dealloc_unchecked")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_dealloc_unchecked")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_dealloc_unchecked")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_dealloc_unchecked")
+)]
+/// [`rtmalloc_dealloc`] without the layout checks.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `rtmalloc_alloc` or
+/// `rtmalloc_alloc_unchecked` with the same `size`/`align`.
+pub unsafe extern "C" fn rtmalloc_dealloc_unchecked(ptr: *mut u8, size: usize, align: usize) {
+    let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
+    unsafe { ALLOC.dealloc(ptr, layout) }
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_realloc_unchecked")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_realloc_unchecked")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_realloc_unchecked")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_realloc_unchecked")
+)]
+/// [`rtmalloc_realloc`] without the layout checks.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `rtmalloc_alloc` or
+/// `rtmalloc_alloc_unchecked` with the same `size`/`align`, and `new_size`
+/// rounded up to `align` must not overflow `isize`.
+pub unsafe extern "C" fn rtmalloc_realloc_unchecked(
+    ptr: *mut u8,
+    size: usize,
+    align: usize,
+    new_size: usize,
+) -> *mut u8 {
+    let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
+    unsafe { ALLOC.realloc(ptr, layout, new_size) }
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_alloc_hint")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_alloc_hint")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_alloc_hint")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_alloc_hint")
+)]
+/// [`rtmalloc_alloc`] with [`hint`](crate::hint) flags (`hint::SHORT_LIVED`,
+/// `hint::COLD`, `hint::INTERLEAVE`) saying how the memory will be used. Unknown flags are
+/// ignored. See [`RtMalloc::alloc_hinted`].
+pub extern "C" fn rtmalloc_alloc_hint(size: usize, align: usize, hints: u32) -> *mut u8 {
+    match checked_layout(size, align) {
+        Some(layout) => unsafe { ALLOC.alloc_hinted(layout, hints) },
+        None => ptr::null_mut(),
+    }
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_dealloc_sized")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_dealloc_sized")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_dealloc_sized")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_dealloc_sized")
+)]
+/// Free `ptr`, allocated with `size` bytes and an `align` of at most 8,
+/// taking its size class from `size` instead of the page map. Null is
+/// ignored. See [`RtMalloc::dealloc_sized`].
+///
+/// # Safety
+///
+/// A non-null `ptr` must have been returned by `rtmalloc_alloc` or
+/// `rtmalloc_alloc_hint` with this `size`, and not passed to
+/// `rtmalloc_realloc` since.
+pub unsafe extern "C" fn rtmalloc_dealloc_sized(ptr: *mut u8, size: usize) {
+    if ptr.is_null() {
+        return;
+    }
+    let layout = unsafe { Layout::from_size_align_unchecked(size, 1) };
+    unsafe { ALLOC.dealloc_sized(ptr, layout) }
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_version")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_version")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_version")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_version")
+)]
+/// Version and build configuration as a static NUL-terminated string, e.g.
+/// `0.1.0 (features: ffi,nightly; page_size: 8192; classes: 46)`.
+pub extern "C" fn rtmalloc_version() -> *const c_char {
+    version::VERSION_STRING.as_ptr()
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_config_fingerprint")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_config_fingerprint")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_config_fingerprint")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_config_fingerprint")
+)]
+/// Fingerprint of the build configuration. Equal values mean equal
+/// allocator metadata layouts.
+pub extern "C" fn rtmalloc_config_fingerprint() -> u64 {
+    version::CONFIG_FINGERPRINT
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_heap_id")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_heap_id")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_heap_id")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_heap_id")
+)]
+/// Identifier unique to this process's heap. Never zero.
+pub extern "C" fn rtmalloc_heap_id() -> u64 {
+    version::heap_id()
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_thread_flush")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_thread_flush")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_thread_flush")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_thread_flush")
+)]
+/// Give everything the calling thread has cached back for other threads to
+/// use. See [`thread::flush_current_cache`](crate::thread::flush_current_cache).
+pub extern "C" fn rtmalloc_thread_flush() {
+    crate::thread::flush_current_cache();
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_selftest")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_selftest")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_selftest")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_selftest")
+)]
+/// Run [`selftest`](crate::selftest()). Returns 0 if every check passed,
+/// otherwise a mask with bit `i` set for each failed `Check::ALL[i]`.
+pub extern "C" fn rtmalloc_selftest() -> u32 {
+    crate::selftest().failed_mask()
+}
+
+/// glibc-compatible `struct mallinfo2`, filled by [`mallinfo2`].
+///
+/// rtmalloc has no arenas or bins; fields map as follows:
+///
+/// | Field | Meaning here |
+/// |-------|--------------|
+/// | `arena` | Bytes mapped from the OS for spans |
+/// | `ordblks` | Free spans in the page heap |
+/// | `smblks` | Free small objects in the central free lists, transfer cache and object stacks |
+/// | `hblks`, `hblkhd`, `usmblks`, `keepcost` | Always 0 |
+/// | `fsmblks` | Bytes in those small objects |
+/// | `uordblks` | `arena - fordblks` |
+/// | `fordblks` | Free bytes: page heap, parked mid-heap spans and `fsmblks` |
+///
+/// Objects held by thread caches or per-CPU slabs count as in use.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mallinfo2 {
+    pub arena: usize,
+    pub ordblks: usize,
+    pub smblks: usize,
+    pub hblks: usize,
+    pub hblkhd: usize,
+    pub usmblks: usize,
+    pub fsmblks: usize,
+    pub uordblks: usize,
+    pub fordblks: usize,
+    pub keepcost: usize,
+}
+
+/// Best-effort heap summary in glibc's `mallinfo2` layout. Takes the page
+/// heap lock and each central list lock in turn, so the totals are not an
+/// atomic snapshot. All zero if called from inside the allocator.
+pub fn mallinfo2() -> Mallinfo2 {
+    use crate::allocator::{CENTRAL_CACHE, MID_HEAP, PAGE_HEAP};
+    use crate::size_class::{self, NUM_SIZE_CLASSES};
+
+    let Some(_guard) = crate::bootstrap::ReentrancyGuard::enter() else {
+        return Mallinfo2::default();
+    };
+    let heap = PAGE_HEAP.lock().usage();
+    let (mut small_objects, mut small_bytes) = (0, 0);
+    for cls in 1..NUM_SIZE_CLASSES {
+        #[allow(unused_mut)]
+        let mut objects = CENTRAL_CACHE.free_objects(cls);
+        #[cfg(any(feature = "percpu", feature = "nightly", feature = "std"))]
+        {
+            objects += crate::allocator::TRANSFER_CACHE.cached_objects(cls);
+        }
+        #[cfg(not(any(feature = "percpu", feature = "nightly", feature = "std")))]
+        {
+            objects += crate::allocator::OBJECT_STACKS.cached_objects(cls);
+        }
+        small_objects += objects;
+        small_bytes += objects * size_class::class_to_size(cls);
+    }
+    let free = heap.free_bytes + MID_HEAP.cached_bytes() + small_bytes;
+    Mallinfo2 {
+        arena: heap.system_bytes,
+        ordblks: heap.free_spans,
+        smblks: small_objects,
+        fsmblks: small_bytes,
+        uordblks: heap.system_bytes.saturating_sub(free),
+        fordblks: free,
+        ..Mallinfo2::default()
+    }
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_mallinfo2")
 )]
 #[cfg_attr(
     all(feature = "testing", feature = "nightly", not(feature = "percpu")),
-    unsafe(export_name = "rtmalloc_nightly_alloc")
+    unsafe(export_name = "rtmalloc_nightly_mallinfo2")
 )]
 #[cfg_attr(
     all(
@@ -31,31 +568,28 @@ static ALLOC: RtMalloc = RtMalloc;
         feature = "std",
         not(any(feature = "nightly", feature = "percpu"))
     ),
-    unsafe(export_name = "rtmalloc_std_alloc")
+    unsafe(export_name = "rtmalloc_std_mallinfo2")
 )]
 #[cfg_attr(
     all(
         feature = "testing",
         not(any(feature = "nightly", feature = "std", feature = "percpu"))
     ),
-    unsafe(export_name = "rtmalloc_nostd_alloc")
+    unsafe(export_name = "rtmalloc_nostd_mallinfo2")
 )]
-/// # Safety
-///
-/// `align` must be a power of two. `size` must be a multiple of `align` or zero.
-pub unsafe extern "C" fn rtmalloc_alloc(size: usize, align: usize) -> *mut u8 {
-    let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
-    unsafe { ALLOC.alloc(layout) }
+/// Heap summary as a glibc `struct mallinfo2`. See [`Mallinfo2`].
+pub extern "C" fn rtmalloc_mallinfo2() -> Mallinfo2 {
+    mallinfo2()
 }
 
 #[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
 #[cfg_attr(
     all(feature = "testing", feature = "percpu"),
-    unsafe(export_name = "rtmalloc_percpu_dealloc")
+    unsafe(export_name = "rtmalloc_percpu_features")
 )]
 #[cfg_attr(
     all(feature = "testing", feature = "nightly", not(feature = "percpu")),
-    unsafe(export_name = "rtmalloc_nightly_dealloc")
+    unsafe(export_name = "rtmalloc_nightly_features")
 )]
 #[cfg_attr(
     all(
@@ -63,31 +597,29 @@ pub unsafe extern "C" fn rtmalloc_alloc(size: usize, align: usize) -> *mut u8 {
         feature = "std",
         not(any(feature = "nightly", feature = "percpu"))
     ),
-    unsafe(export_name = "rtmalloc_std_dealloc")
+    unsafe(export_name = "rtmalloc_std_features")
 )]
 #[cfg_attr(
     all(
         feature = "testing",
         not(any(feature = "nightly", feature = "std", feature = "percpu"))
     ),
-    unsafe(export_name = "rtmalloc_nostd_dealloc")
+    unsafe(export_name = "rtmalloc_nostd_features")
 )]
-/// # Safety
-///
-/// `ptr` must have been returned by `rtmalloc_alloc` with the same `size`/`align`.
-pub unsafe extern "C" fn rtmalloc_dealloc(ptr: *mut u8, size: usize, align: usize) {
-    let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
-    unsafe { ALLOC.dealloc(ptr, layout) }
+/// Compile-time features and detected capabilities. See
+/// [`features`](crate::features()).
+pub extern "C" fn rtmalloc_features() -> version::Features {
+    version::features()
 }
 
 #[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
 #[cfg_attr(
     all(feature = "testing", feature = "percpu"),
-    unsafe(export_name = "rtmalloc_percpu_realloc")
+    unsafe(export_name = "rtmalloc_percpu_num_classes")
 )]
 #[cfg_attr(
     all(feature = "testing", feature = "nightly", not(feature = "percpu")),
-    unsafe(export_name = "rtmalloc_nightly_realloc")
+    unsafe(export_name = "rtmalloc_nightly_num_classes")
 )]
 #[cfg_attr(
     all(
@@ -95,36 +627,241 @@ pub unsafe extern "C" fn rtmalloc_dealloc(ptr: *mut u8, size: usize, align: usiz
         feature = "std",
         not(any(feature = "nightly", feature = "percpu"))
     ),
-    unsafe(export_name = "rtmalloc_std_realloc")
+    unsafe(export_name = "rtmalloc_std_num_classes")
 )]
 #[cfg_attr(
     all(
         feature = "testing",
         not(any(feature = "nightly", feature = "std", feature = "percpu"))
     ),
-    unsafe(export_name = "rtmalloc_nostd_realloc")
+    unsafe(export_name = "rtmalloc_nostd_num_classes")
 )]
-/// # Safety
+/// Number of size classes, numbered from 1. See
+/// [`size_class::num_classes`](crate::size_class::num_classes).
+pub extern "C" fn rtmalloc_num_classes() -> usize {
+    crate::size_class::num_classes()
+}
+
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_class_info")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_class_info")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_class_info")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_class_info")
+)]
+/// Write size class `index` to `out` and return true, or return false if
+/// `index` is outside `1..=rtmalloc_num_classes()` or `out` is null. See
+/// [`size_class::describe`](crate::size_class::describe).
 ///
-/// `ptr` must have been returned by `rtmalloc_alloc` with the same `size`/`align`.
-pub unsafe extern "C" fn rtmalloc_realloc(
-    ptr: *mut u8,
-    size: usize,
-    align: usize,
-    new_size: usize,
-) -> *mut u8 {
-    let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
-    unsafe { ALLOC.realloc(ptr, layout, new_size) }
+/// # Safety
+/// `out` must be null or valid for a write of a
+/// [`ClassDesc`](crate::size_class::ClassDesc).
+pub unsafe extern "C" fn rtmalloc_class_info(
+    index: usize,
+    out: *mut crate::size_class::ClassDesc,
+) -> bool {
+    match crate::size_class::describe(index) {
+        Some(desc) if !out.is_null() => {
+            unsafe { out.write(desc) };
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Receives report text from the `rtmalloc_write_*` functions, in order, in
+/// pieces of at most [`CallbackWriter::CAPACITY`] bytes that are not
+/// NUL-terminated. A piece may end inside a UTF-8 sequence; the text as a
+/// whole is UTF-8. `ctx` is the pointer given with the callback.
+pub type WriteCallback = extern "C" fn(ctx: *mut c_void, text: *const c_char, len: usize);
+
+/// [`fmt::Write`] sink that collects text in a fixed buffer and hands it to
+/// a [`WriteCallback`] whenever the buffer fills and when dropped, so a
+/// report reaches C code without `std` or an allocation.
+pub struct CallbackWriter {
+    callback: WriteCallback,
+    ctx: *mut c_void,
+    buf: [u8; Self::CAPACITY],
+    len: usize,
+}
+
+impl CallbackWriter {
+    /// Most bytes passed to the callback at once.
+    pub const CAPACITY: usize = 256;
+
+    pub fn new(callback: WriteCallback, ctx: *mut c_void) -> Self {
+        Self {
+            callback,
+            ctx,
+            buf: [0; Self::CAPACITY],
+            len: 0,
+        }
+    }
+
+    /// Pass the buffered text to the callback.
+    pub fn flush(&mut self) {
+        if self.len > 0 {
+            (self.callback)(self.ctx, self.buf.as_ptr().cast(), self.len);
+            self.len = 0;
+        }
+    }
+}
+
+impl fmt::Write for CallbackWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut bytes = s.as_bytes();
+        while !bytes.is_empty() {
+            if self.len == Self::CAPACITY {
+                self.flush();
+            }
+            let n = bytes.len().min(Self::CAPACITY - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            bytes = &bytes[n..];
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CallbackWriter {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(feature = "stats")]
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_write_stats")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_write_stats")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_write_stats")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_write_stats")
+)]
+/// Write [`stats::write_report`](crate::stats::write_report) to `callback`.
+pub extern "C" fn rtmalloc_write_stats(callback: WriteCallback, ctx: *mut c_void) {
+    let _ = crate::stats::write_report(&mut CallbackWriter::new(callback, ctx));
+}
+
+#[cfg(feature = "stats")]
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_write_class_report")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_write_class_report")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_write_class_report")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_write_class_report")
+)]
+/// Write [`stats::write_class_report`](crate::stats::write_class_report) to
+/// `callback`.
+pub extern "C" fn rtmalloc_write_class_report(callback: WriteCallback, ctx: *mut c_void) {
+    let _ = crate::stats::write_class_report(&mut CallbackWriter::new(callback, ctx));
+}
+
+#[cfg(feature = "alloc-histogram")]
+#[cfg_attr(not(feature = "testing"), unsafe(no_mangle))]
+#[cfg_attr(
+    all(feature = "testing", feature = "percpu"),
+    unsafe(export_name = "rtmalloc_percpu_write_histogram")
+)]
+#[cfg_attr(
+    all(feature = "testing", feature = "nightly", not(feature = "percpu")),
+    unsafe(export_name = "rtmalloc_nightly_write_histogram")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        feature = "std",
+        not(any(feature = "nightly", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_std_write_histogram")
+)]
+#[cfg_attr(
+    all(
+        feature = "testing",
+        not(any(feature = "nightly", feature = "std", feature = "percpu"))
+    ),
+    unsafe(export_name = "rtmalloc_nostd_write_histogram")
+)]
+/// Write [`histogram::write_report`](crate::histogram::write_report) to
+/// `callback`.
+pub extern "C" fn rtmalloc_write_histogram(callback: WriteCallback, ctx: *mut c_void) {
+    let _ = crate::histogram::write_report(&mut CallbackWriter::new(callback, ctx));
 }
 
+/// Drop-in `malloc`/`free` family for `LD_PRELOAD` or static linking.
+///
+/// # Foreign pointers
+///
+/// When preloaded, `free` and `realloc` can receive objects allocated by the
+/// previous allocator (before rtmalloc was loaded, or by a library that kept
+/// its own function pointers). They are recognised by a page-map miss outside
+/// the bootstrap arena and handled according to [`ForeignPolicy`], chosen with
+/// [`set_foreign_policy`] or the `RTMALLOC_FOREIGN_POINTERS` environment
+/// variable (`forward` or `migrate`, read on first use).
 #[cfg(feature = "c-abi")]
 #[allow(clippy::missing_safety_doc)]
 pub mod c_abi {
-    use super::ALLOC;
+    use super::{ALLOC, EINVAL};
     use crate::allocator::PAGE_MAP;
+    use crate::bootstrap;
     use crate::config::{PAGE_SHIFT, PAGE_SIZE};
+    use crate::platform;
     use crate::size_class;
     use core::alloc::{GlobalAlloc, Layout};
+    use core::ffi::{CStr, c_char, c_int, c_void};
+    use core::sync::atomic::{AtomicU8, AtomicU64, AtomicUsize, Ordering};
+
+    const ENOMEM: i32 = 12;
 
     const MIN_ALIGN: usize = if core::mem::size_of::<usize>() >= 8 {
         16
@@ -132,29 +869,319 @@ pub mod c_abi {
         8
     };
 
+    /// What `free` and `realloc` do with pointers rtmalloc did not allocate.
+    #[repr(u8)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ForeignPolicy {
+        /// Hand the pointer to the next allocator in symbol lookup order
+        /// (`dlsym(RTLD_NEXT, ...)`). The object stays foreign.
+        Forward = 0,
+        /// `realloc` copies the object into a new rtmalloc allocation and
+        /// releases the original through the next allocator; `free` forwards.
+        /// Falls back to forwarding when the next allocator has no
+        /// `malloc_usable_size`, since the old size is then unknown.
+        Migrate = 1,
+    }
+
+    const POLICY_UNSET: u8 = u8::MAX;
+
+    static POLICY: AtomicU8 = AtomicU8::new(POLICY_UNSET);
+
+    /// Set the foreign pointer policy, overriding `RTMALLOC_FOREIGN_POINTERS`.
+    pub fn set_foreign_policy(policy: ForeignPolicy) {
+        POLICY.store(policy as u8, Ordering::Relaxed);
+    }
+
+    /// The foreign pointer policy in effect.
+    pub fn foreign_policy() -> ForeignPolicy {
+        let mut raw = POLICY.load(Ordering::Relaxed);
+        if raw == POLICY_UNSET {
+            let from_env = match unsafe { env(c"RTMALLOC_FOREIGN_POINTERS") } {
+                Some(v) if v.to_bytes() == b"migrate" => ForeignPolicy::Migrate,
+                _ => ForeignPolicy::Forward,
+            };
+            // An explicit `set_foreign_policy` in the meantime wins.
+            raw = match POLICY.compare_exchange(
+                POLICY_UNSET,
+                from_env as u8,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => from_env as u8,
+                Err(current) => current,
+            };
+        }
+        if raw == ForeignPolicy::Migrate as u8 {
+            ForeignPolicy::Migrate
+        } else {
+            ForeignPolicy::Forward
+        }
+    }
+
+    /// Foreign pointer counters since process start.
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct ForeignStats {
+        /// Foreign pointers passed to the next allocator's `free`.
+        pub frees: u64,
+        /// Foreign pointers passed to the next allocator's `realloc`.
+        pub reallocs: u64,
+        /// Foreign objects copied into rtmalloc by `realloc`.
+        pub migrated: u64,
+        /// Bytes copied by those migrations.
+        pub migrated_bytes: u64,
+        /// Foreign pointers dropped because no next allocator was found.
+        pub leaked: u64,
+    }
+
+    static FOREIGN_FREES: AtomicU64 = AtomicU64::new(0);
+    static FOREIGN_REALLOCS: AtomicU64 = AtomicU64::new(0);
+    static MIGRATED: AtomicU64 = AtomicU64::new(0);
+    static MIGRATED_BYTES: AtomicU64 = AtomicU64::new(0);
+    static LEAKED: AtomicU64 = AtomicU64::new(0);
+
+    /// Snapshot of the foreign pointer counters.
+    pub fn foreign_stats() -> ForeignStats {
+        ForeignStats {
+            frees: FOREIGN_FREES.load(Ordering::Relaxed),
+            reallocs: FOREIGN_REALLOCS.load(Ordering::Relaxed),
+            migrated: MIGRATED.load(Ordering::Relaxed),
+            migrated_bytes: MIGRATED_BYTES.load(Ordering::Relaxed),
+            leaked: LEAKED.load(Ordering::Relaxed),
+        }
+    }
+
+    /// C entry point for [`set_foreign_policy`]: 0 = forward, 1 = migrate.
+    /// Returns 0, or `EINVAL` for an unknown policy.
+    #[unsafe(no_mangle)]
+    pub extern "C" fn rtmalloc_set_foreign_policy(policy: c_int) -> c_int {
+        match policy {
+            0 => set_foreign_policy(ForeignPolicy::Forward),
+            1 => set_foreign_policy(ForeignPolicy::Migrate),
+            _ => return EINVAL,
+        }
+        0
+    }
+
+    /// C entry point for [`failure::set_policy`](crate::failure::set_policy):
+    /// 0 = abort, 1 = return null, 2 = call the handler. Returns 0, or
+    /// `EINVAL` for an unknown policy.
+    #[unsafe(no_mangle)]
+    pub extern "C" fn rtmalloc_set_failure_policy(policy: c_int) -> c_int {
+        use crate::failure::{Policy, set_policy};
+        match policy {
+            0 => set_policy(Policy::Abort),
+            1 => set_policy(Policy::ReturnNull),
+            2 => set_policy(Policy::Handler),
+            _ => return EINVAL,
+        }
+        0
+    }
+
+    /// C entry point for [`failure::set_handler`](crate::failure::set_handler).
+    /// A null handler goes back to aborting.
+    #[unsafe(no_mangle)]
+    pub extern "C" fn rtmalloc_set_failure_handler(handler: Option<crate::failure::Handler>) {
+        crate::failure::set_handler(handler);
+    }
+
+    /// C entry point for [`failure::last_error`](crate::failure::last_error).
+    /// A nonzero `clear` resets it to 0 after reading.
+    #[unsafe(no_mangle)]
+    pub extern "C" fn rtmalloc_last_error(clear: c_int) -> c_int {
+        let code = crate::failure::last_error();
+        if clear != 0 {
+            crate::failure::clear_last_error();
+        }
+        code
+    }
+
+    /// C entry point for
+    /// [`page_heap::set_growth_hook`](crate::page_heap::set_growth_hook).
+    /// A null hook removes it.
+    #[unsafe(no_mangle)]
+    pub extern "C" fn rtmalloc_set_growth_hook(hook: Option<crate::page_heap::GrowthHook>) {
+        crate::page_heap::set_growth_hook(hook);
+    }
+
+    /// C entry point for [`foreign_stats`].
+    #[unsafe(no_mangle)]
+    pub unsafe extern "C" fn rtmalloc_foreign_stats(out: *mut ForeignStats) {
+        if !out.is_null() {
+            unsafe { out.write(foreign_stats()) };
+        }
+    }
+
+    #[cfg(unix)]
+    unsafe extern "C" {
+        fn getenv(name: *const c_char) -> *const c_char;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    }
+
+    #[cfg(unix)]
+    unsafe fn env(name: &CStr) -> Option<&'static CStr> {
+        let value = unsafe { getenv(name.as_ptr()) };
+        (!value.is_null()).then(|| unsafe { CStr::from_ptr(value) })
+    }
+
+    #[cfg(not(unix))]
+    unsafe fn env(_name: &CStr) -> Option<&'static CStr> {
+        None
+    }
+
+    /// An entry point of the next allocator, resolved on first use.
+    struct NextFn {
+        /// 0 = unresolved, 1 = not found, otherwise the function address.
+        addr: AtomicUsize,
+        name: &'static CStr,
+    }
+
+    const NEXT_MISSING: usize = 1;
+
+    impl NextFn {
+        const fn new(name: &'static CStr) -> Self {
+            Self {
+                addr: AtomicUsize::new(0),
+                name,
+            }
+        }
+
+        fn get(&self) -> Option<usize> {
+            let mut addr = self.addr.load(Ordering::Acquire);
+            if addr == 0 {
+                addr = Self::resolve(self.name).unwrap_or(NEXT_MISSING);
+                self.addr.store(addr, Ordering::Release);
+            }
+            (addr != NEXT_MISSING).then_some(addr)
+        }
+
+        #[cfg(unix)]
+        fn resolve(name: &CStr) -> Option<usize> {
+            // RTLD_NEXT is `(void *)-1` on glibc, musl and macOS.
+            let rtld_next = core::ptr::without_provenance_mut::<c_void>(usize::MAX);
+            let addr = unsafe { dlsym(rtld_next, name.as_ptr()) } as usize;
+            // Statically linked into the executable with nothing after us,
+            // the lookup can come back to our own exports.
+            let ours = [
+                free as *const () as usize,
+                realloc as *const () as usize,
+                malloc_usable_size as *const () as usize,
+            ];
+            (addr != 0 && !ours.contains(&addr)).then_some(addr)
+        }
+
+        #[cfg(not(unix))]
+        fn resolve(_name: &CStr) -> Option<usize> {
+            None
+        }
+    }
+
+    static NEXT_FREE: NextFn = NextFn::new(c"free");
+    static NEXT_REALLOC: NextFn = NextFn::new(c"realloc");
+    static NEXT_USABLE_SIZE: NextFn = NextFn::new(c"malloc_usable_size");
+
+    /// Whether `ptr` was not allocated by rtmalloc.
+    fn is_foreign(ptr: *mut u8) -> bool {
+        !ALLOC.owns(ptr)
+    }
+
+    /// Release a foreign object through the next allocator.
+    unsafe fn forward_free(ptr: *mut u8) {
+        match NEXT_FREE.get() {
+            Some(addr) => {
+                let next_free: unsafe extern "C" fn(*mut u8) =
+                    unsafe { core::mem::transmute(addr) };
+                unsafe { next_free(ptr) };
+            }
+            None => {
+                LEAKED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[cold]
+    unsafe fn free_foreign(ptr: *mut u8) {
+        FOREIGN_FREES.fetch_add(1, Ordering::Relaxed);
+        unsafe { forward_free(ptr) }
+    }
+
+    #[cold]
+    unsafe fn realloc_foreign(ptr: *mut u8, new_size: usize) -> *mut u8 {
+        if foreign_policy() == ForeignPolicy::Migrate
+            && let Some(addr) = NEXT_USABLE_SIZE.get()
+        {
+            let next_usable_size: unsafe extern "C" fn(*mut u8) -> usize =
+                unsafe { core::mem::transmute(addr) };
+            let old_size = unsafe { next_usable_size(ptr) };
+            let new = unsafe { malloc(new_size) };
+            if new.is_null() {
+                return new;
+            }
+            let copied = old_size.min(new_size);
+            unsafe { core::ptr::copy_nonoverlapping(ptr, new, copied) };
+            unsafe { forward_free(ptr) };
+            MIGRATED.fetch_add(1, Ordering::Relaxed);
+            MIGRATED_BYTES.fetch_add(copied as u64, Ordering::Relaxed);
+            return new;
+        }
+        match NEXT_REALLOC.get() {
+            Some(addr) => {
+                FOREIGN_REALLOCS.fetch_add(1, Ordering::Relaxed);
+                let next_realloc: unsafe extern "C" fn(*mut u8, usize) -> *mut u8 =
+                    unsafe { core::mem::transmute(addr) };
+                unsafe { next_realloc(ptr, new_size) }
+            }
+            // Without the owner we can neither resize nor learn the size;
+            // fail and leave the original intact.
+            None => core::ptr::null_mut(),
+        }
+    }
+
     unsafe fn usable_size(ptr: *mut u8) -> usize {
         if ptr.is_null() {
             return 0;
         }
         let page_id = (ptr as usize) >> PAGE_SHIFT;
+        let sc = PAGE_MAP.size_class(page_id);
+        if sc != 0 {
+            return size_class::class_to_size(sc);
+        }
         let span = PAGE_MAP.get(page_id);
         if span.is_null() {
+            if bootstrap::owns(ptr) {
+                return unsafe { bootstrap::allocation_size(ptr) };
+            }
+            if let Some(addr) = NEXT_USABLE_SIZE.get() {
+                let next_usable_size: unsafe extern "C" fn(*mut u8) -> usize =
+                    unsafe { core::mem::transmute(addr) };
+                return unsafe { next_usable_size(ptr) };
+            }
             return 0;
         }
-        let sc = unsafe { (*span).size_class };
-        if sc != 0 {
-            size_class::class_to_size(sc)
-        } else {
-            (unsafe { (*span).num_pages }) * PAGE_SIZE
+        if !unsafe { (*span).contains(ptr) } {
+            return 0;
         }
+        unsafe { (*span).bytes_from(ptr) }
+    }
+
+    /// Layout for `size` bytes at `align`, a power of two, or None with
+    /// `errno` set to `ENOMEM` if no allocation can be that large.
+    fn sized(size: usize, align: usize) -> Option<Layout> {
+        let layout = Layout::from_size_align(size, align).ok();
+        if layout.is_none() {
+            platform::set_errno(ENOMEM);
+        }
+        layout
     }
 
     #[unsafe(no_mangle)]
     pub unsafe extern "C" fn malloc(size: usize) -> *mut u8 {
         if size == 0 {
-            return MIN_ALIGN as *mut u8;
+            return core::ptr::without_provenance_mut(MIN_ALIGN);
         }
-        let layout = unsafe { Layout::from_size_align_unchecked(size, MIN_ALIGN) };
+        let Some(layout) = sized(size, MIN_ALIGN) else {
+            return core::ptr::null_mut();
+        };
         unsafe { ALLOC.alloc(layout) }
     }
 
@@ -163,8 +1190,10 @@ pub mod c_abi {
         if ptr.is_null() || (ptr as usize) <= MIN_ALIGN {
             return;
         }
-        let layout = unsafe { Layout::from_size_align_unchecked(MIN_ALIGN, MIN_ALIGN) };
-        unsafe { ALLOC.dealloc(ptr, layout) }
+        if is_foreign(ptr) {
+            return unsafe { free_foreign(ptr) };
+        }
+        unsafe { ALLOC.free(ptr) }
     }
 
     #[unsafe(no_mangle)]
@@ -176,20 +1205,25 @@ pub mod c_abi {
             unsafe { free(ptr) };
             return core::ptr::null_mut();
         }
+        if is_foreign(ptr) {
+            return unsafe { realloc_foreign(ptr, new_size) };
+        }
+        if sized(new_size, MIN_ALIGN).is_none() {
+            return core::ptr::null_mut();
+        }
         let layout = unsafe { Layout::from_size_align_unchecked(MIN_ALIGN, MIN_ALIGN) };
-        unsafe { ALLOC.realloc(ptr, layout, new_size) }
+        unsafe { ALLOC.resize(ptr, layout, new_size) }
     }
 
     #[unsafe(no_mangle)]
     pub unsafe extern "C" fn calloc(count: usize, size: usize) -> *mut u8 {
-        let total = match count.checked_mul(size) {
-            Some(t) => t,
-            None => return core::ptr::null_mut(),
-        };
+        let total = count.saturating_mul(size);
         if total == 0 {
-            return MIN_ALIGN as *mut u8;
+            return core::ptr::without_provenance_mut(MIN_ALIGN);
         }
-        let layout = unsafe { Layout::from_size_align_unchecked(total, MIN_ALIGN) };
+        let Some(layout) = sized(total, MIN_ALIGN) else {
+            return core::ptr::null_mut();
+        };
         unsafe { ALLOC.alloc_zeroed(layout) }
     }
 
@@ -200,16 +1234,19 @@ pub mod c_abi {
         size: usize,
     ) -> core::ffi::c_int {
         if !align.is_power_of_two() || align < core::mem::size_of::<usize>() {
-            return 22; // EINVAL
+            return EINVAL;
         }
         if size == 0 {
             unsafe { *memptr = core::ptr::null_mut() };
             return 0;
         }
-        let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
+        // Reports through the return value; `errno` is left alone.
+        let Ok(layout) = Layout::from_size_align(size, align) else {
+            return ENOMEM;
+        };
         let ptr = unsafe { ALLOC.alloc(layout) };
         if ptr.is_null() {
-            12 // ENOMEM
+            ENOMEM
         } else {
             unsafe { *memptr = ptr };
             0
@@ -219,12 +1256,16 @@ pub mod c_abi {
     #[unsafe(no_mangle)]
     pub unsafe extern "C" fn aligned_alloc(align: usize, size: usize) -> *mut u8 {
         if !align.is_power_of_two() || (size > 0 && !size.is_multiple_of(align)) {
+            platform::set_errno(EINVAL);
             return core::ptr::null_mut();
         }
         if size == 0 {
-            return align as *mut u8;
+            // A dangling `align` sentinel would look foreign to `free`.
+            return core::ptr::null_mut();
         }
-        let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
+        let Some(layout) = sized(size, align) else {
+            return core::ptr::null_mut();
+        };
         unsafe { ALLOC.alloc(layout) }
     }
 
@@ -235,16 +1276,24 @@ pub mod c_abi {
 
     #[unsafe(no_mangle)]
     pub unsafe extern "C" fn memalign(align: usize, size: usize) -> *mut u8 {
-        if !align.is_power_of_two() || size == 0 {
+        if !align.is_power_of_two() {
+            platform::set_errno(EINVAL);
+            return core::ptr::null_mut();
+        }
+        if size == 0 {
             return core::ptr::null_mut();
         }
-        let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
+        let Some(layout) = sized(size, align) else {
+            return core::ptr::null_mut();
+        };
         unsafe { ALLOC.alloc(layout) }
     }
 
     #[unsafe(no_mangle)]
     pub unsafe extern "C" fn pvalloc(size: usize) -> *mut u8 {
-        let rounded = (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let rounded = size
+            .checked_next_multiple_of(PAGE_SIZE)
+            .unwrap_or(usize::MAX);
         unsafe { memalign(PAGE_SIZE, rounded) }
     }
 
@@ -252,4 +1301,47 @@ pub mod c_abi {
     pub unsafe extern "C" fn valloc(size: usize) -> *mut u8 {
         unsafe { memalign(PAGE_SIZE, size) }
     }
+
+    /// glibc `mallinfo2`, for tools that query the interposed allocator.
+    #[unsafe(no_mangle)]
+    pub extern "C" fn mallinfo2() -> super::Mallinfo2 {
+        super::mallinfo2()
+    }
+
+    /// glibc's deprecated `struct mallinfo`: [`Mallinfo2`](super::Mallinfo2)
+    /// with `int` fields.
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct Mallinfo {
+        pub arena: c_int,
+        pub ordblks: c_int,
+        pub smblks: c_int,
+        pub hblks: c_int,
+        pub hblkhd: c_int,
+        pub usmblks: c_int,
+        pub fsmblks: c_int,
+        pub uordblks: c_int,
+        pub fordblks: c_int,
+        pub keepcost: c_int,
+    }
+
+    /// glibc `mallinfo`, for legacy callers. Values past `INT_MAX`
+    /// saturate (glibc truncates them).
+    #[unsafe(no_mangle)]
+    pub extern "C" fn mallinfo() -> Mallinfo {
+        let m = super::mallinfo2();
+        let int = |v: usize| c_int::try_from(v).unwrap_or(c_int::MAX);
+        Mallinfo {
+            arena: int(m.arena),
+            ordblks: int(m.ordblks),
+            smblks: int(m.smblks),
+            hblks: int(m.hblks),
+            hblkhd: int(m.hblkhd),
+            usmblks: int(m.usmblks),
+            fsmblks: int(m.fsmblks),
+            uordblks: int(m.uordblks),
+            fordblks: int(m.fordblks),
+            keepcost: int(m.keepcost),
+        }
+    }
 }
@@ -0,0 +1,58 @@
+//! Hints from runtimes about how tasks move between threads and how memory
+//! is used.
+//!
+//! Work-stealing runtimes (tokio and the like) move tasks between worker
+//! threads freely. The thread cache of a worker that lost its tasks keeps
+//! the objects those tasks freed, and its grown budget, while the workers
+//! now running them fetch fresh objects from the central lists. These hints
+//! let the runtime say when that happens.
+//!
+//! All hints are cheap to skip and safe to call from any thread at any time.
+//! With `percpu`, caches belong to CPUs rather than threads and the hints do
+//! nothing; likewise without a thread cache (neither `nightly` nor `std`).
+//!
+//! ```ignore
+//! // In the worker loop, after another worker stole our queue:
+//! rtmalloc::hint::task_migrated();
+//! ```
+//!
+//! The allocation flags below are passed to
+//! [`RtMalloc::alloc_hinted`](crate::RtMalloc::alloc_hinted) (or
+//! `rtmalloc_alloc_hint` from C) by runtimes that know an object's expected
+//! lifetime when they allocate it.
+
+/// The object will be freed soon, typically on the thread that allocated
+/// it. Such objects are what the thread cache is for, so they take the
+/// normal path.
+pub const SHORT_LIVED: u32 = 1 << 0;
+
+/// The object will live long and be touched rarely. It is placed on the
+/// tenured spans of its size class; see
+/// [`RtMalloc::alloc_hinted`](crate::RtMalloc::alloc_hinted).
+pub const COLD: u32 = 1 << 1;
+
+/// The object is large and shared by threads on every NUMA node, e.g. a
+/// hash table or buffer pool. Its pages are interleaved over the online
+/// nodes rather than placed on the node of whichever thread touches them
+/// first, so no node's memory bandwidth becomes the bottleneck. Small
+/// sizes ignore it; on a single node, or off Linux, it does nothing.
+pub const INTERLEAVE: u32 = 1 << 2;
+
+/// A task that ran on the calling thread has moved to another thread.
+///
+/// The calling thread gives half of each cached size class to the transfer
+/// cache, where any thread can pick the objects up in batches, and returns
+/// its spare cache budget to the global pool so busy threads can grow. The
+/// cache refills normally if this thread keeps allocating.
+///
+/// Call it on the thread the task left, e.g. from a worker that is about to
+/// park after its run queue was stolen.
+#[inline]
+pub fn task_migrated() {
+    // Called from inside the allocator (e.g. by instrumentation), taking its
+    // locks could deadlock; the hint is dropped instead.
+    let Some(_guard) = crate::bootstrap::ReentrancyGuard::enter() else {
+        return;
+    };
+    crate::allocator::donate_thread_cache();
+}
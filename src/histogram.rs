@@ -1,14 +1,15 @@
 //! Allocation size histogram.
 //!
 //! Records the distribution of allocation sizes in 8-byte buckets up to
-//! [`MAX_TRACKED`] bytes. Use [`print_report`] to display results and
+//! [`MAX_TRACKED`] bytes. Use [`print_report`] (or [`write_report`] to
+//! format into any [`fmt::Write`]) to display results and
 //! [`optimal_layout`] to derive custom size class configurations.
 
 extern crate std;
 
+use core::fmt;
 use core::sync::atomic::{AtomicU64, Ordering};
 use std::format;
-use std::println;
 use std::string::String;
 use std::vec::Vec;
 
@@ -232,19 +233,21 @@ pub fn export_toml(max_classes: usize, max_waste_pct: f64) -> String {
     layout.to_toml()
 }
 
-/// Print a human-readable histogram report to stdout.
+/// Write a human-readable histogram report to `out`.
 ///
 /// Shows all non-zero buckets with count, percentage, and cumulative percentage.
 /// Appends the output of `optimal_layout(&snap, 64, 0.125)` at the end.
-pub fn print_report() {
+pub fn write_report(out: &mut impl fmt::Write) -> fmt::Result {
     let snap = snapshot();
     let total: u64 = snap.counts.iter().sum::<u64>() + snap.overflow;
 
-    println!(
+    writeln!(
+        out,
         "\nAllocation size histogram (8-byte buckets, max tracked: {} bytes)",
         MAX_TRACKED
-    );
-    println!(
+    )?;
+    writeln!(
+        out,
         "Total tracked: {}   Overflow (>{} bytes): {} ({:.2}%)\n",
         total,
         MAX_TRACKED,
@@ -254,18 +257,19 @@ pub fn print_report() {
         } else {
             0.0
         }
-    );
+    )?;
 
     if total == 0 {
-        println!("  (no allocations recorded)");
-        return;
+        writeln!(out, "  (no allocations recorded)")?;
+        return Ok(());
     }
 
-    println!(
+    writeln!(
+        out,
         "  {:>6}   {:>12}   {:>7}   {:>10}",
         "Size", "Count", "%", "Cumulative"
-    );
-    println!("  {:->6}   {:->12}   {:->7}   {:->10}", "", "", "", "");
+    )?;
+    writeln!(out, "  {:->6}   {:->12}   {:->7}   {:->10}", "", "", "", "")?;
 
     let mut cumulative = 0u64;
     for (i, &count) in snap.counts.iter().enumerate() {
@@ -274,27 +278,43 @@ pub fn print_report() {
         }
         let size = (i + 1) * BUCKET_SIZE;
         cumulative += count;
-        println!(
+        writeln!(
+            out,
             "  {:>6}   {:>12}   {:>6.2}%   {:>9.2}%",
             size,
             count,
             count as f64 / total as f64 * 100.0,
             cumulative as f64 / total as f64 * 100.0,
-        );
+        )?;
     }
 
     let layout = optimal_layout(&snap, 64, 0.125);
-    println!("\nSuggested class layout (max 64 classes, max waste 12.5%):");
+    writeln!(
+        out,
+        "\nSuggested class layout (max 64 classes, max waste 12.5%):"
+    )?;
     if layout.classes.is_empty() {
-        println!("  (insufficient data)");
+        writeln!(out, "  (insufficient data)")?;
     } else {
-        println!("  {:?}", layout.classes);
-        println!(
+        writeln!(out, "  {:?}", layout.classes)?;
+        writeln!(
+            out,
             "  Avg waste: {:.1} bytes/alloc   Fragmentation: {:.2}%",
             layout.avg_waste_bytes,
             layout.fragmentation_ratio * 100.0
-        );
-        println!("\nTOML config (save to a file, build with RTMALLOC_CLASSES=<path>):");
-        println!("{}", layout.to_toml());
+        )?;
+        writeln!(
+            out,
+            "\nTOML config (save to a file, build with RTMALLOC_CLASSES=<path>):"
+        )?;
+        writeln!(out, "{}", layout.to_toml())?;
     }
+    Ok(())
+}
+
+/// Print [`write_report`] to stdout.
+pub fn print_report() {
+    let mut text = String::new();
+    let _ = write_report(&mut text);
+    std::print!("{text}");
 }
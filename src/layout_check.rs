@@ -0,0 +1,166 @@
+//! Layout checks on free (`layout-check` feature).
+//!
+//! `dealloc` finds an object's size class in the page map, not from the
+//! layout the caller passes, so a free with the wrong size goes through
+//! here without a trace. Under another allocator, or a later rtmalloc that
+//! trusts the layout (as [`RtMalloc::dealloc_sized`] does), the same bug
+//! corrupts the heap. With this feature every `dealloc`, `realloc` and
+//! [`RtMalloc::dealloc_iter`] compares the size class the layout maps to
+//! with the object's, and a large layout's size with the span's, and
+//! reports each mismatch:
+//!
+//! ```text
+//! rtmalloc: dealloc layout does not match the allocation (0x7f3a2c001040): size 24 align 8 is class 3 (24 bytes), the object is class 15 (224 bytes), freed from app+0x4a1b2 app+0x4c0e7 app+0x1f3a9 app+0x1f512 app+0x20c48 app+0x2188e
+//! ```
+//!
+//! The line is built in a fixed buffer and written straight to stderr, as
+//! every failure report is: the check runs mid-free, where formatting,
+//! `std`'s stderr lock or a symbolized backtrace could allocate and
+//! re-enter the allocator. With `std` on x86-64 and AArch64 Unix it ends
+//! with the return addresses of the free, innermost first, taken from the
+//! unwind tables into a fixed array and given as offsets into their
+//! executable or library: `addr2line -f -C -i -e <file> <offset>...` names
+//! the code that passed the wrong layout. The first frames are rtmalloc's
+//! own free path. Elsewhere, break on `rtmalloc::layout_check::report` in
+//! a debugger. A registered failure
+//! handler gets [`Failure::LayoutMismatch`] and the pointer; one that
+//! aborts stops at the first mismatch. Otherwise the object is freed by
+//! its real class and the program goes on, so one run can turn up every
+//! bad free.
+//!
+//! To keep the check exact, `realloc` only resizes in place when the new
+//! size maps to the object's class; a size that fits but maps to a smaller
+//! class gets a fresh allocation, so the next free's layout still names the
+//! class the object is in. `dealloc_sized` takes the checked path.
+//!
+//! `free` and `realloc` of the C ABI carry no layout and are not checked.
+//!
+//! [`RtMalloc::dealloc_sized`]: crate::RtMalloc::dealloc_sized
+//! [`RtMalloc::dealloc_iter`]: crate::RtMalloc::dealloc_iter
+//! [`Failure::LayoutMismatch`]: crate::failure::Failure::LayoutMismatch
+
+use crate::allocator::PAGE_MAP;
+use crate::config::PAGE_SHIFT;
+use crate::size_class;
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+static MISMATCHES: AtomicU64 = AtomicU64::new(0);
+
+/// Frees reported so far whose layout did not match the object.
+pub fn mismatches() -> u64 {
+    MISMATCHES.load(Ordering::Relaxed)
+}
+
+/// Report the free of `ptr` with `layout` if the layout does not describe
+/// the object. Pointers the page map does not know (the bootstrap arena,
+/// another allocator's memory) are left alone.
+#[inline]
+pub(crate) fn check(ptr: *mut u8, layout: Layout) {
+    let page_id = ptr.addr() >> PAGE_SHIFT;
+    let class = PAGE_MAP.size_class(page_id);
+    let layout_class = size_class::layout_to_class(layout.size(), layout.align());
+    let usable = if class != 0 {
+        if layout_class == class {
+            return;
+        }
+        size_class::class_to_size(class)
+    } else {
+        let span = PAGE_MAP.get(page_id);
+        if span.is_null() {
+            return;
+        }
+        let usable = unsafe { (*span).bytes_from(ptr) };
+        if layout_class == 0 && layout.size() <= usable {
+            return;
+        }
+        usable
+    };
+    report(ptr, layout, layout_class, class, usable);
+}
+
+/// Return addresses printed after a mismatch.
+const CALLERS: usize = 6;
+
+#[cold]
+#[inline(never)]
+fn report(ptr: *mut u8, layout: Layout, layout_class: usize, class: usize, usable: usize) {
+    MISMATCHES.fetch_add(1, Ordering::Relaxed);
+    let mut pcs = [0; CALLERS];
+    let found = callers(&mut pcs);
+    crate::failure::layout_mismatch(
+        ptr.addr(),
+        layout.size(),
+        layout.align(),
+        layout_class,
+        class,
+        usable,
+        &pcs[..found],
+    );
+}
+
+/// Fill `pcs` with the return addresses of [`report`]'s callers, innermost
+/// first, and return how many there were. The system unwinder reads the
+/// unwind tables in place, so this does not allocate.
+#[cfg(all(
+    feature = "std",
+    unix,
+    not(miri),
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+#[inline(never)]
+fn callers(pcs: &mut [usize]) -> usize {
+    use core::ffi::c_void;
+
+    // `_URC_NO_REASON` and `_URC_END_OF_STACK`.
+    const CONTINUE: i32 = 0;
+    const STOP: i32 = 5;
+
+    unsafe extern "C" {
+        fn _Unwind_Backtrace(
+            trace: extern "C" fn(*mut c_void, *mut c_void) -> i32,
+            arg: *mut c_void,
+        ) -> i32;
+        fn _Unwind_GetIP(context: *mut c_void) -> usize;
+    }
+
+    struct Walk<'a> {
+        pcs: &'a mut [usize],
+        found: usize,
+        skip: usize,
+    }
+
+    extern "C" fn frame(context: *mut c_void, arg: *mut c_void) -> i32 {
+        let walk = unsafe { &mut *(arg as *mut Walk<'_>) };
+        if walk.skip > 0 {
+            walk.skip -= 1;
+            return CONTINUE;
+        }
+        let pc = unsafe { _Unwind_GetIP(context) };
+        if pc == 0 || walk.found == walk.pcs.len() {
+            return STOP;
+        }
+        walk.pcs[walk.found] = pc;
+        walk.found += 1;
+        CONTINUE
+    }
+
+    // The first two frames are this function and `report`.
+    let mut walk = Walk {
+        pcs,
+        found: 0,
+        skip: 2,
+    };
+    unsafe { _Unwind_Backtrace(frame, &mut walk as *mut Walk<'_> as *mut c_void) };
+    walk.found
+}
+
+#[cfg(not(all(
+    feature = "std",
+    unix,
+    not(miri),
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+fn callers(_pcs: &mut [usize]) -> usize {
+    0
+}
@@ -1,5 +1,9 @@
 #![no_std]
-#![cfg_attr(feature = "nightly", feature(thread_local, allocator_api))]
+#![cfg_attr(
+    feature = "nightly",
+    feature(thread_local, allocator_api, strict_provenance_lints)
+)]
+#![cfg_attr(feature = "nightly", deny(fuzzy_provenance_casts))]
 
 //! rtmalloc: A tcmalloc-style memory allocator for Rust.
 //!
@@ -14,31 +18,112 @@
 //! #[global_allocator]
 //! static GLOBAL: rtmalloc::RtMalloc = rtmalloc::RtMalloc;
 //! ```
+//!
+//! # Before `main`
+//!
+//! Every allocation path works from the earliest constructor (C++ static
+//! initializers, `.init_array`), before `std` is set up: all allocator state
+//! is const-initialized, thread caches set themselves up on first use, and
+//! nothing on those paths relies on `std` having run. `tests/init_order.rs`
+//! checks it.
+//!
+//! # Pointer provenance
+//!
+//! Heap memory is exposed once, when the page heap maps it from the OS. Two
+//! places rebuild pointers from bare addresses and take that exposed
+//! provenance back with `ptr::with_exposed_provenance_mut`: a span's start
+//! address (stored as a page id) and links that went through an integer
+//! encoding (safe-linking, tagged object stack heads). Everything else —
+//! carving objects, aligning, bump allocation — derives from a pointer that
+//! already has provenance via `map_addr`/`with_addr`/`add`. Under the
+//! `nightly` feature, `fuzzy_provenance_casts` is denied so no plain
+//! integer-to-pointer cast slips back in.
 
 #[cfg(test)]
 extern crate alloc;
 #[cfg(any(test, feature = "std"))]
 extern crate std;
 
+#[cfg(all(
+    feature = "minimal",
+    any(
+        feature = "stats",
+        feature = "alloc-histogram",
+        feature = "latency-histogram",
+        feature = "lifetime-histogram",
+        feature = "ffi",
+        feature = "debug",
+        feature = "lock-debug",
+        feature = "coredump",
+    )
+))]
+compile_error!(
+    "`minimal` strips stats, histograms, ffi, debug, lock-debug and coredump; enable none of them"
+);
+
 pub mod allocator;
+pub mod bootstrap;
+#[cfg(feature = "std")]
+pub mod calibrate;
 pub mod central_free_list;
+pub mod class_cap;
+#[cfg(all(feature = "control", unix))]
+pub mod control;
+#[cfg(feature = "coredump")]
+pub mod coredump;
 #[cfg(feature = "percpu")]
 pub mod cpu_cache;
+pub mod debug;
+#[cfg(feature = "double-free-check")]
+mod double_free;
+pub mod failure;
 #[cfg(feature = "ffi")]
 pub mod ffi;
+pub mod hint;
 #[cfg(feature = "alloc-histogram")]
 pub mod histogram;
+#[cfg(feature = "layout-check")]
+pub mod layout_check;
+#[cfg(feature = "lifetime-histogram")]
+pub mod lifetime;
 mod macros;
+#[cfg(feature = "lifetime-histogram")]
+pub mod massif;
+#[cfg(feature = "meta-region")]
+pub mod meta;
+pub mod mid_heap;
+pub mod object_stack;
 pub mod page_heap;
 pub mod pagemap;
 pub mod platform;
+#[cfg(all(feature = "pressure", target_os = "linux"))]
+pub mod pressure;
+#[cfg(feature = "lifetime-histogram")]
+pub mod profile;
+#[cfg(feature = "span-quarantine")]
+pub mod quarantine;
+pub mod selftest;
+#[cfg(all(feature = "testing", feature = "std"))]
+pub mod shadow;
 pub mod size_class;
+#[cfg(all(feature = "testing", feature = "std"))]
+pub mod soak;
 pub mod span;
 #[cfg(feature = "stats")]
 pub mod stats;
+#[cfg(all(feature = "stats", feature = "std"))]
+pub mod stats_dump;
 pub mod sync;
+pub mod thread;
 pub mod thread_cache;
+#[cfg(feature = "trace")]
+pub mod trace;
+#[cfg(feature = "tracing")]
+mod tracing_events;
 pub mod transfer_cache;
+#[cfg(feature = "usdt")]
+mod usdt;
+pub mod version;
 
 /// Allocator configuration constants generated by build.rs from TOML config.
 pub mod config {
@@ -47,6 +132,11 @@ pub mod config {
 
 // Re-export the allocator at crate root for convenience
 pub use allocator::RtMalloc;
+#[cfg(feature = "std")]
+pub use calibrate::calibrate;
+pub use page_heap::{heap_region, reserve};
+pub use selftest::selftest;
+pub use version::features;
 
 // Panic handler for staticlib builds (no_std has no default panic handler).
 // Only active when panic="abort" (i.e., the `fast` profile), not during normal checks.
@@ -0,0 +1,362 @@
+//! Sampled allocation lifetimes (`lifetime-histogram` feature).
+//!
+//! Every [`sample_interval`]-th allocation on a thread is stamped with the
+//! time it was made; when it is freed, from any thread, the time it lived
+//! goes into a power-of-two nanosecond histogram for its size class. How
+//! long objects live is what thread cache sizes and decay windows should be
+//! tuned to: objects that die within microseconds want a cache that holds
+//! them, objects that live for seconds only fill it.
+//!
+//! Sampled objects are tracked in a fixed table of [`TABLE_SLOTS`] entries,
+//! so nothing is allocated. A sample that finds its slots taken is counted
+//! in [`Summary::dropped`] instead. Every free looks the pointer up in the
+//! table, a handful of relaxed loads.
+//!
+//! The samples still alive also estimate the heap: each stands for
+//! `interval` allocations of its size, which is what [`live_heap`] adds up
+//! and [`crate::massif`] turns into snapshots, and [`crate::profile`]
+//! compares between two points in time.
+//!
+//! ```ignore
+//! rtmalloc::lifetime::set_sample_interval(256);
+//! run_workload();
+//! rtmalloc::lifetime::print_report();
+//! ```
+
+use crate::size_class::{self, NUM_SIZE_CLASSES};
+use core::cell::Cell;
+use core::fmt;
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::string::String;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+/// Number of lifetime buckets. Bucket `i` counts objects that lived
+/// `[2^i, 2^(i+1))` nanoseconds; the last one is open-ended (~39 hours and
+/// up).
+pub const NUM_BUCKETS: usize = 48;
+
+/// Sampled objects that can be alive at once.
+pub const TABLE_SLOTS: usize = 4096;
+
+/// Slots looked at for one pointer.
+const PROBES: usize = 8;
+
+/// Allocations per thread between samples by default.
+const DEFAULT_INTERVAL: u32 = 1024;
+
+static INTERVAL: AtomicU32 = AtomicU32::new(DEFAULT_INTERVAL);
+
+/// A sampled live object: its address (0 when the slot is free), size class,
+/// requested size, the sample interval it was taken at and its allocation
+/// time in nanoseconds since [`epoch`].
+struct Slot {
+    addr: AtomicUsize,
+    class: AtomicUsize,
+    size: AtomicUsize,
+    weight: AtomicU32,
+    born: AtomicU64,
+}
+
+static TABLE: [Slot; TABLE_SLOTS] = [const {
+    Slot {
+        addr: AtomicUsize::new(0),
+        class: AtomicUsize::new(0),
+        size: AtomicUsize::new(0),
+        weight: AtomicU32::new(0),
+        born: AtomicU64::new(0),
+    }
+}; TABLE_SLOTS];
+
+static HISTOGRAMS: [[AtomicU64; NUM_BUCKETS]; NUM_SIZE_CLASSES] =
+    [const { [const { AtomicU64::new(0) }; NUM_BUCKETS] }; NUM_SIZE_CLASSES];
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+std::thread_local! {
+    /// Allocations left on this thread until the next sample.
+    static COUNTDOWN: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Sample every `interval`-th allocation on each thread; 0 stops sampling.
+/// Objects already sampled are still measured when freed.
+pub fn set_sample_interval(interval: u32) {
+    INTERVAL.store(interval, Ordering::Relaxed);
+}
+
+/// Allocations per thread between samples, 0 when off.
+pub fn sample_interval() -> u32 {
+    INTERVAL.load(Ordering::Relaxed)
+}
+
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+pub(crate) fn now_ns() -> u64 {
+    epoch().elapsed().as_nanos() as u64
+}
+
+/// Fibonacci hash of `addr`: the top bits of the product, which unlike the
+/// low ones depend on every bit of the address.
+#[inline]
+fn first_slot(addr: usize) -> usize {
+    (addr >> 4).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (usize::BITS - TABLE_SLOTS.ilog2())
+}
+
+/// Count an allocation of `size` bytes in class `class` (0 for large) at
+/// `addr`, and sample it if this thread is due.
+#[inline]
+pub(crate) fn on_alloc(addr: *mut u8, class: usize, size: usize) {
+    let interval = INTERVAL.load(Ordering::Relaxed);
+    if interval == 0 || addr.is_null() {
+        return;
+    }
+    // Clamped so that lowering the interval takes effect at once.
+    let due = COUNTDOWN
+        .try_with(|c| match c.get().min(interval - 1) {
+            0 => {
+                c.set(interval - 1);
+                true
+            }
+            n => {
+                c.set(n - 1);
+                false
+            }
+        })
+        .unwrap_or(false);
+    if due {
+        sample(addr.addr(), class, size, interval);
+    }
+}
+
+#[cold]
+fn sample(addr: usize, class: usize, size: usize, weight: u32) {
+    let born = now_ns();
+    let first = first_slot(addr);
+    for i in 0..PROBES {
+        let slot = &TABLE[(first + i) % TABLE_SLOTS];
+        if slot
+            .addr
+            .compare_exchange(0, addr, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            slot.class.store(class, Ordering::Relaxed);
+            slot.size.store(size, Ordering::Relaxed);
+            slot.weight.store(weight, Ordering::Relaxed);
+            slot.born.store(born, Ordering::Release);
+            return;
+        }
+    }
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Record the lifetime of `addr` if it was sampled.
+#[inline]
+pub(crate) fn on_free(addr: *mut u8) {
+    let addr = addr.addr();
+    let first = first_slot(addr);
+    for i in 0..PROBES {
+        let slot = &TABLE[(first + i) % TABLE_SLOTS];
+        if slot.addr.load(Ordering::Relaxed) == addr {
+            record(slot);
+            return;
+        }
+    }
+}
+
+#[cold]
+fn record(slot: &Slot) {
+    let lived = now_ns().saturating_sub(slot.born.load(Ordering::Acquire));
+    let class = slot.class.load(Ordering::Relaxed);
+    slot.addr.store(0, Ordering::Release);
+    let bucket = (u64::BITS - 1 - (lived | 1).leading_zeros()) as usize;
+    HISTOGRAMS[class][bucket.min(NUM_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Lifetimes of the sampled objects of one size class.
+#[derive(Clone, Copy, Debug)]
+pub struct Histogram {
+    /// Object counts per power-of-two nanosecond bucket.
+    pub counts: [u64; NUM_BUCKETS],
+}
+
+impl Histogram {
+    /// Sampled objects freed so far.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Upper bound in nanoseconds of the `p`-th percentile (`0.0..=100.0`),
+    /// or 0 if nothing was recorded. Resolution is one power of two.
+    pub fn percentile(&self, p: f64) -> u64 {
+        let total = self.count();
+        if total == 0 {
+            return 0;
+        }
+        let rank = ((p.clamp(0.0, 100.0) / 100.0) * total as f64)
+            .ceil()
+            .max(1.0) as u64;
+        let mut seen = 0;
+        for (i, &c) in self.counts.iter().enumerate() {
+            seen += c;
+            if seen >= rank {
+                return 1u64 << (i + 1);
+            }
+        }
+        1u64 << NUM_BUCKETS
+    }
+}
+
+/// Lifetime histogram of `size_class`, 0 for large allocations.
+pub fn histogram(size_class: usize) -> Histogram {
+    Histogram {
+        counts: core::array::from_fn(|i| HISTOGRAMS[size_class][i].load(Ordering::Relaxed)),
+    }
+}
+
+/// Sampling totals across all classes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Sampled objects freed and measured.
+    pub measured: u64,
+    /// Sampled objects still alive.
+    pub live: usize,
+    /// Samples skipped because their table slots were taken.
+    pub dropped: u64,
+}
+
+/// Sampling totals.
+pub fn summary() -> Summary {
+    Summary {
+        measured: (0..NUM_SIZE_CLASSES).map(|c| histogram(c).count()).sum(),
+        live: TABLE
+            .iter()
+            .filter(|s| s.addr.load(Ordering::Relaxed) != 0)
+            .count(),
+        dropped: DROPPED.load(Ordering::Relaxed),
+    }
+}
+
+/// A sampled object still alive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// Address of the object.
+    pub addr: usize,
+    /// Size class, 0 for large allocations.
+    pub class: usize,
+    /// Requested size in bytes.
+    pub size: usize,
+    /// Sample interval it was taken at: the allocations it stands for.
+    pub weight: u32,
+    /// Allocation time in nanoseconds since the sampler's clock started.
+    pub born_ns: u64,
+}
+
+/// Live heap estimated from the samples still alive.
+#[derive(Clone, Copy, Debug)]
+pub struct LiveHeap {
+    /// Requested bytes per size class, class 0 for large allocations.
+    pub bytes: [u64; NUM_SIZE_CLASSES],
+    /// Bytes lost to rounding requests up to their class size.
+    pub slack: u64,
+}
+
+impl LiveHeap {
+    /// Requested bytes across all classes.
+    pub fn total(&self) -> u64 {
+        self.bytes.iter().sum()
+    }
+}
+
+/// Estimate the live heap: every sampled object still alive counts for the
+/// sample interval it was taken at. Only as good as the sample count; with
+/// an interval of 1 it is exact for the objects allocated since.
+pub fn live_heap() -> LiveHeap {
+    let mut heap = LiveHeap {
+        bytes: [0; NUM_SIZE_CLASSES],
+        slack: 0,
+    };
+    for_each_live(|s| {
+        let size = s.size as u64;
+        let weight = s.weight as u64;
+        heap.bytes[s.class] += size * weight;
+        if s.class != 0 {
+            heap.slack += (size_class::class_to_size(s.class) as u64).saturating_sub(size) * weight;
+        }
+    });
+    heap
+}
+
+/// Call `f` with every sampled object still alive. A slot taken over while
+/// it is read is skipped.
+pub(crate) fn for_each_live(mut f: impl FnMut(Sample)) {
+    for slot in &TABLE {
+        let addr = slot.addr.load(Ordering::Acquire);
+        if addr == 0 {
+            continue;
+        }
+        let born_ns = slot.born.load(Ordering::Acquire);
+        let sample = Sample {
+            addr,
+            class: slot.class.load(Ordering::Relaxed),
+            size: slot.size.load(Ordering::Relaxed),
+            weight: slot.weight.load(Ordering::Relaxed),
+            born_ns,
+        };
+        if slot.addr.load(Ordering::Acquire) == addr && slot.born.load(Ordering::Relaxed) == born_ns
+        {
+            f(sample);
+        }
+    }
+}
+
+/// Write the median, p90 and p99 lifetime of every class with samples to
+/// `out`.
+pub fn write_report(out: &mut impl fmt::Write) -> fmt::Result {
+    let sum = summary();
+    writeln!(
+        out,
+        "\nAllocation lifetimes (1 in {} allocations sampled)",
+        sample_interval()
+    )?;
+    writeln!(
+        out,
+        "Measured: {}   Live: {}   Dropped: {}\n",
+        sum.measured, sum.live, sum.dropped
+    )?;
+    writeln!(
+        out,
+        "  {:>5}   {:>8}   {:>10}   {:>10}   {:>10}   {:>10}",
+        "Class", "Size", "Samples", "p50 ns", "p90 ns", "p99 ns"
+    )?;
+    for cls in 0..NUM_SIZE_CLASSES {
+        let h = histogram(cls);
+        if h.count() == 0 {
+            continue;
+        }
+        let size: String = if cls == 0 {
+            "large".into()
+        } else {
+            std::format!("{}", size_class::class_to_size(cls))
+        };
+        writeln!(
+            out,
+            "  {:>5}   {:>8}   {:>10}   {:>10}   {:>10}   {:>10}",
+            cls,
+            size,
+            h.count(),
+            h.percentile(50.0),
+            h.percentile(90.0),
+            h.percentile(99.0),
+        )?;
+    }
+    Ok(())
+}
+
+/// Print [`write_report`] to stdout.
+pub fn print_report() {
+    let mut text = String::new();
+    let _ = write_report(&mut text);
+    std::print!("{text}");
+}
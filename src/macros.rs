@@ -29,6 +29,22 @@ macro_rules! stat_add {
     };
 }
 
+/// Raise a stats counter to at least the given value.
+///
+/// Compiles to nothing (including the value expression) when the `stats`
+/// feature is disabled.
+#[macro_export]
+macro_rules! stat_max {
+    ($counter:ident, $val:expr) => {
+        #[cfg(feature = "stats")]
+        {
+            $crate::stats::STATS
+                .$counter
+                .fetch_max($val as u64, ::core::sync::atomic::Ordering::Relaxed);
+        }
+    };
+}
+
 /// Record an allocation size in the histogram.
 ///
 /// Compiles to nothing when the `alloc-histogram` feature is disabled.
@@ -41,3 +57,94 @@ macro_rules! hist_record {
         }
     };
 }
+
+/// Offer an allocation to the lifetime sampler.
+///
+/// Compiles to nothing when the `lifetime-histogram` feature is disabled.
+#[macro_export]
+macro_rules! lifetime_alloc {
+    ($ptr:expr, $class:expr, $size:expr) => {
+        #[cfg(feature = "lifetime-histogram")]
+        {
+            $crate::lifetime::on_alloc($ptr, $class, $size);
+        }
+    };
+}
+
+/// Record the lifetime of a freed pointer if it was sampled.
+///
+/// Compiles to nothing when the `lifetime-histogram` feature is disabled.
+#[macro_export]
+macro_rules! lifetime_free {
+    ($ptr:expr) => {
+        #[cfg(feature = "lifetime-histogram")]
+        {
+            $crate::lifetime::on_free($ptr);
+        }
+    };
+}
+
+/// Evaluate an expression, recording its wall-clock duration in the latency
+/// histogram for the given [`SlowPath`](crate::stats::SlowPath) event.
+///
+/// Expands to just the expression when the `latency-histogram` feature is
+/// disabled.
+#[macro_export]
+macro_rules! time_slow_path {
+    ($event:ident, $e:expr) => {{
+        #[cfg(feature = "latency-histogram")]
+        let __start = ::std::time::Instant::now();
+        let __result = $e;
+        #[cfg(feature = "latency-histogram")]
+        $crate::stats::record_latency(
+            $crate::stats::SlowPath::$event,
+            __start.elapsed().as_nanos() as u64,
+        );
+        __result
+    }};
+}
+
+/// Count an allocation at a hand-picked call site, under `label`.
+///
+/// ```ignore
+/// fn decode(frame: &[u8]) -> Vec<u8> {
+///     rtmalloc::count_site!("codec.decode", frame.len());
+///     frame.to_vec()
+/// }
+/// ```
+///
+/// Each invocation owns a static counter: one relaxed add for the count and
+/// one for the bytes (0 if left out), no lock, no backtrace. Sites sharing a
+/// label are summed by [`stats::site`](crate::stats::site),
+/// [`stats::for_each_site`](crate::stats::for_each_site), the `site.<label>`
+/// tables of [`stats::dump_binary`](crate::stats::dump_binary) and the
+/// control socket's `stats`. Compiles to nothing, including the byte
+/// expression, without the `stats` feature.
+#[cfg(feature = "stats")]
+#[macro_export]
+macro_rules! count_site {
+    ($label:expr) => {
+        $crate::count_site!($label, 0)
+    };
+    ($label:expr, $bytes:expr) => {{
+        static SITE: $crate::stats::Site = $crate::stats::Site::new($label);
+        SITE.record($bytes as u64);
+    }};
+}
+
+/// Count an allocation at a hand-picked call site, under `label`.
+///
+/// Compiles to nothing, including the byte expression, without the `stats`
+/// feature.
+#[cfg(not(feature = "stats"))]
+#[macro_export]
+macro_rules! count_site {
+    ($label:expr) => {
+        $crate::count_site!($label, 0)
+    };
+    ($label:expr, $bytes:expr) => {
+        if false {
+            let _ = ($label, $bytes);
+        }
+    };
+}
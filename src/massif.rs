@@ -0,0 +1,159 @@
+//! Heap snapshots in Valgrind massif format (`lifetime-histogram` feature).
+//!
+//! A [`Profile`] takes timed snapshots of the live heap as estimated by the
+//! lifetime sampler ([`lifetime::live_heap`]) and writes them as a
+//! `massif.out` file, so the heap over time can be looked at with
+//! `ms_print` or massif-visualizer, or loaded into heaptrack_gui, without
+//! running under Valgrind.
+//!
+//! ```ignore
+//! rtmalloc::lifetime::set_sample_interval(64);
+//! let mut profile = rtmalloc::massif::Profile::new();
+//! for _ in 0..100 {
+//!     run_step();
+//!     profile.snapshot();
+//! }
+//! profile.save("massif.out.rtmalloc", "my-app --bench")?;
+//! ```
+//!
+//! The samples carry no call stacks, so the heap tree of a detailed
+//! snapshot has one entry per size class (`size class 7 (128 bytes)`, and
+//! `large allocations`) where massif would have allocation sites. Bytes
+//! lost to rounding up to the class size are reported as
+//! `mem_heap_extra_B`. Like massif, every [`DETAILED_EVERY`]th snapshot and
+//! the peak are detailed; the rest only record totals.
+
+use crate::lifetime::{self, LiveHeap};
+use crate::size_class::{self, NUM_SIZE_CLASSES};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+use std::vec::Vec;
+
+/// Every this many snapshots one is detailed, as massif's default
+/// `--detailed-freq`.
+pub const DETAILED_EVERY: usize = 10;
+
+/// Estimated live heap at one point in time.
+#[derive(Clone, Copy, Debug)]
+pub struct Snapshot {
+    /// Milliseconds since the [`Profile`] was created.
+    pub time_ms: u64,
+    /// The heap at that time.
+    pub heap: LiveHeap,
+}
+
+/// A series of [`Snapshot`]s to write as one massif file.
+pub struct Profile {
+    start: Instant,
+    snapshots: Vec<Snapshot>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profile {
+    /// An empty profile; snapshot times count from now.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            snapshots: Vec::new(),
+        }
+    }
+
+    /// Record the live heap now.
+    pub fn snapshot(&mut self) -> &Snapshot {
+        let time_ms = self.start.elapsed().as_millis() as u64;
+        self.snapshots.push(Snapshot {
+            time_ms,
+            heap: lifetime::live_heap(),
+        });
+        self.snapshots.last().unwrap()
+    }
+
+    /// Snapshots taken so far, oldest first.
+    pub fn snapshots(&self) -> &[Snapshot] {
+        &self.snapshots
+    }
+
+    /// Index of the snapshot with the largest heap, the first if tied.
+    pub fn peak(&self) -> Option<usize> {
+        let mut peak: Option<(usize, u64)> = None;
+        for (i, s) in self.snapshots.iter().enumerate() {
+            let total = s.heap.total() + s.heap.slack;
+            if peak.is_none_or(|(_, best)| total > best) {
+                peak = Some((i, total));
+            }
+        }
+        peak.map(|(i, _)| i)
+    }
+
+    /// Write the snapshots in massif format. `cmd` fills the `cmd:` line,
+    /// which viewers show as the profiled command.
+    pub fn write(&self, out: &mut impl Write, cmd: &str) -> io::Result<()> {
+        writeln!(
+            out,
+            "desc: rtmalloc sampled heap (1 in {} allocations)",
+            lifetime::sample_interval()
+        )?;
+        writeln!(out, "cmd: {cmd}")?;
+        writeln!(out, "time_unit: ms")?;
+        let peak = self.peak();
+        for (i, s) in self.snapshots.iter().enumerate() {
+            writeln!(out, "#-----------\nsnapshot={i}\n#-----------")?;
+            writeln!(out, "time={}", s.time_ms)?;
+            writeln!(out, "mem_heap_B={}", s.heap.total())?;
+            writeln!(out, "mem_heap_extra_B={}", s.heap.slack)?;
+            writeln!(out, "mem_stacks_B=0")?;
+            if Some(i) == peak {
+                writeln!(out, "heap_tree=peak")?;
+                write_tree(out, &s.heap)?;
+            } else if i % DETAILED_EVERY == 0 {
+                writeln!(out, "heap_tree=detailed")?;
+                write_tree(out, &s.heap)?;
+            } else {
+                writeln!(out, "heap_tree=empty")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// [`write`](Self::write) to a new file at `path`.
+    pub fn save(&self, path: impl AsRef<Path>, cmd: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out, cmd)?;
+        out.flush()
+    }
+}
+
+/// The heap tree of a detailed snapshot: a root for all of the heap and
+/// one child per size class holding any, largest first.
+fn write_tree(out: &mut impl Write, heap: &LiveHeap) -> io::Result<()> {
+    let mut classes: Vec<(usize, u64)> = (0..NUM_SIZE_CLASSES)
+        .map(|c| (c, heap.bytes[c]))
+        .filter(|&(_, bytes)| bytes != 0)
+        .collect();
+    classes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    writeln!(
+        out,
+        "n{}: {} (heap allocation functions) malloc/new/new[], --alloc-fns, etc.",
+        classes.len(),
+        heap.total()
+    )?;
+    for (class, bytes) in classes {
+        if class == 0 {
+            writeln!(out, " n0: {bytes} 0x0: large allocations")?;
+        } else {
+            writeln!(
+                out,
+                " n0: {bytes} 0x0: size class {class} ({} bytes)",
+                size_class::class_to_size(class)
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,202 @@
+//! Allocator metadata kept apart from user memory (`meta-region` feature).
+//!
+//! Span structs and page map nodes normally come from the same
+//! `platform::page_alloc` stream as the spans they describe, so the OS is
+//! free to map them right after a user span, and a heap overflow running off
+//! the end of an allocation lands in them. With `meta-region` they are
+//! carved instead from a dedicated region reserved with
+//! [`platform::page_reserve_apart`]: [`GAP`] bytes of inaccessible address
+//! space on each side keep every other mapping, the heap included, away
+//! from it. Inside the region, each chunk handed out is followed by
+//! [`META_GUARD_PAGES`] inaccessible pages, so a linear overflow of one
+//! chunk faults before it reaches the next.
+//!
+//! Chunks are never given back, like the slabs and nodes they serve. When a
+//! region fills up, another one is reserved the same way.
+
+use crate::config::{META_GUARD_PAGES, PAGE_SIZE};
+use crate::platform;
+use crate::sync::SpinMutex;
+use core::ptr;
+
+/// Address space reserved per region.
+pub const REGION_SIZE: usize = if usize::BITS == 64 { 1 << 30 } else { 16 << 20 };
+
+/// Inaccessible address space kept on each side of a region.
+pub const GAP: usize = REGION_SIZE;
+
+/// Bump allocator over the current region.
+struct Region {
+    /// Next chunk of the current region.
+    next: *mut u8,
+    /// End of the current region.
+    end: usize,
+    /// Bytes handed out, guard pages excluded.
+    used: usize,
+    /// Lowest region address reserved so far.
+    lo: usize,
+    /// One past the highest region address reserved so far.
+    hi: usize,
+}
+
+// SAFETY: only accessed through a SpinMutex, and its chunks are never
+// returned.
+unsafe impl Send for Region {}
+
+static REGION: SpinMutex<Region> = SpinMutex::new(Region {
+    next: ptr::null_mut(),
+    end: 0,
+    used: 0,
+    lo: usize::MAX,
+    hi: 0,
+});
+
+impl Region {
+    /// Take `size` bytes (a multiple of the page size) plus the guard pages
+    /// after them, reserving a new region if this one is full.
+    fn take(&mut self, size: usize) -> *mut u8 {
+        let step = size + META_GUARD_PAGES * PAGE_SIZE;
+        if self.next.is_null() || self.next.addr() + step > self.end {
+            let len = REGION_SIZE.max(step);
+            let base = unsafe { platform::page_reserve_apart(len, GAP) };
+            if base.is_null() {
+                return ptr::null_mut();
+            }
+            self.next = base;
+            self.end = base.addr() + len;
+            self.lo = self.lo.min(base.addr());
+            self.hi = self.hi.max(self.end);
+        }
+        let chunk = self.next;
+        self.next = chunk.wrapping_add(step);
+        self.used += size;
+        chunk
+    }
+}
+
+/// Allocate `size` bytes of zeroed, page-aligned metadata from the region.
+/// Returns null on failure.
+///
+/// # Safety
+///
+/// The memory is never freed; `platform::page_dealloc` must not be called
+/// on it.
+pub(crate) unsafe fn alloc(size: usize) -> *mut u8 {
+    let size = size.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    let chunk = REGION.lock().take(size);
+    if chunk.is_null() {
+        return chunk;
+    }
+    unsafe { platform::page_recommit(chunk, size) };
+    // Miri's stand-in memory is always accessible.
+    if !unsafe { platform::page_protect(chunk, size, true) } && !cfg!(miri) {
+        return ptr::null_mut();
+    }
+    chunk
+}
+
+/// Bytes of metadata handed out so far, guard pages excluded.
+pub fn used_bytes() -> usize {
+    REGION.lock().used
+}
+
+/// Whether `ptr` may point into allocator metadata.
+///
+/// Conservative: checks the address range spanned by all regions, so
+/// anything mapped between two regions also reports `true`. Never reports
+/// `false` for a metadata pointer.
+pub fn owns(ptr: *const u8) -> bool {
+    let region = REGION.lock();
+    (region.lo..region.hi).contains(&ptr.addr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::alloc::GlobalAlloc;
+
+    #[test]
+    fn test_chunks_zeroed_and_apart() {
+        let a = unsafe { alloc(100) };
+        let b = unsafe { alloc(3 * PAGE_SIZE) };
+        assert!(!a.is_null() && !b.is_null());
+        assert!(a.addr().is_multiple_of(PAGE_SIZE));
+        assert!(b.addr().is_multiple_of(PAGE_SIZE));
+        assert!(owns(a) && owns(b));
+        unsafe {
+            assert_eq!(*a, 0);
+            assert_eq!(*b.add(3 * PAGE_SIZE - 1), 0);
+            a.write_bytes(0xAB, PAGE_SIZE);
+            b.write_bytes(0xCD, 3 * PAGE_SIZE);
+        }
+        // Other threads' tests may take chunks in between, never closer.
+        let (lo, hi) = (a.min(b), a.max(b));
+        let lo_size = if lo == a { PAGE_SIZE } else { 3 * PAGE_SIZE };
+        assert!(lo.addr() + lo_size + META_GUARD_PAGES * PAGE_SIZE <= hi.addr());
+        assert!(used_bytes() >= 4 * PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_span_metadata_in_region() {
+        let span = crate::span::alloc_span();
+        assert!(!span.is_null());
+        assert!(owns(span.cast()));
+        unsafe { crate::span::dealloc_span(span) };
+
+        let layout = core::alloc::Layout::from_size_align(1 << 20, 8).unwrap();
+        let p = unsafe { crate::RtMalloc.alloc(layout) };
+        assert!(!p.is_null());
+        assert!(!owns(p));
+        let span = crate::allocator::PAGE_MAP.get(p.addr() >> crate::config::PAGE_SHIFT);
+        assert!(owns(span.cast()));
+        unsafe { crate::RtMalloc.dealloc(p, layout) };
+    }
+
+    const CHILD_ENV: &str = "RTMALLOC_META_CHILD";
+
+    /// Body of the child process; a no-op when run directly.
+    #[test]
+    fn child_overflow_into_guard() {
+        if std::env::var_os(CHILD_ENV).is_none() {
+            return;
+        }
+        let chunk = unsafe { alloc(PAGE_SIZE) };
+        unsafe { chunk.write_bytes(1, PAGE_SIZE) };
+        std::println!("OVERFLOWING {chunk:p}");
+        unsafe { chunk.add(PAGE_SIZE).write_volatile(1) };
+        std::println!("CHILD SURVIVED");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_guard_page_faults() {
+        use std::os::unix::process::ExitStatusExt;
+
+        if META_GUARD_PAGES == 0 {
+            return;
+        }
+        let out = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "meta::tests::child_overflow_into_guard",
+                "--exact",
+                "--nocapture",
+                "--test-threads=1",
+            ])
+            .env(CHILD_ENV, "1")
+            .output()
+            .expect("spawn child");
+        let stdout = std::string::String::from_utf8_lossy(&out.stdout);
+        assert!(stdout.contains("OVERFLOWING"), "{out:?}");
+        assert!(!stdout.contains("CHILD SURVIVED"), "{out:?}");
+        assert_eq!(out.status.signal(), Some(11), "{out:?}");
+    }
+
+    #[test]
+    fn test_heap_kept_away() {
+        let meta = unsafe { alloc(PAGE_SIZE) };
+        let layout = core::alloc::Layout::from_size_align(1 << 20, 8).unwrap();
+        let p = unsafe { crate::RtMalloc.alloc(layout) };
+        assert!(p.addr().abs_diff(meta.addr()) >= GAP);
+        unsafe { crate::RtMalloc.dealloc(p, layout) };
+    }
+}
@@ -0,0 +1,294 @@
+//! Mid-heap: cached whole spans for allocations just above the small classes.
+//!
+//! Sizes between `MAX_SMALL_SIZE` and `mid_max_size` (2 MiB by default) would
+//! otherwise go straight to the page heap on every alloc and free, paying its
+//! lock plus a split and a coalesce each time. Here they are rounded up to
+//! coarse classes — powers of two and the halfway points between them — and a
+//! freed span of exactly a class's size is parked on a short per-class list
+//! for the next allocation of that class instead.
+//!
+//! A parked span keeps its page map entries and is marked
+//! [`SpanState::Cached`]: the page heap never coalesces into it, and a second
+//! free of the same pointer is rejected like any free of a non-live span.
+
+use crate::config::{LARGE_TRIM_PAGES, MID_CACHE_SPANS, MID_MAX_SIZE, PAGE_SIZE};
+use crate::size_class::MAX_SMALL_SIZE;
+use crate::span::{Span, SpanList, SpanState};
+use crate::sync::SpinMutex;
+use core::ptr;
+
+/// Ladder position of the smallest class covering `size`: `2 * log2(p)` for
+/// the power of two `p`, one less for `3p / 4`.
+const fn ladder_index(size: usize) -> usize {
+    let p = size.next_power_of_two();
+    let log = p.trailing_zeros() as usize;
+    if p >= 4 && size <= p / 4 * 3 {
+        2 * log - 1
+    } else {
+        2 * log
+    }
+}
+
+/// Size of the ladder position `idx`, the inverse of [`ladder_index`].
+const fn ladder_size(idx: usize) -> usize {
+    if idx.is_multiple_of(2) {
+        1 << (idx / 2)
+    } else {
+        3 << (idx / 2 - 1)
+    }
+}
+
+const BASE_INDEX: usize = ladder_index(MAX_SMALL_SIZE + 1);
+
+/// Number of mid classes. Zero when `mid_max_size` is within the small range
+/// or the mid-heap is disabled.
+pub const NUM_MID_CLASSES: usize = if MID_CACHE_SPANS != 0 && MID_MAX_SIZE > MAX_SMALL_SIZE {
+    ladder_index(MID_MAX_SIZE) - BASE_INDEX + 1
+} else {
+    0
+};
+
+/// Mid class serving `size`, or `None` if `size` is outside the mid range or
+/// the mid-heap is disabled (`mid_cache_spans = 0`).
+#[inline]
+pub const fn size_to_class(size: usize) -> Option<usize> {
+    if MID_CACHE_SPANS == 0 || size <= MAX_SMALL_SIZE || size > MID_MAX_SIZE {
+        return None;
+    }
+    Some(ladder_index(size) - BASE_INDEX)
+}
+
+/// Bytes handed out for mid class `cls`, before rounding to whole pages.
+#[inline]
+pub const fn class_to_size(cls: usize) -> usize {
+    ladder_size(cls + BASE_INDEX)
+}
+
+/// Pages in a span of mid class `cls`.
+#[inline]
+pub const fn class_to_pages(cls: usize) -> usize {
+    class_to_size(cls).div_ceil(PAGE_SIZE)
+}
+
+/// Pages to take from the page heap for `size` bytes of class `cls` when no
+/// parked span is free. Normally the whole class, so the span can be parked
+/// once freed; with `large_trim_pages` set, only the pages `size` needs when
+/// rounding up to the class would leave at least that many unused.
+#[inline]
+#[allow(clippy::absurd_extreme_comparisons)] // large_trim_pages may be 0
+pub const fn carve_pages(size: usize, cls: usize) -> usize {
+    let class_pages = class_to_pages(cls);
+    let pages = size.div_ceil(PAGE_SIZE);
+    if LARGE_TRIM_PAGES != 0 && class_pages - pages >= LARGE_TRIM_PAGES {
+        pages
+    } else {
+        class_pages
+    }
+}
+
+/// Mid class whose spans are exactly `pages` long. Small page sizes can put
+/// several ladder sizes on the same page count; the largest one owns it.
+#[inline]
+const fn pages_to_class(pages: usize) -> Option<usize> {
+    match size_to_class(pages * PAGE_SIZE) {
+        Some(cls) if class_to_pages(cls) == pages => Some(cls),
+        _ => None,
+    }
+}
+
+/// Per-class lists of parked spans.
+pub struct MidHeap {
+    classes: [SpinMutex<SpanList>; NUM_MID_CLASSES],
+}
+
+// SAFETY: The span lists are only touched under their SpinMutex.
+unsafe impl Sync for MidHeap {}
+
+impl Default for MidHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidHeap {
+    pub const fn new() -> Self {
+        Self {
+            classes: [const { SpinMutex::new(SpanList::new()) }; NUM_MID_CLASSES],
+        }
+    }
+
+    /// Take a parked span of `pages` pages, or null if there is none.
+    ///
+    /// The span is in use and still registered in the page map as a large
+    /// span, so its start address can be returned as is.
+    pub fn take(&self, pages: usize) -> *mut Span {
+        let Some(cls) = pages_to_class(pages) else {
+            return ptr::null_mut();
+        };
+        let span = unsafe { self.classes[cls].lock().pop() };
+        if !span.is_null() {
+            unsafe { (*span).state = SpanState::InUse };
+        }
+        span
+    }
+
+    /// Take a parked span from the largest class that has one, or null if
+    /// nothing is parked. Used by the page heap to reclaim spans before it
+    /// grows; the span is handed back in use, as from [`take`](Self::take).
+    pub fn take_largest(&self) -> *mut Span {
+        for cls in (0..NUM_MID_CLASSES).rev() {
+            let span = unsafe { self.classes[cls].lock().pop() };
+            if !span.is_null() {
+                unsafe { (*span).state = SpanState::InUse };
+                return span;
+            }
+        }
+        ptr::null_mut()
+    }
+
+    /// Park a freed large span if it is exactly the size of a mid class and
+    /// that class has room. Returns false if the span should go back to the
+    /// page heap instead.
+    ///
+    /// # Safety
+    ///
+    /// `span` must be a live large span (size class 0) that no one else holds.
+    pub unsafe fn park(&self, span: *mut Span) -> bool {
+        let Some(cls) = pages_to_class(unsafe { (*span).num_pages }) else {
+            return false;
+        };
+        let mut list = self.classes[cls].lock();
+        #[allow(clippy::absurd_extreme_comparisons)] // mid_cache_spans may be 0
+        if list.count >= MID_CACHE_SPANS {
+            return false;
+        }
+        unsafe {
+            (*span).state = SpanState::Cached;
+            (*span).fresh_from_os = false;
+            list.push(span);
+        }
+        true
+    }
+
+    /// Bytes in all parked spans.
+    pub fn cached_bytes(&self) -> usize {
+        (0..NUM_MID_CLASSES)
+            .map(|cls| self.classes[cls].lock().count * class_to_pages(cls) * PAGE_SIZE)
+            .sum()
+    }
+
+    /// Spans currently parked with `pages` pages each.
+    pub fn cached_spans(&self, pages: usize) -> usize {
+        match pages_to_class(pages) {
+            Some(cls) => self.classes[cls].lock().count,
+            None => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ladder_roundtrip() {
+        for idx in 4..60 {
+            assert_eq!(ladder_index(ladder_size(idx)), idx);
+            assert_eq!(ladder_index(ladder_size(idx) + 1), idx + 1);
+        }
+        assert_eq!(ladder_size(ladder_index(300 * 1024)), 384 * 1024);
+        assert_eq!(ladder_size(ladder_index(384 * 1024 + 1)), 512 * 1024);
+    }
+
+    // `minimal` turns the mid-heap off.
+    #[test]
+    #[cfg(not(feature = "minimal"))]
+    fn test_class_bounds() {
+        if NUM_MID_CLASSES == 0 || MID_CACHE_SPANS == 0 {
+            return;
+        }
+        assert_eq!(size_to_class(MAX_SMALL_SIZE), None);
+        assert_eq!(size_to_class(MAX_SMALL_SIZE + 1), Some(0));
+        assert_eq!(size_to_class(MID_MAX_SIZE + 1), None);
+        assert_eq!(size_to_class(MID_MAX_SIZE), Some(NUM_MID_CLASSES - 1));
+
+        let mut prev = 0;
+        for cls in 0..NUM_MID_CLASSES {
+            let size = class_to_size(cls);
+            assert!(size > MAX_SMALL_SIZE && size > prev);
+            assert_eq!(size_to_class(size), Some(cls));
+            assert!(class_to_pages(cls) * PAGE_SIZE >= size);
+            prev = size;
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "minimal"))]
+    #[allow(clippy::absurd_extreme_comparisons)]
+    fn test_carve_pages() {
+        for cls in 0..NUM_MID_CLASSES {
+            let class_pages = class_to_pages(cls);
+            assert_eq!(carve_pages(class_to_size(cls), cls), class_pages);
+
+            let size = if cls == 0 {
+                MAX_SMALL_SIZE + 1
+            } else {
+                class_to_size(cls - 1) + 1
+            };
+            let pages = carve_pages(size, cls);
+            assert!(pages * PAGE_SIZE >= size);
+            if pages != class_pages {
+                assert_eq!(pages, size.div_ceil(PAGE_SIZE));
+                assert!(LARGE_TRIM_PAGES != 0 && class_pages - pages >= LARGE_TRIM_PAGES);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "minimal"))]
+    fn test_park_and_take() {
+        use crate::page_heap::PageHeap;
+        use crate::pagemap::PageMap;
+        use alloc::boxed::Box;
+
+        if NUM_MID_CLASSES == 0 || MID_CACHE_SPANS == 0 {
+            return;
+        }
+        let pm = Box::leak(Box::new(PageMap::new()));
+        let mut heap = PageHeap::new(pm);
+        let mid = MidHeap::new();
+        let pages = class_to_pages(NUM_MID_CLASSES - 1);
+
+        unsafe {
+            // Only spans of exactly a class's size are parked.
+            let odd = heap.allocate_span(pages - 1);
+            if pages_to_class(pages - 1).is_none() {
+                assert!(!mid.park(odd));
+            }
+            heap.deallocate_span(odd);
+
+            let mut spans = [core::ptr::null_mut(); MID_CACHE_SPANS];
+            for s in spans.iter_mut() {
+                *s = heap.allocate_span(pages);
+                assert!(mid.park(*s));
+                assert_eq!((**s).state, SpanState::Cached);
+            }
+            assert_eq!(mid.cached_spans(pages), MID_CACHE_SPANS);
+
+            // A full class sends the span back to the caller.
+            let extra = heap.allocate_span(pages);
+            assert!(!mid.park(extra));
+            heap.deallocate_span(extra);
+
+            // LIFO, and handed back in use.
+            let taken = mid.take(pages);
+            assert_eq!(taken, spans[MID_CACHE_SPANS - 1]);
+            assert_eq!((*taken).state, SpanState::InUse);
+            assert_eq!(mid.cached_spans(pages), MID_CACHE_SPANS - 1);
+            heap.deallocate_span(taken);
+
+            while !mid.take(pages).is_null() {}
+            assert_eq!(mid.cached_spans(pages), 0);
+        }
+    }
+}
@@ -0,0 +1,454 @@
+//! Object stacks: lock-free per-size-class pools for builds without a thread cache.
+//!
+//! Without `percpu`, `nightly` or `std` there is no thread cache, and every
+//! small allocation and free used to lock its class's central free list for a
+//! single object. For classes up to [`STACK_MAX_SIZE`] an [`ObjectStack`] now
+//! sits in front: a Treiber stack of free objects, popped and pushed with one
+//! CAS. The central free list only sees batches. A pop that finds the stack
+//! empty takes `batch_size` objects under the central lock and pushes all but
+//! one; a push that grows the stack past two batches drains it, keeping one
+//! batch and returning the rest.
+//!
+//! Objects on a stack are free to the application but still allocated as far
+//! as their span knows. The span counts catch up when a drain returns them.
+//!
+//! Stacks are LIFO, so classes set to [`ReuseOrder::Fifo`] bypass them.
+
+use crate::central_free_list::{self, CentralCache};
+use crate::failure::{self, Failure};
+use crate::page_heap::PageHeap;
+use crate::pagemap::PageMap;
+use crate::size_class::{self, NUM_SIZE_CLASSES, ReuseOrder};
+use crate::span::FreeObject;
+use crate::sync::SpinMutex;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Largest object size served through an object stack. Bigger classes are
+/// allocated less often and keep locking the central free list per object.
+pub const STACK_MAX_SIZE: usize = 1024;
+
+/// Bits of the packed head holding the top object's address. The tag takes
+/// the rest.
+const TAG_SHIFT: u32 = if usize::BITS == 64 { 48 } else { 32 };
+const ADDR_MASK: u64 = (1 << TAG_SHIFT) - 1;
+
+/// Whether `size_class` goes through its object stack.
+#[inline]
+pub const fn is_stacked(size_class: usize) -> bool {
+    size_class::class_to_size(size_class) <= STACK_MAX_SIZE
+}
+
+/// Pack `top` and `tag` into a head word. A `top` read from a racing pop
+/// may be garbage; it is masked, and the exchange fails anyway.
+#[inline]
+fn pack(top: *mut FreeObject, tag: u64) -> u64 {
+    (tag << TAG_SHIFT) | (top.addr() as u64 & ADDR_MASK)
+}
+
+#[inline]
+fn unpack(head: u64) -> (*mut FreeObject, u64) {
+    (
+        ptr::with_exposed_provenance_mut((head & ADDR_MASK) as usize),
+        head >> TAG_SHIFT,
+    )
+}
+
+/// A Treiber stack of free objects linked through [`FreeObject`].
+///
+/// The head packs the top object with a tag that every update bumps, so a pop
+/// that read a stale top and next cannot succeed after the top was popped and
+/// pushed again in between (ABA).
+pub struct ObjectStack {
+    /// Top object in the low [`TAG_SHIFT`] bits, tag above them.
+    head: AtomicU64,
+    /// Objects on the stack plus those being pushed; never below the real
+    /// count.
+    len: AtomicUsize,
+}
+
+impl Default for ObjectStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ObjectStack {
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicU64::new(0),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Objects on the stack. May briefly count objects still being pushed.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        unpack(self.head.load(Ordering::Relaxed)).0.is_null()
+    }
+
+    /// Pop the top object, or null if the stack is empty.
+    ///
+    /// # Safety
+    ///
+    /// Every object ever pushed must stay mapped for the life of the stack.
+    pub unsafe fn pop(&self) -> *mut FreeObject {
+        let mut old = self.head.load(Ordering::Acquire);
+        loop {
+            let (top, tag) = unpack(old);
+            if top.is_null() {
+                return ptr::null_mut();
+            }
+            // Another thread may pop `top` and start using it before this
+            // read; the tag then fails the exchange below.
+            let Some(next) = (unsafe { FreeObject::racy_next(top) }) else {
+                let now = self.head.load(Ordering::Acquire);
+                if now == old {
+                    failure::fatal(Failure::CorruptedFreeList, top as usize);
+                }
+                old = now;
+                continue;
+            };
+            match self.head.compare_exchange_weak(
+                old,
+                pack(next, tag.wrapping_add(1)),
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                    return top;
+                }
+                Err(now) => old = now,
+            }
+        }
+    }
+
+    /// Push the list `head..=tail` of `count` objects. Returns the stack's
+    /// length including them.
+    ///
+    /// # Safety
+    ///
+    /// `head..=tail` must be a valid list of `count` free objects owned by the
+    /// caller.
+    pub unsafe fn push_list(
+        &self,
+        head: *mut FreeObject,
+        tail: *mut FreeObject,
+        count: usize,
+    ) -> usize {
+        debug_assert!(
+            head as u64 & !ADDR_MASK == 0,
+            "{head:p} does not fit the packed head"
+        );
+        let len = self.len.fetch_add(count, Ordering::Relaxed) + count;
+        let mut old = self.head.load(Ordering::Relaxed);
+        loop {
+            let (top, tag) = unpack(old);
+            unsafe { FreeObject::set_next(tail, top) };
+            match self.head.compare_exchange_weak(
+                old,
+                pack(head, tag.wrapping_add(1)),
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return len,
+                Err(now) => old = now,
+            }
+        }
+    }
+
+    /// Take every object on the stack. Returns (count, head, tail); both
+    /// ends are null when count is 0.
+    pub fn take_all(&self) -> (usize, *mut FreeObject, *mut FreeObject) {
+        let mut old = self.head.load(Ordering::Acquire);
+        let head = loop {
+            let (top, tag) = unpack(old);
+            if top.is_null() {
+                return (0, ptr::null_mut(), ptr::null_mut());
+            }
+            match self.head.compare_exchange_weak(
+                old,
+                pack(ptr::null_mut(), tag.wrapping_add(1)),
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break top,
+                Err(now) => old = now,
+            }
+        };
+
+        // The list is ours now, so its links are stable.
+        let (mut count, mut tail) = (1, head);
+        loop {
+            let next = unsafe { FreeObject::next(tail) };
+            if next.is_null() {
+                break;
+            }
+            tail = next;
+            count += 1;
+        }
+        self.len.fetch_sub(count, Ordering::Relaxed);
+        (count, head, tail)
+    }
+}
+
+/// Array of object stacks, one per size class.
+pub struct ObjectStackArray {
+    stacks: [ObjectStack; NUM_SIZE_CLASSES],
+}
+
+impl Default for ObjectStackArray {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ObjectStackArray {
+    pub const fn new() -> Self {
+        Self {
+            stacks: [const { ObjectStack::new() }; NUM_SIZE_CLASSES],
+        }
+    }
+
+    /// Free objects of `size_class` on its stack.
+    pub fn cached_objects(&self, size_class: usize) -> usize {
+        self.stacks[size_class].len()
+    }
+
+    /// Allocate one object of `size_class`, refilling the stack with a batch
+    /// from the central free list when it is empty. Null if out of memory.
+    ///
+    /// # Safety
+    ///
+    /// `size_class` must satisfy [`is_stacked`]. `central`, `page_heap` and
+    /// `pagemap` must be the global instances.
+    pub unsafe fn allocate(
+        &self,
+        size_class: usize,
+        central: &CentralCache,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
+    ) -> *mut FreeObject {
+        let stack = &self.stacks[size_class];
+        let obj = unsafe { stack.pop() };
+        if !obj.is_null() {
+            crate::stat_inc!(thread_cache_hits);
+            return obj;
+        }
+        crate::stat_inc!(thread_cache_misses);
+        crate::stat_inc!(central_cache_hits);
+
+        let batch = match size_class::reuse_order(size_class) {
+            ReuseOrder::Lifo => size_class::batch_size(size_class),
+            ReuseOrder::Fifo => 1,
+        };
+        let (count, head, tail) = crate::time_slow_path!(CentralRefill, unsafe {
+            central_free_list::remove_range_dropping_lock(
+                central.get(size_class),
+                size_class,
+                batch,
+                page_heap,
+                pagemap,
+            )
+        });
+        #[cfg(feature = "usdt")]
+        crate::usdt::central_refill(size_class, batch, count);
+        if count > 1 {
+            unsafe { stack.push_list(FreeObject::next(head), tail, count - 1) };
+        }
+        head
+    }
+
+    /// Free `obj` onto its class's stack. A stack that grows past two
+    /// batches keeps one and returns the rest to the central free list.
+    ///
+    /// # Safety
+    ///
+    /// `obj` must be a free object of `size_class`, not referenced by any
+    /// other list, and `size_class` must satisfy [`is_stacked`].
+    pub unsafe fn deallocate(
+        &self,
+        size_class: usize,
+        obj: *mut FreeObject,
+        central: &CentralCache,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
+    ) {
+        let (head, count) = match size_class::reuse_order(size_class) {
+            ReuseOrder::Fifo => {
+                unsafe { FreeObject::set_next(obj, ptr::null_mut()) };
+                (obj, 1)
+            }
+            ReuseOrder::Lifo => {
+                let stack = &self.stacks[size_class];
+                let batch = size_class::batch_size(size_class);
+                if unsafe { stack.push_list(obj, obj, 1) } <= 2 * batch {
+                    return;
+                }
+                let (count, head, tail) = stack.take_all();
+                if count <= batch {
+                    // Other threads drained it first.
+                    if count > 0 {
+                        unsafe { stack.push_list(head, tail, count) };
+                    }
+                    return;
+                }
+                let mut last_kept = head;
+                for _ in 1..batch {
+                    last_kept = unsafe { FreeObject::next(last_kept) };
+                }
+                let rest = unsafe { FreeObject::next(last_kept) };
+                unsafe { stack.push_list(head, last_kept, batch) };
+                (rest, count - batch)
+            }
+        };
+
+        unsafe {
+            central_free_list::insert_range_dropping_lock(
+                central.get(size_class),
+                head,
+                count,
+                page_heap,
+                pagemap,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use std::sync::Arc;
+
+    fn make_test_env() -> (
+        &'static PageMap,
+        SpinMutex<PageHeap>,
+        CentralCache,
+        ObjectStackArray,
+    ) {
+        let pm = Box::leak(Box::new(PageMap::new()));
+        let heap = SpinMutex::new(PageHeap::new(pm));
+        let central = CentralCache::new();
+        let stacks = ObjectStackArray::new();
+        (pm, heap, central, stacks)
+    }
+
+    fn objects(n: usize) -> Vec<*mut FreeObject> {
+        (0..n)
+            .map(|_| Box::into_raw(Box::new(0usize)) as *mut FreeObject)
+            .collect()
+    }
+
+    #[test]
+    fn test_push_pop_lifo() {
+        let stack = ObjectStack::new();
+        let objs = objects(3);
+        unsafe {
+            assert!(stack.pop().is_null());
+            for &obj in &objs {
+                stack.push_list(obj, obj, 1);
+            }
+            assert_eq!(stack.len(), 3);
+            for &obj in objs.iter().rev() {
+                assert_eq!(stack.pop(), obj);
+            }
+            assert!(stack.pop().is_null());
+            assert!(stack.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_take_all() {
+        let stack = ObjectStack::new();
+        let objs = objects(4);
+        unsafe {
+            FreeObject::set_next(objs[0], objs[1]);
+            stack.push_list(objs[0], objs[1], 2);
+            FreeObject::set_next(objs[2], objs[3]);
+            stack.push_list(objs[2], objs[3], 2);
+        }
+        assert_eq!(stack.take_all(), (4, objs[2], objs[1]));
+        assert_eq!(stack.len(), 0);
+        assert_eq!(stack.take_all().0, 0);
+    }
+
+    #[test]
+    fn test_concurrent_push_pop() {
+        const THREADS: usize = 8;
+        const PER_THREAD: usize = 64;
+        let stack = Arc::new(ObjectStack::new());
+        let objs = objects(THREADS * PER_THREAD);
+        for &obj in &objs {
+            unsafe { stack.push_list(obj, obj, 1) };
+        }
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let stack = Arc::clone(&stack);
+                std::thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        let mut held = Vec::new();
+                        for _ in 0..4 {
+                            let obj = unsafe { stack.pop() };
+                            assert!(!obj.is_null());
+                            // Scribble over the link, as an application would.
+                            unsafe { (obj as *mut usize).write(usize::MAX) };
+                            held.push(obj);
+                        }
+                        for obj in held {
+                            unsafe { stack.push_list(obj, obj, 1) };
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let (count, _, _) = stack.take_all();
+        assert_eq!(count, objs.len());
+    }
+
+    #[test]
+    fn test_refill_and_drain() {
+        let (pm, heap, central, stacks) = make_test_env();
+        let cls = 1;
+        let batch = size_class::batch_size(cls);
+        assert!(size_class::class_info(cls).objects_per_span() > 4 * batch);
+        unsafe {
+            // A miss takes a batch and hands out one object.
+            let first = stacks.allocate(cls, &central, &heap, pm);
+            assert!(!first.is_null());
+            assert_eq!(stacks.cached_objects(cls), batch - 1);
+            let mut objs = Vec::from([first]);
+            for _ in 0..3 * batch {
+                objs.push(stacks.allocate(cls, &central, &heap, pm));
+            }
+            assert!(objs.iter().all(|o| !o.is_null()));
+
+            // Freeing past two batches returns all but one to the central
+            // list. Everything fits one span, which is retained, so no object
+            // goes missing.
+            let central_free = central.get(cls).lock().free_objects();
+            let cached = stacks.cached_objects(cls);
+            for &obj in &objs {
+                stacks.deallocate(cls, obj, &central, &heap, pm);
+                assert!(stacks.cached_objects(cls) <= 2 * batch);
+            }
+            assert!(stacks.cached_objects(cls) >= batch);
+            assert_eq!(
+                central.get(cls).lock().free_objects() + stacks.cached_objects(cls),
+                central_free + cached + objs.len()
+            );
+        }
+    }
+}
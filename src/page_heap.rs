@@ -2,27 +2,181 @@
 //!
 //! Responsibilities:
 //! - Allocate spans of N pages (searching free lists, splitting larger spans)
-//! - Deallocate spans (coalescing with adjacent free spans)
-//! - Grow the heap by requesting memory from the OS
+//! - Deallocate spans (coalescing with adjacent free spans, optionally
+//!   deferred and done in batches; see [`defer_coalescing`]), decommitting
+//!   those of at least `free_decommit_min` bytes at once, and optionally
+//!   holding them back from reuse for a while (`span_cooldown`)
+//! - Populate size classes from free spans (and spans parked in the
+//!   mid-heap) before growing, leaving `large_reserve_pages` of large free
+//!   spans for large allocations
+//! - Grow the heap by requesting memory from the OS, reporting each change
+//!   to an optional [`GrowthHook`]
+//! - Optionally keep free lists sorted by address (`address_ordered_spans`),
+//!   so the lowest free span is reused first
 //! - Register/unregister spans in the page map
 
-use crate::config::{PAGE_SHIFT, PAGE_SIZE};
+use crate::config::{
+    ADDRESS_ORDERED_SPANS, FREE_DECOMMIT_MIN, HEAP_BASE, LARGE_RESERVE_PAGES, MAX_HEAP, PAGE_SHIFT,
+    PAGE_SIZE, PREFAULT, SPAN_COOLDOWN, SPAN_COOLDOWN_MS,
+};
+use crate::failure::{self, Failure};
+use crate::mid_heap::MidHeap;
 use crate::pagemap::PageMap;
 use crate::platform;
 use crate::span::{self, Span, SpanList, SpanState};
+#[cfg(all(feature = "stats", feature = "std"))]
+use crate::stat_max;
+use crate::{stat_add, stat_inc};
 use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
 #[cfg(feature = "debug")]
 use std::println;
 
 use crate::config::MAX_PAGES;
 
+/// A change in the memory the page heap holds from the OS, passed to the
+/// [`GrowthHook`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct GrowthEvent {
+    /// Start of the region mapped or released.
+    pub addr: usize,
+    /// Bytes added (positive) or given back (negative). The page heap does
+    /// not return memory to the OS yet, so this is currently always positive.
+    pub delta: isize,
+    /// Bytes held from the OS for spans after the change, as in
+    /// [`PageHeapUsage::system_bytes`].
+    pub system_bytes: usize,
+}
+
+/// Called with every [`GrowthEvent`], e.g. to log RSS step changes as they
+/// happen instead of polling.
+///
+/// It runs inside the allocator with the page heap lock held, so it must not
+/// allocate or free through rtmalloc and should return quickly.
+pub type GrowthHook = extern "C" fn(GrowthEvent);
+
+/// Registered [`GrowthHook`] as an address, 0 when none.
+static GROWTH_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// Register `hook` to be called whenever the page heap maps or releases OS
+/// memory. `None` removes it.
+pub fn set_growth_hook(hook: Option<GrowthHook>) {
+    GROWTH_HOOK.store(hook.map_or(0, |h| h as usize), Ordering::Release);
+}
+
+fn growth_hook() -> Option<GrowthHook> {
+    match GROWTH_HOOK.load(Ordering::Acquire) {
+        0 => None,
+        addr => Some(unsafe { core::mem::transmute::<usize, GrowthHook>(addr) }),
+    }
+}
+
+/// Page heap totals, from [`PageHeap::usage`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PageHeapUsage {
+    /// Bytes mapped from the OS for spans. Metadata (span structs, page map
+    /// nodes) is not included.
+    pub system_bytes: usize,
+    /// Free spans, pending ones included.
+    pub free_spans: usize,
+    /// Bytes in those spans.
+    pub free_bytes: usize,
+    /// Freed spans held back from reuse by the cool-down queue
+    /// (`span_cooldown`), not counted as free.
+    pub cooling_spans: usize,
+    /// Bytes in those spans.
+    pub cooling_bytes: usize,
+}
+
+/// Pending spans that force a coalescing pass on free, bounding how much
+/// free memory can sit unmerged between allocations.
+const MAX_PENDING_SPANS: usize = 64;
+
+/// Freed spans waiting out the cool-down before reuse, oldest at `head`.
+/// They are off every list and out of the page map, so nothing carves them
+/// or merges with them until they leave.
+struct Cooldown {
+    spans: [*mut Span; SPAN_COOLDOWN],
+    /// When each span joined, with `span_cooldown_ms`.
+    since_ms: [u64; SPAN_COOLDOWN],
+    head: usize,
+    len: usize,
+}
+
+impl Cooldown {
+    const fn new() -> Self {
+        Self {
+            spans: [ptr::null_mut(); SPAN_COOLDOWN],
+            since_ms: [0; SPAN_COOLDOWN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, span: *mut Span, now_ms: u64) {
+        let slot = self.slot(self.len);
+        self.spans[slot] = span;
+        self.since_ms[slot] = now_ms;
+        self.len += 1;
+    }
+
+    /// The oldest span if more than `keep` are held or, with `now_ms`, it
+    /// has waited `span_cooldown_ms`.
+    fn pop_expired(&mut self, keep: usize, now_ms: Option<u64>) -> Option<*mut Span> {
+        if self.len == 0 {
+            return None;
+        }
+        let aged = now_ms.is_some_and(|now| self.since_ms[self.head] + SPAN_COOLDOWN_MS <= now);
+        if self.len <= keep && !aged {
+            return None;
+        }
+        let span = self.spans[self.head];
+        self.head = self.slot(1);
+        self.len -= 1;
+        Some(span)
+    }
+
+    fn spans(&self) -> impl Iterator<Item = *mut Span> + '_ {
+        (0..self.len).map(|i| self.spans[self.slot(i)])
+    }
+
+    /// Index of the `i`th span from the oldest.
+    fn slot(&self, i: usize) -> usize {
+        (self.head + i) % self.spans.len()
+    }
+}
+
+/// Lists [`PageHeap::span_list`] can return.
+pub const NUM_SPAN_LISTS: usize = MAX_PAGES + 3;
+
 pub struct PageHeap {
     /// free_lists[k] holds free spans of exactly k pages (index 0 unused).
     free_lists: [SpanList; MAX_PAGES + 1],
     /// Free spans larger than MAX_PAGES pages.
     large_spans: SpanList,
+    /// Freed spans waiting for a batched coalescing pass.
+    pending: SpanList,
+    /// Whether `deallocate_span` defers coalescing to `coalesce_pending`.
+    defer: bool,
+    /// Whether free lists are kept sorted by address instead of LIFO.
+    address_ordered: bool,
+    /// Freed spans of at least this many bytes are decommitted (0 = never).
+    free_decommit_min: usize,
+    /// Freed spans not yet eligible for reuse (`span_cooldown`).
+    cooldown: Cooldown,
+    /// Bytes mapped from the OS for spans.
+    system_bytes: usize,
+    /// Id of the most recent OS chunk; see [`Span::chunk_id`].
+    last_chunk: u32,
+    /// Unused part of the `max_heap` region, `region_next..region_end`;
+    /// null and 0 until the region is reserved.
+    region_next: *mut u8,
+    region_end: usize,
     /// Reference to the global page map.
     pagemap: &'static PageMap,
+    /// Mid-heap whose parked spans populates reclaim before growing.
+    mid_heap: Option<&'static MidHeap>,
 }
 
 // SAFETY: PageHeap is only accessed through a SpinMutex. Raw pointers within
@@ -34,8 +188,138 @@ impl PageHeap {
         Self {
             free_lists: [const { SpanList::new() }; MAX_PAGES + 1],
             large_spans: SpanList::new(),
+            pending: SpanList::new(),
+            defer: false,
+            address_ordered: ADDRESS_ORDERED_SPANS,
+            free_decommit_min: FREE_DECOMMIT_MIN,
+            cooldown: Cooldown::new(),
+            system_bytes: 0,
+            last_chunk: 0,
+            region_next: ptr::null_mut(),
+            region_end: 0,
             pagemap,
+            mid_heap: None,
+        }
+    }
+
+    /// Let [`allocate_small_span`](Self::allocate_small_span) reclaim spans
+    /// parked in `mid_heap` before growing the heap.
+    pub const fn with_mid_heap(mut self, mid_heap: &'static MidHeap) -> Self {
+        self.mid_heap = Some(mid_heap);
+        self
+    }
+
+    /// Switch deferred coalescing on or off. Turning it off coalesces
+    /// whatever is pending.
+    ///
+    /// # Safety
+    ///
+    /// Caller must hold exclusive access (via the enclosing `SpinMutex`).
+    pub unsafe fn set_defer_coalescing(&mut self, enabled: bool) {
+        self.defer = enabled;
+        if !enabled {
+            unsafe { self.coalesce_pending() };
+        }
+    }
+
+    /// Coalesce every pending span and move it to the free lists.
+    ///
+    /// # Safety
+    ///
+    /// Caller must hold exclusive access (via the enclosing `SpinMutex`).
+    pub unsafe fn coalesce_pending(&mut self) {
+        loop {
+            // Merging can pull neighbours out of `pending` as well.
+            let span = unsafe { self.pending.pop() };
+            if span.is_null() {
+                break;
+            }
+            unsafe {
+                (*span).state = SpanState::Free;
+                self.coalesce_and_insert(span);
+            }
+        }
+    }
+
+    /// Freed spans still waiting to be coalesced.
+    pub fn pending_spans(&self) -> usize {
+        self.pending.count
+    }
+
+    /// Span list `index` of [`NUM_SPAN_LISTS`]: the exact-size free lists by
+    /// page count (index 0 is always empty), then the large free spans, then
+    /// the pending ones. For checkers that walk the heap a list at a time.
+    pub fn span_list(&self, index: usize) -> &SpanList {
+        match index {
+            0..=MAX_PAGES => &self.free_lists[index],
+            i if i == MAX_PAGES + 1 => &self.large_spans,
+            _ => &self.pending,
+        }
+    }
+
+    /// Bytes mapped from the OS and how many of them sit in free (or
+    /// pending) spans. O(free spans larger than `max_pages`).
+    pub fn usage(&self) -> PageHeapUsage {
+        let mut usage = PageHeapUsage {
+            system_bytes: self.system_bytes,
+            ..PageHeapUsage::default()
+        };
+        for (pages, list) in self.free_lists.iter().enumerate() {
+            usage.free_spans += list.count;
+            usage.free_bytes += list.count * pages * PAGE_SIZE;
+        }
+        for list in [&self.large_spans, &self.pending] {
+            let mut span = list.head;
+            while !span.is_null() {
+                unsafe {
+                    usage.free_spans += 1;
+                    usage.free_bytes += (*span).byte_size();
+                    span = (*span).next;
+                }
+            }
+        }
+        for span in self.cooldown.spans() {
+            usage.cooling_spans += 1;
+            usage.cooling_bytes += unsafe { (*span).byte_size() };
+        }
+        usage
+    }
+
+    /// Coalesce pending spans, then have the OS drop the pages of every free
+    /// span: they stay mapped and read as zero, but no longer count towards
+    /// RSS until touched again. Returns the bytes dropped, 0 where the OS
+    /// cannot do this (see [`platform::page_zero`]).
+    ///
+    /// # Safety
+    ///
+    /// Caller must hold exclusive access (via the enclosing `SpinMutex`).
+    pub unsafe fn release_free(&mut self) -> usize {
+        unsafe { self.coalesce_pending() };
+        let mut released = 0;
+        for list in self.free_lists.iter().chain([&self.large_spans]) {
+            for span in unsafe { list.spans() } {
+                let (ptr, size) = unsafe { ((*span).start_addr(), (*span).byte_size()) };
+                if unsafe { platform::page_zero(ptr, size) } {
+                    released += size;
+                }
+            }
         }
+        released
+    }
+
+    /// Account for `size` bytes at `ptr` newly mapped for spans, returning
+    /// the chunk id for spans made from them.
+    fn note_mapped(&mut self, ptr: *mut u8, size: usize) -> u32 {
+        self.system_bytes += size;
+        self.last_chunk = self.last_chunk.wrapping_add(1);
+        if let Some(hook) = growth_hook() {
+            hook(GrowthEvent {
+                addr: ptr as usize,
+                delta: size as isize,
+                system_bytes: self.system_bytes,
+            });
+        }
+        self.last_chunk
     }
 
     /// Allocate a span of at least `num_pages` pages.
@@ -45,7 +329,83 @@ impl PageHeap {
     ///
     /// Caller must hold exclusive access (via the enclosing `SpinMutex`).
     pub unsafe fn allocate_span(&mut self, num_pages: usize) -> *mut Span {
-        assert!(num_pages > 0);
+        if num_pages == 0 {
+            failure::report(Failure::ZeroPageSpan, 0);
+            return ptr::null_mut();
+        }
+        let span = unsafe { self.take_free(num_pages, 0) };
+        if !span.is_null() {
+            return span;
+        }
+
+        // Nothing in free lists. Grow the heap from the OS.
+        unsafe { self.grow(num_pages, 0) }
+    }
+
+    /// Allocate a span of `num_pages` pages to populate a size class.
+    ///
+    /// Unlike [`allocate_span`](Self::allocate_span), this never carves a
+    /// span above `MAX_PAGES` if that would leave fewer than
+    /// `large_reserve_pages` free pages in such spans, and before growing
+    /// the heap it hands spans parked in the mid-heap back to the free lists,
+    /// largest first, so memory freed by large allocations is reused.
+    ///
+    /// # Safety
+    ///
+    /// Caller must hold exclusive access (via the enclosing `SpinMutex`).
+    pub unsafe fn allocate_small_span(&mut self, num_pages: usize) -> *mut Span {
+        if num_pages == 0 {
+            failure::report(Failure::ZeroPageSpan, 0);
+            return ptr::null_mut();
+        }
+        loop {
+            let span = unsafe { self.take_free(num_pages, LARGE_RESERVE_PAGES) };
+            if !span.is_null() {
+                return span;
+            }
+            let parked = self.mid_heap.map_or(ptr::null_mut(), MidHeap::take_largest);
+            if parked.is_null() {
+                break;
+            }
+            stat_inc!(mid_cache_reclaims);
+            unsafe { self.deallocate_now(parked) };
+        }
+        unsafe { self.grow(num_pages, LARGE_RESERVE_PAGES) }
+    }
+
+    /// Grow the heap for `num_pages` pages the free lists could not supply.
+    /// Spans still cooling down count as a delayed reuse when one of them
+    /// would have done; if the OS has no more memory, they are released
+    /// early rather than failing the allocation.
+    unsafe fn grow(&mut self, num_pages: usize, reserve: usize) -> *mut Span {
+        if self
+            .cooldown
+            .spans()
+            .any(|s| unsafe { (*s).num_pages } >= num_pages)
+        {
+            stat_inc!(cooldown_delayed_reuses);
+        }
+        let span = crate::time_slow_path!(PageHeapGrow, unsafe { self.grow_heap(num_pages) });
+        if !span.is_null() || self.cooldown.len == 0 {
+            return span;
+        }
+        unsafe {
+            self.flush_cooldown();
+            self.take_free(num_pages, reserve)
+        }
+    }
+
+    /// Carve `num_pages` pages from the free lists, or return null. A span
+    /// above `MAX_PAGES` is only used if `reserve` free pages stay in such
+    /// spans afterwards.
+    unsafe fn take_free(&mut self, num_pages: usize, reserve: usize) -> *mut Span {
+        if SPAN_COOLDOWN_MS != 0 && self.cooldown.len != 0 {
+            unsafe { self.expire_cooldown(SPAN_COOLDOWN) };
+        }
+        // Deferred frees are merged here, one batch per allocation.
+        if !self.pending.is_empty() {
+            unsafe { self.coalesce_pending() };
+        }
 
         // Search free lists for an exact or larger match
         if num_pages <= MAX_PAGES {
@@ -60,30 +420,164 @@ impl PageHeap {
 
         // Search large spans (best-fit)
         let best = unsafe { self.find_best_large_span(num_pages) };
-        if !best.is_null() {
-            unsafe { self.large_spans.remove(best) };
-            return unsafe { self.carve_span(best, num_pages) };
+        if best.is_null()
+            || (reserve > 0 && unsafe { self.large_pages_after(best, num_pages) } < reserve)
+        {
+            return ptr::null_mut();
         }
+        unsafe { self.large_spans.remove(best) };
+        unsafe { self.carve_span(best, num_pages) }
+    }
 
-        // Nothing in free lists. Grow the heap from the OS.
-        unsafe { self.grow_heap(num_pages) }
+    /// Free pages left in large spans after carving `num_pages` from `span`.
+    unsafe fn large_pages_after(&self, span: *mut Span, num_pages: usize) -> usize {
+        let total: usize = unsafe { self.large_spans.spans() }
+            .map(|s| unsafe { (*s).num_pages })
+            .sum();
+        let n = unsafe { (*span).num_pages };
+        let rest = n - num_pages;
+        total - n + if rest > MAX_PAGES { rest } else { 0 }
     }
 
     /// Deallocate a span, returning it to the free lists.
-    /// Attempts to coalesce with adjacent free spans.
+    /// Attempts to coalesce with adjacent free spans, or with deferred
+    /// coalescing on, queues it for the next batch. With `span_cooldown`
+    /// set, it waits in the cool-down queue first.
     ///
     /// # Safety
     ///
     /// `span` must be a valid, in-use span previously returned by `allocate_span`.
     pub unsafe fn deallocate_span(&mut self, span: *mut Span) {
+        if SPAN_COOLDOWN == 0 {
+            return unsafe { self.deallocate_now(span) };
+        }
+        unsafe {
+            self.clear_freed(span);
+            // Out of the page map and off the lists, so neither carving nor
+            // a neighbour's merge can reach its pages while it waits.
+            (*span).state = SpanState::Cached;
+            self.expire_cooldown(SPAN_COOLDOWN - 1);
+        }
+        let now = if SPAN_COOLDOWN_MS != 0 {
+            platform::now_ms()
+        } else {
+            0
+        };
+        self.cooldown.push(span, now);
+        stat_inc!(cooldown_spans);
+    }
+
+    /// Release spans from the cool-down queue to the free lists: the oldest
+    /// while more than `keep` are held, then any that have waited
+    /// `span_cooldown_ms`.
+    unsafe fn expire_cooldown(&mut self, keep: usize) {
+        let now =
+            (SPAN_COOLDOWN_MS != 0 && !cfg!(feature = "deterministic")).then(platform::now_ms);
+        while let Some(span) = self.cooldown.pop_expired(keep, now) {
+            unsafe { self.insert_freed(span) };
+        }
+    }
+
+    /// Release every span in the cool-down queue to the free lists.
+    ///
+    /// # Safety
+    ///
+    /// Caller must hold exclusive access (via the enclosing `SpinMutex`).
+    pub unsafe fn flush_cooldown(&mut self) {
+        while let Some(span) = self.cooldown.pop_expired(0, None) {
+            unsafe { self.insert_freed(span) };
+        }
+    }
+
+    /// [`deallocate_span`](Self::deallocate_span) skipping the cool-down,
+    /// for spans whose pages nobody could still be using.
+    unsafe fn deallocate_now(&mut self, span: *mut Span) {
+        unsafe {
+            self.clear_freed(span);
+            self.insert_freed(span);
+        }
+    }
+
+    /// Reset a span coming back from use, drop its interior from the page
+    /// map and decommit it if it is big enough.
+    unsafe fn clear_freed(&mut self, span: *mut Span) {
         unsafe {
-            (*span).state = SpanState::Free;
             (*span).size_class = 0;
+            (*span).fresh_from_os = false;
             (*span).freelist = ptr::null_mut();
+            (*span).freelist_tail = ptr::null_mut();
             (*span).allocated_count = 0;
             (*span).total_count = 0;
+            // Only the endpoints of a free span stay registered. Clearing
+            // the interior now means a page can never keep pointing at a
+            // span struct that coalescing frees and the slab hands out again.
+            self.pagemap.unregister_span(span);
+            // The pages go back to the OS now; the span keeps its place, so
+            // it still merges and is recommitted when carved again.
+            if self.free_decommit_min != 0
+                && (*span).byte_size() >= self.free_decommit_min
+                && !(*span).decommitted
+            {
+                platform::page_decommit((*span).start_addr(), (*span).byte_size());
+                (*span).decommitted = true;
+                stat_add!(free_decommit_bytes, (*span).byte_size() as u64);
+            }
         }
+    }
 
+    /// Put a cleared span on the free lists, merged with its neighbours, or
+    /// with deferred coalescing on, queue it for the next batch.
+    unsafe fn insert_freed(&mut self, span: *mut Span) {
+        unsafe {
+            (*span).state = if self.defer {
+                SpanState::Pending
+            } else {
+                SpanState::Free
+            };
+        }
+        if self.defer {
+            // Endpoints stay visible so a later merge can find the span.
+            unsafe {
+                self.pagemap.register_span_endpoints(span);
+                self.pending.push(span);
+            }
+            if self.pending.count >= MAX_PENDING_SPANS {
+                unsafe { self.coalesce_pending() };
+            }
+            return;
+        }
+
+        unsafe { self.coalesce_and_insert(span) };
+    }
+
+    /// Give the pages of an in-use span past its first `keep_pages` back to
+    /// the free lists, merged with a free right neighbour. The kept pages
+    /// stay registered to `span`. Returns false, leaving the span whole, if
+    /// there is no span struct for the tail.
+    ///
+    /// # Safety
+    ///
+    /// `span` must be an in-use span of more than `keep_pages` pages, with
+    /// nothing in use past the first `keep_pages`.
+    pub unsafe fn trim_span(&mut self, span: *mut Span, keep_pages: usize) -> bool {
+        let tail = span::alloc_span();
+        if tail.is_null() {
+            return false;
+        }
+        unsafe {
+            (*tail).start_page = (*span).start_page + keep_pages;
+            (*tail).num_pages = (*span).num_pages - keep_pages;
+            (*tail).chunk_id = (*span).chunk_id;
+            (*tail).interleaved = (*span).interleaved;
+            (*span).num_pages = keep_pages;
+            self.deallocate_span(tail);
+        }
+        true
+    }
+
+    /// Merge a free span with its free neighbours and put the result on the
+    /// free lists.
+    unsafe fn coalesce_and_insert(&mut self, span: *mut Span) {
         let span = unsafe { self.coalesce_left(span) };
         let span = unsafe { self.coalesce_right(span) };
 
@@ -98,7 +592,11 @@ impl PageHeap {
     /// to the free lists. Returns the (now in-use) span.
     unsafe fn carve_span(&mut self, span: *mut Span, num_pages: usize) -> *mut Span {
         let total = unsafe { (*span).num_pages };
-        assert!(total >= num_pages);
+        if total < num_pages {
+            failure::report(Failure::SpanTooSmall, unsafe { (*span).start_addr() }
+                as usize);
+            return ptr::null_mut();
+        }
 
         if total > num_pages {
             #[cfg(feature = "debug")]
@@ -109,15 +607,18 @@ impl PageHeap {
                 // Can't allocate span metadata - return the whole thing
                 unsafe {
                     (*span).state = SpanState::InUse;
-                    self.pagemap.register_span(span);
+                    return self.register_or_free(span);
                 }
-                return span;
             }
 
             unsafe {
                 (*remainder).start_page = (*span).start_page + num_pages;
                 (*remainder).num_pages = total - num_pages;
                 (*remainder).state = SpanState::Free;
+                (*remainder).fresh_from_os = (*span).fresh_from_os;
+                (*remainder).decommitted = (*span).decommitted;
+                (*remainder).interleaved = (*span).interleaved;
+                (*remainder).chunk_id = (*span).chunk_id;
 
                 // Update original span
                 (*span).num_pages = num_pages;
@@ -139,9 +640,17 @@ impl PageHeap {
         println!("[carve] register span in pagemap");
 
         unsafe {
+            if (*span).decommitted {
+                platform::page_recommit((*span).start_addr(), (*span).byte_size());
+                (*span).decommitted = false;
+            }
+            if (*span).interleaved {
+                platform::page_interleave((*span).start_addr(), (*span).byte_size(), false);
+                (*span).interleaved = false;
+            }
             (*span).state = SpanState::InUse;
-            self.pagemap.register_span(span);
         }
+        let span = unsafe { self.register_or_free(span) };
 
         #[cfg(feature = "debug")]
         println!("[carve] done");
@@ -149,17 +658,58 @@ impl PageHeap {
         span
     }
 
-    /// Insert a free span into the appropriate free list.
+    /// Register every page of an in-use span. If the page map cannot take
+    /// it, the span goes back to the free lists and null is returned.
+    unsafe fn register_or_free(&mut self, span: *mut Span) -> *mut Span {
+        if unsafe { self.pagemap.register_span(span) } {
+            return span;
+        }
+        unsafe { self.deallocate_now(span) };
+        ptr::null_mut()
+    }
+
+    /// Take a free or pending neighbour off whichever list holds it, ready
+    /// to be merged. Returns false for spans that must not be merged.
+    unsafe fn unlink_mergeable(&mut self, span: *mut Span) -> bool {
+        unsafe {
+            match (*span).state {
+                SpanState::Pending => self.pending.remove(span),
+                SpanState::Free => {
+                    let n = (*span).num_pages;
+                    if n <= MAX_PAGES {
+                        self.free_lists[n].remove(span);
+                    } else {
+                        self.large_spans.remove(span);
+                    }
+                }
+                SpanState::InUse | SpanState::Cached => return false,
+            }
+            (*span).state = SpanState::Free;
+        }
+        true
+    }
+
+    /// Insert a free span into the appropriate free list: at the head, or in
+    /// address order with `address_ordered_spans`.
     unsafe fn insert_free(&mut self, span: *mut Span) {
         let n = unsafe { (*span).num_pages };
-        if n <= MAX_PAGES {
-            unsafe { self.free_lists[n].push(span) };
+        let list = if n <= MAX_PAGES {
+            &mut self.free_lists[n]
+        } else {
+            &mut self.large_spans
+        };
+        if self.address_ordered {
+            unsafe { list.insert_by_address(span) };
         } else {
-            unsafe { self.large_spans.push(span) };
+            unsafe { list.push(span) };
         }
     }
 
     /// Find the best-fit span in large_spans that has >= num_pages.
+    ///
+    /// With `deterministic`, ties go to the lowest address instead of the
+    /// most recently freed span, so the choice doesn't depend on free order.
+    /// Address-ordered lists get the same result from the first match.
     unsafe fn find_best_large_span(&self, num_pages: usize) -> *mut Span {
         let mut best: *mut Span = ptr::null_mut();
         let mut best_pages = usize::MAX;
@@ -167,10 +717,13 @@ impl PageHeap {
 
         while !current.is_null() {
             let n = unsafe { (*current).num_pages };
-            if n >= num_pages && n < best_pages {
+            let tie = cfg!(feature = "deterministic")
+                && n == best_pages
+                && unsafe { (*current).start_page < (*best).start_page };
+            if n >= num_pages && (n < best_pages || tie) {
                 best = current;
                 best_pages = n;
-                if n == num_pages {
+                if n == num_pages && !cfg!(feature = "deterministic") {
                     break; // Exact match
                 }
             }
@@ -181,20 +734,26 @@ impl PageHeap {
 
     /// Request pages from the OS and create a new span.
     unsafe fn grow_heap(&mut self, num_pages: usize) -> *mut Span {
-        // Allocate at least 128 pages (1 MiB) at a time to reduce OS calls
-        let alloc_pages = num_pages.max(128);
-        let alloc_size = alloc_pages * PAGE_SIZE;
+        // Allocate at least 128 pages (1 MiB) at a time to reduce OS calls.
+        // Without overcommit the OS refuses mappings past the commit limit
+        // however little of them would be touched, so a refused request is
+        // halved, down to exactly `num_pages`, before growing fails.
+        let mut alloc_pages = num_pages.max(128);
 
         #[cfg(feature = "debug")]
         println!("[grow] mmap");
 
-        let ptr = unsafe { platform::page_alloc(alloc_size) };
-        if ptr.is_null() {
-            if alloc_pages > num_pages {
-                return unsafe { self.grow_heap_exact(num_pages) };
+        let ptr = loop {
+            let ptr = unsafe { self.os_alloc(alloc_pages * PAGE_SIZE) };
+            if !ptr.is_null() || alloc_pages == num_pages {
+                break ptr;
             }
+            alloc_pages = (alloc_pages / 2).max(num_pages);
+        };
+        if ptr.is_null() {
             return ptr::null_mut();
         }
+        let alloc_size = alloc_pages * PAGE_SIZE;
 
         let start_page = (ptr as usize) >> PAGE_SHIFT;
 
@@ -203,14 +762,17 @@ impl PageHeap {
 
         let s = span::alloc_span();
         if s.is_null() {
-            unsafe { platform::page_dealloc(ptr, alloc_size) };
+            unsafe { self.os_free(ptr, alloc_size) };
             return ptr::null_mut();
         }
 
+        let chunk = self.note_mapped(ptr, alloc_size);
         unsafe {
             (*s).start_page = start_page;
             (*s).num_pages = alloc_pages;
             (*s).state = SpanState::InUse; // Will be carved immediately
+            (*s).fresh_from_os = true;
+            (*s).chunk_id = chunk;
         }
 
         #[cfg(feature = "debug")]
@@ -219,29 +781,170 @@ impl PageHeap {
         unsafe { self.carve_span(s, num_pages) }
     }
 
-    /// Fallback: allocate exactly num_pages from the OS.
-    unsafe fn grow_heap_exact(&mut self, num_pages: usize) -> *mut Span {
+    /// Take at least `bytes` from the OS as one free span, with every page
+    /// map node covering it allocated up front. Returns false if the memory
+    /// or a node could not be had; nothing is kept then but nodes.
+    ///
+    /// # Safety
+    ///
+    /// Caller must hold exclusive access (via the enclosing `SpinMutex`).
+    pub unsafe fn reserve(&mut self, bytes: usize) -> bool {
+        let num_pages = bytes.div_ceil(PAGE_SIZE);
+        if num_pages == 0 {
+            return true;
+        }
         let alloc_size = num_pages * PAGE_SIZE;
-        let ptr = unsafe { platform::page_alloc(alloc_size) };
+        let ptr = unsafe { self.os_alloc(alloc_size) };
         if ptr.is_null() {
-            return ptr::null_mut();
+            return false;
         }
 
         let start_page = (ptr as usize) >> PAGE_SHIFT;
-
         let s = span::alloc_span();
-        if s.is_null() {
-            unsafe { platform::page_dealloc(ptr, alloc_size) };
-            return ptr::null_mut();
+        if s.is_null() || !unsafe { self.pagemap.reserve(start_page, num_pages) } {
+            if !s.is_null() {
+                unsafe { span::dealloc_span(s) };
+            }
+            unsafe { self.os_free(ptr, alloc_size) };
+            return false;
         }
 
+        let chunk = self.note_mapped(ptr, alloc_size);
         unsafe {
             (*s).start_page = start_page;
             (*s).num_pages = num_pages;
-            (*s).state = SpanState::InUse;
-            self.pagemap.register_span(s);
+            (*s).state = SpanState::Free;
+            (*s).fresh_from_os = true;
+            (*s).chunk_id = chunk;
+            self.pagemap.register_span_endpoints(s);
+            self.insert_free(s);
+        }
+        true
+    }
+
+    /// Request `size` bytes of fresh heap memory from the OS. It reads as
+    /// zero: mapped memory is zero-filled, prefaulting writes zeros, and
+    /// region pages are taken once and only returned by `os_free` unused.
+    ///
+    /// With `prefault = true` in the config, pages are faulted in here rather than
+    /// on first touch by the application. Time spent is recorded in stats.
+    /// With `max_heap` set the memory comes from the heap region, and null is
+    /// returned once it is used up.
+    unsafe fn os_alloc(&mut self, size: usize) -> *mut u8 {
+        #[cfg(any(all(feature = "stats", feature = "std"), feature = "tracing"))]
+        let start = std::time::Instant::now();
+
+        let ptr = crate::time_slow_path!(
+            OsAlloc,
+            if MAX_HEAP != 0 {
+                unsafe { self.region_alloc(size) }
+            } else if PREFAULT {
+                unsafe { platform::page_alloc_populated(size) }
+            } else {
+                unsafe { platform::page_alloc(size) }
+            }
+        );
+
+        debug_assert!(
+            ptr.addr().is_multiple_of(PAGE_SIZE),
+            "heap memory at {ptr:p} is not PAGE_SIZE-aligned"
+        );
+        // Spans rebuild their start pointer from the page id; see the
+        // provenance notes in the crate docs.
+        ptr.expose_provenance();
+
+        if ptr.is_null() {
+            stat_inc!(os_alloc_failures);
+        } else {
+            stat_inc!(os_alloc_count);
+            stat_add!(os_alloc_bytes, size);
+        }
+        #[cfg(all(feature = "stats", feature = "std"))]
+        {
+            let nanos = start.elapsed().as_nanos() as u64;
+            stat_add!(os_alloc_nanos, nanos);
+            stat_max!(os_alloc_max_nanos, nanos);
+        }
+        #[cfg(feature = "usdt")]
+        if !ptr.is_null() {
+            crate::usdt::heap_grow(ptr.addr(), size);
+        }
+        #[cfg(feature = "tracing")]
+        if !ptr.is_null() {
+            crate::tracing_events::record(crate::tracing_events::Event::HeapGrow {
+                bytes: size,
+                nanos: start.elapsed().as_nanos() as u64,
+            });
+        }
+
+        ptr
+    }
+
+    /// Reserve the `max_heap` region if it isn't yet: at `heap_base` if set,
+    /// anywhere aligned to its size otherwise. Returns false if the OS
+    /// refused; a `heap_base` that is taken is reported through
+    /// [`failure`], since memory anywhere else would be of no use.
+    unsafe fn reserve_region(&mut self) -> bool {
+        if self.region_end != 0 {
+            return true;
+        }
+        let base = if HEAP_BASE != 0 {
+            let base = unsafe { platform::page_reserve_at(HEAP_BASE, MAX_HEAP) };
+            if base.is_null() {
+                failure::report(Failure::HeapPlacement, HEAP_BASE);
+            }
+            base
+        } else {
+            unsafe { platform::page_reserve(MAX_HEAP, MAX_HEAP) }
+        };
+        if base.is_null() {
+            return false;
+        }
+        unsafe { self.pagemap.set_region(base.expose_provenance()) };
+        self.region_next = base;
+        self.region_end = base.addr() + MAX_HEAP;
+        true
+    }
+
+    /// The `max_heap` region, reserved now if the heap hasn't grown yet.
+    ///
+    /// # Safety
+    ///
+    /// Caller must hold exclusive access (via the enclosing `SpinMutex`).
+    pub unsafe fn region(&mut self) -> Option<core::ops::Range<usize>> {
+        if MAX_HEAP == 0 || !unsafe { self.reserve_region() } {
+            return None;
+        }
+        Some(self.region_end - MAX_HEAP..self.region_end)
+    }
+
+    /// Carve `size` bytes off the `max_heap` region, reserving the region on
+    /// first use.
+    unsafe fn region_alloc(&mut self, size: usize) -> *mut u8 {
+        if !unsafe { self.reserve_region() } {
+            return ptr::null_mut();
+        }
+        if self.region_end - self.region_next.addr() < size {
+            failure::set_last_error(failure::ENOMEM);
+            return ptr::null_mut();
+        }
+        let ptr = self.region_next;
+        self.region_next = ptr.wrapping_add(size);
+        unsafe { platform::page_recommit(ptr, size) };
+        if PREFAULT {
+            unsafe { platform::touch_pages(ptr, size) };
+        }
+        ptr
+    }
+
+    /// Give back memory just taken by `os_alloc` that could not be used.
+    unsafe fn os_free(&mut self, ptr: *mut u8, size: usize) {
+        if MAX_HEAP == 0 {
+            unsafe { platform::page_dealloc(ptr, size) };
+        } else if ptr.wrapping_add(size) == self.region_next {
+            unsafe { platform::page_decommit(ptr, size) };
+            self.region_next = ptr;
         }
-        s
     }
 
     /// Try to merge with the free span immediately before `span`.
@@ -257,24 +960,28 @@ impl PageHeap {
         }
 
         unsafe {
-            if (*left).state != SpanState::Free {
-                return span;
-            }
-            // Verify the left span actually ends right before us
-            if (*left).start_page + (*left).num_pages != start {
+            // Verify the left span actually ends right before us, in the
+            // same OS chunk
+            if (*left).start_page + (*left).num_pages != start
+                || (*left).chunk_id != (*span).chunk_id
+            {
                 return span;
             }
 
-            // Remove left from its free list
-            let left_pages = (*left).num_pages;
-            if left_pages <= MAX_PAGES {
-                self.free_lists[left_pages].remove(left);
-            } else {
-                self.large_spans.remove(left);
+            // Remove left from its free (or pending) list
+            if !self.unlink_mergeable(left) {
+                return span;
             }
 
-            // Merge: extend left span to include our pages
+            // Merge: extend left span to include our pages. Left's last
+            // page and our first (registered if we were pending) become
+            // interior; the caller registers the new endpoints.
             (*left).num_pages += (*span).num_pages;
+            (*left).fresh_from_os &= (*span).fresh_from_os;
+            (*left).decommitted |= (*span).decommitted;
+            (*left).interleaved |= (*span).interleaved;
+            self.pagemap.set(start - 1, ptr::null_mut());
+            self.pagemap.set(start, ptr::null_mut());
 
             // Free the now-redundant span struct
             span::dealloc_span(span);
@@ -293,24 +1000,26 @@ impl PageHeap {
         }
 
         unsafe {
-            if (*right).state != SpanState::Free {
-                return span;
-            }
-            // Verify the right span actually starts right after us
-            if (*right).start_page != end_page {
+            // Verify the right span actually starts right after us, in the
+            // same OS chunk
+            if (*right).start_page != end_page || (*right).chunk_id != (*span).chunk_id {
                 return span;
             }
 
-            // Remove right from its free list
-            let right_pages = (*right).num_pages;
-            if right_pages <= MAX_PAGES {
-                self.free_lists[right_pages].remove(right);
-            } else {
-                self.large_spans.remove(right);
+            // Remove right from its free (or pending) list
+            if !self.unlink_mergeable(right) {
+                return span;
             }
 
-            // Merge: extend our span to include right's pages
+            // Merge: extend our span to include right's pages. Right's first
+            // page and our last become interior; the caller re-registers the
+            // new endpoints.
             (*span).num_pages += (*right).num_pages;
+            (*span).fresh_from_os &= (*right).fresh_from_os;
+            (*span).decommitted |= (*right).decommitted;
+            (*span).interleaved |= (*right).interleaved;
+            self.pagemap.set(end_page - 1, ptr::null_mut());
+            self.pagemap.set(end_page, ptr::null_mut());
 
             // Free the now-redundant span struct
             span::dealloc_span(right);
@@ -320,6 +1029,78 @@ impl PageHeap {
     }
 }
 
+/// Turn deferred coalescing of the global page heap on or off.
+///
+/// With it on, freeing a large span is O(1): the span is queued instead of
+/// being merged with its neighbours under the page heap lock. Queued spans
+/// are merged in one batch by the next page heap allocation, once
+/// 64 are waiting, or by [`flush_deferred`]. This trades a little reuse
+/// (a queued span is not handed out until merged) for flatter `free()`
+/// latency when many large spans are released at once. Off by default;
+/// turning it off merges everything queued.
+pub fn defer_coalescing(enabled: bool) {
+    let Some(_guard) = crate::bootstrap::ReentrancyGuard::enter() else {
+        return;
+    };
+    unsafe {
+        crate::allocator::PAGE_HEAP
+            .lock()
+            .set_defer_coalescing(enabled)
+    };
+}
+
+/// Merge every span queued by deferred coalescing now, e.g. from a
+/// runtime's idle or housekeeping thread. A no-op when nothing is queued.
+pub fn flush_deferred() {
+    let Some(_guard) = crate::bootstrap::ReentrancyGuard::enter() else {
+        return;
+    };
+    unsafe { crate::allocator::PAGE_HEAP.lock().coalesce_pending() };
+}
+
+/// Release every span held back by the cool-down queue (`span_cooldown`)
+/// for reuse now, e.g. before a memory-hungry phase. A no-op when the
+/// queue is empty or off.
+pub fn flush_cooldown() {
+    let Some(_guard) = crate::bootstrap::ReentrancyGuard::enter() else {
+        return;
+    };
+    unsafe { crate::allocator::PAGE_HEAP.lock().flush_cooldown() };
+}
+
+/// Freed spans the cool-down queue holds now; 0 from inside the allocator.
+pub fn cooling_spans() -> usize {
+    let Some(_guard) = crate::bootstrap::ReentrancyGuard::enter() else {
+        return 0;
+    };
+    crate::allocator::PAGE_HEAP.lock().cooldown.len
+}
+
+/// Reserve address space for a heap of up to `max_heap_bytes` up front.
+///
+/// The memory is mapped now (faulted in only with `prefault = true`) and
+/// kept free in the page heap, and every page map node covering it is
+/// created here. Allocations served from it then never allocate page map
+/// nodes with the page heap lock held. Returns false if the memory or a
+/// node could not be had.
+pub fn reserve(max_heap_bytes: usize) -> bool {
+    let Some(_guard) = crate::bootstrap::ReentrancyGuard::enter() else {
+        return false;
+    };
+    unsafe { crate::allocator::PAGE_HEAP.lock().reserve(max_heap_bytes) }
+}
+
+/// The address range every span comes from with `max_heap` set: `heap_base`
+/// and the `max_heap` bytes after it if that is set, else wherever the
+/// region was reserved. The region is reserved now if the heap hasn't grown
+/// yet, so code that compresses heap pointers to offsets from the start
+/// can learn it before the first allocation. None without `max_heap` or if
+/// the region could not be reserved.
+pub fn heap_region() -> Option<core::ops::Range<usize>> {
+    let _guard = crate::bootstrap::ReentrancyGuard::enter()?;
+    unsafe { crate::allocator::PAGE_HEAP.lock().region() }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -381,6 +1162,67 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_fresh_from_os() {
+        let (_pm, mut heap) = make_heap();
+        unsafe {
+            // Grown spans and the remainders carved off them are fresh.
+            let a = heap.allocate_span(2);
+            let b = heap.allocate_span(3);
+            assert!((*a).fresh_from_os && (*b).fresh_from_os);
+            let base = (*a).start_addr();
+            assert!((0..5 * PAGE_SIZE).all(|i| base.add(i).read() == 0));
+
+            // Once used and freed, neither the span nor anything it merges
+            // into is fresh any more.
+            base.write_bytes(0xA5, 2 * PAGE_SIZE);
+            heap.deallocate_span(a);
+            let c = heap.allocate_span(2);
+            assert_eq!((*c).start_page, (*a).start_page);
+            assert!(!(*c).fresh_from_os);
+            heap.deallocate_span(c);
+            heap.deallocate_span(b);
+            let d = heap.allocate_span(5);
+            assert_eq!((*d).start_addr(), base);
+            assert!(!(*d).fresh_from_os);
+            heap.deallocate_span(d);
+
+            // Reserved memory starts out fresh too.
+            assert!(heap.reserve(4 * PAGE_SIZE));
+            let e = heap.allocate_span(4);
+            assert!((*e).fresh_from_os);
+            heap.deallocate_span(e);
+        }
+    }
+
+    #[test]
+    fn test_coalescing_stays_in_chunk() {
+        let (_pm, mut heap) = make_heap();
+        unsafe {
+            // Each growth is its own chunk; carving keeps the chunk.
+            let a = heap.allocate_span(64);
+            let b = heap.allocate_span(200);
+            assert_ne!((*a).chunk_id, 0);
+            assert_ne!((*a).chunk_id, (*b).chunk_id);
+            let rest = heap.allocate_span(64);
+            assert_eq!((*rest).chunk_id, (*a).chunk_id);
+            assert_eq!((*rest).start_page, (*a).end_page());
+
+            // Neighbours in the same chunk merge.
+            heap.deallocate_span(rest);
+            heap.deallocate_span(a);
+            assert_eq!((*a).num_pages, 128);
+
+            // Pages adjacent by id but from another mapping do not: pretend
+            // the first half came from a different chunk.
+            let a = heap.allocate_span(64);
+            (*a).chunk_id = (*b).chunk_id;
+            heap.deallocate_span(a);
+            assert_eq!((*a).num_pages, 64);
+            heap.deallocate_span(b);
+        }
+    }
+
     #[test]
     fn test_splitting() {
         let (_pm, mut heap) = make_heap();
@@ -399,6 +1241,334 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_trim_span() {
+        let (pm, mut heap) = make_heap();
+        unsafe {
+            let span = heap.allocate_span(20);
+            let next = heap.allocate_span(4);
+            let first = (*span).start_page;
+            assert_eq!((*next).start_page, first + 20);
+            heap.deallocate_span(next);
+
+            // The tail merges with the free neighbour; the head stays
+            // registered page by page.
+            assert!(heap.trim_span(span, 5));
+            assert_eq!((*span).num_pages, 5);
+            assert!((first..first + 5).all(|page| pm.get(page) == span));
+            let tail = pm.get(first + 5);
+            assert_eq!((*tail).state, SpanState::Free);
+            assert!((*tail).num_pages >= 19);
+            assert_pages_attributed(pm, first, 24);
+
+            let reuse = heap.allocate_span(15);
+            assert_eq!((*reuse).start_page, first + 5);
+            heap.deallocate_span(reuse);
+            heap.deallocate_span(span);
+            assert_eq!((*pm.get(first)).num_pages, (*tail).num_pages + 5);
+        }
+    }
+
+    #[test]
+    fn test_address_ordered_reuse() {
+        let (_pm, mut heap) = make_heap();
+        heap.address_ordered = true;
+        unsafe {
+            let spans: [_; 9] = core::array::from_fn(|_| heap.allocate_span(1));
+
+            // Free every other span, so none of them coalesce, out of order.
+            for i in [5, 1, 7, 3] {
+                heap.deallocate_span(spans[i]);
+            }
+
+            // Reuse starts at the lowest address regardless of free order.
+            for i in [1, 3, 5, 7] {
+                let s = heap.allocate_span(1);
+                assert_eq!((*s).start_page, (*spans[i]).start_page);
+            }
+        }
+    }
+
+    /// Allocate single pages until the free lists are empty.
+    unsafe fn drain_free(heap: &mut PageHeap) -> Vec<*mut Span> {
+        let mut spans = Vec::new();
+        while heap.usage().free_bytes != 0 {
+            spans.push(unsafe { heap.allocate_span(1) });
+        }
+        spans
+    }
+
+    #[test]
+    fn test_populate_reclaims_parked_spans() {
+        use crate::mid_heap::{self, NUM_MID_CLASSES};
+
+        if NUM_MID_CLASSES == 0 {
+            return;
+        }
+        let pm = Box::leak(Box::new(PageMap::new()));
+        let mid = Box::leak(Box::new(MidHeap::new()));
+        let mut heap = PageHeap::new(pm).with_mid_heap(mid);
+        let pages = mid_heap::class_to_pages(NUM_MID_CLASSES - 1);
+        unsafe {
+            let big = heap.allocate_span(pages);
+            let _held = drain_free(&mut heap);
+            assert!(mid.park(big));
+            let system = heap.usage().system_bytes;
+
+            // The parked span is carved up instead of growing the heap.
+            let span = heap.allocate_small_span(1);
+            assert_eq!((*span).start_page, (*big).start_page);
+            assert_eq!(heap.usage().system_bytes, system);
+            assert_eq!(mid.cached_spans(pages), 0);
+
+            // With nothing left to reclaim it grows.
+            let _held = drain_free(&mut heap);
+            assert!(!heap.allocate_small_span(1).is_null());
+            assert!(heap.usage().system_bytes > system);
+        }
+    }
+
+    #[test]
+    fn test_large_reserve() {
+        let (_pm, mut heap) = make_heap();
+        unsafe {
+            let big = heap.allocate_span(MAX_PAGES + 2);
+            let _held = drain_free(&mut heap);
+            heap.deallocate_span(big);
+
+            // Carving one page leaves MAX_PAGES + 1 pages in a large span.
+            assert!(heap.take_free(1, MAX_PAGES + 2).is_null());
+            let span = heap.take_free(1, MAX_PAGES + 1);
+            assert_eq!((*span).start_page, (*big).start_page);
+            assert!(heap.take_free(1, MAX_PAGES + 1).is_null());
+            assert!(!heap.allocate_span(1).is_null());
+        }
+    }
+
+    #[test]
+    fn test_free_decommit() {
+        let (pm, mut heap) = make_heap();
+        heap.free_decommit_min = 4 * PAGE_SIZE;
+        unsafe {
+            let spans: [_; 4] = core::array::from_fn(|_| heap.allocate_span(4));
+            let small = heap.allocate_span(2);
+            for s in spans {
+                (*s).start_addr().write_bytes(0xab, (*s).byte_size());
+            }
+
+            // Small spans keep their pages; big ones are decommitted but
+            // stay registered at both ends, so neighbours still merge.
+            heap.deallocate_span(small);
+            assert!(!(*small).decommitted);
+            heap.deallocate_span(spans[0]);
+            assert!((*spans[0]).decommitted);
+            assert_eq!(pm.get((*spans[0]).start_page), spans[0]);
+            heap.deallocate_span(spans[1]);
+            assert_eq!((*spans[0]).num_pages, 8);
+            assert!((*spans[0]).decommitted);
+
+            // Carving recommits just the part handed out.
+            let again = heap.allocate_span(3);
+            assert_eq!((*again).start_page, (*spans[0]).start_page);
+            assert!(!(*again).decommitted);
+            (*again).start_addr().write_bytes(1, (*again).byte_size());
+            let rest = pm.get((*again).end_page());
+            assert!((*rest).decommitted);
+        }
+    }
+
+    /// Every registered page must map to a span that covers it.
+    unsafe fn assert_pages_attributed(pm: &PageMap, first: usize, count: usize) {
+        for page in first..first + count {
+            let s = pm.get(page);
+            if !s.is_null() {
+                let addr = ptr::without_provenance(page << PAGE_SHIFT);
+                assert!(unsafe { (*s).contains(addr) }, "page {page} misattributed");
+            }
+        }
+    }
+
+    #[test]
+    fn test_coalesce_clears_interior_pages() {
+        let (pm, mut heap) = make_heap();
+        unsafe {
+            let a = heap.allocate_span(4);
+            let b = heap.allocate_span(6);
+            let c = heap.allocate_span(5);
+            let first = (*a).start_page;
+            assert_eq!((*b).start_page, first + 4);
+            assert_eq!((*c).start_page, first + 10);
+            let b_start = (*b).start_page;
+
+            // Free the outer spans, then the middle one: b merges both ways
+            // and its struct, along with c's, goes back to the slab.
+            heap.deallocate_span(a);
+            heap.deallocate_span(c);
+            heap.deallocate_span(b);
+
+            // Only the endpoints of the merged free span stay registered.
+            let merged = pm.get(first);
+            assert!(!merged.is_null());
+            assert_eq!((*merged).state, SpanState::Free);
+            assert!((*merged).num_pages >= 15);
+            let last = first + (*merged).num_pages - 1;
+            assert_eq!(pm.get(last), merged);
+            for page in first + 1..last {
+                assert!(pm.get(page).is_null(), "stale interior page {page}");
+            }
+
+            // Recycle the span structs and carve the region again. The old
+            // middle pages must resolve to the new owners, not old structs.
+            let x = heap.allocate_span(2);
+            let y = heap.allocate_span(9);
+            assert_eq!((*x).start_page, first);
+            assert_eq!(pm.get(b_start), y);
+            assert_pages_attributed(pm, first, 15);
+
+            heap.deallocate_span(y);
+            heap.deallocate_span(x);
+            assert_pages_attributed(pm, first, 15);
+        }
+    }
+
+    #[test]
+    fn test_deferred_coalescing() {
+        let (pm, mut heap) = make_heap();
+        unsafe {
+            heap.set_defer_coalescing(true);
+            let a = heap.allocate_span(3);
+            let b = heap.allocate_span(4);
+            let c = heap.allocate_span(5);
+            let first = (*a).start_page;
+
+            // Frees only queue the spans; nothing merges yet.
+            heap.deallocate_span(a);
+            heap.deallocate_span(b);
+            assert_eq!(heap.pending_spans(), 2);
+            assert_eq!((*b).state, SpanState::Pending);
+            assert_eq!(pm.get(first), a);
+            assert_eq!(pm.get(first + 3), b);
+
+            // A batch merges pending neighbours into one free span, which
+            // stops at the in-use `c`.
+            heap.coalesce_pending();
+            assert_eq!(heap.pending_spans(), 0);
+            let merged = pm.get(first);
+            assert_eq!((*merged).state, SpanState::Free);
+            assert_eq!((*merged).num_pages, 7);
+            assert_eq!(pm.get(first + 6), merged);
+            assert!(pm.get(first + 3).is_null());
+
+            // The next allocation merges what is pending before searching.
+            heap.deallocate_span(c);
+            assert_eq!(heap.pending_spans(), 1);
+            let d = heap.allocate_span(12);
+            assert_eq!(heap.pending_spans(), 0);
+            assert_eq!((*d).start_page, first);
+            heap.deallocate_span(d);
+
+            // The queue is bounded.
+            let spans: Vec<_> = (0..MAX_PENDING_SPANS)
+                .map(|_| heap.allocate_span(1))
+                .collect();
+            for s in spans {
+                heap.deallocate_span(s);
+            }
+            assert!(heap.pending_spans() < MAX_PENDING_SPANS);
+
+            heap.set_defer_coalescing(false);
+            assert_eq!(heap.pending_spans(), 0);
+            assert_pages_attributed(pm, first, 128);
+        }
+    }
+
+    #[test]
+    fn test_reserve() {
+        let (pm, mut heap) = make_heap();
+        let pages = (64 << 20) / PAGE_SIZE;
+        unsafe {
+            assert!(heap.reserve(64 << 20));
+            let reserved = heap.large_spans.head;
+            assert_eq!((*reserved).num_pages, pages);
+            let (first, end) = ((*reserved).start_page, (*reserved).end_page());
+            assert_eq!(pm.get(end - 1), reserved);
+
+            // Allocations are carved from the reservation.
+            let s = heap.allocate_span(10);
+            assert_eq!((*s).start_page, first);
+            let s2 = heap.allocate_span(MAX_PAGES + 1);
+            assert!((first..end).contains(&(*s2).start_page));
+            heap.deallocate_span(s2);
+            heap.deallocate_span(s);
+        }
+    }
+
+    #[test]
+    fn test_usage() {
+        let (_pm, mut heap) = make_heap();
+        assert_eq!(heap.usage(), PageHeapUsage::default());
+        unsafe {
+            let a = heap.allocate_span(3);
+            let b = heap.allocate_span(MAX_PAGES + 1);
+            let usage = heap.usage();
+            let used = (3 + MAX_PAGES + 1) * PAGE_SIZE;
+            assert!(usage.system_bytes >= used);
+            assert_eq!(usage.free_bytes, usage.system_bytes - used);
+
+            heap.deallocate_span(a);
+            let usage = heap.usage();
+            assert_eq!(
+                usage.free_bytes,
+                usage.system_bytes - (MAX_PAGES + 1) * PAGE_SIZE
+            );
+            heap.deallocate_span(b);
+            assert_eq!(heap.usage().free_bytes, heap.usage().system_bytes);
+        }
+    }
+
+    static GROWN: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn record_growth(event: GrowthEvent) {
+        assert!(event.delta > 0 && event.system_bytes >= event.delta as usize);
+        GROWN.fetch_add(event.delta as usize, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_growth_hook() {
+        let (_pm, mut heap) = make_heap();
+        set_growth_hook(Some(record_growth));
+        unsafe {
+            let span = heap.allocate_span(MAX_PAGES + 1);
+            // Other tests grow their heaps concurrently; only a lower bound holds.
+            assert!(GROWN.load(Ordering::Relaxed) >= heap.usage().system_bytes);
+            heap.deallocate_span(span);
+        }
+        set_growth_hook(None);
+    }
+
+    #[test]
+    fn test_max_heap_ceiling() {
+        if MAX_HEAP == 0 {
+            return;
+        }
+        let (pm, mut heap) = make_heap();
+        unsafe {
+            let all = heap.allocate_span(MAX_HEAP / PAGE_SIZE);
+            assert!(!all.is_null());
+            let start = (*all).start_page;
+            assert!((start << PAGE_SHIFT).is_multiple_of(MAX_HEAP));
+            assert!(heap.allocate_span(1).is_null());
+
+            // Pages either side of the region never alias into it.
+            assert_eq!(pm.get(start), all);
+            assert!(pm.get(start - 1).is_null());
+            assert!(pm.get(start + MAX_HEAP / PAGE_SIZE).is_null());
+
+            heap.deallocate_span(all);
+            assert!(!heap.allocate_span(1).is_null());
+        }
+    }
+
     #[test]
     fn test_many_allocations() {
         let (_pm, mut heap) = make_heap();
@@ -3,19 +3,60 @@
 //! For 48-bit virtual addresses with 13-bit page shift, we have 35 bits of
 //! page ID. Split as: root 12 bits, mid 12 bits, leaf 11 bits.
 //!
+//! With `max_heap` set in the config, every span lives in one region of
+//! `MAX_HEAP` bytes aligned to its size, so only the low
+//! `log2(MAX_HEAP) - PAGE_SHIFT` bits of a page ID vary: the mid and root
+//! levels shrink to fit (a 4 GiB heap of 8 KiB pages needs a single root
+//! entry and a 256-entry mid node). The page ID
+//! bits above the tree are compared against the region's in place of the
+//! root bounds check, so neighbours and foreign pointers outside the region
+//! never alias into it. With `heap_base` set as well, those bits are a
+//! constant and the compare needs no load.
+//!
 //! The root is statically allocated (32 KiB). Mid and leaf nodes are lazily
 //! allocated from the OS. Reads are lock-free (AtomicPtr with Acquire).
 //! Writes must happen under external synchronization (the page heap lock).
-
-use crate::config::PAGE_SIZE;
-use crate::platform;
-use crate::span::Span;
+//!
+//! Each leaf also keeps the size class of every page's span in a byte array
+//! next to the span pointers (tcmalloc's "sizeclass cache"), so `free()` can
+//! find the class of a small object with one byte load instead of
+//! dereferencing the span.
+
+use crate::config::{HEAP_BASE, MAX_HEAP, NUM_ARENAS, PAGE_SHIFT, PAGE_SIZE};
+use crate::failure::{self, Failure};
+use crate::size_class::NUM_SIZE_CLASSES;
+use crate::span::{self, Span};
 use core::ptr;
-use core::sync::atomic::{AtomicPtr, Ordering};
-
-const ROOT_BITS: usize = 12;
-const MID_BITS: usize = 12;
-const LEAF_BITS: usize = 11;
+use core::sync::atomic::{AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+
+/// Page ID bits the tree covers.
+pub(crate) const PAGE_ID_BITS: usize = if MAX_HEAP == 0 {
+    35
+} else {
+    MAX_HEAP.trailing_zeros() as usize - PAGE_SHIFT
+};
+
+/// Page ID bits above `PAGE_ID_BITS` of the `heap_base` region, 0 without
+/// one.
+const FIXED_REGION: usize = (HEAP_BASE >> PAGE_SHIFT) >> PAGE_ID_BITS;
+
+/// Length of `PageMap::region`: without `max_heap` the region bits are
+/// always 0 and the map keeps no word for them, which keeps the `minimal`
+/// page map within its footprint budget.
+const REGION_SLOTS: usize = (MAX_HEAP != 0) as usize;
+
+pub(crate) const LEAF_BITS: usize = 11;
+pub(crate) const MID_BITS: usize = if PAGE_ID_BITS - LEAF_BITS < 12 {
+    PAGE_ID_BITS - LEAF_BITS
+} else {
+    12
+};
+pub(crate) const ROOT_BITS: usize = PAGE_ID_BITS - LEAF_BITS - MID_BITS;
+
+const _: () = assert!(
+    PAGE_ID_BITS >= LEAF_BITS && PAGE_ID_BITS <= 35,
+    "max_heap must cover at least one page map leaf (2048 pages) and at most 2^35 pages"
+);
 
 const ROOT_LEN: usize = 1 << ROOT_BITS; // 4096
 const MID_LEN: usize = 1 << MID_BITS; // 4096
@@ -24,6 +65,7 @@ const LEAF_LEN: usize = 1 << LEAF_BITS; // 2048
 const MID_SHIFT: usize = LEAF_BITS; // 11
 const ROOT_SHIFT: usize = LEAF_BITS + MID_BITS; // 23
 
+const ROOT_MASK: usize = ROOT_LEN - 1;
 const MID_MASK: usize = (1 << MID_BITS) - 1;
 const LEAF_MASK: usize = (1 << LEAF_BITS) - 1;
 
@@ -35,13 +77,46 @@ struct MidNode {
 #[repr(C)]
 struct LeafNode {
     spans: [AtomicPtr<Span>; LEAF_LEN],
+    /// `size_class` of the span in `spans[i]`, with its arena in the bits
+    /// above [`CLASS_BITS`]; 0 for large, free and unmapped pages.
+    classes: [AtomicU8; LEAF_LEN],
+}
+
+/// Low bits of a `classes` entry holding the size class.
+const CLASS_BITS: u32 = 6;
+const CLASS_MASK: u8 = (1 << CLASS_BITS) - 1;
+
+const _: () = assert!(NUM_SIZE_CLASSES <= 1 << CLASS_BITS);
+const _: () = assert!(NUM_ARENAS <= 1 << (u8::BITS - CLASS_BITS));
+
+/// `classes` entry for `span`: its size class, tagged with its arena if it
+/// has one.
+///
+/// # Safety
+/// `span` must be valid.
+unsafe fn class_entry(span: *mut Span) -> u8 {
+    let class = unsafe { (*span).size_class } as u8;
+    if class == 0 {
+        return 0;
+    }
+    class | unsafe { (*span).arena } << CLASS_BITS
 }
 
 /// 3-level radix tree for page_id -> *mut Span lookup.
+#[repr(C)]
 pub struct PageMap {
     root: [AtomicPtr<MidNode>; ROOT_LEN],
+    /// Page ID bits above `PAGE_ID_BITS` shared by every mapped page: 0
+    /// without `max_heap`, those of `heap_base` with it, else set once the
+    /// heap region is reserved. Empty without `max_heap`.
+    region: [AtomicUsize; REGION_SLOTS],
 }
 
+// scripts/rtmalloc_gdb.py reads `region` as the word after the root, if
+// there is one.
+const _: () =
+    assert!(core::mem::offset_of!(PageMap, region) == ROOT_LEN * core::mem::size_of::<usize>());
+
 // AtomicPtr is Send+Sync, and we only expose safe operations
 unsafe impl Send for PageMap {}
 unsafe impl Sync for PageMap {}
@@ -62,76 +137,224 @@ impl PageMap {
     pub const fn new() -> Self {
         Self {
             root: null_atomic_array!(ROOT_LEN, MidNode),
+            region: [const { AtomicUsize::new(FIXED_REGION) }; REGION_SLOTS],
         }
     }
 
-    /// Look up the span for a given page ID. Returns null if not set.
-    /// This is lock-free.
+    /// Record the heap region at `base`, aligned to `MAX_HEAP`. Pages
+    /// outside it read as unmapped from then on.
+    ///
+    /// # Safety
+    /// Must be called under external synchronization, before any page of
+    /// the region is recorded.
+    pub unsafe fn set_region(&self, base: usize) {
+        debug_assert!(MAX_HEAP != 0 && base.is_multiple_of(MAX_HEAP));
+        debug_assert!(HEAP_BASE == 0 || base == HEAP_BASE);
+        let page_id = base >> PAGE_SHIFT;
+        if let Some(region) = self.region.first() {
+            region.store(page_id >> PAGE_ID_BITS, Ordering::Relaxed);
+        }
+    }
+
+    /// Root slot covering `page_id`, or None if the page lies outside the
+    /// range the map covers.
     #[inline]
-    pub fn get(&self, page_id: usize) -> *mut Span {
-        let root_idx = page_id >> ROOT_SHIFT;
-        let mid_idx = (page_id >> MID_SHIFT) & MID_MASK;
-        let leaf_idx = page_id & LEAF_MASK;
+    fn root_index(&self, page_id: usize) -> Option<usize> {
+        let region = if HEAP_BASE != 0 {
+            FIXED_REGION
+        } else {
+            self.region
+                .first()
+                .map_or(0, |region| region.load(Ordering::Relaxed))
+        };
+        if page_id >> PAGE_ID_BITS != region {
+            return None;
+        }
+        Some((page_id >> ROOT_SHIFT) & ROOT_MASK)
+    }
 
-        if root_idx >= ROOT_LEN {
+    /// Leaf node covering `page_id`, or null if not allocated.
+    #[inline]
+    fn leaf(&self, page_id: usize) -> *mut LeafNode {
+        let Some(root_idx) = self.root_index(page_id) else {
             return ptr::null_mut();
-        }
+        };
+        let mid_idx = (page_id >> MID_SHIFT) & MID_MASK;
 
         let mid = self.root[root_idx].load(Ordering::Acquire);
         if mid.is_null() {
             return ptr::null_mut();
         }
 
-        let leaf = unsafe { (*mid).children[mid_idx].load(Ordering::Acquire) };
+        unsafe { (*mid).children[mid_idx].load(Ordering::Acquire) }
+    }
+
+    /// Look up the span for a given page ID. Returns null if not set.
+    /// This is lock-free.
+    #[inline]
+    pub fn get(&self, page_id: usize) -> *mut Span {
+        let leaf = self.leaf(page_id);
         if leaf.is_null() {
             return ptr::null_mut();
         }
+        unsafe { (*leaf).spans[page_id & LEAF_MASK].load(Ordering::Acquire) }
+    }
 
-        unsafe { (*leaf).spans[leaf_idx].load(Ordering::Acquire) }
+    /// Size class of the span covering `page_id`: nonzero for pages of a
+    /// small-object span, 0 for large spans and unmapped pages. Lock-free.
+    ///
+    /// Only meaningful for pages of in-use spans; interior pages of free
+    /// spans may hold stale values, as they may for [`get`](Self::get).
+    #[inline]
+    pub fn size_class(&self, page_id: usize) -> usize {
+        self.class_and_arena(page_id).0
+    }
+
+    /// Size class and arena of the span covering `page_id`, from one load.
+    /// The arena is 0 wherever the class is. Lock-free, with the same
+    /// caveats as [`size_class`](Self::size_class).
+    #[inline]
+    pub fn class_and_arena(&self, page_id: usize) -> (usize, usize) {
+        let leaf = self.leaf(page_id);
+        if leaf.is_null() {
+            return (0, 0);
+        }
+        let entry = unsafe { (*leaf).classes[page_id & LEAF_MASK].load(Ordering::Acquire) };
+        let arena = if NUM_ARENAS == 1 {
+            0
+        } else {
+            (entry >> CLASS_BITS) as usize
+        };
+        ((entry & CLASS_MASK) as usize, arena)
     }
 
-    /// Set the span for a given page ID.
+    /// Set the span for a given page ID, caching its current `size_class`.
+    ///
+    /// A page the map cannot record is reported through
+    /// [`failure`](crate::failure) and left unset; for the endpoints of free
+    /// spans that only costs a missed coalesce.
     ///
     /// # Safety
     /// Must be called under external synchronization (the page heap lock).
     /// The span pointer must be valid or null.
     pub unsafe fn set(&self, page_id: usize, span: *mut Span) {
-        let root_idx = page_id >> ROOT_SHIFT;
+        let entry = if span.is_null() {
+            0
+        } else {
+            unsafe { class_entry(span) }
+        };
+        unsafe { self.store(page_id, span, entry) };
+    }
+
+    /// Returns false, after reporting the failure, if the page is out of
+    /// range or its node could not be allocated. Clearing a page never
+    /// allocates: a missing node already reads as null.
+    unsafe fn store(&self, page_id: usize, span: *mut Span, entry: u8) -> bool {
+        let Some(root_idx) = self.root_index(page_id) else {
+            if span.is_null() {
+                return true;
+            }
+            failure::report(Failure::PageOutOfRange, page_id);
+            return false;
+        };
         let mid_idx = (page_id >> MID_SHIFT) & MID_MASK;
         let leaf_idx = page_id & LEAF_MASK;
 
-        assert!(root_idx < ROOT_LEN, "page_id out of range for page map");
+        let leaf = if span.is_null() {
+            let mid = self.root[root_idx].load(Ordering::Acquire);
+            if mid.is_null() {
+                return true;
+            }
+            unsafe { (*mid).children[mid_idx].load(Ordering::Acquire) }
+        } else {
+            unsafe { self.ensure_leaf(root_idx, mid_idx) }
+        };
+        if leaf.is_null() {
+            if span.is_null() {
+                return true;
+            }
+            failure::report(Failure::PageMapNode, page_id);
+            return false;
+        }
 
-        // Ensure mid node exists
+        unsafe {
+            (*leaf).classes[leaf_idx].store(entry, Ordering::Release);
+            (*leaf).spans[leaf_idx].store(span, Ordering::Release);
+        }
+        true
+    }
+
+    /// Leaf node at `root_idx`/`mid_idx`, allocating it and its mid node if
+    /// missing. Null if a node could not be allocated.
+    unsafe fn ensure_leaf(&self, root_idx: usize, mid_idx: usize) -> *mut LeafNode {
         let mut mid = self.root[root_idx].load(Ordering::Acquire);
         if mid.is_null() {
             mid = unsafe { Self::alloc_mid_node() };
-            assert!(!mid.is_null(), "failed to allocate mid node for page map");
+            if mid.is_null() {
+                return ptr::null_mut();
+            }
             // Store with Release so readers see the initialized node
             self.root[root_idx].store(mid, Ordering::Release);
         }
 
-        // Ensure leaf node exists
         let mut leaf = unsafe { (*mid).children[mid_idx].load(Ordering::Acquire) };
         if leaf.is_null() {
             leaf = unsafe { Self::alloc_leaf_node() };
-            assert!(!leaf.is_null(), "failed to allocate leaf node for page map");
+            if leaf.is_null() {
+                return ptr::null_mut();
+            }
             unsafe { (*mid).children[mid_idx].store(leaf, Ordering::Release) };
         }
+        leaf
+    }
 
-        unsafe { (*leaf).spans[leaf_idx].store(span, Ordering::Release) };
+    /// Allocate every mid and leaf node covering `num_pages` pages from
+    /// `start_page`, so recording spans there never allocates.
+    ///
+    /// Returns false, after reporting the failure, if a page is out of range
+    /// or a node could not be allocated. Nodes already created are kept.
+    ///
+    /// # Safety
+    /// Must be called under external synchronization.
+    pub unsafe fn reserve(&self, start_page: usize, num_pages: usize) -> bool {
+        let end = start_page + num_pages;
+        // One leaf per LEAF_LEN-aligned block of pages.
+        let mut page_id = start_page & !LEAF_MASK;
+        while page_id < end {
+            let Some(root_idx) = self.root_index(page_id) else {
+                failure::report(Failure::PageOutOfRange, page_id);
+                return false;
+            };
+            let mid_idx = (page_id >> MID_SHIFT) & MID_MASK;
+            if unsafe { self.ensure_leaf(root_idx, mid_idx) }.is_null() {
+                failure::report(Failure::PageMapNode, page_id);
+                return false;
+            }
+            page_id += LEAF_LEN;
+        }
+        true
     }
 
-    /// Register a span for all pages it covers.
+    /// Register a span for all pages it covers. Must be called again
+    /// whenever the span's `size_class` changes.
+    ///
+    /// Returns false if some page could not be recorded (see
+    /// [`failure`](crate::failure)); the span's pages are then cleared, and
+    /// the caller must fail the allocation.
     ///
     /// # Safety
     /// Must be called under external synchronization.
-    pub unsafe fn register_span(&self, span: *mut Span) {
+    pub unsafe fn register_span(&self, span: *mut Span) -> bool {
         let start = unsafe { (*span).start_page };
         let count = unsafe { (*span).num_pages };
+        let entry = unsafe { class_entry(span) };
         for page_id in start..start + count {
-            unsafe { self.set(page_id, span) };
+            if !unsafe { self.store(page_id, span, entry) } {
+                unsafe { self.unregister_span(span) };
+                return false;
+            }
         }
+        true
     }
 
     /// Register only the first and last pages of a free span.
@@ -160,7 +383,7 @@ impl PageMap {
         let start = unsafe { (*span).start_page };
         let count = unsafe { (*span).num_pages };
         for page_id in start..start + count {
-            unsafe { self.set(page_id, ptr::null_mut()) };
+            unsafe { self.store(page_id, ptr::null_mut(), 0) };
         }
     }
 
@@ -168,15 +391,16 @@ impl PageMap {
         let size = core::mem::size_of::<MidNode>();
         // Round up to page size
         let alloc_size = (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-        let ptr = unsafe { platform::page_alloc(alloc_size) };
-        // page_alloc returns zeroed memory, which is valid for AtomicPtr (all null)
+        let ptr = unsafe { span::metadata_pages(alloc_size) };
+        // Zeroed memory, which is valid for AtomicPtr (all null)
         ptr.cast::<MidNode>()
     }
 
     unsafe fn alloc_leaf_node() -> *mut LeafNode {
         let size = core::mem::size_of::<LeafNode>();
         let alloc_size = (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-        let ptr = unsafe { platform::page_alloc(alloc_size) };
+        let ptr = unsafe { span::metadata_pages(alloc_size) };
+        // Zeroed: null span pointers, size class 0.
         ptr.cast::<LeafNode>()
     }
 }
@@ -228,7 +452,7 @@ mod tests {
             (*s).num_pages = 5;
             (*s).state = SpanState::InUse;
 
-            map.register_span(s);
+            assert!(map.register_span(s));
 
             for page in 100..105 {
                 assert_eq!(map.get(page), s);
@@ -245,6 +469,72 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_pagemap_class_and_arena() {
+        let map = PageMap::new();
+        let s = span::alloc_span();
+        assert!(!s.is_null());
+
+        unsafe {
+            (*s).start_page = 300;
+            (*s).num_pages = 2;
+            (*s).state = SpanState::InUse;
+            (*s).size_class = NUM_SIZE_CLASSES - 1;
+            (*s).arena = (NUM_ARENAS - 1) as u8;
+            assert!(map.register_span(s));
+            for page in 300..302 {
+                assert_eq!(map.size_class(page), NUM_SIZE_CLASSES - 1);
+                assert_eq!(
+                    map.class_and_arena(page),
+                    (NUM_SIZE_CLASSES - 1, NUM_ARENAS - 1)
+                );
+            }
+
+            // Large spans carry no arena.
+            (*s).size_class = 0;
+            assert!(map.register_span(s));
+            assert_eq!(map.class_and_arena(300), (0, 0));
+
+            map.unregister_span(s);
+            span::dealloc_span(s);
+        }
+    }
+
+    #[test]
+    fn test_pagemap_size_class() {
+        let map = PageMap::new();
+        let s = span::alloc_span();
+        assert!(!s.is_null());
+
+        unsafe {
+            (*s).start_page = 200;
+            (*s).num_pages = 3;
+            (*s).state = SpanState::InUse;
+            (*s).size_class = 5;
+            assert!(map.register_span(s));
+            for page in 200..203 {
+                assert_eq!(map.size_class(page), 5);
+            }
+            assert_eq!(map.size_class(199), 0);
+            assert_eq!(map.size_class(203), 0);
+            assert_eq!(map.size_class(usize::MAX >> 1), 0);
+
+            // Reused for a large allocation.
+            (*s).size_class = 0;
+            assert!(map.register_span(s));
+            assert_eq!(map.size_class(201), 0);
+
+            (*s).size_class = 7;
+            assert!(map.register_span(s));
+            map.unregister_span(s);
+            for page in 200..203 {
+                assert_eq!(map.size_class(page), 0);
+            }
+
+            span::dealloc_span(s);
+        }
+    }
+
     #[test]
     fn test_pagemap_high_address() {
         let map = PageMap::new();
@@ -252,8 +542,9 @@ mod tests {
         assert!(!s.is_null());
 
         unsafe {
-            // Use a high page ID that exercises all three levels
-            let page_id = (1 << 20) + (1 << 15) + 42;
+            // Use a high page ID that exercises all three levels, kept
+            // within a `max_heap` tree
+            let page_id = ((1 << 20) + (1 << 15) + 42) & ((1 << PAGE_ID_BITS) - 1);
             (*s).start_page = page_id;
             (*s).num_pages = 1;
 
@@ -265,4 +556,18 @@ mod tests {
             span::dealloc_span(s);
         }
     }
+    #[test]
+    fn test_pagemap_reserve() {
+        let map = PageMap::new();
+        // Straddles a mid node boundary, or a root one with room for it.
+        let start = (1 << ROOT_SHIFT.min(PAGE_ID_BITS - 1)) - LEAF_LEN - 5;
+        let count = 2 * LEAF_LEN + 10;
+        unsafe { assert!(map.reserve(start, count)) };
+        for page_id in [start, start + LEAF_LEN, start + count - 1] {
+            assert!(!map.leaf(page_id).is_null());
+            assert!(map.get(page_id).is_null());
+        }
+        assert!(map.leaf(start + count + LEAF_LEN).is_null());
+        assert!(map.leaf(start - LEAF_LEN).is_null());
+    }
 }
@@ -14,23 +14,203 @@ cfg_if::cfg_if! {
     }
 }
 
-/// Allocate `size` bytes of virtual memory, page-aligned.
+/// First placement hint under the `deterministic` feature: far from the
+/// regions where the loader and the kernel's default mmap base put things.
+#[cfg(feature = "deterministic")]
+const HINT_BASE: usize = if usize::BITS == 64 {
+    0x1000_0000_0000
+} else {
+    0x2000_0000
+};
+
+/// Hints are handed out in multiples of this, which covers both the page size
+/// and the Windows allocation granularity.
+#[cfg(feature = "deterministic")]
+const HINT_GRANULE: usize = if crate::config::PAGE_SIZE > 65536 {
+    crate::config::PAGE_SIZE
+} else {
+    65536
+};
+
+#[cfg(feature = "deterministic")]
+static NEXT_HINT: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(HINT_BASE);
+
+/// Address to suggest to the OS for a mapping of `len` bytes.
+///
+/// With `deterministic`, mappings are laid out bump-style from [`HINT_BASE`]
+/// and never reuse an address, so the same sequence of calls produces the
+/// same addresses on every run (as long as the OS honours the hint).
+/// Otherwise null, letting the OS choose.
+#[inline]
+#[allow(dead_code)] // Unused under Miri
+fn placement_hint(len: usize) -> usize {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "deterministic")] {
+            let len = len.div_ceil(HINT_GRANULE) * HINT_GRANULE;
+            NEXT_HINT.fetch_add(len, core::sync::atomic::Ordering::Relaxed)
+        } else {
+            let _ = len;
+            0
+        }
+    }
+}
+
+/// Allocate `size` bytes of virtual memory, aligned to [`PAGE_SIZE`] even
+/// where that is larger than the OS page or allocation granularity.
 /// Returns null on failure. Memory is zero-initialized by the OS.
 /// `size` is rounded up to the platform allocation granularity.
 ///
 /// # Safety
 /// Caller must eventually call `page_dealloc` with the returned pointer and the
 /// same `size` (before rounding).
+///
+/// [`PAGE_SIZE`]: crate::config::PAGE_SIZE
 #[inline]
 pub unsafe fn page_alloc(size: usize) -> *mut u8 {
-    cfg_if::cfg_if! {
-        if #[cfg(miri)] {
-            unsafe { miri::page_alloc(size) }
-        } else if #[cfg(windows)] {
-            unsafe { windows::page_alloc(size) }
-        } else if #[cfg(unix)] {
-            unsafe { unix::page_alloc(size) }
+    let ptr = {
+        cfg_if::cfg_if! {
+            if #[cfg(miri)] {
+                unsafe { miri::page_alloc(size) }
+            } else if #[cfg(windows)] {
+                unsafe { windows::page_alloc(size) }
+            } else if #[cfg(unix)] {
+                unsafe { unix::page_alloc(size) }
+            }
+        }
+    };
+    debug_assert_page_aligned(ptr);
+    note_failure(ptr)
+}
+
+/// Like [`page_alloc`], but faults in every page before returning.
+///
+/// Moves the page-fault cost of fresh memory from the first touch to the
+/// allocation itself (`MAP_POPULATE` on Linux, an explicit touch elsewhere).
+///
+/// # Safety
+/// Same contract as [`page_alloc`].
+#[inline]
+pub unsafe fn page_alloc_populated(size: usize) -> *mut u8 {
+    let ptr = {
+        cfg_if::cfg_if! {
+            if #[cfg(miri)] {
+                unsafe { miri::page_alloc_populated(size) }
+            } else if #[cfg(windows)] {
+                unsafe { windows::page_alloc_populated(size) }
+            } else if #[cfg(unix)] {
+                unsafe { unix::page_alloc_populated(size) }
+            }
         }
+    };
+    debug_assert_page_aligned(ptr);
+    note_failure(ptr)
+}
+
+/// Record why the OS refused a mapping, for
+/// [`failure::last_error`](crate::failure::last_error).
+#[inline]
+fn note_failure(ptr: *mut u8) -> *mut u8 {
+    if ptr.is_null() {
+        crate::failure::set_last_error(last_os_error());
+    }
+    ptr
+}
+
+/// Page IDs are addresses shifted by `PAGE_SHIFT`, so a misaligned mapping
+/// would make every span covering it start inside someone else's page.
+#[inline]
+fn debug_assert_page_aligned(ptr: *mut u8) {
+    debug_assert!(
+        (ptr as usize).is_multiple_of(crate::config::PAGE_SIZE),
+        "page_alloc returned {ptr:p}, not aligned to PAGE_SIZE"
+    );
+}
+
+/// Reserve `size` bytes of zeroed address space aligned to `align` (a power
+/// of two, at least the page size). Returns null on failure.
+///
+/// Pages must be committed with [`page_recommit`] before use; on Unix that is
+/// a no-op and they are backed on first touch.
+///
+/// # Safety
+/// The range is never released; `page_dealloc` must not be called on it.
+#[inline]
+pub unsafe fn page_reserve(size: usize, align: usize) -> *mut u8 {
+    let ptr = {
+        cfg_if::cfg_if! {
+            if #[cfg(miri)] {
+                unsafe { miri::page_reserve(size, align) }
+            } else if #[cfg(windows)] {
+                unsafe { windows::page_reserve(size, align) }
+            } else if #[cfg(unix)] {
+                unsafe { unix::page_reserve(size, align) }
+            }
+        }
+    };
+    note_failure(ptr)
+}
+
+/// Reserve `size` bytes of address space at exactly `base`, like
+/// [`page_reserve`]. Returns null, with [`last_os_error`] telling why
+/// (`EEXIST` on Unix), if the range is not free; a mapping is never moved
+/// elsewhere or placed over another.
+///
+/// # Safety
+/// As for [`page_reserve`].
+#[inline]
+pub unsafe fn page_reserve_at(base: usize, size: usize) -> *mut u8 {
+    let ptr = {
+        cfg_if::cfg_if! {
+            if #[cfg(miri)] {
+                unsafe { miri::page_reserve_at(base, size) }
+            } else if #[cfg(windows)] {
+                unsafe { windows::page_reserve_at(base, size) }
+            } else if #[cfg(unix)] {
+                unsafe { unix::page_reserve_at(base, size) }
+            }
+        }
+    };
+    note_failure(ptr)
+}
+
+/// Reserve `size` bytes of address space with `gap` more on each side, all
+/// of it inaccessible, and return the start of the middle `size` bytes
+/// (aligned to [`PAGE_SIZE`]). The gaps are never handed out, so no other
+/// mapping lands within `gap` bytes of the range. Returns null on failure.
+///
+/// Pages of the middle range are made usable with [`page_recommit`]
+/// followed by [`page_protect`].
+///
+/// # Safety
+/// As for [`page_reserve`].
+///
+/// [`PAGE_SIZE`]: crate::config::PAGE_SIZE
+pub unsafe fn page_reserve_apart(size: usize, gap: usize) -> *mut u8 {
+    let total = size + 2 * gap;
+    let base = unsafe { page_reserve(total, crate::config::PAGE_SIZE) };
+    if base.is_null() {
+        return base;
+    }
+    // Windows reservations are inaccessible until committed; Unix ones are
+    // mapped read-write.
+    #[cfg(all(unix, not(miri)))]
+    if !unsafe { page_protect(base, total, false) } {
+        note_failure(core::ptr::null_mut());
+        unsafe { unix::page_dealloc(base, total) };
+        return core::ptr::null_mut();
+    }
+    base.wrapping_add(gap)
+}
+
+/// Write one byte per OS page so the kernel backs the whole range now.
+///
+/// The memory is freshly mapped and zeroed, so writing zero is invisible.
+pub(crate) unsafe fn touch_pages(ptr: *mut u8, size: usize) {
+    const OS_PAGE: usize = 4096;
+    let mut off = 0;
+    while off < size {
+        unsafe { core::ptr::write_volatile(ptr.add(off), 0) };
+        off += OS_PAGE;
     }
 }
 
@@ -71,11 +251,13 @@ pub unsafe fn page_decommit(ptr: *mut u8, size: usize) {
     }
 }
 
-/// Recommit previously decommitted pages.
+/// Recommit previously decommitted pages, or commit pages of a
+/// [`page_reserve`] range for the first time.
 ///
 /// # Safety
-/// `ptr` and `size` must refer to a range within a live `page_alloc` allocation
-/// that was previously decommitted.
+/// `ptr` and `size` must refer to a range within a live `page_alloc`
+/// allocation that was previously decommitted, or within a `page_reserve`
+/// range.
 #[inline]
 pub unsafe fn page_recommit(ptr: *mut u8, size: usize) {
     cfg_if::cfg_if! {
@@ -91,6 +273,314 @@ pub unsafe fn page_recommit(ptr: *mut u8, size: usize) {
     }
 }
 
+/// Zero `size` bytes at `ptr` by having the OS drop the pages instead of
+/// writing them: they read as zero and fault in fresh on next touch. Returns
+/// false, with nothing written, where the OS does not guarantee that (only
+/// Linux and Android do) or the range holds no whole OS page.
+///
+/// # Safety
+/// `ptr` and `size` must refer to a range within a live `page_alloc`
+/// allocation or a committed part of a `page_reserve` range, whose contents
+/// may be discarded.
+#[inline]
+pub unsafe fn page_zero(ptr: *mut u8, size: usize) -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(miri)] {
+            unsafe { miri::page_zero(ptr, size) }
+        } else if #[cfg(windows)] {
+            unsafe { windows::page_zero(ptr, size) }
+        } else if #[cfg(unix)] {
+            unsafe { unix::page_zero(ptr, size) }
+        }
+    }
+}
+
+/// Make `ptr..ptr + size` inaccessible, so that any load or store faults,
+/// or with `accessible` readable and writable again (`mprotect` /
+/// `VirtualProtect`). Returns false where the protection was not changed.
+///
+/// # Safety
+/// `ptr` and `size` must be page-aligned and refer to a committed range
+/// within a live `page_alloc` allocation or a `page_reserve` range, which
+/// nothing touches while it is inaccessible.
+#[inline]
+pub unsafe fn page_protect(ptr: *mut u8, size: usize, accessible: bool) -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(miri)] {
+            unsafe { miri::page_protect(ptr, size, accessible) }
+        } else if #[cfg(windows)] {
+            unsafe { windows::page_protect(ptr, size, accessible) }
+        } else if #[cfg(unix)] {
+            unsafe { unix::page_protect(ptr, size, accessible) }
+        }
+    }
+}
+
+/// Spread the pages of `ptr..ptr + size` over every online NUMA node
+/// (`MPOL_INTERLEAVE`), or with `interleave` false give them back the
+/// default policy of faulting in on the touching thread's node. Pages
+/// already faulted in stay where they are; the policy places the rest as
+/// they are touched. Returns false where the policy was not set: on a
+/// single node, off Linux, or if the kernel refused.
+///
+/// # Safety
+/// `ptr` and `size` must be page-aligned and refer to a range within a live
+/// `page_alloc` allocation or a `page_reserve` range.
+#[inline]
+pub unsafe fn page_interleave(ptr: *mut u8, size: usize, interleave: bool) -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(all(unix, not(miri)))] {
+            let nodes = if interleave {
+                let nodes = numa_node_mask();
+                if nodes.count_ones() < 2 {
+                    return false;
+                }
+                nodes
+            } else {
+                0
+            };
+            unsafe { unix::page_interleave(ptr, size, nodes) }
+        } else {
+            let _ = (ptr, size, interleave);
+            false
+        }
+    }
+}
+
+/// Identifier of the current process (`getpid` / `GetCurrentProcessId`).
+#[inline]
+pub fn process_id() -> u32 {
+    cfg_if::cfg_if! {
+        if #[cfg(miri)] {
+            miri::process_id()
+        } else if #[cfg(windows)] {
+            windows::process_id()
+        } else if #[cfg(unix)] {
+            unix::process_id()
+        }
+    }
+}
+
+/// Identifier of the calling thread, never 0 (`pthread_self` /
+/// `GetCurrentThreadId`). Unique among running threads; an exited thread's
+/// id may be reused.
+#[inline]
+pub fn thread_id() -> usize {
+    cfg_if::cfg_if! {
+        if #[cfg(miri)] {
+            miri::thread_id()
+        } else if #[cfg(windows)] {
+            windows::thread_id()
+        } else if #[cfg(unix)] {
+            unix::thread_id()
+        }
+    }
+}
+
+/// Path of the loaded executable or shared library containing `addr`, and
+/// `addr`'s offset from where it was loaded (`dladdr`). None where that is
+/// unknown, and always on Windows and under Miri.
+#[cfg(feature = "layout-check")]
+pub fn module_offset(addr: usize) -> Option<(&'static core::ffi::CStr, usize)> {
+    cfg_if::cfg_if! {
+        if #[cfg(all(unix, not(miri)))] {
+            unix::module_offset(addr)
+        } else {
+            let _ = addr;
+            None
+        }
+    }
+}
+
+/// Milliseconds on a monotonic clock with an arbitrary start
+/// (`clock_gettime(CLOCK_MONOTONIC)` / `GetTickCount64`). Always 0 under
+/// Miri.
+#[inline]
+pub fn now_ms() -> u64 {
+    cfg_if::cfg_if! {
+        if #[cfg(miri)] {
+            miri::now_ms()
+        } else if #[cfg(windows)] {
+            windows::now_ms()
+        } else if #[cfg(unix)] {
+            unix::now_ms()
+        }
+    }
+}
+
+/// Value of the environment variable `name`, read through the C library so
+/// it works before `std` is set up and without it. None where there is no C
+/// environment to read (Windows, Miri).
+#[inline]
+pub fn env(name: &core::ffi::CStr) -> Option<&'static core::ffi::CStr> {
+    cfg_if::cfg_if! {
+        if #[cfg(all(unix, not(miri)))] {
+            unix::env(name)
+        } else {
+            let _ = name;
+            None
+        }
+    }
+}
+
+/// Write `bytes` to the standard error stream with a raw system call, so it
+/// works before `std` is set up, after it is torn down, and without it.
+/// Does nothing under Miri.
+pub fn write_stderr(bytes: &[u8]) {
+    cfg_if::cfg_if! {
+        if #[cfg(miri)] {
+            let _ = bytes;
+        } else if #[cfg(windows)] {
+            windows::write_stderr(bytes)
+        } else if #[cfg(unix)] {
+            unix::write_stderr(bytes)
+        }
+    }
+}
+
+/// Read the start of the file at `path` into `buf`, returning the bytes
+/// read. Only used for the small kernel files under `/sys`; None off Linux
+/// and Android.
+pub fn read_file(path: &core::ffi::CStr, buf: &mut [u8]) -> Option<usize> {
+    cfg_if::cfg_if! {
+        if #[cfg(all(unix, not(miri)))] {
+            unix::read_file(path, buf)
+        } else {
+            let _ = (path, buf);
+            None
+        }
+    }
+}
+
+/// Set the calling thread's C `errno`, for C entry points that report errors
+/// the C way. Does nothing without a C library to hold it (Windows, Miri).
+pub fn set_errno(code: i32) {
+    cfg_if::cfg_if! {
+        if #[cfg(all(unix, not(miri)))] {
+            unix::set_errno(code)
+        } else {
+            let _ = code;
+        }
+    }
+}
+
+/// The calling thread's error code for the OS call that just failed: `errno`
+/// on Unix, `GetLastError()` on Windows, `ENOMEM` where neither can be read
+/// (Miri).
+pub fn last_os_error() -> i32 {
+    cfg_if::cfg_if! {
+        if #[cfg(all(unix, not(miri)))] {
+            unix::errno()
+        } else if #[cfg(all(windows, not(miri)))] {
+            windows::last_error()
+        } else {
+            crate::failure::ENOMEM
+        }
+    }
+}
+
+/// Whether the kernel implements restartable sequences (Linux x86_64 and
+/// aarch64; false elsewhere).
+pub fn rseq_supported() -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(all(unix, not(miri)))] {
+            unix::rseq_supported()
+        } else {
+            false
+        }
+    }
+}
+
+/// Number of NUMA nodes the kernel reports online, 0 if unknown.
+pub fn numa_nodes() -> u32 {
+    let mut buf = [0u8; 256];
+    match read_file(c"/sys/devices/system/node/online", &mut buf) {
+        Some(n) => count_id_list(&buf[..n]),
+        None => 0,
+    }
+}
+
+/// Online NUMA nodes as a bit mask, bit `n` for node `n`, read once. Nodes
+/// from 63 up are left out; 0 if unknown.
+pub fn numa_node_mask() -> u64 {
+    use core::sync::atomic::{AtomicU64, Ordering};
+    /// Bit 63 alone stands for "not read yet"; node 63 is never in the mask.
+    const UNREAD: u64 = 1 << 63;
+    static MASK: AtomicU64 = AtomicU64::new(UNREAD);
+    let mask = MASK.load(Ordering::Relaxed);
+    if mask != UNREAD {
+        return mask;
+    }
+    let mut buf = [0u8; 256];
+    let mask = read_file(c"/sys/devices/system/node/online", &mut buf)
+        .map_or(0, |n| id_list_mask(&buf[..n]) & !UNREAD);
+    MASK.store(mask, Ordering::Relaxed);
+    mask
+}
+
+/// Whether transparent huge pages are on, for every mapping or for those
+/// that ask (`always` or `madvise` selected). False if unknown.
+pub fn transparent_hugepages() -> bool {
+    let mut buf = [0u8; 128];
+    let Some(n) = read_file(c"/sys/kernel/mm/transparent_hugepage/enabled", &mut buf) else {
+        return false;
+    };
+    let text = &buf[..n];
+    let Some(open) = text.iter().position(|&b| b == b'[') else {
+        return false;
+    };
+    !text[open..].starts_with(b"[never]")
+}
+
+/// Number of ids in a kernel id list such as `0`, `0-3` or `0,2-5`.
+fn count_id_list(text: &[u8]) -> u32 {
+    let mut count = 0;
+    for range in text.trim_ascii().split(|&b| b == b',') {
+        let mut bounds = range.splitn(2, |&b| b == b'-').map(|n| {
+            core::str::from_utf8(n)
+                .ok()
+                .and_then(|n| n.parse::<u32>().ok())
+        });
+        match (bounds.next().flatten(), bounds.next()) {
+            (Some(_), None) => count += 1,
+            (Some(lo), Some(Some(hi))) if hi >= lo => count += hi - lo + 1,
+            _ => return 0,
+        }
+    }
+    count
+}
+
+/// Ids below 64 of a kernel id list as a bit mask; 0 if it does not parse.
+fn id_list_mask(text: &[u8]) -> u64 {
+    let mut mask = 0u64;
+    for range in text.trim_ascii().split(|&b| b == b',') {
+        let mut bounds = range.splitn(2, |&b| b == b'-').map(|n| {
+            core::str::from_utf8(n)
+                .ok()
+                .and_then(|n| n.parse::<u32>().ok())
+        });
+        let (lo, hi) = match (bounds.next().flatten(), bounds.next()) {
+            (Some(id), None) => (id, id),
+            (Some(lo), Some(Some(hi))) if hi >= lo => (lo, hi),
+            _ => return 0,
+        };
+        for id in lo..=hi.min(63) {
+            mask |= 1 << id;
+        }
+    }
+    mask
+}
+
+/// Terminate the process immediately without unwinding.
+#[cold]
+pub fn abort() -> ! {
+    unsafe extern "C" {
+        #[link_name = "abort"]
+        fn c_abort() -> !;
+    }
+    unsafe { c_abort() }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,6 +618,143 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_alloc_populated() {
+        unsafe {
+            let size = PAGE_SIZE * 4;
+            let ptr = page_alloc_populated(size);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % PAGE_SIZE, 0);
+            assert_eq!(*ptr, 0);
+            assert_eq!(*ptr.add(size - 1), 0);
+            page_dealloc(ptr, size);
+        }
+    }
+
+    #[cfg(all(feature = "deterministic", not(miri)))]
+    #[test]
+    fn test_hinted_placement() {
+        unsafe {
+            let a = page_alloc(PAGE_SIZE);
+            let b = page_alloc(PAGE_SIZE);
+            assert!(a as usize >= HINT_BASE && b as usize >= HINT_BASE);
+            assert!(b > a, "hinted mappings grow upwards");
+            assert_eq!(a as usize % HINT_GRANULE, 0);
+            page_dealloc(a, PAGE_SIZE);
+            page_dealloc(b, PAGE_SIZE);
+        }
+    }
+
+    #[cfg(all(unix, not(miri), target_pointer_width = "64"))]
+    #[test]
+    fn test_reserve_at() {
+        unsafe {
+            let size = PAGE_SIZE * 4;
+            let taken = page_alloc(size);
+            assert!(page_reserve_at(taken.addr(), size).is_null());
+            assert_eq!(last_os_error(), 17);
+            assert_eq!(*taken, 0, "the mapping there is left alone");
+            page_dealloc(taken, size);
+
+            let base = 0x3000_0000_0000;
+            let ptr = page_reserve_at(base, size);
+            assert_eq!(ptr.addr(), base);
+            page_recommit(ptr, size);
+            *ptr.add(size - 1) = 1;
+            page_dealloc(ptr, size);
+        }
+    }
+
+    #[test]
+    fn test_alloc_page_size_aligned() {
+        unsafe {
+            for pages in [1, 3, 16, 129] {
+                let size = PAGE_SIZE * pages;
+                let a = page_alloc(size);
+                let b = page_alloc_populated(size);
+                assert!(!a.is_null() && !b.is_null());
+                assert_eq!(a as usize % PAGE_SIZE, 0);
+                assert_eq!(b as usize % PAGE_SIZE, 0);
+                page_dealloc(a, size);
+                page_dealloc(b, size);
+            }
+        }
+    }
+
+    /// Page map nodes and fresh large spans are used without clearing, so
+    /// every backend must hand out zeroed memory, including address ranges
+    /// it had mapped and released before.
+    #[test]
+    fn test_alloc_is_zeroed() {
+        unsafe {
+            for round in 0..4 {
+                for pages in [1, 5, 64] {
+                    let size = PAGE_SIZE * pages;
+                    let ptr = if round % 2 == 0 {
+                        page_alloc(size)
+                    } else {
+                        page_alloc_populated(size)
+                    };
+                    assert!(!ptr.is_null());
+                    assert!((0..size).all(|i| ptr.add(i).read() == 0));
+                    ptr.write_bytes(0xC3, size);
+                    page_dealloc(ptr, size);
+                }
+            }
+
+            let size = PAGE_SIZE * 4;
+            let ptr = page_reserve(size, PAGE_SIZE);
+            assert!(!ptr.is_null());
+            page_recommit(ptr, size);
+            assert!((0..size).all(|i| ptr.add(i).read() == 0));
+        }
+    }
+
+    #[test]
+    fn test_page_zero() {
+        unsafe {
+            let size = PAGE_SIZE * 8;
+            let ptr = page_alloc(size);
+            ptr.write_bytes(0x5A, size);
+            // Unaligned at both ends: the partial pages are cleared by hand.
+            let (start, len) = (100, size - 300);
+            let supported = cfg!(all(
+                any(target_os = "linux", target_os = "android"),
+                not(miri)
+            ));
+            assert_eq!(page_zero(ptr.add(start), len), supported);
+            if supported {
+                for i in 0..size {
+                    let zeroed = (start..start + len).contains(&i);
+                    assert_eq!(ptr.add(i).read(), if zeroed { 0 } else { 0x5A }, "{i}");
+                }
+            } else {
+                assert!((0..size).all(|i| ptr.add(i).read() == 0x5A));
+            }
+            assert!(!page_zero(ptr.add(1), 16));
+            page_dealloc(ptr, size);
+        }
+    }
+
+    #[test]
+    fn test_id_list_mask() {
+        assert_eq!(id_list_mask(b"0\n"), 0b1);
+        assert_eq!(id_list_mask(b"0-3"), 0b1111);
+        assert_eq!(id_list_mask(b"0,2-4,7"), 0b1001_1101);
+        assert_eq!(id_list_mask(b"60-70"), 0b1111 << 60);
+        assert_eq!(id_list_mask(b"3-1"), 0);
+        assert_eq!(id_list_mask(b""), 0);
+    }
+
+    #[test]
+    #[cfg(not(miri))]
+    fn test_now_ms_advances() {
+        let start = now_ms();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let elapsed = now_ms() - start;
+        assert!((19..10_000).contains(&elapsed), "{elapsed} ms");
+    }
+
     #[test]
     fn test_alloc_large() {
         unsafe {
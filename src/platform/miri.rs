@@ -13,6 +13,20 @@ pub unsafe fn page_alloc(size: usize) -> *mut u8 {
     unsafe { alloc::alloc::alloc_zeroed(layout) }
 }
 
+pub unsafe fn page_alloc_populated(size: usize) -> *mut u8 {
+    unsafe { page_alloc(size) }
+}
+
+pub unsafe fn page_reserve(size: usize, align: usize) -> *mut u8 {
+    let layout = Layout::from_size_align(size, align).unwrap();
+    unsafe { alloc::alloc::alloc_zeroed(layout) }
+}
+
+/// Miri has no address space to place a mapping in.
+pub unsafe fn page_reserve_at(_base: usize, _size: usize) -> *mut u8 {
+    core::ptr::null_mut()
+}
+
 pub unsafe fn page_dealloc(ptr: *mut u8, size: usize) {
     let layout = Layout::from_size_align(size, crate::config::PAGE_SIZE).unwrap();
     unsafe { alloc::alloc::dealloc(ptr, layout) };
@@ -21,3 +35,27 @@ pub unsafe fn page_dealloc(ptr: *mut u8, size: usize) {
 pub unsafe fn page_decommit(_ptr: *mut u8, _size: usize) {}
 
 pub unsafe fn page_recommit(_ptr: *mut u8, _size: usize) {}
+
+pub unsafe fn page_zero(_ptr: *mut u8, _size: usize) -> bool {
+    false
+}
+
+pub unsafe fn page_protect(_ptr: *mut u8, _size: usize, _accessible: bool) -> bool {
+    false
+}
+
+pub fn process_id() -> u32 {
+    0
+}
+
+pub fn now_ms() -> u64 {
+    0
+}
+
+pub fn thread_id() -> usize {
+    // Miri emulates `pthread_self` for Unix targets.
+    unsafe extern "C" {
+        fn pthread_self() -> usize;
+    }
+    unsafe { pthread_self() }
+}
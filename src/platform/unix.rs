@@ -1,14 +1,47 @@
 //! Unix virtual memory implementation using mmap/munmap.
 
 use crate::config::PAGE_SIZE;
-use core::ffi::c_void;
+use core::ffi::{CStr, c_char, c_long, c_void};
 
+const PROT_NONE: i32 = 0x0;
 const PROT_READ: i32 = 0x1;
 const PROT_WRITE: i32 = 0x2;
 const MAP_PRIVATE: i32 = 0x02;
 const MAP_ANONYMOUS: i32 = 0x20;
-const MAP_FAILED: *mut c_void = !0usize as *mut c_void;
+const MAP_FAILED: *mut c_void = core::ptr::without_provenance_mut(!0usize);
 const MADV_DONTNEED: i32 = 4;
+#[cfg(target_vendor = "apple")]
+const CLOCK_MONOTONIC: i32 = 6;
+#[cfg(any(target_os = "freebsd", target_os = "dragonfly"))]
+const CLOCK_MONOTONIC: i32 = 4;
+#[cfg(not(any(
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly"
+)))]
+const CLOCK_MONOTONIC: i32 = 1;
+
+#[cfg(feature = "layout-check")]
+#[repr(C)]
+struct DlInfo {
+    fname: *const c_char,
+    fbase: *mut c_void,
+    sname: *const c_char,
+    saddr: *mut c_void,
+}
+
+#[repr(C)]
+struct Timespec {
+    tv_sec: c_long,
+    tv_nsec: c_long,
+}
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const MAP_POPULATE: i32 = 0x8000;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const MAP_NORESERVE: i32 = 0x4000;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const MAP_FIXED_NOREPLACE: i32 = 0x10_0000;
+const EEXIST: i32 = 17;
 
 unsafe extern "C" {
     fn mmap(
@@ -23,15 +56,119 @@ unsafe extern "C" {
     fn munmap(addr: *mut c_void, length: usize) -> i32;
 
     fn madvise(addr: *mut c_void, length: usize, advice: i32) -> i32;
+
+    fn mprotect(addr: *mut c_void, length: usize, prot: i32) -> i32;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn getpagesize() -> i32;
+
+    fn getpid() -> i32;
+
+    fn pthread_self() -> usize;
+
+    #[cfg(feature = "layout-check")]
+    fn dladdr(addr: *const c_void, info: *mut DlInfo) -> i32;
+
+    fn getenv(name: *const c_char) -> *const c_char;
+
+    fn write(fd: i32, buf: *const c_void, count: usize) -> isize;
+
+    fn clock_gettime(clock: i32, tp: *mut Timespec) -> i32;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn open(path: *const c_char, flags: i32, ...) -> i32;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn read(fd: i32, buf: *mut c_void, count: usize) -> isize;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn close(fd: i32) -> i32;
+
+    #[cfg(target_os = "linux")]
+    fn syscall(num: c_long, ...) -> c_long;
+
+    #[cfg(target_os = "linux")]
+    fn __errno_location() -> *mut i32;
+
+    #[cfg(target_os = "android")]
+    fn __errno() -> *mut i32;
+
+    #[cfg(any(target_vendor = "apple", target_os = "freebsd"))]
+    fn __error() -> *mut i32;
 }
 
 pub unsafe fn page_alloc(size: usize) -> *mut u8 {
+    unsafe { map_aligned(size, PAGE_SIZE, 0) }
+}
+
+/// Map and fault in the pages up front. Linux uses `MAP_POPULATE`; other
+/// Unixes touch each page after mapping.
+pub unsafe fn page_alloc_populated(size: usize) -> *mut u8 {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            unsafe { map_aligned(size, PAGE_SIZE, MAP_POPULATE) }
+        } else {
+            let ptr = unsafe { map_aligned(size, PAGE_SIZE, 0) };
+            if !ptr.is_null() {
+                unsafe { super::touch_pages(ptr, size) };
+            }
+            ptr
+        }
+    }
+}
+
+/// Anonymous mapping, pages are backed on first touch. Linux skips the swap
+/// reservation, so a large heap region costs nothing until used.
+pub unsafe fn page_reserve(size: usize, align: usize) -> *mut u8 {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            unsafe { map_aligned(size, align, MAP_NORESERVE) }
+        } else {
+            unsafe { map_aligned(size, align, 0) }
+        }
+    }
+}
+
+/// Like [`page_reserve`], at exactly `base`. Linux refuses with `EEXIST`
+/// when anything is mapped in the range (`MAP_FIXED_NOREPLACE`). Kernels
+/// before 4.17 and other Unixes take `base` as a hint only, so a mapping
+/// placed elsewhere is dropped and refused the same way.
+pub unsafe fn page_reserve_at(base: usize, size: usize) -> *mut u8 {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            let extra_flags = MAP_NORESERVE | MAP_FIXED_NOREPLACE;
+        } else {
+            let extra_flags = 0;
+        }
+    }
+    let raw = unsafe {
+        mmap(
+            core::ptr::without_provenance_mut(base),
+            size,
+            PROT_READ | PROT_WRITE,
+            MAP_PRIVATE | MAP_ANONYMOUS | extra_flags,
+            -1,
+            0,
+        )
+    };
+    if raw == MAP_FAILED {
+        return core::ptr::null_mut();
+    }
+    if raw.addr() != base {
+        unsafe { munmap(raw, size) };
+        set_errno(EEXIST);
+        return core::ptr::null_mut();
+    }
+    raw.cast()
+}
+
+unsafe fn map_aligned(size: usize, align: usize, extra_flags: i32) -> *mut u8 {
     let raw = unsafe {
         mmap(
-            core::ptr::null_mut(),
-            size + PAGE_SIZE,
+            core::ptr::without_provenance_mut(super::placement_hint(size + align)),
+            size + align,
             PROT_READ | PROT_WRITE,
-            MAP_PRIVATE | MAP_ANONYMOUS,
+            MAP_PRIVATE | MAP_ANONYMOUS | extra_flags,
             -1,
             0,
         )
@@ -40,22 +177,22 @@ pub unsafe fn page_alloc(size: usize) -> *mut u8 {
         return core::ptr::null_mut();
     }
 
-    let raw_addr = raw as usize;
-    let aligned_addr = (raw_addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let raw = raw.cast::<u8>();
+    let aligned = raw.map_addr(|a| (a + align - 1) & !(align - 1));
 
-    // Trim leading waste (0 or 4096 bytes)
-    let lead = aligned_addr - raw_addr;
+    // Trim leading waste (less than `align` bytes)
+    let lead = aligned.addr() - raw.addr();
     if lead > 0 {
-        unsafe { munmap(raw_addr as *mut c_void, lead) };
+        unsafe { munmap(raw.cast(), lead) };
     }
 
-    // Trim trailing waste (PAGE_SIZE - lead bytes)
-    let trail = (raw_addr + size + PAGE_SIZE) - (aligned_addr + size);
+    // Trim trailing waste (`align - lead` bytes)
+    let trail = align - lead;
     if trail > 0 {
-        unsafe { munmap((aligned_addr + size) as *mut c_void, trail) };
+        unsafe { munmap(aligned.wrapping_add(size).cast(), trail) };
     }
 
-    aligned_addr as *mut u8
+    aligned
 }
 
 pub unsafe fn page_dealloc(ptr: *mut u8, size: usize) {
@@ -65,3 +202,194 @@ pub unsafe fn page_dealloc(ptr: *mut u8, size: usize) {
 pub unsafe fn page_decommit(ptr: *mut u8, size: usize) {
     unsafe { madvise(ptr as *mut c_void, size, MADV_DONTNEED) };
 }
+
+pub unsafe fn page_protect(ptr: *mut u8, size: usize, accessible: bool) -> bool {
+    let prot = if accessible {
+        PROT_READ | PROT_WRITE
+    } else {
+        PROT_NONE
+    };
+    unsafe { mprotect(ptr as *mut c_void, size, prot) == 0 }
+}
+
+/// Linux drops the contents of private anonymous pages on `MADV_DONTNEED`:
+/// the next touch maps a zero page. Only whole OS pages can be dropped, so
+/// the partial pages at either end are cleared by hand.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub unsafe fn page_zero(ptr: *mut u8, size: usize) -> bool {
+    let page = unsafe { getpagesize() } as usize;
+    let start = ptr.addr().next_multiple_of(page);
+    let end = (ptr.addr() + size) & !(page - 1);
+    if start >= end {
+        return false;
+    }
+    let first = ptr.with_addr(start);
+    if unsafe { madvise(first.cast(), end - start, MADV_DONTNEED) } != 0 {
+        return false;
+    }
+    unsafe {
+        ptr.write_bytes(0, start - ptr.addr());
+        ptr.with_addr(end).write_bytes(0, ptr.addr() + size - end);
+    }
+    true
+}
+
+/// Other Unixes may keep the old contents after `MADV_DONTNEED`.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub unsafe fn page_zero(_ptr: *mut u8, _size: usize) -> bool {
+    false
+}
+
+/// `mbind` the range to `MPOL_INTERLEAVE` over the nodes in `nodes`, or
+/// back to `MPOL_DEFAULT` when `nodes` is 0. Neither moves pages already
+/// faulted in.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub unsafe fn page_interleave(ptr: *mut u8, size: usize, nodes: u64) -> bool {
+    #[cfg(target_arch = "x86_64")]
+    const SYS_MBIND: c_long = 237;
+    #[cfg(target_arch = "aarch64")]
+    const SYS_MBIND: c_long = 235;
+    const MPOL_DEFAULT: i32 = 0;
+    const MPOL_INTERLEAVE: i32 = 3;
+    let (mode, mask, max_node) = if nodes == 0 {
+        (MPOL_DEFAULT, core::ptr::null::<u64>(), 0usize)
+    } else {
+        // The kernel reads one bit fewer than `maxnode`.
+        (MPOL_INTERLEAVE, &raw const nodes, u64::BITS as usize + 1)
+    };
+    let ret = unsafe { syscall(SYS_MBIND, ptr, size, mode, mask, max_node, 0u32) };
+    ret == 0
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+pub unsafe fn page_interleave(_ptr: *mut u8, _size: usize, _nodes: u64) -> bool {
+    false
+}
+
+pub fn env(name: &CStr) -> Option<&'static CStr> {
+    let value = unsafe { getenv(name.as_ptr()) };
+    (!value.is_null()).then(|| unsafe { CStr::from_ptr(value) })
+}
+
+pub fn process_id() -> u32 {
+    unsafe { getpid() as u32 }
+}
+
+pub fn thread_id() -> usize {
+    unsafe { pthread_self() }
+}
+
+#[cfg(feature = "layout-check")]
+pub fn module_offset(addr: usize) -> Option<(&'static CStr, usize)> {
+    let mut info = DlInfo {
+        fname: core::ptr::null(),
+        fbase: core::ptr::null_mut(),
+        sname: core::ptr::null(),
+        saddr: core::ptr::null_mut(),
+    };
+    if unsafe { dladdr(addr as *const c_void, &mut info) } == 0 || info.fname.is_null() {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(info.fname) };
+    Some((name, addr.wrapping_sub(info.fbase as usize)))
+}
+
+pub fn write_stderr(bytes: &[u8]) {
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let n = unsafe { write(2, rest.as_ptr().cast(), rest.len()) };
+        match usize::try_from(n) {
+            Ok(n) if n > 0 => rest = &rest[n..],
+            _ => return,
+        }
+    }
+}
+
+pub fn now_ms() -> u64 {
+    let mut ts = Timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { clock_gettime(CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1000 + ts.tv_nsec as u64 / 1_000_000
+}
+
+/// Read up to `buf.len()` bytes from the start of the file at `path`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn read_file(path: &CStr, buf: &mut [u8]) -> Option<usize> {
+    const O_CLOEXEC: i32 = 0o2000000;
+    let fd = unsafe { open(path.as_ptr(), O_CLOEXEC) };
+    if fd < 0 {
+        return None;
+    }
+    let n = unsafe { read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+    unsafe { close(fd) };
+    usize::try_from(n).ok()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn read_file(_path: &CStr, _buf: &mut [u8]) -> Option<usize> {
+    None
+}
+
+/// Set the calling thread's C `errno`. Does nothing on Unixes whose errno
+/// accessor is not declared here.
+pub fn set_errno(code: i32) {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            unsafe { *__errno_location() = code };
+        } else if #[cfg(target_os = "android")] {
+            unsafe { *__errno() = code };
+        } else if #[cfg(any(target_vendor = "apple", target_os = "freebsd"))] {
+            unsafe { *__error() = code };
+        } else {
+            let _ = code;
+        }
+    }
+}
+
+/// The calling thread's C `errno`, or `ENOMEM` on Unixes whose errno
+/// accessor is not declared here.
+pub fn errno() -> i32 {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            unsafe { *__errno_location() }
+        } else if #[cfg(target_os = "android")] {
+            unsafe { *__errno() }
+        } else if #[cfg(any(target_vendor = "apple", target_os = "freebsd"))] {
+            unsafe { *__error() }
+        } else {
+            crate::failure::ENOMEM
+        }
+    }
+}
+
+/// Ask the kernel for rseq with arguments it always rejects: `EINVAL` means
+/// the syscall exists, `ENOSYS` that it does not.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub fn rseq_supported() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    const SYS_RSEQ: c_long = 334;
+    #[cfg(target_arch = "aarch64")]
+    const SYS_RSEQ: c_long = 293;
+    const ENOSYS: i32 = 38;
+    let ret = unsafe { syscall(SYS_RSEQ, core::ptr::null_mut::<c_void>(), 0u32, 0i32, 0u32) };
+    ret == -1 && unsafe { *__errno_location() } != ENOSYS
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+pub fn rseq_supported() -> bool {
+    false
+}
@@ -1,11 +1,13 @@
 //! Windows virtual memory implementation using VirtualAlloc/VirtualFree.
 
+use crate::config::PAGE_SIZE;
 use core::ffi::c_void;
 
 const MEM_COMMIT: u32 = 0x1000;
 const MEM_RESERVE: u32 = 0x2000;
 const MEM_RELEASE: u32 = 0x8000;
 const MEM_DECOMMIT: u32 = 0x4000;
+const PAGE_NOACCESS: u32 = 0x01;
 const PAGE_READWRITE: u32 = 0x04;
 
 // Windows allocation granularity is 64 KiB.
@@ -22,6 +24,38 @@ unsafe extern "system" {
 
     #[link_name = "VirtualFree"]
     fn virtual_free(lp_address: *mut c_void, dw_size: usize, dw_free_type: u32) -> i32;
+
+    #[link_name = "VirtualProtect"]
+    fn virtual_protect(
+        lp_address: *mut c_void,
+        dw_size: usize,
+        fl_new_protect: u32,
+        lpfl_old_protect: *mut u32,
+    ) -> i32;
+
+    #[link_name = "GetCurrentProcessId"]
+    fn get_current_process_id() -> u32;
+
+    #[link_name = "GetCurrentThreadId"]
+    fn get_current_thread_id() -> u32;
+
+    #[link_name = "GetLastError"]
+    fn get_last_error() -> u32;
+
+    #[link_name = "GetTickCount64"]
+    fn get_tick_count64() -> u64;
+
+    #[link_name = "GetStdHandle"]
+    fn get_std_handle(n_std_handle: u32) -> *mut c_void;
+
+    #[link_name = "WriteFile"]
+    fn write_file(
+        h_file: *mut c_void,
+        lp_buffer: *const c_void,
+        n_number_of_bytes_to_write: u32,
+        lp_number_of_bytes_written: *mut u32,
+        lp_overlapped: *mut c_void,
+    ) -> i32;
 }
 
 /// Round up to the next multiple of `align` (must be a power of 2).
@@ -32,6 +66,36 @@ const fn round_up(size: usize, align: usize) -> usize {
 
 pub unsafe fn page_alloc(size: usize) -> *mut u8 {
     let alloc_size = round_up(size, ALLOC_GRANULARITY);
+    if PAGE_SIZE > ALLOC_GRANULARITY {
+        // VirtualAlloc only aligns to the granularity, so pages larger than
+        // that need an aligned reservation committed in one go.
+        let ptr = unsafe { page_reserve(alloc_size, PAGE_SIZE) };
+        if ptr.is_null() {
+            return ptr;
+        }
+        let committed =
+            unsafe { virtual_alloc(ptr as *mut c_void, alloc_size, MEM_COMMIT, PAGE_READWRITE) };
+        if committed.is_null() {
+            unsafe { page_dealloc(ptr) };
+            return core::ptr::null_mut();
+        }
+        return ptr;
+    }
+    let hint = super::placement_hint(alloc_size);
+    if hint != 0 {
+        // Unlike mmap, VirtualAlloc fails instead of moving an occupied hint.
+        let ptr = unsafe {
+            virtual_alloc(
+                core::ptr::without_provenance_mut(hint),
+                alloc_size,
+                MEM_COMMIT | MEM_RESERVE,
+                PAGE_READWRITE,
+            )
+        };
+        if !ptr.is_null() {
+            return ptr as *mut u8;
+        }
+    }
     let ptr = unsafe {
         virtual_alloc(
             core::ptr::null_mut(),
@@ -43,6 +107,57 @@ pub unsafe fn page_alloc(size: usize) -> *mut u8 {
     ptr as *mut u8
 }
 
+/// `MEM_COMMIT` only charges commit; pages still fault on first touch.
+/// Touch every page so the faults happen here.
+pub unsafe fn page_alloc_populated(size: usize) -> *mut u8 {
+    let ptr = unsafe { page_alloc(size) };
+    if !ptr.is_null() {
+        unsafe { super::touch_pages(ptr, size) };
+    }
+    ptr
+}
+
+/// Reserve only: commit with `page_recommit` before use. An aligned range is
+/// found by reserving `size + align`, releasing it and reserving the aligned
+/// part, which another thread can take in between; retried a few times.
+pub unsafe fn page_reserve(size: usize, align: usize) -> *mut u8 {
+    let size = round_up(size, ALLOC_GRANULARITY);
+    for _ in 0..8 {
+        let raw = unsafe {
+            virtual_alloc(
+                core::ptr::null_mut(),
+                size + align,
+                MEM_RESERVE,
+                PAGE_READWRITE,
+            )
+        };
+        if raw.is_null() {
+            return core::ptr::null_mut();
+        }
+        unsafe { virtual_free(raw, 0, MEM_RELEASE) };
+        // The released range is only an address to ask for again.
+        let aligned = core::ptr::without_provenance_mut(round_up(raw.addr(), align));
+        let ptr = unsafe { virtual_alloc(aligned, size, MEM_RESERVE, PAGE_READWRITE) };
+        if !ptr.is_null() {
+            return ptr as *mut u8;
+        }
+    }
+    core::ptr::null_mut()
+}
+
+/// Reserve only, at exactly `base`; fails if any of the range is taken.
+pub unsafe fn page_reserve_at(base: usize, size: usize) -> *mut u8 {
+    let ptr = unsafe {
+        virtual_alloc(
+            core::ptr::without_provenance_mut(base),
+            round_up(size, ALLOC_GRANULARITY),
+            MEM_RESERVE,
+            PAGE_READWRITE,
+        )
+    };
+    ptr as *mut u8
+}
+
 pub unsafe fn page_dealloc(ptr: *mut u8) {
     // MEM_RELEASE requires dwSize = 0 (releases entire allocation)
     unsafe { virtual_free(ptr as *mut c_void, 0, MEM_RELEASE) };
@@ -55,3 +170,54 @@ pub unsafe fn page_decommit(ptr: *mut u8, size: usize) {
 pub unsafe fn page_recommit(ptr: *mut u8, size: usize) {
     unsafe { virtual_alloc(ptr as *mut c_void, size, MEM_COMMIT, PAGE_READWRITE) };
 }
+
+pub unsafe fn page_protect(ptr: *mut u8, size: usize, accessible: bool) -> bool {
+    let protect = if accessible {
+        PAGE_READWRITE
+    } else {
+        PAGE_NOACCESS
+    };
+    let mut old = 0;
+    unsafe { virtual_protect(ptr as *mut c_void, size, protect, &mut old) != 0 }
+}
+
+/// Decommit and recommit would zero the pages, but the recommit can fail
+/// under commit pressure and leave live memory inaccessible, so this is not
+/// used.
+pub unsafe fn page_zero(_ptr: *mut u8, _size: usize) -> bool {
+    false
+}
+
+pub fn process_id() -> u32 {
+    unsafe { get_current_process_id() }
+}
+
+pub fn thread_id() -> usize {
+    unsafe { get_current_thread_id() as usize }
+}
+
+pub fn now_ms() -> u64 {
+    unsafe { get_tick_count64() }
+}
+
+pub fn last_error() -> i32 {
+    unsafe { get_last_error() as i32 }
+}
+
+pub fn write_stderr(bytes: &[u8]) {
+    const STD_ERROR_HANDLE: u32 = -12i32 as u32;
+    let handle = unsafe { get_std_handle(STD_ERROR_HANDLE) };
+    if handle.is_null() || handle.addr() == usize::MAX {
+        return;
+    }
+    let mut written = 0;
+    unsafe {
+        write_file(
+            handle,
+            bytes.as_ptr().cast(),
+            bytes.len().min(u32::MAX as usize) as u32,
+            &mut written,
+            core::ptr::null_mut(),
+        )
+    };
+}
@@ -0,0 +1,272 @@
+//! Cache flushes on memory pressure (`pressure` feature, Linux only).
+//!
+//! Memory the allocator keeps cached for reuse is memory a container near
+//! its limit doesn't have: the kernel reclaims page cache from under the
+//! application, throttles it, and in the end the OOM killer takes the pod.
+//! A [`Monitor`] watches the kernel's pressure signals from a background
+//! thread and gives cached memory back when they fire. The process opts in
+//! once at startup:
+//!
+//! ```ignore
+//! let _monitor = rtmalloc::pressure::start(rtmalloc::pressure::Config::default())?;
+//! ```
+//!
+//! Two signals are read every [`Config::poll_interval`]:
+//!
+//! - PSI, the `memory.pressure` file of the process's cgroup (cgroup v2),
+//!   or `/proc/pressure/memory` outside one. It counts pressure when some
+//!   task was stalled on memory for at least [`Config::stall_ratio`] of
+//!   the interval.
+//! - The cgroup's `memory.events`. It counts pressure when the `high` or
+//!   `max` count went up: the cgroup was throttled at `memory.high` or
+//!   reclaimed at `memory.max`.
+//!
+//! On pressure the monitor calls [`relieve`], at most once per
+//! [`Config::min_interval`] so that a long episode doesn't turn into a
+//! flush loop that slows the application down further. Thread caches belong
+//! to their threads, so they are only asked to flush, and do so on their
+//! next slow path; per-CPU slabs (`percpu`) are drained on the spot.
+
+use crate::allocator::PAGE_HEAP;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+/// System-wide PSI file, used outside a cgroup v2.
+const SYSTEM_PSI: &str = "/proc/pressure/memory";
+
+/// Mount point of the cgroup v2 hierarchy.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Give back everything the allocator caches that it can reach from the
+/// calling thread, and return the bytes dropped from RSS.
+///
+/// Every thread cache is asked to flush on its next slow path (see
+/// [`crate::thread::request_flush_all`]), every CPU's slab is drained with
+/// `percpu`, transfer caches and parked mid-heap spans go back to the page
+/// heap, and the OS drops the page heap's free pages.
+pub fn relieve() -> usize {
+    crate::thread::request_flush_all();
+    #[cfg(feature = "percpu")]
+    {
+        use crate::allocator::{CENTRAL_CACHE, PAGE_MAP, TRANSFER_CACHE};
+        crate::cpu_cache::drain_all(&TRANSFER_CACHE, &CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP);
+    }
+    crate::allocator::flush_shared_caches();
+    unsafe { PAGE_HEAP.lock().release_free() }
+}
+
+/// What a [`Monitor`] watches and how often it may act.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// PSI file to read, or `None` to ignore PSI.
+    pub psi: Option<PathBuf>,
+    /// cgroup v2 `memory.events` file to read, or `None` to ignore it.
+    pub events: Option<PathBuf>,
+    /// Share of the poll interval that some task must have spent stalled
+    /// on memory for PSI to count as pressure.
+    pub stall_ratio: f64,
+    /// How often the files are read.
+    pub poll_interval: Duration,
+    /// Least time between two calls to [`relieve`].
+    pub min_interval: Duration,
+}
+
+impl Default for Config {
+    /// The files of the process's cgroup if it is in a cgroup v2, the
+    /// system-wide PSI file otherwise. Pressure at a 10% stall, polled
+    /// every second, relieved at most every 10 seconds.
+    fn default() -> Self {
+        let cgroup = own_cgroup();
+        let in_cgroup = |name: &str| {
+            cgroup
+                .as_ref()
+                .map(|dir| dir.join(name))
+                .filter(|path| path.exists())
+        };
+        Self {
+            psi: in_cgroup("memory.pressure")
+                .or_else(|| Some(PathBuf::from(SYSTEM_PSI)).filter(|path| path.exists())),
+            events: in_cgroup("memory.events"),
+            stall_ratio: 0.1,
+            poll_interval: Duration::from_secs(1),
+            min_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Directory of the process's cgroup v2, from the `0::` line of
+/// `/proc/self/cgroup`.
+fn own_cgroup() -> Option<PathBuf> {
+    let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let path = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+    Some(Path::new(CGROUP_ROOT).join(path.trim_start_matches('/')))
+}
+
+/// Microseconds some task has spent stalled on memory, from the `total=`
+/// field of the `some` line of a PSI file.
+fn parse_psi_total(psi: &str) -> Option<u64> {
+    psi.lines()
+        .find_map(|line| line.strip_prefix("some "))?
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("total="))?
+        .parse()
+        .ok()
+}
+
+/// Sum of the `high` and `max` counts of a `memory.events` file.
+fn parse_events(events: &str) -> Option<u64> {
+    let mut seen = false;
+    let mut sum = 0;
+    for line in events.lines() {
+        if let Some(("high" | "max", count)) = line.split_once(' ') {
+            sum += count.parse::<u64>().ok()?;
+            seen = true;
+        }
+    }
+    seen.then_some(sum)
+}
+
+fn read(path: &Option<PathBuf>, parse: fn(&str) -> Option<u64>) -> Option<u64> {
+    parse(&fs::read_to_string(path.as_ref()?).ok()?)
+}
+
+/// Readings of the last poll, to tell what changed since.
+struct Watch {
+    config: Config,
+    psi: Option<(u64, Instant)>,
+    events: Option<u64>,
+}
+
+impl Watch {
+    fn new(config: Config) -> Self {
+        let now = Instant::now();
+        Self {
+            psi: read(&config.psi, parse_psi_total).map(|total| (total, now)),
+            events: read(&config.events, parse_events),
+            config,
+        }
+    }
+
+    /// Read both files; true if either shows pressure since the last poll.
+    fn poll(&mut self) -> bool {
+        let now = Instant::now();
+        let mut pressure = false;
+        if let Some(total) = read(&self.config.psi, parse_psi_total) {
+            if let Some((last, at)) = self.psi {
+                let stalled = total.saturating_sub(last) as f64;
+                let elapsed = now.duration_since(at).as_micros().max(1) as f64;
+                pressure |= stalled / elapsed >= self.config.stall_ratio;
+            }
+            self.psi = Some((total, now));
+        }
+        if let Some(count) = read(&self.config.events, parse_events) {
+            pressure |= self.events.is_some_and(|last| count > last);
+            self.events = Some(count);
+        }
+        pressure
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    stop: AtomicBool,
+    reliefs: AtomicU64,
+    released_bytes: AtomicU64,
+}
+
+/// A running pressure monitor. Dropping it leaves the monitor running for
+/// the rest of the process.
+pub struct Monitor {
+    shared: Arc<Shared>,
+    thread: JoinHandle<()>,
+}
+
+impl Monitor {
+    /// How many times the monitor has called [`relieve`].
+    pub fn reliefs(&self) -> u64 {
+        self.shared.reliefs.load(Ordering::Relaxed)
+    }
+
+    /// Bytes those calls dropped from RSS.
+    pub fn released_bytes(&self) -> u64 {
+        self.shared.released_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Stop watching and wait for the monitor thread to exit.
+    pub fn stop(self) {
+        self.shared.stop.store(true, Ordering::Release);
+        self.thread.thread().unpark();
+        self.thread
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e));
+    }
+}
+
+/// Start watching in a background thread. Fails if neither file in
+/// `config` can be read and parsed.
+pub fn start(config: Config) -> io::Result<Monitor> {
+    let watch = Watch::new(config);
+    if watch.psi.is_none() && watch.events.is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no readable memory pressure file",
+        ));
+    }
+    let shared = Arc::new(Shared::default());
+    let monitor = Arc::clone(&shared);
+    let thread = std::thread::Builder::new()
+        .name("rtmalloc-pressure".into())
+        .spawn(move || run(watch, &monitor))?;
+    Ok(Monitor { shared, thread })
+}
+
+fn run(mut watch: Watch, shared: &Shared) {
+    let mut last_relief: Option<Instant> = None;
+    loop {
+        std::thread::park_timeout(watch.config.poll_interval);
+        if shared.stop.load(Ordering::Acquire) {
+            return;
+        }
+        if !watch.poll() {
+            continue;
+        }
+        let now = Instant::now();
+        if last_relief.is_some_and(|at| now.duration_since(at) < watch.config.min_interval) {
+            continue;
+        }
+        last_relief = Some(now);
+        let released = relieve();
+        shared.reliefs.fetch_add(1, Ordering::Relaxed);
+        shared
+            .released_bytes
+            .fetch_add(released as u64, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_psi_total() {
+        let psi = "some avg10=1.50 avg60=0.20 avg300=0.05 total=123456\n\
+                   full avg10=0.00 avg60=0.00 avg300=0.00 total=789\n";
+        assert_eq!(parse_psi_total(psi), Some(123456));
+        assert_eq!(parse_psi_total("full avg10=0.00 total=1\n"), None);
+        assert_eq!(parse_psi_total(""), None);
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let events = "low 4\nhigh 7\nmax 2\noom 0\noom_kill 0\n";
+        assert_eq!(parse_events(events), Some(9));
+        assert_eq!(parse_events("low 1\n"), None);
+        assert_eq!(parse_events("high x\nmax 1\n"), None);
+    }
+}
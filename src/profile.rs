@@ -0,0 +1,205 @@
+//! Heap snapshot diffs for leak hunting (`lifetime-histogram` feature).
+//!
+//! A [`snapshot`] copies the sampled objects the lifetime sampler
+//! ([`crate::lifetime`]) still holds as alive. Taken at two points in a
+//! long-running service, say before and after a thousand requests that
+//! should leave nothing behind, [`diff`] lists the objects of the second
+//! that were allocated since the first and are still not freed: whatever
+//! grows between the two, each sample standing for the interval it was
+//! taken at.
+//!
+//! ```ignore
+//! rtmalloc::lifetime::set_sample_interval(64);
+//! let before = rtmalloc::profile::snapshot();
+//! serve_requests(1000);
+//! let after = rtmalloc::profile::snapshot();
+//! rtmalloc::profile::diff(&before, &after).print_report();
+//! ```
+//!
+//! The samples carry no call stacks, so new objects are grouped by size
+//! class (class 0 for large allocations); [`Diff::samples`] keeps each
+//! one's address, size and age for a closer look. An object counts as the
+//! same in both snapshots when both its address and its allocation time
+//! match, so an address freed and handed out again in between is new.
+
+use crate::lifetime::{self, Sample};
+use crate::size_class::{self, NUM_SIZE_CLASSES};
+use core::fmt;
+use std::string::String;
+use std::vec::Vec;
+
+/// The sampled objects alive at one point in time.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    /// Samples ordered by address, then allocation time.
+    samples: Vec<Sample>,
+    /// Sample interval when taken.
+    interval: u32,
+    /// When taken, on the clock of [`Sample::born_ns`].
+    taken_ns: u64,
+}
+
+impl Snapshot {
+    /// Samples in the snapshot, ordered by address.
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    /// Sample interval in force when the snapshot was taken.
+    pub fn sample_interval(&self) -> u32 {
+        self.interval
+    }
+}
+
+/// Copy the sampled objects alive now. The copy is made into a buffer
+/// allocated up front, which is left out should it be sampled itself.
+pub fn snapshot() -> Snapshot {
+    let mut samples: Vec<Sample> = Vec::with_capacity(lifetime::TABLE_SLOTS);
+    let own = samples.as_ptr().addr();
+    lifetime::for_each_live(|s| {
+        if s.addr != own && samples.len() < samples.capacity() {
+            samples.push(s);
+        }
+    });
+    samples.sort_unstable_by_key(|s| (s.addr, s.born_ns));
+    Snapshot {
+        samples,
+        interval: lifetime::sample_interval(),
+        taken_ns: lifetime::now_ns(),
+    }
+}
+
+/// Objects of one size class allocated between two snapshots and still
+/// alive at the second.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClassGrowth {
+    /// Samples taken.
+    pub samples: u64,
+    /// Objects estimated from the samples.
+    pub objects: u64,
+    /// Requested bytes estimated from the samples.
+    pub bytes: u64,
+}
+
+/// What [`diff`] found: the new allocations not freed.
+#[derive(Clone, Debug)]
+pub struct Diff {
+    /// Growth per size class, class 0 for large allocations.
+    pub classes: [ClassGrowth; NUM_SIZE_CLASSES],
+    /// The new samples, oldest first.
+    pub samples: Vec<Sample>,
+    /// When the second snapshot was taken, to age the new samples by.
+    taken_ns: u64,
+}
+
+/// Samples in `after` that are not in `before`: objects allocated between
+/// the two snapshots and not freed by the second.
+pub fn diff(before: &Snapshot, after: &Snapshot) -> Diff {
+    let mut samples: Vec<Sample> = after
+        .samples
+        .iter()
+        .filter(|s| {
+            before
+                .samples
+                .binary_search_by_key(&(s.addr, s.born_ns), |b| (b.addr, b.born_ns))
+                .is_err()
+        })
+        .copied()
+        .collect();
+    samples.sort_unstable_by_key(|s| s.born_ns);
+    let mut classes = [ClassGrowth::default(); NUM_SIZE_CLASSES];
+    for s in &samples {
+        let growth = &mut classes[s.class];
+        growth.samples += 1;
+        growth.objects += s.weight as u64;
+        growth.bytes += s.size as u64 * s.weight as u64;
+    }
+    Diff {
+        classes,
+        samples,
+        taken_ns: after.taken_ns,
+    }
+}
+
+impl Diff {
+    /// Estimated objects across all classes.
+    pub fn objects(&self) -> u64 {
+        self.classes.iter().map(|c| c.objects).sum()
+    }
+
+    /// Estimated requested bytes across all classes.
+    pub fn bytes(&self) -> u64 {
+        self.classes.iter().map(|c| c.bytes).sum()
+    }
+
+    /// Write the growth of every class with new objects, most bytes first,
+    /// then the oldest new samples, to `out`.
+    pub fn write_report(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        writeln!(
+            out,
+            "\nNew allocations not freed: {} samples, ~{} objects, ~{} bytes\n",
+            self.samples.len(),
+            self.objects(),
+            self.bytes()
+        )?;
+        writeln!(
+            out,
+            "  {:>5}   {:>8}   {:>10}   {:>10}   {:>12}",
+            "Class", "Size", "Samples", "Objects", "Bytes"
+        )?;
+        let mut order: Vec<usize> = (0..NUM_SIZE_CLASSES)
+            .filter(|&c| self.classes[c].samples != 0)
+            .collect();
+        order.sort_by(|&a, &b| {
+            self.classes[b]
+                .bytes
+                .cmp(&self.classes[a].bytes)
+                .then(a.cmp(&b))
+        });
+        for cls in order {
+            let growth = &self.classes[cls];
+            writeln!(
+                out,
+                "  {:>5}   {:>8}   {:>10}   {:>10}   {:>12}",
+                cls,
+                class_size(cls),
+                growth.samples,
+                growth.objects,
+                growth.bytes,
+            )?;
+        }
+        if self.samples.is_empty() {
+            return Ok(());
+        }
+        writeln!(out, "\n  Oldest new samples:")?;
+        for s in self.samples.iter().take(OLDEST_SHOWN) {
+            writeln!(
+                out,
+                "  {:#018x}   {:>8} bytes   class {:>3}   age {} ns",
+                s.addr,
+                s.size,
+                s.class,
+                self.taken_ns.saturating_sub(s.born_ns),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Print [`write_report`](Self::write_report) to stdout.
+    pub fn print_report(&self) {
+        let mut text = String::new();
+        let _ = self.write_report(&mut text);
+        std::print!("{text}");
+    }
+}
+
+/// New samples listed one by one in a report.
+const OLDEST_SHOWN: usize = 10;
+
+fn class_size(cls: usize) -> String {
+    if cls == 0 {
+        "large".into()
+    } else {
+        std::format!("{}", size_class::class_to_size(cls))
+    }
+}
@@ -0,0 +1,145 @@
+//! Freed large spans held back inaccessible (`span-quarantine` feature).
+//!
+//! For test runs: a large allocation's span is not handed back to the mid-
+//! or page heap when freed. Its pages are made inaccessible (`PROT_NONE`,
+//! `PAGE_NOACCESS` on Windows) and it waits in a FIFO of the last
+//! [`window`] freed spans; only when newer frees push it out is it made
+//! accessible again and returned to the page heap. Any load or store
+//! through a dangling pointer into it in the meantime faults at once, at
+//! the use, instead of reading or corrupting whatever the pages went to
+//! next:
+//!
+//! ```ignore
+//! rtmalloc::quarantine::set_window(1024);
+//! run_tests();
+//! rtmalloc::quarantine::flush();
+//! ```
+//!
+//! Only spans of large allocations (above the small classes) are
+//! quarantined; small objects share their span with live ones and go back
+//! to the caches as usual. A held span is marked [`SpanState::Cached`], like
+//! one parked in the mid-heap, so the page heap never coalesces into it and
+//! a second free of the same pointer is rejected. The window costs address
+//! space, not memory: the pages stay mapped but are not touched, and each
+//! held span is one more kernel mapping.
+//!
+//! [`SpanState::Cached`]: crate::span::SpanState::Cached
+
+use crate::allocator::PAGE_HEAP;
+use crate::platform;
+use crate::span::{Span, SpanState};
+use crate::sync::SpinMutex;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Most spans the window can hold.
+pub const MAX_WINDOW: usize = 4096;
+
+/// Spans held by default.
+const DEFAULT_WINDOW: usize = 256;
+
+static WINDOW: AtomicUsize = AtomicUsize::new(DEFAULT_WINDOW);
+
+/// The held spans, oldest at `head`.
+struct Ring {
+    spans: [*mut Span; MAX_WINDOW],
+    head: usize,
+    len: usize,
+}
+
+// SAFETY: the spans are only touched under the ring's lock or after being
+// taken out of it.
+unsafe impl Send for Ring {}
+
+static RING: SpinMutex<Ring> = SpinMutex::new(Ring {
+    spans: [ptr::null_mut(); MAX_WINDOW],
+    head: 0,
+    len: 0,
+});
+
+impl Ring {
+    fn push(&mut self, span: *mut Span) {
+        self.spans[(self.head + self.len) % MAX_WINDOW] = span;
+        self.len += 1;
+    }
+
+    /// The oldest span if more than `keep` are held.
+    fn pop_over(&mut self, keep: usize) -> Option<*mut Span> {
+        if self.len <= keep {
+            return None;
+        }
+        let span = self.spans[self.head];
+        self.head = (self.head + 1) % MAX_WINDOW;
+        self.len -= 1;
+        Some(span)
+    }
+}
+
+/// Hold up to `spans` freed large spans, at most [`MAX_WINDOW`]; 0 turns
+/// the quarantine off. Spans past a smaller window are released at once.
+pub fn set_window(spans: usize) {
+    WINDOW.store(spans.min(MAX_WINDOW), Ordering::Relaxed);
+    trim(spans.min(MAX_WINDOW));
+}
+
+/// Freed large spans held at most.
+pub fn window() -> usize {
+    WINDOW.load(Ordering::Relaxed)
+}
+
+/// Freed large spans held now.
+pub fn held() -> usize {
+    RING.lock().len
+}
+
+/// Release every held span to the page heap.
+pub fn flush() {
+    trim(0);
+}
+
+/// Release the oldest spans until at most `keep` are held.
+fn trim(keep: usize) {
+    loop {
+        let span = RING.lock().pop_over(keep);
+        match span {
+            Some(span) => unsafe { release(span) },
+            None => return,
+        }
+    }
+}
+
+/// Take the freed large `span` into the quarantine, making its pages
+/// inaccessible, and release the oldest one if the window is full. Returns
+/// false, leaving the span alone, when the quarantine is off.
+///
+/// # Safety
+///
+/// `span` must be a live large span whose allocation was just freed.
+pub(crate) unsafe fn hold(span: *mut Span) -> bool {
+    let window = window();
+    if window == 0 {
+        return false;
+    }
+    unsafe {
+        (*span).state = SpanState::Cached;
+        platform::page_protect((*span).start_addr(), (*span).byte_size(), false);
+    }
+    let oldest = {
+        let mut ring = RING.lock();
+        let oldest = ring.pop_over(window - 1);
+        ring.push(span);
+        oldest
+    };
+    if let Some(oldest) = oldest {
+        unsafe { release(oldest) };
+    }
+    true
+}
+
+/// Make a held span accessible again and free it to the page heap.
+unsafe fn release(span: *mut Span) {
+    unsafe {
+        platform::page_protect((*span).start_addr(), (*span).byte_size(), true);
+        PAGE_HEAP.lock().deallocate_span(span);
+    }
+}
@@ -0,0 +1,404 @@
+//! Built-in check that the allocator works where it was linked.
+//!
+//! [`selftest`] runs every tier — the thread or per-CPU cache, the transfer
+//! cache, the central free lists, the page heap, large and over-aligned
+//! allocations and realloc across them — on blocks filled with a canary
+//! pattern, and reports which tiers handed out memory that was null,
+//! misaligned, overlapping or not zeroed. Meant for a first run in an unusual
+//! environment (prelinked, static-pie, a container without rseq) before
+//! trusting it with real work.
+//!
+//! ```ignore
+//! let report = rtmalloc::selftest();
+//! assert!(report.passed(), "{report}");
+//! ```
+
+use crate::allocator::RtMalloc;
+use crate::config::PAGE_SIZE;
+use crate::size_class::{self, MAX_SMALL_SIZE, NUM_SIZE_CLASSES};
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ptr;
+
+/// One part of the allocator the self-test exercises.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Check {
+    /// Same-class objects freed and taken again from the thread or per-CPU
+    /// cache.
+    Cache,
+    /// Batches handed to the transfer cache by a cache flush and taken back.
+    Transfer,
+    /// One live object of every small size class at once.
+    Central,
+    /// Multi-page allocations just above the largest size class.
+    PageHeap,
+    /// Allocations of several MiB.
+    Large,
+    /// Alignments from 16 bytes up to well past `PAGE_SIZE`.
+    OverAligned,
+    /// Contents kept by realloc from small to page-heap to large sizes and
+    /// back.
+    Realloc,
+    /// `alloc_zeroed` on memory that was dirtied and freed.
+    Zeroed,
+}
+
+impl Check {
+    /// Every check, in the order they run.
+    pub const ALL: [Check; 8] = [
+        Check::Cache,
+        Check::Transfer,
+        Check::Central,
+        Check::PageHeap,
+        Check::Large,
+        Check::OverAligned,
+        Check::Realloc,
+        Check::Zeroed,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Check::Cache => "cache",
+            Check::Transfer => "transfer",
+            Check::Central => "central",
+            Check::PageHeap => "page heap",
+            Check::Large => "large",
+            Check::OverAligned => "over-aligned",
+            Check::Realloc => "realloc",
+            Check::Zeroed => "zeroed",
+        }
+    }
+}
+
+/// How a check failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// An allocation returned null.
+    OutOfMemory,
+    /// A pointer did not have the requested alignment.
+    Misaligned,
+    /// A block's canary changed while it was live: two blocks overlap, or
+    /// realloc lost the contents.
+    Corrupted,
+    /// `alloc_zeroed` returned memory that was not zero.
+    NotZeroed,
+}
+
+impl Fault {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Fault::OutOfMemory => "out of memory",
+            Fault::Misaligned => "misaligned",
+            Fault::Corrupted => "canary corrupted",
+            Fault::NotZeroed => "not zeroed",
+        }
+    }
+}
+
+/// What [`selftest`] found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Report {
+    /// Outcome of each check, in [`Check::ALL`] order.
+    pub results: [(Check, Result<(), Fault>); Check::ALL.len()],
+    /// Whether the caches were bypassed (see
+    /// [`debug::bypass_caches`](crate::debug::bypass_caches)), in which case
+    /// the cache and transfer checks only reached the central lists.
+    pub caches_bypassed: bool,
+}
+
+impl Report {
+    /// Whether every check passed.
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|(_, r)| r.is_ok())
+    }
+
+    /// The checks that failed, and how.
+    pub fn failures(&self) -> impl Iterator<Item = (Check, Fault)> + '_ {
+        self.results
+            .iter()
+            .filter_map(|&(c, r)| r.err().map(|f| (c, f)))
+    }
+
+    /// Bit `i` set if `Check::ALL[i]` failed; 0 if all passed.
+    pub fn failed_mask(&self) -> u32 {
+        self.results
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, r))| r.is_err())
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    }
+}
+
+/// One line per check.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "rtmalloc {} selftest: {} (features: {}{})",
+            crate::version::VERSION,
+            if self.passed() { "passed" } else { "FAILED" },
+            crate::version::FEATURES,
+            if self.caches_bypassed {
+                "; caches bypassed"
+            } else {
+                ""
+            }
+        )?;
+        for &(check, result) in &self.results {
+            match result {
+                Ok(()) => writeln!(f, "  {:<14} ok", check.name())?,
+                Err(fault) => writeln!(f, "  {:<14} FAILED: {}", check.name(), fault.name())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run every check and report the outcome.
+///
+/// Allocates and frees a few tens of MiB through [`RtMalloc`] directly,
+/// whether or not it is the global allocator, and flushes the calling
+/// thread's cache along the way. The report itself is built without allocating. A failed
+/// check frees what it still holds and the rest still run, though memory a
+/// broken tier handed out twice may then be freed twice.
+pub fn selftest() -> Report {
+    let mut results = Check::ALL.map(|c| (c, Ok(())));
+    for (check, result) in &mut results {
+        *result = match check {
+            Check::Cache => check_cache(),
+            Check::Transfer => check_transfer(),
+            Check::Central => check_central(),
+            Check::PageHeap => check_page_heap(),
+            Check::Large => check_large(),
+            Check::OverAligned => check_over_aligned(),
+            Check::Realloc => check_realloc(),
+            Check::Zeroed => check_zeroed(),
+        };
+    }
+    Report {
+        results,
+        caches_bypassed: crate::debug::caches_bypassed(),
+    }
+}
+
+/// Most blocks a check holds live at once.
+const MAX_LIVE: usize = 256;
+
+/// Canary byte `i` of a block filled with `seed`. Depends only on the seed
+/// and offset, so it survives realloc moving the block.
+#[inline]
+fn canary(seed: u8, i: usize) -> u8 {
+    seed.wrapping_add(i as u8) ^ (i >> 8) as u8
+}
+
+unsafe fn fill(ptr: *mut u8, len: usize, seed: u8) {
+    for i in 0..len {
+        unsafe { ptr.add(i).write(canary(seed, i)) };
+    }
+}
+
+unsafe fn intact(ptr: *const u8, len: usize, seed: u8) -> bool {
+    (0..len).all(|i| unsafe { ptr.add(i).read() } == canary(seed, i))
+}
+
+/// Blocks a check holds live, each filled with its own seed. Whatever is
+/// still held is freed on drop, however the check ends.
+struct Blocks {
+    live: [(*mut u8, Layout, u8); MAX_LIVE],
+    len: usize,
+}
+
+impl Blocks {
+    fn new() -> Self {
+        Self {
+            live: [(ptr::null_mut(), Layout::new::<u8>(), 0); MAX_LIVE],
+            len: 0,
+        }
+    }
+
+    /// Allocate a block, check its alignment and fill it with `seed`.
+    fn alloc(&mut self, layout: Layout, seed: u8) -> Result<*mut u8, Fault> {
+        let ptr = unsafe { RtMalloc.alloc(layout) };
+        self.hold(ptr, layout, seed)?;
+        unsafe { fill(ptr, layout.size(), seed) };
+        Ok(ptr)
+    }
+
+    /// Allocate a zeroed block, check it, then fill it with `seed`.
+    fn alloc_zeroed(&mut self, layout: Layout, seed: u8) -> Result<*mut u8, Fault> {
+        let ptr = unsafe { RtMalloc.alloc_zeroed(layout) };
+        self.hold(ptr, layout, seed)?;
+        if (0..layout.size()).any(|i| unsafe { ptr.add(i).read() } != 0) {
+            return Err(Fault::NotZeroed);
+        }
+        unsafe { fill(ptr, layout.size(), seed) };
+        Ok(ptr)
+    }
+
+    fn hold(&mut self, ptr: *mut u8, layout: Layout, seed: u8) -> Result<(), Fault> {
+        if ptr.is_null() {
+            return Err(Fault::OutOfMemory);
+        }
+        debug_assert!(self.len < MAX_LIVE);
+        self.live[self.len] = (ptr, layout, seed);
+        self.len += 1;
+        if !ptr.addr().is_multiple_of(layout.align()) {
+            return Err(Fault::Misaligned);
+        }
+        Ok(())
+    }
+
+    /// Check every live block's canary.
+    fn verify(&self) -> Result<(), Fault> {
+        for &(ptr, layout, seed) in &self.live[..self.len] {
+            if !unsafe { intact(ptr, layout.size(), seed) } {
+                return Err(Fault::Corrupted);
+            }
+        }
+        Ok(())
+    }
+
+    /// Verify every block, then free them all.
+    fn free_all(&mut self) -> Result<(), Fault> {
+        let verified = self.verify();
+        self.release();
+        verified
+    }
+
+    fn release(&mut self) {
+        for &(ptr, layout, _) in &self.live[..self.len] {
+            unsafe { RtMalloc.dealloc(ptr, layout) };
+        }
+        self.len = 0;
+    }
+}
+
+impl Drop for Blocks {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// Allocate `count` blocks of `layout`, free them, and allocate them again,
+/// flushing the thread's cache in between if `flush`.
+fn churn(layout: Layout, count: usize, flush: bool) -> Result<(), Fault> {
+    let count = count.min(MAX_LIVE);
+    let mut blocks = Blocks::new();
+    for round in 0..2u8 {
+        for i in 0..count {
+            blocks.alloc(layout, round.wrapping_mul(97).wrapping_add(i as u8))?;
+        }
+        blocks.free_all()?;
+        if flush {
+            crate::thread::flush_current_cache();
+        }
+    }
+    Ok(())
+}
+
+fn check_cache() -> Result<(), Fault> {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let cls = size_class::layout_to_class(64, 8);
+    churn(layout, 2 * size_class::batch_size(cls), false)
+}
+
+fn check_transfer() -> Result<(), Fault> {
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let cls = size_class::layout_to_class(256, 8);
+    churn(layout, 8 * size_class::batch_size(cls), true)
+}
+
+fn check_central() -> Result<(), Fault> {
+    let mut blocks = Blocks::new();
+    for cls in 1..NUM_SIZE_CLASSES.min(MAX_LIVE) {
+        let layout = Layout::from_size_align(size_class::class_to_size(cls), 8).unwrap();
+        blocks.alloc(layout, cls as u8)?;
+    }
+    blocks.free_all()
+}
+
+fn check_page_heap() -> Result<(), Fault> {
+    let mut blocks = Blocks::new();
+    let sizes = [
+        MAX_SMALL_SIZE + 1,
+        2 * MAX_SMALL_SIZE,
+        MAX_SMALL_SIZE + 5 * PAGE_SIZE + 1,
+    ];
+    for (i, size) in sizes.into_iter().enumerate() {
+        blocks.alloc(Layout::from_size_align(size, 8).unwrap(), i as u8)?;
+    }
+    blocks.free_all()
+}
+
+fn check_large() -> Result<(), Fault> {
+    let mut blocks = Blocks::new();
+    for (i, size) in [1 << 20, 4 << 20, (8 << 20) + 123].into_iter().enumerate() {
+        blocks.alloc(Layout::from_size_align(size, 8).unwrap(), i as u8)?;
+    }
+    blocks.free_all()
+}
+
+fn check_over_aligned() -> Result<(), Fault> {
+    let mut blocks = Blocks::new();
+    let aligns = [16, 64, 4096, PAGE_SIZE, 4 * PAGE_SIZE, 1 << 20];
+    let mut seed = 0u8;
+    for align in aligns {
+        for size in [8, 1000, 3 * PAGE_SIZE] {
+            seed = seed.wrapping_add(1);
+            blocks.alloc(Layout::from_size_align(size, align).unwrap(), seed)?;
+        }
+    }
+    blocks.free_all()
+}
+
+fn check_realloc() -> Result<(), Fault> {
+    let sizes = [
+        24,
+        200,
+        4000,
+        MAX_SMALL_SIZE + 1,
+        3 << 20,
+        MAX_SMALL_SIZE + 1,
+        100,
+        8,
+    ];
+    let seed = 0x5a;
+    let mut blocks = Blocks::new();
+    let mut ptr = blocks.alloc(Layout::from_size_align(sizes[0], 8).unwrap(), seed)?;
+    for &new_size in &sizes[1..] {
+        let layout = blocks.live[0].1;
+        let new = unsafe { RtMalloc.realloc(ptr, layout, new_size) };
+        if new.is_null() {
+            return Err(Fault::OutOfMemory);
+        }
+        ptr = new;
+        blocks.live[0] = (ptr, Layout::from_size_align(new_size, 8).unwrap(), seed);
+        if !unsafe { intact(ptr, layout.size().min(new_size), seed) } {
+            return Err(Fault::Corrupted);
+        }
+        unsafe { fill(ptr, new_size, seed) };
+    }
+    blocks.free_all()
+}
+
+fn check_zeroed() -> Result<(), Fault> {
+    let small = Layout::from_size_align(512, 8).unwrap();
+    let large = Layout::from_size_align(2 << 20, 8).unwrap();
+    let mut blocks = Blocks::new();
+    for round in 0..2 {
+        for i in 0..32 {
+            if round == 0 {
+                blocks.alloc(small, i)?;
+            } else {
+                blocks.alloc_zeroed(small, i)?;
+            }
+        }
+        if round == 0 {
+            blocks.alloc(large, 0xff)?;
+        } else {
+            blocks.alloc_zeroed(large, 0xff)?;
+        }
+        blocks.free_all()?;
+    }
+    Ok(())
+}
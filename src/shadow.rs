@@ -0,0 +1,326 @@
+//! Shadow allocator for tests (`testing` + `std`).
+//!
+//! [`ShadowMalloc`] wraps [`RtMalloc`] and keeps its own record of every
+//! allocation — address, layout and the size class the page map gave it — in
+//! a `std` `HashMap` behind a mutex. The map's own memory comes from the
+//! system allocator, so the bookkeeping never runs through the allocator it
+//! is checking.
+//!
+//! Every free is checked against the record: the pointer must be live, the
+//! layout must be the one it was allocated with, and the page map must still
+//! file it under the same class (or, for large allocations, under a live
+//! span covering it). A pointer handed out while still live is caught at
+//! allocation. Problems are recorded as [`Violation`]s rather than panicking
+//! inside the allocator, and a bad free is not passed on, so the heap stays
+//! usable for the rest of the run.
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static GLOBAL: rtmalloc::shadow::ShadowMalloc = rtmalloc::shadow::ShadowMalloc;
+//!
+//! // ... run a workload ...
+//! assert_eq!(rtmalloc::shadow::take_violations(), []);
+//! ```
+
+use crate::allocator::{PAGE_MAP, RtMalloc};
+use crate::bootstrap;
+use crate::config::PAGE_SHIFT;
+use crate::size_class;
+use crate::span::SpanState;
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+use core::ptr;
+use std::alloc::System;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::vec::Vec;
+
+/// Something the shadow record disagrees with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// A pointer was handed out while an earlier allocation of it was live.
+    Reused { ptr: usize, size: usize },
+    /// Free of a pointer that was never allocated.
+    UnknownFree { ptr: usize },
+    /// Second free of the same allocation.
+    DoubleFree { ptr: usize },
+    /// Free with a layout other than the one the pointer was allocated with.
+    LayoutMismatch {
+        ptr: usize,
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    /// The page map files the pointer under a different size class than the
+    /// layout (at allocation) or the record (at free) says.
+    ClassMismatch {
+        ptr: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A large pointer with no live span covering it.
+    BadSpan { ptr: usize },
+}
+
+#[derive(Clone, Copy)]
+enum Entry {
+    Live {
+        size: usize,
+        align: usize,
+        class: usize,
+    },
+    Freed,
+}
+
+struct Shadow {
+    entries: HashMap<usize, Entry>,
+    violations: Vec<Violation>,
+}
+
+static SHADOW: Mutex<Option<Shadow>> = Mutex::new(None);
+
+std::thread_local! {
+    /// Set while this thread is inside the shadow bookkeeping; allocations
+    /// made then are the map's own and go to the system allocator.
+    static INTERNAL: Cell<bool> = const { Cell::new(false) };
+}
+
+/// `RtMalloc` with every allocation checked against a shadow record.
+pub struct ShadowMalloc;
+
+/// Whether this thread is in the bookkeeping. A thread whose locals are
+/// gone is treated as outside it.
+fn internal() -> bool {
+    INTERNAL.try_with(Cell::get).unwrap_or(false)
+}
+
+/// Run `f` on the shadow state with the thread marked internal, or return
+/// None if the thread cannot be marked.
+fn with_shadow<R>(f: impl FnOnce(&mut Shadow) -> R) -> Option<R> {
+    INTERNAL
+        .try_with(|flag| {
+            flag.set(true);
+            let mut guard = SHADOW.lock().unwrap_or_else(|e| e.into_inner());
+            let shadow = guard.get_or_insert_with(|| Shadow {
+                entries: HashMap::new(),
+                violations: Vec::new(),
+            });
+            let r = f(shadow);
+            drop(guard);
+            flag.set(false);
+            r
+        })
+        .ok()
+}
+
+/// Whether an allocation is recorded: not zero-sized, and not served from
+/// the bootstrap arena to a call made from inside the allocator.
+fn tracked(ptr: *mut u8, size: usize) -> bool {
+    size != 0 && !ptr.is_null() && !bootstrap::owns(ptr)
+}
+
+/// Size class the page map reports for `ptr`, or a violation if a large
+/// pointer has no live span.
+fn page_map_class(ptr: *mut u8) -> Result<usize, Violation> {
+    let page_id = (ptr as usize) >> PAGE_SHIFT;
+    let class = PAGE_MAP.size_class(page_id);
+    if class != 0 {
+        return Ok(class);
+    }
+    let span = PAGE_MAP.get(page_id);
+    let live =
+        !span.is_null() && unsafe { (*span).state == SpanState::InUse && (*span).contains(ptr) };
+    if live {
+        Ok(0)
+    } else {
+        Err(Violation::BadSpan { ptr: ptr as usize })
+    }
+}
+
+impl Shadow {
+    /// Record a fresh allocation. `strict` requires the page map class to be
+    /// the layout's own; after a realloc that stayed in place it may be
+    /// larger.
+    fn record_alloc(&mut self, ptr: *mut u8, layout: Layout, strict: bool) {
+        let addr = ptr as usize;
+        let expected = size_class::layout_to_class(layout.size(), layout.align());
+        let class = match page_map_class(ptr) {
+            Ok(found) => {
+                if strict && found != expected {
+                    self.violations.push(Violation::ClassMismatch {
+                        ptr: addr,
+                        expected,
+                        found,
+                    });
+                }
+                found
+            }
+            Err(v) => {
+                self.violations.push(v);
+                expected
+            }
+        };
+        let entry = Entry::Live {
+            size: layout.size(),
+            align: layout.align(),
+            class,
+        };
+        if let Some(Entry::Live { size, .. }) = self.entries.insert(addr, entry) {
+            self.violations.push(Violation::Reused { ptr: addr, size });
+        }
+    }
+
+    /// Check a free against the record, marking the entry freed if it holds.
+    fn check_free(&mut self, ptr: *mut u8, layout: Layout) -> Result<(), Violation> {
+        let addr = ptr as usize;
+        let (size, align, class) = match self.entries.get(&addr) {
+            Some(&Entry::Live { size, align, class }) => (size, align, class),
+            Some(Entry::Freed) => return Err(Violation::DoubleFree { ptr: addr }),
+            None => return Err(Violation::UnknownFree { ptr: addr }),
+        };
+        if (size, align) != (layout.size(), layout.align()) {
+            return Err(Violation::LayoutMismatch {
+                ptr: addr,
+                expected: (size, align),
+                found: (layout.size(), layout.align()),
+            });
+        }
+        let found = page_map_class(ptr)?;
+        if found != class {
+            return Err(Violation::ClassMismatch {
+                ptr: addr,
+                expected: class,
+                found,
+            });
+        }
+        self.entries.insert(addr, Entry::Freed);
+        Ok(())
+    }
+
+    /// Check a free, recording any violation. True if it may be passed on.
+    fn free(&mut self, ptr: *mut u8, layout: Layout) -> bool {
+        let r = self.check_free(ptr, layout);
+        if let Err(v) = r {
+            self.violations.push(v);
+        }
+        r.is_ok()
+    }
+}
+
+unsafe impl GlobalAlloc for ShadowMalloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if internal() {
+            return unsafe { System.alloc(layout) };
+        }
+        let ptr = unsafe { RtMalloc.alloc(layout) };
+        if tracked(ptr, layout.size()) {
+            with_shadow(|s| s.record_alloc(ptr, layout, true));
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if internal() {
+            return unsafe { System.alloc_zeroed(layout) };
+        }
+        let ptr = unsafe { RtMalloc.alloc_zeroed(layout) };
+        if tracked(ptr, layout.size()) {
+            with_shadow(|s| s.record_alloc(ptr, layout, true));
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if internal() {
+            return unsafe { System.dealloc(ptr, layout) };
+        }
+        if tracked(ptr, layout.size()) && !with_shadow(|s| s.free(ptr, layout)).unwrap_or(true) {
+            return;
+        }
+        unsafe { RtMalloc.dealloc(ptr, layout) };
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if internal() {
+            return unsafe { System.realloc(ptr, layout, new_size) };
+        }
+        if tracked(ptr, layout.size()) && !with_shadow(|s| s.free(ptr, layout)).unwrap_or(true) {
+            // Passing a bad pointer on would corrupt the heap; fail instead.
+            return ptr::null_mut();
+        }
+        let new = unsafe { RtMalloc.realloc(ptr, layout, new_size) };
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        with_shadow(|s| {
+            if new.is_null() {
+                // Failed: the old allocation is still live.
+                if tracked(ptr, layout.size()) {
+                    s.record_alloc(ptr, layout, false);
+                }
+            } else if tracked(new, new_size) {
+                s.record_alloc(new, new_layout, false);
+            }
+        });
+        new
+    }
+}
+
+/// Allocations the shadow record holds as live.
+pub fn live_allocations() -> usize {
+    with_shadow(|s| {
+        s.entries
+            .values()
+            .filter(|e| matches!(e, Entry::Live { .. }))
+            .count()
+    })
+    .unwrap_or(0)
+}
+
+/// Violations recorded since the last call, oldest first.
+pub fn take_violations() -> Vec<Violation> {
+    // Taken out under the internal flag, so the system-allocated list is
+    // also dropped under it; the copy returned comes from the allocator.
+    let Some(taken) = with_shadow(|s| core::mem::take(&mut s.violations)) else {
+        return Vec::new();
+    };
+    let out = taken.clone();
+    let _ = INTERNAL.try_with(|flag| {
+        flag.set(true);
+        drop(taken);
+        flag.set(false);
+    });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_misuse() {
+        let shadow = ShadowMalloc;
+        let layout = Layout::from_size_align(48, 8).unwrap();
+        unsafe {
+            let p = shadow.alloc(layout);
+            assert!(!p.is_null());
+            let wrong = Layout::from_size_align(64, 8).unwrap();
+            shadow.dealloc(p, wrong);
+            shadow.dealloc(p, layout);
+            shadow.dealloc(p, layout);
+
+            let q = shadow.alloc(layout);
+            let r = shadow.realloc(q, layout, 4096);
+            assert!(!r.is_null());
+            shadow.dealloc(r, Layout::from_size_align(4096, 8).unwrap());
+
+            let big = Layout::from_size_align(1 << 20, 8).unwrap();
+            let b = shadow.alloc(big);
+            shadow.dealloc(b, big);
+            shadow.dealloc(b, big);
+        }
+
+        let v = take_violations();
+        assert!(matches!(v[0], Violation::LayoutMismatch { .. }));
+        assert!(matches!(v[1], Violation::DoubleFree { .. }));
+        assert!(matches!(v[2], Violation::DoubleFree { .. }));
+        assert_eq!(v.len(), 3);
+    }
+}
@@ -3,18 +3,27 @@
 //! Objects are bucketed into size classes to reduce fragmentation and enable
 //! free list management. The table is configured via a TOML file at build time
 //! (see `default_classes.toml` and the `RTMALLOC_CLASSES` env var).
+//!
+//! How sizes map onto the table is a [`ClassMap`]. The allocator uses
+//! [`Classes`], picked by the `class_map` config option from the maps in
+//! this module, and calls it statically: the functions here are thin
+//! wrappers over that one type.
 
-use crate::config::PAGE_SIZE;
+use crate::config::{PAGE_SHIFT, PAGE_SIZE, SPAN_PAGES_SCALE};
+use core::sync::atomic::{AtomicU8, AtomicU32, Ordering};
 
 /// Information about a single size class.
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct SizeClassInfo {
     /// Allocation size for this class (bytes). All allocations in this class
     /// are rounded up to this size.
     pub size: usize,
     /// Number of pages per span for this class.
     pub pages: usize,
-    /// Number of objects to transfer between thread cache and central cache at once.
+    /// Number of objects to transfer between thread cache and central cache at
+    /// once, as built. The value in effect is [`batch_size`], which can be
+    /// lowered at runtime.
     pub batch_size: usize,
 }
 
@@ -25,20 +34,80 @@ impl SizeClassInfo {
 }
 
 // Generated by build.rs from TOML config. Defines:
-//   pub const NUM_SIZE_CLASSES: usize
-//   pub const MAX_SMALL_SIZE: usize
-//   pub static SIZE_CLASSES: [SizeClassInfo; NUM_SIZE_CLASSES]
+//   pub type Classes = <the ClassMap named by `class_map`>
+//   pub static SIZE_CLASSES: [SizeClassInfo; N]
 include!(concat!(env!("OUT_DIR"), "/size_class_gen.rs"));
 
-pub const NUM_SIZE_CLASSES: usize = SIZE_CLASSES.len();
-pub const MAX_SMALL_SIZE: usize = SIZE_CLASSES[NUM_SIZE_CLASSES - 1].size;
+/// Mapping between allocation sizes and size classes.
+///
+/// Public so tools can query the maps, but sealed: the allocator is built
+/// around one map chosen at build time, so only the types in this module
+/// can implement it. To try another mapping, add the type here, name it in
+/// `build.rs`'s `class_map_type`, and select it with `class_map`. Every
+/// allocator tier goes through [`Classes`], so nothing else changes.
+/// Implementations are plain types with static methods, so the choice costs
+/// nothing on the hot path.
+pub trait ClassMap: sealed::Sealed {
+    /// Class table indexed by class. Entry 0 is the sentinel for
+    /// allocations served by the page heap; sizes are strictly increasing
+    /// multiples of 8 after it.
+    const CLASSES: &'static [SizeClassInfo];
+
+    /// Whether this map can serve [`Self::CLASSES`]. Checked at compile time
+    /// for [`Classes`].
+    const VALID: bool = true;
+
+    /// Smallest class whose size is at least `size`: 1 for 0, and 0 above
+    /// the largest class.
+    fn size_to_class(size: usize) -> usize;
+
+    /// Allocation size of `cls`.
+    #[inline]
+    fn class_to_size(cls: usize) -> usize {
+        Self::CLASSES[cls].size
+    }
+
+    /// Pages per span of `cls`.
+    #[inline]
+    fn class_pages(cls: usize) -> usize {
+        Self::CLASSES[cls].pages
+    }
+
+    /// Objects per batch of `cls`, as built.
+    #[inline]
+    fn class_batch(cls: usize) -> usize {
+        Self::CLASSES[cls].batch_size
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::LookupClassMap {}
+    impl Sealed for super::PowerOfTwoClassMap {}
+}
+
+const _: () = assert!(
+    <Classes as ClassMap>::VALID,
+    "size class table does not fit the selected class_map"
+);
+
+pub const NUM_SIZE_CLASSES: usize = <Classes as ClassMap>::CLASSES.len();
+pub const MAX_SMALL_SIZE: usize = <Classes as ClassMap>::CLASSES[NUM_SIZE_CLASSES - 1].size;
+
+/// The default [`ClassMap`]: a byte table for sizes up to 1 KiB and a
+/// linear scan of the larger classes. Works with any table.
+pub struct LookupClassMap;
+
+const TABLE_LEN: usize = SIZE_CLASSES.len();
+const TABLE_MAX_SIZE: usize = SIZE_CLASSES[TABLE_LEN - 1].size;
 
 /// First class index with size > 1024 (skip point for the linear scan).
 /// Maximum size covered by the fast lookup table.
 /// Capped at 1024 to keep the table small; sizes above this use linear scan.
 const SMALL_LOOKUP_MAX: usize = const {
-    if MAX_SMALL_SIZE < 1024 {
-        MAX_SMALL_SIZE
+    if TABLE_MAX_SIZE < 1024 {
+        TABLE_MAX_SIZE
     } else {
         1024
     }
@@ -50,7 +119,7 @@ const SMALL_LOOKUP_LEN: usize = SMALL_LOOKUP_MAX / 8 + 1;
 /// First class index with size > SMALL_LOOKUP_MAX (start of linear scan).
 const FIRST_CLASS_ABOVE_LOOKUP: usize = const {
     let mut cls = 0;
-    while cls < NUM_SIZE_CLASSES && SIZE_CLASSES[cls].size <= SMALL_LOOKUP_MAX {
+    while cls < TABLE_LEN && SIZE_CLASSES[cls].size <= SMALL_LOOKUP_MAX {
         cls += 1;
     }
     cls
@@ -63,14 +132,14 @@ static SMALL_LOOKUP: [u8; SMALL_LOOKUP_LEN] = const {
     while i < SMALL_LOOKUP_LEN {
         let size = if i == 0 { 0 } else { i * 8 };
         let mut cls = 1u8;
-        while (cls as usize) < NUM_SIZE_CLASSES {
+        while (cls as usize) < TABLE_LEN {
             if SIZE_CLASSES[cls as usize].size >= size {
                 break;
             }
             cls += 1;
         }
-        if (cls as usize) >= NUM_SIZE_CLASSES {
-            cls = (NUM_SIZE_CLASSES - 1) as u8;
+        if (cls as usize) >= TABLE_LEN {
+            cls = (TABLE_LEN - 1) as u8;
         }
         table[i] = cls;
         i += 1;
@@ -78,55 +147,346 @@ static SMALL_LOOKUP: [u8; SMALL_LOOKUP_LEN] = const {
     table
 };
 
+impl ClassMap for LookupClassMap {
+    const CLASSES: &'static [SizeClassInfo] = &SIZE_CLASSES;
+
+    #[inline]
+    fn size_to_class(size: usize) -> usize {
+        if size == 0 {
+            return 1;
+        }
+        if size > TABLE_MAX_SIZE {
+            return 0;
+        }
+        if size <= SMALL_LOOKUP_MAX {
+            let idx = size.div_ceil(8);
+            return SMALL_LOOKUP[idx] as usize;
+        }
+        // Linear scan for sizes above the lookup table.
+        let mut cls = FIRST_CLASS_ABOVE_LOOKUP;
+        while cls < TABLE_LEN {
+            if SIZE_CLASSES[cls].size >= size {
+                return cls;
+            }
+            cls += 1;
+        }
+        0
+    }
+}
+
+/// Powers of two from 8 bytes, found by rounding the size up instead of a
+/// table lookup. Up to half of each object can be padding, so this trades
+/// memory for a branch-light mapping. Needs `class_map = "power_of_two"`,
+/// which makes build.rs generate the matching table.
+pub struct PowerOfTwoClassMap;
+
+impl ClassMap for PowerOfTwoClassMap {
+    const CLASSES: &'static [SizeClassInfo] = &SIZE_CLASSES;
+
+    const VALID: bool = {
+        let mut cls = 1;
+        while cls < TABLE_LEN && SIZE_CLASSES[cls].size == 8 << (cls - 1) {
+            cls += 1;
+        }
+        cls == TABLE_LEN
+    };
+
+    #[inline]
+    fn size_to_class(size: usize) -> usize {
+        if size > TABLE_MAX_SIZE {
+            return 0;
+        }
+        size.max(8).next_power_of_two().trailing_zeros() as usize - 2
+    }
+}
+
 /// Map an allocation size to its size class index.
 /// Returns 1 for size 0 (minimum allocation is 8 bytes).
 /// Returns 0 for sizes > MAX_SMALL_SIZE (large allocation).
 #[inline]
-pub const fn size_to_class(size: usize) -> usize {
-    if size == 0 {
-        return 1;
-    }
-    if size > MAX_SMALL_SIZE {
-        return 0;
-    }
-    if size <= SMALL_LOOKUP_MAX {
-        let idx = size.div_ceil(8);
-        return SMALL_LOOKUP[idx] as usize;
-    }
-    // Linear scan for sizes above the lookup table.
-    let mut cls = FIRST_CLASS_ABOVE_LOOKUP;
-    while cls < NUM_SIZE_CLASSES {
-        if SIZE_CLASSES[cls].size >= size {
-            return cls;
-        }
-        cls += 1;
-    }
-    0
+pub fn size_to_class(size: usize) -> usize {
+    Classes::size_to_class(size)
 }
 
 /// Get the allocation size for a given size class.
 #[inline]
 pub const fn class_to_size(cls: usize) -> usize {
-    SIZE_CLASSES[cls].size
+    <Classes as ClassMap>::CLASSES[cls].size
 }
 
 /// Get the size class info for a given class index.
 #[inline]
 pub const fn class_info(cls: usize) -> &'static SizeClassInfo {
-    &SIZE_CLASSES[cls]
+    &<Classes as ClassMap>::CLASSES[cls]
+}
+
+/// Batch sizes in effect, starting at the built table's.
+static BATCH_SIZES: [AtomicU32; NUM_SIZE_CLASSES] = const {
+    let mut sizes = [const { AtomicU32::new(0) }; NUM_SIZE_CLASSES];
+    let mut cls = 0;
+    while cls < NUM_SIZE_CLASSES {
+        sizes[cls] = AtomicU32::new(class_info(cls).batch_size as u32);
+        cls += 1;
+    }
+    sizes
+};
+
+/// Objects moved per transfer between the front-end caches and the central
+/// free lists for `cls`.
+#[inline]
+pub fn batch_size(cls: usize) -> usize {
+    BATCH_SIZES[cls].load(Ordering::Relaxed) as usize
+}
+
+/// Set the batch size of `cls`, clamped to `1..=class_info(cls).batch_size`,
+/// and return the value applied.
+///
+/// Batches can only shrink: per-CPU slabs are laid out for the built batch
+/// sizes. Transfers already in flight finish with the old size.
+pub fn set_batch_size(cls: usize, batch: usize) -> usize {
+    let batch = batch.clamp(1, Classes::class_batch(cls));
+    BATCH_SIZES[cls].store(batch as u32, Ordering::Relaxed);
+    batch
+}
+
+/// Pages per span in effect, starting at the built table's.
+static SPAN_PAGES: [AtomicU32; NUM_SIZE_CLASSES] = const {
+    let mut pages = [const { AtomicU32::new(0) }; NUM_SIZE_CLASSES];
+    let mut cls = 0;
+    while cls < NUM_SIZE_CLASSES {
+        pages[cls] = AtomicU32::new(class_info(cls).pages as u32);
+        cls += 1;
+    }
+    pages
+};
+
+/// Pages of the next span the central free list of `cls` takes from the
+/// page heap.
+#[inline]
+pub fn span_pages(cls: usize) -> usize {
+    SPAN_PAGES[cls].load(Ordering::Relaxed) as usize
+}
+
+/// Fewest and most pages a span of `cls` may have: the built count divided
+/// and multiplied by `span_pages_scale`, and never too few for one object.
+pub const fn span_pages_range(cls: usize) -> (usize, usize) {
+    let info = class_info(cls);
+    let mut min = info.pages / SPAN_PAGES_SCALE;
+    let fit = info.size.div_ceil(PAGE_SIZE);
+    if min < fit {
+        min = fit;
+    }
+    if min == 0 {
+        min = 1;
+    }
+    let max = info.pages * SPAN_PAGES_SCALE;
+    (if min < max { min } else { max }, max)
+}
+
+/// Set the pages per span of `cls`, clamped to [`span_pages_range`], and
+/// return the value applied. Spans already carved keep their size.
+///
+/// With `span_pages_scale` at its default of 1 the range is the built count
+/// alone.
+pub fn set_span_pages(cls: usize, pages: usize) -> usize {
+    let (min, max) = span_pages_range(cls);
+    let pages = pages.max(min).min(max);
+    SPAN_PAGES[cls].store(pages as u32, Ordering::Relaxed);
+    pages
+}
+
+/// Order in which freed objects of a class are handed out again by the
+/// central free list and the transfer cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ReuseOrder {
+    /// Most recently freed first: the hottest in cache (the default).
+    Lifo = 0,
+    /// Least recently freed first: a freed object stays free as long as
+    /// possible, which makes use-after-free much harder to exploit.
+    Fifo = 1,
+}
+
+/// Reuse order in effect for each class.
+static REUSE_ORDERS: [AtomicU8; NUM_SIZE_CLASSES] =
+    [const { AtomicU8::new(ReuseOrder::Lifo as u8) }; NUM_SIZE_CLASSES];
+
+/// Reuse order of `cls` in the central free list and transfer cache.
+#[inline]
+pub fn reuse_order(cls: usize) -> ReuseOrder {
+    match REUSE_ORDERS[cls].load(Ordering::Relaxed) {
+        0 => ReuseOrder::Lifo,
+        _ => ReuseOrder::Fifo,
+    }
+}
+
+/// Set the reuse order of `cls`. Takes effect for objects freed and batches
+/// taken from then on; thread caches and per-CPU slabs stay LIFO.
+pub fn set_reuse_order(cls: usize, order: ReuseOrder) {
+    REUSE_ORDERS[cls].store(order as u8, Ordering::Relaxed);
 }
 
+/// Smallest alignment with a row in [`ALIGNED_CLASSES`]; every class size
+/// is a multiple of 8.
+const MIN_ALIGN_SHIFT: usize = 4;
+
+/// For each alignment from 16 bytes to a page, the smallest class at or
+/// above each class whose size is a multiple of it, 0 if none is.
+static ALIGNED_CLASSES: [[u8; NUM_SIZE_CLASSES]; PAGE_SHIFT + 1 - MIN_ALIGN_SHIFT] = {
+    let mut table = [[0u8; NUM_SIZE_CLASSES]; PAGE_SHIFT + 1 - MIN_ALIGN_SHIFT];
+    let mut row = 0;
+    while row < table.len() {
+        let align = 1 << (row + MIN_ALIGN_SHIFT);
+        // Walk down so each class takes the nearest fit above it.
+        let mut fit = 0;
+        let mut cls = NUM_SIZE_CLASSES - 1;
+        while cls > 0 {
+            if class_info(cls).size.is_multiple_of(align) {
+                fit = cls;
+            }
+            table[row][cls] = fit as u8;
+            cls -= 1;
+        }
+        row += 1;
+    }
+    table
+};
+
+const _: () = {
+    let mut row = 0;
+    while row < ALIGNED_CLASSES.len() {
+        let mut cls = 1;
+        while cls < NUM_SIZE_CLASSES {
+            let fit = ALIGNED_CLASSES[row][cls] as usize;
+            assert!(
+                fit == 0
+                    || class_info(fit)
+                        .size
+                        .is_multiple_of(1 << (row + MIN_ALIGN_SHIFT))
+            );
+            cls += 1;
+        }
+        row += 1;
+    }
+};
+
+/// Size class serving an allocation of `size` bytes aligned to `align`, or
+/// 0 if it must come from the page heap.
+///
+/// Invariant for a nonzero result `c`: `class_to_size(c) >= size` and
+/// `class_to_size(c) % align == 0`. Spans are page aligned and objects sit
+/// at multiples of the class size within them, so every object of class `c`
+/// is `align`-aligned. A zero result means a large allocation, whose span
+/// starts on an `align` boundary (see `RtMalloc::alloc_large`).
+///
+/// Alignments above 8 up to a page, including ones above `size` such as
+/// `(8, 64)`, take the smallest class of at least `max(size, align)` bytes
+/// whose size is a multiple of `align`, from a table built with the class
+/// table and checked at compile time. A class table without such a class
+/// sends the layout to the page heap; it never yields a class whose objects
+/// could be under-aligned.
+///
+/// `dealloc`, `realloc` and `malloc_usable_size` never use this to find an
+/// object's class: they read it from the page map. An in-place realloc
+/// shrink keeps the object in its original, larger class, so the layout a
+/// caller frees with may map to a smaller class than the object's.
+#[inline]
+pub fn layout_to_class(size: usize, align: usize) -> usize {
+    if align <= 8 {
+        // Every class size is a multiple of 8.
+        return size_to_class(size);
+    }
+    if align > PAGE_SIZE {
+        return 0;
+    }
+    let size = if size > align { size } else { align };
+    let row = align.trailing_zeros() as usize - MIN_ALIGN_SHIFT;
+    ALIGNED_CLASSES[row][size_to_class(size)] as usize
+}
+
+const _: () = {
+    let mut cls = 1;
+    while cls < NUM_SIZE_CLASSES {
+        assert!(class_info(cls).size.is_multiple_of(8));
+        cls += 1;
+    }
+};
+
 /// Maximum allocation size handled by size classes.
 #[inline]
 pub const fn max_small_size() -> usize {
     MAX_SMALL_SIZE
 }
 
+/// A size class as reported by [`iter`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct ClassDesc {
+    /// Class index, as taken by [`class_info`] and [`set_batch_size`].
+    pub index: usize,
+    /// Allocation size in bytes.
+    pub size: usize,
+    /// Pages per span.
+    pub pages: usize,
+    /// Objects per transfer in effect (see [`batch_size`]).
+    pub batch: usize,
+}
+
+/// Number of size classes in the table this build was made with, not
+/// counting class 0, which stands for allocations served by the page heap.
+/// Classes are numbered `1..=num_classes()`.
+#[inline]
+pub const fn num_classes() -> usize {
+    NUM_SIZE_CLASSES - 1
+}
+
+/// Class `index`, or None outside `1..=num_classes()`.
+pub fn describe(index: usize) -> Option<ClassDesc> {
+    if index == 0 || index >= NUM_SIZE_CLASSES {
+        return None;
+    }
+    let info = class_info(index);
+    Some(ClassDesc {
+        index,
+        size: info.size,
+        pages: info.pages,
+        batch: batch_size(index),
+    })
+}
+
+/// Every size class, smallest first.
+///
+/// Wrappers and tools that bucket by class should size their tables from
+/// this rather than assume the default table: `RTMALLOC_CLASSES` and
+/// `class_map` change the number of classes and their sizes.
+pub fn iter() -> impl DoubleEndedIterator<Item = ClassDesc> + ExactSizeIterator + Clone {
+    (1..NUM_SIZE_CLASSES).map(|index| describe(index).unwrap())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
+    fn test_iter_matches_table() {
+        assert_eq!(iter().len(), num_classes());
+        let mut last = 0;
+        for (i, desc) in iter().enumerate() {
+            assert_eq!(desc.index, i + 1);
+            assert_eq!(desc.size, class_to_size(desc.index));
+            assert_eq!(desc.pages, class_info(desc.index).pages);
+            assert_eq!(desc.batch, batch_size(desc.index));
+            assert!(desc.size > last);
+            last = desc.size;
+        }
+        assert_eq!(last, max_small_size());
+        assert_eq!(describe(0), None);
+        assert_eq!(describe(num_classes() + 1), None);
+    }
+
+    // These three check the default class table.
+    #[test]
+    #[cfg(not(feature = "minimal"))]
     fn test_size_to_class_zero() {
         let cls = size_to_class(0);
         assert_eq!(cls, 1);
@@ -134,6 +494,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(feature = "minimal"))]
     fn test_size_to_class_exact() {
         assert_eq!(class_to_size(size_to_class(8)), 8);
         assert_eq!(class_to_size(size_to_class(16)), 16);
@@ -149,6 +510,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(feature = "minimal"))]
     fn test_size_to_class_rounds_up() {
         assert_eq!(class_to_size(size_to_class(1)), 8);
         assert_eq!(class_to_size(size_to_class(7)), 8);
@@ -195,6 +557,49 @@ mod tests {
         }
     }
 
+    /// Check `M::size_to_class` against a scan of its table.
+    fn check_map<M: ClassMap>() {
+        let max = M::CLASSES[M::CLASSES.len() - 1].size;
+        assert_eq!(M::size_to_class(0), 1);
+        assert_eq!(M::size_to_class(max + 1), 0);
+        for size in (1..=max).step_by(7).chain([max]) {
+            let expected = (1..M::CLASSES.len())
+                .find(|&cls| M::class_to_size(cls) >= size)
+                .unwrap();
+            assert_eq!(M::size_to_class(size), expected, "size={size}");
+        }
+    }
+
+    #[test]
+    fn test_class_maps() {
+        check_map::<LookupClassMap>();
+        check_map::<Classes>();
+        if PowerOfTwoClassMap::VALID {
+            check_map::<PowerOfTwoClassMap>();
+        }
+    }
+
+    #[test]
+    fn test_power_of_two_rounding() {
+        for size in 1..=TABLE_MAX_SIZE.min(1 << 16) {
+            let cls = PowerOfTwoClassMap::size_to_class(size);
+            let class_size = 8usize << (cls - 1);
+            assert!(class_size >= size && (class_size == 8 || class_size / 2 < size));
+        }
+    }
+
+    #[test]
+    fn test_reuse_order() {
+        // The largest class: no other test here depends on its order.
+        let cls = NUM_SIZE_CLASSES - 1;
+        assert_eq!(reuse_order(cls), ReuseOrder::Lifo);
+        set_reuse_order(cls, ReuseOrder::Fifo);
+        assert_eq!(reuse_order(cls), ReuseOrder::Fifo);
+        assert_eq!(reuse_order(1), ReuseOrder::Lifo);
+        set_reuse_order(cls, ReuseOrder::Lifo);
+        assert_eq!(reuse_order(cls), ReuseOrder::Lifo);
+    }
+
     #[test]
     #[allow(clippy::needless_range_loop)]
     fn test_all_sizes_8_aligned() {
@@ -220,6 +625,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_span_pages_range() {
+        for cls in 1..NUM_SIZE_CLASSES {
+            let info = class_info(cls);
+            let (min, max) = span_pages_range(cls);
+            assert!(min >= 1 && min <= info.pages && info.pages <= max);
+            assert!(min * PAGE_SIZE >= info.size);
+            let pages = span_pages(cls);
+            assert!(min <= pages && pages <= max);
+        }
+        // Out-of-range requests are clamped; the last class is left as built.
+        let cls = NUM_SIZE_CLASSES - 1;
+        let (min, max) = span_pages_range(cls);
+        assert_eq!(set_span_pages(cls, usize::MAX), max);
+        assert_eq!(set_span_pages(cls, 0), min);
+        assert_eq!(
+            set_span_pages(cls, class_info(cls).pages),
+            class_info(cls).pages
+        );
+    }
+
     #[test]
     fn test_num_size_classes() {
         assert_eq!(NUM_SIZE_CLASSES, SIZE_CLASSES.len());
@@ -229,4 +655,66 @@ mod tests {
     fn test_max_small_size() {
         assert_eq!(MAX_SMALL_SIZE, class_to_size(NUM_SIZE_CLASSES - 1));
     }
+
+    #[test]
+    fn test_layout_to_class_invariant() {
+        let mut aligns = alloc::vec::Vec::new();
+        let mut align = 1;
+        while align <= PAGE_SIZE * 4 {
+            aligns.push(align);
+            align *= 2;
+        }
+        let mut sizes = alloc::vec![1, 7, 8, 9];
+        for cls in 1..NUM_SIZE_CLASSES {
+            let size = class_to_size(cls);
+            sizes.extend([size - 1, size, size + 1]);
+        }
+        for &align in &aligns {
+            for &size in &sizes {
+                let cls = layout_to_class(size, align);
+                // No smaller class would have fit.
+                let first = size_to_class(size.max(align));
+                let end = if cls == 0 { NUM_SIZE_CLASSES } else { cls };
+                if first != 0 && align <= PAGE_SIZE {
+                    for smaller in first..end {
+                        assert!(
+                            !class_to_size(smaller).is_multiple_of(align),
+                            "size={size} align={align} skipped class {smaller}"
+                        );
+                    }
+                }
+                if cls == 0 {
+                    continue;
+                }
+                let class_size = class_to_size(cls);
+                assert!(class_size >= size, "size={size} align={align}");
+                assert!(
+                    class_size.is_multiple_of(align),
+                    "size={size} align={align} class_size={class_size}"
+                );
+                assert!(align <= PAGE_SIZE);
+            }
+        }
+    }
+
+    #[test]
+    fn test_layout_to_class_matches_size_to_class() {
+        for size in 1..=MAX_SMALL_SIZE.min(4096) {
+            for align in [1, 2, 4, 8] {
+                assert_eq!(layout_to_class(size, align), size_to_class(size));
+            }
+        }
+        assert_eq!(layout_to_class(MAX_SMALL_SIZE + 1, 8), 0);
+        assert_eq!(layout_to_class(8, PAGE_SIZE * 2), 0);
+    }
+
+    #[test]
+    fn test_layout_align_above_size() {
+        for shift in MIN_ALIGN_SHIFT..=PAGE_SHIFT {
+            let align = 1 << shift;
+            let cls = layout_to_class(8, align);
+            assert_eq!(cls, layout_to_class(align, align), "align={align}");
+            assert!(cls == 0 || class_to_size(cls).is_multiple_of(align));
+        }
+    }
 }
@@ -0,0 +1,421 @@
+//! Background page map verifier for soak tests (`testing` + `std`).
+//!
+//! Races between the page heap, the central lists and the lock-free page
+//! map lookups tend to show up only after hours of uptime, as a page that
+//! maps to the wrong span long after the free that caused it. [`start`]
+//! runs a thread that wakes every interval and checks a random subset of
+//! the spans the allocator tracks:
+//!
+//! - spans on the central lists: every page must map to the span, with the
+//!   span's class and arena cached next to it;
+//! - free and pending spans in the page heap: both endpoints must map to
+//!   the span, and a span on an exact-size list must have that many pages.
+//!
+//! Each list is checked under its own lock, one at a time, so a pass only
+//! stalls the threads that touch that list. Discrepancies are logged to
+//! stderr with the span and page they were found on, and collected for the
+//! [`Summary`].
+//!
+//! ```ignore
+//! let soak = rtmalloc::soak::start(Duration::from_millis(100), 256);
+//! // ... run the workload for hours ...
+//! let summary = soak.stop();
+//! assert_eq!(summary.discrepancies, []);
+//! ```
+
+use crate::allocator::{CENTRAL_CACHE, PAGE_HEAP, PAGE_MAP};
+use crate::central_free_list::CentralCache;
+use crate::config::{MAX_PAGES, NUM_ARENAS};
+use crate::page_heap::{NUM_SPAN_LISTS, PageHeap};
+use crate::pagemap::PageMap;
+use crate::size_class::NUM_SIZE_CLASSES;
+use crate::span::{Span, SpanState};
+use crate::sync::SpinMutex;
+use crate::version::{entropy, mix64};
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::vec::Vec;
+
+/// Discrepancies kept per list and pass; the lock is held while they are
+/// collected, so they go to a fixed buffer rather than the heap.
+const FOUND_PER_LIST: usize = 8;
+
+/// The list a checked span was found on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum List {
+    /// A central free list.
+    Central { class: usize, arena: usize },
+    /// The page heap's free list for spans of exactly `pages` pages.
+    Free { pages: usize },
+    /// The page heap's list of free spans larger than `MAX_PAGES`.
+    Large,
+    /// Freed spans waiting for deferred coalescing.
+    Pending,
+}
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            List::Central { class, arena } => write!(f, "central class {class} arena {arena}"),
+            List::Free { pages } => write!(f, "free list {pages}"),
+            List::Large => f.write_str("large free list"),
+            List::Pending => f.write_str("pending list"),
+        }
+    }
+}
+
+/// What a check found wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// The page maps to another span struct (`found`, 0 for none).
+    WrongSpan { found: usize },
+    /// The page map caches another class and arena for the page than the
+    /// span's.
+    WrongClass {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    /// The span's own class and arena are not its central list's.
+    WrongOwner { found: (usize, usize) },
+    /// The span's state does not match the list it is on.
+    WrongState { expected: SpanState },
+    /// A span on an exact-size free list has another number of pages.
+    WrongSize { expected: usize },
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Kind::WrongSpan { found: 0 } => f.write_str("page not mapped"),
+            Kind::WrongSpan { found } => write!(f, "page maps to span {found:#x}"),
+            Kind::WrongClass { expected, found } => write!(
+                f,
+                "page map has class {} arena {}, span has class {} arena {}",
+                found.0, found.1, expected.0, expected.1
+            ),
+            Kind::WrongOwner { found } => {
+                write!(f, "span has class {} arena {}", found.0, found.1)
+            }
+            Kind::WrongState { expected } => write!(f, "span is not {expected:?}"),
+            Kind::WrongSize { expected } => write!(f, "span is not {expected} pages"),
+        }
+    }
+}
+
+/// One inconsistency, with the span it was found on as it was then.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Discrepancy {
+    pub kind: Kind,
+    pub list: List,
+    /// Address of the span struct.
+    pub span: usize,
+    pub start_page: usize,
+    pub num_pages: usize,
+    pub state: SpanState,
+    /// Page the check failed on; the span's first page for checks on the
+    /// span itself.
+    pub page: usize,
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: span {:#x} pages {:#x}..{:#x} ({:?}), page {:#x}: {}",
+            self.list,
+            self.span,
+            self.start_page,
+            self.start_page + self.num_pages,
+            self.state,
+            self.page,
+            self.kind
+        )
+    }
+}
+
+/// Result of one [`verify`] pass.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pass {
+    /// Spans checked.
+    pub spans: usize,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+/// Discrepancies found on one list, collected under its lock.
+struct Found {
+    list: List,
+    items: [Option<Discrepancy>; FOUND_PER_LIST],
+    len: usize,
+}
+
+impl Found {
+    fn new(list: List) -> Self {
+        Self {
+            list,
+            items: [None; FOUND_PER_LIST],
+            len: 0,
+        }
+    }
+
+    /// Record `kind` on `page` of `span`. Beyond `FOUND_PER_LIST` the rest
+    /// are dropped; the list is broken either way.
+    unsafe fn report(&mut self, span: *mut Span, page: usize, kind: Kind) {
+        if self.len == FOUND_PER_LIST {
+            return;
+        }
+        let s = unsafe { &*span };
+        self.items[self.len] = Some(Discrepancy {
+            kind,
+            list: self.list,
+            span: span as usize,
+            start_page: s.start_page,
+            num_pages: s.num_pages,
+            state: s.state,
+            page,
+        });
+        self.len += 1;
+    }
+
+    fn drain_into(self, out: &mut Vec<Discrepancy>) {
+        out.extend(self.items.into_iter().flatten());
+    }
+}
+
+/// Check one span of a central list: owned by the list, and every page
+/// mapped to it with the list's class and arena.
+unsafe fn check_central_span(span: *mut Span, pagemap: &PageMap, found: &mut Found) {
+    let List::Central { class, arena } = found.list else {
+        return;
+    };
+    let s = unsafe { &*span };
+    if s.state != SpanState::InUse {
+        let expected = SpanState::InUse;
+        unsafe { found.report(span, s.start_page, Kind::WrongState { expected }) };
+    }
+    if (s.size_class, s.arena as usize) != (class, arena) {
+        let owner = (s.size_class, s.arena as usize);
+        unsafe { found.report(span, s.start_page, Kind::WrongOwner { found: owner }) };
+    }
+    for page in s.start_page..s.end_page() {
+        let mapped = pagemap.get(page);
+        if mapped != span {
+            let kind = Kind::WrongSpan {
+                found: mapped as usize,
+            };
+            unsafe { found.report(span, page, kind) };
+        }
+        let cached = pagemap.class_and_arena(page);
+        if cached != (class, arena) {
+            let kind = Kind::WrongClass {
+                expected: (class, arena),
+                found: cached,
+            };
+            unsafe { found.report(span, page, kind) };
+        }
+    }
+}
+
+/// Check one span of a page heap list: in the list's state, of its size,
+/// and with both endpoints mapped to it.
+unsafe fn check_free_span(span: *mut Span, pagemap: &PageMap, found: &mut Found) {
+    let s = unsafe { &*span };
+    let expected = match found.list {
+        List::Pending => SpanState::Pending,
+        _ => SpanState::Free,
+    };
+    if s.state != expected {
+        unsafe { found.report(span, s.start_page, Kind::WrongState { expected }) };
+    }
+    if let List::Free { pages } = found.list
+        && s.num_pages != pages
+    {
+        let kind = Kind::WrongSize { expected: pages };
+        unsafe { found.report(span, s.start_page, kind) };
+    }
+    let last = s.end_page().saturating_sub(1).max(s.start_page);
+    for page in [s.start_page, last] {
+        let mapped = pagemap.get(page);
+        if mapped != span {
+            let kind = Kind::WrongSpan {
+                found: mapped as usize,
+            };
+            unsafe { found.report(span, page, kind) };
+        }
+    }
+}
+
+/// Check spans on the central lists, starting at list `start` and going
+/// round until `budget` spans are done or every list was visited.
+fn verify_central(
+    cache: &CentralCache,
+    pagemap: &PageMap,
+    start: usize,
+    budget: usize,
+    pass: &mut Pass,
+) {
+    let classes = NUM_SIZE_CLASSES - 1;
+    let lists = classes * NUM_ARENAS;
+    let mut left = budget;
+    for i in 0..lists {
+        if left == 0 {
+            break;
+        }
+        let index = (start + i) % lists;
+        let (arena, class) = (index / classes, index % classes + 1);
+        let mut found = Found::new(List::Central { class, arena });
+        {
+            let list = cache.arena(arena, class).lock();
+            for span in unsafe { list.spans() }.take(left) {
+                unsafe { check_central_span(span, pagemap, &mut found) };
+                left -= 1;
+            }
+        }
+        found.drain_into(&mut pass.discrepancies);
+    }
+    pass.spans += budget - left;
+}
+
+/// Check free and pending spans of the page heap the same way as
+/// [`verify_central`].
+fn verify_page_heap(
+    heap: &SpinMutex<PageHeap>,
+    pagemap: &PageMap,
+    start: usize,
+    budget: usize,
+    pass: &mut Pass,
+) {
+    let mut left = budget;
+    for i in 0..NUM_SPAN_LISTS {
+        if left == 0 {
+            break;
+        }
+        let index = (start + i) % NUM_SPAN_LISTS;
+        let list = match index {
+            0..=MAX_PAGES => List::Free { pages: index },
+            i if i == MAX_PAGES + 1 => List::Large,
+            _ => List::Pending,
+        };
+        let mut found = Found::new(list);
+        {
+            let heap = heap.lock();
+            for span in unsafe { heap.span_list(index).spans() }.take(left) {
+                unsafe { check_free_span(span, pagemap, &mut found) };
+                left -= 1;
+            }
+        }
+        found.drain_into(&mut pass.discrepancies);
+    }
+    pass.spans += budget - left;
+}
+
+/// Check up to `budget` central-list spans and up to `budget` page heap
+/// spans, starting from random lists.
+fn verify_with(
+    cache: &CentralCache,
+    heap: &SpinMutex<PageHeap>,
+    pagemap: &PageMap,
+    seed: u64,
+    budget: usize,
+) -> Pass {
+    let mut pass = Pass::default();
+    verify_central(cache, pagemap, seed as usize, budget, &mut pass);
+    let start = mix64(seed) as usize;
+    verify_page_heap(heap, pagemap, start, budget, &mut pass);
+    pass
+}
+
+/// Run one pass over the global heap, as the [`start`] thread does.
+pub fn verify(budget: usize) -> Pass {
+    let seed = mix64(entropy());
+    verify_with(&CENTRAL_CACHE, &PAGE_HEAP, &PAGE_MAP, seed, budget)
+}
+
+/// What a verifier thread saw until it was stopped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub passes: u64,
+    /// Spans checked across all passes.
+    pub spans: u64,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+/// A running verifier thread.
+pub struct Soak {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Summary>,
+}
+
+impl Soak {
+    /// Stop the thread after its current pass and return what it found.
+    pub fn stop(self) -> Summary {
+        self.stop.store(true, Ordering::Release);
+        self.thread.thread().unpark();
+        self.thread
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    }
+}
+
+/// Start a thread that runs a [`verify`] pass of `budget` spans every
+/// `interval`, logging each discrepancy to stderr as it is found.
+pub fn start(interval: Duration, budget: usize) -> Soak {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::clone(&stop);
+    let thread = std::thread::Builder::new()
+        .name("rtmalloc-soak".into())
+        .spawn(move || {
+            let mut summary = Summary::default();
+            while !stopped.load(Ordering::Acquire) {
+                let pass = verify(budget);
+                summary.passes += 1;
+                summary.spans += pass.spans as u64;
+                for d in &pass.discrepancies {
+                    std::eprintln!("rtmalloc soak: pass {}: {d}", summary.passes);
+                }
+                summary.discrepancies.extend(pass.discrepancies);
+                std::thread::park_timeout(interval);
+            }
+            summary
+        })
+        .expect("failed to spawn the soak verifier");
+    Soak { stop, thread }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ptr;
+    use std::boxed::Box;
+
+    #[test]
+    fn test_verify_finds_bad_mapping() {
+        let pm: &'static PageMap = Box::leak(Box::new(PageMap::new()));
+        let heap = SpinMutex::new(PageHeap::new(pm));
+        let cache = CentralCache::new();
+        unsafe {
+            let (count, _, _) = cache.get(1).lock().remove_range(4, &heap, pm);
+            assert!(count > 0);
+            let big = heap.lock().allocate_span(3);
+            heap.lock().deallocate_span(big);
+        }
+        let clean = verify_with(&cache, &heap, pm, 0, usize::MAX);
+        assert!(clean.spans >= 2, "{clean:?}");
+        assert_eq!(clean.discrepancies, []);
+
+        let span = unsafe { cache.get(1).lock().spans().next().unwrap() };
+        let page = unsafe { (*span).start_page };
+        unsafe { pm.set(page, ptr::null_mut()) };
+        let pass = verify_with(&cache, &heap, pm, 0, usize::MAX);
+        let d = pass.discrepancies[0];
+        assert_eq!(d.kind, Kind::WrongSpan { found: 0 });
+        assert_eq!(d.list, List::Central { class: 1, arena: 0 });
+        assert_eq!((d.span, d.page), (span as usize, page));
+        assert!(std::format!("{d}").contains("page not mapped"));
+        unsafe { pm.set(page, span) };
+        let pass = verify_with(&cache, &heap, pm, 0, usize::MAX);
+        assert_eq!(pass.discrepancies, []);
+    }
+}
@@ -2,7 +2,6 @@
 //! for Span structs themselves.
 
 use crate::config::PAGE_SIZE;
-use crate::platform;
 use crate::sync::SpinMutex;
 use core::ptr;
 
@@ -14,13 +13,137 @@ pub enum SpanState {
     Free = 0,
     /// Span is in use (holding allocated objects or a large allocation).
     InUse = 1,
+    /// Large span parked in the mid-heap or held in the span quarantine: not
+    /// handed out, never coalesced.
+    Cached = 2,
+    /// Freed to the page heap but not yet coalesced (deferred coalescing).
+    Pending = 3,
 }
 
 /// An intrusive free list node stored inside freed memory.
-/// The `next` pointer occupies the first 8 bytes of the freed object.
+/// The `next` link occupies the first 8 bytes of the freed object.
+///
+/// Always go through [`FreeObject::next`] / [`FreeObject::set_next`]: with the
+/// `safe-linking` feature the stored word is not a plain pointer (see
+/// [`protect`]).
 #[repr(C)]
 pub struct FreeObject {
-    pub next: *mut FreeObject,
+    next: usize,
+}
+
+impl FreeObject {
+    /// Read the next link of `obj`. Aborts if the stored link is corrupt.
+    ///
+    /// # Safety
+    ///
+    /// `obj` must point to a free object whose link was written by
+    /// [`FreeObject::set_next`].
+    #[inline(always)]
+    pub unsafe fn next(obj: *mut FreeObject) -> *mut FreeObject {
+        let stored = unsafe { (*obj).next };
+        match reveal(obj, stored) {
+            Some(next) => next,
+            None => corrupted_link(obj),
+        }
+    }
+
+    /// Read the next link of `obj` while another thread may have taken `obj`
+    /// and be overwriting it. `None` if the stored word is not a valid link.
+    /// Neither result means anything unless the caller then confirms `obj`
+    /// was still free when it was read.
+    ///
+    /// # Safety
+    ///
+    /// `obj` must point to mapped, 8-byte aligned memory.
+    #[inline(always)]
+    pub unsafe fn racy_next(obj: *mut FreeObject) -> Option<*mut FreeObject> {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        let stored = unsafe { AtomicUsize::from_ptr(ptr::addr_of_mut!((*obj).next)) };
+        reveal(obj, stored.load(Ordering::Relaxed))
+    }
+
+    /// Write the next link of `obj`.
+    ///
+    /// # Safety
+    ///
+    /// `obj` must point to writable memory of at least 8 bytes.
+    #[inline(always)]
+    pub unsafe fn set_next(obj: *mut FreeObject, next: *mut FreeObject) {
+        unsafe { (*obj).next = protect(obj, next) };
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "safe-linking")] {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Per-process secret mixed into every stored link. Zero means "not yet
+        /// chosen"; the first [`protect`] call picks it, before any link exists.
+        static LINK_SECRET: AtomicUsize = AtomicUsize::new(0);
+
+        #[cold]
+        fn init_secret() -> usize {
+            #[cfg(feature = "deterministic")]
+            let seed = crate::version::entropy();
+            #[cfg(not(feature = "deterministic"))]
+            let seed = crate::version::entropy() ^ ptr::addr_of!(LINK_SECRET) as u64;
+            let new = (crate::version::mix64(seed) as usize) & !0b111 | 0b1000;
+            match LINK_SECRET.compare_exchange(0, new, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => new,
+                Err(existing) => existing,
+            }
+        }
+
+        #[inline(always)]
+        fn secret() -> usize {
+            let s = LINK_SECRET.load(Ordering::Relaxed);
+            if s != 0 { s } else { init_secret() }
+        }
+
+        /// Encode `next` for storage at `slot` (glibc-style safe-linking).
+        ///
+        /// The link is XORed with the slot's page-aligned address bits, so a
+        /// pointer copied from one object to another decodes to garbage, and
+        /// with a per-process secret, so a leaked heap address is not enough to
+        /// forge a link. The secret's low three bits are zero, keeping the
+        /// alignment check in [`reveal`] meaningful.
+        #[inline(always)]
+        pub(crate) fn protect(slot: *mut FreeObject, next: *mut FreeObject) -> usize {
+            next as usize ^ ((slot as usize >> crate::config::PAGE_SHIFT) << 3) ^ secret()
+        }
+
+        /// Decode a stored link. `None` if the result cannot be a free object
+        /// (objects are at least 8-byte aligned).
+        #[inline(always)]
+        pub(crate) fn reveal(slot: *mut FreeObject, stored: usize) -> Option<*mut FreeObject> {
+            let next = stored ^ ((slot as usize >> crate::config::PAGE_SHIFT) << 3) ^ secret();
+            if next & 0b111 != 0 {
+                return None;
+            }
+            // Encoding dropped the provenance; free objects all lie in
+            // memory exposed when it was mapped (see the crate docs).
+            Some(ptr::with_exposed_provenance_mut(next))
+        }
+    } else {
+        #[inline(always)]
+        pub(crate) fn protect(_slot: *mut FreeObject, next: *mut FreeObject) -> usize {
+            next.addr()
+        }
+
+        #[inline(always)]
+        pub(crate) fn reveal(_slot: *mut FreeObject, stored: usize) -> Option<*mut FreeObject> {
+            Some(ptr::with_exposed_provenance_mut(stored))
+        }
+    }
+}
+
+/// A free list link failed validation: heap corruption (use-after-free write,
+/// overflow into a free object, or a forged link). Continuing would hand out
+/// an attacker-chosen address, so abort whatever the failure policy.
+#[cold]
+#[inline(never)]
+fn corrupted_link(obj: *mut FreeObject) -> ! {
+    crate::failure::fatal(crate::failure::Failure::CorruptedFreeList, obj as usize)
 }
 
 /// Metadata for a contiguous run of pages.
@@ -37,12 +160,40 @@ pub struct Span {
     pub size_class: usize,
     /// Current state.
     pub state: SpanState,
+    /// Every page is still as the OS mapped it: zero, and never handed out.
+    /// Set when the page heap maps the span, kept by carving, and lost when
+    /// the span comes back from use or merges with a span that was used, so
+    /// `alloc_zeroed` can skip clearing a large span only when it is provably
+    /// fresh.
+    pub fresh_from_os: bool,
+    /// Some pages may be decommitted (`free_decommit_min`): set when a free
+    /// span's pages are given back, kept by carving and merging, and cleared
+    /// once the page heap recommits a span it hands out.
+    pub decommitted: bool,
+    /// Some pages may have the NUMA interleave policy (`numa_interleave_min`
+    /// or [`hint::INTERLEAVE`](crate::hint::INTERLEAVE)): set when a large
+    /// allocation is interleaved, kept by carving and merging, and cleared
+    /// once the page heap resets the policy of a span it hands out.
+    pub interleaved: bool,
+    /// Arena of the central list that carved this span into objects (see
+    /// [`thread::set_arena`](crate::thread::set_arena)); 0 unless small.
+    pub arena: u8,
+    /// Carved for its central list's tenured pool (see
+    /// [`Age`](crate::central_free_list::Age)); false unless small.
+    pub tenured: bool,
     /// Number of objects currently allocated from this span.
     pub allocated_count: u32,
     /// Total number of objects that fit in this span (for the assigned size class).
     pub total_count: u32,
+    /// OS mapping the pages come from, numbered by the page heap as it
+    /// grows; kept by carving. Spans only merge within one chunk, so pages
+    /// adjacent by id but mapped separately are never joined and a chunk can
+    /// later be unmapped whole.
+    pub chunk_id: u32,
     /// Head of the intrusive free list of unallocated objects within this span.
     pub freelist: *mut FreeObject,
+    /// Last object on `freelist` (null when it is empty), for FIFO reuse.
+    pub freelist_tail: *mut FreeObject,
     /// Previous span in a doubly-linked list (page heap free lists, central cache span lists).
     pub prev: *mut Span,
     /// Next span in a doubly-linked list.
@@ -53,7 +204,7 @@ impl Span {
     /// The base address of the memory region this span covers.
     #[inline]
     pub fn start_addr(&self) -> *mut u8 {
-        (self.start_page << crate::config::PAGE_SHIFT) as *mut u8
+        ptr::with_exposed_provenance_mut(self.start_page << crate::config::PAGE_SHIFT)
     }
 
     /// Total bytes covered by this span.
@@ -67,6 +218,21 @@ impl Span {
     pub fn end_page(&self) -> usize {
         self.start_page + self.num_pages
     }
+
+    /// Whether `ptr` falls inside the memory this span covers.
+    #[inline]
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        let addr = ptr as usize;
+        let start = self.start_addr() as usize;
+        addr >= start && addr - start < self.byte_size()
+    }
+
+    /// Bytes from `ptr` to the end of the span. `ptr` must be contained in it.
+    #[inline]
+    pub fn bytes_from(&self, ptr: *const u8) -> usize {
+        debug_assert!(self.contains(ptr));
+        self.start_addr() as usize + self.byte_size() - ptr as usize
+    }
 }
 
 /// A doubly-linked list of spans.
@@ -106,6 +272,35 @@ impl SpanList {
         }
     }
 
+    /// Insert a span before the first span at a higher address, keeping the
+    /// list sorted by `start_page`. O(length).
+    ///
+    /// # Safety
+    ///
+    /// `span` must be a valid, non-null pointer to a `Span` not already in a
+    /// list, and the list must already be sorted.
+    pub unsafe fn insert_by_address(&mut self, span: *mut Span) {
+        unsafe {
+            let mut prev: *mut Span = ptr::null_mut();
+            let mut next = self.head;
+            while !next.is_null() && (*next).start_page < (*span).start_page {
+                prev = next;
+                next = (*next).next;
+            }
+            (*span).prev = prev;
+            (*span).next = next;
+            if prev.is_null() {
+                self.head = span;
+            } else {
+                (*prev).next = span;
+            }
+            if !next.is_null() {
+                (*next).prev = span;
+            }
+            self.count += 1;
+        }
+    }
+
     /// Remove a specific span from the list.
     ///
     /// # Safety
@@ -146,6 +341,20 @@ impl SpanList {
     pub fn is_empty(&self) -> bool {
         self.head.is_null()
     }
+
+    /// The spans in list order.
+    ///
+    /// # Safety
+    ///
+    /// The list's internal pointers must be valid (maintained by `push`/`remove`)
+    /// and the list must not change while the iterator is in use.
+    pub unsafe fn spans(&self) -> impl Iterator<Item = *mut Span> + '_ {
+        let first = (!self.head.is_null()).then_some(self.head);
+        core::iter::successors(first, |&span| {
+            let next = unsafe { (*span).next };
+            (!next.is_null()).then_some(next)
+        })
+    }
 }
 
 /// Allocates Span structs from OS pages, avoiding use of the main allocator.
@@ -185,17 +394,18 @@ impl SpanSlabInner {
         let span_align = core::mem::align_of::<Span>();
 
         // Align bump_ptr
-        let ptr = self.bump_ptr as usize;
-        let aligned = (ptr + span_align - 1) & !(span_align - 1);
-        let end = aligned + span_size;
-
-        if end <= self.bump_end as usize {
-            self.bump_ptr = end as *mut u8;
-            return aligned as *mut Span;
+        let aligned = self
+            .bump_ptr
+            .map_addr(|a| (a + span_align - 1) & !(span_align - 1));
+        let end = aligned.wrapping_add(span_size);
+
+        if end.addr() <= self.bump_end.addr() {
+            self.bump_ptr = end;
+            return aligned.cast();
         }
 
         // Need a new slab. Allocate one page (8 KiB) for span metadata.
-        let slab = unsafe { platform::page_alloc(PAGE_SIZE) };
+        let slab = unsafe { metadata_pages(PAGE_SIZE) };
         if slab.is_null() {
             return ptr::null_mut();
         }
@@ -216,6 +426,19 @@ impl SpanSlabInner {
     }
 }
 
+/// Zeroed pages for allocator metadata (span slabs, page map nodes): from
+/// the dedicated metadata region with `meta-region`, straight from the OS
+/// otherwise. Never freed.
+pub(crate) unsafe fn metadata_pages(size: usize) -> *mut u8 {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "meta-region")] {
+            unsafe { crate::meta::alloc(size) }
+        } else {
+            unsafe { crate::platform::page_alloc(size) }
+        }
+    }
+}
+
 /// Global span slab allocator, protected by a spinlock.
 static SPAN_SLAB: SpinMutex<SpanSlabInner> = SpinMutex::new(SpanSlabInner::new());
 
@@ -244,6 +467,80 @@ mod tests {
     use super::*;
     use alloc::vec::Vec;
 
+    #[test]
+    fn test_free_object_link_roundtrip() {
+        let mut slots = [0usize; 4];
+        let a = slots.as_mut_ptr() as *mut FreeObject;
+        let b = unsafe { a.add(2) };
+        unsafe {
+            FreeObject::set_next(a, b);
+            FreeObject::set_next(b, ptr::null_mut());
+            assert_eq!(FreeObject::next(a), b);
+            assert!(FreeObject::next(b).is_null());
+        }
+    }
+
+    #[cfg(feature = "safe-linking")]
+    #[test]
+    fn test_safe_linking_hides_pointer() {
+        let mut slots = [0usize; 4];
+        let a = slots.as_mut_ptr() as *mut FreeObject;
+        let b = unsafe { a.add(2) };
+        unsafe {
+            FreeObject::set_next(a, b);
+            assert_ne!((a as *mut usize).read(), b as usize);
+            // A null link is not stored as zero either.
+            FreeObject::set_next(a, ptr::null_mut());
+            assert_ne!((a as *mut usize).read(), 0);
+        }
+    }
+
+    #[cfg(feature = "safe-linking")]
+    #[test]
+    #[should_panic(expected = "corrupted free list link")]
+    fn test_safe_linking_detects_overwrite() {
+        let mut slots = [0usize; 4];
+        let a = slots.as_mut_ptr() as *mut FreeObject;
+        unsafe { FreeObject::set_next(a, a.wrapping_add(2)) };
+        // Use-after-free style write of a plain pointer into the link word.
+        unsafe { (a as *mut usize).write(0x4141_4141_4141_4141) };
+        let _ = unsafe { FreeObject::next(a) };
+    }
+
+    #[test]
+    fn test_span_contains() {
+        let span = Span {
+            start_page: 16,
+            num_pages: 3,
+            size_class: 0,
+            state: SpanState::InUse,
+            fresh_from_os: false,
+            decommitted: false,
+            interleaved: false,
+            arena: 0,
+            tenured: false,
+            allocated_count: 0,
+            total_count: 0,
+            chunk_id: 0,
+            freelist: ptr::null_mut(),
+            freelist_tail: ptr::null_mut(),
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+        };
+        let start = span.start_addr();
+        assert!(span.contains(start));
+        assert!(span.contains(start.wrapping_add(PAGE_SIZE + 7)));
+        assert!(span.contains(start.wrapping_add(3 * PAGE_SIZE - 1)));
+        assert!(!span.contains(start.wrapping_add(3 * PAGE_SIZE)));
+        assert!(!span.contains(start.wrapping_sub(1)));
+
+        assert_eq!(span.bytes_from(start), 3 * PAGE_SIZE);
+        assert_eq!(
+            span.bytes_from(start.wrapping_add(2 * PAGE_SIZE)),
+            PAGE_SIZE
+        );
+    }
+
     #[test]
     fn test_alloc_dealloc_span() {
         let span = alloc_span();
@@ -254,7 +551,9 @@ mod tests {
             assert_eq!((*span).num_pages, 0);
             assert_eq!((*span).size_class, 0);
             assert_eq!((*span).state, SpanState::Free);
+            assert!(!(*span).fresh_from_os);
             assert!((*span).freelist.is_null());
+            assert!((*span).freelist_tail.is_null());
             assert!((*span).prev.is_null());
             assert!((*span).next.is_null());
 
@@ -15,8 +15,16 @@
 //! Obtain a [`Snapshot`] with [`snapshot()`]. Individual counter loads are
 //! individually atomic but not globally consistent with each other.
 
-use core::sync::atomic::{AtomicU64, Ordering};
+use crate::size_class::NUM_SIZE_CLASSES;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
 
+/// What `calibrate()` measured on this host and the values it chose.
+#[cfg(feature = "std")]
+pub use crate::calibrate::{Calibration, calibration};
+
+#[repr(C)]
 pub(crate) struct Stats {
     // ---- Global allocation stats ----
     /// Total calls to alloc with size > 0.
@@ -29,20 +37,58 @@ pub(crate) struct Stats {
     pub alloc_bytes: AtomicU64,
 
     // ---- Cache tier breakdown ----
-    /// Allocations served from thread/CPU cache (fast path, no lock).
+    /// Small allocations served by the front-end cache (fast path, no lock).
     pub thread_cache_hits: AtomicU64,
-    /// Allocations that fell through to central/page heap (slow path).
+    /// Small allocations the front-end cache could not serve.
     pub thread_cache_misses: AtomicU64,
-    /// Allocations served by the central free list.
+    /// Front-end misses refilled from the transfer cache.
+    pub transfer_cache_hits: AtomicU64,
+    /// Front-end misses that went on to a central free list.
     pub central_cache_hits: AtomicU64,
     /// Large allocations going directly to the page heap.
     pub page_heap_allocs: AtomicU64,
+    /// Large allocations served by a span parked in the mid-heap.
+    pub mid_cache_hits: AtomicU64,
+    /// Parked mid-heap spans handed back to the page heap for a populate.
+    pub mid_cache_reclaims: AtomicU64,
+    /// Central list misses that waited for another thread's populate.
+    pub central_populate_waits: AtomicU64,
+    /// Populates that hit a size class byte cap.
+    pub class_cap_hits: AtomicU64,
+    /// `alloc_zeroed` bytes left unwritten because the span was fresh from the OS.
+    pub zeroed_fresh_bytes: AtomicU64,
+    /// `alloc_zeroed` bytes cleared by dropping their pages (`zero_decommit_min`).
+    pub zeroed_decommit_bytes: AtomicU64,
+    /// Bytes of freed spans decommitted at once (`free_decommit_min`).
+    pub free_decommit_bytes: AtomicU64,
+    /// Freed spans put in the cool-down queue (`span_cooldown`).
+    pub cooldown_spans: AtomicU64,
+    /// Heap growths a span in the cool-down queue could have served.
+    pub cooldown_delayed_reuses: AtomicU64,
+    /// Batches pushed out of the transfer cache by `max_transfer_bytes`.
+    pub transfer_cache_evictions: AtomicU64,
+    /// Bytes thread caches gave back after `thread_cache_decay_ms` idle.
+    pub thread_cache_decay_bytes: AtomicU64,
+    /// Bytes cut off large spans by reallocs shrinking them in place.
+    pub realloc_trim_bytes: AtomicU64,
+    /// Reallocs that kept the pointer.
+    pub realloc_in_place: AtomicU64,
+    /// Reallocs that allocated, copied and freed.
+    pub realloc_moved: AtomicU64,
+    /// Bytes copied by moving reallocs.
+    pub realloc_copy_bytes: AtomicU64,
 
     // ---- Page heap / OS ----
-    /// Calls to `platform::page_alloc`.
+    /// Successful calls to `platform::page_alloc`.
     pub os_alloc_count: AtomicU64,
     /// Bytes requested from the OS via `platform::page_alloc`.
     pub os_alloc_bytes: AtomicU64,
+    /// Total nanoseconds spent in OS allocation calls (requires `std`).
+    pub os_alloc_nanos: AtomicU64,
+    /// Slowest single OS allocation call in nanoseconds (requires `std`).
+    pub os_alloc_max_nanos: AtomicU64,
+    /// OS allocation calls that were refused.
+    pub os_alloc_failures: AtomicU64,
     /// Times `carve_span` produced a remainder (i.e. a span was split).
     pub span_splits: AtomicU64,
     /// Times `coalesce_left` or `coalesce_right` merged two adjacent spans.
@@ -58,10 +104,29 @@ impl Stats {
             alloc_bytes: AtomicU64::new(0),
             thread_cache_hits: AtomicU64::new(0),
             thread_cache_misses: AtomicU64::new(0),
+            transfer_cache_hits: AtomicU64::new(0),
             central_cache_hits: AtomicU64::new(0),
             page_heap_allocs: AtomicU64::new(0),
+            mid_cache_hits: AtomicU64::new(0),
+            mid_cache_reclaims: AtomicU64::new(0),
+            central_populate_waits: AtomicU64::new(0),
+            class_cap_hits: AtomicU64::new(0),
+            zeroed_fresh_bytes: AtomicU64::new(0),
+            zeroed_decommit_bytes: AtomicU64::new(0),
+            free_decommit_bytes: AtomicU64::new(0),
+            cooldown_spans: AtomicU64::new(0),
+            cooldown_delayed_reuses: AtomicU64::new(0),
+            transfer_cache_evictions: AtomicU64::new(0),
+            thread_cache_decay_bytes: AtomicU64::new(0),
+            realloc_trim_bytes: AtomicU64::new(0),
+            realloc_in_place: AtomicU64::new(0),
+            realloc_moved: AtomicU64::new(0),
+            realloc_copy_bytes: AtomicU64::new(0),
             os_alloc_count: AtomicU64::new(0),
             os_alloc_bytes: AtomicU64::new(0),
+            os_alloc_nanos: AtomicU64::new(0),
+            os_alloc_max_nanos: AtomicU64::new(0),
+            os_alloc_failures: AtomicU64::new(0),
             span_splits: AtomicU64::new(0),
             span_coalesces: AtomicU64::new(0),
         }
@@ -70,6 +135,42 @@ impl Stats {
 
 pub(crate) static STATS: Stats = Stats::new();
 
+/// Names of the [`Stats`] counters in declaration order. Out-of-process
+/// readers (see `coredump`) index the counter array with these.
+pub const COUNTER_NAMES: [&str; core::mem::size_of::<Stats>() / 8] = [
+    "alloc_count",
+    "dealloc_count",
+    "realloc_count",
+    "alloc_bytes",
+    "thread_cache_hits",
+    "thread_cache_misses",
+    "transfer_cache_hits",
+    "central_cache_hits",
+    "page_heap_allocs",
+    "mid_cache_hits",
+    "mid_cache_reclaims",
+    "central_populate_waits",
+    "class_cap_hits",
+    "zeroed_fresh_bytes",
+    "zeroed_decommit_bytes",
+    "free_decommit_bytes",
+    "cooldown_spans",
+    "cooldown_delayed_reuses",
+    "transfer_cache_evictions",
+    "thread_cache_decay_bytes",
+    "realloc_trim_bytes",
+    "realloc_in_place",
+    "realloc_moved",
+    "realloc_copy_bytes",
+    "os_alloc_count",
+    "os_alloc_bytes",
+    "os_alloc_nanos",
+    "os_alloc_max_nanos",
+    "os_alloc_failures",
+    "span_splits",
+    "span_coalesces",
+];
+
 /// A point-in-time snapshot of all allocation statistics.
 ///
 /// Fields are plain `u64` values loaded from the global atomic counters.
@@ -88,18 +189,82 @@ pub struct Snapshot {
     pub realloc_count: u64,
     /// Sum of all requested byte sizes passed to alloc.
     pub alloc_bytes: u64,
-    /// Allocations served from thread/CPU cache (fast path, no lock).
+    /// Small allocations served by the front-end cache: the thread cache,
+    /// the per-CPU slab, or (without either) a class's object stack.
     pub thread_cache_hits: u64,
-    /// Allocations that fell through to central/page heap (slow path).
+    /// Small allocations the front-end cache could not serve, including
+    /// every small allocation of a build or thread without one. Each miss
+    /// is also counted by exactly one of the next two tiers, so
+    /// `transfer_cache_hits + central_cache_hits` tracks this count.
     pub thread_cache_misses: u64,
-    /// Allocations served by the central free list.
+    /// Misses refilled from a batch the transfer cache held.
+    pub transfer_cache_hits: u64,
+    /// Misses that went on to a central free list, which fetches spans
+    /// from the page heap as needed.
     pub central_cache_hits: u64,
     /// Large allocations going directly to the page heap.
     pub page_heap_allocs: u64,
-    /// Calls to `platform::page_alloc`.
+    /// Large allocations served by a span parked in the mid-heap.
+    pub mid_cache_hits: u64,
+    /// Parked mid-heap spans given back to the page heap so a size class
+    /// could populate without growing the heap.
+    pub mid_cache_reclaims: u64,
+    /// Spins on an empty central list while another thread fetched its span
+    /// from the page heap.
+    pub central_populate_waits: u64,
+    /// Populates that would have taken a size class past its byte cap (see
+    /// [`class_cap`](crate::class_cap)), whether the handler let them
+    /// through or not.
+    pub class_cap_hits: u64,
+    /// `alloc_zeroed` bytes not cleared because the span came straight from
+    /// the OS, already zero.
+    pub zeroed_fresh_bytes: u64,
+    /// `alloc_zeroed` bytes of reused large spans cleared by having the OS
+    /// drop their pages instead of writing zeros; see `zero_decommit_min`.
+    pub zeroed_decommit_bytes: u64,
+    /// Bytes of freed spans whose pages were decommitted right away; see
+    /// `free_decommit_min`.
+    pub free_decommit_bytes: u64,
+    /// Freed spans held back from reuse by the cool-down queue; see
+    /// `span_cooldown`.
+    pub cooldown_spans: u64,
+    /// Times the page heap grew while a span in the cool-down queue was big
+    /// enough to serve the allocation: reuse the cool-down delayed.
+    pub cooldown_delayed_reuses: u64,
+    /// Batches pushed out of the transfer cache to the central free lists to
+    /// stay within `max_transfer_bytes`.
+    pub transfer_cache_evictions: u64,
+    /// Bytes thread caches gave back to the transfer cache because a size
+    /// class sat unused for `thread_cache_decay_ms`.
+    pub thread_cache_decay_bytes: u64,
+    /// Bytes of large allocations given back to the page heap when a
+    /// realloc shrank them in place.
+    pub realloc_trim_bytes: u64,
+    /// Reallocs that returned the same pointer: the new size fit the
+    /// object's size class or span, or a large span was trimmed.
+    pub realloc_in_place: u64,
+    /// Reallocs that allocated anew, copied and freed the old object. With
+    /// `realloc_in_place` this adds up to `realloc_count`, less failed
+    /// moves.
+    pub realloc_moved: u64,
+    /// Bytes copied by moving reallocs.
+    pub realloc_copy_bytes: u64,
+    /// Bytes the transfer cache holds now, across all classes. A level
+    /// rather than a count; 0 without a transfer cache.
+    pub transfer_cache_bytes: u64,
+    /// Successful calls to `platform::page_alloc`.
     pub os_alloc_count: u64,
     /// Bytes requested from the OS via `platform::page_alloc`.
     pub os_alloc_bytes: u64,
+    /// Total nanoseconds spent in OS allocation calls, including prefaulting
+    /// when `prefault` is enabled. Always 0 without the `std` feature.
+    pub os_alloc_nanos: u64,
+    /// Slowest single OS allocation call in nanoseconds. Always 0 without `std`.
+    pub os_alloc_max_nanos: u64,
+    /// OS allocation calls that were refused, such as the smaller retries
+    /// of a page heap growth under a commit limit. Not counted in
+    /// `os_alloc_count` or `os_alloc_bytes`.
+    pub os_alloc_failures: u64,
     /// Times a span was split (carve_span produced a remainder).
     pub span_splits: u64,
     /// Times two adjacent free spans were merged.
@@ -116,11 +281,603 @@ pub fn snapshot() -> Snapshot {
         alloc_bytes: s.alloc_bytes.load(Ordering::Relaxed),
         thread_cache_hits: s.thread_cache_hits.load(Ordering::Relaxed),
         thread_cache_misses: s.thread_cache_misses.load(Ordering::Relaxed),
+        transfer_cache_hits: s.transfer_cache_hits.load(Ordering::Relaxed),
         central_cache_hits: s.central_cache_hits.load(Ordering::Relaxed),
         page_heap_allocs: s.page_heap_allocs.load(Ordering::Relaxed),
+        mid_cache_hits: s.mid_cache_hits.load(Ordering::Relaxed),
+        mid_cache_reclaims: s.mid_cache_reclaims.load(Ordering::Relaxed),
+        central_populate_waits: s.central_populate_waits.load(Ordering::Relaxed),
+        class_cap_hits: s.class_cap_hits.load(Ordering::Relaxed),
+        zeroed_fresh_bytes: s.zeroed_fresh_bytes.load(Ordering::Relaxed),
+        zeroed_decommit_bytes: s.zeroed_decommit_bytes.load(Ordering::Relaxed),
+        free_decommit_bytes: s.free_decommit_bytes.load(Ordering::Relaxed),
+        cooldown_spans: s.cooldown_spans.load(Ordering::Relaxed),
+        cooldown_delayed_reuses: s.cooldown_delayed_reuses.load(Ordering::Relaxed),
+        transfer_cache_evictions: s.transfer_cache_evictions.load(Ordering::Relaxed),
+        thread_cache_decay_bytes: s.thread_cache_decay_bytes.load(Ordering::Relaxed),
+        realloc_trim_bytes: s.realloc_trim_bytes.load(Ordering::Relaxed),
+        realloc_in_place: s.realloc_in_place.load(Ordering::Relaxed),
+        realloc_moved: s.realloc_moved.load(Ordering::Relaxed),
+        realloc_copy_bytes: s.realloc_copy_bytes.load(Ordering::Relaxed),
+        transfer_cache_bytes: transfer_cache_bytes(None) as u64,
         os_alloc_count: s.os_alloc_count.load(Ordering::Relaxed),
         os_alloc_bytes: s.os_alloc_bytes.load(Ordering::Relaxed),
+        os_alloc_nanos: s.os_alloc_nanos.load(Ordering::Relaxed),
+        os_alloc_max_nanos: s.os_alloc_max_nanos.load(Ordering::Relaxed),
+        os_alloc_failures: s.os_alloc_failures.load(Ordering::Relaxed),
         span_splits: s.span_splits.load(Ordering::Relaxed),
         span_coalesces: s.span_coalesces.load(Ordering::Relaxed),
     }
 }
+
+/// Bytes the transfer cache holds for `size_class`, or across all classes
+/// for `None`. Always 0 without a transfer cache (neither `percpu`,
+/// `nightly` nor `std`).
+pub fn transfer_cache_bytes(size_class: Option<usize>) -> usize {
+    cfg_if::cfg_if! {
+        if #[cfg(any(feature = "percpu", feature = "nightly", feature = "std"))] {
+            let tc = &crate::allocator::TRANSFER_CACHE;
+            match size_class {
+                Some(cls) => tc.cached_bytes(cls),
+                None => tc.total_bytes(),
+            }
+        } else {
+            let _ = size_class;
+            0
+        }
+    }
+}
+
+// ---- Per-class span churn ----
+
+/// Span traffic between one central free list and the page heap.
+struct ClassChurn {
+    populates: AtomicU64,
+    releases: AtomicU64,
+}
+
+static SPAN_CHURN: [ClassChurn; NUM_SIZE_CLASSES] = [const {
+    ClassChurn {
+        populates: AtomicU64::new(0),
+        releases: AtomicU64::new(0),
+    }
+}; NUM_SIZE_CLASSES];
+
+/// Record that the central list of `size_class` took a span from the page heap.
+#[inline]
+pub(crate) fn record_span_populate(size_class: usize) {
+    SPAN_CHURN[size_class]
+        .populates
+        .fetch_add(1, Ordering::Relaxed);
+}
+
+/// Record that the central list of `size_class` gave an empty span back.
+#[inline]
+pub(crate) fn record_span_release(size_class: usize) {
+    SPAN_CHURN[size_class]
+        .releases
+        .fetch_add(1, Ordering::Relaxed);
+}
+
+/// Span churn of one size class.
+///
+/// Many populates closely matched by releases mean the class oscillates
+/// across a span boundary; see `max_retained_spans` in the config.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SpanChurn {
+    /// Spans the central free list took from the page heap.
+    pub populates: u64,
+    /// Empty spans the central free list returned to the page heap.
+    pub releases: u64,
+}
+
+/// Load the span churn counters of `size_class` with `Relaxed` ordering.
+pub fn span_churn(size_class: usize) -> SpanChurn {
+    let c = &SPAN_CHURN[size_class];
+    SpanChurn {
+        populates: c.populates.load(Ordering::Relaxed),
+        releases: c.releases.load(Ordering::Relaxed),
+    }
+}
+
+// ---- Call-site counters ----
+
+/// Counter behind one [`count_site!`](crate::count_site) invocation.
+///
+/// A site joins the list read by [`site`] and [`for_each_site`] the first
+/// time it records, and stays on it; it is never removed.
+pub struct Site {
+    label: &'static str,
+    count: AtomicU64,
+    bytes: AtomicU64,
+    registered: AtomicBool,
+    next: AtomicPtr<Site>,
+}
+
+/// Head of the list of sites that have recorded, newest first.
+static SITES: AtomicPtr<Site> = AtomicPtr::new(ptr::null_mut());
+
+impl Site {
+    pub const fn new(label: &'static str) -> Self {
+        Self {
+            label,
+            count: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            registered: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Count one allocation of `bytes` at this site.
+    #[inline]
+    pub fn record(&'static self, bytes: u64) {
+        if !self.registered.load(Ordering::Relaxed) {
+            self.register();
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    #[cold]
+    fn register(&'static self) {
+        if self.registered.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let me = self as *const Site as *mut Site;
+        let mut head = SITES.load(Ordering::Relaxed);
+        loop {
+            self.next.store(head, Ordering::Relaxed);
+            match SITES.compare_exchange_weak(head, me, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+}
+
+/// Sites that have recorded, newest first.
+fn sites() -> impl Iterator<Item = &'static Site> {
+    let mut next = SITES.load(Ordering::Acquire);
+    core::iter::from_fn(move || {
+        // SAFETY: only `'static` sites are linked, and never unlinked.
+        let site = unsafe { next.as_ref()? };
+        next = site.next.load(Ordering::Acquire);
+        Some(site)
+    })
+}
+
+/// Allocations counted under one label.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SiteCount {
+    /// Times a site with the label ran.
+    pub count: u64,
+    /// Bytes those runs passed.
+    pub bytes: u64,
+}
+
+/// Totals of every [`count_site!`](crate::count_site) with `label`; zero if
+/// none has run yet.
+pub fn site(label: &str) -> SiteCount {
+    let mut total = SiteCount::default();
+    for s in sites().filter(|s| s.label == label) {
+        total.count += s.count.load(Ordering::Relaxed);
+        total.bytes += s.bytes.load(Ordering::Relaxed);
+    }
+    total
+}
+
+/// Call `f` once per label that has counted, with the totals of every site
+/// carrying it, newest label first.
+pub fn for_each_site(mut f: impl FnMut(&'static str, SiteCount)) {
+    for (i, s) in sites().enumerate() {
+        if sites().take(i).all(|earlier| earlier.label != s.label) {
+            f(s.label, site(s.label));
+        }
+    }
+}
+
+// ---- Text reports ----
+
+/// Write every counter, page heap occupancy and every
+/// [`count_site!`](crate::count_site) label to `out`, one `name value` line
+/// each:
+///
+/// ```text
+/// alloc_count 15230
+/// ...
+/// system_bytes 8388608
+/// site.codec.decode.count 12
+/// site.codec.decode.bytes 98304
+/// ```
+///
+/// Formats straight into `out` without allocating, so it works without
+/// `std`: [`print_report`] writes it to stdout, and `rtmalloc_write_stats`
+/// (with `ffi`) to a C callback. No allocator lock is held while `out` runs.
+pub fn write_report(out: &mut impl fmt::Write) -> fmt::Result {
+    for (name, counter) in COUNTER_NAMES.iter().zip(counters()) {
+        writeln!(out, "{name} {}", counter.load(Ordering::Relaxed))?;
+    }
+    writeln!(out, "transfer_cache_bytes {}", transfer_cache_bytes(None))?;
+    let (heap, mid) = heap_usage();
+    writeln!(out, "system_bytes {}", heap.system_bytes)?;
+    writeln!(out, "free_spans {}", heap.free_spans)?;
+    writeln!(out, "free_bytes {}", heap.free_bytes)?;
+    writeln!(out, "cooling_spans {}", heap.cooling_spans)?;
+    writeln!(out, "cooling_bytes {}", heap.cooling_bytes)?;
+    writeln!(out, "mid_cached_bytes {mid}")?;
+    let mut result = Ok(());
+    for_each_site(|label, total| {
+        if result.is_ok() {
+            result = writeln!(out, "site.{label}.count {}", total.count)
+                .and_then(|()| writeln!(out, "site.{label}.bytes {}", total.bytes));
+        }
+    });
+    result
+}
+
+/// Write a table of the size classes to `out`: size, pages per span and
+/// batch size, objects free in the central list and cached above it, and
+/// span churn, one row per class. Like [`write_report`], it needs no `std`;
+/// [`print_class_report`] prints it and `rtmalloc_write_class_report` hands
+/// it to a C callback.
+pub fn write_class_report(out: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(
+        out,
+        "{:>5} {:>8} {:>5} {:>5} {:>10} {:>10} {:>9} {:>9}",
+        "class", "size", "pages", "batch", "central", "cached", "populates", "releases"
+    )?;
+    for cls in 1..NUM_SIZE_CLASSES {
+        let [
+            class,
+            size,
+            pages,
+            batch,
+            central,
+            cached,
+            populates,
+            releases,
+        ] = class_row(cls);
+        writeln!(
+            out,
+            "{class:>5} {size:>8} {pages:>5} {batch:>5} {central:>10} {cached:>10} \
+             {populates:>9} {releases:>9}"
+        )?;
+    }
+    Ok(())
+}
+
+/// Print [`write_report`] to stdout.
+#[cfg(feature = "std")]
+pub fn print_report() {
+    let mut text = std::string::String::new();
+    let _ = write_report(&mut text);
+    std::print!("{text}");
+}
+
+/// Print [`write_class_report`] to stdout.
+#[cfg(feature = "std")]
+pub fn print_class_report() {
+    let mut text = std::string::String::new();
+    let _ = write_class_report(&mut text);
+    std::print!("{text}");
+}
+
+// ---- Binary dump ----
+
+/// First bytes of a [`dump_binary`] stream.
+pub const DUMP_MAGIC: [u8; 8] = *b"RTMSTATS";
+/// Version of the [`dump_binary`] framing. Adding tables or columns does not
+/// change it; readers look both up by name.
+pub const DUMP_VERSION: u32 = 1;
+
+/// The [`Stats`] counters as an array, in [`COUNTER_NAMES`] order.
+fn counters() -> &'static [AtomicU64; COUNTER_NAMES.len()] {
+    // SAFETY: `Stats` is `repr(C)` and made only of `AtomicU64`s, the same
+    // layout as the array (see also `coredump`).
+    unsafe { &*(&STATS as *const Stats as *const [AtomicU64; COUNTER_NAMES.len()]) }
+}
+
+/// Columns of the per-class table of [`dump_binary`] and
+/// [`write_class_report`].
+const CLASS_COLUMNS: [&str; 8] = [
+    "class",
+    "size",
+    "pages",
+    "batch_size",
+    "central_free_objects",
+    "cached_objects",
+    "span_populates",
+    "span_releases",
+];
+
+/// The [`CLASS_COLUMNS`] of `cls`. The central list count reads as 0 from
+/// inside the allocator.
+fn class_row(cls: usize) -> [u64; CLASS_COLUMNS.len()] {
+    use crate::size_class;
+
+    let info = size_class::class_info(cls);
+    let central = match crate::bootstrap::ReentrancyGuard::enter() {
+        Some(_guard) => crate::allocator::CENTRAL_CACHE.free_objects(cls),
+        None => 0,
+    };
+    cfg_if::cfg_if! {
+        if #[cfg(any(feature = "percpu", feature = "nightly", feature = "std"))] {
+            let cached = crate::allocator::TRANSFER_CACHE.cached_objects(cls);
+        } else {
+            let cached = crate::allocator::OBJECT_STACKS.cached_objects(cls);
+        }
+    }
+    let churn = span_churn(cls);
+    [
+        cls as u64,
+        info.size as u64,
+        size_class::span_pages(cls) as u64,
+        size_class::batch_size(cls) as u64,
+        central as u64,
+        cached as u64,
+        churn.populates,
+        churn.releases,
+    ]
+}
+
+/// Page heap occupancy and the bytes parked in the mid-heap; zero from
+/// inside the allocator.
+fn heap_usage() -> (crate::page_heap::PageHeapUsage, usize) {
+    use crate::allocator::{MID_HEAP, PAGE_HEAP};
+
+    match crate::bootstrap::ReentrancyGuard::enter() {
+        Some(_guard) => (PAGE_HEAP.lock().usage(), MID_HEAP.cached_bytes()),
+        None => Default::default(),
+    }
+}
+
+/// Write a length-prefixed name.
+fn dump_name(out: &mut impl FnMut(&[u8]), name: &[u8]) {
+    out(&[name.len() as u8]);
+    out(name);
+}
+
+/// Write a table header: name, column count, row count, column names. The
+/// rows follow as `rows * columns.len()` little-endian `u64`s.
+fn dump_table_header(out: &mut impl FnMut(&[u8]), name: &str, columns: &[&str], rows: usize) {
+    dump_name(out, name.as_bytes());
+    out(&(columns.len() as u16).to_le_bytes());
+    out(&(rows as u32).to_le_bytes());
+    for column in columns {
+        dump_name(out, column.as_bytes());
+    }
+}
+
+fn dump_row(out: &mut impl FnMut(&[u8]), row: &[u64]) {
+    for v in row {
+        out(&v.to_le_bytes());
+    }
+}
+
+/// Write a compact binary snapshot of every counter, the per-class tables
+/// and page heap occupancy, passing it to `out` in consecutive chunks.
+///
+/// The stream is self-describing: [`DUMP_MAGIC`], [`DUMP_VERSION`] and the
+/// build's config fingerprint (all little-endian), then tables, each with a
+/// name, its column names and rows of `u64`, ended by an empty table name.
+/// Tables written:
+///
+/// - `counters`: one row, every [`Snapshot`] field.
+/// - `classes`: one row per size class: `class`, `size`, `pages`,
+///   `batch_size`, `central_free_objects`, `cached_objects` (transfer cache
+///   or object stack), `span_populates`, `span_releases`.
+/// - `page_heap`: one row: `system_bytes`, `free_spans`, `free_bytes`,
+///   `mid_cached_bytes`, `cooling_spans`, `cooling_bytes`.
+/// - `site.<label>`: one per [`count_site!`](crate::count_site) label that
+///   has counted, one row: `count`, `bytes`. Labels longer than the name
+///   limit are cut short.
+/// - `latency` (with `latency-histogram`): one row per [`SlowPath`], an
+///   `event` column then `b0..b31`, bucket `bN` counting events of
+///   `[2^N, 2^(N+1))` ns.
+///
+/// `std` builds can load and compare dumps with
+/// [`stats_dump`](crate::stats_dump). No allocator lock is held while `out`
+/// runs, so it may allocate. Like [`mallinfo2`](crate::ffi::mallinfo2),
+/// locked values read as 0 when called from inside the allocator.
+pub fn dump_binary(mut out: impl FnMut(&[u8])) {
+    let out = &mut out;
+    out(&DUMP_MAGIC);
+    out(&DUMP_VERSION.to_le_bytes());
+    out(&crate::version::CONFIG_FINGERPRINT.to_le_bytes());
+
+    let mut columns = [""; COUNTER_NAMES.len() + 1];
+    columns[..COUNTER_NAMES.len()].copy_from_slice(&COUNTER_NAMES);
+    columns[COUNTER_NAMES.len()] = "transfer_cache_bytes";
+    dump_table_header(out, "counters", &columns, 1);
+    for c in counters() {
+        dump_row(out, &[c.load(Ordering::Relaxed)]);
+    }
+    dump_row(out, &[transfer_cache_bytes(None) as u64]);
+
+    dump_table_header(out, "classes", &CLASS_COLUMNS, NUM_SIZE_CLASSES - 1);
+    for cls in 1..NUM_SIZE_CLASSES {
+        dump_row(out, &class_row(cls));
+    }
+
+    let (heap, mid) = heap_usage();
+    dump_table_header(
+        out,
+        "page_heap",
+        &[
+            "system_bytes",
+            "free_spans",
+            "free_bytes",
+            "mid_cached_bytes",
+            "cooling_spans",
+            "cooling_bytes",
+        ],
+        1,
+    );
+    dump_row(
+        out,
+        &[
+            heap.system_bytes as u64,
+            heap.free_spans as u64,
+            heap.free_bytes as u64,
+            mid as u64,
+            heap.cooling_spans as u64,
+            heap.cooling_bytes as u64,
+        ],
+    );
+
+    for_each_site(|label, total| {
+        let mut name = [0u8; u8::MAX as usize];
+        name[..5].copy_from_slice(b"site.");
+        let mut len = label.len().min(name.len() - 5);
+        while !label.is_char_boundary(len) {
+            len -= 1;
+        }
+        name[5..5 + len].copy_from_slice(&label.as_bytes()[..len]);
+        let name = core::str::from_utf8(&name[..5 + len]).unwrap_or("site.");
+        dump_table_header(out, name, &["count", "bytes"], 1);
+        dump_row(out, &[total.count, total.bytes]);
+    });
+
+    #[cfg(feature = "latency-histogram")]
+    {
+        dump_name(out, b"latency");
+        out(&(NUM_LATENCY_BUCKETS as u16 + 1).to_le_bytes());
+        out(&(NUM_SLOW_PATHS as u32).to_le_bytes());
+        dump_name(out, b"event");
+        for i in 0..NUM_LATENCY_BUCKETS {
+            let name = [b'b', b'0' + (i / 10) as u8, b'0' + (i % 10) as u8];
+            let name: &[u8] = if i < 10 { &[name[0], name[2]] } else { &name };
+            dump_name(out, name);
+        }
+        let latency = latency_snapshot();
+        for (i, event) in [
+            SlowPath::CentralRefill,
+            SlowPath::PageHeapGrow,
+            SlowPath::OsAlloc,
+            SlowPath::CentralRelease,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            dump_row(out, &[i as u64]);
+            dump_row(out, &latency.get(event).counts);
+        }
+    }
+
+    dump_name(out, b"");
+}
+
+// ---- Slow-path latency histograms (`latency-histogram` feature) ----
+
+/// Number of latency buckets. Bucket `i` counts events that took
+/// `[2^i, 2^(i+1))` nanoseconds (bucket 0 also holds sub-nanosecond events);
+/// the last bucket is open-ended (~2.1 s and up).
+#[cfg(feature = "latency-histogram")]
+pub const NUM_LATENCY_BUCKETS: usize = 32;
+
+/// Allocator slow-path events that are timed.
+///
+/// Events nest: a central refill may grow the page heap, which in turn calls
+/// the OS, so one slow allocation can be recorded in all three histograms.
+#[cfg(feature = "latency-histogram")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlowPath {
+    /// Thread/CPU cache refill that missed the transfer cache and went to the
+    /// central free list.
+    CentralRefill = 0,
+    /// Page heap found no free span and grew from the OS.
+    PageHeapGrow = 1,
+    /// A single OS mapping call (`mmap` / `VirtualAlloc`).
+    OsAlloc = 2,
+    /// One chunk of a batch freed to the central free list: how long its
+    /// lock was held, at most `max_objects_per_lock` objects.
+    CentralRelease = 3,
+}
+
+#[cfg(feature = "latency-histogram")]
+const NUM_SLOW_PATHS: usize = 4;
+
+#[cfg(feature = "latency-histogram")]
+static LATENCY: [[AtomicU64; NUM_LATENCY_BUCKETS]; NUM_SLOW_PATHS] =
+    [const { [const { AtomicU64::new(0) }; NUM_LATENCY_BUCKETS] }; NUM_SLOW_PATHS];
+
+/// Record one slow-path event that took `nanos` nanoseconds.
+#[cfg(feature = "latency-histogram")]
+#[inline]
+pub fn record_latency(event: SlowPath, nanos: u64) {
+    let bucket = (u64::BITS - 1 - (nanos | 1).leading_zeros()) as usize;
+    LATENCY[event as usize][bucket.min(NUM_LATENCY_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Point-in-time copy of one slow-path latency histogram.
+#[cfg(feature = "latency-histogram")]
+#[derive(Clone, Copy, Debug)]
+pub struct LatencyHistogram {
+    /// Event counts per power-of-two nanosecond bucket.
+    pub counts: [u64; NUM_LATENCY_BUCKETS],
+}
+
+#[cfg(feature = "latency-histogram")]
+impl LatencyHistogram {
+    /// Total number of recorded events.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Upper bound in nanoseconds of the `p`-th percentile (`0.0..=100.0`),
+    /// or 0 if nothing was recorded. Resolution is one power of two.
+    pub fn percentile(&self, p: f64) -> u64 {
+        let total = self.count();
+        if total == 0 {
+            return 0;
+        }
+        let rank = ((p.clamp(0.0, 100.0) / 100.0) * total as f64)
+            .ceil()
+            .max(1.0) as u64;
+        let mut seen = 0;
+        for (i, &c) in self.counts.iter().enumerate() {
+            seen += c;
+            if seen >= rank {
+                return 1u64 << (i + 1);
+            }
+        }
+        1u64 << NUM_LATENCY_BUCKETS
+    }
+}
+
+/// Latency histograms for every [`SlowPath`] event.
+#[cfg(feature = "latency-histogram")]
+#[derive(Clone, Copy, Debug)]
+pub struct LatencySnapshot {
+    pub central_refill: LatencyHistogram,
+    pub page_heap_grow: LatencyHistogram,
+    pub os_alloc: LatencyHistogram,
+    pub central_release: LatencyHistogram,
+}
+
+#[cfg(feature = "latency-histogram")]
+impl LatencySnapshot {
+    /// Histogram for a single event kind.
+    pub fn get(&self, event: SlowPath) -> &LatencyHistogram {
+        match event {
+            SlowPath::CentralRefill => &self.central_refill,
+            SlowPath::PageHeapGrow => &self.page_heap_grow,
+            SlowPath::OsAlloc => &self.os_alloc,
+            SlowPath::CentralRelease => &self.central_release,
+        }
+    }
+}
+
+/// Load all slow-path latency histograms with `Relaxed` ordering.
+#[cfg(feature = "latency-histogram")]
+pub fn latency_snapshot() -> LatencySnapshot {
+    let load = |event: SlowPath| LatencyHistogram {
+        counts: core::array::from_fn(|i| LATENCY[event as usize][i].load(Ordering::Relaxed)),
+    };
+    LatencySnapshot {
+        central_refill: load(SlowPath::CentralRefill),
+        page_heap_grow: load(SlowPath::PageHeapGrow),
+        os_alloc: load(SlowPath::OsAlloc),
+        central_release: load(SlowPath::CentralRelease),
+    }
+}
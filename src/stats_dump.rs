@@ -0,0 +1,224 @@
+//! Load and compare snapshots written by [`stats::dump_binary`].
+//!
+//! Meant for memory regression checks in CI: save a dump after a workload,
+//! load the one from a baseline run, and look at what moved.
+//!
+//! ```ignore
+//! rtmalloc::stats_dump::save("after.rtmstats")?;
+//! let base = rtmalloc::stats_dump::read("baseline.rtmstats")?;
+//! let now = rtmalloc::stats_dump::read("after.rtmstats")?;
+//! for change in now.diff(&base) {
+//!     println!("{change}");
+//! }
+//! ```
+
+use crate::stats::{self, DUMP_MAGIC, DUMP_VERSION};
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::string::String;
+use std::vec::Vec;
+
+/// One table of a dump: named columns and rows of `u64`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Table {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<u64>>,
+}
+
+impl Table {
+    /// Index of the column called `name`.
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c == name)
+    }
+
+    /// Value of `column` in row `row`.
+    pub fn get(&self, row: usize, column: &str) -> Option<u64> {
+        Some(self.rows.get(row)?[self.column(column)?])
+    }
+}
+
+/// A parsed [`stats::dump_binary`] snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dump {
+    /// Framing version, [`DUMP_VERSION`].
+    pub version: u32,
+    /// Config fingerprint of the build that wrote the dump. Per-class rows
+    /// only line up between dumps with the same fingerprint.
+    pub fingerprint: u64,
+    pub tables: Vec<Table>,
+}
+
+/// One value that differs between two dumps.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub table: String,
+    pub row: usize,
+    pub column: String,
+    pub before: u64,
+    pub after: u64,
+}
+
+impl Change {
+    /// `after - before`.
+    pub fn delta(&self) -> i128 {
+        self.after as i128 - self.before as i128
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}].{}: {} -> {} ({:+})",
+            self.table,
+            self.row,
+            self.column,
+            self.before,
+            self.after,
+            self.delta()
+        )
+    }
+}
+
+impl Dump {
+    /// Table called `name`.
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+
+    /// Value of `column` in row `row` of table `table`.
+    pub fn get(&self, table: &str, row: usize, column: &str) -> Option<u64> {
+        self.table(table)?.get(row, column)
+    }
+
+    /// Every value that differs from `base`, table by table in this dump's
+    /// order. Rows are matched by index and columns by name; a table, row or
+    /// column missing on one side counts as 0 there.
+    pub fn diff(&self, base: &Dump) -> Vec<Change> {
+        let empty = Table {
+            name: String::new(),
+            columns: Vec::new(),
+            rows: Vec::new(),
+        };
+        let names = self.tables.iter().chain(&base.tables).map(|t| &t.name);
+        let mut seen = Vec::new();
+        let mut changes = Vec::new();
+        for name in names {
+            if seen.contains(&name) {
+                continue;
+            }
+            seen.push(name);
+            let after = self.table(name).unwrap_or(&empty);
+            let before = base.table(name).unwrap_or(&empty);
+            let mut columns: Vec<&String> = after.columns.iter().collect();
+            columns.extend(before.columns.iter().filter(|c| !after.columns.contains(c)));
+            for row in 0..after.rows.len().max(before.rows.len()) {
+                for column in &columns {
+                    let old = before.get(row, column).unwrap_or(0);
+                    let new = after.get(row, column).unwrap_or(0);
+                    if old != new {
+                        changes.push(Change {
+                            table: name.clone(),
+                            row,
+                            column: (*column).clone(),
+                            before: old,
+                            after: new,
+                        });
+                    }
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Take a dump of the current process with [`stats::dump_binary`].
+pub fn capture() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    stats::dump_binary(|chunk| bytes.extend_from_slice(chunk));
+    bytes
+}
+
+/// Write a dump of the current process to `path`.
+pub fn save(path: impl AsRef<Path>) -> io::Result<()> {
+    std::fs::write(path, capture())
+}
+
+/// Read a dump written by [`save`].
+pub fn read(path: impl AsRef<Path>) -> io::Result<Dump> {
+    parse(&std::fs::read(path)?)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Cursor over the dump bytes.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(invalid("truncated stats dump"));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn name(&mut self) -> io::Result<String> {
+        let len = self.array::<1>()?[0] as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("bad name in stats dump"))
+    }
+}
+
+/// Parse a dump produced by [`stats::dump_binary`].
+pub fn parse(bytes: &[u8]) -> io::Result<Dump> {
+    let mut r = Reader(bytes);
+    if r.array::<8>().ok() != Some(DUMP_MAGIC) {
+        return Err(invalid("not an rtmalloc stats dump"));
+    }
+    let version = u32::from_le_bytes(r.array()?);
+    if version != DUMP_VERSION {
+        return Err(invalid("unsupported stats dump version"));
+    }
+    let fingerprint = u64::from_le_bytes(r.array()?);
+    let mut tables = Vec::new();
+    loop {
+        let name = r.name()?;
+        if name.is_empty() {
+            break;
+        }
+        let num_columns = u16::from_le_bytes(r.array()?) as usize;
+        let num_rows = u32::from_le_bytes(r.array()?) as usize;
+        let columns = (0..num_columns)
+            .map(|_| r.name())
+            .collect::<io::Result<Vec<_>>>()?;
+        if num_rows.saturating_mul(num_columns).saturating_mul(8) > r.0.len() {
+            return Err(invalid("truncated stats dump"));
+        }
+        let rows = (0..num_rows)
+            .map(|_| {
+                (0..num_columns)
+                    .map(|_| Ok(u64::from_le_bytes(r.array()?)))
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        tables.push(Table {
+            name,
+            columns,
+            rows,
+        });
+    }
+    Ok(Dump {
+        version,
+        fingerprint,
+        tables,
+    })
+}
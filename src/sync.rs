@@ -2,14 +2,96 @@
 //!
 //! We cannot use `std::sync::Mutex` because it allocates. Instead we provide
 //! a simple test-and-set spinlock and a `SpinMutex<T>` wrapper.
+//!
+//! Spin waits can't yield to an OS scheduler without `std`. On a single-core
+//! RTOS target, a thread spinning on a lock held by a preempted thread never
+//! lets the holder run; register the scheduler's yield with
+//! [`set_spin_relax`] there.
+//!
+//! # Lock debugging
+//!
+//! A thread that takes an allocator lock it already holds spins forever.
+//! That is what happens when a signal handler or a hook (a failure or cap
+//! handler, a `tracing` subscriber) allocates while the allocator it
+//! interrupted holds a lock, and all it leaves behind is a hung process.
+//! The `lock-debug` feature makes every lock remember its [`Holder`]: the
+//! holder's thread id and where it took the lock. A thread that then tries
+//! to take a lock it holds aborts at once with
+//! [`Failure::LockReentered`](crate::failure::Failure::LockReentered):
+//!
+//! ```text
+//! rtmalloc: lock re-entered by the thread holding it (0x55b8b28714a0), held since src/allocator.rs:803:43, taken at src/allocator.rs:803:43
+//! ```
+//!
+//! Builds with a reentrancy guard (`stats`, `debug`, `alloc-histogram` or
+//! `tracing`, with `std` or `nightly`) already serve such nested calls from
+//! the bootstrap arena; the check catches the rest, and locks taken twice by
+//! the allocator itself.
+//!
+//! With `std` as well, a lock released by a panic is poisoned, and the next
+//! thread to take it aborts with
+//! [`Failure::LockPoisoned`](crate::failure::Failure::LockPoisoned) instead
+//! of working on the state the panic left half-updated. [`SpinLock::holder`]
+//! tells who holds a lock, e.g. from a debugger or a watchdog.
 
 use core::cell::UnsafeCell;
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "lock-debug")]
+use core::panic::Location;
+#[cfg(feature = "lock-debug")]
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Registered spin relax hook as an address, 0 for the default.
+static SPIN_RELAX: AtomicUsize = AtomicUsize::new(0);
+
+/// Call `relax` on every round of the allocator's spin waits, instead of
+/// [`core::hint::spin_loop`]. Pass `core::hint::spin_loop` to go back to
+/// the default.
+///
+/// It runs inside the allocator, possibly with other allocator locks held,
+/// so it must not allocate or free through rtmalloc. Yielding to another
+/// thread is fine.
+///
+/// ```ignore
+/// rtmalloc::sync::set_spin_relax(|| rtos::task_yield());
+/// ```
+pub fn set_spin_relax(relax: fn()) {
+    SPIN_RELAX.store(relax as usize, Ordering::Release);
+}
+
+/// One round of a spin wait: the hook from [`set_spin_relax`], or a
+/// spin-loop hint.
+#[inline]
+pub fn spin_relax() {
+    match SPIN_RELAX.load(Ordering::Acquire) {
+        0 => core::hint::spin_loop(),
+        addr => unsafe { core::mem::transmute::<usize, fn()>(addr)() },
+    }
+}
+
+/// Who holds a [`SpinLock`] (`lock-debug` feature).
+#[cfg(feature = "lock-debug")]
+#[derive(Clone, Copy, Debug)]
+pub struct Holder {
+    /// [`platform::thread_id`](crate::platform::thread_id) of the holder.
+    pub thread: usize,
+    /// Where the holder took the lock.
+    pub site: &'static Location<'static>,
+}
 
 /// A simple test-and-set spinlock.
 pub struct SpinLock {
     locked: AtomicBool,
+    /// Thread id of the holder, 0 when free.
+    #[cfg(feature = "lock-debug")]
+    owner: AtomicUsize,
+    /// Where the holder took the lock.
+    #[cfg(feature = "lock-debug")]
+    site: AtomicPtr<Location<'static>>,
+    /// Set when a panic released the lock.
+    #[cfg(all(feature = "lock-debug", feature = "std"))]
+    poisoned: AtomicBool,
 }
 
 impl Default for SpinLock {
@@ -22,33 +104,45 @@ impl SpinLock {
     pub const fn new() -> Self {
         Self {
             locked: AtomicBool::new(false),
+            #[cfg(feature = "lock-debug")]
+            owner: AtomicUsize::new(0),
+            #[cfg(feature = "lock-debug")]
+            site: AtomicPtr::new(core::ptr::null_mut()),
+            #[cfg(all(feature = "lock-debug", feature = "std"))]
+            poisoned: AtomicBool::new(false),
         }
     }
 
     #[inline]
+    #[cfg_attr(feature = "lock-debug", track_caller)]
     pub fn lock(&self) {
         if self
             .locked
             .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
+            self.acquired();
             return;
         }
         self.lock_slow();
     }
 
     #[cold]
+    #[cfg_attr(feature = "lock-debug", track_caller)]
     fn lock_slow(&self) {
+        #[cfg(feature = "lock-debug")]
+        self.check_reentry();
         loop {
             // Spin while locked (read-only, doesn't invalidate cache line)
             while self.locked.load(Ordering::Relaxed) {
-                core::hint::spin_loop();
+                spin_relax();
             }
             if self
                 .locked
                 .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
                 .is_ok()
             {
+                self.acquired();
                 return;
             }
         }
@@ -56,14 +150,87 @@ impl SpinLock {
 
     #[inline]
     pub fn unlock(&self) {
+        #[cfg(all(feature = "lock-debug", feature = "std"))]
+        if std::thread::panicking() {
+            self.poisoned.store(true, Ordering::Relaxed);
+        }
+        #[cfg(feature = "lock-debug")]
+        self.owner.store(0, Ordering::Relaxed);
         self.locked.store(false, Ordering::Release);
     }
 
     #[inline]
+    #[cfg_attr(feature = "lock-debug", track_caller)]
     pub fn try_lock(&self) -> bool {
-        self.locked
+        let locked = self
+            .locked
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
-            .is_ok()
+            .is_ok();
+        if locked {
+            self.acquired();
+        }
+        locked
+    }
+
+    /// Who holds the lock, or `None` if it is free. Racy: the answer may be
+    /// stale by the time it is looked at.
+    #[cfg(feature = "lock-debug")]
+    pub fn holder(&self) -> Option<Holder> {
+        let thread = self.owner.load(Ordering::Acquire);
+        let site = self.site.load(Ordering::Relaxed);
+        if thread == 0 || site.is_null() {
+            return None;
+        }
+        Some(Holder {
+            thread,
+            site: unsafe { &*site },
+        })
+    }
+
+    /// Record the calling thread as the holder, after a poisoned check.
+    #[inline(always)]
+    #[cfg_attr(feature = "lock-debug", track_caller)]
+    fn acquired(&self) {
+        #[cfg(feature = "lock-debug")]
+        {
+            let at = Location::caller();
+            #[cfg(feature = "std")]
+            if self.poisoned.load(Ordering::Relaxed) {
+                let held = self.site.load(Ordering::Relaxed);
+                let held = if held.is_null() {
+                    at
+                } else {
+                    unsafe { &*held }
+                };
+                crate::failure::lock_fatal(
+                    crate::failure::Failure::LockPoisoned,
+                    self as *const Self as usize,
+                    held,
+                    at,
+                );
+            }
+            self.site
+                .store(core::ptr::from_ref(at).cast_mut(), Ordering::Relaxed);
+            self.owner
+                .store(crate::platform::thread_id(), Ordering::Release);
+        }
+    }
+
+    /// Abort if the calling thread already holds the lock: spinning would
+    /// never end.
+    #[cfg(feature = "lock-debug")]
+    #[track_caller]
+    fn check_reentry(&self) {
+        if let Some(holder) = self.holder()
+            && holder.thread == crate::platform::thread_id()
+        {
+            crate::failure::lock_fatal(
+                crate::failure::Failure::LockReentered,
+                self as *const Self as usize,
+                holder.site,
+                Location::caller(),
+            );
+        }
     }
 }
 
@@ -78,6 +245,10 @@ pub struct SpinMutex<T> {
 }
 
 impl<T> SpinMutex<T> {
+    /// Offset of the protected value, for out-of-process readers.
+    #[cfg(feature = "coredump")]
+    pub(crate) const DATA_OFFSET: usize = core::mem::offset_of!(Self, data);
+
     pub const fn new(val: T) -> Self {
         Self {
             lock: SpinLock::new(),
@@ -86,12 +257,14 @@ impl<T> SpinMutex<T> {
     }
 
     #[inline]
+    #[cfg_attr(feature = "lock-debug", track_caller)]
     pub fn lock(&self) -> SpinMutexGuard<'_, T> {
         self.lock.lock();
         SpinMutexGuard { mutex: self }
     }
 
     #[inline]
+    #[cfg_attr(feature = "lock-debug", track_caller)]
     pub fn try_lock(&self) -> Option<SpinMutexGuard<'_, T>> {
         if self.lock.try_lock() {
             Some(SpinMutexGuard { mutex: self })
@@ -99,6 +272,12 @@ impl<T> SpinMutex<T> {
             None
         }
     }
+
+    /// Who holds the mutex; see [`SpinLock::holder`].
+    #[cfg(feature = "lock-debug")]
+    pub fn holder(&self) -> Option<Holder> {
+        self.lock.holder()
+    }
 }
 
 unsafe impl<T: Send> Send for SpinMutex<T> {}
@@ -132,6 +311,46 @@ impl<T> Drop for SpinMutexGuard<'_, T> {
     }
 }
 
+/// Pads and aligns a value to a cache line of its own, so that neighbouring
+/// elements of an array (the per-class locks of the central and transfer
+/// caches) never share one and a thread hammering one class doesn't slow
+/// threads working on the next.
+///
+/// The line is `cache_line_size` bytes from the build config: 128 by
+/// default on aarch64, 64 elsewhere. `minimal` builds don't pad; their
+/// targets rarely have more than one core to contend.
+#[cfg_attr(
+    all(not(feature = "minimal"), not(rtmalloc_cache_line_128)),
+    repr(align(64))
+)]
+#[cfg_attr(
+    all(not(feature = "minimal"), rtmalloc_cache_line_128),
+    repr(align(128))
+)]
+pub struct CachePadded<T>(T);
+
+#[cfg(not(feature = "minimal"))]
+const _: () = assert!(core::mem::align_of::<CachePadded<u8>>() == crate::config::CACHE_LINE_SIZE);
+
+impl<T> CachePadded<T> {
+    /// Offset of the padded value, for out-of-process readers.
+    #[cfg(feature = "coredump")]
+    pub(crate) const DATA_OFFSET: usize = core::mem::offset_of!(Self, 0);
+
+    pub const fn new(val: T) -> Self {
+        Self(val)
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -155,6 +374,33 @@ mod tests {
         lock.unlock();
     }
 
+    #[test]
+    fn test_spin_relax_hook() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        fn relax() {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            core::hint::spin_loop();
+        }
+        set_spin_relax(relax);
+
+        let lock = Arc::new(SpinLock::new());
+        lock.lock();
+        let waiter = {
+            let lock = Arc::clone(&lock);
+            std::thread::spawn(move || {
+                lock.lock();
+                lock.unlock();
+            })
+        };
+        while CALLS.load(Ordering::Relaxed) == 0 {
+            std::thread::yield_now();
+        }
+        lock.unlock();
+        waiter.join().unwrap();
+        set_spin_relax(core::hint::spin_loop);
+        assert!(CALLS.load(Ordering::Relaxed) > 0);
+    }
+
     #[test]
     fn test_spinmutex_basic() {
         let mutex = SpinMutex::new(42u64);
@@ -197,4 +443,59 @@ mod tests {
         let guard = mutex.lock();
         assert_eq!(*guard, num_threads * iterations);
     }
+
+    #[cfg(feature = "lock-debug")]
+    #[test]
+    fn test_holder_recorded() {
+        let mutex = SpinMutex::new(0u32);
+        assert!(mutex.holder().is_none());
+        let guard = mutex.lock();
+        let line = line!() - 1;
+        let holder = mutex.holder().unwrap();
+        assert_eq!(holder.thread, crate::platform::thread_id());
+        assert_eq!(holder.site.file(), file!());
+        assert_eq!(holder.site.line(), line);
+        drop(guard);
+        assert!(mutex.holder().is_none());
+    }
+
+    #[cfg(feature = "lock-debug")]
+    #[test]
+    #[should_panic(expected = "lock re-entered by the thread holding it")]
+    fn test_reentry_detected() {
+        let mutex = SpinMutex::new(0u32);
+        let _outer = mutex.lock();
+        let _inner = mutex.lock();
+    }
+
+    #[cfg(feature = "lock-debug")]
+    #[test]
+    fn test_other_thread_waits() {
+        let mutex = Arc::new(SpinMutex::new(0u32));
+        let guard = mutex.lock();
+        let waiter = {
+            let mutex = Arc::clone(&mutex);
+            std::thread::spawn(move || *mutex.lock() += 1)
+        };
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        drop(guard);
+        waiter.join().unwrap();
+        assert_eq!(*mutex.lock(), 1);
+    }
+
+    #[cfg(all(feature = "lock-debug", feature = "std"))]
+    #[test]
+    #[should_panic(expected = "lock poisoned by a panic")]
+    fn test_panic_poisons() {
+        let mutex = Arc::new(SpinMutex::new(0u32));
+        let poisoner = {
+            let mutex = Arc::clone(&mutex);
+            std::thread::spawn(move || {
+                let _guard = mutex.lock();
+                panic!("while holding the lock");
+            })
+        };
+        assert!(poisoner.join().is_err());
+        let _guard = mutex.lock();
+    }
 }
@@ -0,0 +1,83 @@
+//! Requests about the calling thread's cache.
+//!
+//! Thread caches keep freed objects for reuse and only give them back in
+//! batches, as they overflow or when the thread exits. A thread that is about
+//! to sleep for a long time, or a process about to fork workers, can hand its
+//! cache back early instead.
+//!
+//! Safe to call from any thread at any time. With `percpu`, caches belong to
+//! CPUs rather than threads and these calls do nothing; likewise without a
+//! thread cache (neither `nightly` nor `std`).
+//!
+//! ```ignore
+//! // Before parking an idle worker:
+//! rtmalloc::thread::flush_current_cache();
+//! ```
+//!
+//! Threads can also pick an arena: a pool of spans for small objects that
+//! only the threads in that arena allocate from. Built with `num_arenas`
+//! above 1, a latency-critical thread moved to its own arena never gets an
+//! object whose cache lines it shares with another thread's objects.
+//!
+//! ```ignore
+//! // At the start of the audio thread:
+//! assert!(rtmalloc::thread::set_arena(1));
+//! ```
+
+/// Give everything the calling thread has cached to the transfer cache.
+///
+/// Any thread can then pick the objects up in batches, and the central free
+/// lists can return spans that become empty. The cache's budget above the
+/// per-thread minimum goes back to the global pool. The cache itself stays:
+/// the thread's next allocations refill it as usual.
+#[inline]
+pub fn flush_current_cache() {
+    // Called from inside the allocator (e.g. by instrumentation), taking its
+    // locks could deadlock; the request is dropped instead.
+    let Some(_guard) = crate::bootstrap::ReentrancyGuard::enter() else {
+        return;
+    };
+    crate::allocator::flush_thread_cache();
+}
+
+/// Ask every thread to flush its cache, as [`flush_current_cache`] does, the
+/// next time it misses or overflows it.
+///
+/// For code that frees memory on behalf of the whole process, such as a
+/// memory pressure handler (see `rtmalloc::pressure`). A thread that keeps
+/// hitting its cache, or never allocates again, holds on to it until then.
+pub fn request_flush_all() {
+    crate::thread_cache::request_flush();
+}
+
+/// Serve the calling thread's small allocations from spans of `arena`.
+///
+/// Arena 0 is the shared default. Every other arena, up to the `num_arenas`
+/// build setting, has its own central free lists, carves its own spans, and
+/// skips the transfer cache, so its objects never sit in a span with
+/// objects of threads outside it. Threads that share an arena share its
+/// spans. The thread's cache is flushed back to its old arena first.
+///
+/// Objects keep their arena for life. Freeing one on a thread of another
+/// arena sends it straight back to its own arena's central list, taking
+/// that list's lock, where a free within the arena stays in the thread
+/// cache. Large allocations are not affected, nor are allocations made
+/// while caches are bypassed (see [`crate::debug::bypass_caches`]).
+///
+/// Returns false, changing nothing, if `arena` is not below `num_arenas` or
+/// the calling thread has no cache of its own (with `percpu`, without
+/// `nightly` or `std`, or while the thread exits).
+#[inline]
+pub fn set_arena(arena: usize) -> bool {
+    let Some(_guard) = crate::bootstrap::ReentrancyGuard::enter() else {
+        return false;
+    };
+    crate::allocator::set_thread_arena(arena)
+}
+
+/// The arena the calling thread allocates from; 0 unless it called
+/// [`set_arena`].
+#[inline]
+pub fn arena() -> usize {
+    crate::allocator::thread_arena()
+}
@@ -3,47 +3,96 @@
 //! Each thread gets its own ThreadCache via `thread_local!`. The fast path
 //! (thread cache hit) requires zero synchronization. When the thread cache
 //! is empty or full, it batches transfers to/from the central free list.
+//!
+//! With `thread_cache_decay_ms` set, each class also records when it was last
+//! used, and slow paths give back half of every class left idle for a whole
+//! decay window, so a burst's leftovers drain over a few windows instead of
+//! waiting for a scavenge.
+//!
+//! [`request_flush`] asks every cache to flush itself on its owner's next
+//! slow path, for memory pressure handlers that cannot reach other threads'
+//! caches directly.
+//!
+//! A cache moved to a private arena (see [`crate::thread::set_arena`])
+//! skips the shared transfer cache and trades batches with that arena's
+//! central lists directly.
+//!
+//! With `double-free-check`, objects freed into the cache carry a key that
+//! a second free of the same object finds (see `double_free`).
 
-use crate::central_free_list::CentralCache;
+use crate::central_free_list::{self, CentralCache};
 use crate::config::{
-    MAX_DYNAMIC_FREE_LIST_LENGTH, MAX_OVERAGES, MIN_PER_THREAD_CACHE_SIZE,
-    OVERALL_THREAD_CACHE_SIZE, STEAL_AMOUNT,
+    ARRAY_CACHE_SLOTS, MAX_DYNAMIC_FREE_LIST_LENGTH, MAX_OVERAGES, MIN_PER_THREAD_CACHE_SIZE,
+    NUM_ARENAS, OVERALL_THREAD_CACHE_SIZE, STEAL_AMOUNT, THREAD_CACHE_DECAY_MS,
 };
 use crate::page_heap::PageHeap;
 use crate::pagemap::PageMap;
+use crate::platform;
 use crate::size_class::{self, NUM_SIZE_CLASSES};
 use crate::span::FreeObject;
 use crate::sync::SpinMutex;
 use crate::transfer_cache::TransferCacheArray;
 use core::ptr;
-use core::sync::atomic::{AtomicIsize, Ordering};
+use core::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
 
 /// Unclaimed cache budget available for thread caches to claim.
 /// Starts at OVERALL_THREAD_CACHE_SIZE; each thread claims/returns portions.
 static UNCLAIMED_CACHE_SPACE: AtomicIsize = AtomicIsize::new(OVERALL_THREAD_CACHE_SIZE as isize);
 
-/// Per-size-class free list within the thread cache.
+/// Total budget across all thread caches. Starts at OVERALL_THREAD_CACHE_SIZE.
+static OVERALL_CACHE_SIZE: AtomicUsize = AtomicUsize::new(OVERALL_THREAD_CACHE_SIZE);
+
+/// Total bytes all thread caches together may hold.
+pub fn overall_cache_size() -> usize {
+    OVERALL_CACHE_SIZE.load(Ordering::Relaxed)
+}
+
+/// Change the total thread cache budget, at least `min_per_thread_cache`, and
+/// return the value applied.
+///
+/// Growing it makes the difference claimable at once. Shrinking it only stops
+/// threads from claiming more; caches over their share give budget back as
+/// they scavenge or exit.
+pub fn set_overall_cache_size(bytes: usize) -> usize {
+    let bytes = bytes.max(MIN_PER_THREAD_CACHE_SIZE);
+    let old = OVERALL_CACHE_SIZE.swap(bytes, Ordering::Relaxed);
+    UNCLAIMED_CACHE_SPACE.fetch_add(bytes as isize - old as isize, Ordering::Relaxed);
+    bytes
+}
+
+/// Bumped by [`request_flush`]; each cache flushes when it sees a new value.
+static FLUSH_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Ask every thread cache to [`flush`](ThreadCache::flush) on its owner's
+/// next slow path. Threads that only hit their cache, or never allocate
+/// again, keep what they hold until then.
+pub fn request_flush() {
+    FLUSH_REQUESTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Hot per-size-class free list state, touched on every alloc and dealloc.
+///
+/// Packed to 16 bytes so four classes share one cache line and a class never
+/// straddles two. Colder per-class tuning state lives in separate arrays on
+/// [`ThreadCache`].
+#[repr(C)]
 struct FreeList {
     /// Head of the singly-linked intrusive free list.
     head: *mut FreeObject,
     /// Number of objects currently in this list.
     length: u32,
-    /// Maximum length before we return objects to central cache.
-    max_length: u32,
-    /// Consecutive overage count (for shrinking max_length).
-    length_overages: u32,
     /// Minimum length since last scavenge (low-water mark).
     /// Objects above this level were never needed and are safe to release.
     low_water_mark: u32,
 }
 
+const _: () = assert!(core::mem::size_of::<FreeList>() == 16);
+
 impl FreeList {
     const fn new() -> Self {
         Self {
             head: ptr::null_mut(),
             length: 0,
-            max_length: 1, // Start small, grows adaptively
-            length_overages: 0,
             low_water_mark: 0,
         }
     }
@@ -52,7 +101,7 @@ impl FreeList {
     fn pop(&mut self) -> *mut FreeObject {
         let obj = self.head;
         if !obj.is_null() {
-            self.head = unsafe { (*obj).next };
+            self.head = unsafe { FreeObject::next(obj) };
             self.length -= 1;
             if self.length < self.low_water_mark {
                 self.low_water_mark = self.length;
@@ -63,26 +112,17 @@ impl FreeList {
 
     #[inline]
     fn push(&mut self, obj: *mut FreeObject) {
-        unsafe { (*obj).next = self.head };
+        unsafe { FreeObject::set_next(obj, self.head) };
         self.head = obj;
         self.length += 1;
     }
 
-    /// Push a linked list of `count` objects.
-    fn push_batch(&mut self, head: *mut FreeObject, count: u32) {
+    /// Push a linked list of `count` objects ending at `tail`.
+    fn push_batch(&mut self, head: *mut FreeObject, tail: *mut FreeObject, count: u32) {
         if head.is_null() || count == 0 {
             return;
         }
-        // Find the tail of the batch
-        let mut tail = head;
-        for _ in 1..count {
-            let next = unsafe { (*tail).next };
-            if next.is_null() {
-                break;
-            }
-            tail = next;
-        }
-        unsafe { (*tail).next = self.head };
+        unsafe { FreeObject::set_next(tail, self.head) };
         self.head = head;
         self.length += count;
     }
@@ -94,8 +134,8 @@ impl FreeList {
         let mut popped = 0u32;
         while popped < count && !self.head.is_null() {
             let obj = self.head;
-            self.head = unsafe { (*obj).next };
-            unsafe { (*obj).next = head };
+            self.head = unsafe { FreeObject::next(obj) };
+            unsafe { FreeObject::set_next(obj, head) };
             if tail.is_null() {
                 tail = obj; // First popped becomes tail after reversal
             }
@@ -107,13 +147,108 @@ impl FreeList {
     }
 }
 
+/// Small fixed-size array checked before the [`FreeList`] of a size class.
+///
+/// A hit reads the object pointer straight out of the thread cache rather than
+/// loading `head->next` from the object, which removes the dependent load from
+/// the fastest path. Objects parked here are never written to, except for
+/// the `double-free-check` key. Sized by the `array_cache_slots` config;
+/// the default of 0 leaves it out, since it has not yet measured as a win.
+#[repr(C)]
+struct ArrayCache {
+    slots: [*mut FreeObject; ARRAY_CACHE_SLOTS],
+    count: u32,
+}
+
+impl ArrayCache {
+    const fn new() -> Self {
+        Self {
+            slots: [ptr::null_mut(); ARRAY_CACHE_SLOTS],
+            count: 0,
+        }
+    }
+
+    #[inline(always)]
+    fn pop(&mut self) -> *mut FreeObject {
+        if self.count == 0 {
+            return ptr::null_mut();
+        }
+        self.count -= 1;
+        self.slots[self.count as usize]
+    }
+
+    /// Returns false if the array is full.
+    #[inline(always)]
+    fn push(&mut self, obj: *mut FreeObject) -> bool {
+        if self.count as usize >= ARRAY_CACHE_SLOTS {
+            return false;
+        }
+        self.slots[self.count as usize] = obj;
+        self.count += 1;
+        true
+    }
+}
+
+/// One size class of a [`ThreadCacheInfo`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClassCacheInfo {
+    /// Objects in the free list.
+    pub length: u32,
+    /// Objects in the array cache in front of it.
+    pub array_length: u32,
+    /// Free list length above which objects go back to the central lists.
+    pub max_length: u32,
+    /// Lowest free list length since the last scavenge; what sits above it
+    /// was not needed and is released by the next one.
+    pub low_water_mark: u32,
+    /// Bytes held in both.
+    pub bytes: usize,
+}
+
+/// Per-class contents and limits of one thread cache, from
+/// [`ThreadCache::info`].
+#[derive(Clone, Copy, Debug)]
+pub struct ThreadCacheInfo {
+    /// Indexed by size class; index 0 (large allocations) is always empty.
+    pub classes: [ClassCacheInfo; NUM_SIZE_CLASSES],
+    /// Bytes cached across all classes.
+    pub total_bytes: usize,
+    /// The cache's current share of the overall budget.
+    pub max_bytes: usize,
+}
+
 /// Per-thread cache holding free lists for each size class.
+///
+/// Laid out as a structure of arrays: the hot `(head, length)` state for all
+/// classes is contiguous and cache-line aligned, so the fast path touches a
+/// single line and `scavenge` walks a dense array. The per-class limits used
+/// only on the slow path are kept in separate arrays behind it.
+#[repr(C, align(64))]
 pub struct ThreadCache {
     lists: [FreeList; NUM_SIZE_CLASSES],
+    /// First-level cache in front of each free list.
+    arrays: [ArrayCache; NUM_SIZE_CLASSES],
+    /// Maximum length before we return objects to central cache.
+    /// Starts small and grows adaptively.
+    max_lengths: [u32; NUM_SIZE_CLASSES],
+    /// Consecutive overage count per class (for shrinking `max_lengths`).
+    length_overages: [u32; NUM_SIZE_CLASSES],
+    /// When each class was last used, as `now_ms` stood then. Only kept
+    /// with `thread_cache_decay_ms` set.
+    last_used: [u64; NUM_SIZE_CLASSES],
+    /// `platform::now_ms()` as of the latest slow path. The fast path stamps
+    /// classes with it rather than reading the clock.
+    now_ms: u64,
+    /// `now_ms` at the latest decay pass.
+    last_decay: u64,
+    /// `FLUSH_REQUESTS` as of the latest flush it asked for.
+    flush_seen: usize,
     /// Total bytes cached across all size classes.
     total_size: usize,
     /// Per-thread cache size limit.
     max_size: usize,
+    /// Arena every cached object belongs to; 0 is the shared one.
+    arena: u8,
 }
 
 impl Default for ThreadCache {
@@ -128,8 +263,16 @@ impl ThreadCache {
     pub const fn new_const() -> Self {
         Self {
             lists: [const { FreeList::new() }; NUM_SIZE_CLASSES],
+            arrays: [const { ArrayCache::new() }; NUM_SIZE_CLASSES],
+            max_lengths: [1; NUM_SIZE_CLASSES],
+            length_overages: [0; NUM_SIZE_CLASSES],
+            last_used: [0; NUM_SIZE_CLASSES],
+            now_ms: 0,
+            last_decay: 0,
+            flush_seen: 0,
             total_size: 0,
             max_size: 0, // Sentinel: not yet initialized
+            arena: 0,
         }
     }
 
@@ -138,9 +281,9 @@ impl ThreadCache {
         UNCLAIMED_CACHE_SPACE.fetch_sub(MIN_PER_THREAD_CACHE_SIZE as isize, Ordering::Relaxed);
 
         Self {
-            lists: [const { FreeList::new() }; NUM_SIZE_CLASSES],
-            total_size: 0,
             max_size: MIN_PER_THREAD_CACHE_SIZE,
+            flush_seen: FLUSH_REQUESTS.load(Ordering::Relaxed),
+            ..Self::new_const()
         }
     }
 
@@ -155,6 +298,61 @@ impl ThreadCache {
     pub fn init(&mut self) {
         UNCLAIMED_CACHE_SPACE.fetch_sub(MIN_PER_THREAD_CACHE_SIZE as isize, Ordering::Relaxed);
         self.max_size = MIN_PER_THREAD_CACHE_SIZE;
+        self.flush_seen = FLUSH_REQUESTS.load(Ordering::Relaxed);
+    }
+
+    /// Arena this cache takes objects from and gives them back to.
+    #[inline(always)]
+    pub fn arena(&self) -> usize {
+        if NUM_ARENAS == 1 {
+            0
+        } else {
+            self.arena as usize
+        }
+    }
+
+    /// Switch this cache to `arena`, after giving everything it holds back
+    /// to the old one. Objects of another arena freed later on this thread
+    /// go straight back to their own arena instead of into this cache.
+    ///
+    /// # Safety
+    ///
+    /// Must be called on the owning thread of an initialized cache, with
+    /// `arena < NUM_ARENAS`.
+    pub unsafe fn set_arena(
+        &mut self,
+        arena: usize,
+        transfer_cache: &TransferCacheArray,
+        central: &CentralCache,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
+    ) {
+        debug_assert!(arena < NUM_ARENAS);
+        if arena != self.arena() {
+            unsafe { self.release_all(transfer_cache, central, page_heap, pagemap) };
+            self.arena = arena as u8;
+        }
+    }
+
+    /// What this cache holds, per size class.
+    pub fn info(&self) -> ThreadCacheInfo {
+        let mut info = ThreadCacheInfo {
+            classes: [ClassCacheInfo::default(); NUM_SIZE_CLASSES],
+            total_bytes: self.total_size,
+            max_bytes: self.max_size,
+        };
+        for cls in 1..NUM_SIZE_CLASSES {
+            let list = &self.lists[cls];
+            let array_length = self.arrays[cls].count;
+            info.classes[cls] = ClassCacheInfo {
+                length: list.length,
+                array_length,
+                max_length: self.max_lengths[cls],
+                low_water_mark: list.low_water_mark,
+                bytes: (list.length + array_length) as usize * size_class::class_to_size(cls),
+            };
+        }
+        info
     }
 
     /// Flush all cached objects back to the central cache and return budget.
@@ -169,32 +367,109 @@ impl ThreadCache {
         central: &CentralCache,
         page_heap: &SpinMutex<PageHeap>,
         pagemap: &PageMap,
+    ) {
+        unsafe { self.release_all(transfer_cache, central, page_heap, pagemap) };
+        // Return budget to global pool
+        if self.max_size > 0 {
+            UNCLAIMED_CACHE_SPACE.fetch_add(self.max_size as isize, Ordering::Relaxed);
+            self.max_size = 0;
+        }
+    }
+
+    /// Give every cached object to the transfer cache and all budget above
+    /// the per-thread minimum back to the global pool. The cache stays
+    /// usable and refills from one batch per class.
+    ///
+    /// # Safety
+    ///
+    /// Must be called on the owning thread of an initialized cache.
+    pub unsafe fn flush(
+        &mut self,
+        transfer_cache: &TransferCacheArray,
+        central: &CentralCache,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
+    ) {
+        unsafe { self.release_all(transfer_cache, central, page_heap, pagemap) };
+        for cls in 1..size_class::NUM_SIZE_CLASSES {
+            self.lists[cls].low_water_mark = 0;
+            let batch = size_class::batch_size(cls) as u32;
+            let max_length = &mut self.max_lengths[cls];
+            *max_length = (*max_length).min(batch);
+            self.length_overages[cls] = 0;
+        }
+        if self.max_size > MIN_PER_THREAD_CACHE_SIZE {
+            UNCLAIMED_CACHE_SPACE.fetch_add(
+                (self.max_size - MIN_PER_THREAD_CACHE_SIZE) as isize,
+                Ordering::Relaxed,
+            );
+            self.max_size = MIN_PER_THREAD_CACHE_SIZE;
+        }
+    }
+
+    /// Move every cached object, array caches included, to the transfer cache.
+    unsafe fn release_all(
+        &mut self,
+        transfer_cache: &TransferCacheArray,
+        central: &CentralCache,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
     ) {
         for cls in 1..size_class::NUM_SIZE_CLASSES {
             let list = &mut self.lists[cls];
-            if list.length > 0 {
-                let info = size_class::class_info(cls);
-                let (count, head, tail) = list.pop_batch(list.length);
-                if count > 0 {
-                    self.total_size -= count as usize * info.size;
-                    unsafe {
-                        transfer_cache.insert_range(
-                            cls,
-                            head,
-                            tail,
-                            count as usize,
-                            central,
-                            page_heap,
-                            pagemap,
-                        )
-                    };
+            loop {
+                let obj = self.arrays[cls].pop();
+                if obj.is_null() {
+                    break;
                 }
+                list.push(obj);
             }
+            let length = list.length;
+            unsafe {
+                self.release_batches(cls, length, transfer_cache, central, page_heap, pagemap)
+            };
         }
-        // Return budget to global pool
-        if self.max_size > 0 {
-            UNCLAIMED_CACHE_SPACE.fetch_add(self.max_size as isize, Ordering::Relaxed);
-            self.max_size = 0;
+    }
+
+    /// Give half of every free list back to the transfer cache, and this
+    /// thread's spare budget back to the global pool.
+    ///
+    /// For threads that expect to go quiet, e.g. a worker whose tasks were
+    /// just stolen by another: the objects and budget it would strand are
+    /// handed to whichever threads now do the work. The array caches are
+    /// left alone; they are at most a few objects per class.
+    ///
+    /// # Safety
+    ///
+    /// Must be called on the owning thread of an initialized cache.
+    pub unsafe fn donate(
+        &mut self,
+        transfer_cache: &TransferCacheArray,
+        central: &CentralCache,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
+    ) {
+        for cls in 1..size_class::NUM_SIZE_CLASSES {
+            let to_release = self.lists[cls].length.div_ceil(2);
+            unsafe {
+                self.release_batches(cls, to_release, transfer_cache, central, page_heap, pagemap)
+            };
+            let list = &mut self.lists[cls];
+            list.low_water_mark = list.length;
+
+            // Restart growth from one batch, as after a scavenge.
+            let batch = size_class::batch_size(cls) as u32;
+            let max_length = &mut self.max_lengths[cls];
+            *max_length = (*max_length).min(batch);
+            self.length_overages[cls] = 0;
+        }
+
+        // Keep enough budget for what is still cached, and at least the
+        // per-thread minimum. The rest goes back for busy threads to claim.
+        let keep = self.total_size.max(MIN_PER_THREAD_CACHE_SIZE);
+        if self.max_size > keep {
+            UNCLAIMED_CACHE_SPACE.fetch_add((self.max_size - keep) as isize, Ordering::Relaxed);
+            self.max_size = keep;
         }
     }
 
@@ -213,11 +488,24 @@ impl ThreadCache {
         page_heap: &SpinMutex<PageHeap>,
         pagemap: &PageMap,
     ) -> *mut u8 {
-        let list = &mut self.lists[size_class];
-        let obj = list.pop();
+        // The array is off by default; keep its separate cache line and
+        // checks off the fast path then.
+        let mut obj = ptr::null_mut();
+        if ARRAY_CACHE_SLOTS != 0 {
+            obj = self.arrays[size_class].pop();
+        }
+        if obj.is_null() {
+            obj = self.lists[size_class].pop();
+        }
         if !obj.is_null() {
+            #[cfg(feature = "double-free-check")]
+            unsafe {
+                crate::double_free::leave(obj, size_class)
+            };
             let obj_size = size_class::class_to_size(size_class);
             self.total_size -= obj_size;
+            self.touch(size_class);
+            crate::stat_inc!(thread_cache_hits);
             return obj as *mut u8;
         }
         // Slow path: fetch from transfer cache / central cache
@@ -239,23 +527,42 @@ impl ThreadCache {
         page_heap: &SpinMutex<PageHeap>,
         pagemap: &PageMap,
     ) {
-        let list = &mut self.lists[size_class];
         let obj = ptr as *mut FreeObject;
-        list.push(obj);
-
+        #[cfg(feature = "double-free-check")]
+        unsafe {
+            crate::double_free::enter(obj, size_class)
+        };
         let obj_size = size_class::class_to_size(size_class);
         self.total_size += obj_size;
+        self.touch(size_class);
 
-        // Check if we should return objects to transfer/central cache
-        if list.length > list.max_length {
-            unsafe {
-                self.release_to_central(size_class, transfer_cache, central, page_heap, pagemap)
-            };
+        if ARRAY_CACHE_SLOTS == 0 || !self.arrays[size_class].push(obj) {
+            let list = &mut self.lists[size_class];
+            list.push(obj);
+
+            // Check if we should return objects to transfer/central cache
+            if list.length > self.max_lengths[size_class] {
+                unsafe {
+                    self.release_to_central(size_class, transfer_cache, central, page_heap, pagemap)
+                };
+            }
         }
 
         // Check total cache size for GC
         if self.total_size > self.max_size {
+            unsafe { self.tick(transfer_cache, central, page_heap, pagemap) };
+            #[cfg(any(feature = "tracing", feature = "usdt"))]
+            let before = self.total_size;
+            #[cfg(feature = "tracing")]
+            let start = std::time::Instant::now();
             unsafe { self.scavenge(transfer_cache, central, page_heap, pagemap) };
+            #[cfg(feature = "usdt")]
+            crate::usdt::thread_cache_scavenge(before - self.total_size, self.total_size);
+            #[cfg(feature = "tracing")]
+            crate::tracing_events::record(crate::tracing_events::Event::Scavenge {
+                bytes: before - self.total_size,
+                nanos: start.elapsed().as_nanos() as u64,
+            });
         }
     }
 
@@ -272,15 +579,24 @@ impl ThreadCache {
         page_heap: &SpinMutex<PageHeap>,
         pagemap: &PageMap,
     ) -> *mut u8 {
+        crate::stat_inc!(thread_cache_misses);
+        unsafe { self.tick(transfer_cache, central, page_heap, pagemap) };
+        self.touch(size_class);
         let info = size_class::class_info(size_class);
-        let batch = info.batch_size;
-        let list = &mut self.lists[size_class];
+        let batch = size_class::batch_size(size_class);
 
         // Slow start: only fetch min(max_length, batch) objects
-        let num_to_move = (list.max_length as usize).min(batch).max(1);
+        let num_to_move = (self.max_lengths[size_class] as usize).min(batch).max(1);
 
-        let (count, head) = unsafe {
-            transfer_cache.remove_range(size_class, num_to_move, central, page_heap, pagemap)
+        let (count, head, tail) = unsafe {
+            self.take_batch(
+                size_class,
+                num_to_move,
+                transfer_cache,
+                central,
+                page_heap,
+                pagemap,
+            )
         };
 
         if count == 0 || head.is_null() {
@@ -289,21 +605,122 @@ impl ThreadCache {
 
         // Take the first object for the caller
         let result = head;
-        let remaining_head = unsafe { (*head).next };
+        let remaining_head = unsafe { FreeObject::next(head) };
         let remaining_count = count - 1;
 
         // Put the rest in our thread-local free list
         if remaining_count > 0 {
-            list.push_batch(remaining_head, remaining_count as u32);
+            self.lists[size_class].push_batch(remaining_head, tail, remaining_count as u32);
             self.total_size += remaining_count * info.size;
         }
 
         // Grow max_length: slow start then linear growth
-        Self::grow_max_length_on_fetch(list, batch);
+        self.grow_max_length_on_fetch(size_class, batch);
 
         result as *mut u8
     }
 
+    /// Take up to `count` objects of `cls` from this cache's arena: through
+    /// the transfer cache for the shared arena, from the arena's central list
+    /// otherwise.
+    unsafe fn take_batch(
+        &self,
+        cls: usize,
+        count: usize,
+        transfer_cache: &TransferCacheArray,
+        central: &CentralCache,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
+    ) -> (usize, *mut FreeObject, *mut FreeObject) {
+        match self.arena() {
+            0 => unsafe { transfer_cache.remove_range(cls, count, central, page_heap, pagemap) },
+            arena => unsafe {
+                crate::stat_inc!(central_cache_hits);
+                central_free_list::remove_range_dropping_lock(
+                    central.arena(arena, cls),
+                    cls,
+                    count,
+                    page_heap,
+                    pagemap,
+                )
+            },
+        }
+    }
+
+    /// Give a list of `count` objects of `cls` back to this cache's arena,
+    /// the way [`take_batch`](Self::take_batch) got them.
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn give_batch(
+        &self,
+        cls: usize,
+        head: *mut FreeObject,
+        tail: *mut FreeObject,
+        count: usize,
+        transfer_cache: &TransferCacheArray,
+        central: &CentralCache,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
+    ) {
+        #[cfg(feature = "double-free-check")]
+        unsafe {
+            crate::double_free::leave_list(head, count, cls)
+        };
+        match self.arena() {
+            0 => unsafe {
+                transfer_cache.insert_range(cls, head, tail, count, central, page_heap, pagemap)
+            },
+            arena => unsafe {
+                central_free_list::insert_range_dropping_lock(
+                    central.arena(arena, cls),
+                    head,
+                    count,
+                    page_heap,
+                    pagemap,
+                )
+            },
+        }
+    }
+
+    /// Give `count` objects of `cls` to the transfer cache one batch at a
+    /// time, so it can keep them as whole batches and whatever reaches the
+    /// central list arrives in bounded pieces. Returns the bytes released.
+    unsafe fn release_batches(
+        &mut self,
+        cls: usize,
+        count: u32,
+        transfer_cache: &TransferCacheArray,
+        central: &CentralCache,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
+    ) -> usize {
+        let info = size_class::class_info(cls);
+        let batch = size_class::batch_size(cls) as u32;
+        let mut left = count;
+        let mut bytes = 0;
+        while left > 0 {
+            let (n, head, tail) = self.lists[cls].pop_batch(left.min(batch));
+            if n == 0 {
+                break;
+            }
+            left -= n;
+            bytes += n as usize * info.size;
+            unsafe {
+                self.give_batch(
+                    cls,
+                    head,
+                    tail,
+                    n as usize,
+                    transfer_cache,
+                    central,
+                    page_heap,
+                    pagemap,
+                )
+            };
+        }
+        self.total_size -= bytes;
+        bytes
+    }
+
     /// Release excess objects from a size class back to transfer/central cache.
     ///
     /// Matches Google tcmalloc's ListTooLong:
@@ -318,8 +735,9 @@ impl ThreadCache {
         page_heap: &SpinMutex<PageHeap>,
         pagemap: &PageMap,
     ) {
+        unsafe { self.tick(transfer_cache, central, page_heap, pagemap) };
         let info = size_class::class_info(size_class);
-        let batch = info.batch_size as u32;
+        let batch = size_class::batch_size(size_class) as u32;
         let list = &mut self.lists[size_class];
 
         // Release exactly batch_size objects (or all if fewer)
@@ -332,11 +750,12 @@ impl ThreadCache {
         self.total_size -= count as usize * info.size;
 
         unsafe {
-            transfer_cache.insert_range(
+            self.give_batch(
                 size_class,
                 head,
                 tail,
                 count as usize,
+                transfer_cache,
                 central,
                 page_heap,
                 pagemap,
@@ -344,15 +763,17 @@ impl ThreadCache {
         };
 
         // Adjust max_length per gperftools logic:
-        if list.max_length < batch {
+        let max_length = &mut self.max_lengths[size_class];
+        if *max_length < batch {
             // Slow start: grow by 1
-            list.max_length += 1;
-        } else if list.max_length > batch {
+            *max_length += 1;
+        } else if *max_length > batch {
             // Track overages: if we keep overflowing, shrink max_length
-            list.length_overages += 1;
-            if list.length_overages > MAX_OVERAGES {
-                list.max_length = list.max_length.saturating_sub(batch).max(batch);
-                list.length_overages = 0;
+            let overages = &mut self.length_overages[size_class];
+            *overages += 1;
+            if *overages > MAX_OVERAGES {
+                *max_length = max_length.saturating_sub(batch).max(batch);
+                *overages = 0;
             }
         }
     }
@@ -360,17 +781,86 @@ impl ThreadCache {
     /// Grow max_length on fetch: slow-start then linear growth.
     /// Matches gperftools FetchFromCentralCache growth logic.
     #[inline]
-    fn grow_max_length_on_fetch(list: &mut FreeList, batch_size: usize) {
-        if (list.max_length as usize) < batch_size {
-            list.max_length += 1;
+    fn grow_max_length_on_fetch(&mut self, size_class: usize, batch_size: usize) {
+        let max_length = &mut self.max_lengths[size_class];
+        if (*max_length as usize) < batch_size {
+            *max_length += 1;
         } else {
             let batch = batch_size as u32;
-            let new_len = list.max_length + batch;
+            let new_len = *max_length + batch;
             // Round down to multiple of batch_size (per gperftools)
             let new_len = new_len - (new_len % batch);
-            list.max_length = new_len.min(MAX_DYNAMIC_FREE_LIST_LENGTH);
+            *max_length = new_len.min(MAX_DYNAMIC_FREE_LIST_LENGTH);
         }
-        list.length_overages = 0;
+        self.length_overages[size_class] = 0;
+    }
+
+    /// Record a use of `size_class` for decay.
+    #[inline(always)]
+    fn touch(&mut self, size_class: usize) {
+        if THREAD_CACHE_DECAY_MS != 0 {
+            self.last_used[size_class] = self.now_ms;
+        }
+    }
+
+    /// Slow-path housekeeping: flush if [`request_flush`] was called since
+    /// the last check, then read the time and run a decay pass if a window
+    /// has passed since the last one (only with `thread_cache_decay_ms`).
+    #[inline]
+    unsafe fn tick(
+        &mut self,
+        transfer_cache: &TransferCacheArray,
+        central: &CentralCache,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
+    ) {
+        let requests = FLUSH_REQUESTS.load(Ordering::Relaxed);
+        if requests != self.flush_seen {
+            self.flush_seen = requests;
+            unsafe { self.flush(transfer_cache, central, page_heap, pagemap) };
+        }
+        if THREAD_CACHE_DECAY_MS == 0 {
+            return;
+        }
+        self.now_ms = platform::now_ms();
+        #[allow(clippy::absurd_extreme_comparisons)]
+        if self.now_ms - self.last_decay >= THREAD_CACHE_DECAY_MS {
+            unsafe { self.decay(transfer_cache, central, page_heap, pagemap) };
+        }
+    }
+
+    /// Give half of each class not used since the last decay pass back to
+    /// the transfer cache, and restart its growth from one batch.
+    ///
+    /// Passes are at least a decay window apart, so a class is halved once
+    /// per window it stays idle. The array caches are left alone, as in
+    /// [`donate`](Self::donate).
+    #[cold]
+    unsafe fn decay(
+        &mut self,
+        transfer_cache: &TransferCacheArray,
+        central: &CentralCache,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
+    ) {
+        for cls in 1..size_class::NUM_SIZE_CLASSES {
+            if self.last_used[cls] >= self.last_decay {
+                continue;
+            }
+            let to_release = self.lists[cls].length.div_ceil(2);
+            let _bytes = unsafe {
+                self.release_batches(cls, to_release, transfer_cache, central, page_heap, pagemap)
+            };
+            crate::stat_add!(thread_cache_decay_bytes, _bytes);
+            let list = &mut self.lists[cls];
+            list.low_water_mark = list.length;
+
+            let batch = size_class::batch_size(cls) as u32;
+            let max_length = &mut self.max_lengths[cls];
+            *max_length = (*max_length).min(batch);
+            self.length_overages[cls] = 0;
+        }
+        self.last_decay = self.now_ms;
     }
 
     /// GC: release idle objects across all size classes.
@@ -386,34 +876,29 @@ impl ThreadCache {
         pagemap: &PageMap,
     ) {
         for cls in 1..size_class::NUM_SIZE_CLASSES {
-            let list = &mut self.lists[cls];
-            let lwm = list.low_water_mark;
+            let lwm = self.lists[cls].low_water_mark;
 
             if lwm > 0 {
                 // Release half the idle objects (above low-water mark)
                 let to_release = if lwm > 1 { lwm / 2 } else { 1 };
-
-                let info = size_class::class_info(cls);
-                let (count, head, tail) = list.pop_batch(to_release);
-                self.total_size -= count as usize * info.size;
-
                 unsafe {
-                    transfer_cache.insert_range(
+                    self.release_batches(
                         cls,
-                        head,
-                        tail,
-                        count as usize,
+                        to_release,
+                        transfer_cache,
                         central,
                         page_heap,
                         pagemap,
                     )
                 };
             }
+            let list = &mut self.lists[cls];
 
             // Shrink max_length if it's grown beyond batch_size
-            let batch = size_class::class_info(cls).batch_size as u32;
-            if list.max_length > batch {
-                list.max_length = list.max_length.saturating_sub(batch).max(batch);
+            let batch = size_class::batch_size(cls) as u32;
+            let max_length = &mut self.max_lengths[cls];
+            if *max_length > batch {
+                *max_length = max_length.saturating_sub(batch).max(batch);
             }
 
             // Reset low-water mark for next epoch
@@ -427,6 +912,22 @@ impl ThreadCache {
 
     /// Try to steal budget from the global pool to grow this thread's cache.
     /// Uses CAS to atomically claim STEAL_AMOUNT from unclaimed space.
+    ///
+    /// With `deterministic`, the limit must not depend on what other threads
+    /// have claimed: it grows by STEAL_AMOUNT up to the overall budget
+    /// regardless of the pool, which is still charged so `flush_and_destroy`
+    /// balances.
+    #[cfg(feature = "deterministic")]
+    fn increase_cache_limit(&mut self) {
+        if self.max_size + STEAL_AMOUNT <= overall_cache_size() {
+            UNCLAIMED_CACHE_SPACE.fetch_sub(STEAL_AMOUNT as isize, Ordering::Relaxed);
+            self.max_size += STEAL_AMOUNT;
+        }
+    }
+
+    /// Try to steal budget from the global pool to grow this thread's cache.
+    /// Uses CAS to atomically claim STEAL_AMOUNT from unclaimed space.
+    #[cfg(not(feature = "deterministic"))]
     fn increase_cache_limit(&mut self) {
         loop {
             let current = UNCLAIMED_CACHE_SPACE.load(Ordering::Relaxed);
@@ -513,7 +1014,8 @@ mod tests {
 
         unsafe {
             let mut allocs: Vec<(usize, *mut u8)> = Vec::new();
-            for cls in [1, 4, 8, 12, 16, 20, 24] {
+            let classes = [1, 4, 8, 12, 16, 20, 24];
+            for cls in classes.into_iter().filter(|&c| c < NUM_SIZE_CLASSES) {
                 for _ in 0..50 {
                     let ptr = tc.allocate(cls, &xfer, &central, &heap, pm);
                     assert!(!ptr.is_null());
@@ -545,4 +1047,219 @@ mod tests {
             tc.deallocate(ptr2, 2, &xfer, &central, &heap, pm);
         }
     }
+
+    #[test]
+    fn test_array_cache_in_front_of_list() {
+        let (pm, heap, central, xfer) = make_test_env();
+        let mut tc = ThreadCache::new();
+        let cls = 3;
+        let n = ARRAY_CACHE_SLOTS + 8;
+
+        unsafe {
+            let ptrs: Vec<*mut u8> = (0..n)
+                .map(|_| tc.allocate(cls, &xfer, &central, &heap, pm))
+                .collect();
+            for &p in &ptrs {
+                assert!(!p.is_null());
+            }
+            let list_before = tc.lists[cls].length;
+            for &p in &ptrs {
+                tc.deallocate(p, cls, &xfer, &central, &heap, pm);
+            }
+            assert_eq!(tc.arrays[cls].count as usize, ARRAY_CACHE_SLOTS);
+
+            // The array fills with the first frees and is drained LIFO first.
+            for i in (0..ARRAY_CACHE_SLOTS).rev() {
+                let p = tc.allocate(cls, &xfer, &central, &heap, pm);
+                assert_eq!(p, ptrs[i]);
+            }
+            assert_eq!(tc.arrays[cls].count, 0);
+            assert!(tc.lists[cls].length >= list_before);
+        }
+    }
+
+    #[test]
+    fn test_flush_drains_array_cache() {
+        let (pm, heap, central, xfer) = make_test_env();
+        let mut tc = ThreadCache::new();
+
+        unsafe {
+            let p = tc.allocate(5, &xfer, &central, &heap, pm);
+            tc.deallocate(p, 5, &xfer, &central, &heap, pm);
+            tc.flush_and_destroy(&xfer, &central, &heap, pm);
+        }
+        assert_eq!(tc.arrays[5].count, 0);
+        assert_eq!(tc.lists[5].length, 0);
+        assert_eq!(tc.total_size, 0);
+    }
+
+    #[test]
+    fn test_flush_keeps_cache_usable() {
+        let (pm, heap, central, xfer) = make_test_env();
+        let mut tc = ThreadCache::new();
+        let cls = 4;
+
+        unsafe {
+            let ptrs: Vec<*mut u8> = (0..200)
+                .map(|_| tc.allocate(cls, &xfer, &central, &heap, pm))
+                .collect();
+            for &p in &ptrs {
+                tc.deallocate(p, cls, &xfer, &central, &heap, pm);
+            }
+            tc.increase_cache_limit();
+            assert!(tc.total_size > 0);
+
+            tc.flush(&xfer, &central, &heap, pm);
+            assert_eq!(tc.total_size, 0);
+            assert_eq!(tc.lists[cls].length, 0);
+            assert_eq!(tc.arrays[cls].count, 0);
+            assert_eq!(tc.max_size, MIN_PER_THREAD_CACHE_SIZE);
+            assert!(tc.is_initialized());
+
+            // Still a working cache.
+            let p = tc.allocate(cls, &xfer, &central, &heap, pm);
+            assert!(!p.is_null());
+            tc.deallocate(p, cls, &xfer, &central, &heap, pm);
+            tc.flush_and_destroy(&xfer, &central, &heap, pm);
+        }
+    }
+
+    #[test]
+    fn test_flush_request_empties_cache_on_slow_path() {
+        let (pm, heap, central, xfer) = make_test_env();
+        let mut tc = ThreadCache::new();
+        let (cls, other) = (4, 5);
+
+        unsafe {
+            let ptrs: Vec<*mut u8> = (0..200)
+                .map(|_| tc.allocate(cls, &xfer, &central, &heap, pm))
+                .collect();
+            for &p in &ptrs {
+                tc.deallocate(p, cls, &xfer, &central, &heap, pm);
+            }
+            assert!(tc.lists[cls].length > 1);
+
+            // A request made since the cache last looked; the global counter
+            // is left alone so other tests' caches don't flush.
+            tc.flush_seen = tc.flush_seen.wrapping_sub(1);
+            let p = tc.allocate(other, &xfer, &central, &heap, pm);
+            assert_eq!(tc.flush_seen, FLUSH_REQUESTS.load(Ordering::Relaxed));
+            assert_eq!(tc.lists[cls].length, 0);
+            assert_eq!(tc.arrays[cls].count, 0);
+            tc.deallocate(p, other, &xfer, &central, &heap, pm);
+            tc.flush_and_destroy(&xfer, &central, &heap, pm);
+        }
+    }
+
+    #[test]
+    fn test_decay_halves_idle_classes() {
+        let (pm, heap, central, xfer) = make_test_env();
+        let mut tc = ThreadCache::new();
+        let (idle, busy) = (4, 5);
+
+        unsafe {
+            for cls in [idle, busy] {
+                let ptrs: Vec<*mut u8> = (0..200)
+                    .map(|_| tc.allocate(cls, &xfer, &central, &heap, pm))
+                    .collect();
+                for &p in &ptrs {
+                    tc.deallocate(p, cls, &xfer, &central, &heap, pm);
+                }
+            }
+            let (idle_cached, busy_cached) = (tc.lists[idle].length, tc.lists[busy].length);
+            assert!(idle_cached > 1 && busy_cached > 1);
+
+            // Only `busy` was used since the pass at 10.
+            tc.last_used[idle] = 5;
+            tc.last_used[busy] = 10;
+            tc.last_decay = 10;
+            tc.now_ms = 20;
+            tc.decay(&xfer, &central, &heap, pm);
+            assert_eq!(tc.lists[idle].length, idle_cached / 2);
+            assert_eq!(tc.lists[busy].length, busy_cached);
+            assert_eq!(tc.last_decay, 20);
+
+            // Another idle window: both are halved.
+            tc.now_ms = 30;
+            tc.decay(&xfer, &central, &heap, pm);
+            assert_eq!(tc.lists[idle].length, idle_cached / 2 / 2);
+            assert_eq!(tc.lists[busy].length, busy_cached / 2);
+
+            let info = tc.info();
+            let bytes: usize = info.classes.iter().map(|c| c.bytes).sum();
+            assert_eq!(bytes, info.total_bytes);
+            tc.flush_and_destroy(&xfer, &central, &heap, pm);
+        }
+    }
+
+    #[test]
+    fn test_donate_halves_lists_and_budget() {
+        let (pm, heap, central, xfer) = make_test_env();
+        let mut tc = ThreadCache::new();
+        let cls = 4;
+
+        unsafe {
+            let ptrs: Vec<*mut u8> = (0..200)
+                .map(|_| tc.allocate(cls, &xfer, &central, &heap, pm))
+                .collect();
+            for &p in &ptrs {
+                tc.deallocate(p, cls, &xfer, &central, &heap, pm);
+            }
+            tc.increase_cache_limit();
+            let cached = tc.lists[cls].length;
+            assert!(cached > 1);
+
+            tc.donate(&xfer, &central, &heap, pm);
+            assert_eq!(tc.lists[cls].length, cached / 2);
+            assert_eq!(tc.lists[cls].low_water_mark, cached / 2);
+            assert!(tc.max_lengths[cls] <= size_class::class_info(cls).batch_size as u32);
+            assert_eq!(tc.max_size, MIN_PER_THREAD_CACHE_SIZE);
+
+            // The donated objects are reachable through the transfer cache.
+            let (count, head, _) = xfer.remove_range(cls, 1, &central, &heap, pm);
+            assert_eq!(count, 1);
+            assert!(!head.is_null());
+
+            tc.flush_and_destroy(&xfer, &central, &heap, pm);
+        }
+    }
+
+    #[test]
+    fn test_info_reports_lists_and_arrays() {
+        let (pm, heap, central, xfer) = make_test_env();
+        let mut tc = ThreadCache::new();
+        let cls = 6;
+        let n = ARRAY_CACHE_SLOTS + 10;
+
+        unsafe {
+            let ptrs: Vec<*mut u8> = (0..n)
+                .map(|_| tc.allocate(cls, &xfer, &central, &heap, pm))
+                .collect();
+            for &p in &ptrs {
+                tc.deallocate(p, cls, &xfer, &central, &heap, pm);
+            }
+
+            let info = tc.info();
+            let c = info.classes[cls];
+            assert_eq!(c.length, tc.lists[cls].length);
+            assert_eq!(c.array_length as usize, ARRAY_CACHE_SLOTS);
+            assert_eq!(c.max_length, tc.max_lengths[cls]);
+            assert_eq!(
+                c.bytes,
+                (c.length + c.array_length) as usize * size_class::class_to_size(cls)
+            );
+            assert_eq!(info.total_bytes, info.classes.iter().map(|c| c.bytes).sum());
+            assert_eq!(info.max_bytes, MIN_PER_THREAD_CACHE_SIZE);
+
+            tc.flush_and_destroy(&xfer, &central, &heap, pm);
+        }
+        assert_eq!(tc.info().total_bytes, 0);
+    }
+
+    #[test]
+    fn test_hot_lists_cache_line_aligned() {
+        assert_eq!(core::mem::align_of::<ThreadCache>(), 64);
+        assert_eq!(core::mem::offset_of!(ThreadCache, lists), 0);
+        assert_eq!(core::mem::size_of::<ThreadCache>() % 64, 0);
+    }
 }
@@ -0,0 +1,551 @@
+//! Replayable allocation traces (`trace` feature).
+//!
+//! [`TraceMalloc`] wraps [`RtMalloc`] and, between [`start`] and [`stop`],
+//! logs every call as a [`Record`] — timestamp, thread, operation, size,
+//! alignment and pointers — to a file. [`Replay`] turns such a file back
+//! into a sequence of calls and runs it against any [`GlobalAlloc`], so
+//! allocators can be compared on a real workload instead of a synthetic one.
+//!
+//! Records go to a lock-free ring of [`RING_LEN`] slots: a thread reserves a
+//! slot with one compare-exchange and publishes it with a release store. The
+//! thread that takes the ring past half full writes the published prefix to
+//! the file; one that finds it full writes it out before going on, so
+//! records are never dropped. Writing is plain `write(2)` calls from a stack
+//! buffer and never allocates.
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static GLOBAL: rtmalloc::trace::TraceMalloc = rtmalloc::trace::TraceMalloc;
+//!
+//! rtmalloc::trace::start("app.trace")?;
+//! // ... run the workload ...
+//! rtmalloc::trace::stop()?;
+//! ```
+//!
+//! Then replay it with `cargo run --release --features trace --example
+//! replay -- app.trace`.
+
+use crate::allocator::RtMalloc;
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+/// Slots in the record ring.
+pub const RING_LEN: usize = 1 << 14;
+
+/// First bytes of a trace file, followed by the format version and the
+/// record size as little-endian `u32`s.
+const MAGIC: &[u8; 8] = b"RTMTRACE";
+const VERSION: u32 = 1;
+
+/// Records encoded per `write` call.
+const WRITE_BATCH: usize = 128;
+
+/// What a [`Record`] logs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Op {
+    Alloc = 0,
+    AllocZeroed = 1,
+    Dealloc = 2,
+    /// `old_ptr` resized to `size`, now at `ptr` (null if it failed).
+    Realloc = 3,
+}
+
+impl Op {
+    fn from_u32(v: u32) -> Option<Op> {
+        match v {
+            0 => Some(Op::Alloc),
+            1 => Some(Op::AllocZeroed),
+            2 => Some(Op::Dealloc),
+            3 => Some(Op::Realloc),
+            _ => None,
+        }
+    }
+}
+
+/// One logged call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Nanoseconds since the first [`start`].
+    pub ts: u64,
+    /// Small per-thread number, in order of each thread's first record.
+    pub thread: u32,
+    pub op: Op,
+    /// Requested size; the new size for [`Op::Realloc`].
+    pub size: u64,
+    pub align: u64,
+    /// Pointer returned, or freed for [`Op::Dealloc`].
+    pub ptr: u64,
+    /// Pointer passed to [`Op::Realloc`]; 0 otherwise.
+    pub old_ptr: u64,
+}
+
+impl Record {
+    /// Encoded size in a trace file.
+    pub const SIZE: usize = 48;
+
+    fn encode(&self, out: &mut [u8]) {
+        out[0..8].copy_from_slice(&self.ts.to_le_bytes());
+        out[8..12].copy_from_slice(&self.thread.to_le_bytes());
+        out[12..16].copy_from_slice(&(self.op as u32).to_le_bytes());
+        out[16..24].copy_from_slice(&self.size.to_le_bytes());
+        out[24..32].copy_from_slice(&self.align.to_le_bytes());
+        out[32..40].copy_from_slice(&self.ptr.to_le_bytes());
+        out[40..48].copy_from_slice(&self.old_ptr.to_le_bytes());
+    }
+
+    fn decode(b: &[u8]) -> Option<Record> {
+        let u64_at = |i: usize| u64::from_le_bytes(b[i..i + 8].try_into().unwrap());
+        let u32_at = |i: usize| u32::from_le_bytes(b[i..i + 4].try_into().unwrap());
+        Some(Record {
+            ts: u64_at(0),
+            thread: u32_at(8),
+            op: Op::from_u32(u32_at(12))?,
+            size: u64_at(16),
+            align: u64_at(24),
+            ptr: u64_at(32),
+            old_ptr: u64_at(40),
+        })
+    }
+}
+
+/// A ring slot. `seq` is the position it was last published for, plus one;
+/// the fields are only read once it matches.
+struct Slot {
+    seq: AtomicU64,
+    ts: AtomicU64,
+    thread_op: AtomicU64,
+    size: AtomicU64,
+    align: AtomicU64,
+    ptr: AtomicU64,
+    old_ptr: AtomicU64,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            ts: AtomicU64::new(0),
+            thread_op: AtomicU64::new(0),
+            size: AtomicU64::new(0),
+            align: AtomicU64::new(0),
+            ptr: AtomicU64::new(0),
+            old_ptr: AtomicU64::new(0),
+        }
+    }
+}
+
+static RING: [Slot; RING_LEN] = [const { Slot::new() }; RING_LEN];
+/// Next position to reserve.
+static HEAD: AtomicU64 = AtomicU64::new(0);
+/// Next position to write out; advanced only under `WRITER`.
+static TAIL: AtomicU64 = AtomicU64::new(0);
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static WRITER: Mutex<Option<File>> = Mutex::new(None);
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+static NEXT_THREAD: AtomicU32 = AtomicU32::new(1);
+
+std::thread_local! {
+    static THREAD: Cell<u32> = const { Cell::new(0) };
+}
+
+/// This thread's number, assigned on first use; 0 once its locals are gone.
+fn thread_number() -> u32 {
+    THREAD
+        .try_with(|t| {
+            if t.get() == 0 {
+                t.set(NEXT_THREAD.fetch_add(1, Ordering::Relaxed));
+            }
+            t.get()
+        })
+        .unwrap_or(0)
+}
+
+/// Reserve the next ring position, writing records out first while the ring
+/// is full.
+fn reserve() -> u64 {
+    loop {
+        let pos = HEAD.load(Ordering::Relaxed);
+        if pos.saturating_sub(TAIL.load(Ordering::Acquire)) >= RING_LEN as u64 {
+            let _ = write_out(true);
+            std::thread::yield_now();
+            continue;
+        }
+        if HEAD
+            .compare_exchange_weak(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            return pos;
+        }
+    }
+}
+
+/// Fill and publish the slot reserved at `pos`, then write the ring out if
+/// it is past half full and nobody else is.
+fn publish(pos: u64, op: Op, layout: Layout, ptr: *mut u8, old_ptr: *mut u8) {
+    let slot = &RING[pos as usize % RING_LEN];
+    let ts = EPOCH.get().map_or(0, |e| e.elapsed().as_nanos() as u64);
+    slot.ts.store(ts, Ordering::Relaxed);
+    slot.thread_op.store(
+        (thread_number() as u64) << 32 | op as u64,
+        Ordering::Relaxed,
+    );
+    slot.size.store(layout.size() as u64, Ordering::Relaxed);
+    slot.align.store(layout.align() as u64, Ordering::Relaxed);
+    slot.ptr.store(ptr.addr() as u64, Ordering::Relaxed);
+    slot.old_ptr.store(old_ptr.addr() as u64, Ordering::Relaxed);
+    slot.seq.store(pos + 1, Ordering::Release);
+
+    if pos.saturating_sub(TAIL.load(Ordering::Relaxed)) >= RING_LEN as u64 / 2 {
+        let _ = write_out(false);
+    }
+}
+
+fn log(op: Op, layout: Layout, ptr: *mut u8, old_ptr: *mut u8) {
+    if ENABLED.load(Ordering::Relaxed) {
+        publish(reserve(), op, layout, ptr, old_ptr);
+    }
+}
+
+/// Write the published records from `TAIL` on to the file. Waits for the
+/// writer lock if `wait`, else gives up if another thread holds it. Stops at
+/// the first slot still being filled.
+fn write_out(wait: bool) -> io::Result<()> {
+    let mut writer = if wait {
+        WRITER.lock().unwrap_or_else(|e| e.into_inner())
+    } else {
+        match WRITER.try_lock() {
+            Ok(w) => w,
+            Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(std::sync::TryLockError::WouldBlock) => return Ok(()),
+        }
+    };
+    let mut tail = TAIL.load(Ordering::Relaxed);
+    let head = HEAD.load(Ordering::Acquire);
+    let mut buf = [0u8; WRITE_BATCH * Record::SIZE];
+    let mut result = Ok(());
+    while tail < head {
+        let mut n = 0;
+        while n < WRITE_BATCH && tail < head {
+            let slot = &RING[tail as usize % RING_LEN];
+            if slot.seq.load(Ordering::Acquire) != tail + 1 {
+                break;
+            }
+            let thread_op = slot.thread_op.load(Ordering::Relaxed);
+            let record = Record {
+                ts: slot.ts.load(Ordering::Relaxed),
+                thread: (thread_op >> 32) as u32,
+                op: Op::from_u32(thread_op as u32).unwrap_or(Op::Alloc),
+                size: slot.size.load(Ordering::Relaxed),
+                align: slot.align.load(Ordering::Relaxed),
+                ptr: slot.ptr.load(Ordering::Relaxed),
+                old_ptr: slot.old_ptr.load(Ordering::Relaxed),
+            };
+            record.encode(&mut buf[n * Record::SIZE..(n + 1) * Record::SIZE]);
+            n += 1;
+            tail += 1;
+        }
+        if n == 0 {
+            break;
+        }
+        // With no file (between `stop` and `start`) the records are dropped.
+        if let Some(file) = writer.as_mut()
+            && result.is_ok()
+        {
+            result = file.write_all(&buf[..n * Record::SIZE]);
+        }
+        TAIL.store(tail, Ordering::Release);
+    }
+    result
+}
+
+/// Start logging to a new file at `path`, replacing any trace in progress.
+pub fn start(path: impl AsRef<Path>) -> io::Result<()> {
+    stop()?;
+    let mut file = File::create(path)?;
+    file.write_all(MAGIC)?;
+    file.write_all(&VERSION.to_le_bytes())?;
+    file.write_all(&(Record::SIZE as u32).to_le_bytes())?;
+    EPOCH.get_or_init(Instant::now);
+    *WRITER.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Write out everything logged so far.
+pub fn flush() -> io::Result<()> {
+    write_out(true)
+}
+
+/// Stop logging, write out what is left and close the file.
+///
+/// Calls already past the enabled check finish their records; any still
+/// being filled when this returns are dropped.
+pub fn stop() -> io::Result<()> {
+    ENABLED.store(false, Ordering::Relaxed);
+    let result = write_out(true);
+    let file = WRITER.lock().unwrap_or_else(|e| e.into_inner()).take();
+    match file {
+        Some(mut f) => result.and(f.flush()),
+        None => result,
+    }
+}
+
+/// Whether a trace is being logged.
+pub fn is_recording() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// `RtMalloc` with every call logged while a trace is recording.
+///
+/// A free is logged before the memory is released and an allocation after
+/// it returns, so a pointer's records are in order across threads even when
+/// its address is reused at once.
+pub struct TraceMalloc;
+
+unsafe impl GlobalAlloc for TraceMalloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { RtMalloc.alloc(layout) };
+        log(Op::Alloc, layout, ptr, core::ptr::null_mut());
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { RtMalloc.alloc_zeroed(layout) };
+        log(Op::AllocZeroed, layout, ptr, core::ptr::null_mut());
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        log(Op::Dealloc, layout, ptr, core::ptr::null_mut());
+        unsafe { RtMalloc.dealloc(ptr, layout) };
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return unsafe { RtMalloc.realloc(ptr, layout, new_size) };
+        }
+        // Reserved first: the old block may be freed and handed to another
+        // thread before this call returns.
+        let pos = reserve();
+        let new = unsafe { RtMalloc.realloc(ptr, layout, new_size) };
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        publish(pos, Op::Realloc, new_layout, new, ptr);
+        new
+    }
+}
+
+/// Read every record of the trace file at `path`.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<Record>> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let header = MAGIC.len() + 8;
+    if bytes.len() < header || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not an rtmalloc trace"));
+    }
+    let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    let size = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
+    if version != VERSION || size != Record::SIZE {
+        return Err(invalid("unsupported trace version"));
+    }
+    bytes[header..]
+        .chunks_exact(Record::SIZE)
+        .map(|b| Record::decode(b).ok_or_else(|| invalid("bad record")))
+        .collect()
+}
+
+/// One call of a prepared [`Replay`], on block slots instead of addresses.
+#[derive(Clone, Copy)]
+enum Step {
+    Alloc {
+        slot: usize,
+        layout: Layout,
+        zeroed: bool,
+    },
+    Dealloc {
+        slot: usize,
+    },
+    Realloc {
+        slot: usize,
+        new_size: usize,
+    },
+}
+
+/// A trace prepared for replay: addresses are resolved to block slots up
+/// front, so a run only indexes an array and times the allocator alone.
+///
+/// Calls are replayed in logged order on one thread. Frees of blocks
+/// allocated before the trace started, and calls that failed when logged,
+/// are left out.
+pub struct Replay {
+    steps: Vec<Step>,
+    slots: usize,
+    skipped: usize,
+}
+
+/// What one [`Replay::run`] did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    /// Calls made to the allocator.
+    pub ops: usize,
+    /// Of those, calls that returned null.
+    pub failed: usize,
+    /// Time spent in the calls, excluding freeing what the trace left live.
+    pub elapsed: Duration,
+    /// Most bytes requested and live at once.
+    pub peak_live_bytes: usize,
+}
+
+impl Replay {
+    pub fn new(records: &[Record]) -> Replay {
+        let mut steps = Vec::with_capacity(records.len());
+        let mut live: HashMap<u64, usize> = HashMap::new();
+        let mut free_slots = Vec::new();
+        let (mut slots, mut skipped) = (0, 0);
+        let mut take_slot = |free_slots: &mut Vec<usize>| {
+            free_slots.pop().unwrap_or_else(|| {
+                slots += 1;
+                slots - 1
+            })
+        };
+        for r in records {
+            match r.op {
+                Op::Alloc | Op::AllocZeroed => {
+                    let Ok(layout) = Layout::from_size_align(r.size as usize, r.align as usize)
+                    else {
+                        skipped += 1;
+                        continue;
+                    };
+                    if r.ptr == 0 || layout.size() == 0 {
+                        skipped += 1;
+                        continue;
+                    }
+                    let slot = take_slot(&mut free_slots);
+                    live.insert(r.ptr, slot);
+                    let zeroed = r.op == Op::AllocZeroed;
+                    steps.push(Step::Alloc {
+                        slot,
+                        layout,
+                        zeroed,
+                    });
+                }
+                Op::Dealloc => match live.remove(&r.ptr) {
+                    Some(slot) => {
+                        steps.push(Step::Dealloc { slot });
+                        free_slots.push(slot);
+                    }
+                    None => skipped += 1,
+                },
+                Op::Realloc => match live.remove(&r.old_ptr) {
+                    Some(slot) if r.ptr != 0 && r.size != 0 => {
+                        live.insert(r.ptr, slot);
+                        steps.push(Step::Realloc {
+                            slot,
+                            new_size: r.size as usize,
+                        });
+                    }
+                    Some(slot) => {
+                        // Failed: the old block is still live.
+                        live.insert(r.old_ptr, slot);
+                        skipped += 1;
+                    }
+                    None => skipped += 1,
+                },
+            }
+        }
+        Replay {
+            steps,
+            slots,
+            skipped,
+        }
+    }
+
+    /// Calls the replay makes.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Logged calls left out (see [`Replay`]).
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Make every call against `alloc`, writing the first byte of each new
+    /// block, then free whatever the trace left live.
+    pub fn run<A: GlobalAlloc>(&self, alloc: &A) -> ReplayStats {
+        let empty = (core::ptr::null_mut::<u8>(), Layout::new::<u8>());
+        let mut blocks = std::vec![empty; self.slots];
+        let mut stats = ReplayStats::default();
+        let mut live_bytes = 0usize;
+        let start = Instant::now();
+        for step in &self.steps {
+            stats.ops += 1;
+            match *step {
+                Step::Alloc {
+                    slot,
+                    layout,
+                    zeroed,
+                } => {
+                    let ptr = unsafe {
+                        if zeroed {
+                            alloc.alloc_zeroed(layout)
+                        } else {
+                            alloc.alloc(layout)
+                        }
+                    };
+                    if ptr.is_null() {
+                        stats.failed += 1;
+                        continue;
+                    }
+                    unsafe { ptr.write(1) };
+                    blocks[slot] = (ptr, layout);
+                    live_bytes += layout.size();
+                }
+                Step::Dealloc { slot } => {
+                    let (ptr, layout) = core::mem::replace(&mut blocks[slot], empty);
+                    if !ptr.is_null() {
+                        unsafe { alloc.dealloc(ptr, layout) };
+                        live_bytes -= layout.size();
+                    }
+                }
+                Step::Realloc { slot, new_size } => {
+                    let (ptr, layout) = blocks[slot];
+                    if ptr.is_null() {
+                        continue;
+                    }
+                    let new = unsafe { alloc.realloc(ptr, layout, new_size) };
+                    if new.is_null() {
+                        stats.failed += 1;
+                        continue;
+                    }
+                    unsafe { new.write(1) };
+                    let new_layout =
+                        unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+                    blocks[slot] = (new, new_layout);
+                    live_bytes = live_bytes - layout.size() + new_size;
+                }
+            }
+            stats.peak_live_bytes = stats.peak_live_bytes.max(live_bytes);
+        }
+        stats.elapsed = start.elapsed();
+        for (ptr, layout) in blocks {
+            if !ptr.is_null() {
+                unsafe { alloc.dealloc(ptr, layout) };
+            }
+        }
+        stats
+    }
+}
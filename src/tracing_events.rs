@@ -0,0 +1,172 @@
+//! `tracing` integration for slow-path events (`tracing` feature).
+//!
+//! Heap growth, span release, thread cache scavenges and per-CPU drains are
+//! reported as `tracing` events with target `rtmalloc` at `DEBUG` level,
+//! carrying byte counts and, where the operation is timed, `nanos`.
+//!
+//! Subscribers allocate, and these operations run under allocator locks, so
+//! nothing is emitted where it happens. Each event is queued on the thread
+//! that hit it and emitted when that thread leaves the allocator (its
+//! outermost `ReentrancyGuard` drops). This is also why the operations are
+//! reported as events with a duration rather than as `tracing` spans. Events
+//! caused by the subscriber's own allocations wait for the next flush; if
+//! more than [`QUEUE_LEN`] pile up before a flush, the rest are counted and
+//! reported as dropped.
+
+use core::cell::RefCell;
+use core::mem;
+
+/// Events queued per thread between flushes.
+pub const QUEUE_LEN: usize = 16;
+
+/// A slow-path operation worth reporting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Event {
+    /// Memory was requested from the OS.
+    HeapGrow { bytes: usize, nanos: u64 },
+    /// A central free list gave an empty span back to the page heap.
+    SpanRelease { size_class: usize, bytes: usize },
+    /// A thread cache over its budget released idle objects.
+    Scavenge { bytes: usize, nanos: u64 },
+    /// A per-CPU slab moved a batch to the transfer cache.
+    #[cfg(feature = "percpu")]
+    CpuDrain {
+        size_class: usize,
+        objects: usize,
+        bytes: usize,
+        nanos: u64,
+    },
+}
+
+struct Queue {
+    events: [Event; QUEUE_LEN],
+    len: usize,
+    dropped: usize,
+    flushing: bool,
+}
+
+std::thread_local! {
+    static QUEUE: RefCell<Queue> = const {
+        RefCell::new(Queue {
+            events: [Event::HeapGrow { bytes: 0, nanos: 0 }; QUEUE_LEN],
+            len: 0,
+            dropped: 0,
+            flushing: false,
+        })
+    };
+}
+
+/// Queue `event` for the calling thread's next flush. Never allocates.
+#[inline]
+pub(crate) fn record(event: Event) {
+    let _ = QUEUE.try_with(|q| {
+        let Ok(mut q) = q.try_borrow_mut() else {
+            return;
+        };
+        if q.len < QUEUE_LEN {
+            let len = q.len;
+            q.events[len] = event;
+            q.len += 1;
+        } else {
+            q.dropped += 1;
+        }
+    });
+}
+
+/// Emit the calling thread's queued events. Called when the thread leaves
+/// the allocator; does nothing while a flush is already running.
+#[inline]
+pub(crate) fn flush() {
+    let _ = QUEUE.try_with(|q| {
+        let pending = q
+            .try_borrow()
+            .is_ok_and(|q| !q.flushing && (q.len > 0 || q.dropped > 0));
+        if pending {
+            drain(q);
+        }
+    });
+}
+
+#[cold]
+fn drain(q: &RefCell<Queue>) {
+    q.borrow_mut().flushing = true;
+    // Emitting can allocate and queue more events. Take a few rounds of
+    // those; anything left waits for the next flush.
+    for _ in 0..4 {
+        let (events, len, dropped) = {
+            let mut q = q.borrow_mut();
+            (q.events, mem::take(&mut q.len), mem::take(&mut q.dropped))
+        };
+        if len == 0 && dropped == 0 {
+            break;
+        }
+        for event in &events[..len] {
+            emit(event);
+        }
+        if dropped > 0 {
+            tracing::debug!(target: "rtmalloc", dropped, "events dropped");
+        }
+    }
+    q.borrow_mut().flushing = false;
+}
+
+fn emit(event: &Event) {
+    match *event {
+        Event::HeapGrow { bytes, nanos } => {
+            tracing::debug!(target: "rtmalloc", bytes, nanos, "heap grow");
+        }
+        Event::SpanRelease { size_class, bytes } => {
+            tracing::debug!(target: "rtmalloc", size_class, bytes, "span release");
+        }
+        Event::Scavenge { bytes, nanos } => {
+            tracing::debug!(target: "rtmalloc", bytes, nanos, "thread cache scavenge");
+        }
+        #[cfg(feature = "percpu")]
+        Event::CpuDrain {
+            size_class,
+            objects,
+            bytes,
+            nanos,
+        } => {
+            tracing::debug!(
+                target: "rtmalloc",
+                size_class,
+                objects,
+                bytes,
+                nanos,
+                "per-cpu drain"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued() -> (usize, usize) {
+        QUEUE.with(|q| {
+            let q = q.borrow();
+            (q.len, q.dropped)
+        })
+    }
+
+    #[test]
+    fn test_queue_and_flush() {
+        let grow = Event::HeapGrow {
+            bytes: 1 << 20,
+            nanos: 5,
+        };
+        record(grow);
+        assert_eq!(queued(), (1, 0));
+        assert_eq!(QUEUE.with(|q| q.borrow().events[0]), grow);
+
+        for _ in 0..QUEUE_LEN {
+            record(grow);
+        }
+        assert_eq!(queued(), (QUEUE_LEN, 1));
+
+        flush();
+        assert_eq!(queued(), (0, 0));
+    }
+}
@@ -4,32 +4,96 @@
 //! transfer full batches to/from here in O(1). This avoids the per-object span
 //! lookups in the central free list for the common case where one thread frees
 //! a batch and another allocates it.
+//!
+//! The bytes held are counted per class and in total. With
+//! `max_transfer_bytes` set, caching a batch that would take the total past
+//! it evicts the oldest batch of the same class to the central free list
+//! instead, or sends the new batch there if the class has none. The cap is
+//! checked without a global lock, so concurrent inserts can overshoot it by
+//! a batch each.
+//!
+//! With the `lockfree-transfer` feature, each class caches its batches in
+//! a bounded lock-free MPMC ring instead of a slot array under a spinlock
+//! (see [`BatchRing`]), so threads trading batches of a hot class never
+//! wait for each other. The ring hands out its oldest batch first whatever
+//! the class's [`ReuseOrder`](crate::size_class::ReuseOrder), and only the
+//! partial batches built from single-object frees still take a lock. The
+//! slot array stays the default until the ring has proven itself.
+//!
+//! With the `minimal` feature the cache is collapsed: `TransferCacheArray` is
+//! zero-sized and every call goes straight to the central free list.
 
 use crate::central_free_list::{self, CentralCache};
 use crate::page_heap::PageHeap;
 use crate::pagemap::PageMap;
-use crate::size_class::{self, NUM_SIZE_CLASSES};
 use crate::span::FreeObject;
 use crate::sync::SpinMutex;
-use core::ptr;
 
-use crate::config::MAX_TRANSFER_SLOTS;
+cfg_if::cfg_if! {
+    if #[cfg(not(feature = "minimal"))] {
+        use crate::config::{MAX_TRANSFER_BYTES, MAX_TRANSFER_SLOTS};
+        use crate::size_class::{self, NUM_SIZE_CLASSES};
+        #[cfg(not(feature = "lockfree-transfer"))]
+        use crate::size_class::ReuseOrder;
+        use crate::sync::CachePadded;
+        use core::ptr;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+    }
+}
+
+/// A cached batch as (count, head, tail).
+#[cfg(not(feature = "minimal"))]
+type Batch = (usize, *mut FreeObject, *mut FreeObject);
+
+/// Split the first `count` objects off `batch`. Returns them and, if any
+/// are left, the rest.
+///
+/// # Safety
+///
+/// `batch` must be a list of its count objects ending at its tail.
+#[cfg(not(feature = "minimal"))]
+unsafe fn split_batch(batch: Batch, count: usize) -> (Batch, Option<Batch>) {
+    let (len, head, tail) = batch;
+    if count == 0 || count >= len {
+        return (batch, None);
+    }
+    let mut last = head;
+    for _ in 1..count {
+        last = unsafe { FreeObject::next(last) };
+    }
+    let rest = unsafe { FreeObject::next(last) };
+    unsafe { FreeObject::set_next(last, ptr::null_mut()) };
+    ((count, head, last), Some((len - count, rest, tail)))
+}
 
+#[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
 #[derive(Clone, Copy)]
 struct TransferCacheSlot {
     head: *mut FreeObject,
     tail: *mut FreeObject,
 }
 
-/// Per-size-class transfer cache (LIFO stack of batches).
+/// Per-size-class transfer cache: a ring of batches, popped from the newest
+/// end (LIFO) or the oldest (FIFO) according to the class's [`ReuseOrder`].
+#[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
 struct TransferCacheInner {
     slots: [TransferCacheSlot; MAX_TRANSFER_SLOTS],
+    /// Slot of the oldest cached batch.
+    first: usize,
     used: usize,
+    /// Objects in each cached batch. Fixed while any batch is cached, so a
+    /// runtime batch size change only takes effect once the cache empties.
+    batch: usize,
+    /// Batch being assembled from single-object frees (`insert_one`).
+    partial: TransferCacheSlot,
+    partial_len: usize,
 }
 
 // SAFETY: Only accessed through external SpinMutex synchronization.
+#[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
 unsafe impl Send for TransferCacheInner {}
 
+#[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
 impl TransferCacheInner {
     const fn new() -> Self {
         Self {
@@ -37,35 +101,317 @@ impl TransferCacheInner {
                 head: ptr::null_mut(),
                 tail: ptr::null_mut(),
             }; MAX_TRANSFER_SLOTS],
+            first: 0,
             used: 0,
+            batch: 0,
+            partial: TransferCacheSlot {
+                head: ptr::null_mut(),
+                tail: ptr::null_mut(),
+            },
+            partial_len: 0,
         }
     }
 
-    /// Pop a batch. Returns (head, tail) or None.
-    fn pop(&mut self) -> Option<(*mut FreeObject, *mut FreeObject)> {
+    /// Pop the newest (LIFO) or oldest (FIFO) batch. Returns
+    /// (count, head, tail) or None.
+    fn pop(&mut self, order: ReuseOrder) -> Option<(usize, *mut FreeObject, *mut FreeObject)> {
         if self.used == 0 {
             return None;
         }
         self.used -= 1;
-        let slot = self.slots[self.used];
-        Some((slot.head, slot.tail))
+        let i = match order {
+            ReuseOrder::Lifo => (self.first + self.used) % MAX_TRANSFER_SLOTS,
+            ReuseOrder::Fifo => {
+                let i = self.first;
+                self.first = (self.first + 1) % MAX_TRANSFER_SLOTS;
+                i
+            }
+        };
+        let slot = self.slots[i];
+        Some((self.batch, slot.head, slot.tail))
     }
 
-    /// Push a batch. Returns true if successful, false if full.
-    fn push(&mut self, head: *mut FreeObject, tail: *mut FreeObject) -> bool {
-        if self.used >= MAX_TRANSFER_SLOTS {
+    /// Push a batch of `count` objects. Returns false if the cache is full or
+    /// holds batches of a different size.
+    fn push(&mut self, head: *mut FreeObject, tail: *mut FreeObject, count: usize) -> bool {
+        if self.used >= MAX_TRANSFER_SLOTS || (self.used > 0 && count != self.batch) {
             return false;
         }
-        self.slots[self.used] = TransferCacheSlot { head, tail };
+        self.slots[(self.first + self.used) % MAX_TRANSFER_SLOTS] =
+            TransferCacheSlot { head, tail };
         self.used += 1;
+        self.batch = count;
         true
     }
+
+    /// Add one object to the partial batch. Returns the batch as
+    /// (count, head, tail) once it holds at least `batch_size` objects.
+    unsafe fn push_one(
+        &mut self,
+        obj: *mut FreeObject,
+        batch_size: usize,
+    ) -> Option<(usize, *mut FreeObject, *mut FreeObject)> {
+        unsafe { FreeObject::set_next(obj, self.partial.head) };
+        if self.partial.head.is_null() {
+            self.partial.tail = obj;
+        }
+        self.partial.head = obj;
+        self.partial_len += 1;
+        if self.partial_len < batch_size {
+            return None;
+        }
+        self.take_partial()
+    }
+
+    /// Take the partial batch. Returns (count, head, tail) or None.
+    fn take_partial(&mut self) -> Option<(usize, *mut FreeObject, *mut FreeObject)> {
+        if self.partial_len == 0 {
+            return None;
+        }
+        let batch = (self.partial_len, self.partial.head, self.partial.tail);
+        self.clear_partial();
+        Some(batch)
+    }
+
+    fn clear_partial(&mut self) {
+        self.partial.head = ptr::null_mut();
+        self.partial.tail = ptr::null_mut();
+        self.partial_len = 0;
+    }
+
+    /// Take up to `count` objects: from the partial batch if there is one,
+    /// else from the next batch in `order`. What is left over becomes the
+    /// partial batch.
+    unsafe fn take_split(&mut self, count: usize, order: ReuseOrder) -> Option<Batch> {
+        let batch = self.take_partial().or_else(|| self.pop(order))?;
+        let (taken, rest) = unsafe { split_batch(batch, count) };
+        if let Some((len, head, tail)) = rest {
+            self.partial = TransferCacheSlot { head, tail };
+            self.partial_len = len;
+        }
+        Some(taken)
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "lockfree-transfer", not(feature = "minimal")))] {
+        use core::cell::UnsafeCell;
+        use core::sync::atomic::fence;
+
+        /// One slot of a [`BatchRing`]. `stamp` says whose turn it is: equal
+        /// to a push position when the slot is free for that push, one past
+        /// it once the batch is written and free for the matching pop.
+        struct RingSlot {
+            stamp: AtomicUsize,
+            batch: UnsafeCell<Batch>,
+        }
+
+        /// Bounded lock-free MPMC queue of batches, after Vyukov's bounded
+        /// queue as crossbeam's `ArrayQueue` builds it.
+        ///
+        /// `head` and `tail` count positions, each an index into `slots` in
+        /// the low bits and a lap count above them. A push claims `tail` with
+        /// a CAS once the slot's stamp shows it free, writes the batch and
+        /// publishes it by bumping the stamp; a pop does the same on `head`
+        /// and hands the slot to the push one lap later. No thread ever waits
+        /// for another to finish, and a full or empty ring fails at once.
+        struct BatchRing {
+            head: AtomicUsize,
+            tail: AtomicUsize,
+            slots: [RingSlot; MAX_TRANSFER_SLOTS],
+        }
+
+        // SAFETY: a slot's batch is only touched by the thread whose CAS
+        // claimed it, and handed over through the stamp's release/acquire.
+        unsafe impl Sync for BatchRing {}
+        unsafe impl Send for BatchRing {}
+
+        impl BatchRing {
+            /// Positions per lap: the slot count rounded up to a power of two
+            /// past it, so a lap never lands on an index.
+            const ONE_LAP: usize = (MAX_TRANSFER_SLOTS + 1).next_power_of_two();
+
+            const fn new() -> Self {
+                let mut slots = [const {
+                    RingSlot {
+                        stamp: AtomicUsize::new(0),
+                        batch: UnsafeCell::new((0, ptr::null_mut(), ptr::null_mut())),
+                    }
+                }; MAX_TRANSFER_SLOTS];
+                let mut i = 0;
+                while i < MAX_TRANSFER_SLOTS {
+                    slots[i].stamp = AtomicUsize::new(i);
+                    i += 1;
+                }
+                Self {
+                    head: AtomicUsize::new(0),
+                    tail: AtomicUsize::new(0),
+                    slots,
+                }
+            }
+
+            /// Position after `pos`: the next index, or index 0 of the next lap.
+            fn next(pos: usize) -> usize {
+                if (pos & (Self::ONE_LAP - 1)) + 1 < MAX_TRANSFER_SLOTS {
+                    pos + 1
+                } else {
+                    (pos & !(Self::ONE_LAP - 1)).wrapping_add(Self::ONE_LAP)
+                }
+            }
+
+            /// Queue `batch`, or hand it back if the ring is full.
+            fn push(&self, batch: Batch) -> Result<(), Batch> {
+                let mut tail = self.tail.load(Ordering::Relaxed);
+                loop {
+                    let slot = &self.slots[tail & (Self::ONE_LAP - 1)];
+                    let stamp = slot.stamp.load(Ordering::Acquire);
+                    if stamp == tail {
+                        match self.tail.compare_exchange_weak(
+                            tail,
+                            Self::next(tail),
+                            Ordering::SeqCst,
+                            Ordering::Relaxed,
+                        ) {
+                            Ok(_) => {
+                                unsafe { *slot.batch.get() = batch };
+                                slot.stamp.store(tail + 1, Ordering::Release);
+                                return Ok(());
+                            }
+                            Err(current) => tail = current,
+                        }
+                    } else if stamp.wrapping_add(Self::ONE_LAP) == tail + 1 {
+                        // The slot still holds the batch from a lap ago: full,
+                        // unless a pop has moved on since.
+                        fence(Ordering::SeqCst);
+                        if self.head.load(Ordering::Relaxed).wrapping_add(Self::ONE_LAP) == tail {
+                            return Err(batch);
+                        }
+                        tail = self.tail.load(Ordering::Relaxed);
+                    } else {
+                        // Another push claimed the slot and is still writing.
+                        core::hint::spin_loop();
+                        tail = self.tail.load(Ordering::Relaxed);
+                    }
+                }
+            }
+
+            /// Take the oldest batch, or `None` if the ring is empty.
+            fn pop(&self) -> Option<Batch> {
+                let mut head = self.head.load(Ordering::Relaxed);
+                loop {
+                    let slot = &self.slots[head & (Self::ONE_LAP - 1)];
+                    let stamp = slot.stamp.load(Ordering::Acquire);
+                    if stamp == head + 1 {
+                        match self.head.compare_exchange_weak(
+                            head,
+                            Self::next(head),
+                            Ordering::SeqCst,
+                            Ordering::Relaxed,
+                        ) {
+                            Ok(_) => {
+                                let batch = unsafe { *slot.batch.get() };
+                                slot.stamp
+                                    .store(head.wrapping_add(Self::ONE_LAP), Ordering::Release);
+                                return Some(batch);
+                            }
+                            Err(current) => head = current,
+                        }
+                    } else if stamp == head {
+                        // Nothing written here yet: empty, unless a push has
+                        // claimed a slot since.
+                        fence(Ordering::SeqCst);
+                        if self.tail.load(Ordering::Relaxed) == head {
+                            return None;
+                        }
+                        head = self.head.load(Ordering::Relaxed);
+                    } else {
+                        // Another pop took the slot and is still reading.
+                        core::hint::spin_loop();
+                        head = self.head.load(Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+
+        /// Batch being assembled from single-object frees (`insert_one`).
+        struct PartialBatch {
+            head: *mut FreeObject,
+            tail: *mut FreeObject,
+            len: usize,
+        }
+
+        // SAFETY: Only accessed through external SpinMutex synchronization.
+        unsafe impl Send for PartialBatch {}
+
+        impl PartialBatch {
+            /// Take the batch, if it holds any object.
+            fn take(&mut self) -> Option<Batch> {
+                if self.len == 0 {
+                    return None;
+                }
+                let batch = (self.len, self.head, self.tail);
+                *self = Self { head: ptr::null_mut(), tail: ptr::null_mut(), len: 0 };
+                Some(batch)
+            }
+        }
+
+        /// Per-size-class transfer cache of a `lockfree-transfer` build.
+        struct RingCache {
+            ring: BatchRing,
+            /// Objects in the ring and the partial batch.
+            objects: AtomicUsize,
+            partial: SpinMutex<PartialBatch>,
+        }
+
+        impl RingCache {
+            const fn new() -> Self {
+                Self {
+                    ring: BatchRing::new(),
+                    objects: AtomicUsize::new(0),
+                    partial: SpinMutex::new(PartialBatch {
+                        head: ptr::null_mut(),
+                        tail: ptr::null_mut(),
+                        len: 0,
+                    }),
+                }
+            }
+
+            /// Take the oldest batch, or failing that the partial one.
+            fn take(&self) -> Option<Batch> {
+                let batch = self.ring.pop().or_else(|| self.partial.lock().take())?;
+                self.objects.fetch_sub(batch.0, Ordering::Relaxed);
+                Some(batch)
+            }
+
+            /// Take up to `count` objects: from the partial batch if there
+            /// is one, else from the oldest batch. What is left over becomes
+            /// the partial batch.
+            unsafe fn take_split(&self, count: usize) -> Option<Batch> {
+                let mut partial = self.partial.lock();
+                let batch = partial.take().or_else(|| self.ring.pop())?;
+                let (taken, rest) = unsafe { split_batch(batch, count) };
+                if let Some((len, head, tail)) = rest {
+                    *partial = PartialBatch { head, tail, len };
+                }
+                drop(partial);
+                self.objects.fetch_sub(taken.0, Ordering::Relaxed);
+                Some(taken)
+            }
+        }
+    }
 }
 
 /// Array of transfer caches, one per size class.
-/// Each is individually locked (separate from central free list locks).
+/// Each is individually locked (separate from central free list locks), or
+/// a lock-free ring with `lockfree-transfer`, and sits on its own cache line.
 pub struct TransferCacheArray {
-    caches: [SpinMutex<TransferCacheInner>; NUM_SIZE_CLASSES],
+    #[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
+    caches: [CachePadded<SpinMutex<TransferCacheInner>>; NUM_SIZE_CLASSES],
+    #[cfg(all(feature = "lockfree-transfer", not(feature = "minimal")))]
+    caches: [CachePadded<RingCache>; NUM_SIZE_CLASSES],
+    /// Bytes in cached and partial batches across all classes.
+    #[cfg(not(feature = "minimal"))]
+    bytes: AtomicUsize,
 }
 
 impl Default for TransferCacheArray {
@@ -77,12 +423,184 @@ impl Default for TransferCacheArray {
 impl TransferCacheArray {
     pub const fn new() -> Self {
         Self {
-            caches: [const { SpinMutex::new(TransferCacheInner::new()) }; NUM_SIZE_CLASSES],
+            #[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
+            caches: [const { CachePadded::new(SpinMutex::new(TransferCacheInner::new())) };
+                NUM_SIZE_CLASSES],
+            #[cfg(all(feature = "lockfree-transfer", not(feature = "minimal")))]
+            caches: [const { CachePadded::new(RingCache::new()) }; NUM_SIZE_CLASSES],
+            #[cfg(not(feature = "minimal"))]
+            bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Bytes of `size_class` held in cached and partial batches.
+    pub fn cached_bytes(&self, size_class: usize) -> usize {
+        self.cached_objects(size_class) * crate::size_class::class_to_size(size_class)
+    }
+
+    /// Bytes held across all classes.
+    pub fn total_bytes(&self) -> usize {
+        #[cfg(not(feature = "minimal"))]
+        {
+            self.bytes.load(Ordering::Relaxed)
+        }
+        #[cfg(feature = "minimal")]
+        {
+            0
+        }
+    }
+
+    #[cfg(not(feature = "minimal"))]
+    fn add_bytes(&self, size_class: usize, count: usize) {
+        let bytes = count * size_class::class_to_size(size_class);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    #[cfg(not(feature = "minimal"))]
+    fn sub_bytes(&self, size_class: usize, count: usize) {
+        let bytes = count * size_class::class_to_size(size_class);
+        self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    /// Cache a full batch under its class lock, keeping the total within
+    /// `cap` bytes (0 = none) by evicting the class's oldest batch. Returns
+    /// the batch, as (count, head), that has to go to the central list
+    /// instead: the evicted one, or the new one if it could not be cached.
+    #[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
+    #[allow(clippy::too_many_arguments)]
+    fn cache_batch(
+        &self,
+        tc: &mut TransferCacheInner,
+        size_class: usize,
+        head: *mut FreeObject,
+        tail: *mut FreeObject,
+        count: usize,
+        cap: usize,
+    ) -> Option<(usize, *mut FreeObject)> {
+        let bytes = count * size_class::class_to_size(size_class);
+        if cap != 0 && self.bytes.load(Ordering::Relaxed) + bytes > cap {
+            // Same-sized batches swap one for one, leaving the total as is.
+            if tc.used == 0 || tc.batch != count {
+                return Some((count, head));
+            }
+            let (old_count, old_head, _) = tc.pop(ReuseOrder::Fifo)?;
+            tc.push(head, tail, count);
+            crate::stat_inc!(transfer_cache_evictions);
+            return Some((old_count, old_head));
+        }
+        if !tc.push(head, tail, count) {
+            return Some((count, head));
+        }
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        None
+    }
+
+    /// Cache a full batch in its class's ring, keeping the total within
+    /// `cap` bytes (0 = none) by evicting the class's oldest batch. Returns
+    /// the batches, as (count, head), that have to go to the central list
+    /// instead: the evicted one, and the new one if the ring was full.
+    #[cfg(all(feature = "lockfree-transfer", not(feature = "minimal")))]
+    fn cache_batch(
+        &self,
+        size_class: usize,
+        head: *mut FreeObject,
+        tail: *mut FreeObject,
+        count: usize,
+        cap: usize,
+    ) -> [Option<(usize, *mut FreeObject)>; 2] {
+        let tc = &self.caches[size_class];
+        let bytes = count * size_class::class_to_size(size_class);
+        let mut evicted = None;
+        if cap != 0 && self.bytes.load(Ordering::Relaxed) + bytes > cap {
+            let Some((old_count, old_head, _)) = tc.take() else {
+                return [Some((count, head)), None];
+            };
+            self.sub_bytes(size_class, old_count);
+            crate::stat_inc!(transfer_cache_evictions);
+            evicted = Some((old_count, old_head));
+        }
+        // Counted before the push, so a pop racing it never takes the
+        // counts below zero.
+        tc.objects.fetch_add(count, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        if tc.ring.push((count, head, tail)).is_err() {
+            tc.objects.fetch_sub(count, Ordering::Relaxed);
+            self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+            return [evicted, Some((count, head))];
+        }
+        [evicted, None]
+    }
+
+    /// Objects of `size_class` held in cached and partial batches.
+    pub fn cached_objects(&self, size_class: usize) -> usize {
+        #[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
+        {
+            let tc = self.caches[size_class].lock();
+            tc.used * tc.batch + tc.partial_len
+        }
+        #[cfg(all(feature = "lockfree-transfer", not(feature = "minimal")))]
+        {
+            self.caches[size_class].objects.load(Ordering::Relaxed)
+        }
+        #[cfg(feature = "minimal")]
+        {
+            let _ = size_class;
+            0
+        }
+    }
+
+    /// Move every cached and partial batch to the central free lists and
+    /// return the bytes moved, so spans they kept busy can go back to the
+    /// page heap.
+    pub fn drain(
+        &self,
+        central: &CentralCache,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
+    ) -> usize {
+        #[cfg(not(feature = "minimal"))]
+        {
+            let mut bytes = 0;
+            for cls in 1..NUM_SIZE_CLASSES {
+                loop {
+                    #[cfg(not(feature = "lockfree-transfer"))]
+                    let batch = {
+                        let mut tc = self.caches[cls].lock();
+                        tc.pop(ReuseOrder::Fifo).or_else(|| tc.take_partial())
+                    };
+                    #[cfg(feature = "lockfree-transfer")]
+                    let batch = self.caches[cls].take();
+                    let Some((count, head, _)) = batch else {
+                        break;
+                    };
+                    self.sub_bytes(cls, count);
+                    bytes += count * size_class::class_to_size(cls);
+                    unsafe {
+                        central_free_list::insert_range_dropping_lock(
+                            central.get(cls),
+                            head,
+                            count,
+                            page_heap,
+                            pagemap,
+                        )
+                    };
+                }
+            }
+            bytes
+        }
+        #[cfg(feature = "minimal")]
+        {
+            let _ = (central, page_heap, pagemap);
+            0
         }
     }
 
     /// Remove a batch of objects for the given size class.
     /// Tries transfer cache first (O(1)), falls through to central free list on miss.
+    /// Fewer than a batch's worth are split off a cached batch, the rest
+    /// kept as the partial batch.
+    /// Returns (count, head, tail) so callers can splice the list without
+    /// walking it.
     ///
     /// # Safety
     ///
@@ -94,20 +612,46 @@ impl TransferCacheArray {
         central: &CentralCache,
         page_heap: &SpinMutex<PageHeap>,
         pagemap: &PageMap,
-    ) -> (usize, *mut FreeObject) {
-        let batch_size = size_class::class_info(size_class).batch_size;
-
-        // Try transfer cache (O(1) if hit)
+    ) -> (usize, *mut FreeObject, *mut FreeObject) {
+        // Try transfer cache (O(1) if hit). A caller asking for less than a
+        // batch gets just that, split off the partial batch or a whole one,
+        // so low-demand paths don't pull in a batch they won't use.
+        #[cfg(not(feature = "minimal"))]
+        let small = count < size_class::batch_size(size_class);
+        #[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
         {
+            let order = size_class::reuse_order(size_class);
             let mut tc = self.caches[size_class].lock();
-            if let Some((head, _tail)) = tc.pop() {
-                return (batch_size, head);
+            let batch = if small {
+                unsafe { tc.take_split(count, order) }
+            } else {
+                tc.pop(order).or_else(|| tc.take_partial())
+            };
+            if let Some(batch) = batch {
+                self.sub_bytes(size_class, batch.0);
+                crate::stat_inc!(transfer_cache_hits);
+                return batch;
+            }
+        }
+        #[cfg(all(feature = "lockfree-transfer", not(feature = "minimal")))]
+        {
+            let tc = &self.caches[size_class];
+            let batch = if small {
+                unsafe { tc.take_split(count) }
+            } else {
+                tc.take()
+            };
+            if let Some(batch) = batch {
+                self.sub_bytes(size_class, batch.0);
+                crate::stat_inc!(transfer_cache_hits);
+                return batch;
             }
         }
         // Transfer cache lock released before central lock -- no deadlock possible
+        crate::stat_inc!(central_cache_hits);
 
         // Fall through to central free list (with lock dropping for page heap calls)
-        unsafe {
+        let refill = crate::time_slow_path!(CentralRefill, unsafe {
             central_free_list::remove_range_dropping_lock(
                 central.get(size_class),
                 size_class,
@@ -115,7 +659,10 @@ impl TransferCacheArray {
                 page_heap,
                 pagemap,
             )
-        }
+        });
+        #[cfg(feature = "usdt")]
+        crate::usdt::central_refill(size_class, count, refill.0);
+        refill
     }
 
     /// Insert a batch of objects for the given size class.
@@ -137,16 +684,25 @@ impl TransferCacheArray {
         page_heap: &SpinMutex<PageHeap>,
         pagemap: &PageMap,
     ) {
-        let batch_size = size_class::class_info(size_class).batch_size;
-
         // Only cache exact-batch-size transfers
-        if count == batch_size {
+        #[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
+        let (head, count) = if count == size_class::batch_size(size_class) {
             let mut tc = self.caches[size_class].lock();
-            if tc.push(head, tail) {
-                return;
+            match self.cache_batch(&mut tc, size_class, head, tail, count, MAX_TRANSFER_BYTES) {
+                None => return,
+                // Transfer cache full or over its cap -- fall through
+                Some((count, head)) => (head, count),
             }
-            // Transfer cache full -- fall through
+        } else {
+            (head, count)
+        };
+        #[cfg(all(feature = "lockfree-transfer", not(feature = "minimal")))]
+        if count == size_class::batch_size(size_class) {
+            let spill = self.cache_batch(size_class, head, tail, count, MAX_TRANSFER_BYTES);
+            return unsafe { spill_to_central(spill, size_class, central, page_heap, pagemap) };
         }
+        #[cfg(feature = "minimal")]
+        let _ = tail;
         // Transfer cache lock released before central lock
 
         // Fall through to central free list (with lock dropping for span dealloc)
@@ -160,13 +716,113 @@ impl TransferCacheArray {
             )
         }
     }
+
+    /// Insert a single object freed by a thread without a thread cache.
+    ///
+    /// Objects accumulate in a per-class partial batch under the transfer
+    /// cache lock. A full partial batch becomes a regular batch, or goes to
+    /// the central free list if the cache is full. The central lock is
+    /// taken once per `batch_size` frees instead of once per free.
+    ///
+    /// # Safety
+    ///
+    /// `obj` must be a free object of `size_class`, not referenced by any
+    /// other list.
+    pub unsafe fn insert_one(
+        &self,
+        size_class: usize,
+        obj: *mut FreeObject,
+        central: &CentralCache,
+        page_heap: &SpinMutex<PageHeap>,
+        pagemap: &PageMap,
+    ) {
+        #[cfg(feature = "minimal")]
+        let (head, count) = (obj, 1);
+        #[cfg(all(feature = "lockfree-transfer", not(feature = "minimal")))]
+        {
+            let batch_size = size_class::batch_size(size_class);
+            let tc = &self.caches[size_class];
+            let mut partial = tc.partial.lock();
+            unsafe { FreeObject::set_next(obj, partial.head) };
+            if partial.head.is_null() {
+                partial.tail = obj;
+            }
+            partial.head = obj;
+            partial.len += 1;
+            if partial.len < batch_size {
+                tc.objects.fetch_add(1, Ordering::Relaxed);
+                self.add_bytes(size_class, 1);
+                return;
+            }
+            let (count, head, tail) = partial.take().unwrap();
+            drop(partial);
+            // The partial objects counted so far now leave as one batch.
+            tc.objects.fetch_sub(count - 1, Ordering::Relaxed);
+            self.sub_bytes(size_class, count - 1);
+            let spill = self.cache_batch(size_class, head, tail, count, MAX_TRANSFER_BYTES);
+            unsafe { spill_to_central(spill, size_class, central, page_heap, pagemap) }
+        }
+        #[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
+        let (head, count) = {
+            let batch_size = size_class::batch_size(size_class);
+            let mut tc = self.caches[size_class].lock();
+            let Some((count, head, tail)) = (unsafe { tc.push_one(obj, batch_size) }) else {
+                self.add_bytes(size_class, 1);
+                return;
+            };
+            // The partial objects counted so far now leave as one batch.
+            self.sub_bytes(size_class, count - 1);
+            match self.cache_batch(&mut tc, size_class, head, tail, count, MAX_TRANSFER_BYTES) {
+                None => return,
+                Some((count, head)) => (head, count),
+            }
+        };
+
+        #[cfg(not(all(feature = "lockfree-transfer", not(feature = "minimal"))))]
+        unsafe {
+            central_free_list::insert_range_dropping_lock(
+                central.get(size_class),
+                head,
+                count,
+                page_heap,
+                pagemap,
+            )
+        }
+    }
+}
+
+/// Hand the batches [`TransferCacheArray::cache_batch`] could not keep to
+/// the central free list.
+#[cfg(all(feature = "lockfree-transfer", not(feature = "minimal")))]
+unsafe fn spill_to_central(
+    spill: [Option<(usize, *mut FreeObject)>; 2],
+    size_class: usize,
+    central: &CentralCache,
+    page_heap: &SpinMutex<PageHeap>,
+    pagemap: &PageMap,
+) {
+    for (count, head) in spill.into_iter().flatten() {
+        unsafe {
+            central_free_list::insert_range_dropping_lock(
+                central.get(size_class),
+                head,
+                count,
+                page_heap,
+                pagemap,
+            )
+        };
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::MAX_TRANSFER_SLOTS;
     use crate::page_heap::PageHeap;
     use crate::pagemap::PageMap;
+    #[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
+    use crate::size_class::ReuseOrder;
+    use crate::size_class::{self, NUM_SIZE_CLASSES};
     use alloc::boxed::Box;
 
     fn make_test_env() -> (
@@ -186,25 +842,26 @@ mod tests {
     fn test_transfer_cache_remove_populates() {
         let (pm, heap, central, tc) = make_test_env();
         unsafe {
-            let (count, head) = tc.remove_range(1, 32, &central, &heap, pm);
+            let (count, head, _) = tc.remove_range(1, 32, &central, &heap, pm);
             assert!(count > 0);
             assert!(!head.is_null());
         }
     }
 
     #[test]
+    #[cfg(not(feature = "minimal"))]
     fn test_transfer_cache_roundtrip() {
         let (pm, heap, central, tc) = make_test_env();
         unsafe {
             // Get a batch from central (through transfer cache)
             let batch_size = size_class::class_info(1).batch_size;
-            let (count, head) = tc.remove_range(1, batch_size, &central, &heap, pm);
+            let (count, head, _) = tc.remove_range(1, batch_size, &central, &heap, pm);
             assert_eq!(count, batch_size);
 
             // Find the tail
             let mut tail = head;
             for _ in 1..count {
-                let next = (*tail).next;
+                let next = FreeObject::next(tail);
                 if next.is_null() {
                     break;
                 }
@@ -215,7 +872,7 @@ mod tests {
             tc.insert_range(1, head, tail, count, &central, &heap, pm);
 
             // Remove again -- should come from transfer cache (O(1))
-            let (count2, head2) = tc.remove_range(1, batch_size, &central, &heap, pm);
+            let (count2, head2, _) = tc.remove_range(1, batch_size, &central, &heap, pm);
             assert_eq!(count2, batch_size);
             assert_eq!(head2, head); // Same batch returned (LIFO)
         }
@@ -229,12 +886,12 @@ mod tests {
 
             // Fill 64 slots + central fallthrough
             for _ in 0..MAX_TRANSFER_SLOTS + 1 {
-                let (count, head) = tc.remove_range(4, batch_size, &central, &heap, pm);
+                let (count, head, _) = tc.remove_range(4, batch_size, &central, &heap, pm);
                 assert!(count > 0);
 
                 let mut tail = head;
                 for _ in 1..count {
-                    let next = (*tail).next;
+                    let next = FreeObject::next(tail);
                     if next.is_null() {
                         break;
                     }
@@ -245,9 +902,369 @@ mod tests {
             }
 
             // Should still be able to remove (from transfer cache or central)
-            let (count, head) = tc.remove_range(4, batch_size, &central, &heap, pm);
+            let (count, head, _) = tc.remove_range(4, batch_size, &central, &heap, pm);
             assert!(count > 0);
             assert!(!head.is_null());
         }
     }
+
+    #[test]
+    #[cfg(not(feature = "minimal"))]
+    fn test_remove_fewer_splits_batch() {
+        let (pm, heap, central, tc) = make_test_env();
+        let cls = 3;
+        let batch_size = size_class::batch_size(cls);
+        unsafe {
+            let (count, head, tail) = tc.remove_range(cls, batch_size, &central, &heap, pm);
+            assert_eq!(count, batch_size);
+            tc.insert_range(cls, head, tail, count, &central, &heap, pm);
+            assert_eq!(tc.cached_objects(cls), batch_size);
+            let next = FreeObject::next(head);
+
+            // One object asked for, one handed out; the rest stays cached as
+            // the partial batch and serves the next small requests.
+            let (count, first, last) = tc.remove_range(cls, 1, &central, &heap, pm);
+            assert_eq!((count, first, last), (1, head, head));
+            assert!(FreeObject::next(first).is_null());
+            assert_eq!(tc.cached_objects(cls), batch_size - 1);
+            let (count, second, _) = tc.remove_range(cls, 2, &central, &heap, pm);
+            assert_eq!(count, 2);
+            assert_eq!(second, next);
+            assert_eq!(tc.cached_objects(cls), batch_size - 3);
+
+            // A full request takes what is left.
+            let (count, _, rest_tail) = tc.remove_range(cls, batch_size, &central, &heap, pm);
+            assert_eq!(count, batch_size - 3);
+            assert_eq!(rest_tail, tail);
+            assert_eq!(tc.cached_objects(cls), 0);
+            assert_eq!(tc.total_bytes(), 0);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "minimal"))]
+    fn test_byte_accounting() {
+        let (pm, heap, central, tc) = make_test_env();
+        let cls = 3;
+        let size = size_class::class_to_size(cls);
+        let batch_size = size_class::batch_size(cls);
+        unsafe {
+            let (count, head, tail) = tc.remove_range(cls, batch_size, &central, &heap, pm);
+            assert_eq!(tc.total_bytes(), 0);
+            tc.insert_range(cls, head, tail, count, &central, &heap, pm);
+            assert_eq!(tc.total_bytes(), count * size);
+            assert_eq!(tc.cached_bytes(cls), count * size);
+
+            let (_, head, _) = tc.remove_range(cls, batch_size, &central, &heap, pm);
+            assert_eq!(tc.total_bytes(), 0);
+            tc.insert_one(cls, head, &central, &heap, pm);
+            assert_eq!(tc.total_bytes(), size);
+            assert_eq!(tc.cached_bytes(cls), size);
+        }
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
+    fn test_byte_cap_evicts_oldest() {
+        let (pm, heap, central, tc) = make_test_env();
+        let cls = 3;
+        let batch_size = size_class::batch_size(cls);
+        let batch_bytes = batch_size * size_class::class_to_size(cls);
+        let cap = 2 * batch_bytes;
+        unsafe {
+            let mut first = ptr::null_mut();
+            for i in 0..3 {
+                let (count, head, tail) = central_free_list::remove_range_dropping_lock(
+                    central.get(cls),
+                    cls,
+                    batch_size,
+                    &heap,
+                    pm,
+                );
+                assert_eq!(count, batch_size);
+                if i == 0 {
+                    first = head;
+                }
+                let mut c = tc.caches[cls].lock();
+                if let Some((n, evicted)) = tc.cache_batch(&mut c, cls, head, tail, count, cap) {
+                    // The third batch pushes out the first.
+                    assert_eq!((n, evicted), (batch_size, first));
+                    drop(c);
+                    central_free_list::insert_range_dropping_lock(
+                        central.get(cls),
+                        evicted,
+                        n,
+                        &heap,
+                        pm,
+                    );
+                }
+            }
+            assert_eq!(tc.total_bytes(), cap);
+            assert_eq!(tc.caches[cls].lock().used, 2);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "minimal"))]
+    fn test_insert_one_assembles_batch() {
+        let (pm, heap, central, tc) = make_test_env();
+        unsafe {
+            let batch_size = size_class::class_info(2).batch_size;
+            let (count, head, _) = tc.remove_range(2, batch_size, &central, &heap, pm);
+            assert_eq!(count, batch_size);
+
+            let mut objs = alloc::vec::Vec::new();
+            let mut obj = head;
+            while !obj.is_null() {
+                objs.push(obj);
+                obj = FreeObject::next(obj);
+            }
+            assert_eq!(objs.len(), batch_size);
+
+            // Free one at a time: the last insert completes a full batch.
+            for &obj in &objs {
+                tc.insert_one(2, obj, &central, &heap, pm);
+            }
+            #[cfg(not(feature = "lockfree-transfer"))]
+            {
+                assert_eq!(tc.caches[2].lock().used, 1);
+                assert_eq!(tc.caches[2].lock().partial_len, 0);
+            }
+            #[cfg(feature = "lockfree-transfer")]
+            {
+                assert_eq!(tc.caches[2].partial.lock().len, 0);
+                assert_eq!(tc.cached_objects(2), batch_size);
+            }
+
+            let (count, head, _) = tc.remove_range(2, batch_size, &central, &heap, pm);
+            assert_eq!(count, batch_size);
+            let mut n = 0;
+            let mut obj = head;
+            while !obj.is_null() {
+                assert!(objs.contains(&obj));
+                n += 1;
+                obj = FreeObject::next(obj);
+            }
+            assert_eq!(n, batch_size);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "minimal"))]
+    fn test_remove_takes_partial_batch() {
+        let (pm, heap, central, tc) = make_test_env();
+        unsafe {
+            let batch_size = size_class::class_info(3).batch_size;
+            let (_, head, _) = tc.remove_range(3, batch_size, &central, &heap, pm);
+            let second = FreeObject::next(head);
+
+            tc.insert_one(3, head, &central, &heap, pm);
+            tc.insert_one(3, second, &central, &heap, pm);
+
+            // No full batches: the partial one is handed out as is.
+            let (count, got, tail) = tc.remove_range(3, batch_size, &central, &heap, pm);
+            assert_eq!(count, 2);
+            assert_eq!(got, second);
+            assert_eq!(FreeObject::next(got), head);
+            assert_eq!(tail, head);
+            assert!(FreeObject::next(head).is_null());
+        }
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
+    fn test_cached_batches_share_a_size() {
+        let mut inner = TransferCacheInner::new();
+        let objs: [FreeObject; 3] = unsafe { core::mem::zeroed() };
+        let p = |i: usize| &objs[i] as *const FreeObject as *mut FreeObject;
+
+        assert!(inner.push(p(0), p(0), 4));
+        // A batch of another size waits until the cache is empty.
+        assert!(!inner.push(p(1), p(1), 2));
+        assert!(inner.push(p(1), p(1), 4));
+        assert_eq!(inner.pop(ReuseOrder::Lifo), Some((4, p(1), p(1))));
+        assert_eq!(inner.pop(ReuseOrder::Lifo), Some((4, p(0), p(0))));
+        assert!(inner.push(p(2), p(2), 2));
+        assert_eq!(inner.pop(ReuseOrder::Lifo), Some((2, p(2), p(2))));
+        assert_eq!(inner.pop(ReuseOrder::Lifo), None);
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "minimal", feature = "lockfree-transfer")))]
+    fn test_reuse_order() {
+        let mut inner = TransferCacheInner::new();
+        let objs: [FreeObject; MAX_TRANSFER_SLOTS + 2] = unsafe { core::mem::zeroed() };
+        let p = |i: usize| &objs[i] as *const FreeObject as *mut FreeObject;
+        let head = |b: Option<(usize, *mut FreeObject, *mut FreeObject)>| b.map(|b| b.1);
+
+        for i in 0..3 {
+            assert!(inner.push(p(i), p(i), 1));
+        }
+        assert_eq!(head(inner.pop(ReuseOrder::Fifo)), Some(p(0)));
+        assert_eq!(head(inner.pop(ReuseOrder::Lifo)), Some(p(2)));
+        assert_eq!(head(inner.pop(ReuseOrder::Fifo)), Some(p(1)));
+        assert_eq!(inner.pop(ReuseOrder::Fifo), None);
+
+        // The ring wraps around: a full cache drains oldest first.
+        for i in 0..MAX_TRANSFER_SLOTS {
+            assert!(inner.push(p(i), p(i), 1));
+        }
+        assert!(!inner.push(p(MAX_TRANSFER_SLOTS), p(MAX_TRANSFER_SLOTS), 1));
+        assert_eq!(head(inner.pop(ReuseOrder::Fifo)), Some(p(0)));
+        assert!(inner.push(p(MAX_TRANSFER_SLOTS), p(MAX_TRANSFER_SLOTS), 1));
+        for i in 1..=MAX_TRANSFER_SLOTS {
+            assert_eq!(head(inner.pop(ReuseOrder::Fifo)), Some(p(i)));
+        }
+        assert_eq!(inner.pop(ReuseOrder::Fifo), None);
+    }
+
+    /// Walk `count` objects from `head` and return the last one.
+    unsafe fn last_of(head: *mut FreeObject, count: usize) -> *mut FreeObject {
+        let mut node = head;
+        for _ in 1..count {
+            node = unsafe { FreeObject::next(node) };
+        }
+        node
+    }
+
+    #[test]
+    fn test_remove_range_returns_tail() {
+        let (pm, heap, central, tc) = make_test_env();
+        unsafe {
+            // Largest class: a batch spans several central spans.
+            let cls = NUM_SIZE_CLASSES - 1;
+            let batch_size = size_class::class_info(cls).batch_size;
+            let (count, head, tail) = tc.remove_range(cls, batch_size, &central, &heap, pm);
+            assert!(count > 0);
+            assert_eq!(tail, last_of(head, count));
+
+            // Small class: from central, then as a cached batch.
+            let batch_size = size_class::class_info(1).batch_size;
+            let (count, head, tail) = tc.remove_range(1, batch_size, &central, &heap, pm);
+            assert_eq!(count, batch_size);
+            assert_eq!(tail, last_of(head, count));
+            tc.insert_range(1, head, tail, count, &central, &heap, pm);
+            let (count2, head2, tail2) = tc.remove_range(1, batch_size, &central, &heap, pm);
+            assert_eq!((count2, head2, tail2), (count, head, tail));
+        }
+    }
+
+    #[cfg(all(feature = "lockfree-transfer", not(feature = "minimal")))]
+    fn batch(objs: &[FreeObject], i: usize) -> Batch {
+        let p = &objs[i] as *const FreeObject as *mut FreeObject;
+        (i, p, p)
+    }
+
+    #[test]
+    #[cfg(all(feature = "lockfree-transfer", not(feature = "minimal")))]
+    fn test_ring_byte_cap_evicts_oldest() {
+        let (pm, heap, central, tc) = make_test_env();
+        let cls = 3;
+        let batch_size = size_class::batch_size(cls);
+        let batch_bytes = batch_size * size_class::class_to_size(cls);
+        let cap = 2 * batch_bytes;
+        unsafe {
+            let mut first = ptr::null_mut();
+            for i in 0..3 {
+                let (count, head, tail) = central_free_list::remove_range_dropping_lock(
+                    central.get(cls),
+                    cls,
+                    batch_size,
+                    &heap,
+                    pm,
+                );
+                assert_eq!(count, batch_size);
+                if i == 0 {
+                    first = head;
+                }
+                let spill = tc.cache_batch(cls, head, tail, count, cap);
+                // The third batch pushes out the first.
+                let expected = if i == 2 {
+                    Some((batch_size, first))
+                } else {
+                    None
+                };
+                assert_eq!(spill, [expected, None]);
+                spill_to_central(spill, cls, &central, &heap, pm);
+            }
+            assert_eq!(tc.total_bytes(), cap);
+            assert_eq!(tc.cached_objects(cls), 2 * batch_size);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "lockfree-transfer", not(feature = "minimal")))]
+    fn test_ring_fifo_and_full() {
+        let ring = BatchRing::new();
+        let objs: [FreeObject; MAX_TRANSFER_SLOTS + 1] = unsafe { core::mem::zeroed() };
+        assert_eq!(ring.pop(), None);
+
+        // Several laps: a full ring refuses, and drains oldest first.
+        for lap in 0..3 {
+            for i in 0..MAX_TRANSFER_SLOTS {
+                assert!(ring.push(batch(&objs, i)).is_ok(), "lap {lap} slot {i}");
+            }
+            let extra = batch(&objs, MAX_TRANSFER_SLOTS);
+            assert_eq!(ring.push(extra), Err(extra));
+            for i in 0..MAX_TRANSFER_SLOTS {
+                assert_eq!(ring.pop(), Some(batch(&objs, i)));
+            }
+            assert_eq!(ring.pop(), None);
+        }
+
+        // Interleaved pushes and pops wrap around the end of the slots.
+        for i in 0..3 * MAX_TRANSFER_SLOTS {
+            let b = batch(&objs, i % MAX_TRANSFER_SLOTS);
+            assert!(ring.push(b).is_ok());
+            assert_eq!(ring.pop(), Some(b));
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "lockfree-transfer", not(feature = "minimal")))]
+    fn test_ring_concurrent_push_pop() {
+        use alloc::sync::Arc;
+        use alloc::vec::Vec;
+        const THREADS: usize = 8;
+        const ROUNDS: usize = 20_000;
+        let ring = Arc::new(BatchRing::new());
+        // Each thread moves its own tokens through the shared ring; a token
+        // lost or handed out twice shows up in the final count.
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let ring = Arc::clone(&ring);
+                std::thread::spawn(move || {
+                    let mut taken = 0usize;
+                    for i in 0..ROUNDS {
+                        let token = (t * ROUNDS + i + 1, ptr::null_mut(), ptr::null_mut());
+                        while ring.push(token).is_err() {
+                            if ring.pop().is_some() {
+                                taken += 1;
+                            }
+                        }
+                        if ring.pop().is_some() {
+                            taken += 1;
+                        }
+                    }
+                    taken
+                })
+            })
+            .collect();
+        let mut taken: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        while ring.pop().is_some() {
+            taken += 1;
+        }
+        assert_eq!(taken, THREADS * ROUNDS);
+    }
+
+    #[test]
+    #[cfg(not(feature = "minimal"))]
+    fn test_caches_on_separate_cache_lines() {
+        use crate::config::CACHE_LINE_SIZE;
+        let tc = TransferCacheArray::new();
+        for pair in tc.caches.windows(2) {
+            let end = &*pair[0] as *const _ as usize + size_of_val(&*pair[0]) - 1;
+            assert!(end / CACHE_LINE_SIZE < &*pair[1] as *const _ as usize / CACHE_LINE_SIZE);
+        }
+    }
 }
@@ -0,0 +1,116 @@
+//! SystemTap/DTrace-style static probes on slow-path events (`usdt` feature).
+//!
+//! Each probe is a single `nop` plus an entry in the binary's
+//! `.note.stapsdt` section giving its address, the provider `rtmalloc`, its
+//! name and where its arguments are, in the format SystemTap's `sys/sdt.h`
+//! emits. Tools that read the note (bpftrace, `perf probe`, SystemTap)
+//! patch the `nop` into a breakpoint only while they are attached:
+//!
+//! ```text
+//! # bpftrace -e 'usdt:./server:rtmalloc:heap_grow { @bytes = hist(arg1); }'
+//! ```
+//!
+//! | Probe | Arguments |
+//! |-------|-----------|
+//! | `heap_grow` | address, bytes |
+//! | `span_release` | size class, address, bytes |
+//! | `central_refill` | size class, objects asked for, objects got |
+//! | `thread_cache_scavenge` | bytes released, bytes left in the cache |
+//!
+//! No semaphores: arguments are always computed, but every one of them is
+//! already in hand where its probe sits. Probes exist on Linux for x86_64
+//! and aarch64; elsewhere, and under Miri, the functions are empty.
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    not(miri)
+))]
+macro_rules! probe {
+    ($name:literal, $args:literal $(, $op:ident = $val:expr)* $(,)?) => {
+        // SAFETY: a `nop` and a note section; no registers, memory or
+        // flags change.
+        unsafe {
+            core::arch::asm!(
+                "990: nop",
+                ".pushsection .note.stapsdt, \"\", \"note\"",
+                ".balign 4",
+                ".4byte 992f-991f, 994f-993f, 3",
+                "991: .asciz \"stapsdt\"",
+                "992: .balign 4",
+                "993: .8byte 990b",
+                ".8byte _.stapsdt.base",
+                ".8byte 0",
+                ".asciz \"rtmalloc\"",
+                concat!(".asciz \"", $name, "\""),
+                concat!(".asciz \"", $args, "\""),
+                "994: .balign 4",
+                ".popsection",
+                ".ifndef _.stapsdt.base",
+                ".pushsection .stapsdt.base, \"aGR\", \"progbits\", .stapsdt.base, comdat",
+                ".weak _.stapsdt.base",
+                ".hidden _.stapsdt.base",
+                "_.stapsdt.base: .space 1",
+                ".size _.stapsdt.base, 1",
+                ".popsection",
+                ".endif",
+                $($op = in(reg) $val,)*
+                // Operands must print as `%rdi`, the register syntax of
+                // SystemTap's argument strings.
+                options(nomem, nostack, preserves_flags),
+                #[cfg(target_arch = "x86_64")]
+                options(att_syntax),
+            )
+        }
+    };
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    not(miri)
+)))]
+macro_rules! probe {
+    ($name:literal, $args:literal $(, $op:ident = $val:expr)* $(,)?) => {{
+        $(let _ = $val;)*
+    }};
+}
+
+/// `heap_grow`: the page heap mapped `bytes` from the OS at `addr`.
+#[inline(always)]
+pub(crate) fn heap_grow(addr: usize, bytes: usize) {
+    probe!("heap_grow", "8@{a} 8@{b}", a = addr, b = bytes);
+}
+
+/// `span_release`: a central free list gave an empty span back to the page
+/// heap.
+#[inline(always)]
+pub(crate) fn span_release(size_class: usize, addr: usize, bytes: usize) {
+    probe!(
+        "span_release",
+        "8@{a} 8@{b} 8@{c}",
+        a = size_class,
+        b = addr,
+        c = bytes
+    );
+}
+
+/// `central_refill`: a front-end miss took `got` of the `asked` objects
+/// from a central free list.
+#[inline(always)]
+pub(crate) fn central_refill(size_class: usize, asked: usize, got: usize) {
+    probe!(
+        "central_refill",
+        "8@{a} 8@{b} 8@{c}",
+        a = size_class,
+        b = asked,
+        c = got
+    );
+}
+
+/// `thread_cache_scavenge`: a thread cache over its budget released
+/// `bytes`, leaving `left`.
+#[inline(always)]
+pub(crate) fn thread_cache_scavenge(bytes: usize, left: usize) {
+    probe!("thread_cache_scavenge", "8@{a} 8@{b}", a = bytes, b = left);
+}
@@ -0,0 +1,200 @@
+//! Build and runtime identification.
+//!
+//! Exposes the crate version together with the build configuration (enabled
+//! features, page size, number of size classes), a fingerprint of that
+//! configuration, and a per-process heap identifier. Tools that attach to a
+//! process (debuggers, profilers, heap dump readers) use these to check that
+//! they understand the allocator's metadata layout before reading it.
+
+use core::ffi::CStr;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+mod build_info {
+    include!(concat!(env!("OUT_DIR"), "/build_info_gen.rs"));
+}
+
+/// Crate version (semver).
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Enabled cargo features, sorted and comma separated.
+pub const FEATURES: &str = build_info::FEATURES;
+
+/// Human-readable version string, e.g.
+/// `0.1.0 (features: nightly,stats; page_size: 8192; classes: 46)`.
+pub const VERSION_STRING: &CStr = build_info::VERSION_STRING;
+
+/// Hash of the crate version, resolved config, size class table and enabled
+/// features. Two builds with the same fingerprint share a metadata layout.
+pub const CONFIG_FINGERPRINT: u64 = build_info::CONFIG_FINGERPRINT;
+
+/// Which allocator variant this binary embeds and what it found on the
+/// machine, as returned by [`features`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    /// Built with `percpu`: per-CPU caches on rseq instead of thread caches.
+    pub percpu: bool,
+    /// Built with `nightly`: thread caches in `#[thread_local]` statics.
+    pub nightly_tls: bool,
+    /// Built with `std`.
+    pub std: bool,
+    /// Built with `stats`: [`crate::stats`] keeps its counters.
+    pub stats: bool,
+    /// The kernel implements rseq, so `percpu` caches can run here.
+    pub rseq: bool,
+    /// Transparent huge pages are enabled (`always` or `madvise`).
+    pub hugepages: bool,
+    /// NUMA nodes online, 0 if unknown.
+    pub numa_nodes: u32,
+}
+
+/// Compile-time features and runtime capabilities of this process, for bug
+/// reports and support tickets. [`VERSION_STRING`] has the full feature
+/// list; this adds what was detected on the machine.
+///
+/// The runtime fields are probed on every call (a syscall and two small
+/// reads under `/sys` on Linux), so keep it out of hot paths. Elsewhere they
+/// read as false and 0.
+pub fn features() -> Features {
+    Features {
+        percpu: cfg!(feature = "percpu"),
+        nightly_tls: cfg!(feature = "nightly"),
+        std: cfg!(feature = "std"),
+        stats: cfg!(feature = "stats"),
+        rseq: crate::platform::rseq_supported(),
+        hugepages: crate::platform::transparent_hugepages(),
+        numa_nodes: crate::platform::numa_nodes(),
+    }
+}
+
+static HEAP_SEED: AtomicU64 = AtomicU64::new(0);
+static HEAP_ID: AtomicU64 = AtomicU64::new(0);
+/// Process that computed [`HEAP_ID`]; a forked child inherits both.
+static HEAP_PID: AtomicU32 = AtomicU32::new(0);
+
+/// SplitMix64 finalizer.
+#[inline]
+pub(crate) fn mix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Seed that replaces every source of entropy under the `deterministic`
+/// feature.
+#[cfg(feature = "deterministic")]
+pub(crate) const FIXED_SEED: u64 = 0x7274_6d61_6c6c_6f63; // "rtmalloc"
+
+/// Low-quality but cheap entropy: a cycle counter where one is available,
+/// otherwise the current stack address. [`FIXED_SEED`] with `deterministic`.
+#[inline]
+pub(crate) fn entropy() -> u64 {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "deterministic")] {
+            FIXED_SEED
+        } else if #[cfg(all(target_arch = "x86_64", not(miri)))] {
+            unsafe { core::arch::x86_64::_rdtsc() }
+        } else {
+            let local = 0u8;
+            core::ptr::addr_of!(local) as u64
+        }
+    }
+}
+
+/// Identifier of this process's heap. Never zero.
+///
+/// Derived from the process id and a seed taken once from the
+/// (ASLR-randomized) address of a static and a timestamp, so two processes
+/// get different values, and so does a forked child from its parent even
+/// when the parent asked before forking. With `deterministic` it is derived
+/// from [`FIXED_SEED`] alone and is the same in every process.
+pub fn heap_id() -> u64 {
+    let pid = crate::platform::process_id();
+    if HEAP_PID.load(Ordering::Acquire) == pid {
+        let id = HEAP_ID.load(Ordering::Relaxed);
+        if id != 0 {
+            return id;
+        }
+    }
+
+    // A pure function of the seed and the pid, so threads racing here store
+    // the same value.
+    #[cfg(feature = "deterministic")]
+    let id = mix64(heap_seed()).max(1);
+    #[cfg(not(feature = "deterministic"))]
+    let id = mix64(heap_seed() ^ (pid as u64) << 32).max(1);
+    HEAP_ID.store(id, Ordering::Relaxed);
+    HEAP_PID.store(pid, Ordering::Release);
+    id
+}
+
+fn heap_seed() -> u64 {
+    let seed = HEAP_SEED.load(Ordering::Relaxed);
+    if seed != 0 {
+        return seed;
+    }
+
+    #[cfg(feature = "deterministic")]
+    let new = entropy();
+    #[cfg(not(feature = "deterministic"))]
+    let new = (core::ptr::addr_of!(HEAP_SEED) as u64 ^ entropy().rotate_left(17)).max(1);
+    match HEAP_SEED.compare_exchange(0, new, Ordering::Relaxed, Ordering::Relaxed) {
+        Ok(_) => new,
+        Err(existing) => existing,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_string() {
+        let s = VERSION_STRING.to_str().unwrap();
+        assert!(s.starts_with(VERSION));
+        assert!(s.contains(&alloc::format!("page_size: {}", crate::config::PAGE_SIZE)));
+        assert!(s.contains(&alloc::format!(
+            "classes: {}",
+            crate::size_class::NUM_SIZE_CLASSES
+        )));
+    }
+
+    #[test]
+    fn test_features() {
+        let f = features();
+        assert_eq!(f.percpu, cfg!(feature = "percpu"));
+        assert_eq!(f.nightly_tls, cfg!(feature = "nightly"));
+        assert_eq!(f.stats, FEATURES.split(',').any(|f| f == "stats"));
+    }
+
+    #[test]
+    fn test_heap_id_stable() {
+        let id = heap_id();
+        assert_ne!(id, 0);
+        assert_eq!(heap_id(), id);
+    }
+
+    #[cfg(all(unix, not(miri), not(feature = "deterministic")))]
+    #[test]
+    fn test_heap_id_changes_in_forked_child() {
+        unsafe extern "C" {
+            fn fork() -> i32;
+            fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+            fn _exit(status: i32) -> !;
+        }
+
+        let parent = heap_id();
+        let pid = unsafe { fork() };
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            // Only async-signal-safe calls in the child: `getpid` and `_exit`.
+            let child = heap_id();
+            unsafe { _exit(if child != parent && child != 0 { 0 } else { 1 }) };
+        }
+        let mut status = 0;
+        assert_eq!(unsafe { waitpid(pid, &mut status, 0) }, pid);
+        assert_eq!(status, 0, "child saw the parent's heap id");
+        assert_eq!(heap_id(), parent);
+    }
+}
@@ -3,14 +3,16 @@
 //! Verifies that allocations respect alignment requirements for various
 //! alignment values, including over-aligned allocations (> 8 bytes).
 
-use rtmalloc::RtMalloc;
+mod common;
+
 use std::alloc::{GlobalAlloc, Layout};
 
 #[global_allocator]
-static GLOBAL: RtMalloc = RtMalloc;
+static GLOBAL: common::Global = common::Global;
 
 #[test]
 fn test_standard_alignments() {
+    let _shadow = common::ShadowCheck;
     for align in [1, 2, 4, 8] {
         for &size in &[1, 7, 8, 15, 16, 31, 64, 255, 256, 1024, 4096] {
             if size < align {
@@ -33,6 +35,7 @@ fn test_standard_alignments() {
 
 #[test]
 fn test_over_aligned_16() {
+    let _shadow = common::ShadowCheck;
     let align = 16;
     for &size in &[16, 32, 64, 128, 256, 1024] {
         let layout = Layout::from_size_align(size, align).unwrap();
@@ -50,6 +53,7 @@ fn test_over_aligned_16() {
 
 #[test]
 fn test_over_aligned_32() {
+    let _shadow = common::ShadowCheck;
     let align = 32;
     for &size in &[32, 64, 128, 256, 1024] {
         let layout = Layout::from_size_align(size, align).unwrap();
@@ -67,6 +71,7 @@ fn test_over_aligned_32() {
 
 #[test]
 fn test_over_aligned_64() {
+    let _shadow = common::ShadowCheck;
     let align = 64;
     for &size in &[64, 128, 256, 512, 1024, 4096] {
         let layout = Layout::from_size_align(size, align).unwrap();
@@ -84,6 +89,7 @@ fn test_over_aligned_64() {
 
 #[test]
 fn test_over_aligned_256() {
+    let _shadow = common::ShadowCheck;
     let align = 256;
     for &size in &[256, 512, 1024, 4096, 8192] {
         let layout = Layout::from_size_align(size, align).unwrap();
@@ -101,6 +107,7 @@ fn test_over_aligned_256() {
 
 #[test]
 fn test_over_aligned_4096() {
+    let _shadow = common::ShadowCheck;
     let align = 4096;
     for &size in &[4096, 8192, 16384, 65536] {
         let layout = Layout::from_size_align(size, align).unwrap();
@@ -118,6 +125,7 @@ fn test_over_aligned_4096() {
 
 #[test]
 fn test_over_aligned_page_size() {
+    let _shadow = common::ShadowCheck;
     // align == PAGE_SIZE (8192): should work via simple alloc_large
     let align = 8192;
     for &size in &[8192, 16384, 65536] {
@@ -136,6 +144,7 @@ fn test_over_aligned_page_size() {
 
 #[test]
 fn test_over_aligned_above_page_size() {
+    let _shadow = common::ShadowCheck;
     // align > PAGE_SIZE: requires over-allocation + trimming
     for align in [16384, 32768, 65536] {
         for &size in &[align, align * 2] {
@@ -159,6 +168,7 @@ fn test_over_aligned_above_page_size() {
 
 #[test]
 fn test_many_over_aligned_above_page_size() {
+    let _shadow = common::ShadowCheck;
     // Multiple over-aligned allocations to verify prefix/suffix span recycling
     let align = 16384;
     let size = 16384;
@@ -186,6 +196,7 @@ fn test_many_over_aligned_above_page_size() {
 
 #[test]
 fn test_alignment_realloc_preserves_alignment() {
+    let _shadow = common::ShadowCheck;
     for align in [16, 32, 64, 256] {
         let size = align * 2;
         let layout = Layout::from_size_align(size, align).unwrap();
@@ -220,6 +231,7 @@ fn test_alignment_realloc_preserves_alignment() {
 
 #[test]
 fn test_many_aligned_allocations() {
+    let _shadow = common::ShadowCheck;
     // Allocate many over-aligned objects to stress the allocator's
     // alignment handling across multiple spans/pages.
     let align = 64;
@@ -250,6 +262,7 @@ fn test_many_aligned_allocations() {
 
 #[test]
 fn test_zero_size_layout() {
+    let _shadow = common::ShadowCheck;
     // Zero-sized allocations should return a non-null aligned pointer
     let layout = Layout::from_size_align(0, 1).unwrap();
     let ptr = unsafe { GLOBAL.alloc(layout) };
@@ -259,3 +272,212 @@ fn test_zero_size_layout() {
         unsafe { GLOBAL.dealloc(ptr, layout) };
     }
 }
+
+/// Sizes just below, at and above the largest size class, for every power
+/// of two alignment up to 4 pages. Each pointer must be aligned, fully
+/// writable, and freeable with its layout.
+#[test]
+fn test_small_large_boundary_matrix() {
+    let _shadow = common::ShadowCheck;
+    let max_small = rtmalloc::size_class::MAX_SMALL_SIZE;
+    let page = rtmalloc::config::PAGE_SIZE;
+    let mut align = 1;
+    while align <= page * 4 {
+        for size in [
+            1,
+            align,
+            max_small - align.min(max_small / 2),
+            max_small - 1,
+            max_small,
+            max_small + 1,
+            max_small + align,
+        ] {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let ptr = unsafe { GLOBAL.alloc(layout) };
+            assert!(!ptr.is_null(), "alloc failed: size={size}, align={align}");
+            assert_eq!(ptr as usize % align, 0, "size={size}, align={align}");
+            unsafe {
+                ptr.write_bytes(0x5A, size);
+                assert_eq!(*ptr.add(size - 1), 0x5A);
+                GLOBAL.dealloc(ptr, layout);
+            }
+        }
+        align *= 2;
+    }
+}
+
+/// Realloc across the small/large boundary in both directions keeps the
+/// contents and the alignment, and the result can be freed with the new
+/// layout (grow) or the old one after an in-place shrink, which gives the
+/// pages past the new size back. `layout-check` moves shrinks that change
+/// the class instead.
+#[test]
+#[cfg(not(feature = "layout-check"))]
+fn test_realloc_across_small_large_boundary() {
+    let _shadow = common::ShadowCheck;
+    let max_small = rtmalloc::size_class::MAX_SMALL_SIZE;
+    let page = rtmalloc::config::PAGE_SIZE;
+    for align in [8, 16, 64, 4096, page, page * 2] {
+        let small = 256.max(align);
+        let large = max_small + page;
+
+        // Small -> large.
+        let layout = Layout::from_size_align(small, align).unwrap();
+        let ptr = unsafe { GLOBAL.alloc(layout) };
+        assert!(!ptr.is_null());
+        unsafe { ptr.write_bytes(0xC3, small) };
+        let grown = unsafe { GLOBAL.realloc(ptr, layout, large) };
+        assert!(!grown.is_null(), "grow failed: align={align}");
+        assert_eq!(grown as usize % align, 0, "grow lost alignment: {align}");
+        for i in 0..small {
+            assert_eq!(unsafe { *grown.add(i) }, 0xC3, "grow corrupted byte {i}");
+        }
+        unsafe { grown.write_bytes(0xC3, large) };
+
+        // Large -> small: shrinks in place, same pointer.
+        let large_layout = Layout::from_size_align(large, align).unwrap();
+        let shrunk = unsafe { GLOBAL.realloc(grown, large_layout, small) };
+        assert_eq!(shrunk, grown, "shrink moved: align={align}");
+        for i in 0..small {
+            assert_eq!(unsafe { *shrunk.add(i) }, 0xC3);
+        }
+
+        // The shrink trimmed the span, so growing again moves it.
+        let small_layout = Layout::from_size_align(small, align).unwrap();
+        let regrown = unsafe { GLOBAL.realloc(shrunk, small_layout, large) };
+        assert!(!regrown.is_null(), "regrow failed: align={align}");
+        assert_eq!(
+            regrown as usize % align,
+            0,
+            "regrow lost alignment: {align}"
+        );
+        for i in 0..small {
+            assert_eq!(
+                unsafe { *regrown.add(i) },
+                0xC3,
+                "regrow corrupted byte {i}"
+            );
+        }
+
+        // Free with the layout of the latest realloc.
+        unsafe { GLOBAL.dealloc(regrown, large_layout) };
+    }
+}
+
+/// After an in-place shrink the caller frees with a layout whose size maps
+/// to a smaller class than the object's. The object must still go back to
+/// its real class and be reusable at that size.
+#[test]
+#[cfg(not(feature = "layout-check"))]
+fn test_dealloc_after_in_place_shrink_uses_real_class() {
+    let _shadow = common::ShadowCheck;
+    for align in [8, 16, 32, 128] {
+        let layout = Layout::from_size_align(1024, align).unwrap();
+        let ptr = unsafe { GLOBAL.alloc(layout) };
+        assert!(!ptr.is_null());
+        let shrunk = unsafe { GLOBAL.realloc(ptr, layout, align) };
+        assert_eq!(shrunk, ptr);
+        unsafe { GLOBAL.dealloc(shrunk, Layout::from_size_align(align, align).unwrap()) };
+
+        // The freed slot is a 1024-byte object; filling a fresh 1024-byte
+        // allocation must not clobber any neighbour.
+        let a = unsafe { GLOBAL.alloc(layout) };
+        let b = unsafe { GLOBAL.alloc(layout) };
+        assert!(!a.is_null() && !b.is_null());
+        unsafe {
+            a.write_bytes(0x11, 1024);
+            b.write_bytes(0x22, 1024);
+            assert!((0..1024).all(|i| *a.add(i) == 0x11));
+            assert!((0..1024).all(|i| *b.add(i) == 0x22));
+            GLOBAL.dealloc(a, layout);
+            GLOBAL.dealloc(b, layout);
+        }
+    }
+}
+
+/// Over-aligned large allocations are carved out of a bigger span whose
+/// prefix and suffix go back to the page heap. Freeing neighbours makes
+/// those pieces coalesce and their span structs get reused; the aligned
+/// blocks must still realloc and free as themselves afterwards.
+#[test]
+fn test_over_aligned_trimmed_spans_survive_coalescing() {
+    let _shadow = common::ShadowCheck;
+    let align = 65536;
+    let mut blocks = Vec::new();
+    for i in 0..24usize {
+        let size = align * (1 + i % 3);
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let ptr = unsafe { GLOBAL.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % align, 0);
+        unsafe { ptr.write_bytes(i as u8, size) };
+        blocks.push((ptr, layout));
+    }
+
+    // Free every other block so trimmed pieces merge across them, then
+    // churn page-sized allocations through the freed ranges.
+    let mut survivors = Vec::new();
+    for (i, (ptr, layout)) in blocks.into_iter().enumerate() {
+        if i % 2 == 0 {
+            unsafe { GLOBAL.dealloc(ptr, layout) };
+        } else {
+            survivors.push((i, ptr, layout));
+        }
+    }
+    let churn_layout = Layout::from_size_align(3 * 8192, 8).unwrap();
+    let churn: Vec<_> = (0..64)
+        .map(|_| unsafe { GLOBAL.alloc(churn_layout) })
+        .collect();
+    for &p in &churn {
+        assert!(!p.is_null());
+        unsafe { p.write_bytes(0xEE, churn_layout.size()) };
+    }
+
+    for (i, ptr, layout) in survivors {
+        let size = layout.size();
+        assert!((0..size).all(|j| unsafe { *ptr.add(j) } == i as u8));
+
+        // The full usable size is still attributed to this block.
+        let same = unsafe { GLOBAL.realloc(ptr, layout, size) };
+        assert_eq!(same, ptr, "in-place realloc moved block {i}");
+
+        let grown = unsafe { GLOBAL.realloc(ptr, layout, size * 2) };
+        assert!(!grown.is_null());
+        assert!((0..size).all(|j| unsafe { *grown.add(j) } == i as u8));
+        unsafe { GLOBAL.dealloc(grown, Layout::from_size_align(size * 2, align).unwrap()) };
+    }
+
+    for p in churn {
+        assert!((0..churn_layout.size()).all(|j| unsafe { *p.add(j) } == 0xEE));
+        unsafe { GLOBAL.dealloc(p, churn_layout) };
+    }
+}
+
+#[test]
+fn test_align_above_size() {
+    let _shadow = common::ShadowCheck;
+    // Layouts like (8, 64): live objects must be aligned and not overlap.
+    for shift in 4..=13 {
+        let align = 1usize << shift;
+        for size in [1, 8, align / 2, align - 1] {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let mut ptrs: Vec<*mut u8> = (0..64).map(|_| unsafe { GLOBAL.alloc(layout) }).collect();
+            for (i, &ptr) in ptrs.iter().enumerate() {
+                assert!(!ptr.is_null(), "alloc failed: size={size}, align={align}");
+                assert_eq!(
+                    ptr as usize % align,
+                    0,
+                    "misaligned: ptr={ptr:?}, size={size}, align={align}"
+                );
+                unsafe { ptr.write_bytes(i as u8, size) };
+            }
+            ptrs.sort();
+            for pair in ptrs.windows(2) {
+                assert!(pair[1] as usize - pair[0] as usize >= align);
+            }
+            for ptr in ptrs {
+                unsafe { GLOBAL.dealloc(ptr, layout) };
+            }
+        }
+    }
+}
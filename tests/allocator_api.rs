@@ -0,0 +1,100 @@
+//! `RtMalloc` as an `Allocator` handle next to the system allocator, which
+//! stays global here.
+//!
+//! Run with: cargo test --features nightly --test allocator_api
+//!
+//! Not with `c-abi`: rtmalloc then interposes `malloc`, so the system
+//! allocator's objects are rtmalloc's too.
+
+#![cfg(all(feature = "nightly", not(feature = "c-abi")))]
+#![feature(allocator_api)]
+
+use rtmalloc::RtMalloc;
+use std::alloc::{Allocator, Layout};
+use std::sync::mpsc;
+
+fn owned(ptr: *const u8) -> bool {
+    RtMalloc.owns(ptr)
+}
+
+#[test]
+fn test_collections_in_rtmalloc() {
+    let mut v: Vec<u64, RtMalloc> = Vec::new_in(RtMalloc);
+    for i in 0..100_000 {
+        v.push(i);
+    }
+    assert!(owned(v.as_ptr().cast()));
+    v.truncate(10);
+    v.shrink_to_fit();
+    assert_eq!(v, (0..10).collect::<Vec<_>>());
+
+    let b = Box::new_in([7u8; 300], RtMalloc);
+    let c = b.clone();
+    assert_eq!(*c, [7u8; 300]);
+    assert!(owned(c.as_ptr()));
+
+    // The global allocator is untouched.
+    let sys = vec![1u8; 300];
+    assert!(!owned(sys.as_ptr()));
+}
+
+#[test]
+fn test_mixed_across_threads() {
+    let (tx, rx) = mpsc::channel::<(Vec<u8, RtMalloc>, Vec<u8>)>();
+    let producer = std::thread::spawn(move || {
+        for i in 0..2000 {
+            let mut ours = Vec::with_capacity_in(16 + i % 5000, RtMalloc);
+            ours.resize(ours.capacity(), i as u8);
+            let theirs = vec![i as u8; 16 + i % 700];
+            tx.send((ours, theirs)).unwrap();
+        }
+    });
+    let mut kept = Vec::new();
+    for (i, (ours, theirs)) in rx.iter().enumerate() {
+        assert!(ours.iter().all(|&b| b == i as u8));
+        assert!(owned(ours.as_ptr()) && !owned(theirs.as_ptr()));
+        // Most are freed here as they arrive, the rest after the producer
+        // has exited.
+        if i % 3 == 0 {
+            kept.push(ours);
+        }
+    }
+    producer.join().unwrap();
+    drop(kept);
+}
+
+#[test]
+fn test_grow_shrink_and_zeroed() {
+    let alloc = RtMalloc;
+    let small = Layout::from_size_align(24, 8).unwrap();
+    unsafe {
+        let p = alloc.allocate(small).unwrap().cast::<u8>();
+        p.as_ptr().write_bytes(0xAB, 24);
+        let grown = alloc
+            .grow_zeroed(p, small, Layout::from_size_align(5000, 8).unwrap())
+            .unwrap()
+            .cast::<u8>();
+        let bytes = std::slice::from_raw_parts(grown.as_ptr(), 5000);
+        assert!(bytes[..24].iter().all(|&b| b == 0xAB));
+        assert!(bytes[24..].iter().all(|&b| b == 0));
+
+        // Alignment change: moved, contents kept.
+        let big = Layout::from_size_align(5000, 8).unwrap();
+        let aligned = Layout::from_size_align(8192, 4096).unwrap();
+        let moved = alloc.grow(grown, big, aligned).unwrap().cast::<u8>();
+        assert_eq!(moved.as_ptr() as usize % 4096, 0);
+        assert_eq!(*moved.as_ptr(), 0xAB);
+        let shrunk = alloc
+            .shrink(moved, aligned, Layout::from_size_align(16, 4096).unwrap())
+            .unwrap()
+            .cast::<u8>();
+        assert_eq!(*shrunk.as_ptr(), 0xAB);
+        alloc.deallocate(shrunk, Layout::from_size_align(16, 4096).unwrap());
+
+        let z = alloc
+            .allocate_zeroed(Layout::from_size_align(1 << 20, 8).unwrap())
+            .unwrap();
+        assert!(z.as_ref().iter().all(|&b| b == 0));
+        alloc.deallocate(z.cast(), Layout::from_size_align(1 << 20, 8).unwrap());
+    }
+}
@@ -0,0 +1,82 @@
+//! Per-thread arenas.
+//!
+//! Run with: cargo test --features std --test arena
+//! With private arenas to test: RTMALLOC_CLASSES=tests/arenas.toml cargo test --features std --test arena
+
+#![cfg(all(not(feature = "percpu"), any(feature = "nightly", feature = "std")))]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::config::{NUM_ARENAS, PAGE_SHIFT};
+use rtmalloc::thread;
+use std::collections::HashSet;
+use std::sync::mpsc;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+fn pages(boxes: &[Box<[u8; 48]>]) -> HashSet<usize> {
+    boxes
+        .iter()
+        .map(|b| (&**b as *const [u8; 48]).addr() >> PAGE_SHIFT)
+        .collect()
+}
+
+#[test]
+fn test_set_arena_bounds() {
+    std::thread::spawn(|| {
+        assert_eq!(thread::arena(), 0);
+        assert!(!thread::set_arena(NUM_ARENAS));
+        assert!(thread::set_arena(NUM_ARENAS - 1));
+        assert_eq!(thread::arena(), NUM_ARENAS - 1);
+        let v = vec![1u8; 100];
+        assert!(thread::set_arena(0));
+        assert_eq!(thread::arena(), 0);
+        drop(v);
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn test_private_arena_spans() {
+    if NUM_ARENAS == 1 {
+        return;
+    }
+    let shared: Vec<Box<[u8; 48]>> = (0..2000).map(|_| Box::new([0u8; 48])).collect();
+
+    let (tx, rx) = mpsc::channel();
+    let (back_tx, back_rx) = mpsc::channel::<Vec<Box<[u8; 48]>>>();
+    let worker = std::thread::spawn(move || {
+        assert!(thread::set_arena(1));
+        let private: Vec<Box<[u8; 48]>> = (0..2000).map(|_| Box::new([1u8; 48])).collect();
+        tx.send(private).unwrap();
+        // Objects freed by another thread go back to this arena, and are
+        // handed out here again.
+        let returned = back_rx.recv().unwrap();
+        drop(returned);
+        let again: Vec<Box<[u8; 48]>> = (0..2000).map(|_| Box::new([2u8; 48])).collect();
+        pages(&again)
+    });
+
+    let mut private = rx.recv().unwrap();
+    assert!(private.iter().all(|b| b[0] == 1));
+    let private_pages = pages(&private);
+    assert!(pages(&shared).is_disjoint(&private_pages));
+
+    // Freed on an arena-0 thread, half of them: the other half keeps the
+    // spans in arena 1, and the freed objects must not be reused here.
+    let mut i = 0;
+    private.retain(|_| {
+        i += 1;
+        i % 2 == 0
+    });
+    let more: Vec<Box<[u8; 48]>> = (0..4000).map(|_| Box::new([3u8; 48])).collect();
+    assert!(pages(&more).is_disjoint(&private_pages));
+
+    // Frees of arena-0 objects on the private thread go back to arena 0.
+    back_tx.send(more).unwrap();
+    let again_pages = worker.join().unwrap();
+    assert!(pages(&shared).is_disjoint(&again_pages));
+    assert!(!again_pages.is_disjoint(&private_pages));
+    drop((shared, private));
+}
@@ -1,13 +1,18 @@
 //! Basic integration test: use rtmalloc as the global allocator and exercise
 //! standard Rust collections.
 
+mod common;
+
 use rtmalloc::RtMalloc;
+use rtmalloc::hint;
+use std::alloc::{GlobalAlloc, Layout};
 
 #[global_allocator]
-static GLOBAL: RtMalloc = RtMalloc;
+static GLOBAL: common::Global = common::Global;
 
 #[test]
 fn test_box() {
+    let _shadow = common::ShadowCheck;
     let b = Box::new(42u64);
     assert_eq!(*b, 42);
     drop(b);
@@ -15,6 +20,7 @@ fn test_box() {
 
 #[test]
 fn test_vec() {
+    let _shadow = common::ShadowCheck;
     let mut v = Vec::new();
     for i in 0..1000 {
         v.push(i);
@@ -26,6 +32,7 @@ fn test_vec() {
 
 #[test]
 fn test_string() {
+    let _shadow = common::ShadowCheck;
     let mut s = String::new();
     for _ in 0..100 {
         s.push_str("hello world ");
@@ -35,6 +42,7 @@ fn test_string() {
 
 #[test]
 fn test_hashmap() {
+    let _shadow = common::ShadowCheck;
     use std::collections::HashMap;
     let mut map = HashMap::new();
     for i in 0..500 {
@@ -46,6 +54,7 @@ fn test_hashmap() {
 
 #[test]
 fn test_vec_of_strings() {
+    let _shadow = common::ShadowCheck;
     let v: Vec<String> = (0..200).map(|i| format!("item_{}", i)).collect();
     assert_eq!(v.len(), 200);
     assert_eq!(v[100], "item_100");
@@ -53,6 +62,7 @@ fn test_vec_of_strings() {
 
 #[test]
 fn test_nested_collections() {
+    let _shadow = common::ShadowCheck;
     let mut v: Vec<Vec<u32>> = Vec::new();
     for i in 0..50 {
         let inner: Vec<u32> = (0..i).collect();
@@ -63,6 +73,7 @@ fn test_nested_collections() {
 
 #[test]
 fn test_large_allocation() {
+    let _shadow = common::ShadowCheck;
     // Allocate > 256 KiB (goes through large allocation path)
     let v: Vec<u8> = vec![0xAB; 512 * 1024];
     assert_eq!(v.len(), 512 * 1024);
@@ -71,6 +82,7 @@ fn test_large_allocation() {
 
 #[test]
 fn test_various_sizes() {
+    let _shadow = common::ShadowCheck;
     // Exercise different size classes
     let _a: Box<[u8; 1]> = Box::new([0; 1]);
     let _b: Box<[u8; 8]> = Box::new([0; 8]);
@@ -85,9 +97,259 @@ fn test_various_sizes() {
 
 #[test]
 fn test_alloc_free_cycle() {
+    let _shadow = common::ShadowCheck;
     for _ in 0..100 {
         let v: Vec<u64> = (0..100).collect();
         assert_eq!(v.len(), 100);
         drop(v);
     }
 }
+
+#[test]
+fn test_mid_size_buffers() {
+    let _shadow = common::ShadowCheck;
+    // Sizes between the largest size class and 2 MiB cycle through the
+    // mid-heap. Contents must survive growth and reuse of parked spans.
+    for round in 0..4u8 {
+        let mut bufs = Vec::new();
+        for kib in [300, 384, 500, 700, 1024, 1500, 2048] {
+            let mut v: Vec<u8> = Vec::with_capacity(kib * 1024);
+            v.resize(kib * 1024, round ^ kib as u8);
+            bufs.push(v);
+        }
+        for v in &mut bufs {
+            let fill = v[0];
+            assert!(v.iter().all(|&b| b == fill));
+            v.resize(v.len() + 100 * 1024, fill);
+            assert!(v.iter().all(|&b| b == fill));
+        }
+    }
+}
+
+#[test]
+fn test_realloc_shrink_trims_large() {
+    let _shadow = common::ShadowCheck;
+    use std::alloc::GlobalAlloc;
+    #[cfg(feature = "stats")]
+    let before = rtmalloc::stats::snapshot().realloc_trim_bytes;
+    // A shrink keeps at least the pages the new size reaches (a mid-size
+    // one those of its new mid class, 768 KiB for 600 KiB); over-aligned
+    // pointers sit inside their span. Grows within the kept pages stay put,
+    // past them they move.
+    for (size, shrink, regrow, align) in [
+        (1 << 20, (600 << 10) - 1000, 600 << 10, 8),
+        (4 << 20, (2500 << 10) - 1000, 2500 << 10, 8),
+        (1 << 20, (300 << 10) - 1000, 300 << 10, 1 << 16),
+    ] {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        unsafe {
+            let p = GLOBAL.alloc(layout);
+            assert!(!p.is_null());
+            p.write_bytes(0x5A, size);
+            let shrunk = GLOBAL.realloc(p, layout, shrink);
+            assert_eq!(shrunk, p, "{size} -> {shrink}");
+            let shrunk_layout = Layout::from_size_align(shrink, align).unwrap();
+            let same = GLOBAL.realloc(shrunk, shrunk_layout, regrow);
+            assert_eq!(same, p, "{shrink} -> {regrow}");
+            let regrow_layout = Layout::from_size_align(regrow, align).unwrap();
+            let grown = GLOBAL.realloc(same, regrow_layout, size);
+            assert!(!grown.is_null());
+            assert_eq!(grown.addr() % align, 0);
+            let block = std::slice::from_raw_parts(grown, regrow);
+            assert!(block.iter().all(|&b| b == 0x5A), "{size}/{align}");
+            GLOBAL.dealloc(grown, layout);
+        }
+    }
+    #[cfg(feature = "stats")]
+    {
+        let trimmed = rtmalloc::stats::snapshot().realloc_trim_bytes - before;
+        assert!(
+            trimmed >= (256 << 10) + (1500 << 10) + (700 << 10),
+            "{trimmed}"
+        );
+    }
+}
+
+#[test]
+fn test_reserve() {
+    let _shadow = common::ShadowCheck;
+    assert!(rtmalloc::reserve(32 << 20));
+    let bufs: Vec<Vec<u8>> = (0..64).map(|i| vec![i as u8; 64 * 1024]).collect();
+    for (i, v) in bufs.iter().enumerate() {
+        assert!(v.iter().all(|&b| b == i as u8));
+    }
+}
+
+#[test]
+fn test_flush_current_cache() {
+    let _shadow = common::ShadowCheck;
+    let keep: Vec<Box<[u8; 48]>> = (0..500).map(|i| Box::new([i as u8; 48])).collect();
+    drop((0..500).map(|_| Box::new([0u8; 48])).collect::<Vec<_>>());
+    rtmalloc::thread::flush_current_cache();
+    rtmalloc::thread::flush_current_cache();
+    let again: Vec<Box<[u8; 48]>> = (0..500).map(|i| Box::new([!i as u8; 48])).collect();
+    for (i, (a, b)) in keep.iter().zip(&again).enumerate() {
+        assert!(a.iter().all(|&x| x == i as u8));
+        assert!(b.iter().all(|&x| x == !i as u8));
+    }
+}
+
+#[test]
+fn test_dealloc_iter() {
+    let _shadow = common::ShadowCheck;
+    use std::alloc::{GlobalAlloc, Layout};
+
+    // Straight from `RtMalloc`, which the shadow allocator does not see.
+    let layout = Layout::new::<[u64; 4]>();
+    let boxes: Vec<*mut u8> = (0..1000u64)
+        .map(|i| unsafe {
+            let p = RtMalloc.alloc(layout);
+            p.cast::<[u64; 4]>().write([i; 4]);
+            p
+        })
+        .collect();
+    unsafe { RtMalloc.dealloc_iter(layout, boxes.iter().copied()) };
+
+    // Large allocations go through the regular free path.
+    let big = Layout::from_size_align(1 << 20, 8).unwrap();
+    let ptrs: Vec<*mut u8> = (0..4).map(|_| unsafe { RtMalloc.alloc(big) }).collect();
+    unsafe { RtMalloc.dealloc_iter(big, ptrs) };
+
+    // The freed memory is reused.
+    let again: Vec<Box<[u64; 4]>> = (0..1000u64).map(|i| Box::new([i; 4])).collect();
+    assert_eq!(again[999][3], 999);
+}
+
+#[test]
+fn test_dump_thread_cache() {
+    let _shadow = common::ShadowCheck;
+    let v: Vec<Box<[u8; 64]>> = (0..200).map(|_| Box::new([0u8; 64])).collect();
+    drop(v);
+    let info = rtmalloc::debug::dump_thread_cache();
+    let has_cache = cfg!(any(feature = "nightly", feature = "std")) && !cfg!(feature = "percpu");
+    assert_eq!(info.is_some(), has_cache);
+    if let Some(info) = info {
+        assert!(info.total_bytes > 0);
+        assert!(info.total_bytes <= info.max_bytes);
+        assert!(info.to_string().contains("thread cache:"));
+    }
+}
+
+#[test]
+fn test_alloc_zeroed_after_reuse() {
+    let _shadow = common::ShadowCheck;
+    use std::alloc::{GlobalAlloc, Layout};
+    // Small, mid-heap and page heap sizes, over-aligned included, and sizes
+    // past `zero_decommit_min` ending mid-page. Each block is dirtied and
+    // freed, so the next one may reuse its memory.
+    let decommit = rtmalloc::config::ZERO_DECOMMIT_MIN.max(1 << 20) + 100;
+    for (size, align) in [
+        (24, 8),
+        (3000, 64),
+        (40 << 10, 8),
+        (300 << 10, 8),
+        (2 << 20, 8),
+        (100 << 10, 1 << 16),
+        (decommit, 8),
+        (decommit, 1 << 16),
+    ] {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        for _ in 0..4 {
+            unsafe {
+                let p = GLOBAL.alloc_zeroed(layout);
+                assert!(!p.is_null());
+                let block = std::slice::from_raw_parts_mut(p, size);
+                assert!(block.iter().all(|&b| b == 0), "{size}/{align}");
+                block.fill(0xEE);
+                GLOBAL.dealloc(p, layout);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_selftest() {
+    let _shadow = common::ShadowCheck;
+    let report = rtmalloc::selftest();
+    assert!(report.passed(), "{report}");
+    assert_eq!(report.failed_mask(), 0);
+    assert_eq!(report.failures().count(), 0);
+    assert!(report.to_string().contains("selftest: passed"));
+}
+
+// `minimal` keeps one span pool per class, shared by cold objects.
+#[cfg(not(feature = "minimal"))]
+#[test]
+fn test_cold_hint_spans() {
+    let _shadow = common::ShadowCheck;
+    let layout = Layout::new::<[u8; 48]>();
+    let hot: Vec<Box<[u8; 48]>> = (0..2000).map(|_| Box::new([0u8; 48])).collect();
+    let cold: Vec<*mut u8> = (0..2000)
+        .map(|_| unsafe { RtMalloc.alloc_hinted(layout, hint::COLD) })
+        .collect();
+    assert!(cold.iter().all(|p| !p.is_null()));
+
+    // Cold objects are carved from tenured spans only.
+    let page = |p: *const u8| p.addr() >> rtmalloc::config::PAGE_SHIFT;
+    let hot_pages: std::collections::HashSet<usize> =
+        hot.iter().map(|b| page(b.as_ptr())).collect();
+    assert!(cold.iter().all(|&p| !hot_pages.contains(&page(p))));
+    for p in cold {
+        unsafe { RtMalloc.dealloc_sized(p, layout) };
+    }
+}
+
+/// NUMA policy of the page at `p`: 0 default, 3 interleave. `None` where
+/// it can't be read.
+fn mempolicy(p: *mut u8) -> Option<i32> {
+    cfg_if::cfg_if! {
+        if #[cfg(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))] {
+            unsafe extern "C" {
+                fn syscall(num: std::ffi::c_long, ...) -> std::ffi::c_long;
+            }
+            #[cfg(target_arch = "x86_64")]
+            const SYS_GET_MEMPOLICY: std::ffi::c_long = 239;
+            #[cfg(target_arch = "aarch64")]
+            const SYS_GET_MEMPOLICY: std::ffi::c_long = 236;
+            const MPOL_F_ADDR: u64 = 1 << 1;
+            let mut mode = -1i32;
+            let null = std::ptr::null_mut::<u64>();
+            let ret = unsafe { syscall(SYS_GET_MEMPOLICY, &mut mode, null, 0u64, p, MPOL_F_ADDR) };
+            (ret == 0).then_some(mode)
+        } else {
+            let _ = p;
+            None
+        }
+    }
+}
+
+#[test]
+fn test_interleave_hint() {
+    let _shadow = common::ShadowCheck;
+    let layout = Layout::from_size_align(4 << 20, 8).unwrap();
+    let multi_node = rtmalloc::platform::numa_node_mask().count_ones() > 1;
+    let p = unsafe { RtMalloc.alloc_hinted(layout, hint::INTERLEAVE) };
+    assert!(!p.is_null());
+    unsafe { p.write_bytes(0xAB, layout.size()) };
+    if multi_node {
+        assert_eq!(mempolicy(p), Some(3));
+    }
+    unsafe { RtMalloc.dealloc(p, layout) };
+
+    // The pages go back with the default policy for the next user.
+    let q = unsafe { GLOBAL.alloc(layout) };
+    assert!(!q.is_null());
+    if multi_node {
+        assert_eq!(mempolicy(q), Some(0));
+    }
+    unsafe { GLOBAL.dealloc(q, layout) };
+
+    // Small sizes take the normal path.
+    let small = Layout::new::<[u8; 48]>();
+    let p = unsafe { RtMalloc.alloc_hinted(small, hint::INTERLEAVE) };
+    assert!(!p.is_null());
+    unsafe { RtMalloc.dealloc_sized(p, small) };
+}
@@ -0,0 +1,37 @@
+//! Allocation with the caches bypassed. A test binary of its own: the switch
+//! is global and would change what other tests see.
+
+use rtmalloc::RtMalloc;
+use rtmalloc::debug;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+#[test]
+fn test_bypass_caches() {
+    // Warm the thread cache, if there is one, before switching.
+    drop((0..100).map(|_| Box::new([0u8; 64])).collect::<Vec<_>>());
+    let cached = debug::dump_thread_cache().map(|i| i.total_bytes);
+
+    debug::bypass_caches(true);
+    assert!(debug::caches_bypassed());
+
+    let boxes: Vec<Box<[u8; 64]>> = (0..1000).map(|i| Box::new([i as u8; 64])).collect();
+    let mid: Vec<Vec<u8>> = (1..20).map(|i| vec![i as u8; i * 40_000]).collect();
+    let mut grown = Vec::new();
+    for i in 0..100_000u32 {
+        grown.push(i);
+    }
+    assert_eq!(boxes[999][63], 999u32 as u8);
+    assert_eq!(mid[18][760_000 - 1], 19);
+    assert_eq!(grown[99_999], 99_999);
+    drop((boxes, mid, grown));
+
+    // Nothing went through the thread cache while bypassed.
+    assert_eq!(debug::dump_thread_cache().map(|i| i.total_bytes), cached);
+
+    debug::bypass_caches(false);
+    assert!(!debug::caches_bypassed());
+    let v: Vec<Box<u64>> = (0..1000).map(Box::new).collect();
+    assert_eq!(*v[999], 999);
+}
@@ -0,0 +1,73 @@
+//! Keeps `include/rtmalloc.h` in sync with the exports in `src/ffi.rs`.
+//!
+//! Run with: cargo test --test c_header
+
+use std::path::Path;
+use std::process::Command;
+
+const ROOT: &str = env!("CARGO_MANIFEST_DIR");
+
+fn read(path: &str) -> String {
+    std::fs::read_to_string(Path::new(ROOT).join(path)).unwrap()
+}
+
+/// Names of the `extern "C" fn rtmalloc_*` functions defined in `src`.
+fn exported(src: &str) -> Vec<String> {
+    src.lines()
+        .filter_map(|line| {
+            let rest = &line[line.find("extern \"C\" fn rtmalloc_")? + "extern \"C\" fn ".len()..];
+            Some(rest[..rest.find('(')?].to_string())
+        })
+        .collect()
+}
+
+/// Names of the `rtmalloc_*` functions declared in the header.
+fn declared(header: &str) -> Vec<String> {
+    header
+        .lines()
+        .filter(|line| !line.starts_with(' ') && !line.starts_with("typedef"))
+        .filter_map(|line| {
+            let open = line.find('(')?;
+            let name = line[..open].rsplit([' ', '*']).next()?;
+            name.starts_with("rtmalloc_").then(|| name.to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn test_header_declares_every_export() {
+    let mut exported = exported(&read("src/ffi.rs"));
+    let mut declared = declared(&read("include/rtmalloc.h"));
+    exported.sort();
+    declared.sort();
+    assert!(exported.len() >= 14, "{exported:?}");
+    assert_eq!(declared, exported);
+}
+
+#[test]
+fn test_header_compiles() {
+    let header = Path::new(ROOT).join("include/rtmalloc.h");
+    for (lang, std) in [("c", "-std=c11"), ("c++", "-std=c++11")] {
+        let Ok(out) = Command::new("cc")
+            .args([
+                "-x",
+                lang,
+                std,
+                "-fsyntax-only",
+                "-Wall",
+                "-Wextra",
+                "-Werror",
+            ])
+            .arg(&header)
+            .output()
+        else {
+            eprintln!("no C compiler, skipping");
+            return;
+        };
+        assert!(
+            out.status.success(),
+            "{lang}: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+}
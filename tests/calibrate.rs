@@ -0,0 +1,58 @@
+//! Host calibration. One test: calibration changes process-wide settings.
+
+#![cfg(feature = "std")]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::calibrate;
+use rtmalloc::config::MIN_PER_THREAD_CACHE_SIZE;
+use rtmalloc::size_class::{self, NUM_SIZE_CLASSES};
+use rtmalloc::thread_cache;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+#[test]
+fn test_calibrate() {
+    assert_eq!(calibrate::calibration(), None);
+
+    let c = rtmalloc::calibrate();
+    assert!(c.local_ns > 0.0 && c.handoff_ns > 0.0);
+    for cls in 1..NUM_SIZE_CLASSES {
+        let batch = c.batch_sizes[cls] as usize;
+        assert!((1..=size_class::class_info(cls).batch_size).contains(&batch));
+        assert_eq!(size_class::batch_size(cls), batch);
+    }
+    assert_eq!(thread_cache::overall_cache_size(), c.thread_cache_budget);
+
+    // Stored: later calls return the same result without measuring.
+    assert_eq!(rtmalloc::calibrate(), c);
+    assert_eq!(calibrate::calibration(), Some(c));
+    #[cfg(feature = "stats")]
+    assert_eq!(rtmalloc::stats::calibration(), Some(c));
+
+    // The allocator keeps working with the tuned settings, across threads.
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            std::thread::spawn(move || {
+                let v: Vec<Vec<u8>> = (0..5000).map(|i| vec![t as u8; 8 + i % 3000]).collect();
+                v
+            })
+        })
+        .collect();
+    for (t, h) in handles.into_iter().enumerate() {
+        let v = h.join().unwrap();
+        assert!(v.iter().all(|b| b.iter().all(|&x| x == t as u8)));
+    }
+
+    // A saved calibration is clamped to what this build allows.
+    let mut saved = c;
+    saved.batch_sizes[1] = u32::MAX;
+    saved.thread_cache_budget = 0;
+    let applied = calibrate::apply(&saved);
+    assert_eq!(
+        applied.batch_sizes[1] as usize,
+        size_class::class_info(1).batch_size
+    );
+    assert_eq!(applied.thread_cache_budget, MIN_PER_THREAD_CACHE_SIZE);
+    assert_eq!(calibrate::calibration(), Some(applied));
+}
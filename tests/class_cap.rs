@@ -0,0 +1,79 @@
+//! Per-size-class byte caps.
+//!
+//! Run with: cargo test --test class_cap
+
+use rtmalloc::RtMalloc;
+use rtmalloc::class_cap;
+use rtmalloc::config::PAGE_SIZE;
+use rtmalloc::size_class;
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+static LET_THROUGH: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn allow(size_class: usize, held: usize, cap: usize) -> bool {
+    assert!(held >= cap && class_cap::cap(size_class) == cap);
+    LET_THROUGH.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+#[test]
+fn test_cap_fails_one_class_only() {
+    let cls = size_class::size_to_class(3000);
+    let layout = Layout::from_size_align(size_class::class_to_size(cls), 8).unwrap();
+    let span_bytes = size_class::class_info(cls).pages * PAGE_SIZE;
+    let cap = class_cap::held(cls) + 2 * span_bytes;
+    class_cap::set_cap(cls, cap);
+
+    let mut ptrs = Vec::new();
+    loop {
+        let p = unsafe { GLOBAL.alloc(layout) };
+        if p.is_null() {
+            break;
+        }
+        ptrs.push(p);
+        assert!(ptrs.len() < 1 << 20, "cap never hit");
+    }
+    assert!(class_cap::held(cls) <= cap);
+    // Other classes are not affected.
+    let other: Vec<Box<[u8; 40]>> = (0..10_000).map(|_| Box::new([7; 40])).collect();
+
+    // A handler can let populates past the cap.
+    class_cap::set_handler(Some(allow));
+    let p = unsafe { GLOBAL.alloc(layout) };
+    assert!(!p.is_null());
+    assert!(LET_THROUGH.load(Ordering::Relaxed) > 0);
+    assert!(class_cap::held(cls) > cap);
+    ptrs.push(p);
+    class_cap::set_handler(None);
+
+    for p in ptrs {
+        unsafe { GLOBAL.dealloc(p, layout) };
+    }
+    class_cap::set_cap(cls, 0);
+    assert!(!unsafe { GLOBAL.alloc(layout) }.is_null());
+    drop(other);
+}
+
+#[test]
+fn test_set_size_cap() {
+    let changed = class_cap::set_size_cap(100_000..=usize::MAX, 1 << 30);
+    let big = (1..size_class::NUM_SIZE_CLASSES)
+        .filter(|&c| size_class::class_to_size(c) >= 100_000)
+        .count();
+    assert_eq!(changed, big);
+    for cls in 1..size_class::NUM_SIZE_CLASSES {
+        let want = if size_class::class_to_size(cls) >= 100_000 {
+            1 << 30
+        } else {
+            0
+        };
+        if cls != size_class::size_to_class(3000) {
+            assert_eq!(class_cap::cap(cls), want);
+        }
+    }
+    class_cap::set_size_cap(100_000..=usize::MAX, 0);
+}
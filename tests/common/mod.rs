@@ -0,0 +1,256 @@
+//! Workloads shared by the `global_*` integration tests, which each install
+//! `RtMalloc` as the global allocator under one front-end variant, and the
+//! [`Global`] allocator of the general suite.
+
+// Each test binary uses only some of these.
+#![allow(dead_code, unused_imports)]
+
+/// Global allocator of the general suite: `RtMalloc`, or with `testing`
+/// and `std` the shadow allocator around it, which checks every free.
+#[cfg(not(all(feature = "testing", feature = "std")))]
+pub use rtmalloc::RtMalloc as Global;
+#[cfg(all(feature = "testing", feature = "std"))]
+pub use rtmalloc::shadow::ShadowMalloc as Global;
+
+/// Fails the test it is dropped in if the shadow allocator recorded a
+/// violation. Does nothing without it.
+pub struct ShadowCheck;
+
+impl Drop for ShadowCheck {
+    fn drop(&mut self) {
+        #[cfg(all(feature = "testing", feature = "std"))]
+        if !std::thread::panicking() {
+            assert_eq!(rtmalloc::shadow::take_violations(), []);
+        }
+    }
+}
+
+/// This test binary, set up to run only `test_name`, with `env` set to tell
+/// that test it is the child. For tests that must crash, abort or change
+/// process-wide state without taking the rest of the suite with them.
+pub fn child_command(test_name: &str, env: &str) -> Command {
+    let mut command = Command::new(std::env::current_exe().unwrap());
+    command
+        .args([test_name, "--exact", "--nocapture", "--test-threads=1"])
+        .env(env, "1");
+    command
+}
+
+/// Run [`child_command`] to completion.
+pub fn run_child(test_name: &str, env: &str) -> Output {
+    child_command(test_name, env).output().expect("spawn child")
+}
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::process::{Command, Output};
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+
+/// Spawn and join short-lived threads in waves, each allocating on the way
+/// out, so thread caches are created and torn down many times.
+pub fn thread_churn() {
+    for wave in 0..20 {
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                std::thread::spawn(move || {
+                    let v: Vec<u64> = (0..200).map(|i| i * t + wave).collect();
+                    let s = format!("{wave}-{t}-{}", v.iter().sum::<u64>());
+                    (v.len(), s)
+                })
+            })
+            .collect();
+        for (t, h) in handles.into_iter().enumerate() {
+            let (len, s) = h.join().unwrap();
+            assert_eq!(len, 200);
+            assert!(s.starts_with(&format!("{wave}-{t}-")));
+        }
+    }
+}
+
+struct Node {
+    id: usize,
+    parent: RefCell<Weak<Node>>,
+    children: RefCell<Vec<Rc<Node>>>,
+    drops: Rc<AtomicUsize>,
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.drops.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Build `Rc` trees with weak parent links and strong cycles, break the
+/// cycles by hand, and check every node is dropped.
+pub fn rc_cycles() {
+    let drops = Rc::new(AtomicUsize::new(0));
+    let mut created = 0;
+    for round in 0..50 {
+        let root = Rc::new(Node {
+            id: round,
+            parent: RefCell::new(Weak::new()),
+            children: RefCell::new(Vec::new()),
+            drops: drops.clone(),
+        });
+        for i in 0..20 {
+            let child = Rc::new(Node {
+                id: i,
+                parent: RefCell::new(Rc::downgrade(&root)),
+                children: RefCell::new(Vec::new()),
+                drops: drops.clone(),
+            });
+            // A strong cycle through the root on every fourth child.
+            if i % 4 == 0 {
+                child.children.borrow_mut().push(root.clone());
+            }
+            root.children.borrow_mut().push(child);
+        }
+        created += 21;
+        for child in root.children.borrow().iter() {
+            assert_eq!(child.parent.borrow().upgrade().unwrap().id, round);
+            child.children.borrow_mut().clear();
+        }
+    }
+    assert_eq!(drops.load(Ordering::Relaxed), created);
+}
+
+/// Share `Arc` graphs with cycles across threads, then break them.
+pub fn arc_cycles() {
+    struct Shared {
+        peer: Mutex<Option<Arc<Shared>>>,
+        payload: Vec<u8>,
+    }
+
+    let pairs: Vec<_> = (0..64)
+        .map(|i| {
+            let a = Arc::new(Shared {
+                peer: Mutex::new(None),
+                payload: vec![i as u8; 64 + i * 16],
+            });
+            let b = Arc::new(Shared {
+                peer: Mutex::new(Some(a.clone())),
+                payload: vec![!(i as u8); 32],
+            });
+            *a.peer.lock().unwrap() = Some(b.clone());
+            (a, b)
+        })
+        .collect();
+    let weak: Vec<_> = pairs.iter().map(|(a, _)| Arc::downgrade(a)).collect();
+
+    let pairs = Arc::new(pairs);
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            let pairs = pairs.clone();
+            std::thread::spawn(move || {
+                for (a, b) in pairs.iter().skip(t).step_by(4) {
+                    let peer = a.peer.lock().unwrap().take().unwrap();
+                    assert!(Arc::ptr_eq(&peer, b));
+                    assert_eq!(b.payload.len(), 32);
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    drop(pairs);
+    assert!(weak.iter().all(|w| w.upgrade().is_none()));
+}
+
+/// Grow, shrink and rehash maps of owned keys and values.
+pub fn hashmap_churn() {
+    let mut map: HashMap<String, Vec<u32>> = HashMap::new();
+    for round in 0..10u32 {
+        for i in 0..2000u32 {
+            map.entry(format!("key-{}", i * 7 % 1500))
+                .or_default()
+                .push(i + round);
+        }
+        map.retain(|k, v| {
+            v.truncate(4);
+            !k.ends_with('3')
+        });
+        map.shrink_to_fit();
+    }
+    assert!(!map.is_empty());
+    assert!(map.values().all(|v| v.len() <= 4));
+}
+
+/// String building with many small, reallocating writes.
+pub fn format_heavy() {
+    let mut out = String::new();
+    for i in 0..5000 {
+        let s = format!("{i:>8}|{:x}|{:?}|{}", i * 31, (i, "x"), i as f64 / 3.0);
+        write!(out, "{s};").unwrap();
+        if out.len() > 1 << 16 {
+            out = out[out.len() / 2..].to_owned();
+        }
+    }
+    let lines: Vec<String> = out.split(';').map(str::to_owned).collect();
+    assert!(lines.len() > 100);
+}
+
+/// Thread-local values whose destructors allocate, format and register
+/// further thread-locals while the thread is being torn down, in whatever
+/// order the runtime runs them relative to the allocator's own cache.
+pub fn tls_destructors() {
+    struct Reporter {
+        tx: RefCell<Option<mpsc::Sender<String>>>,
+        name: &'static str,
+    }
+
+    impl Drop for Reporter {
+        fn drop(&mut self) {
+            let mut v: Vec<String> = (0..32).map(|i| format!("{}-{i}", self.name)).collect();
+            v.sort();
+            if let Some(tx) = self.tx.borrow_mut().take() {
+                // Touch a thread-local that may already have been destroyed,
+                // or initialize a new one during teardown.
+                let _ = LATE.try_with(|late| late.borrow_mut().push(v.len()));
+                tx.send(v.join(",")).unwrap();
+            }
+        }
+    }
+
+    thread_local! {
+        static FIRST: Reporter = const {
+            Reporter { tx: RefCell::new(None), name: "first" }
+        };
+        static SECOND: Reporter = const {
+            Reporter { tx: RefCell::new(None), name: "second" }
+        };
+        static LATE: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let handles: Vec<_> = (0..16)
+        .map(|t| {
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                // Alternate registration order between threads.
+                let order = if t % 2 == 0 {
+                    [&FIRST, &SECOND]
+                } else {
+                    [&SECOND, &FIRST]
+                };
+                for key in order {
+                    key.with(|r| *r.tx.borrow_mut() = Some(tx.clone()));
+                }
+                let v: Vec<Box<[u8]>> = (0..100).map(|i| vec![0u8; i * 8].into()).collect();
+                v.len()
+            })
+        })
+        .collect();
+    drop(tx);
+    for h in handles {
+        assert_eq!(h.join().unwrap(), 100);
+    }
+    let reports: Vec<String> = rx.iter().collect();
+    assert_eq!(reports.len(), 32);
+    for r in &reports {
+        assert_eq!(r.split(',').count(), 32);
+    }
+}
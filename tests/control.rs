@@ -0,0 +1,120 @@
+//! Control socket.
+//!
+//! Run with: cargo test --features control --test control
+
+#![cfg(all(feature = "control", unix))]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::control;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+/// Reply lines up to and including the final `ok` or `error: ...`.
+fn reply(reader: &mut impl BufRead) -> Vec<String> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        assert_ne!(reader.read_line(&mut line).unwrap(), 0, "server hung up");
+        let line = line.trim_end().to_owned();
+        let done = line == "ok" || line.starts_with("error: ");
+        lines.push(line);
+        if done {
+            return lines;
+        }
+    }
+}
+
+fn value(lines: &[String], name: &str) -> usize {
+    lines
+        .iter()
+        .find_map(|l| l.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+        .unwrap_or_else(|| panic!("no {name} in {lines:?}"))
+}
+
+#[test]
+fn test_socket_commands() {
+    let path = std::env::temp_dir().join(format!("rtmalloc-control-{}.sock", std::process::id()));
+    let server = control::start(&path).unwrap();
+    assert_eq!(server.path(), path);
+
+    rtmalloc::count_site!("control.big", 8 << 20);
+    let big: Vec<Vec<u8>> = (0..8).map(|_| vec![1u8; 1 << 20]).collect();
+    drop(big);
+
+    let stream = UnixStream::connect(&path).unwrap();
+    let mut reader = BufReader::new(&stream);
+    let mut out = &stream;
+    out.write_all(b"stats\nflush\nrelease\nbogus\n\n").unwrap();
+
+    let stats = reply(&mut reader);
+    assert_eq!(stats.last().unwrap(), "ok");
+    assert!(value(&stats, "system_bytes") >= 8 << 20);
+    #[cfg(feature = "stats")]
+    {
+        assert!(value(&stats, "alloc_count") > 0);
+        assert_eq!(value(&stats, "site.control.big.count"), 1);
+        assert_eq!(value(&stats, "site.control.big.bytes"), 8 << 20);
+    }
+
+    let flushed = reply(&mut reader);
+    assert_eq!(flushed.last().unwrap(), "ok");
+    value(&flushed, "flushed");
+
+    let released = reply(&mut reader);
+    #[cfg(target_os = "linux")]
+    assert!(value(&released, "released") >= 8 << 20);
+    assert_eq!(released.last().unwrap(), "ok");
+
+    assert_eq!(reply(&mut reader), ["error: unknown command, try `help`"]);
+    assert_eq!(reply(&mut reader), ["error: empty command"]);
+    drop(reader);
+    drop(stream);
+
+    server.stop();
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_socket_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("rtmalloc-private-{}.sock", std::process::id()));
+    let server = control::start(&path).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(control::start(&path).is_err(), "live socket replaced");
+
+    let stream = UnixStream::connect(&path).unwrap();
+    let mut reader = BufReader::new(&stream);
+    let mut out = &stream;
+    out.write_all(&[b'x'; 4096]).unwrap();
+    assert_eq!(reply(&mut reader), ["error: command too long"]);
+    let mut rest = String::new();
+    assert_eq!(
+        reader.read_line(&mut rest).unwrap(),
+        0,
+        "server kept reading"
+    );
+    drop(reader);
+    drop(stream);
+
+    server.stop();
+}
+
+#[test]
+#[cfg(not(feature = "percpu"))]
+fn test_thread_cache_size() {
+    let old = value(&reply_of("thread-cache-size"), "thread_cache_size");
+    let set = reply_of(&format!("thread-cache-size {}", old * 2));
+    assert_eq!(set, [format!("thread_cache_size {}", old * 2), "ok".into()]);
+    control::execute(&format!("thread-cache-size {old}"));
+    assert!(reply_of("thread-cache-size x")[0].starts_with("error: "));
+}
+
+#[cfg(not(feature = "percpu"))]
+fn reply_of(line: &str) -> Vec<String> {
+    control::execute(line).lines().map(str::to_owned).collect()
+}
@@ -0,0 +1,80 @@
+//! Span cool-down: a freed span is not handed out again until newer frees
+//! push it out of the queue.
+//!
+//! Run with: RTMALLOC_CLASSES=tests/cooldown.toml cargo test --features stats --test cooldown
+
+use rtmalloc::RtMalloc;
+use rtmalloc::config::SPAN_COOLDOWN;
+use rtmalloc::page_heap;
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::Mutex;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+/// The queue is process-wide; tests that free large allocations run one
+/// at a time.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Large, so each allocation has a span of its own.
+fn layout() -> Layout {
+    Layout::from_size_align(256 << 10, 8).unwrap()
+}
+
+#[test]
+fn test_freed_span_not_reused_within_window() {
+    if SPAN_COOLDOWN == 0 {
+        return;
+    }
+    let _serial = SERIAL.lock().unwrap();
+    page_heap::flush_cooldown();
+    assert_eq!(page_heap::cooling_spans(), 0);
+
+    // Each allocation comes from elsewhere while the earlier spans cool.
+    let mut freed = Vec::new();
+    loop {
+        let p = unsafe { GLOBAL.alloc(layout()) };
+        assert!(!freed.contains(&p), "{p:p} reused while cooling");
+        unsafe { GLOBAL.dealloc(p, layout()) };
+        freed.push(p);
+        assert_eq!(page_heap::cooling_spans(), freed.len());
+        if freed.len() == SPAN_COOLDOWN {
+            break;
+        }
+    }
+
+    page_heap::flush_cooldown();
+    assert_eq!(page_heap::cooling_spans(), 0);
+    // Released spans are usable again.
+    let p = unsafe { GLOBAL.alloc(layout()) };
+    unsafe {
+        p.write_bytes(1, layout().size());
+        GLOBAL.dealloc(p, layout());
+    }
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_cooldown_stats() {
+    if SPAN_COOLDOWN == 0 {
+        return;
+    }
+    let _serial = SERIAL.lock().unwrap();
+    page_heap::flush_cooldown();
+    // Bigger than anything the free lists hold.
+    let huge = Layout::from_size_align(64 << 20, 8).unwrap();
+    let before = rtmalloc::stats::snapshot();
+    let p = unsafe { GLOBAL.alloc(huge) };
+    unsafe { GLOBAL.dealloc(p, huge) };
+    // The only span that fits is cooling, so the heap grows.
+    let q = unsafe { GLOBAL.alloc(huge) };
+    let after = rtmalloc::stats::snapshot();
+    assert!(after.cooldown_spans > before.cooldown_spans);
+    assert!(after.cooldown_delayed_reuses > before.cooldown_delayed_reuses);
+
+    let mut report = String::new();
+    rtmalloc::stats::write_report(&mut report).unwrap();
+    assert!(report.contains("cooling_spans 1"), "{report}");
+    unsafe { GLOBAL.dealloc(q, huge) };
+    page_heap::flush_cooldown();
+}
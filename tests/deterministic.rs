@@ -0,0 +1,71 @@
+//! Integration tests for the deterministic feature: the same workload must
+//! produce the same heap layout in every process.
+//!
+//! Run with: cargo test --features deterministic,std --test deterministic
+
+#![cfg(feature = "deterministic")]
+
+mod common;
+
+use rtmalloc::RtMalloc;
+use std::fmt::Write;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+const CHILD_ENV: &str = "RTMALLOC_DETERMINISTIC_CHILD";
+
+/// Allocation pattern mixing small, large and freed-and-reused objects.
+fn trace_workload() -> String {
+    let mut out = String::with_capacity(1 << 16);
+    let mut live: Vec<Vec<u8>> = Vec::new();
+    for i in 0..2000usize {
+        let size = [8, 24, 100, 700, 4096, 40_000, 300_000][i % 7] + i % 13;
+        live.push(vec![0u8; size]);
+        if i % 3 == 0 {
+            live.swap_remove(i * 7 % live.len());
+        }
+    }
+    for v in &live {
+        write!(out, "{:x} ", v.as_ptr() as usize).unwrap();
+    }
+    writeln!(out, "heap_id={:x}", rtmalloc::version::heap_id()).unwrap();
+    out
+}
+
+/// Body of the child process; a no-op when run directly.
+#[test]
+fn child_trace() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        println!("TRACE {}", trace_workload());
+    }
+}
+
+fn run_child() -> String {
+    let out = common::run_child("child_trace", CHILD_ENV);
+    assert!(out.status.success(), "child failed: {out:?}");
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    stdout
+        .lines()
+        // libtest's `test child_trace ...` shares the line.
+        .find_map(|l| l.split_once("TRACE ").map(|(_, t)| t))
+        .expect("child printed no trace")
+        .to_owned()
+}
+
+#[test]
+fn test_layout_identical_across_processes() {
+    let first = run_child();
+    let second = run_child();
+    assert_eq!(first, second);
+}
+
+#[test]
+fn test_heap_id_is_fixed() {
+    let id = rtmalloc::version::heap_id();
+    assert_ne!(id, 0);
+    assert_eq!(
+        run_child().rsplit("heap_id=").next().unwrap(),
+        format!("{id:x}")
+    );
+}
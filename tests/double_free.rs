@@ -0,0 +1,74 @@
+//! Double-free detection: freeing an object twice into the thread cache
+//! aborts with a message, objects freed and allocated again are not taken
+//! for double frees.
+//!
+//! Run with: cargo test --features double-free-check,std --test double_free
+
+#![cfg(all(feature = "double-free-check", feature = "std"))]
+
+mod common;
+
+use rtmalloc::RtMalloc;
+use std::alloc::{GlobalAlloc, Layout};
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+const CHILD_ENV: &str = "RTMALLOC_DOUBLE_FREE_CHILD";
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+/// Body of the child process; a no-op when run directly.
+#[test]
+fn child_double_free() {
+    if std::env::var_os(CHILD_ENV).is_none() {
+        return;
+    }
+    let p = unsafe { GLOBAL.alloc(layout(64)) };
+    println!("FREEING {p:p}");
+    unsafe {
+        GLOBAL.dealloc(p, layout(64));
+        GLOBAL.dealloc(p, layout(64));
+    }
+    println!("CHILD SURVIVED");
+}
+
+#[test]
+fn test_double_free_aborts() {
+    let out = common::run_child("child_double_free", CHILD_ENV);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success(), "{out:?}");
+    assert!(stdout.contains("FREEING"), "{out:?}");
+    assert!(!stdout.contains("CHILD SURVIVED"), "{out:?}");
+    assert!(
+        stderr.contains("rtmalloc: double free of an object in the thread cache"),
+        "{out:?}"
+    );
+}
+
+#[test]
+fn test_reuse_is_not_a_double_free() {
+    // Every class, through the array cache, the free list and batches
+    // handed to the central lists and fetched back.
+    for size in [8, 16, 24, 100, 1000, 5000, 40_000] {
+        let mut ptrs: Vec<*mut u8> = (0..2000)
+            .map(|_| unsafe { GLOBAL.alloc(layout(size)) })
+            .collect();
+        for _ in 0..3 {
+            for &p in &ptrs {
+                unsafe { GLOBAL.dealloc(p, layout(size)) };
+            }
+            for p in &mut ptrs {
+                *p = unsafe { GLOBAL.alloc(layout(size)) };
+            }
+        }
+        for p in ptrs {
+            unsafe { GLOBAL.dealloc(p, layout(size)) };
+        }
+    }
+    let v: Vec<String> = (0..10_000).map(|i| i.to_string()).collect();
+    drop(v);
+}
@@ -0,0 +1,149 @@
+//! C entry points fed sizes and alignments no `Layout` can hold.
+//!
+//! Run with: cargo test --features ffi --test ffi_layout
+//! And the malloc family: cargo test --features c-abi --test ffi_layout
+
+#![cfg(all(feature = "ffi", not(feature = "testing")))]
+
+use rtmalloc::ffi::{
+    rtmalloc_alloc, rtmalloc_alloc_hint, rtmalloc_alloc_unchecked, rtmalloc_class_info,
+    rtmalloc_dealloc, rtmalloc_dealloc_sized, rtmalloc_dealloc_unchecked, rtmalloc_num_classes,
+    rtmalloc_realloc,
+};
+use rtmalloc::hint;
+use rtmalloc::size_class::{self, ClassDesc};
+
+const EINVAL: i32 = 22;
+
+fn errno() -> Option<i32> {
+    std::io::Error::last_os_error().raw_os_error()
+}
+
+fn clear_errno() {
+    rtmalloc::platform::set_errno(0);
+}
+
+#[test]
+fn test_rejects_bad_layouts() {
+    let bad = [
+        (8, 0),
+        (8, 3),
+        (8, 48),
+        (usize::MAX, 8),
+        (usize::MAX - 7, 8),
+        (isize::MAX as usize, 16),
+        (64, 1 << (usize::BITS - 1)),
+    ];
+    for (size, align) in bad {
+        clear_errno();
+        assert!(rtmalloc_alloc(size, align).is_null(), "{size} {align}");
+        assert_eq!(errno(), Some(EINVAL), "{size} {align}");
+    }
+}
+
+#[test]
+fn test_bad_realloc_and_dealloc_leave_pointer_alone() {
+    let p = rtmalloc_alloc(100, 16);
+    assert!(!p.is_null());
+    unsafe {
+        p.write_bytes(0x5A, 100);
+        for new_size in [usize::MAX, isize::MAX as usize] {
+            clear_errno();
+            assert!(rtmalloc_realloc(p, 100, 16, new_size).is_null());
+            assert_eq!(errno(), Some(EINVAL));
+        }
+        // Ignored rather than freed with a made-up layout.
+        rtmalloc_dealloc(p, 100, 3);
+        rtmalloc_dealloc(std::ptr::null_mut(), 100, 16);
+        assert_eq!(*p.add(99), 0x5A);
+
+        let q = rtmalloc_realloc(p, 100, 16, 5000);
+        assert!(!q.is_null());
+        assert_eq!(*q.add(99), 0x5A);
+        rtmalloc_dealloc(q, 5000, 16);
+
+        let r = rtmalloc_alloc_unchecked(64, 64);
+        assert!(!r.is_null() && r.addr().is_multiple_of(64));
+        rtmalloc_dealloc_unchecked(r, 64, 64);
+    }
+}
+
+#[test]
+fn test_hinted_alloc_and_sized_free() {
+    clear_errno();
+    assert!(rtmalloc_alloc_hint(8, 3, hint::COLD).is_null());
+    assert_eq!(errno(), Some(EINVAL));
+
+    for size in [1, 24, 200, 3000, 100_000] {
+        for hints in [0, hint::SHORT_LIVED, hint::COLD, u32::MAX] {
+            let p = rtmalloc_alloc_hint(size, 8, hints);
+            assert!(!p.is_null() && p.addr().is_multiple_of(8), "{size} {hints}");
+            unsafe {
+                p.write_bytes(0xA5, size);
+                assert_eq!(*p.add(size - 1), 0xA5);
+                rtmalloc_dealloc_sized(p, size);
+            }
+        }
+    }
+    unsafe { rtmalloc_dealloc_sized(std::ptr::null_mut(), 64) };
+
+    // Freed objects are handed out again.
+    let p = rtmalloc_alloc(48, 8);
+    unsafe { rtmalloc_dealloc_sized(p, 48) };
+    let q = rtmalloc_alloc(48, 8);
+    assert_eq!(p, q);
+    unsafe { rtmalloc_dealloc_sized(q, 48) };
+}
+
+#[cfg(feature = "c-abi")]
+#[test]
+fn test_malloc_family_rejects_huge_sizes() {
+    use rtmalloc::ffi::c_abi;
+    const ENOMEM: i32 = 12;
+
+    unsafe {
+        for size in [usize::MAX, isize::MAX as usize] {
+            clear_errno();
+            assert!(c_abi::malloc(size).is_null());
+            assert_eq!(errno(), Some(ENOMEM));
+            clear_errno();
+            assert!(c_abi::pvalloc(size).is_null());
+            assert_eq!(errno(), Some(ENOMEM));
+            clear_errno();
+            assert!(c_abi::memalign(64, size).is_null());
+            assert_eq!(errno(), Some(ENOMEM));
+            let mut out = std::ptr::null_mut();
+            assert_eq!(c_abi::posix_memalign(&mut out, 64, size), ENOMEM);
+        }
+        clear_errno();
+        assert!(c_abi::calloc(usize::MAX / 2, 3).is_null());
+        assert_eq!(errno(), Some(ENOMEM));
+        clear_errno();
+        assert!(c_abi::aligned_alloc(8, usize::MAX - 7).is_null());
+        assert_eq!(errno(), Some(ENOMEM));
+        clear_errno();
+        assert!(c_abi::memalign(24, 64).is_null());
+        assert_eq!(errno(), Some(EINVAL));
+
+        let p = c_abi::malloc(32);
+        assert!(c_abi::realloc(p, usize::MAX).is_null());
+        c_abi::free(p);
+    }
+}
+
+#[test]
+fn test_class_info_outside_table() {
+    let n = rtmalloc_num_classes();
+    assert_eq!(n, size_class::num_classes());
+    let mut out = ClassDesc::default();
+    unsafe {
+        assert!(!rtmalloc_class_info(0, &mut out));
+        assert!(!rtmalloc_class_info(n + 1, &mut out));
+        assert!(!rtmalloc_class_info(usize::MAX, &mut out));
+        assert!(!rtmalloc_class_info(1, std::ptr::null_mut()));
+        assert_eq!(out, ClassDesc::default());
+
+        assert!(rtmalloc_class_info(n, &mut out));
+    }
+    assert_eq!(Some(out), size_class::iter().last());
+}
@@ -0,0 +1,164 @@
+//! Realistic std usage with RtMalloc as the global allocator, on the
+//! `percpu` rseq slab, and again in a child process where rseq is refused
+//! by a seccomp filter so every thread takes the central-list fallback.
+//!
+//! Run with: cargo +nightly test --features percpu --test global_percpu
+
+#![cfg(feature = "percpu")]
+
+mod common;
+
+use rtmalloc::RtMalloc;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+const CHILD_ENV: &str = "RTMALLOC_NO_RSEQ_CHILD";
+
+#[test]
+fn test_thread_churn() {
+    common::thread_churn();
+}
+
+#[test]
+fn test_rc_cycles() {
+    common::rc_cycles();
+}
+
+#[test]
+fn test_arc_cycles() {
+    common::arc_cycles();
+}
+
+#[test]
+fn test_hashmap_churn() {
+    common::hashmap_churn();
+}
+
+#[test]
+fn test_format_heavy() {
+    common::format_heavy();
+}
+
+#[test]
+fn test_tls_destructors() {
+    common::tls_destructors();
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_cpu_stats() {
+    let total = |f: fn(&rtmalloc::cpu_cache::CpuStats) -> u64| {
+        rtmalloc::debug::cpu_stats().map(|c| f(&c)).sum::<u64>()
+    };
+    let (allocs, frees) = (total(|c| c.allocs), total(|c| c.frees));
+    let boxes: Vec<Box<u64>> = (0..10_000).map(Box::new).collect();
+    drop(boxes);
+    assert!(rtmalloc::cpu_cache::num_cpus() > 0);
+    assert!(total(|c| c.allocs) >= allocs + 9_000);
+    assert!(total(|c| c.frees) >= frees + 9_000);
+    assert!(total(|c| c.refills) > 0);
+    let cpus = rtmalloc::cpu_cache::num_cpus();
+    assert_eq!(rtmalloc::debug::cpu_stats().count(), cpus as usize);
+    assert!(rtmalloc::cpu_cache::cpu_stats(cpus).is_none());
+}
+
+/// Make `rseq(2)` fail with ENOSYS for the calling thread and every thread
+/// it spawns afterwards, as on a kernel without rseq. False if seccomp is
+/// unavailable here.
+fn deny_rseq() -> bool {
+    #[repr(C)]
+    struct SockFilter {
+        code: u16,
+        jt: u8,
+        jf: u8,
+        k: u32,
+    }
+    #[repr(C)]
+    struct SockFprog {
+        len: u16,
+        filter: *const SockFilter,
+    }
+    unsafe extern "C" {
+        fn prctl(option: i32, ...) -> i32;
+    }
+
+    const LD_ABS_W: u16 = 0x20;
+    const JEQ_K: u16 = 0x15;
+    const RET_K: u16 = 0x06;
+    const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+    const SYS_RSEQ: u32 = 334;
+    const RET_ERRNO_ENOSYS: u32 = 0x0005_0000 | 38;
+    const RET_ALLOW: u32 = 0x7fff_0000;
+    const PR_SET_NO_NEW_PRIVS: i32 = 38;
+    const PR_SET_SECCOMP: i32 = 22;
+    const SECCOMP_MODE_FILTER: u64 = 2;
+
+    let insn = |code, jt, jf, k| SockFilter { code, jt, jf, k };
+    let filter = [
+        insn(LD_ABS_W, 0, 0, 4), // seccomp_data.arch
+        insn(JEQ_K, 0, 3, AUDIT_ARCH_X86_64),
+        insn(LD_ABS_W, 0, 0, 0), // seccomp_data.nr
+        insn(JEQ_K, 0, 1, SYS_RSEQ),
+        insn(RET_K, 0, 0, RET_ERRNO_ENOSYS),
+        insn(RET_K, 0, 0, RET_ALLOW),
+    ];
+    let prog = SockFprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr(),
+    };
+    unsafe {
+        prctl(PR_SET_NO_NEW_PRIVS, 1u64, 0u64, 0u64, 0u64) == 0
+            && prctl(
+                PR_SET_SECCOMP,
+                SECCOMP_MODE_FILTER,
+                &prog as *const SockFprog,
+            ) == 0
+    }
+}
+
+/// Body of the child process; a no-op when run directly.
+#[test]
+fn child_without_rseq() {
+    if std::env::var_os(CHILD_ENV).is_none() {
+        return;
+    }
+    if !deny_rseq() {
+        println!("RESULT skip");
+        return;
+    }
+    std::thread::spawn(|| {
+        let v: Vec<u8> = vec![1; 100];
+        assert_eq!(v.len(), 100);
+        assert_eq!(rseq::current_cpu(), None);
+        assert!(!rseq::rseq_available());
+    })
+    .join()
+    .unwrap();
+    common::thread_churn();
+    common::rc_cycles();
+    common::arc_cycles();
+    common::hashmap_churn();
+    common::format_heavy();
+    common::tls_destructors();
+    let report = rtmalloc::selftest();
+    assert!(report.passed(), "{report}");
+    println!("RESULT ok");
+}
+
+#[test]
+fn test_rseq_unavailable_fallback() {
+    let out = common::child_command("child_without_rseq", CHILD_ENV)
+        // Keep glibc from registering rseq itself, so the allocator's own
+        // registration is the one the filter refuses.
+        .env("GLIBC_TUNABLES", "glibc.pthread.rseq=0")
+        .output()
+        .expect("spawn child");
+    assert!(out.status.success(), "child failed: {out:?}");
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    match stdout.lines().find_map(|l| l.split_once("RESULT ")) {
+        Some((_, "ok")) => {}
+        Some((_, "skip")) => eprintln!("seccomp unavailable; rseq fallback not exercised"),
+        _ => panic!("child printed no result: {stdout}"),
+    }
+}
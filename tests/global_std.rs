@@ -0,0 +1,43 @@
+//! Realistic std usage with RtMalloc as the global allocator, on the
+//! `std` thread cache (`std::thread_local!`).
+//!
+//! Run with: cargo test --features std --test global_std
+
+#![cfg(all(feature = "std", not(any(feature = "nightly", feature = "percpu"))))]
+
+mod common;
+
+use rtmalloc::RtMalloc;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+#[test]
+fn test_thread_churn() {
+    common::thread_churn();
+}
+
+#[test]
+fn test_rc_cycles() {
+    common::rc_cycles();
+}
+
+#[test]
+fn test_arc_cycles() {
+    common::arc_cycles();
+}
+
+#[test]
+fn test_hashmap_churn() {
+    common::hashmap_churn();
+}
+
+#[test]
+fn test_format_heavy() {
+    common::format_heavy();
+}
+
+#[test]
+fn test_tls_destructors() {
+    common::tls_destructors();
+}
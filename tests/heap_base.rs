@@ -0,0 +1,65 @@
+//! Heap placed at a fixed base: every allocation lands in the `max_heap`
+//! bytes after `heap_base`, so it fits a 32-bit offset from the base.
+//!
+//! Run with: RTMALLOC_CLASSES=tests/heap_base.toml cargo test --test heap_base
+
+#![cfg(target_pointer_width = "64")]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::config::{HEAP_BASE, MAX_HEAP};
+use std::alloc::{GlobalAlloc, Layout};
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+fn offset(ptr: *const u8) -> u32 {
+    assert!(
+        (HEAP_BASE..HEAP_BASE + MAX_HEAP).contains(&ptr.addr()),
+        "{ptr:p} outside the heap region"
+    );
+    u32::try_from(ptr.addr() - HEAP_BASE).unwrap()
+}
+
+#[test]
+fn test_region_at_base() {
+    if HEAP_BASE == 0 {
+        return;
+    }
+    assert_eq!(
+        rtmalloc::heap_region(),
+        Some(HEAP_BASE..HEAP_BASE + MAX_HEAP)
+    );
+}
+
+#[test]
+fn test_allocations_compress_to_offsets() {
+    if HEAP_BASE == 0 {
+        return;
+    }
+    let boxed = Box::new(7u64);
+    let text = "x".repeat(100_000);
+    let v: Vec<u32> = (0..1_000_000).collect();
+    for ptr in [
+        (&*boxed as *const u64).cast::<u8>(),
+        text.as_ptr(),
+        v.as_ptr().cast(),
+    ] {
+        let offset = offset(ptr);
+        assert_eq!(HEAP_BASE + offset as usize, ptr.addr());
+    }
+
+    for (size, align) in [
+        (1, 1),
+        (5000, 8),
+        (300_000, 8),
+        (4 << 20, 8),
+        (1 << 20, 1 << 16),
+    ] {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let p = unsafe { GLOBAL.alloc(layout) };
+        assert!(!p.is_null());
+        offset(p);
+        assert!(p.addr().is_multiple_of(align));
+        unsafe { GLOBAL.dealloc(p, layout) };
+    }
+}
@@ -226,6 +226,18 @@ fn test_print_report_does_not_panic() {
     histogram::print_report();
 }
 
+#[test]
+fn test_write_report_lists_buckets() {
+    histogram::record(24);
+    let mut text = String::new();
+    histogram::write_report(&mut text).unwrap();
+    assert!(text.contains("Allocation size histogram"));
+    assert!(
+        text.lines()
+            .any(|line| line.trim_start().starts_with("24 "))
+    );
+}
+
 // --- real allocations ---
 
 #[test]
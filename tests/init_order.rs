@@ -0,0 +1,114 @@
+//! Allocation from constructors that run before `main`.
+//!
+//! C++ static initializers, and Rust code in `.init_array`, call the
+//! allocator before Rust's runtime is set up. The constructor below runs at
+//! the earliest init priority, ahead of `std`'s own, and exercises every
+//! allocation path; the test checks what it recorded.
+//!
+//! Run with: cargo test --features std --test init_order
+//! And the malloc family: cargo test --features c-abi --test init_order
+
+#![cfg(target_os = "linux")]
+
+use rtmalloc::RtMalloc;
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+/// One bit per check below that passed in the constructor.
+static PASSED: AtomicU32 = AtomicU32::new(0);
+
+const RAN: u32 = 1 << 0;
+const BOX: u32 = 1 << 1;
+const VEC_GROWTH: u32 = 1 << 2;
+const LARGE: u32 = 1 << 3;
+const ZEROED: u32 = 1 << 4;
+const REALLOC: u32 = 1 << 5;
+const MALLOC: u32 = 1 << 6;
+
+fn pass(check: u32, ok: bool) {
+    if ok {
+        PASSED.fetch_or(check, Ordering::Relaxed);
+    }
+}
+
+#[used]
+#[unsafe(link_section = ".init_array.00000")]
+static BEFORE_STD: extern "C" fn() = before_main;
+
+extern "C" fn before_main() {
+    pass(RAN, true);
+
+    let b = Box::new(0x5A5A_u64);
+    pass(BOX, *b == 0x5A5A);
+    drop(b);
+
+    let mut v = Vec::new();
+    for i in 0..10_000u32 {
+        v.push(i);
+    }
+    pass(
+        VEC_GROWTH,
+        v.iter().enumerate().all(|(i, &x)| x == i as u32),
+    );
+    drop(v);
+
+    let large = vec![7u8; 4 << 20];
+    pass(LARGE, large[(4 << 20) - 1] == 7);
+    drop(large);
+
+    unsafe {
+        let layout = Layout::from_size_align(3000, 64).unwrap();
+        let p = GLOBAL.alloc_zeroed(layout);
+        pass(
+            ZEROED,
+            !p.is_null() && p.addr().is_multiple_of(64) && (0..3000).all(|i| *p.add(i) == 0),
+        );
+        p.write_bytes(0xC3, 3000);
+        let q = GLOBAL.realloc(p, layout, 100_000);
+        pass(REALLOC, !q.is_null() && *q.add(2999) == 0xC3);
+        GLOBAL.dealloc(q, Layout::from_size_align(100_000, 64).unwrap());
+    }
+
+    #[cfg(feature = "c-abi")]
+    unsafe {
+        use rtmalloc::ffi::c_abi;
+        let p = c_abi::malloc(40);
+        let z = c_abi::calloc(10, 10);
+        let r = c_abi::realloc(p, 5000);
+        pass(
+            MALLOC,
+            !r.is_null() && !z.is_null() && c_abi::malloc_usable_size(r) >= 5000,
+        );
+        c_abi::free(r);
+        c_abi::free(z);
+    }
+    #[cfg(not(feature = "c-abi"))]
+    pass(MALLOC, true);
+}
+
+#[test]
+fn test_constructor_allocations() {
+    let passed = PASSED.load(Ordering::Relaxed);
+    assert_ne!(passed & RAN, 0, "constructor did not run");
+    for (name, check) in [
+        ("box", BOX),
+        ("vec growth", VEC_GROWTH),
+        ("large", LARGE),
+        ("zeroed", ZEROED),
+        ("realloc", REALLOC),
+        ("malloc family", MALLOC),
+    ] {
+        assert_ne!(passed & check, 0, "{name} failed before main");
+    }
+}
+
+#[test]
+fn test_constructor_memory_reused_after_main() {
+    // What the constructor freed went back into the heap it shares with
+    // `main`, which keeps serving.
+    let v: Vec<Box<[u8; 64]>> = (0..1000).map(|_| Box::new([1; 64])).collect();
+    assert!(v.iter().all(|b| b[63] == 1));
+}
@@ -0,0 +1,51 @@
+//! Integration tests for the latency-histogram feature.
+//!
+//! Run with: cargo test --features latency-histogram,nightly --test latency
+
+#![cfg(feature = "latency-histogram")]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::stats::{self, NUM_LATENCY_BUCKETS, SlowPath};
+use std::alloc::{GlobalAlloc, Layout};
+
+#[test]
+fn test_record_lands_in_power_of_two_bucket() {
+    let before = stats::latency_snapshot();
+    stats::record_latency(SlowPath::CentralRefill, 0);
+    stats::record_latency(SlowPath::CentralRefill, 1000); // [512, 1024)
+    stats::record_latency(SlowPath::CentralRefill, u64::MAX);
+    let after = stats::latency_snapshot();
+    let (b, a) = (before.central_refill, after.central_refill);
+    assert!(a.counts[0] > b.counts[0]);
+    assert!(a.counts[9] > b.counts[9]);
+    assert!(a.counts[NUM_LATENCY_BUCKETS - 1] > b.counts[NUM_LATENCY_BUCKETS - 1]);
+}
+
+#[test]
+fn test_percentile_upper_bound() {
+    let mut hist = stats::latency_snapshot().os_alloc;
+    hist.counts = [0; NUM_LATENCY_BUCKETS];
+    assert_eq!(hist.percentile(99.0), 0);
+    hist.counts[4] = 99; // [16, 32) ns
+    hist.counts[20] = 1; // [1 Mi, 2 Mi) ns
+    assert_eq!(hist.percentile(50.0), 32);
+    assert_eq!(hist.percentile(99.0), 32);
+    assert_eq!(hist.percentile(100.0), 1 << 21);
+}
+
+#[test]
+fn test_growth_events_recorded() {
+    let before = stats::latency_snapshot();
+    // A fresh large allocation must grow the heap and map memory.
+    let layout = Layout::from_size_align(64 << 20, 8).unwrap();
+    let ptr = unsafe { RtMalloc.alloc(layout) };
+    assert!(!ptr.is_null());
+    unsafe { RtMalloc.dealloc(ptr, layout) };
+    let after = stats::latency_snapshot();
+    for event in [SlowPath::PageHeapGrow, SlowPath::OsAlloc] {
+        assert!(
+            after.get(event).count() > before.get(event).count(),
+            "{event:?} not recorded"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Layout checks on free: a dealloc whose layout does not match the
+//! allocation is reported, matching ones and frees after realloc are not.
+//!
+//! Run with: cargo test --features layout-check --test layout_check
+
+#![cfg(feature = "layout-check")]
+
+mod common;
+
+use rtmalloc::RtMalloc;
+use rtmalloc::failure::{self, Failure};
+use rtmalloc::layout_check;
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+/// The mismatch count and the failure handler are process-wide; tests that
+/// read them run one at a time.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+static REPORTED: AtomicUsize = AtomicUsize::new(0);
+static REPORTED_PTR: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn count(failure: Failure, detail: usize) {
+    if failure == Failure::LayoutMismatch {
+        REPORTED.fetch_add(1, Ordering::Relaxed);
+        REPORTED_PTR.store(detail, Ordering::Relaxed);
+    }
+}
+
+const CHILD_ENV: &str = "RTMALLOC_LAYOUT_CHECK_CHILD";
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+/// Frees with a wrong layout from a function of its own, so the report can
+/// be traced back to it. Never inlined, so it has a frame.
+#[inline(never)]
+fn free_with_wrong_layout() {
+    let p = unsafe { GLOBAL.alloc(layout(200)) };
+    unsafe { GLOBAL.dealloc(p, layout(24)) };
+}
+
+/// Body of the child process; a no-op when run directly.
+#[test]
+fn child_mismatch() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        free_with_wrong_layout();
+    }
+}
+
+#[cfg(all(
+    feature = "std",
+    unix,
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+#[test]
+fn test_report_names_callers() {
+    let out = common::run_child("child_mismatch", CHILD_ENV);
+    assert!(out.status.success(), "{out:?}");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let line = stderr
+        .lines()
+        .find(|l| l.starts_with("rtmalloc: dealloc layout does not match"))
+        .unwrap_or_else(|| panic!("no report in {stderr:?}"));
+    let (_, callers) = line
+        .split_once(", freed from ")
+        .unwrap_or_else(|| panic!("no callers in {line:?}"));
+    let exe = std::env::current_exe().unwrap();
+    let name = exe.file_name().unwrap().to_str().unwrap();
+    let offsets: Vec<String> = callers
+        .split(' ')
+        .map(|caller| {
+            let (file, offset) = caller.split_once('+').unwrap();
+            assert_eq!(file, name, "{line}");
+            offset.to_owned()
+        })
+        .collect();
+    let symbols = std::process::Command::new("addr2line")
+        .args(["-f", "-C", "-i", "-e"])
+        .arg(&exe)
+        .args(&offsets)
+        .output()
+        .expect("failed to run addr2line");
+    let symbols = String::from_utf8_lossy(&symbols.stdout);
+    assert!(symbols.contains("free_with_wrong_layout"), "{symbols}");
+}
+
+#[test]
+fn test_wrong_size_is_reported() {
+    let _serial = SERIAL.lock().unwrap();
+    failure::set_handler(Some(count));
+    let before = layout_check::mismatches();
+    let reported = REPORTED.load(Ordering::Relaxed);
+
+    let small = unsafe { GLOBAL.alloc(layout(200)) };
+    unsafe { GLOBAL.dealloc(small, layout(24)) };
+    assert_eq!(layout_check::mismatches(), before + 1);
+    assert_eq!(REPORTED.load(Ordering::Relaxed), reported + 1);
+    assert_eq!(REPORTED_PTR.load(Ordering::Relaxed), small.addr());
+
+    let large = unsafe { GLOBAL.alloc(layout(1 << 20)) };
+    unsafe { GLOBAL.dealloc(large, layout(4 << 20)) };
+    assert_eq!(layout_check::mismatches(), before + 2);
+
+    let large = unsafe { GLOBAL.alloc(layout(1 << 20)) };
+    unsafe { GLOBAL.dealloc(large, layout(100)) };
+    assert_eq!(layout_check::mismatches(), before + 3);
+
+    failure::set_handler(None);
+}
+
+#[test]
+fn test_matching_frees_are_quiet() {
+    let _serial = SERIAL.lock().unwrap();
+    let before = layout_check::mismatches();
+
+    for size in [1, 8, 24, 100, 1000, 5000, 40_000, 1 << 20] {
+        let p = unsafe { GLOBAL.alloc(layout(size)) };
+        unsafe { GLOBAL.dealloc(p, layout(size)) };
+    }
+    let v: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+    drop(v);
+
+    assert_eq!(layout_check::mismatches(), before);
+}
+
+#[test]
+fn test_free_after_realloc_is_quiet() {
+    let _serial = SERIAL.lock().unwrap();
+    let before = layout_check::mismatches();
+
+    // Shrinking into a smaller class must move the object, or the free
+    // with the new size would name the wrong class.
+    let p = unsafe { GLOBAL.alloc(layout(256)) };
+    let p = unsafe { GLOBAL.realloc(p, layout(256), 24) };
+    unsafe { GLOBAL.dealloc(p, layout(24)) };
+
+    let p = unsafe { GLOBAL.alloc(layout(100)) };
+    let p = unsafe { GLOBAL.realloc(p, layout(100), 5000) };
+    let p = unsafe { GLOBAL.realloc(p, layout(5000), 1 << 20) };
+    let p = unsafe { GLOBAL.realloc(p, layout(1 << 20), 300_000) };
+    unsafe { GLOBAL.dealloc(p, layout(300_000)) };
+
+    let mut v: Vec<u64> = Vec::new();
+    for i in 0..10_000 {
+        v.push(i);
+    }
+    v.truncate(3);
+    v.shrink_to_fit();
+    drop(v);
+
+    assert_eq!(layout_check::mismatches(), before);
+}
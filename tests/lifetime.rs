@@ -0,0 +1,81 @@
+//! Integration tests for the lifetime-histogram feature.
+//!
+//! Run with: cargo test --features lifetime-histogram --test lifetime
+
+#![cfg(feature = "lifetime-histogram")]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::lifetime::{self, NUM_BUCKETS};
+use rtmalloc::size_class;
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+/// The sample interval is process-wide; tests that change it run one at a
+/// time.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[test]
+fn test_sampled_frees_land_in_their_class() {
+    let _serial = SERIAL.lock().unwrap();
+    let layout = Layout::from_size_align(200, 8).unwrap();
+    let cls = size_class::layout_to_class(200, 8);
+    let before = lifetime::histogram(cls).count();
+
+    lifetime::set_sample_interval(1);
+    let ptrs: Vec<*mut u8> = (0..64).map(|_| unsafe { RtMalloc.alloc(layout) }).collect();
+    std::thread::sleep(Duration::from_millis(2));
+    for p in ptrs {
+        unsafe { RtMalloc.dealloc(p, layout) };
+    }
+    lifetime::set_sample_interval(1024);
+
+    let hist = lifetime::histogram(cls);
+    assert!(hist.count() >= before + 64);
+    // Every one of them lived through the sleep.
+    assert!(hist.percentile(50.0) >= 2_000_000);
+}
+
+#[test]
+fn test_large_allocations_use_class_zero() {
+    let _serial = SERIAL.lock().unwrap();
+    let layout = Layout::from_size_align(1 << 20, 8).unwrap();
+    let before = lifetime::histogram(0).count();
+
+    lifetime::set_sample_interval(1);
+    let p = unsafe { RtMalloc.alloc(layout) };
+    assert!(!p.is_null());
+    unsafe { RtMalloc.dealloc(p, layout) };
+    lifetime::set_sample_interval(1024);
+
+    assert!(lifetime::histogram(0).count() > before);
+}
+
+#[test]
+fn test_disabled_sampling_records_nothing() {
+    let _serial = SERIAL.lock().unwrap();
+    lifetime::set_sample_interval(0);
+    let before = lifetime::summary();
+    for _ in 0..1000 {
+        drop(std::hint::black_box(Box::new([0u8; 48])));
+    }
+    let after = lifetime::summary();
+    lifetime::set_sample_interval(1024);
+    assert_eq!(after.measured, before.measured);
+    assert_eq!(after.dropped, before.dropped);
+}
+
+#[test]
+fn test_percentile_upper_bound() {
+    let mut hist = lifetime::histogram(1);
+    hist.counts = [0; NUM_BUCKETS];
+    assert_eq!(hist.percentile(99.0), 0);
+    hist.counts[10] = 90; // [1 Ki, 2 Ki) ns
+    hist.counts[30] = 10; // [1 Gi, 2 Gi) ns
+    assert_eq!(hist.percentile(50.0), 1 << 11);
+    assert_eq!(hist.percentile(90.0), 1 << 11);
+    assert_eq!(hist.percentile(99.0), 1 << 31);
+}
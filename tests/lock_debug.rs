@@ -0,0 +1,61 @@
+//! Integration tests for the lock-debug feature: a hook that allocates while
+//! the page heap lock is held must abort with a diagnostic, not hang.
+//!
+//! Run with: cargo test --features lock-debug --test lock_debug
+
+// Builds with a reentrancy guard serve the nested allocation from the
+// bootstrap arena instead of taking the lock again.
+#![cfg(all(
+    feature = "lock-debug",
+    unix,
+    not(any(
+        feature = "alloc-histogram",
+        feature = "debug",
+        feature = "stats",
+        feature = "tracing"
+    ))
+))]
+
+mod common;
+
+use rtmalloc::RtMalloc;
+use rtmalloc::page_heap::{self, GrowthEvent};
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+const CHILD_ENV: &str = "RTMALLOC_LOCK_DEBUG_CHILD";
+
+/// Allocates from inside the page heap, as a careless hook would.
+extern "C" fn allocating_hook(_event: GrowthEvent) {
+    std::hint::black_box(vec![0u8; 1 << 20]);
+}
+
+/// Body of the child process; a no-op when run directly.
+#[test]
+fn child_reenter() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        page_heap::set_growth_hook(Some(allocating_hook));
+        std::hint::black_box(vec![0u8; 256 << 20]);
+        page_heap::set_growth_hook(None);
+        println!("NOT ABORTED");
+    }
+}
+
+#[test]
+fn test_reentry_aborts_with_sites() {
+    let out = common::run_child("child_reenter", CHILD_ENV);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success(), "child survived: {out:?}");
+    assert!(!String::from_utf8_lossy(&out.stdout).contains("NOT ABORTED"));
+    let line = stderr
+        .lines()
+        .find(|l| l.starts_with("rtmalloc: lock re-entered by the thread holding it (0x"))
+        .unwrap_or_else(|| panic!("no diagnostic in {stderr:?}"));
+    let (_, sites) = line.split_once("), held since ").unwrap();
+    let (held, at) = sites.split_once(", taken at ").unwrap();
+    for site in [held, at] {
+        assert!(site.starts_with("src/"), "{site}");
+        assert_eq!(site.split(':').count(), 3, "{site}");
+    }
+}
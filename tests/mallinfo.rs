@@ -0,0 +1,29 @@
+//! glibc-compatible heap summary.
+//!
+//! Run with: cargo test --features ffi --test mallinfo
+
+#![cfg(feature = "ffi")]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::ffi::{Mallinfo2, rtmalloc_mallinfo2};
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+#[test]
+fn test_mallinfo2() {
+    let before = rtmalloc_mallinfo2();
+    let big = vec![1u8; 8 << 20];
+    let during = rtmalloc_mallinfo2();
+    drop(big);
+    let after = rtmalloc_mallinfo2();
+
+    for m in [before, during, after] {
+        assert_eq!(m.arena, m.uordblks + m.fordblks, "{m:?}");
+        assert!(m.fsmblks <= m.fordblks);
+        assert_eq!((m.hblks, m.hblkhd, m.usmblks, m.keepcost), (0, 0, 0, 0));
+    }
+    assert!(during.uordblks >= before.uordblks + (8 << 20));
+    assert!(after.fordblks >= 8 << 20);
+    assert_ne!(during, Mallinfo2::default());
+}
@@ -0,0 +1,117 @@
+//! Integration tests for massif heap snapshots.
+//!
+//! Run with: cargo test --features lifetime-histogram --test massif
+
+#![cfg(feature = "lifetime-histogram")]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::lifetime;
+use rtmalloc::massif::{DETAILED_EVERY, Profile};
+use rtmalloc::size_class;
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::Mutex;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+/// The sample interval is process-wide; tests that change it run one at a
+/// time.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+const SIZE: usize = 3000;
+
+fn alloc_many(n: usize) -> Vec<*mut u8> {
+    let layout = Layout::from_size_align(SIZE, 8).unwrap();
+    (0..n).map(|_| unsafe { RtMalloc.alloc(layout) }).collect()
+}
+
+fn free_all(ptrs: Vec<*mut u8>) {
+    let layout = Layout::from_size_align(SIZE, 8).unwrap();
+    for p in ptrs {
+        unsafe { RtMalloc.dealloc(p, layout) };
+    }
+}
+
+#[test]
+fn test_live_heap_counts_sampled_objects() {
+    let _serial = SERIAL.lock().unwrap();
+    let cls = size_class::layout_to_class(SIZE, 8);
+    let rounding = (size_class::class_to_size(cls) - SIZE) as u64;
+
+    lifetime::set_sample_interval(1);
+    let before = lifetime::live_heap();
+    let ptrs = alloc_many(100);
+    let during = lifetime::live_heap();
+    free_all(ptrs);
+    let after = lifetime::live_heap();
+    lifetime::set_sample_interval(1024);
+
+    assert_eq!(during.bytes[cls] - before.bytes[cls], 100 * SIZE as u64);
+    assert!(during.slack >= before.slack + 100 * rounding);
+    assert_eq!(after.bytes[cls], before.bytes[cls]);
+}
+
+#[test]
+fn test_profile_writes_massif_format() {
+    let _serial = SERIAL.lock().unwrap();
+    let cls = size_class::layout_to_class(SIZE, 8);
+
+    lifetime::set_sample_interval(1);
+    let mut profile = Profile::new();
+    let mut live = Vec::new();
+    for _ in 0..12 {
+        live.push(alloc_many(20));
+        profile.snapshot();
+    }
+    for ptrs in live.drain(..6) {
+        free_all(ptrs);
+    }
+    profile.snapshot();
+    for ptrs in live {
+        free_all(ptrs);
+    }
+    lifetime::set_sample_interval(1024);
+
+    let snaps = profile.snapshots();
+    assert_eq!(profile.peak(), Some(11));
+    assert!(snaps.windows(2).all(|w| w[0].time_ms <= w[1].time_ms));
+
+    let mut out = Vec::new();
+    profile.write(&mut out, "massif-test").unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(lines[0].starts_with("desc: "));
+    assert_eq!(lines[1], "cmd: massif-test");
+    assert_eq!(lines[2], "time_unit: ms");
+    assert_eq!(text.matches("\nsnapshot=").count(), 13);
+    assert_eq!(text.matches("heap_tree=peak").count(), 1);
+    assert_eq!(
+        text.matches("heap_tree=detailed").count(),
+        13usize.div_ceil(DETAILED_EVERY)
+    );
+
+    // The peak's tree has our class as a child with all 240 objects.
+    let peak = text.find("heap_tree=peak").unwrap();
+    let tree: Vec<&str> = text[peak..]
+        .lines()
+        .skip(1)
+        .take_while(|l| !l.starts_with('#'))
+        .collect();
+    let root_bytes: u64 = tree[0].split(' ').nth(1).unwrap().parse().unwrap();
+    assert_eq!(root_bytes, snaps[11].heap.total());
+    let label = format!(
+        "0x0: size class {cls} ({} bytes)",
+        size_class::class_to_size(cls)
+    );
+    let child = tree.iter().find(|l| l.ends_with(&label)).unwrap();
+    let child_bytes: u64 = child
+        .trim_start()
+        .split(' ')
+        .nth(1)
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(child_bytes >= 240 * SIZE as u64);
+    let children: usize = tree[0][1..tree[0].find(':').unwrap()].parse().unwrap();
+    assert_eq!(children, tree.len() - 1);
+}
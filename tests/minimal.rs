@@ -0,0 +1,86 @@
+//! Size regression tests for the `minimal` feature.
+//!
+//! The footprint test runs with the rest of the suite. The code size test
+//! needs `cargo-bloat` and a release build, so it is ignored by default:
+//!
+//! ```text
+//! cargo install cargo-bloat
+//! cargo test --features minimal --test minimal -- --ignored
+//! ```
+
+#![cfg(feature = "minimal")]
+
+use core::mem::size_of;
+use rtmalloc::central_free_list::CentralCache;
+use rtmalloc::mid_heap::MidHeap;
+use rtmalloc::page_heap::PageHeap;
+use rtmalloc::pagemap::PageMap;
+use rtmalloc::size_class::NUM_SIZE_CLASSES;
+use rtmalloc::thread_cache::ThreadCache;
+use rtmalloc::transfer_cache::TransferCacheArray;
+use std::process::Command;
+
+/// Bytes of `.text` the rtmalloc crate may contribute to a release binary.
+const CODE_BUDGET: usize = 16 * 1024;
+
+#[test]
+fn test_static_footprint() {
+    const { assert!(NUM_SIZE_CLASSES <= 16, "too many size classes") };
+
+    // Collapsed tiers take no space at all.
+    assert_eq!(size_of::<TransferCacheArray>(), 0);
+    assert_eq!(size_of::<MidHeap>(), 0);
+
+    let budgets = [
+        ("ThreadCache", size_of::<ThreadCache>(), 1024),
+        ("PageHeap", size_of::<PageHeap>(), 1024),
+        ("CentralCache", size_of::<CentralCache>(), 4096),
+        ("PageMap", size_of::<PageMap>(), 32 * 1024),
+    ];
+    for (name, size, budget) in budgets {
+        assert!(size <= budget, "{name} is {size} bytes, budget {budget}");
+    }
+}
+
+#[test]
+#[ignore = "needs cargo-bloat and a release build"]
+fn test_code_size() {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let output = Command::new(cargo)
+        .current_dir(manifest_dir)
+        .args([
+            "bloat",
+            "--release",
+            "--example",
+            "demo",
+            "--features",
+            "minimal",
+        ])
+        .args(["--crates", "-n", "0", "--message-format", "json"])
+        // Separate target dir: the outer `cargo test` holds the build lock.
+        .args(["--target-dir", "target/bloat"])
+        .output()
+        .expect("failed to run cargo bloat");
+    assert!(
+        output.status.success(),
+        "cargo bloat failed (is cargo-bloat installed?):\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let json = String::from_utf8(output.stdout).unwrap();
+    let key = r#""name":"rtmalloc","size":"#;
+    let start = json
+        .find(key)
+        .expect("rtmalloc missing from cargo bloat output")
+        + key.len();
+    let size: usize = json[start..]
+        .split(|c: char| !c.is_ascii_digit())
+        .next()
+        .and_then(|n| n.parse().ok())
+        .expect("unparseable crate size");
+    assert!(
+        size <= CODE_BUDGET,
+        "rtmalloc is {size} bytes of code, budget {CODE_BUDGET}"
+    );
+}
@@ -1,13 +1,15 @@
 //! Multi-threaded integration test.
 
-use rtmalloc::RtMalloc;
+mod common;
+
 use std::sync::Arc;
 
 #[global_allocator]
-static GLOBAL: RtMalloc = RtMalloc;
+static GLOBAL: common::Global = common::Global;
 
 #[test]
 fn test_multithreaded_alloc() {
+    let _shadow = common::ShadowCheck;
     let num_threads = 8;
     let iterations = 1000;
 
@@ -35,6 +37,7 @@ fn test_multithreaded_alloc() {
 
 #[test]
 fn test_cross_thread_free() {
+    let _shadow = common::ShadowCheck;
     // Allocate on one thread, free on another
     let num_threads = 4;
     let items_per_thread = 500;
@@ -74,8 +77,33 @@ fn test_cross_thread_free() {
     assert_eq!(total, num_threads * items_per_thread);
 }
 
+#[test]
+fn test_free_only_threads() {
+    let _shadow = common::ShadowCheck;
+    // Threads whose only allocator work is freeing objects from elsewhere.
+    // Their frees go through the transfer cache; the objects must be
+    // reusable afterwards.
+    let batches: Vec<Vec<Box<[u64; 4]>>> = (0..8)
+        .map(|t| (0..1000).map(|i| Box::new([t * 1000 + i; 4])).collect())
+        .collect();
+
+    let handles: Vec<_> = batches
+        .into_iter()
+        .map(|batch| std::thread::spawn(move || drop(batch)))
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+
+    let items: Vec<Box<[u64; 4]>> = (0..8000).map(|i| Box::new([i; 4])).collect();
+    for (i, item) in items.iter().enumerate() {
+        assert_eq!(**item, [i as u64; 4]);
+    }
+}
+
 #[test]
 fn test_arc_shared() {
+    let _shadow = common::ShadowCheck;
     let data = Arc::new(vec![1u64, 2, 3, 4, 5]);
     let handles: Vec<_> = (0..8)
         .map(|_| {
@@ -94,6 +122,7 @@ fn test_arc_shared() {
 
 #[test]
 fn test_mixed_sizes_multithreaded() {
+    let _shadow = common::ShadowCheck;
     let handles: Vec<_> = (0..4)
         .map(|_| {
             std::thread::spawn(|| {
@@ -118,3 +147,40 @@ fn test_mixed_sizes_multithreaded() {
         h.join().unwrap();
     }
 }
+
+#[test]
+fn test_task_migrated_hint() {
+    let _shadow = common::ShadowCheck;
+    // Simulate a work-stealing handoff: a worker warms its cache, its task
+    // moves to another worker, and the first one hints before going idle.
+    let (tx, rx) = std::sync::mpsc::channel::<Vec<Box<[u8; 48]>>>();
+
+    let origin = std::thread::spawn(move || {
+        for round in 0..20u8 {
+            let boxes: Vec<Box<[u8; 48]>> = (0..500).map(|_| Box::new([round; 48])).collect();
+            let scratch: Vec<Vec<u32>> = (0..200).map(|i| vec![i; 16]).collect();
+            drop(scratch);
+            tx.send(boxes).unwrap();
+            rtmalloc::hint::task_migrated();
+        }
+        // The cache keeps working after donating.
+        let v: Vec<u64> = (0..1000).collect();
+        assert_eq!(v.iter().sum::<u64>(), 999 * 1000 / 2);
+    });
+
+    let thief = std::thread::spawn(move || {
+        let mut seen = 0;
+        for (round, boxes) in rx.into_iter().enumerate() {
+            assert!(boxes.iter().all(|b| b.iter().all(|&x| x == round as u8)));
+            let fresh: Vec<Box<[u8; 48]>> = (0..500).map(|_| Box::new([0xAA; 48])).collect();
+            assert!(fresh.iter().all(|b| b[47] == 0xAA));
+            seen += 1;
+        }
+        seen
+    });
+
+    origin.join().unwrap();
+    assert_eq!(thief.join().unwrap(), 20);
+    // Calling it on a thread without a warm cache is harmless too.
+    rtmalloc::hint::task_migrated();
+}
@@ -0,0 +1,101 @@
+//! Heap growth under a hard address space limit, standing in for a kernel
+//! with overcommit disabled: refused mappings are retried smaller, and the
+//! allocation that finally fails leaves `ENOMEM` in `failure::last_error`.
+//!
+//! Run with: cargo test --test overcommit
+
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use rtmalloc::RtMalloc;
+use rtmalloc::failure;
+use rtmalloc::page_heap::{self, GrowthEvent};
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+const CHILD_ENV: &str = "RTMALLOC_OVERCOMMIT_CHILD";
+
+const RLIMIT_AS: i32 = 9;
+
+#[repr(C)]
+struct Rlimit {
+    cur: u64,
+    max: u64,
+}
+
+unsafe extern "C" {
+    fn getrlimit(resource: i32, rlim: *mut Rlimit) -> i32;
+    fn setrlimit(resource: i32, rlim: *const Rlimit) -> i32;
+}
+
+/// Growth the page heap made while the hook was set that was neither its
+/// usual 1 MiB nor just the 128 KiB the span needed.
+static HALVED_GROWTH: AtomicIsize = AtomicIsize::new(0);
+
+extern "C" fn record_growth(event: GrowthEvent) {
+    if event.delta > 128 << 10 && event.delta < 1 << 20 {
+        HALVED_GROWTH.store(event.delta, Ordering::Relaxed);
+    }
+}
+
+/// Bytes of address space the process has mapped.
+fn mapped_bytes() -> u64 {
+    let statm = std::fs::read_to_string("/proc/self/statm").unwrap();
+    let pages: u64 = statm.split_whitespace().next().unwrap().parse().unwrap();
+    pages * 4096
+}
+
+/// Body of the child process; a no-op when run directly.
+#[test]
+fn child_grow_to_limit() {
+    if std::env::var_os(CHILD_ENV).is_none() {
+        return;
+    }
+    let layout = Layout::from_size_align(100 << 10, 8).unwrap();
+    let mut kept = Vec::with_capacity(1024);
+    let mut old = Rlimit { cur: 0, max: 0 };
+    unsafe {
+        assert_eq!(getrlimit(RLIMIT_AS, &mut old), 0);
+        let limit = Rlimit {
+            cur: mapped_bytes() + (3 << 19),
+            max: old.max,
+        };
+        failure::clear_last_error();
+        page_heap::set_growth_hook(Some(record_growth));
+        assert_eq!(setrlimit(RLIMIT_AS, &limit), 0);
+        let mut failed = false;
+        while kept.len() < kept.capacity() {
+            let p = GLOBAL.alloc(layout);
+            if p.is_null() {
+                failed = true;
+                break;
+            }
+            kept.push(p);
+        }
+        assert_eq!(setrlimit(RLIMIT_AS, &old), 0);
+        page_heap::set_growth_hook(None);
+        assert!(failed, "limit never reached");
+    }
+    assert_eq!(failure::last_error(), failure::ENOMEM);
+    // 100 KiB takes a 128 KiB span; 1 MiB was refused before 256 or
+    // 512 KiB were mapped.
+    assert_ne!(HALVED_GROWTH.load(Ordering::Relaxed), 0);
+    for p in kept {
+        unsafe { GLOBAL.dealloc(p, layout) };
+    }
+    println!("CHILD OK");
+}
+
+#[test]
+fn test_growth_retries_smaller_under_limit() {
+    let out = common::run_child("child_grow_to_limit", CHILD_ENV);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        out.status.success() && stdout.contains("CHILD OK"),
+        "{out:?}"
+    );
+}
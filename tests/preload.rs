@@ -0,0 +1,147 @@
+//! Smoke test of the `LD_PRELOAD` library: builds the `rtmalloc_preload`
+//! example, checks what it exports and links, then runs coreutils under it.
+//!
+//! Run with: cargo test --features preload --test preload
+
+#![cfg(all(feature = "preload", target_os = "linux"))]
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::OnceLock;
+
+const ROOT: &str = env!("CARGO_MANIFEST_DIR");
+
+/// Exports of `c_abi` besides the `rtmalloc_*` functions.
+const MALLOC_FAMILY: &[&str] = &[
+    "aligned_alloc",
+    "calloc",
+    "free",
+    "mallinfo",
+    "mallinfo2",
+    "malloc",
+    "malloc_usable_size",
+    "memalign",
+    "posix_memalign",
+    "pvalloc",
+    "realloc",
+    "valloc",
+];
+
+/// Build the library once for all tests.
+fn library() -> &'static Path {
+    static LIB: OnceLock<PathBuf> = OnceLock::new();
+    LIB.get_or_init(|| {
+        let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+        let target_dir = Path::new(ROOT).join("target/preload");
+        let output = Command::new(cargo)
+            .current_dir(ROOT)
+            .args(["build", "--example", "rtmalloc_preload"])
+            .args(["--features", "preload"])
+            // Separate target dir: the outer `cargo test` holds the build lock.
+            .arg("--target-dir")
+            .arg(&target_dir)
+            .output()
+            .expect("failed to run cargo build");
+        assert!(
+            output.status.success(),
+            "cargo build failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        target_dir.join("debug/examples/librtmalloc_preload.so")
+    })
+}
+
+fn run(tool: &str, args: &[&str]) -> String {
+    let output = Command::new(tool)
+        .args(args)
+        .output()
+        .unwrap_or_else(|e| panic!("failed to run {tool}: {e}"));
+    assert!(output.status.success(), "{tool} failed: {output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Run `cmd` with `stdin`, with or without the library preloaded.
+fn coreutil(cmd: &[&str], stdin: &[u8], preload: bool) -> Output {
+    let mut command = Command::new(cmd[0]);
+    command
+        .args(&cmd[1..])
+        .current_dir(ROOT)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if preload {
+        command.env("LD_PRELOAD", library());
+    }
+    let mut child = command.spawn().unwrap_or_else(|e| panic!("{cmd:?}: {e}"));
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_exports_only_malloc_family() {
+    let lib = library().to_str().unwrap();
+    let symbols = run("nm", &["-D", "--defined-only", "--format=posix", lib]);
+    let exported: Vec<&str> = symbols
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+    for name in &exported {
+        assert!(
+            MALLOC_FAMILY.contains(name) || name.starts_with("rtmalloc_"),
+            "unexpected export {name}"
+        );
+    }
+    for name in MALLOC_FAMILY
+        .iter()
+        .chain(&["rtmalloc_alloc", "rtmalloc_version"])
+    {
+        assert!(exported.contains(name), "{name} not exported");
+    }
+}
+
+#[test]
+fn test_no_dynamic_std() {
+    let dynamic = run("readelf", &["-d", library().to_str().unwrap()]);
+    let needed: Vec<&str> = dynamic.lines().filter(|l| l.contains("(NEEDED)")).collect();
+    assert!(!needed.is_empty(), "{dynamic}");
+    for line in needed {
+        assert!(!line.contains("libstd"), "{line}");
+    }
+}
+
+#[test]
+fn test_library_is_loaded() {
+    let output = coreutil(&["cat", "/proc/self/maps"], b"", true);
+    assert!(output.status.success(), "{output:?}");
+    let maps = String::from_utf8_lossy(&output.stdout);
+    assert!(maps.contains("librtmalloc_preload.so"), "{maps}");
+}
+
+#[test]
+fn test_coreutils() {
+    let lines: String = (0..20_000)
+        .map(|i| format!("{}\n", i * 7919 % 10_007))
+        .collect();
+    let commands: &[(&[&str], &[u8])] = &[
+        (&["ls", "-l", "src", "tests"], b""),
+        (&["cat", "Cargo.toml", "README.md"], b""),
+        (&["sort", "-n", "-r"], lines.as_bytes()),
+        (&["sort", "-u"], lines.as_bytes()),
+        (&["wc", "README.md", "Cargo.toml"], b""),
+        (&["sha256sum", "README.md"], b""),
+        (&["du", "-s", "src"], b""),
+        (&["tr", "0-9", "a-j"], lines.as_bytes()),
+    ];
+    for &(cmd, stdin) in commands {
+        let expected = coreutil(cmd, stdin, false);
+        let output = coreutil(cmd, stdin, true);
+        assert!(output.status.success(), "{cmd:?}: {output:?}");
+        assert!(output.stderr.is_empty(), "{cmd:?}: {output:?}");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&expected.stdout),
+            "{cmd:?}"
+        );
+    }
+}
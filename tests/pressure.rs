@@ -0,0 +1,98 @@
+//! Memory pressure monitor, driven by fake PSI and `memory.events` files.
+//!
+//! Run with: cargo test --features pressure --test pressure
+
+#![cfg(all(feature = "pressure", target_os = "linux"))]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::pressure::{self, Config, Monitor};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+/// Reliefs release the whole process's free memory; tests that count what
+/// theirs released run one at a time.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rtmalloc-{}-{name}", std::process::id()));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn config(psi: Option<PathBuf>, events: Option<PathBuf>, min_interval: Duration) -> Config {
+    Config {
+        psi,
+        events,
+        stall_ratio: 0.1,
+        poll_interval: Duration::from_millis(5),
+        min_interval,
+    }
+}
+
+/// Wait up to five seconds for the monitor to have relieved `n` times.
+fn wait_for_reliefs(monitor: &Monitor, n: u64) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while monitor.reliefs() < n {
+        assert!(Instant::now() < deadline, "{} reliefs", monitor.reliefs());
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn test_events_trigger_release() {
+    let _serial = SERIAL.lock().unwrap();
+    let events = temp_file("events", "low 0\nhigh 0\nmax 0\noom 0\n");
+    let monitor = pressure::start(config(None, Some(events.clone()), Duration::ZERO)).unwrap();
+
+    let big: Vec<Vec<u8>> = (0..8).map(|_| vec![1u8; 1 << 20]).collect();
+    drop(big);
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(monitor.reliefs(), 0);
+
+    fs::write(&events, "low 0\nhigh 3\nmax 0\noom 0\n").unwrap();
+    wait_for_reliefs(&monitor, 1);
+    assert!(monitor.released_bytes() >= 8 << 20);
+
+    monitor.stop();
+    fs::remove_file(events).unwrap();
+}
+
+#[test]
+fn test_psi_stall_triggers_once_per_interval() {
+    let _serial = SERIAL.lock().unwrap();
+    let some = |total: u64| {
+        format!(
+            "some avg10=0.00 avg60=0.00 avg300=0.00 total={total}\n\
+             full avg10=0.00 avg60=0.00 avg300=0.00 total=0\n"
+        )
+    };
+    let psi = temp_file("psi", &some(0));
+    let monitor =
+        pressure::start(config(Some(psi.clone()), None, Duration::from_secs(3600))).unwrap();
+
+    // Stalled for a minute: far over 10% of any poll interval.
+    fs::write(&psi, some(60_000_000)).unwrap();
+    wait_for_reliefs(&monitor, 1);
+
+    // Still stalling, but inside the rate limit.
+    fs::write(&psi, some(120_000_000)).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(monitor.reliefs(), 1);
+
+    monitor.stop();
+    fs::remove_file(psi).unwrap();
+}
+
+#[test]
+fn test_start_needs_a_source() {
+    let missing = std::env::temp_dir().join("rtmalloc-no-such-pressure-file");
+    let err = pressure::start(config(Some(missing), None, Duration::ZERO))
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+}
@@ -0,0 +1,113 @@
+//! Integration tests for heap snapshot diffs.
+//!
+//! Run with: cargo test --features lifetime-histogram --test profile
+
+#![cfg(feature = "lifetime-histogram")]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::lifetime;
+use rtmalloc::profile;
+use rtmalloc::size_class;
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::Mutex;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+/// The sample interval is process-wide; tests that change it run one at a
+/// time.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+const SIZE: usize = 1500;
+
+fn layout() -> Layout {
+    Layout::from_size_align(SIZE, 8).unwrap()
+}
+
+fn alloc_many(n: usize) -> Vec<*mut u8> {
+    (0..n)
+        .map(|_| unsafe { RtMalloc.alloc(layout()) })
+        .collect()
+}
+
+fn free_all(ptrs: Vec<*mut u8>) {
+    for p in ptrs {
+        unsafe { RtMalloc.dealloc(p, layout()) };
+    }
+}
+
+#[test]
+fn test_diff_finds_objects_not_freed() {
+    let _serial = SERIAL.lock().unwrap();
+    let cls = size_class::layout_to_class(SIZE, 8);
+
+    lifetime::set_sample_interval(1);
+    let before = profile::snapshot();
+    let freed = alloc_many(50);
+    let leaked = alloc_many(30);
+    free_all(freed);
+    let after = profile::snapshot();
+    lifetime::set_sample_interval(1024);
+
+    let diff = profile::diff(&before, &after);
+    for &p in &leaked {
+        assert!(diff.samples.iter().any(|s| s.addr == p.addr()), "{p:p}");
+    }
+    assert!(diff.classes[cls].samples >= 30);
+    assert!(diff.classes[cls].bytes >= 30 * SIZE as u64);
+    assert!(
+        diff.samples
+            .windows(2)
+            .all(|w| w[0].born_ns <= w[1].born_ns)
+    );
+
+    // Nothing new between two snapshots with no allocations in between.
+    let again = profile::snapshot();
+    assert_eq!(profile::diff(&after, &again).classes[cls].samples, 0);
+    free_all(leaked);
+}
+
+#[test]
+fn test_reused_address_is_new() {
+    let _serial = SERIAL.lock().unwrap();
+
+    lifetime::set_sample_interval(1);
+    let p = unsafe { RtMalloc.alloc(layout()) };
+    let before = profile::snapshot();
+    unsafe { RtMalloc.dealloc(p, layout()) };
+    let q = unsafe { RtMalloc.alloc(layout()) };
+    let after = profile::snapshot();
+    lifetime::set_sample_interval(1024);
+
+    let diff = profile::diff(&before, &after);
+    assert!(diff.samples.iter().any(|s| s.addr == q.addr()));
+    unsafe { RtMalloc.dealloc(q, layout()) };
+}
+
+#[test]
+fn test_report_lists_growing_class() {
+    let _serial = SERIAL.lock().unwrap();
+    let cls = size_class::layout_to_class(SIZE, 8);
+
+    lifetime::set_sample_interval(1);
+    let before = profile::snapshot();
+    let leaked = alloc_many(20);
+    let after = profile::snapshot();
+    lifetime::set_sample_interval(1024);
+
+    let mut out = String::new();
+    profile::diff(&before, &after)
+        .write_report(&mut out)
+        .unwrap();
+    assert!(out.contains("New allocations not freed"), "{out}");
+    let class_size = size_class::class_to_size(cls).to_string();
+    assert!(
+        out.lines().any(|l| {
+            let cols: Vec<&str> = l.split_whitespace().collect();
+            cols.len() == 5 && cols[0] == cls.to_string() && cols[1] == class_size
+        }),
+        "{out}"
+    );
+    assert!(out.contains("Oldest new samples"), "{out}");
+    free_all(leaked);
+}
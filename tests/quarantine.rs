@@ -0,0 +1,107 @@
+//! Span quarantine: a freed large allocation stays inaccessible for the
+//! window, so a use after free faults, and its pages are not handed out
+//! again until newer frees push it out.
+//!
+//! Run with: cargo test --features span-quarantine,std --test quarantine
+
+#![cfg(all(feature = "span-quarantine", feature = "std", unix))]
+
+mod common;
+
+use rtmalloc::RtMalloc;
+use rtmalloc::quarantine;
+use rtmalloc::size_class::MAX_SMALL_SIZE;
+use std::alloc::{GlobalAlloc, Layout};
+use std::os::unix::process::ExitStatusExt;
+use std::sync::Mutex;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+/// The window is process-wide; tests that free large allocations run one
+/// at a time.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+const CHILD_ENV: &str = "RTMALLOC_QUARANTINE_CHILD";
+
+const SIGSEGV: i32 = 11;
+
+/// Past the small classes, so every allocation has a span of its own.
+fn layout() -> Layout {
+    Layout::from_size_align(MAX_SMALL_SIZE + 1, 8).unwrap()
+}
+
+/// Body of the child process; a no-op when run directly.
+#[test]
+fn child_use_after_free() {
+    if std::env::var_os(CHILD_ENV).is_none() {
+        return;
+    }
+    let p = unsafe { GLOBAL.alloc(layout()) };
+    unsafe {
+        p.write_bytes(7, layout().size());
+        GLOBAL.dealloc(p, layout());
+    }
+    println!("READING {p:p}");
+    let byte = unsafe { p.add(100).read_volatile() };
+    println!("CHILD SURVIVED {byte}");
+}
+
+#[test]
+fn test_use_after_free_faults() {
+    let _serial = SERIAL.lock().unwrap();
+    let out = common::run_child("child_use_after_free", CHILD_ENV);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("READING"), "{out:?}");
+    assert!(!stdout.contains("CHILD SURVIVED"), "{out:?}");
+    assert_eq!(out.status.signal(), Some(SIGSEGV), "{out:?}");
+}
+
+#[test]
+fn test_freed_spans_not_reused_within_window() {
+    let _serial = SERIAL.lock().unwrap();
+    quarantine::set_window(8);
+    quarantine::flush();
+
+    let first = unsafe { GLOBAL.alloc(layout()) };
+    unsafe { GLOBAL.dealloc(first, layout()) };
+    assert_eq!(quarantine::held(), 1);
+    // Seven more frees fill the window without releasing the first.
+    for _ in 0..7 {
+        let p = unsafe { GLOBAL.alloc(layout()) };
+        assert_ne!(p, first);
+        unsafe { GLOBAL.dealloc(p, layout()) };
+    }
+    assert_eq!(quarantine::held(), 8);
+    // The ninth pushes it out.
+    let p = unsafe { GLOBAL.alloc(layout()) };
+    unsafe { GLOBAL.dealloc(p, layout()) };
+    assert_eq!(quarantine::held(), 8);
+
+    quarantine::flush();
+    assert_eq!(quarantine::held(), 0);
+    quarantine::set_window(256);
+}
+
+#[test]
+fn test_released_spans_are_usable() {
+    let _serial = SERIAL.lock().unwrap();
+    quarantine::set_window(4);
+    let ptrs: Vec<*mut u8> = (0..32).map(|_| unsafe { GLOBAL.alloc(layout()) }).collect();
+    for &p in &ptrs {
+        unsafe { GLOBAL.dealloc(p, layout()) };
+    }
+    assert_eq!(quarantine::held(), 4);
+    // Spans out of the quarantine are writable again when handed out.
+    quarantine::set_window(0);
+    assert_eq!(quarantine::held(), 0);
+    for _ in 0..32 {
+        let p = unsafe { GLOBAL.alloc(layout()) };
+        unsafe {
+            p.write_bytes(1, layout().size());
+            GLOBAL.dealloc(p, layout());
+        }
+    }
+    assert_eq!(quarantine::held(), 0);
+    quarantine::set_window(256);
+}
@@ -0,0 +1,171 @@
+//! Realloc transitions: within a size class, across classes, between small
+//! and large, to and from zero, and over-aligned, with the in-place and
+//! moved counters when `stats` is on.
+//!
+//! Run with: cargo test --test realloc
+//! With the counters: cargo test --features stats --test realloc
+
+mod common;
+
+use rtmalloc::size_class::{self, MAX_SMALL_SIZE};
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::Mutex;
+
+#[global_allocator]
+static GLOBAL: common::Global = common::Global;
+
+/// The stats counters are process-wide; reallocs run one test at a time so
+/// each test sees only its own.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// `layout-check` only keeps a realloc in place within the object's class.
+const SHRINK_IN_PLACE: bool = !cfg!(feature = "layout-check");
+
+fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+}
+
+/// Allocate `size` bytes filled with a pattern of their offsets.
+unsafe fn alloc_filled(size: usize, align: usize) -> *mut u8 {
+    let p = unsafe { GLOBAL.alloc(layout(size, align)) };
+    assert!(!p.is_null());
+    for i in 0..size {
+        unsafe { p.add(i).write(i as u8) };
+    }
+    p
+}
+
+/// Realloc and check the first `keep` bytes came along.
+unsafe fn realloc_checked(p: *mut u8, old: Layout, new_size: usize, keep: usize) -> *mut u8 {
+    let q = unsafe { GLOBAL.realloc(p, old, new_size) };
+    assert!(!q.is_null(), "{} -> {new_size}", old.size());
+    assert_eq!(q.addr() % old.align(), 0, "{} -> {new_size}", old.size());
+    for i in 0..keep {
+        assert_eq!(unsafe { q.add(i).read() }, i as u8, "byte {i}");
+    }
+    q
+}
+
+#[test]
+fn test_same_class() {
+    let _shadow = common::ShadowCheck;
+    let _serial = SERIAL.lock().unwrap();
+    let class_size = size_class::class_to_size(size_class::layout_to_class(100, 8));
+    unsafe {
+        let p = alloc_filled(100, 8);
+        let q = realloc_checked(p, layout(100, 8), class_size, 100);
+        assert_eq!(q, p, "grow within the class");
+        let r = realloc_checked(q, layout(class_size, 8), 97, 97);
+        assert_eq!(r, p, "shrink within the class");
+        GLOBAL.dealloc(r, layout(97, 8));
+    }
+}
+
+#[test]
+fn test_cross_class() {
+    let _shadow = common::ShadowCheck;
+    let _serial = SERIAL.lock().unwrap();
+    unsafe {
+        let p = alloc_filled(100, 8);
+        let q = realloc_checked(p, layout(100, 8), 1000, 100);
+        assert_ne!(q, p, "grow past the class");
+        let r = realloc_checked(q, layout(1000, 8), 16, 16);
+        assert_eq!(r == q, SHRINK_IN_PLACE, "shrink to a smaller class");
+        GLOBAL.dealloc(r, layout(16, 8));
+    }
+}
+
+#[test]
+fn test_small_to_large() {
+    let _shadow = common::ShadowCheck;
+    let _serial = SERIAL.lock().unwrap();
+    let large = MAX_SMALL_SIZE + 1;
+    unsafe {
+        let p = alloc_filled(64, 8);
+        let q = realloc_checked(p, layout(64, 8), large, 64);
+        assert_ne!(q, p);
+        let r = realloc_checked(q, layout(large, 8), 4 << 20, 64);
+        GLOBAL.dealloc(r, layout(4 << 20, 8));
+    }
+}
+
+#[test]
+fn test_large_to_small() {
+    let _shadow = common::ShadowCheck;
+    let _serial = SERIAL.lock().unwrap();
+    let large = 1 << 20;
+    unsafe {
+        let p = alloc_filled(large, 8);
+        let q = realloc_checked(p, layout(large, 8), 64, 64);
+        assert_eq!(q == p, SHRINK_IN_PLACE);
+        // Back up past the trimmed span moves.
+        let r = realloc_checked(q, layout(64, 8), large, 64);
+        GLOBAL.dealloc(r, layout(large, 8));
+    }
+}
+
+#[test]
+fn test_zero_sizes() {
+    let _shadow = common::ShadowCheck;
+    let _serial = SERIAL.lock().unwrap();
+    unsafe {
+        // To zero frees and hands back a dangling, aligned pointer.
+        for (size, align) in [(64, 8), (MAX_SMALL_SIZE + 1, 8), (48, 64)] {
+            let p = alloc_filled(size, align);
+            let q = GLOBAL.realloc(p, layout(size, align), 0);
+            assert_eq!(q.addr(), align);
+        }
+        // From a zero-size or null allocation is a fresh one.
+        let dangling = std::ptr::without_provenance_mut::<u8>(8);
+        let p = GLOBAL.realloc(dangling, layout(0, 8), 200);
+        assert!(!p.is_null());
+        p.write_bytes(0xEE, 200);
+        GLOBAL.dealloc(p, layout(200, 8));
+        let q = GLOBAL.realloc(std::ptr::null_mut(), layout(16, 16), 32);
+        assert!(!q.is_null());
+        assert_eq!(q.addr() % 16, 0);
+        GLOBAL.dealloc(q, layout(32, 16));
+    }
+}
+
+#[test]
+fn test_over_aligned() {
+    let _shadow = common::ShadowCheck;
+    let _serial = SERIAL.lock().unwrap();
+    let large = MAX_SMALL_SIZE + 1;
+    for align in [16, 64, 512, 4096, 1 << 16] {
+        unsafe {
+            let p = alloc_filled(48, align);
+            let q = realloc_checked(p, layout(48, align), 200, 48);
+            let r = realloc_checked(q, layout(200, align), large, 48);
+            let s = realloc_checked(r, layout(large, align), 100, 48);
+            GLOBAL.dealloc(s, layout(100, align));
+        }
+    }
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_in_place_and_moved_counters() {
+    let _shadow = common::ShadowCheck;
+    use rtmalloc::stats;
+
+    let _serial = SERIAL.lock().unwrap();
+    let before = stats::snapshot();
+    unsafe {
+        // In place: the class has room.
+        let p = alloc_filled(100, 8);
+        let p = realloc_checked(p, layout(100, 8), 104, 100);
+        // Moved: copied into a bigger class, then into a span.
+        let p = realloc_checked(p, layout(104, 8), 5000, 100);
+        let p = realloc_checked(p, layout(5000, 8), 1 << 20, 100);
+        GLOBAL.dealloc(p, layout(1 << 20, 8));
+    }
+    let after = stats::snapshot();
+    let in_place = after.realloc_in_place - before.realloc_in_place;
+    let moved = after.realloc_moved - before.realloc_moved;
+    let copied = after.realloc_copy_bytes - before.realloc_copy_bytes;
+    assert!(in_place >= 1, "{in_place}");
+    assert!(moved >= 2, "{moved}");
+    assert!(copied >= 104 + 5000, "{copied}");
+}
@@ -0,0 +1,84 @@
+//! The shared workloads under the shadow allocator, which checks every free
+//! against its own record of what was allocated.
+//!
+//! Run with: cargo test --features testing,std --test shadow
+
+#![cfg(all(feature = "testing", feature = "std"))]
+
+mod common;
+
+use rtmalloc::shadow::{self, ShadowMalloc};
+use std::alloc::{GlobalAlloc, Layout};
+
+#[global_allocator]
+static GLOBAL: ShadowMalloc = ShadowMalloc;
+
+/// Run `f` and fail if the shadow record caught anything.
+fn checked(f: impl FnOnce()) {
+    f();
+    assert_eq!(shadow::take_violations(), []);
+}
+
+#[test]
+fn test_thread_churn() {
+    checked(common::thread_churn);
+}
+
+#[test]
+fn test_rc_cycles() {
+    checked(common::rc_cycles);
+}
+
+#[test]
+fn test_arc_cycles() {
+    checked(common::arc_cycles);
+}
+
+#[test]
+fn test_hashmap_churn() {
+    checked(common::hashmap_churn);
+}
+
+#[test]
+fn test_format_heavy() {
+    checked(common::format_heavy);
+}
+
+#[test]
+fn test_tls_destructors() {
+    checked(common::tls_destructors);
+}
+
+#[test]
+fn test_size_and_alignment_sweep() {
+    checked(|| {
+        let mut live = Vec::new();
+        for size in (1..=300_000).step_by(997) {
+            for align in [1, 8, 64, 4096, 65536] {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let ptr = unsafe { GLOBAL.alloc(layout) };
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % align, 0);
+                live.push((ptr, layout));
+            }
+        }
+        for (ptr, layout) in live {
+            unsafe { GLOBAL.dealloc(ptr, layout) };
+        }
+    });
+}
+
+#[test]
+fn test_realloc_ladder() {
+    checked(|| {
+        for start in [8, 200, 5000, 300_000] {
+            let mut v: Vec<u8> = Vec::with_capacity(start);
+            for i in 0..1_000_000usize {
+                v.push(i as u8);
+            }
+            v.truncate(10);
+            v.shrink_to_fit();
+            assert_eq!(v[9], 9);
+        }
+    });
+}
@@ -0,0 +1,29 @@
+//! The shared workloads with the page map verifier running alongside.
+//!
+//! Run with: cargo test --features testing,std --test soak
+
+#![cfg(all(feature = "testing", feature = "std"))]
+
+mod common;
+
+use rtmalloc::RtMalloc;
+use rtmalloc::soak;
+use std::time::Duration;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+#[test]
+fn test_workloads_under_verifier() {
+    let verifier = soak::start(Duration::from_millis(1), 64);
+    common::thread_churn();
+    common::hashmap_churn();
+    common::arc_cycles();
+    let summary = verifier.stop();
+    assert!(summary.passes > 0);
+    assert_eq!(summary.discrepancies, []);
+
+    let pass = soak::verify(usize::MAX);
+    assert!(pass.spans > 0);
+    assert_eq!(pass.discrepancies, []);
+}
@@ -0,0 +1,134 @@
+//! Cache tier counters.
+//!
+//! Run with: cargo test --features stats,std --test stats
+//! With the C report callbacks: cargo test --features stats,std,ffi --test stats
+
+#![cfg(all(
+    feature = "stats",
+    any(feature = "nightly", feature = "std", feature = "percpu")
+))]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::stats;
+use std::hint::black_box;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+#[test]
+fn test_tier_counters_add_up() {
+    let before = stats::snapshot();
+    std::thread::spawn(|| {
+        for _ in 0..10 {
+            let v: Vec<Box<[u8; 48]>> = (0..1000).map(|_| Box::new([0u8; 48])).collect();
+            black_box(v);
+        }
+    })
+    .join()
+    .unwrap();
+    let after = stats::snapshot();
+
+    let hits = after.thread_cache_hits - before.thread_cache_hits;
+    let misses = after.thread_cache_misses - before.thread_cache_misses;
+    let tiers = (after.transfer_cache_hits - before.transfer_cache_hits)
+        + (after.central_cache_hits - before.central_cache_hits);
+    assert!(misses > 0 && hits > misses, "{hits} hits, {misses} misses");
+    // Allocations racing the snapshots may count on one side only.
+    assert!(
+        tiers.abs_diff(misses) <= 16,
+        "{tiers} refills, {misses} misses"
+    );
+    // The second round onwards finds the first round's objects.
+    assert!(after.transfer_cache_hits > before.transfer_cache_hits);
+}
+
+fn decode(frame: &[u8]) -> Vec<u8> {
+    rtmalloc::count_site!("test.decode", frame.len());
+    frame.to_vec()
+}
+
+#[test]
+fn test_count_site_sums_label() {
+    assert_eq!(stats::site("test.decode"), stats::SiteCount::default());
+    for len in [10, 20, 30] {
+        black_box(decode(&vec![0u8; len]));
+    }
+    // A second site with the same label adds to the first.
+    rtmalloc::count_site!("test.decode");
+    rtmalloc::count_site!("test.other", 7);
+
+    let decode = stats::site("test.decode");
+    assert_eq!((decode.count, decode.bytes), (4, 60));
+    assert_eq!(stats::site("test.other").bytes, 7);
+
+    let mut listed = Vec::new();
+    stats::for_each_site(|label, total| {
+        if label.starts_with("test.") {
+            listed.push((label, total.count));
+        }
+    });
+    listed.sort();
+    assert_eq!(listed, [("test.decode", 4), ("test.other", 1)]);
+}
+
+/// Value of the `name value` line for `name` in a text report.
+fn value(report: &str, name: &str) -> u64 {
+    report
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no {name} in report"))
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn test_text_reports() {
+    rtmalloc::count_site!("test.report", 3);
+    black_box(Box::new([0u8; 100]));
+
+    let mut report = String::new();
+    stats::write_report(&mut report).unwrap();
+    for name in stats::COUNTER_NAMES {
+        value(&report, name);
+    }
+    assert!(value(&report, "alloc_count") > 0);
+    assert!(value(&report, "system_bytes") > 0);
+    assert_eq!(value(&report, "site.test.report.bytes"), 3);
+
+    let mut classes = String::new();
+    stats::write_class_report(&mut classes).unwrap();
+    assert_eq!(
+        classes.lines().count(),
+        rtmalloc::size_class::num_classes() + 1
+    );
+    let first: Vec<&str> = classes.lines().nth(1).unwrap().split_whitespace().collect();
+    assert_eq!(first[0], "1");
+    assert_eq!(first[1], rtmalloc::size_class::class_to_size(1).to_string());
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn test_reports_through_c_callback() {
+    use rtmalloc::ffi::{CallbackWriter, rtmalloc_write_class_report, rtmalloc_write_stats};
+    use std::ffi::{c_char, c_void};
+
+    extern "C" fn collect(ctx: *mut c_void, text: *const c_char, len: usize) {
+        assert!(len <= CallbackWriter::CAPACITY);
+        let out = unsafe { &mut *ctx.cast::<Vec<u8>>() };
+        out.extend_from_slice(unsafe { std::slice::from_raw_parts(text.cast(), len) });
+    }
+
+    let mut bytes = Vec::new();
+    rtmalloc_write_stats(collect, (&raw mut bytes).cast());
+    let report = String::from_utf8(bytes).unwrap();
+    assert!(report.len() > CallbackWriter::CAPACITY);
+    assert!(value(&report, "alloc_count") > 0);
+
+    let mut bytes = Vec::new();
+    rtmalloc_write_class_report(collect, (&raw mut bytes).cast());
+    let classes = String::from_utf8(bytes).unwrap();
+    assert_eq!(
+        classes.lines().count(),
+        rtmalloc::size_class::num_classes() + 1
+    );
+}
@@ -0,0 +1,108 @@
+//! Binary stats dumps and their diffs.
+//!
+//! Run with: cargo test --features stats,std --test stats_dump
+
+#![cfg(all(feature = "stats", feature = "std"))]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::size_class::NUM_SIZE_CLASSES;
+use rtmalloc::stats;
+use rtmalloc::stats_dump;
+use std::hint::black_box;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+#[test]
+fn test_dump_round_trip_and_diff() {
+    let base = stats_dump::parse(&stats_dump::capture()).unwrap();
+    let keep: Vec<Vec<u8>> = (0..1000).map(|i| vec![1u8; 64 + i]).collect();
+    let big = black_box(vec![0u8; 16 << 20]);
+    let now = stats_dump::parse(&stats_dump::capture()).unwrap();
+
+    assert_eq!(now.version, stats::DUMP_VERSION);
+    assert_eq!(now.fingerprint, rtmalloc::version::CONFIG_FINGERPRINT);
+    let counters = now.table("counters").unwrap();
+    assert_eq!(counters.rows.len(), 1);
+    assert_eq!(counters.columns.len(), stats::COUNTER_NAMES.len() + 1);
+    let classes = now.table("classes").unwrap();
+    assert_eq!(classes.rows.len(), NUM_SIZE_CLASSES - 1);
+    assert_eq!(classes.get(0, "class"), Some(1));
+    assert!(now.get("page_heap", 0, "system_bytes").unwrap() >= 16 << 20);
+    #[cfg(feature = "latency-histogram")]
+    {
+        let latency = now.table("latency").unwrap();
+        assert_eq!(latency.rows.len(), 4);
+        assert_eq!(latency.column("b9"), Some(10));
+        assert_eq!(latency.column("b31"), Some(32));
+    }
+
+    let changes = now.diff(&base);
+    let alloc_count = changes
+        .iter()
+        .find(|c| c.table == "counters" && c.column == "alloc_count")
+        .unwrap();
+    assert!(alloc_count.delta() >= 1001, "{alloc_count}");
+    assert!(changes.iter().all(|c| c.before != c.after));
+    assert!(now.diff(&now).is_empty());
+
+    // Added or missing tables and columns compare against 0.
+    let mut trimmed = base.clone();
+    trimmed.tables.retain(|t| t.name != "page_heap");
+    trimmed.tables[0].columns.pop();
+    trimmed.tables[0].rows[0].pop();
+    let back = base.diff(&trimmed);
+    assert!(back.iter().any(|c| c.table == "page_heap" && c.before == 0));
+    drop((keep, big));
+}
+
+#[test]
+fn test_count_site_tables() {
+    rtmalloc::count_site!("dump.site", 100);
+    let base = stats_dump::parse(&stats_dump::capture()).unwrap();
+    assert_eq!(base.get("site.dump.site", 0, "count"), Some(1));
+    assert_eq!(base.get("site.dump.site", 0, "bytes"), Some(100));
+
+    for _ in 0..2 {
+        rtmalloc::count_site!("dump.site", 50);
+    }
+    const LONG: &str = "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx\
+                        xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx\
+                        xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx\
+                        xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx\
+                        xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx";
+    rtmalloc::count_site!(LONG);
+    let now = stats_dump::parse(&stats_dump::capture()).unwrap();
+    let changes = now.diff(&base);
+    let bytes = changes
+        .iter()
+        .find(|c| c.table == "site.dump.site" && c.column == "bytes")
+        .unwrap();
+    assert_eq!((bytes.before, bytes.after), (100, 200));
+    // Labels past the name limit are cut short, and new ones diff against 0.
+    let long = changes
+        .iter()
+        .find(|c| c.table.starts_with("site.xxx"))
+        .unwrap();
+    assert_eq!((long.table.len(), long.before), (255, 0));
+}
+
+#[test]
+fn test_save_and_read() {
+    let path = std::env::temp_dir().join(format!("rtmalloc-{}.rtmstats", std::process::id()));
+    stats_dump::save(&path).unwrap();
+    let dump = stats_dump::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(dump.table("counters").is_some());
+}
+
+#[test]
+fn test_parse_rejects_bad_input() {
+    let bytes = stats_dump::capture();
+    let err = stats_dump::parse(b"not a stats dump").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    for len in [4, 20, bytes.len() / 2, bytes.len() - 1] {
+        let err = stats_dump::parse(&bytes[..len]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -5,11 +5,12 @@
 //! Any corruption (use-after-free, double-free, buffer overflow) will
 //! cause a pattern mismatch and assertion failure.
 
-use rtmalloc::RtMalloc;
+mod common;
+
 use std::alloc::{GlobalAlloc, Layout};
 
 #[global_allocator]
-static GLOBAL: RtMalloc = RtMalloc;
+static GLOBAL: common::Global = common::Global;
 
 /// Fill a buffer with a deterministic pattern derived from its address and size.
 fn fill_pattern(ptr: *mut u8, size: usize) {
@@ -35,6 +36,7 @@ fn check_pattern(ptr: *mut u8, size: usize) -> bool {
 
 #[test]
 fn stress_fill_pattern_single_thread() {
+    let _shadow = common::ShadowCheck;
     let sizes: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 4096, 8192];
     let rounds = 50;
 
@@ -81,6 +83,7 @@ fn stress_fill_pattern_single_thread() {
 
 #[test]
 fn stress_fill_pattern_cross_thread() {
+    let _shadow = common::ShadowCheck;
     use std::sync::mpsc;
 
     let npairs = 4;
@@ -151,6 +154,7 @@ fn check_fixed(ptr: *mut u8, size: usize, seed: usize) -> bool {
 
 #[test]
 fn stress_realloc_pattern() {
+    let _shadow = common::ShadowCheck;
     let initial_size = 64;
     let layout = Layout::from_size_align(initial_size, 8).unwrap();
 
@@ -188,6 +192,7 @@ fn stress_realloc_pattern() {
 
 #[test]
 fn stress_many_threads_concurrent() {
+    let _shadow = common::ShadowCheck;
     // Many threads doing alloc+fill+verify+free simultaneously
     let nthreads = 8;
     let ops_per_thread = 200;
@@ -230,3 +235,44 @@ fn stress_many_threads_concurrent() {
         h.join().unwrap();
     }
 }
+
+#[test]
+fn stress_deferred_coalescing() {
+    let _shadow = common::ShadowCheck;
+    // Large frees only queue spans; allocations and flushes merge them.
+    // Patterns must survive while other threads churn the page heap.
+    rtmalloc::page_heap::defer_coalescing(true);
+
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            std::thread::spawn(move || {
+                let mut live: Vec<(*mut u8, Layout)> = Vec::new();
+                for round in 0..40usize {
+                    let size = (3 + (round * 5 + t) % 29) * 8192 + 123;
+                    let layout = Layout::from_size_align(size, 8).unwrap();
+                    let ptr = unsafe { GLOBAL.alloc(layout) };
+                    assert!(!ptr.is_null());
+                    fill_pattern(ptr, size);
+                    live.push((ptr, layout));
+                    if live.len() > 8 {
+                        let (p, l) = live.swap_remove(round % live.len());
+                        assert!(check_pattern(p, l.size()));
+                        unsafe { GLOBAL.dealloc(p, l) };
+                    }
+                    if round % 16 == 0 {
+                        rtmalloc::page_heap::flush_deferred();
+                    }
+                }
+                for (p, l) in live {
+                    assert!(check_pattern(p, l.size()));
+                    unsafe { GLOBAL.dealloc(p, l) };
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+
+    rtmalloc::page_heap::defer_coalescing(false);
+}
@@ -0,0 +1,80 @@
+//! Integration tests for the trace feature.
+//!
+//! Run with: cargo test --features trace --test trace
+
+#![cfg(feature = "trace")]
+
+use rtmalloc::RtMalloc;
+use rtmalloc::trace::{self, Op, Replay, TraceMalloc};
+use std::alloc::System;
+use std::hint::black_box;
+
+#[global_allocator]
+static GLOBAL: TraceMalloc = TraceMalloc;
+
+#[test]
+fn test_record_and_replay() {
+    let path = std::env::temp_dir().join(format!("rtmalloc-{}.trace", std::process::id()));
+    trace::start(&path).unwrap();
+    assert!(trace::is_recording());
+
+    let mut boxes: Vec<Box<[u8]>> = Vec::new();
+    for i in 0..20_000usize {
+        boxes.push(vec![i as u8; 16 + i % 2000].into_boxed_slice());
+        if i % 3 == 0 {
+            drop(boxes.swap_remove(i % boxes.len()));
+        }
+    }
+    let mut grow = Vec::new();
+    for i in 0..4096u32 {
+        grow.push(i);
+    }
+    let threads: Vec<_> = (0..4)
+        .map(|t| {
+            std::thread::spawn(move || {
+                for i in 0..1000 {
+                    black_box(vec![t as u8; 64 + i]);
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    drop(boxes);
+    drop(grow);
+
+    trace::stop().unwrap();
+    assert!(!trace::is_recording());
+    let records = trace::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // More records than the ring holds, so it was written out while full.
+    assert!(records.len() > trace::RING_LEN, "{}", records.len());
+    assert!(records.iter().any(|r| r.op == Op::Realloc));
+    let threads: std::collections::HashSet<u32> = records.iter().map(|r| r.thread).collect();
+    assert!(threads.len() >= 5, "{threads:?}");
+    for r in &records {
+        assert_ne!(r.op == Op::Realloc, r.old_ptr == 0, "{r:?}");
+    }
+
+    let replay = Replay::new(&records);
+    assert!(replay.len() > trace::RING_LEN);
+    let system = replay.run(&System);
+    let rtmalloc = replay.run(&RtMalloc);
+    assert_eq!(system.ops, replay.len());
+    assert_eq!(system.failed, 0);
+    assert_eq!(rtmalloc.ops, system.ops);
+    assert_eq!(rtmalloc.failed, 0);
+    assert_eq!(rtmalloc.peak_live_bytes, system.peak_live_bytes);
+    assert!(system.peak_live_bytes > 20_000 * 16 / 2);
+}
+
+#[test]
+fn test_read_rejects_other_files() {
+    let path = std::env::temp_dir().join(format!("rtmalloc-{}.not-trace", std::process::id()));
+    std::fs::write(&path, b"not a trace at all").unwrap();
+    let err = trace::read(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
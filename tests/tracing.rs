@@ -0,0 +1,74 @@
+//! Integration tests for the tracing feature.
+//!
+//! Run with: cargo test --features tracing --test tracing
+
+#![cfg(feature = "tracing")]
+
+use rtmalloc::RtMalloc;
+use std::alloc::{GlobalAlloc, Layout};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+/// (message, bytes) for each `rtmalloc` event.
+type Seen = Arc<Mutex<Vec<(String, u64)>>>;
+
+struct Collect(Seen);
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    bytes: u64,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == "bytes" {
+            self.bytes = value;
+        }
+    }
+}
+
+impl Subscriber for Collect {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target() == "rtmalloc"
+    }
+    fn new_span(&self, _: &Attributes<'_>) -> Id {
+        Id::from_u64(1)
+    }
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        self.0.lock().unwrap().push((fields.message, fields.bytes));
+    }
+    fn enter(&self, _: &Id) {}
+    fn exit(&self, _: &Id) {}
+}
+
+#[test]
+fn test_heap_grow_event() {
+    let seen = Seen::default();
+    tracing::subscriber::with_default(Collect(seen.clone()), || {
+        // A fresh large allocation must grow the heap.
+        let layout = Layout::from_size_align(64 << 20, 8).unwrap();
+        let ptr = unsafe { RtMalloc.alloc(layout) };
+        assert!(!ptr.is_null());
+        unsafe { RtMalloc.dealloc(ptr, layout) };
+    });
+    let seen = seen.lock().unwrap();
+    assert!(
+        seen.iter()
+            .any(|(msg, bytes)| msg == "heap grow" && *bytes >= 64 << 20),
+        "no heap grow event in {seen:?}"
+    );
+}
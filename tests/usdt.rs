@@ -0,0 +1,47 @@
+//! USDT probes: each probe leaves a SystemTap note in the binary, and the
+//! paths that carry them still work.
+//!
+//! Run with: cargo test --features usdt,std --test usdt
+
+#![cfg(all(
+    feature = "usdt",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+
+use rtmalloc::RtMalloc;
+
+#[global_allocator]
+static GLOBAL: RtMalloc = RtMalloc;
+
+const PROBES: [&str; 4] = [
+    "heap_grow",
+    "span_release",
+    "central_refill",
+    "thread_cache_scavenge",
+];
+
+#[test]
+fn test_probe_notes_present() {
+    let exe = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+    for name in PROBES {
+        // Provider, name and argument string follow each other in the note.
+        let entry = format!("rtmalloc\0{name}\08@");
+        assert!(
+            exe.windows(entry.len()).any(|w| w == entry.as_bytes()),
+            "no note for probe {name}"
+        );
+    }
+}
+
+#[test]
+fn test_probed_paths_run() {
+    // Enough churn to grow the heap, refill, scavenge and release spans.
+    for round in 0..4 {
+        let boxes: Vec<Box<[u8; 256]>> = (0..20_000).map(|i| Box::new([i as u8; 256])).collect();
+        assert_eq!(boxes[round * 7][0], (round * 7) as u8);
+        drop(boxes);
+        let big = vec![round as u8; 4 << 20];
+        assert_eq!(big[12345], round as u8);
+    }
+}